@@ -0,0 +1,24 @@
+#!/bin/bash
+
+# Build and test the crate without the default bevy feature, so the ash renderer, vulkan_common,
+# the memory pools and the loaders keep compiling on their own
+SUCCESS=true
+
+echo "Checking the library without default features..."
+if ! cargo check --no-default-features --lib; then
+    echo "The library doesn't build without the bevy feature"
+    SUCCESS=false
+fi
+
+echo "Running the library tests without default features..."
+if ! cargo test --no-default-features --lib; then
+    echo "The library tests fail without the bevy feature"
+    SUCCESS=false
+fi
+
+if [ "$SUCCESS" = true ]; then
+    echo "The no-Bevy build passed!"
+else
+    echo "The no-Bevy build failed"
+    exit 1
+fi
@@ -15,13 +15,16 @@ if (Get-Command glslc -ErrorAction SilentlyContinue) {
     exit 1
 }
 
-# Compile all vertex and fragment shaders
+# Compile all vertex, fragment and tessellation shaders
 $shaderDir = "shaders"
 $success = $true
 
-# Find all .vert and .frag files and compile them
+# Find all .vert, .frag, .tesc, .tese and .comp files and compile them
 $shaderFiles = Get-ChildItem -Path $shaderDir -Filter "*.vert"
 $shaderFiles += Get-ChildItem -Path $shaderDir -Filter "*.frag"
+$shaderFiles += Get-ChildItem -Path $shaderDir -Filter "*.tesc"
+$shaderFiles += Get-ChildItem -Path $shaderDir -Filter "*.tese"
+$shaderFiles += Get-ChildItem -Path $shaderDir -Filter "*.comp"
 
 foreach ($shader in $shaderFiles) {
     $output = "$($shader.FullName).spv"
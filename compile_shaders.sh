@@ -14,12 +14,12 @@ else
     exit 1
 fi
 
-# Compile all vertex and fragment shaders
+# Compile all vertex, fragment, tessellation and compute shaders
 SHADER_DIR="shaders"
 SUCCESS=true
 
-# Find all .vert and .frag files and compile them
-for shader in "$SHADER_DIR"/*.vert "$SHADER_DIR"/*.frag; do
+# Find all .vert, .frag, .tesc, .tese and .comp files and compile them
+for shader in "$SHADER_DIR"/*.vert "$SHADER_DIR"/*.frag "$SHADER_DIR"/*.tesc "$SHADER_DIR"/*.tese "$SHADER_DIR"/*.comp; do
     if [ -f "$shader" ]; then
         output="${shader}.spv"
         echo "Compiling $(basename "$shader") -> $(basename "$output")"
@@ -100,6 +100,15 @@ fn process_node(
                         combined_indices.push(triangle[2] + *vertex_offset);
                     }
                 }
+            } else {
+                // No indices provided, the vertices are a triangle list in order
+                for i in (0..positions.len() as u32).step_by(3) {
+                    if i + 2 < positions.len() as u32 {
+                        combined_indices.push(i + *vertex_offset);
+                        combined_indices.push(i + 1 + *vertex_offset);
+                        combined_indices.push(i + 2 + *vertex_offset);
+                    }
+                }
             }
             
             *vertex_offset += positions.len() as u32;
@@ -126,19 +135,18 @@ fn main() {
         .add_systems(PostStartup, setup_vulkan_renderer)
         .add_systems(
             Update,
-            render_frame.run_if(resource_exists::<VulkanContext>),
+            render_frame,
         )
         .run();
 }
 
-#[derive(Resource)]
+// Non-send, so the renderer stays on the main thread with the window
 struct VulkanContext(VulkanRenderer);
 
-fn setup_vulkan_renderer(
-    mut commands: Commands,
-    windows: Query<(Entity, &RawHandleWrapperHolder, &Window), With<PrimaryWindow>>,
-) {
-    let (_entity, handle_wrapper, window) = windows.single().expect("Failed to get primary window");
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn setup_vulkan_renderer(world: &mut World) {
+    let mut windows = world.query_filtered::<(&RawHandleWrapperHolder, &Window), With<PrimaryWindow>>();
+    let (handle_wrapper, window) = windows.single(world).expect("Failed to get primary window");
     
     println!("Window size: {:?}x{:?}", window.width(), window.height());
     
@@ -445,10 +453,10 @@ fn setup_vulkan_renderer(
     
     println!("Model bounds: center {:?}, extents {:?}", center, extents);
     
-    commands.insert_resource(VulkanContext(renderer));
+    world.insert_non_send_resource(VulkanContext(renderer));
     
     // Spawn camera with controller at the center of the model
-    commands.spawn((
+    world.spawn((
         Camera3d::default(),
         Transform::from_translation(center),
         CameraController::default().print_controls(),
@@ -458,25 +466,27 @@ fn setup_vulkan_renderer(
 }
 
 fn render_frame(
-    mut vulkan: ResMut<VulkanContext>,
+    vulkan: Option<NonSendMut<VulkanContext>>,
     mut fps_logger: Local<FpsLogger>,
     time: Res<Time>,
     camera_query: Query<&Transform, With<Camera>>,
 ) {
+    let Some(mut vulkan) = vulkan else {
+        return;
+    };
+    
     fps_logger.update(&time);
     
     // Use the camera transform from the camera entity
     if let Ok(camera_transform) = camera_query.single() {
         // Calculate view-projection matrix
         let view_matrix = camera_transform.compute_matrix().inverse();
-        let aspect_ratio = 1920.0 / 1080.0; // TODO: Get from window
         let fov = std::f32::consts::PI / 3.0;
         let near = 0.1;
         let far = 1000.0;
         
-        // Create projection matrix and flip Y to correct for Vulkan's coordinate system
-        let mut proj_matrix = Mat4::perspective_rh(fov, aspect_ratio, near, far);
-        proj_matrix.y_axis.y = -proj_matrix.y_axis.y; // Flip Y axis
+        // Y flipped for Vulkan, with the swapchain's aspect ratio
+        let proj_matrix = vulkan.0.perspective(fov, near, far);
         let view_proj = proj_matrix * view_matrix;
         
         let _ = vulkan.0.render_frame_with_view_proj(view_proj);
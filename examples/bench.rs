@@ -0,0 +1,271 @@
+use std::env;
+use std::str::FromStr;
+use std::time::Instant;
+
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, RawHandleWrapperHolder};
+use bevy::math::{Mat4, Vec3};
+
+use vulkan_bevy_renderer::{
+    setup_bevy_app_with_window,
+    vulkan_renderer_unified::{FrameStats, RendererConfig, VulkanRenderer},
+    gltf_loader::GltfData,
+    mesh::InstanceData,
+    skinned_mesh::{SkinnedMeshData, SkinnedVertex},
+    random::SeededRng,
+    ash::vk,
+};
+
+// Renders a fixed scene of instanced grapes for a number of frames and prints CPU and GPU frame
+// time statistics, for tracking performance across commits:
+//   cargo run --release --example bench -- --instances 10000 --frames 2000 --seed 1
+// The scene and camera only depend on the arguments, so runs with the same seed are comparable.
+// --ssbo draws the same instances with their matrices in a storage buffer instead of attributes.
+// --skinned adds every instance as its own skinned mesh instead, packed into the joint arena, and
+// --per-mesh-sets gives each of them its own joint buffer and descriptor sets to compare against.
+fn main() {
+    let config = BenchConfig::from_args();
+    let mode = match (config.skinned, config.per_mesh_sets, config.ssbo) {
+        (true, true, _) => "skinned meshes with their own descriptor sets",
+        (true, false, _) => "skinned meshes in the joint arena",
+        (false, _, true) => "storage buffer matrices",
+        (false, _, false) => "instance attributes",
+    };
+    println!(
+        "Benchmarking {} instances for {} frames ({} warmup), seed {}, {}",
+        config.instances, config.frames, config.warmup, config.seed, mode
+    );
+
+    let mut app = setup_bevy_app_with_window(1280.0, 720.0, "Flo Benchmark");
+    app.insert_resource(config)
+        .add_systems(PostStartup, setup_vulkan_renderer)
+        .add_systems(Update, render_frame)
+        .run();
+}
+
+#[derive(Resource, Clone, Copy)]
+struct BenchConfig {
+    instances: usize,
+    frames: usize,
+    // Frames rendered before measuring, so pipeline and driver warmup doesn't skew the numbers
+    warmup: usize,
+    seed: u64,
+    ssbo: bool,
+    skinned: bool,
+    per_mesh_sets: bool,
+}
+
+// Joints per skinned mesh, only the first one moves the mesh
+const SKINNED_JOINTS: usize = 32;
+
+impl BenchConfig {
+    fn from_args() -> Self {
+        let mut config = Self {
+            instances: 1000,
+            frames: 1000,
+            warmup: 100,
+            seed: 1,
+            ssbo: false,
+            skinned: false,
+            per_mesh_sets: false,
+        };
+        let mut args: Vec<String> = env::args().skip(1).collect();
+        let mut take_flag = |flag: &str| match args.iter().position(|arg| arg == flag) {
+            Some(position) => {
+                args.remove(position);
+                true
+            }
+            None => false,
+        };
+        config.ssbo = take_flag("--ssbo");
+        config.skinned = take_flag("--skinned");
+        config.per_mesh_sets = take_flag("--per-mesh-sets");
+        for pair in args.chunks(2) {
+            let (name, value) = (pair[0].as_str(), pair.get(1));
+            match name {
+                "--instances" => config.instances = parse_arg(name, value),
+                "--frames" => config.frames = parse_arg::<usize>(name, value).max(1),
+                "--warmup" => config.warmup = parse_arg(name, value),
+                "--seed" => config.seed = parse_arg(name, value),
+                other => panic!(
+                    "Unknown argument {} (expected --instances, --frames, --warmup, --seed, --ssbo, --skinned or --per-mesh-sets)",
+                    other
+                ),
+            }
+        }
+        config
+    }
+}
+
+fn parse_arg<T: FromStr>(name: &str, value: Option<&String>) -> T {
+    let value = value.unwrap_or_else(|| panic!("Missing value for {}", name));
+    value.parse().unwrap_or_else(|_| panic!("Invalid value for {}: {}", name, value))
+}
+
+// Non-send, so the renderer stays on the main thread with the window
+struct VulkanContext {
+    renderer: VulkanRenderer,
+    frame: usize,
+    cpu_times_ms: Vec<f32>,
+    gpu_times_ms: Vec<f32>,
+    stats: FrameStats,
+}
+
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn setup_vulkan_renderer(world: &mut World) {
+    let config = *world.resource::<BenchConfig>();
+    let mut windows = world.query_filtered::<&RawHandleWrapperHolder, With<PrimaryWindow>>();
+    let handle_wrapper = windows.single(world).expect("Failed to get primary window");
+
+    let mesh_data = GltfData::load_from_file("assets/red_grapes_wjbgdiz_low.glb")
+        .expect("Failed to load GLB file")
+        .mesh_data;
+
+    let mut renderer = VulkanRenderer::new_multi_mesh(
+        handle_wrapper,
+        "shaders/mesh_mvp.vert.spv",
+        "shaders/mesh.frag.spv",
+        Vec::new(),
+    ).expect("Failed to create Vulkan renderer");
+    if config.per_mesh_sets {
+        renderer = renderer.with_config(RendererConfig { skinned_joint_arena_size: 0, ..Default::default() });
+    }
+    let pipeline = if config.skinned {
+        renderer.add_skinned_pipeline(
+            "instanced",
+            "shaders/skinned_instanced.vert.spv",
+            "shaders/mesh.frag.spv",
+            true,
+            SKINNED_JOINTS,
+        )
+    } else if config.ssbo {
+        renderer.add_instance_ssbo_pipeline(
+            "instanced",
+            "shaders/mesh_instanced_ssbo.vert.spv",
+            "shaders/mesh.frag.spv",
+            vk::FrontFace::CLOCKWISE,
+        )
+    } else {
+        renderer.add_instanced_color_pipeline(
+            "instanced",
+            "shaders/mesh_instanced_color.vert.spv",
+            "shaders/mesh.frag.spv",
+            vk::FrontFace::CLOCKWISE,
+        )
+    };
+    pipeline.expect("Failed to add instanced pipeline");
+
+    // Positions, spins and colors all come from the seed, in a box that grows with the count
+    // so the density stays about the same
+    let mut rng = SeededRng::new(config.seed);
+    let half_extent = (config.instances as f32).cbrt() * 0.75;
+    let instances: Vec<InstanceData> = (0..config.instances)
+        .map(|_| {
+            let position = Vec3::new(
+                rng.range_f32(-half_extent..half_extent),
+                rng.range_f32(-half_extent..half_extent),
+                rng.range_f32(-half_extent..half_extent),
+            );
+            let spin = rng.range_f32(0.0..std::f32::consts::TAU);
+            let color = [rng.range_f32(0.3..1.0), rng.range_f32(0.1..0.5), rng.range_f32(0.3..1.0), 1.0];
+            InstanceData::new(Mat4::from_translation(position) * Mat4::from_rotation_y(spin), color)
+        })
+        .collect();
+    // The storage buffer path has no instance colors, the colors are still drawn from the rng
+    // so both paths place the same instances
+    let mesh = if config.skinned {
+        // Every vertex follows joint 0, which places the mesh like the instance transform would
+        let vertices: Vec<SkinnedVertex> = mesh_data.vertices.iter()
+            .map(|v| SkinnedVertex::new(v.position, v.normal, v.uv, v.color, [0; 4], [1.0, 0.0, 0.0, 0.0]))
+            .collect();
+        instances.iter().try_for_each(|instance| {
+            let mut joint_matrices = vec![Mat4::IDENTITY; SKINNED_JOINTS];
+            joint_matrices[0] = Mat4::from_cols_array(&instance.transform);
+            let skinned_data = SkinnedMeshData::new(vertices.clone(), mesh_data.indices.clone(), joint_matrices);
+            renderer.add_skinned_mesh_instanced(&skinned_data, &[[0.0, 0.0, 0.0]], Some("instanced".to_string())).map(|_| ())
+        })
+    } else if config.ssbo {
+        let transforms: Vec<Mat4> = instances.iter().map(|instance| Mat4::from_cols_array(&instance.transform)).collect();
+        renderer.add_mesh_instanced_ssbo(&mesh_data, &transforms, Some("instanced".to_string())).map(|_| ())
+    } else {
+        renderer.add_mesh_instanced_with_colors(&mesh_data, instances, Some("instanced".to_string())).map(|_| ())
+    };
+    mesh.expect("Failed to add instanced mesh");
+
+    world.insert_non_send_resource(VulkanContext {
+        renderer,
+        frame: 0,
+        cpu_times_ms: Vec::with_capacity(config.frames),
+        gpu_times_ms: Vec::with_capacity(config.frames),
+        stats: FrameStats::default(),
+    });
+}
+
+fn render_frame(
+    vulkan: Option<NonSendMut<VulkanContext>>,
+    config: Res<BenchConfig>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(mut vulkan) = vulkan else {
+        return;
+    };
+
+    let window = windows.single().expect("Failed to get primary window");
+    let width = window.physical_width();
+    let height = window.physical_height();
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // A fixed camera keeps every frame the same amount of work
+    let distance = (config.instances as f32).cbrt() * 2.0 + 3.0;
+    let view = Mat4::look_at_rh(Vec3::new(0.0, 0.0, distance), Vec3::ZERO, Vec3::NEG_Y);
+    let proj = Mat4::perspective_rh(60.0_f32.to_radians(), width as f32 / height as f32, 0.1, distance * 4.0);
+
+    // CPU time covers waiting for the frame's fence, recording, submitting and presenting
+    let start = Instant::now();
+    vulkan.renderer.render_frame_with_camera_multi(view, proj);
+    let cpu_time_ms = start.elapsed().as_secs_f32() * 1000.0;
+
+    vulkan.frame += 1;
+    if vulkan.frame <= config.warmup {
+        return;
+    }
+
+    vulkan.cpu_times_ms.push(cpu_time_ms);
+    if let Some(gpu_time_ms) = vulkan.renderer.last_gpu_frame_time_ms() {
+        vulkan.gpu_times_ms.push(gpu_time_ms);
+    }
+    vulkan.stats = vulkan.renderer.last_frame_stats();
+
+    if vulkan.cpu_times_ms.len() == config.frames {
+        println!("{:?}", vulkan.stats);
+        let descriptors = vulkan.renderer.memory_report().descriptors;
+        println!("Descriptor sets: {} in {} pool blocks", descriptors.sets, descriptors.pool_blocks);
+        print_times("CPU", &mut vulkan.cpu_times_ms);
+        if vulkan.gpu_times_ms.is_empty() {
+            println!("GPU: timestamps not supported on the graphics queue");
+        } else {
+            print_times("GPU", &mut vulkan.gpu_times_ms);
+        }
+        exit.write(AppExit::Success);
+    }
+}
+
+fn print_times(label: &str, times_ms: &mut [f32]) {
+    times_ms.sort_by(f32::total_cmp);
+    let average = times_ms.iter().sum::<f32>() / times_ms.len() as f32;
+    // Nearest rank percentile of the sorted times
+    let percentile = |p: f32| times_ms[((p / 100.0 * times_ms.len() as f32).ceil() as usize).clamp(1, times_ms.len()) - 1];
+    println!(
+        "{}: avg {:.3} ms, p50 {:.3} ms, p95 {:.3} ms, p99 {:.3} ms, max {:.3} ms ({} frames)",
+        label,
+        average,
+        percentile(50.0),
+        percentile(95.0),
+        percentile(99.0),
+        times_ms[times_ms.len() - 1],
+        times_ms.len(),
+    );
+}
@@ -19,18 +19,19 @@ fn main() {
             (
                 collect_egui_input,
                 render_frame,
-            ).chain().run_if(resource_exists::<VulkanContext>),
+            ).chain(),
         )
         .run();
 }
 
-#[derive(Resource)]
+// Non-send, so the renderer stays on the main thread with the window
 struct VulkanContext {
     renderer: VulkanRenderer,
     fps_logger: FpsLogger,
     demo_text: String,
     slider_value: f32,
     checkbox_value: bool,
+    ui_scale: f32,
 }
 
 #[derive(Resource, Default)]
@@ -40,6 +41,7 @@ struct EguiInputState {
 }
 
 fn collect_egui_input(
+    vulkan: Option<NonSendMut<VulkanContext>>,
     mut egui_input: ResMut<EguiInputState>,
     windows: Query<&Window, With<PrimaryWindow>>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
@@ -52,9 +54,19 @@ fn collect_egui_input(
     
     let window = windows.single().expect("Failed to get primary window");
     
-    // Update cursor position
-    if let Some(cursor_pos) = window.cursor_position() {
-        let pos = egui::pos2(cursor_pos.x, cursor_pos.y);
+    // Keep egui's scale in sync with the window, which can be resized or moved to a display
+    // with another scale factor
+    let pixels_per_point = match vulkan {
+        Some(mut vulkan) => {
+            vulkan.renderer.update_egui_swapchain(window.physical_width(), window.physical_height(), window.scale_factor());
+            vulkan.renderer.egui_integration.as_ref().map_or(1.0, |egui| egui.pixels_per_point())
+        }
+        None => 1.0,
+    };
+    
+    // Update cursor position, egui wants it in points
+    if let Some(cursor_pos) = window.physical_cursor_position() {
+        let pos = egui::pos2(cursor_pos.x / pixels_per_point, cursor_pos.y / pixels_per_point);
         egui_input.cursor_pos = Some(pos);
         egui_input.events.push(egui::Event::PointerMoved(pos));
     }
@@ -162,11 +174,10 @@ fn bevy_key_to_egui(key: KeyCode) -> Option<egui::Key> {
     }
 }
 
-fn setup_vulkan_renderer(
-    mut commands: Commands,
-    windows: Query<(Entity, &RawHandleWrapperHolder, &Window), With<PrimaryWindow>>,
-) {
-    let (_entity, handle_wrapper, window) = windows.single().expect("Failed to get primary window");
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn setup_vulkan_renderer(world: &mut World) {
+    let mut windows = world.query_filtered::<(&RawHandleWrapperHolder, &Window), With<PrimaryWindow>>();
+    let (handle_wrapper, window) = windows.single(world).expect("Failed to get primary window");
     
     println!("Window size: {:?}x{:?}", window.width(), window.height());
     println!("Initializing egui example...");
@@ -180,25 +191,30 @@ fn setup_vulkan_renderer(
     ).expect("Failed to create Vulkan renderer");
     
     // Initialize egui integration
-    let render_pass = renderer.get_render_pass();
+    let render_pass = renderer.get_present_render_pass();
     renderer.initialize_egui(render_pass)
         .expect("Failed to initialize egui");
     
-    commands.insert_resource(VulkanContext { 
+    world.insert_non_send_resource(VulkanContext { 
         renderer,
         fps_logger: FpsLogger::new(),
         demo_text: String::from("Hello from egui!"),
         slider_value: 0.5,
         checkbox_value: true,
+        ui_scale: 1.0,
     });
 }
 
 fn render_frame(
-    mut vulkan: ResMut<VulkanContext>,
+    vulkan: Option<NonSendMut<VulkanContext>>,
     time: Res<Time>,
     windows: Query<&Window, With<PrimaryWindow>>,
     egui_input: Res<EguiInputState>,
 ) {
+    let Some(mut vulkan) = vulkan else {
+        return;
+    };
+    
     vulkan.fps_logger.update(&time);
     
     let window = windows.single().expect("Failed to get primary window");
@@ -209,16 +225,17 @@ fn render_frame(
     let mut demo_text = vulkan.demo_text.clone();
     let mut slider_value = vulkan.slider_value;
     let mut checkbox_value = vulkan.checkbox_value;
+    let mut ui_scale = vulkan.ui_scale;
     let frame_time_ms = time.delta_secs() * 1000.0;
     
+    // The window in points at the current UI scale
+    let screen_rect = vulkan.renderer.egui_integration.as_ref().map(|egui| egui.screen_rect());
+    
     // Get the egui context and run UI code
     let egui_output = if let Some(ctx) = vulkan.renderer.get_egui_context() {
         // Create raw input for egui with collected events
         let mut raw_input = egui::RawInput {
-            screen_rect: Some(egui::Rect::from_min_size(
-                egui::Pos2::ZERO,
-                egui::vec2(width, height),
-            )),
+            screen_rect,
             time: Some(time.elapsed_secs_f64()),
             predicted_dt: time.delta_secs(),
             ..Default::default()
@@ -274,6 +291,9 @@ fn render_frame(
                     ui.label(format!("Checkbox: {}", checkbox_value));
                 });
                 
+                ui.add(egui::Slider::new(&mut ui_scale, 0.5..=3.0)
+                    .text("UI scale"));
+                
                 ui.separator();
                 
                 if ui.button("Reset").clicked() {
@@ -291,6 +311,12 @@ fn render_frame(
         vulkan.slider_value = slider_value;
         vulkan.checkbox_value = checkbox_value;
         
+        // Applies from the next frame, this one was laid out at the old scale
+        if ui_scale != vulkan.ui_scale {
+            vulkan.ui_scale = ui_scale;
+            vulkan.renderer.set_egui_ui_scale(ui_scale).expect("egui is initialized");
+        }
+        
         Some(output)
     } else {
         None
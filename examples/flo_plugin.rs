@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+
+use vulkan_bevy_renderer::{
+    setup_bevy_app,
+    bevy_plugin::{FloCamera, FloMeshData, FloRenderPlugin},
+    camera_controller::{CameraController, CameraControllerPlugin},
+    primitives,
+};
+
+fn main() {
+    let mut app = setup_bevy_app();
+
+    app.add_plugins((FloRenderPlugin::default(), CameraControllerPlugin))
+        .add_systems(Startup, spawn_scene)
+        .add_systems(Update, spin)
+        .run();
+}
+
+// Turns the entity about its y axis, in radians per second
+#[derive(Component)]
+struct Spin(f32);
+
+// Plain entities: the plugin uploads each FloMeshData and draws it at the entity's transform
+fn spawn_scene(mut commands: Commands) {
+    // The controller moves entities with a Bevy Camera, FloCamera is what the renderer reads
+    commands.spawn((
+        Camera3d::default(),
+        FloCamera::default(),
+        Transform::from_xyz(0.0, 3.0, 8.0).looking_at(Vec3::ZERO, Vec3::Y),
+        CameraController::default().print_controls(),
+    ));
+
+    commands.spawn((
+        FloMeshData(primitives::plane(20.0, 20.0, 1)),
+        Transform::from_xyz(0.0, -1.0, 0.0),
+    ));
+    commands.spawn((
+        FloMeshData(primitives::cuboid(1.0, 1.0, 1.0, false)),
+        Transform::from_xyz(-2.5, 0.0, 0.0),
+        Spin(1.0),
+    ));
+    commands.spawn((
+        FloMeshData(primitives::sphere(0.6, 32, 16)),
+        Transform::from_xyz(0.0, 0.0, 0.0),
+    ));
+    commands.spawn((
+        FloMeshData(primitives::torus(0.6, 0.2, 32, 16)),
+        Transform::from_xyz(2.5, 0.0, 0.0),
+        Spin(-0.5),
+    ));
+}
+
+fn spin(time: Res<Time>, mut spinning: Query<(&Spin, &mut Transform)>) {
+    for (Spin(speed), mut transform) in &mut spinning {
+        transform.rotate_y(speed * time.delta_secs());
+    }
+}
@@ -1,33 +1,47 @@
 use bevy::prelude::*;
 use bevy::window::{PrimaryWindow, RawHandleWrapperHolder};
 use bevy::input::mouse::MouseButton;
+use bevy::time::common_conditions::paused;
 use std::sync::{Arc, Mutex};
 
 use vulkan_bevy_renderer::{
+    ash::vk,
     setup_bevy_app,
-    vulkan_renderer_unified::{VulkanRenderer, PushConstants},
-    mesh::{MeshData, Vertex},
+    FocusSettings,
+    vulkan_renderer_unified::VulkanRenderer,
+    mesh::{MeshData, MeshLayout, MeshTopology, Vertex},
+    water::WaterData,
     fps_logger::FpsLogger,
+    random::SeededRng,
 };
 
 const WATER_GRID_LEN: usize = 64;
 const WATER_SIZE: f32 = 8.0;
 const WATER_HALF_SIZE: f32 = 4.0; // WATER_SIZE * 0.5
-const GRAVITY: f32 = 10.;
-const FRICTION: f32 = 0.6;
+const WATER_REST_HEIGHT: f32 = 1.0;
+// Wall cells hold still water at the rest height, so open walls drain a basin towards it
+const WATER_WALL_HEIGHT: f32 = WATER_REST_HEIGHT;
+// Draw the water grid as triangle strips through the flat water pipeline instead of a
+// tessellated triangle list, should look the same as the flat list version
+const WATER_TRIANGLE_STRIPS: bool = false;
 
 fn main() {
     let mut app = setup_bevy_app();
     
     app.insert_resource(WaterSimData::default())
+        .insert_resource(FocusSettings { pause_when_unfocused: true })
+        .init_resource::<WaterUiInput>()
+        .init_resource::<Rain>()
         .add_systems(PostStartup, setup_vulkan_renderer)
         .add_systems(
             Update,
             (
-                water_sim,
+                collect_ui_input,
+                spawn_raindrops.run_if(not(paused)),
+                water_sim.run_if(not(paused)),
                 handle_mouse_clicks,
                 render_frame,
-            ).run_if(resource_exists::<VulkanContext>),
+            ).chain().run_if(resource_exists::<VulkanContext>),
         )
         .run();
 }
@@ -38,9 +52,88 @@ struct VulkanContext {
     water_mesh_index: Option<usize>,
 }
 
+// Pointer input forwarded to the egui parameter window
+#[derive(Resource, Default)]
+struct WaterUiInput {
+    events: Vec<egui::Event>,
+    cursor_pos: Option<egui::Pos2>,
+    // Set while the cursor is over the UI so clicks don't disturb the water underneath
+    wants_pointer: bool,
+}
+
+// Tunable shallow water behaviour, the defaults match the original hardcoded constants
+#[derive(Clone, Copy, Debug)]
+struct WaterParams {
+    gravity: f32,
+    // Fraction of flow kept after one second
+    friction: f32,
+    // How strongly flow is diffused between neighbouring cells, 0 disables it
+    viscosity: f32,
+    // Rate at which height relaxes back to the rest level, 0 disables it
+    damping: f32,
+    // What walls do with waves reaching them. 1 reflects them and keeps the volume constant.
+    // Lower values open the wall faces towards water at the rest height, so waves drain out
+    // (or fill in) through them and a basin calms down; 0 absorbs them fully. The water moved
+    // through those faces is lost or gained, so the volume drifts towards the rest volume.
+    wall_reflection: f32,
+}
+
+impl Default for WaterParams {
+    fn default() -> Self {
+        Self {
+            gravity: 10.0,
+            friction: 0.6,
+            viscosity: 0.0,
+            damping: 0.0,
+            wall_reflection: 1.0,
+        }
+    }
+}
+
+// Ambient ripples from random drops on the water, 0 drops per second turns rain off
+#[derive(Clone, Copy, Debug)]
+struct RainConfig {
+    drops_per_second: f32,
+    // Height added to the cell a drop lands on
+    drop_height: f32,
+    // Same seed and frame times give the same drops
+    seed: u64,
+}
+
+impl Default for RainConfig {
+    fn default() -> Self {
+        Self {
+            drops_per_second: 0.0,
+            drop_height: 0.3,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Resource)]
+struct Rain {
+    config: RainConfig,
+    rng: SeededRng,
+    // Fraction of a drop carried over to the next frame so the rate doesn't depend on the frame rate
+    pending_drops: f32,
+}
+
+impl Default for Rain {
+    fn default() -> Self {
+        let config = RainConfig::default();
+        Self {
+            config,
+            rng: SeededRng::new(config.seed),
+            pending_drops: 0.0,
+        }
+    }
+}
+
 #[derive(Resource, Clone)]
 struct WaterSimData {
-    height: [[f32; WATER_GRID_LEN]; WATER_GRID_LEN],
+    params: WaterParams,
+    // Heights of the cells, pushed to the water mesh with update_water_surface
+    surface: WaterData,
     flow_x: [[f32; WATER_GRID_LEN]; WATER_GRID_LEN],
     flow_y: [[f32; WATER_GRID_LEN]; WATER_GRID_LEN],
     last_disturbed_pos: Option<(usize, usize)>,
@@ -51,7 +144,8 @@ struct WaterSimData {
 impl Default for WaterSimData {
     fn default() -> Self {
         let mut water_data = Self {
-            height: [[1.0; WATER_GRID_LEN]; WATER_GRID_LEN],
+            params: WaterParams::default(),
+            surface: WaterData::new(WATER_GRID_LEN, WATER_SIZE, WATER_REST_HEIGHT),
             flow_x: [[0.0; WATER_GRID_LEN]; WATER_GRID_LEN],
             flow_y: [[0.0; WATER_GRID_LEN]; WATER_GRID_LEN],
             last_disturbed_pos: None,
@@ -82,14 +176,14 @@ fn setup_vulkan_renderer(
     compute_grid_screen_positions(&mut water_data, window.width(), window.height());
     
     // Create a basic mesh for initializing the renderer (won't be rendered)
-    let basic_mesh = MeshData {
-        vertices: vec![
+    let basic_mesh = MeshData::new(
+        vec![
             Vertex { position: [0.0, 0.0, 0.0], normal: [0.0, 1.0, 0.0], uv: [0.0, 0.0], color: [1.0, 1.0, 1.0, 1.0] },
             Vertex { position: [1.0, 0.0, 0.0], normal: [0.0, 1.0, 0.0], uv: [1.0, 0.0], color: [1.0, 1.0, 1.0, 1.0] },
             Vertex { position: [0.0, 0.0, 1.0], normal: [0.0, 1.0, 0.0], uv: [0.0, 1.0], color: [1.0, 1.0, 1.0, 1.0] },
         ],
-        indices: vec![0, 1, 2],
-    };
+        vec![0, 1, 2],
+    );
     
     let vulkan_renderer = Arc::new(Mutex::new(None));
     let vulkan_renderer_clone = vulkan_renderer.clone();
@@ -109,14 +203,52 @@ fn setup_vulkan_renderer(
                 return;
             }
             
-            // Add fluid rendering pipelines
-            if let Err(e) = renderer.add_fluid_pipeline("water", "shaders/water.vert.spv", "shaders/water.frag.spv") {
-                eprintln!("Failed to add water pipeline: {}", e);
-                return;
+            // Add fluid rendering pipelines. The water mesh is deinterleaved so each frame
+            // only reuploads the positions and normals, not the uvs.
+            // Tessellated water smooths out the grid up close, flat water is the fallback.
+            // Tessellation draws patches, so strips always use the flat pipeline.
+            if WATER_TRIANGLE_STRIPS {
+                if let Err(e) = renderer.add_fluid_pipeline_with_topology(
+                    "water",
+                    "shaders/water.vert.spv",
+                    "shaders/water.frag.spv",
+                    vk::CullModeFlags::NONE,
+                    vk::FrontFace::COUNTER_CLOCKWISE,
+                    MeshLayout::Deinterleaved,
+                    MeshTopology::TriangleStrip,
+                ) {
+                    eprintln!("Failed to add water strip pipeline: {}", e);
+                    return;
+                }
+            } else {
+                match renderer.add_tessellated_water_pipeline(
+                    "water",
+                    None,
+                    vk::CullModeFlags::NONE,
+                    vk::FrontFace::COUNTER_CLOCKWISE,
+                    MeshLayout::Deinterleaved,
+                ) {
+                    Ok(tessellated) => println!("Water pipeline added (tessellated: {})", tessellated),
+                    Err(e) => {
+                        eprintln!("Failed to add tessellated water pipeline: {}, using flat water", e);
+                        if let Err(e) = renderer.add_fluid_pipeline_with_layout(
+                            "water",
+                            "shaders/water.vert.spv",
+                            "shaders/water.frag.spv",
+                            vk::CullModeFlags::NONE,
+                            vk::FrontFace::COUNTER_CLOCKWISE,
+                            MeshLayout::Deinterleaved,
+                        ) {
+                            eprintln!("Failed to add water pipeline: {}", e);
+                            return;
+                        }
+                    }
+                }
             }
             
-            // Try to add wall pipeline with actual stone wall textures
-            match renderer.add_wall_pipeline_with_textures() {
+            // Try to add wall pipeline with actual stone wall textures.
+            // Wall faces wind counter-clockwise around their normals, so back faces can be culled.
+            match renderer.add_wall_pipeline_with_textures(vk::CullModeFlags::BACK, vk::FrontFace::COUNTER_CLOCKWISE) {
                 Ok(_) => {
                     println!("Successfully loaded wall pipeline with stone textures");
                 }
@@ -126,17 +258,17 @@ fn setup_vulkan_renderer(
                 }
             }
             
-            // Create and add water mesh
-            let water_mesh_data = create_water_mesh();
+            let render_pass = renderer.get_present_render_pass();
+            if let Err(e) = renderer.initialize_egui(render_pass) {
+                eprintln!("Failed to initialize egui, water parameters won't be editable: {}", e);
+            }
+            
+            // Create and add the water surface mesh, update_water_surface keeps it in sync with the sim
+            let topology = if WATER_TRIANGLE_STRIPS { MeshTopology::TriangleStrip } else { MeshTopology::TriangleList };
             let water_mesh_index;
             
-            println!("Water mesh has {} vertices and {} indices", water_mesh_data.vertices.len(), water_mesh_data.indices.len());
-            match renderer.add_mesh(&water_mesh_data) {
+            match renderer.add_water_mesh(&water_data.surface, "water", topology) {
                 Ok(water_index) => {
-                    // Set transform for the mesh so it gets rendered
-                    renderer.update_mesh_transforms(water_index, vec![bevy::math::Mat4::IDENTITY]);
-                    // Set the mesh to use the water pipeline
-                    renderer.set_mesh_pipeline(water_index, "water");
                     water_mesh_index = Some(water_index);
                     println!("Added water mesh at index {} with water pipeline", water_index);
                 }
@@ -247,6 +379,7 @@ fn water_sim(
     mut water_data: ResMut<WaterSimData>,
 ) {
     let delta_time = time.delta_secs();
+    let params = water_data.params;
     
     // Clear boundary flows
     for i in 0..WATER_GRID_LEN {
@@ -256,6 +389,13 @@ fn water_sim(
         water_data.flow_y[i][WATER_GRID_LEN-1] = 0.;
     }
 
+    // How open faces between water and a wall are, 0 keeps their flow at zero
+    let wall_openness = 1.0 - params.wall_reflection.clamp(0.0, 1.0);
+    // Height of a cell as seen by a flow, the same height wall cells are pinned to below
+    let flow_height = |water_data: &WaterSimData, x: usize, y: usize| {
+        if water_data.wall_mask[x][y] { WATER_WALL_HEIGHT } else { water_data.surface.heights[x][y] }
+    };
+
     // Calculate flows
     for x in 0..WATER_GRID_LEN {
         for y in 0..WATER_GRID_LEN {
@@ -263,11 +403,15 @@ fn water_sim(
             if x > 0 {
                 let source_has_wall = water_data.wall_mask[x-1][y];
                 let dest_has_wall = water_data.wall_mask[x][y];
-                let height_diff = water_data.height[x-1][y] - water_data.height[x][y];
+                let height_diff = flow_height(&water_data, x-1, y) - flow_height(&water_data, x, y);
                 
                 if !source_has_wall && !dest_has_wall {
-                    let new_flow = water_data.flow_x[x][y] * FRICTION.powf(delta_time) + 
-                        height_diff * GRAVITY * delta_time;
+                    let new_flow = water_data.flow_x[x][y] * params.friction.powf(delta_time) + 
+                        height_diff * params.gravity * delta_time;
+                    water_data.flow_x[x][y] = new_flow;
+                } else if source_has_wall != dest_has_wall && wall_openness > 0.0 {
+                    let new_flow = water_data.flow_x[x][y] * params.friction.powf(delta_time) + 
+                        height_diff * params.gravity * delta_time * wall_openness;
                     water_data.flow_x[x][y] = new_flow;
                 } else {
                     water_data.flow_x[x][y] = 0.0;
@@ -280,11 +424,15 @@ fn water_sim(
             if y > 0 {
                 let source_has_wall = water_data.wall_mask[x][y-1];
                 let dest_has_wall = water_data.wall_mask[x][y];
-                let height_diff = water_data.height[x][y-1] - water_data.height[x][y];
+                let height_diff = flow_height(&water_data, x, y-1) - flow_height(&water_data, x, y);
                 
                 if !source_has_wall && !dest_has_wall {
-                    let new_flow = water_data.flow_y[x][y] * FRICTION.powf(delta_time) + 
-                        height_diff * GRAVITY * delta_time;
+                    let new_flow = water_data.flow_y[x][y] * params.friction.powf(delta_time) + 
+                        height_diff * params.gravity * delta_time;
+                    water_data.flow_y[x][y] = new_flow;
+                } else if source_has_wall != dest_has_wall && wall_openness > 0.0 {
+                    let new_flow = water_data.flow_y[x][y] * params.friction.powf(delta_time) + 
+                        height_diff * params.gravity * delta_time * wall_openness;
                     water_data.flow_y[x][y] = new_flow;
                 } else {
                     water_data.flow_y[x][y] = 0.0;
@@ -295,6 +443,10 @@ fn water_sim(
         }
     }
 
+    if params.viscosity > 0.0 {
+        diffuse_flows(&mut water_data, params.viscosity * delta_time);
+    }
+
     // Prevent water from flowing faster than available
     for x in 0..WATER_GRID_LEN {
         for y in 0..WATER_GRID_LEN {
@@ -313,7 +465,7 @@ fn water_sim(
                 total_outflow += 0.0f32.max(water_data.flow_y[x][y+1]);
             }
 
-            let max_outflow = water_data.height[x][y] / delta_time;
+            let max_outflow = water_data.surface.heights[x][y] / delta_time;
 
             if total_outflow > 0. {
                 let scale = 1.0f32.min(max_outflow / total_outflow);
@@ -333,36 +485,117 @@ fn water_sim(
         }
     }
 
-    // Update heights based on flows
+    // Frame rate independent fraction of the distance to the rest height removed this step
+    let damping_factor = 1.0 - (-params.damping * delta_time).exp();
+
+    // Update heights based on flows. Flows through wall faces are zero unless the walls are
+    // open, and the wall side of them is never stored, which is where volume is lost or gained.
     for x in 0..WATER_GRID_LEN {
         for y in 0..WATER_GRID_LEN {
             let mut height_change = 0.0;
             
-            let can_receive_from_left = x > 0 && !water_data.wall_mask[x-1][y] && !water_data.wall_mask[x][y];
+            let can_receive_from_left = x > 0 && !water_data.wall_mask[x][y];
             if can_receive_from_left {
                 height_change += water_data.flow_x[x][y];
             }
             
-            let can_receive_from_top = y > 0 && !water_data.wall_mask[x][y-1] && !water_data.wall_mask[x][y];
+            let can_receive_from_top = y > 0 && !water_data.wall_mask[x][y];
             if can_receive_from_top {
                 height_change += water_data.flow_y[x][y];
             } 
             
-            let can_flow_right = x < WATER_GRID_LEN - 1 && !water_data.wall_mask[x+1][y];
+            let can_flow_right = x < WATER_GRID_LEN - 1;
             if can_flow_right {
                 height_change -= water_data.flow_x[x+1][y];
             }
             
-            let can_flow_bottom = y < WATER_GRID_LEN - 1 && !water_data.wall_mask[x][y+1];
+            let can_flow_bottom = y < WATER_GRID_LEN - 1;
             if can_flow_bottom {
                 height_change -= water_data.flow_y[x][y+1];
             }
             
-            water_data.height[x][y] += height_change * delta_time;
-            water_data.height[x][y] = water_data.height[x][y].max(0.1);
+            water_data.surface.heights[x][y] += height_change * delta_time;
+            water_data.surface.heights[x][y] += (WATER_REST_HEIGHT - water_data.surface.heights[x][y]) * damping_factor;
+            water_data.surface.heights[x][y] = water_data.surface.heights[x][y].max(0.1);
             
             if water_data.wall_mask[x][y] {
-                water_data.height[x][y] = 0.1;
+                water_data.surface.heights[x][y] = WATER_WALL_HEIGHT;
+            }
+        }
+    }
+}
+
+// Blend each flow towards the average of its neighbours along the same axis.
+// Works on a copy so the result doesn't depend on iteration order.
+fn diffuse_flows(water_data: &mut WaterSimData, amount: f32) {
+    // Clamp so a large timestep can't overshoot the neighbour average
+    let amount = amount.min(1.0);
+    let flow_x = water_data.flow_x;
+    let flow_y = water_data.flow_y;
+
+    for x in 1..WATER_GRID_LEN - 1 {
+        for y in 1..WATER_GRID_LEN - 1 {
+            // Flows touching a wall aren't diffused, they only move with wall_reflection below 1
+            if !water_data.wall_mask[x-1][y] && !water_data.wall_mask[x][y] {
+                let neighbour_avg = (flow_x[x-1][y] + flow_x[x+1][y] + flow_x[x][y-1] + flow_x[x][y+1]) * 0.25;
+                water_data.flow_x[x][y] += (neighbour_avg - flow_x[x][y]) * amount;
+            }
+            if !water_data.wall_mask[x][y-1] && !water_data.wall_mask[x][y] {
+                let neighbour_avg = (flow_y[x-1][y] + flow_y[x+1][y] + flow_y[x][y-1] + flow_y[x][y+1]) * 0.25;
+                water_data.flow_y[x][y] += (neighbour_avg - flow_y[x][y]) * amount;
+            }
+        }
+    }
+}
+
+fn spawn_raindrops(
+    time: Res<Time>,
+    mut rain: ResMut<Rain>,
+    mut water_data: ResMut<WaterSimData>,
+) {
+    if rain.config.drops_per_second <= 0.0 {
+        rain.pending_drops = 0.0;
+        return;
+    }
+    
+    rain.pending_drops += rain.config.drops_per_second * time.delta_secs();
+    while rain.pending_drops >= 1.0 {
+        rain.pending_drops -= 1.0;
+        let x = rain.rng.range_usize(0..WATER_GRID_LEN);
+        let z = rain.rng.range_usize(0..WATER_GRID_LEN);
+        // Drops landing on walls are lost
+        if !water_data.wall_mask[x][z] {
+            water_data.surface.heights[x][z] += rain.config.drop_height;
+        }
+    }
+}
+
+fn collect_ui_input(
+    mut ui_input: ResMut<WaterUiInput>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mouse_button: Res<ButtonInput<MouseButton>>,
+) {
+    ui_input.events.clear();
+
+    let Ok(window) = windows.single() else {
+        return;
+    };
+
+    ui_input.cursor_pos = window.cursor_position().map(|pos| egui::pos2(pos.x, pos.y));
+    if let Some(pos) = ui_input.cursor_pos {
+        ui_input.events.push(egui::Event::PointerMoved(pos));
+
+        for (pressed, just_changed) in [
+            (true, mouse_button.just_pressed(MouseButton::Left)),
+            (false, mouse_button.just_released(MouseButton::Left)),
+        ] {
+            if just_changed {
+                ui_input.events.push(egui::Event::PointerButton {
+                    pos,
+                    button: egui::PointerButton::Primary,
+                    pressed,
+                    modifiers: egui::Modifiers::default(),
+                });
             }
         }
     }
@@ -372,8 +605,9 @@ fn handle_mouse_clicks(
     mouse_button: Res<ButtonInput<MouseButton>>,
     windows: Query<&Window>,
     mut water_data: ResMut<WaterSimData>,
+    ui_input: Res<WaterUiInput>,
 ) {
-    if mouse_button.pressed(MouseButton::Left) {
+    if mouse_button.pressed(MouseButton::Left) && !ui_input.wants_pointer {
         if let Ok(window) = windows.single() {
             // Update screen positions in case window was resized
             compute_grid_screen_positions(&mut water_data, window.width(), window.height());
@@ -437,7 +671,7 @@ fn handle_mouse_clicks(
                         };
                         
                         if should_disturb {
-                            water_data.height[best_grid_x][best_grid_z] += 1.0;
+                            water_data.surface.heights[best_grid_x][best_grid_z] += 1.0;
                             water_data.last_disturbed_pos = Some((best_grid_x, best_grid_z));
                         }
                     }
@@ -451,10 +685,12 @@ fn handle_mouse_clicks(
 
 fn render_frame(
     vulkan: Res<VulkanContext>,
-    water_data: Res<WaterSimData>,
+    mut water_data: ResMut<WaterSimData>,
+    mut rain: ResMut<Rain>,
+    mut ui_input: ResMut<WaterUiInput>,
     mut fps_logger: Local<FpsLogger>,
     time: Res<Time>,
-    _windows: Query<&Window>,
+    windows: Query<&Window>,
 ) {
     fps_logger.update(&time);
     
@@ -462,131 +698,70 @@ fn render_frame(
     if let Ok(mut renderer_guard) = vulkan.renderer.lock() {
         if let Some(ref mut renderer) = *renderer_guard {
             if let Some(water_index) = vulkan.water_mesh_index {
-                update_water_mesh_heights(renderer, water_index, &water_data.height);
+                if let Err(e) = renderer.update_water_surface(water_index, &water_data.surface) {
+                    eprintln!("Failed to update water surface: {}", e);
+                }
             }
             
             // Get window resolution
-            let resolution = if let Ok(window) = _windows.single() {
+            let resolution = if let Ok(window) = windows.single() {
                 [window.width(), window.height()]
             } else {
                 [800.0, 600.0]
             };
             
-            // Create push constants with camera position matching the original hardcoded values
-            let camera_pos = [0.0, 6.0, 8.0];
-            let push_constants = PushConstants {
-                time: time.elapsed_secs(),
-                camera_position_x: camera_pos[0],
-                camera_position_y: camera_pos[1], 
-                camera_position_z: camera_pos[2],
-                resolution,
-                water_level: 0.0,
-                grid_scale: WATER_SIZE / WATER_GRID_LEN as f32,
+            let egui_output = if let Some(ctx) = renderer.get_egui_context() {
+                let raw_input = egui::RawInput {
+                    screen_rect: Some(egui::Rect::from_min_size(
+                        egui::Pos2::ZERO,
+                        egui::vec2(resolution[0], resolution[1]),
+                    )),
+                    time: Some(time.elapsed_secs_f64()),
+                    predicted_dt: time.delta_secs(),
+                    events: ui_input.events.clone(),
+                    ..Default::default()
+                };
+
+                ctx.begin_pass(raw_input);
+                draw_water_params_window(ctx, &mut water_data.params, &mut rain.config);
+                ui_input.wants_pointer = ctx.is_pointer_over_area() || ctx.wants_pointer_input();
+                Some(ctx.end_pass())
+            } else {
+                None
             };
-            
-            // Use the fluid rendering method
-            let view = bevy::math::Mat4::IDENTITY;
-            let proj = bevy::math::Mat4::IDENTITY;
-            renderer.render_frame_fluid(view, proj, &push_constants);
-        }
-    }
-}
 
-fn update_water_mesh_heights(renderer: &mut VulkanRenderer, mesh_index: usize, heights: &[[f32; WATER_GRID_LEN]; WATER_GRID_LEN]) {
-    let vertices_per_side = WATER_GRID_LEN + 1;
-    let grid_scale = WATER_SIZE / WATER_GRID_LEN as f32;
-    let mut new_vertices = Vec::new();
-    
-    // Generate vertices with updated heights
-    for y_idx in 0..vertices_per_side {
-        for x_idx in 0..vertices_per_side {
-            let x = -WATER_HALF_SIZE + (x_idx as f32 / WATER_GRID_LEN as f32) * WATER_SIZE;
-            let z = -WATER_HALF_SIZE + (y_idx as f32 / WATER_GRID_LEN as f32) * WATER_SIZE;
-            
-            // Get the height for this vertex
-            let grid_x = x_idx.min(WATER_GRID_LEN - 1);
-            let grid_y = y_idx.min(WATER_GRID_LEN - 1);
-            let height = heights[grid_x][grid_y];
-            
-            // Calculate normal based on neighboring heights
-            let mut dx = 0.0;
-            let mut dy = 0.0;
-            
-            if x_idx > 0 && x_idx < vertices_per_side - 1 {
-                let h_left = if x_idx > 0 && grid_x > 0 { heights[grid_x - 1][grid_y] } else { heights[grid_x][grid_y] };
-                let h_right = if x_idx < WATER_GRID_LEN - 1 { heights[grid_x + 1][grid_y] } else { heights[grid_x][grid_y] };
-                dx = (h_right - h_left) / (2.0 * grid_scale);
-            }
-            
-            if y_idx > 0 && y_idx < vertices_per_side - 1 {
-                let h_up = if y_idx > 0 && grid_y > 0 { heights[grid_x][grid_y - 1] } else { heights[grid_x][grid_y] };
-                let h_down = if y_idx < WATER_GRID_LEN - 1 { heights[grid_x][grid_y + 1] } else { heights[grid_x][grid_y] };
-                dy = (h_down - h_up) / (2.0 * grid_scale);
-            }
-            
-            // Normal = (-dx, 1, -dy) normalized
-            let normal_len = (dx * dx + 1.0 + dy * dy).sqrt();
-            let normal = [-dx / normal_len, 1.0 / normal_len, -dy / normal_len];
-            
-            let u = x_idx as f32 / WATER_GRID_LEN as f32;
-            let v = y_idx as f32 / WATER_GRID_LEN as f32;
-            
-            new_vertices.push(Vertex {
-                position: [x, height - 1.0, z],
-                normal,
-                uv: [u, v],
-                color: [1.0, 1.0, 1.0, 1.0],
-            });
+            // Camera position matching the original hardcoded values
+            renderer.render_frame_water(Vec3::new(0.0, 6.0, 8.0), egui_output);
         }
     }
-    
-    // Update the mesh vertices in the renderer
-    renderer.update_mesh_vertices_full(mesh_index, &new_vertices);
 }
 
-fn create_water_mesh() -> MeshData {
-    let vertices_per_side = WATER_GRID_LEN + 1;
-    let mut vertices = Vec::new();
-    let mut indices = Vec::new();
-    
-    // Generate vertices
-    for y_idx in 0..vertices_per_side {
-        for x_idx in 0..vertices_per_side {
-            let x = -WATER_HALF_SIZE + (x_idx as f32 / WATER_GRID_LEN as f32) * WATER_SIZE;
-            let z = -WATER_HALF_SIZE + (y_idx as f32 / WATER_GRID_LEN as f32) * WATER_SIZE;
-            let u = x_idx as f32 / WATER_GRID_LEN as f32;
-            let v = y_idx as f32 / WATER_GRID_LEN as f32;
-            
-            vertices.push(Vertex {
-                position: [x, 0.0, z],
-                normal: [0.0, 1.0, 0.0],
-                uv: [u, v],
-                color: [1.0, 1.0, 1.0, 1.0],
+fn draw_water_params_window(ctx: &egui::Context, params: &mut WaterParams, rain: &mut RainConfig) {
+    egui::Window::new("Water")
+        .default_pos(egui::pos2(20.0, 20.0))
+        .show(ctx, |ui| {
+            ui.add(egui::Slider::new(&mut params.gravity, 0.0..=30.0).text("Gravity"));
+            ui.add(egui::Slider::new(&mut params.friction, 0.0..=1.0).text("Friction"));
+            ui.add(egui::Slider::new(&mut params.viscosity, 0.0..=20.0).text("Viscosity"));
+            ui.add(egui::Slider::new(&mut params.damping, 0.0..=2.0).text("Damping"));
+            ui.add(egui::Slider::new(&mut params.wall_reflection, 0.0..=1.0).text("Wall reflection"));
+
+            ui.horizontal(|ui| {
+                if ui.button("Water").clicked() {
+                    *params = WaterParams::default();
+                }
+                if ui.button("Syrup").clicked() {
+                    *params = WaterParams { gravity: 6.0, friction: 0.05, viscosity: 15.0, damping: 0.5, wall_reflection: 1.0 };
+                }
+                if ui.button("Rapids").clicked() {
+                    *params = WaterParams { gravity: 20.0, friction: 0.9, viscosity: 0.0, damping: 0.0, wall_reflection: 1.0 };
+                }
             });
-        }
-    }
-    
-    // Generate indices for triangles
-    for y in 0..WATER_GRID_LEN {
-        for x in 0..WATER_GRID_LEN {
-            let top_left = y * vertices_per_side + x;
-            let top_right = top_left + 1;
-            let bottom_left = (y + 1) * vertices_per_side + x;
-            let bottom_right = bottom_left + 1;
-            
-            // First triangle
-            indices.push(top_left as u32);
-            indices.push(bottom_left as u32);
-            indices.push(top_right as u32);
-            
-            // Second triangle
-            indices.push(top_right as u32);
-            indices.push(bottom_left as u32);
-            indices.push(bottom_right as u32);
-        }
-    }
-    
-    MeshData { vertices, indices }
+
+            ui.separator();
+            ui.add(egui::Slider::new(&mut rain.drops_per_second, 0.0..=200.0).text("Rain (drops/s)"));
+            ui.add(egui::Slider::new(&mut rain.drop_height, 0.0..=1.0).text("Drop height"));
+        });
 }
 
 fn create_wall_mesh() -> MeshData {
@@ -661,5 +836,63 @@ fn create_wall_mesh() -> MeshData {
         vertex_offset, vertex_offset + 2, vertex_offset + 3,
     ]);
     
-    MeshData { vertices, indices }
-}
\ No newline at end of file
+    MeshData::new(vertices, indices)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+    
+    // Potential energy of the water above and below the rest height plus the energy of the flows
+    fn total_energy(water_data: &WaterSimData) -> f32 {
+        let mut energy = 0.0;
+        for x in 0..WATER_GRID_LEN {
+            for y in 0..WATER_GRID_LEN {
+                if !water_data.wall_mask[x][y] {
+                    let height = water_data.surface.heights[x][y] - WATER_REST_HEIGHT;
+                    energy += 0.5 * water_data.params.gravity * height * height;
+                }
+                energy += 0.5 * (water_data.flow_x[x][y].powi(2) + water_data.flow_y[x][y].powi(2));
+            }
+        }
+        energy
+    }
+    
+    // Drops a bump into the walled box and returns its energy before and after half a minute
+    fn energy_after_splash(wall_reflection: f32) -> (f32, f32) {
+        let mut world = World::new();
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs_f32(1.0 / 60.0));
+        world.insert_resource(time);
+        
+        let mut water_data = WaterSimData::default();
+        // No friction or damping, walls are the only thing that can take energy out
+        water_data.params.friction = 1.0;
+        water_data.params.wall_reflection = wall_reflection;
+        for x in 28..36 {
+            for y in 28..36 {
+                water_data.surface.heights[x][y] += 0.2;
+            }
+        }
+        let initial = total_energy(&water_data);
+        world.insert_resource(water_data);
+        
+        for _ in 0..1800 {
+            world.run_system_once(water_sim).unwrap();
+        }
+        (initial, total_energy(world.resource::<WaterSimData>()))
+    }
+    
+    #[test]
+    fn reflecting_walls_keep_the_energy() {
+        let (initial, last) = energy_after_splash(1.0);
+        assert!((last - initial).abs() < initial * 0.01, "{initial} -> {last}");
+    }
+    
+    #[test]
+    fn open_walls_drain_the_energy() {
+        let (initial, last) = energy_after_splash(0.5);
+        assert!(last < initial * 0.9, "{initial} -> {last}");
+    }
+}
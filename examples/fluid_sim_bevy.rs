@@ -9,6 +9,10 @@ use bevy::window::{Window, WindowPlugin, PresentMode};
 const WATER_GRID_LEN: usize = 64;
 const GRAVITY: f32 = 10.;
 const FRICTION: f32 = 0.6;
+// Grayscale image with walls drawn in non-black pixels, e.g. a maze. None keeps the open pool
+const WALL_MASK_IMAGE: Option<&str> = None;
+// World units of arrow length per unit of flow in the flow field overlay
+const FLOW_ARROW_SCALE: f32 = 0.05;
 
 fn main() {
     App::new()
@@ -23,10 +27,36 @@ fn main() {
         .add_plugins(MaterialPlugin::<WaterMaterial>::default())
         .add_plugins(MaterialPlugin::<SkyMaterial>::default())
         .add_systems(Startup, setup)
+        .init_resource::<FlowFieldOverlay>()
         .add_systems(Update, (water_sim, animate_water_mesh, update_water_material, update_sky_material, handle_mouse_clicks, log_fps))
+        .add_systems(Update, (toggle_flow_field, draw_flow_field).chain())
         .run();
 }
 
+// Arrows along the water's flow, toggled with F
+#[derive(Resource, Default)]
+struct FlowFieldOverlay {
+    enabled: bool,
+}
+
+fn toggle_flow_field(keyboard: Res<ButtonInput<KeyCode>>, mut overlay: ResMut<FlowFieldOverlay>) {
+    if keyboard.just_pressed(KeyCode::KeyF) {
+        overlay.enabled = !overlay.enabled;
+        println!("Flow field overlay {}", if overlay.enabled { "on" } else { "off" });
+    }
+}
+
+fn draw_flow_field(overlay: Res<FlowFieldOverlay>, water_query: Query<&WaterData>, mut gizmos: Gizmos) {
+    if !overlay.enabled {
+        return;
+    }
+    for water_data in water_query.iter() {
+        for (start, end, color) in water_data.flow_field_lines(FLOW_ARROW_SCALE) {
+            gizmos.arrow(start, end, color);
+        }
+    }
+}
+
 fn animate_water_mesh(
     mut meshes: ResMut<Assets<Mesh>>,
     query: Query<(&WaterMesh, &WaterData)>,
@@ -264,12 +294,11 @@ fn update_water_material(
         Vec3::ZERO
     };
     
-    // Get window resolution
-    let resolution = if let Ok(window) = windows.single() {
-        Vec2::new(window.width(), window.height())
-    } else {
-        Vec2::new(1920.0, 1080.0)
+    // In pixels, like the Vulkan water path's resolution push constant
+    let Ok(window) = windows.single() else {
+        return;
     };
+    let resolution = Vec2::new(window.physical_width() as f32, window.physical_height() as f32);
     
     // Update all water materials
     for material_handle in water_query.iter() {
@@ -307,52 +336,48 @@ fn handle_mouse_clicks(
     windows: Query<&Window>,
     mut water_query: Query<&mut WaterData>,
 ) {
-    if mouse_button.pressed(MouseButton::Left) {
-        if let Ok((camera, camera_transform)) = camera_query.single() {
-            if let Ok(window) = windows.single() {
-                if let Some(cursor_position) = window.cursor_position() {
-                    // Create a ray from the camera through the cursor
-                    if let Ok(ray) = camera.viewport_to_world(camera_transform, cursor_position) {
-                        // Calculate intersection with water plane (y = 0)
-                        let t = -ray.origin.y / ray.direction.y;
-                        if t > 0.0 {
-                            let hit_point = ray.origin + ray.direction * t;
-                            
-                            // Convert world position to grid coordinates
-                            let grid_x = ((hit_point.x + 4.0) / 8.0 * WATER_GRID_LEN as f32) as usize;
-                            let grid_y = ((hit_point.z + 4.0) / 8.0 * WATER_GRID_LEN as f32) as usize;
-                            
-                            // Apply disturbance if within bounds
-                            if grid_x < WATER_GRID_LEN && grid_y < WATER_GRID_LEN {
-                                for mut water_data in water_query.iter_mut() {
-                                    // Skip displacement for wall cells
-                                    if water_data.wall_mask[grid_x][grid_y] {
-                                        continue;
-                                    }
-                                    
-                                    // Only disturb if we moved to a new grid cell
-                                    let should_disturb = match water_data.last_disturbed_pos {
-                                        Some((last_x, last_y)) => last_x != grid_x || last_y != grid_y,
-                                        None => true,
-                                    };
-                                    
-                                    if should_disturb {
-                                        water_data.height[grid_x][grid_y] += 1.0;
-                                        water_data.last_disturbed_pos = Some((grid_x, grid_y));
-                                        // println!("Disturbing at grid position: ({}, {})", grid_x, grid_y);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    } else {
+    if !mouse_button.pressed(MouseButton::Left) {
         // Reset last position when mouse is released
         for mut water_data in water_query.iter_mut() {
             water_data.last_disturbed_pos = None;
         }
+        return;
+    }
+    
+    let Ok((camera, camera_transform)) = camera_query.single() else {
+        return;
+    };
+    let Ok(window) = windows.single() else {
+        return;
+    };
+    let Some(cursor_position) = window.cursor_position() else {
+        return;
+    };
+    // Create a ray from the camera through the cursor
+    let Ok(ray) = camera.viewport_to_world(camera_transform, cursor_position) else {
+        return;
+    };
+    
+    for mut water_data in water_query.iter_mut() {
+        // Intersect the ray with this water's plane
+        let t = (water_data.world_center.y - ray.origin.y) / ray.direction.y;
+        if t <= 0.0 {
+            continue;
+        }
+        let Some((grid_x, grid_y)) = water_data.world_to_grid(ray.origin + ray.direction * t) else {
+            continue;
+        };
+        
+        // Only disturb if we moved to a new grid cell
+        let should_disturb = match water_data.last_disturbed_pos {
+            Some((last_x, last_y)) => last_x != grid_x || last_y != grid_y,
+            None => true,
+        };
+        
+        if should_disturb {
+            water_data.height[grid_x][grid_y] += 1.0;
+            water_data.last_disturbed_pos = Some((grid_x, grid_y));
+        }
     }
 }
 
@@ -602,12 +627,18 @@ fn setup(
         ..default()
     });
 
+    // Initialize water data with wall boundaries
+    let mut water_data = WaterData::default();
+    
     // Water plane with 64x64 grid
-    let water_mesh_handle = meshes.add(create_water_mesh(8.0, 64));
+    let water_mesh_handle = meshes.add(create_water_mesh(water_data.world_size, WATER_GRID_LEN as u32));
     let water_material_handle = water_materials.add(WaterMaterial::new(Color::srgba(0.1, 0.3, 0.8, 0.8)));
     
-    // Initialize water data with wall boundaries
-    let mut water_data = WaterData::default();
+    if let Some(path) = WALL_MASK_IMAGE {
+        if let Err(e) = water_data.set_walls_from_image(path) {
+            eprintln!("Failed to load wall mask {}: {}", path, e);
+        }
+    }
     
     // Set wall mask for boundary cells (edges of the water plane)
     for i in 0..WATER_GRID_LEN {
@@ -619,11 +650,12 @@ fn setup(
         water_data.wall_mask[0][i] = true;
         water_data.wall_mask[WATER_GRID_LEN - 1][i] = true;
     }
+    water_data.warn_about_enclosed_regions();
     
     commands.spawn((
         Mesh3d(water_mesh_handle.clone()),
         MeshMaterial3d(water_material_handle),
-        Transform::default(),
+        Transform::from_translation(water_data.world_center),
         water_data,
         WaterMesh { handle: water_mesh_handle },
         // Wireframe, // enable wireframe for debugging
@@ -672,6 +704,9 @@ struct WaterData {
     flow_y: [[f32; WATER_GRID_LEN]; WATER_GRID_LEN],
     last_disturbed_pos: Option<(usize, usize)>,
     wall_mask: [[bool; WATER_GRID_LEN]; WATER_GRID_LEN], // Track where walls are placed
+    // World position of the center of the water plane at rest height, and its width and depth
+    world_center: Vec3,
+    world_size: f32,
 }
 
 #[derive(Component)]
@@ -690,7 +725,150 @@ impl Default for WaterData {
             flow_y: [[0.0; WATER_GRID_LEN]; WATER_GRID_LEN],
             last_disturbed_pos: None,
             wall_mask: [[false; WATER_GRID_LEN]; WATER_GRID_LEN], // No walls initially
+            world_center: Vec3::ZERO,
+            world_size: 8.0,
+        }
+    }
+}
+
+impl WaterData {
+    // Cell under a world position (the height is ignored), None outside the grid or on a wall
+    fn world_to_grid(&self, world_pos: Vec3) -> Option<(usize, usize)> {
+        let u = (world_pos.x - self.world_center.x) / self.world_size + 0.5;
+        let v = (world_pos.z - self.world_center.z) / self.world_size + 0.5;
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+        
+        let grid_x = ((u * WATER_GRID_LEN as f32) as usize).min(WATER_GRID_LEN - 1);
+        let grid_y = ((v * WATER_GRID_LEN as f32) as usize).min(WATER_GRID_LEN - 1);
+        if self.wall_mask[grid_x][grid_y] {
+            return None;
+        }
+        Some((grid_x, grid_y))
+    }
+    
+    // Walls block flow between cells, heights under a wall are ignored
+    fn set_wall(&mut self, grid_x: usize, grid_y: usize, is_wall: bool) {
+        if grid_x < WATER_GRID_LEN && grid_y < WATER_GRID_LEN {
+            self.wall_mask[grid_x][grid_y] = is_wall;
+        }
+    }
+    
+    // Replaces the walls with an image's non-black pixels. The image is stretched over the grid,
+    // x to the right and y down, and sampled at each cell's center.
+    fn set_walls_from_image(&mut self, path: &str) -> Result<(), image::ImageError> {
+        let mask = image::open(path)?.to_luma8();
+        let (width, height) = mask.dimensions();
+        for grid_x in 0..WATER_GRID_LEN {
+            for grid_y in 0..WATER_GRID_LEN {
+                let pixel_x = ((grid_x as f32 + 0.5) / WATER_GRID_LEN as f32 * width as f32) as u32;
+                let pixel_y = ((grid_y as f32 + 0.5) / WATER_GRID_LEN as f32 * height as f32) as u32;
+                let is_wall = mask.get_pixel(pixel_x.min(width - 1), pixel_y.min(height - 1))[0] > 0;
+                self.set_wall(grid_x, grid_y, is_wall);
+            }
+        }
+        Ok(())
+    }
+    
+    // Water can't leave a region walled off from the rest, and with the 0.1 height floor a
+    // small pocket can keep filling up. Warns when the open cells aren't all connected.
+    fn warn_about_enclosed_regions(&self) {
+        let mut region = [[0usize; WATER_GRID_LEN]; WATER_GRID_LEN];
+        let mut region_sizes = Vec::new();
+        for start_x in 0..WATER_GRID_LEN {
+            for start_y in 0..WATER_GRID_LEN {
+                if self.wall_mask[start_x][start_y] || region[start_x][start_y] != 0 {
+                    continue;
+                }
+                
+                // Flood fill the cells reachable from here, regions are numbered from 1
+                region_sizes.push(0);
+                let region_id = region_sizes.len();
+                let mut stack = vec![(start_x, start_y)];
+                region[start_x][start_y] = region_id;
+                while let Some((x, y)) = stack.pop() {
+                    region_sizes[region_id - 1] += 1;
+                    let neighbours = [
+                        (x.wrapping_sub(1), y),
+                        (x + 1, y),
+                        (x, y.wrapping_sub(1)),
+                        (x, y + 1),
+                    ];
+                    for (nx, ny) in neighbours {
+                        if nx < WATER_GRID_LEN && ny < WATER_GRID_LEN
+                            && !self.wall_mask[nx][ny] && region[nx][ny] == 0 {
+                            region[nx][ny] = region_id;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
+        }
+        
+        if region_sizes.len() > 1 {
+            println!(
+                "WARNING: walls split the water into {} enclosed regions (sizes {:?}), water can build up in the small ones",
+                region_sizes.len(),
+                region_sizes,
+            );
+        }
+    }
+    
+    // World height of the water surface over a cell, e.g. for boats and buoyancy. None outside
+    // the grid or on a wall. The sim runs on the CPU, so this reads the live heights with no
+    // latency and there's nothing to sync. A GPU sim would need a host visible copy of its
+    // height buffer, refreshed every few frames so queries don't stall on the GPU.
+    fn read_height(&self, grid_x: usize, grid_y: usize) -> Option<f32> {
+        if grid_x >= WATER_GRID_LEN || grid_y >= WATER_GRID_LEN || self.wall_mask[grid_x][grid_y] {
+            return None;
+        }
+        // The plane sits at the rest height of 1.0
+        Some(self.world_center.y + self.height[grid_x][grid_y] - 1.0)
+    }
+    
+    // World position of a cell's center on the water plane at rest height
+    fn grid_to_world(&self, grid_x: usize, grid_y: usize) -> Vec3 {
+        let cell_size = self.world_size / WATER_GRID_LEN as f32;
+        let half_size = self.world_size * 0.5;
+        self.world_center + Vec3::new(
+            (grid_x as f32 + 0.5) * cell_size - half_size,
+            0.0,
+            (grid_y as f32 + 0.5) * cell_size - half_size,
+        )
+    }
+    
+    // A line per open cell from its center on the water surface along the cell's flow, colored
+    // from blue to red by length. Lines are capped at one cell so high flow doesn't overlap the
+    // neighbours, the color stays red once capped. Walls and still cells get no line.
+    fn flow_field_lines(&self, scale: f32) -> Vec<(Vec3, Vec3, Color)> {
+        let cell_size = self.world_size / WATER_GRID_LEN as f32;
+        let mut lines = Vec::new();
+        for x in 0..WATER_GRID_LEN {
+            for y in 0..WATER_GRID_LEN {
+                let Some(surface_height) = self.read_height(x, y) else {
+                    continue;
+                };
+                
+                // flow_x[x][y] runs through the face between cells x - 1 and x, so the cell's
+                // other edge is flow_x[x + 1][y], likewise for y. Past the grid there's no flow.
+                let flow_right = if x + 1 < WATER_GRID_LEN { self.flow_x[x + 1][y] } else { 0.0 };
+                let flow_up = if y + 1 < WATER_GRID_LEN { self.flow_y[x][y + 1] } else { 0.0 };
+                let flow = Vec2::new(self.flow_x[x][y] + flow_right, self.flow_y[x][y] + flow_up) * 0.5;
+                let length = flow.length() * scale;
+                if length < cell_size * 0.05 {
+                    continue;
+                }
+                
+                let capped_length = length.min(cell_size);
+                let direction = flow.normalize() * capped_length;
+                let start = self.grid_to_world(x, y).with_y(surface_height);
+                let end = start + Vec3::new(direction.x, 0.0, direction.y);
+                let hue = 240.0 * (1.0 - capped_length / cell_size);
+                lines.push((start, end, Color::hsl(hue, 1.0, 0.5)));
+            }
         }
+        lines
     }
 }
 
@@ -716,7 +894,7 @@ impl WaterMaterial {
             color: Vec4::new(color.to_linear().red, color.to_linear().green, color.to_linear().blue, color.to_linear().alpha),
             time: 0.0,
             camera_position: Vec3::ZERO,
-            resolution: Vec2::new(1920.0, 1080.0), // Default resolution
+            resolution: Vec2::ONE, // Set from the window by update_water_material
             water_level: 0.0,
             grid_scale: 8.0 / WATER_GRID_LEN as f32, // Scale based on water size
         }
@@ -1,12 +1,12 @@
 use bevy::prelude::*;
 use bevy::window::{PrimaryWindow, RawHandleWrapperHolder};
-use rand::Rng;
 
 use vulkan_bevy_renderer::{
     setup_bevy_app,
     vulkan_renderer_unified::VulkanRenderer,
     gltf_loader::GltfData,
     fps_logger::FpsLogger,
+    random::scatter_positions,
     ash,
 };
 
@@ -16,32 +16,27 @@ fn main() {
     app.add_systems(PostStartup, setup_vulkan_renderer)
         .add_systems(
             Update,
-            render_frame.run_if(resource_exists::<VulkanContext>),
+            render_frame,
         )
         .run();
 }
 
-#[derive(Resource)]
+// Seed for the instance positions
+const GRAPES_SEED: u64 = 1000;
+
+// Non-send, so the renderer stays on the main thread with the window
 struct VulkanContext(VulkanRenderer);
 
-fn setup_vulkan_renderer(
-    mut commands: Commands,
-    windows: Query<(Entity, &RawHandleWrapperHolder, &Window), With<PrimaryWindow>>,
-) {
-    let (_entity, handle_wrapper, window) = windows.single().expect("Failed to get primary window");
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn setup_vulkan_renderer(world: &mut World) {
+    let mut windows = world.query_filtered::<(&RawHandleWrapperHolder, &Window), With<PrimaryWindow>>();
+    let (handle_wrapper, window) = windows.single(world).expect("Failed to get primary window");
     
     println!("Window size: {:?}x{:?}", window.width(), window.height());
     
-    // Generate random positions for 1000 instances in a larger volume
-    let mut rng = rand::thread_rng();
-    let mut instance_positions = Vec::new();
-    for _ in 0..1000 {
-        instance_positions.push([
-            rng.gen_range(-10.0..10.0),
-            rng.gen_range(-7.0..7.0),
-            rng.gen_range(-5.0..5.0),
-        ]);
-    }
+    // Generate random positions for 1000 instances in a larger volume,
+    // seeded so every run benchmarks the same scene
+    let instance_positions = scatter_positions(GRAPES_SEED, 1000, [-10.0, -7.0, -5.0], [10.0, 7.0, 5.0]);
     println!("Generated {} random positions", instance_positions.len());
     
     // Load the GLB file using the consolidated loader
@@ -88,15 +83,19 @@ fn setup_vulkan_renderer(
         ).expect("Failed to create instanced Vulkan renderer")
     };
     
-    commands.insert_resource(VulkanContext(renderer));
+    world.insert_non_send_resource(VulkanContext(renderer));
     println!("Instanced Vulkan renderer created successfully!");
 }
 
 fn render_frame(
-    mut vulkan: ResMut<VulkanContext>,
+    vulkan: Option<NonSendMut<VulkanContext>>,
     mut fps_logger: Local<FpsLogger>,
     time: Res<Time>,
 ) {
+    let Some(mut vulkan) = vulkan else {
+        return;
+    };
+    
     fps_logger.update(&time);
     
     // Render using true GPU instanced rendering
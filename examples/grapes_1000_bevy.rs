@@ -13,7 +13,10 @@ use bevy::scene::ScenePlugin;
 use bevy::transform::TransformPlugin;
 use bevy::input::InputPlugin;
 use bevy::input::keyboard::KeyboardFocusLost;
-use rand::Rng;
+use vulkan_bevy_renderer::random::SeededRng;
+
+// Seed for the grape positions
+const GRAPES_SEED: u64 = 1000;
 
 fn main() {
     App::new()
@@ -53,14 +56,15 @@ fn setup(
 ) {
     println!("Setting up scene with 1000 grapes...");
     
-    let mut rng = rand::thread_rng();
+    // Seeded so every run benchmarks the same scene
+    let mut rng = SeededRng::new(GRAPES_SEED);
     
     // Spawn 1000 grape models at random positions
     for _ in 0..1000 {
         // Keep them close together for a dense cloud
-        let x = rng.gen_range(-1.0..1.0);
-        let y = rng.gen_range(-0.5..0.5);
-        let z = rng.gen_range(-0.5..0.5);
+        let x = rng.range_f32(-1.0..1.0);
+        let y = rng.range_f32(-0.5..0.5);
+        let z = rng.range_f32(-0.5..0.5);
         
         commands.spawn((
             SceneRoot(asset_server.load("red_grapes_wjbgdiz_low.glb#Scene0")),
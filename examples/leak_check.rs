@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, RawHandleWrapperHolder};
+
+use vulkan_bevy_renderer::{setup_bevy_app, vulkan_renderer_unified::VulkanRenderer};
+use vulkan_bevy_renderer::mesh::{MeshData, Vertex};
+use vulkan_bevy_renderer::mesh_textured::{TexturedMeshData, TexturedVertex};
+use vulkan_bevy_renderer::texture::TextureData;
+
+// Creates and shuts down each renderer variant on the window. Built with the leak-check feature,
+// shutting down a renderer panics if any Vulkan object it created is still alive:
+//   cargo run --example leak_check --features leak-check
+fn main() {
+    let mut app = setup_bevy_app();
+
+    app.add_systems(PostStartup, create_and_drop_renderers)
+        .run();
+}
+
+fn quad() -> MeshData {
+    MeshData::new(
+        vec![
+            Vertex::new([-0.5, -0.5, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0]),
+            Vertex::new([0.5, -0.5, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0]),
+            Vertex::new([0.5, 0.5, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0]),
+            Vertex::new([-0.5, 0.5, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0]),
+        ],
+        vec![0, 1, 2, 2, 3, 0],
+    )
+}
+
+fn textured_quad() -> TexturedMeshData {
+    TexturedMeshData::new(
+        vec![
+            TexturedVertex::new([-0.5, -0.5, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0], 0),
+            TexturedVertex::new([0.5, -0.5, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0], 0),
+            TexturedVertex::new([0.5, 0.5, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0], 1),
+            TexturedVertex::new([-0.5, 0.5, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0], 1),
+        ],
+        vec![0, 1, 2, 2, 3, 0],
+    )
+}
+
+// Exclusive systems run on the main thread, where the window's surface can be created
+fn create_and_drop_renderers(world: &mut World) {
+    let mut windows = world.query_filtered::<&RawHandleWrapperHolder, With<PrimaryWindow>>();
+    let handle_wrapper = windows.single(world).expect("Failed to get primary window").clone();
+    let mesh = quad();
+
+    let renderer = VulkanRenderer::new_simple(&handle_wrapper, "shaders/triangle.vert.spv", "shaders/triangle.frag.spv", 3)
+        .expect("Failed to create simple renderer");
+    renderer.shutdown().expect("Failed to shut down the renderer");
+    println!("new_simple: no leaks");
+
+    let renderer = VulkanRenderer::new_from_mesh_data(&handle_wrapper, "shaders/mesh.vert.spv", "shaders/mesh.frag.spv", &mesh, 1)
+        .expect("Failed to create mesh renderer");
+    renderer.shutdown().expect("Failed to shut down the renderer");
+    println!("new_from_mesh_data: no leaks");
+
+    let textures = vec![TextureData::placeholder(), TextureData::placeholder()];
+    let renderer = VulkanRenderer::new_texture_array(
+        &handle_wrapper,
+        "shaders/texture_array.vert.spv",
+        "shaders/texture_array.frag.spv",
+        &textured_quad(),
+        &textures,
+    ).expect("Failed to create texture array renderer");
+    renderer.shutdown().expect("Failed to shut down the renderer");
+    println!("new_texture_array: no leaks");
+
+    let renderer = VulkanRenderer::new_textured_instanced(
+        &handle_wrapper,
+        "shaders/mesh_textured_instanced.vert.spv",
+        "shaders/mesh_textured.frag.spv",
+        &mesh,
+        None,
+        &[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]],
+    ).expect("Failed to create textured instanced renderer");
+    renderer.shutdown().expect("Failed to shut down the renderer");
+    println!("new_textured_instanced: no leaks");
+
+    // Meshes and pipelines added and removed after creation are freed too
+    let mut renderer = VulkanRenderer::new_multi_mesh(
+        &handle_wrapper,
+        "shaders/mesh.vert.spv",
+        "shaders/mesh.frag.spv",
+        vec![(&mesh, vec![[0.0, 0.0, 0.0]])],
+    ).expect("Failed to create multi mesh renderer");
+    renderer.add_pipeline("second", "shaders/mesh.vert.spv", "shaders/mesh.frag.spv").expect("Failed to add pipeline");
+    renderer.add_fluid_pipeline("water", "shaders/water.vert.spv", "shaders/water.frag.spv").expect("Failed to add fluid pipeline");
+    let added = renderer.add_mesh(&mesh).expect("Failed to add mesh");
+    renderer.add_mesh(&mesh).expect("Failed to add mesh");
+    renderer.remove_mesh(added);
+    renderer.shutdown().expect("Failed to shut down the renderer");
+    println!("new_multi_mesh: no leaks");
+
+    let _ = world.send_event(AppExit::Success);
+}
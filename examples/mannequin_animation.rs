@@ -1,5 +1,7 @@
 // Set this to true to render multiple instanced mannequins, false for single mannequin
 const SHOW_MULTIPLE: bool = true;
+// DualQuaternion keeps volume at twisted joints, compare the elbows and shoulders with LinearBlend
+const SKINNING_MODE: SkinningMode = SkinningMode::LinearBlend;
 
 use bevy::prelude::*;
 use bevy::window::{PrimaryWindow, RawHandleWrapperHolder, WindowPlugin, Window};
@@ -19,8 +21,10 @@ use bevy::input::InputPlugin;
 use bevy::input::keyboard::KeyboardFocusLost;
 
 use vulkan_bevy_renderer::{
+    FocusSettings,
+    pause_on_focus_change,
     vulkan_renderer_unified::VulkanRenderer,
-    skinned_mesh::SkinnedMeshData,
+    skinned_mesh::{SkinnedMeshData, SkinningMode},
     utils,
     fps_logger::FpsLogger
 };
@@ -74,6 +78,9 @@ fn main() {
             scene_spawned: false,
         })
         .insert_resource(utils::ExtractedMeshData::default())
+        // Animation players run on virtual time, so this freezes the mannequin while unfocused
+        .insert_resource(FocusSettings { pause_when_unfocused: true })
+        .add_systems(PreUpdate, pause_on_focus_change)
         .add_systems(Startup, setup)
         .add_systems(Update, (
             check_gltf_loaded,
@@ -230,15 +237,12 @@ fn create_vulkan_renderer_for_mesh(
         },
     ];
     
-    let basic_mesh_data = MeshData {
-        vertices,
-        indices: vec![0],
-    };
+    let basic_mesh_data = MeshData::new(vertices, vec![0]);
     
     // Create base renderer with a simple shader
     let mut renderer = VulkanRenderer::new_from_mesh_data(
         window_handle,
-        "shaders/mesh.vert.spv",
+        "shaders/mesh_mvp.vert.spv",
         "shaders/mesh.frag.spv",
         &basic_mesh_data,
         1,
@@ -247,19 +251,23 @@ fn create_vulkan_renderer_for_mesh(
     // Add the appropriate skinned pipeline with correct descriptor set layout
     if SHOW_MULTIPLE {
         // Add instanced skinning pipeline
-        renderer.add_skinned_pipeline(
+        renderer.add_skinned_pipeline_with_skinning_mode(
             "skinned_instanced",
             "shaders/skinned_instanced.vert.spv",
             "shaders/mesh.frag.spv",
             true, // use_instancing
+            mesh_data.joint_matrices.len(),
+            SKINNING_MODE,
         )?;
     } else {
         // Add single skinned mesh pipeline
-        renderer.add_skinned_pipeline(
+        renderer.add_skinned_pipeline_with_skinning_mode(
             "skinned",
             "shaders/skinned_full.vert.spv",
             "shaders/mesh.frag.spv",
             false, // use_instancing
+            mesh_data.joint_matrices.len(),
+            SKINNING_MODE,
         )?;
     }
     
@@ -299,7 +307,7 @@ fn render_frame(renderer: &mut VulkanRenderer, _mesh_data: &SkinnedMeshData, _ti
     );
     let proj = bevy::math::Mat4::perspective_rh(
         60.0_f32.to_radians(),  // FOV
-        800.0 / 600.0,
+        renderer.aspect_ratio(),
         0.1,    // Normal near plane
         1000.0, // Normal far plane
     );
@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, RawHandleWrapperHolder};
+use bevy::math::{Mat4, Vec3};
+
+use vulkan_bevy_renderer::{
+    setup_bevy_app,
+    vulkan_renderer_unified::VulkanRenderer,
+    vulkan_common::{BlendMode, CoreOptions},
+    gltf_loader::GltfData,
+    fps_logger::FpsLogger,
+    ash::vk,
+};
+
+// How much bigger the outline shell is than the model
+const OUTLINE_SCALE: f32 = 1.05;
+
+fn main() {
+    let mut app = setup_bevy_app();
+
+    app.add_systems(PostStartup, setup_vulkan_renderer)
+        .add_systems(
+            Update,
+            render_frame,
+        )
+        .run();
+}
+
+// Non-send, so the renderer stays on the main thread with the window
+struct VulkanContext {
+    renderer: VulkanRenderer,
+    fps_logger: FpsLogger,
+    model: usize,
+    outline: usize,
+}
+
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn setup_vulkan_renderer(world: &mut World) {
+    let mut windows = world.query_filtered::<&RawHandleWrapperHolder, With<PrimaryWindow>>();
+    let handle_wrapper = windows.single(world).expect("Failed to get primary window");
+
+    let mesh_data = GltfData::load_from_file("assets/red_grapes_wjbgdiz_low.glb")
+        .expect("Failed to load GLB file")
+        .mesh_data;
+
+    // The stencil aspect has to be asked for when the renderer is created
+    let options = CoreOptions { stencil: true, ..Default::default() };
+    let mut renderer = VulkanRenderer::new_multi_mesh_with_options(
+        handle_wrapper,
+        "shaders/mesh_mvp.vert.spv",
+        "shaders/mesh.frag.spv",
+        Vec::new(),
+        &options,
+    ).expect("Failed to create Vulkan renderer");
+
+    // The model writes its reference (1) wherever it's drawn
+    let write_stencil = vk::StencilOpState {
+        fail_op: vk::StencilOp::KEEP,
+        pass_op: vk::StencilOp::REPLACE,
+        depth_fail_op: vk::StencilOp::KEEP,
+        compare_op: vk::CompareOp::ALWAYS,
+        compare_mask: 0xff,
+        write_mask: 0xff,
+        reference: 0,
+    };
+    renderer.add_stencil_pipeline(
+        "model",
+        "shaders/mesh_mvp.vert.spv",
+        "shaders/mesh.frag.spv",
+        write_stencil,
+        BlendMode::Opaque,
+    ).expect("Failed to add model pipeline");
+
+    // The scaled up shell only shows where the model didn't write, leaving a rim around it
+    let outside_model = vk::StencilOpState {
+        pass_op: vk::StencilOp::KEEP,
+        compare_op: vk::CompareOp::NOT_EQUAL,
+        write_mask: 0,
+        ..write_stencil
+    };
+    renderer.add_stencil_pipeline(
+        "outline",
+        "shaders/mesh_mvp.vert.spv",
+        "shaders/outline.frag.spv",
+        outside_model,
+        BlendMode::Opaque,
+    ).expect("Failed to add outline pipeline");
+
+    // Pipelines are drawn in the order their meshes were added, so the model goes first
+    let model = renderer.add_mesh(&mesh_data).expect("Failed to add model mesh");
+    renderer.set_mesh_pipeline(model, "model");
+    renderer.set_mesh_stencil_reference(model, 1);
+    renderer.set_mesh_color(model, [0.8, 0.1, 0.2, 1.0]);
+
+    let outline = renderer.add_mesh(&mesh_data).expect("Failed to add outline mesh");
+    renderer.set_mesh_pipeline(outline, "outline");
+    renderer.set_mesh_stencil_reference(outline, 1);
+    renderer.set_mesh_color(outline, [1.0, 0.8, 0.1, 1.0]);
+    // The outline stays visible through whatever is in front of the model
+    renderer.set_mesh_depth_test(outline, false);
+    renderer.set_mesh_depth_write(outline, false);
+
+    world.insert_non_send_resource(VulkanContext {
+        renderer,
+        fps_logger: FpsLogger::new(),
+        model,
+        outline,
+    });
+}
+
+fn render_frame(
+    vulkan: Option<NonSendMut<VulkanContext>>,
+    time: Res<Time>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+) {
+    let Some(mut vulkan) = vulkan else {
+        return;
+    };
+
+    vulkan.fps_logger.update(&time);
+
+    let window = windows.single().expect("Failed to get primary window");
+    let width = window.physical_width();
+    let height = window.physical_height();
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // Spin the model, the shell follows it scaled about the same origin
+    let spin = Mat4::from_rotation_y(time.elapsed_secs() * 0.5);
+    let (model, outline) = (vulkan.model, vulkan.outline);
+    vulkan.renderer.update_mesh_transforms(model, vec![spin]);
+    vulkan.renderer.update_mesh_transforms(outline, vec![spin * Mat4::from_scale(Vec3::splat(OUTLINE_SCALE))]);
+
+    let view = Mat4::look_at_rh(Vec3::new(0.0, 0.5, 3.0), Vec3::ZERO, Vec3::NEG_Y);
+    let proj = Mat4::perspective_rh(60.0_f32.to_radians(), width as f32 / height as f32, 0.1, 100.0);
+    vulkan.renderer.render_frame_with_camera_multi(view, proj);
+}
@@ -9,22 +9,21 @@ fn main() {
     app.add_systems(PostStartup, setup_vulkan_renderer)
         .add_systems(
             Update,
-            render_frame.run_if(resource_exists::<VulkanContext>),
+            render_frame,
         )
         .run();
 }
 
-#[derive(Resource)]
+// Non-send, so the renderer stays on the main thread with the window
 struct VulkanContext {
     renderer: VulkanRenderer,
     fps_logger: FpsLogger,
 }
 
-fn setup_vulkan_renderer(
-    mut commands: Commands,
-    windows: Query<(Entity, &RawHandleWrapperHolder, &Window), With<PrimaryWindow>>,
-) {
-    let (_entity, handle_wrapper, window) = windows.single().expect("Failed to get primary window");
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn setup_vulkan_renderer(world: &mut World) {
+    let mut windows = world.query_filtered::<(&RawHandleWrapperHolder, &Window), With<PrimaryWindow>>();
+    let (handle_wrapper, window) = windows.single(world).expect("Failed to get primary window");
     
     println!("Window size: {:?}x{:?}", window.width(), window.height());
     println!("Window visible: {:?}", window.visible);
@@ -36,16 +35,20 @@ fn setup_vulkan_renderer(
         3,
     ).expect("Failed to create Vulkan renderer");
     
-    commands.insert_resource(VulkanContext { 
+    world.insert_non_send_resource(VulkanContext { 
         renderer,
         fps_logger: FpsLogger::new(),
     });
 }
 
 fn render_frame(
-    mut vulkan: ResMut<VulkanContext>,
+    vulkan: Option<NonSendMut<VulkanContext>>,
     time: Res<Time>,
 ) {
+    let Some(mut vulkan) = vulkan else {
+        return;
+    };
+    
     vulkan.fps_logger.update(&time);
     vulkan.renderer.render_frame();
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, RawHandleWrapperHolder};
+use bevy::math::{Mat4, Vec2, Vec3};
+
+use vulkan_bevy_renderer::{
+    setup_bevy_app,
+    vulkan_renderer_unified::VulkanRenderer,
+    vulkan_common::SamplerAddressModes,
+    primitives,
+    fps_logger::FpsLogger,
+    ash::vk,
+};
+
+const TEXTURE_PATH: &str = "assets/Stone Wall/Stone_Wall_basecolor.jpg";
+
+fn main() {
+    let mut app = setup_bevy_app();
+
+    app.add_systems(PostStartup, setup_vulkan_renderer)
+        .add_systems(
+            Update,
+            render_frame,
+        )
+        .run();
+}
+
+// Non-send, so the renderer stays on the main thread with the window
+struct VulkanContext {
+    renderer: VulkanRenderer,
+    fps_logger: FpsLogger,
+}
+
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn setup_vulkan_renderer(world: &mut World) {
+    let mut windows = world.query_filtered::<&RawHandleWrapperHolder, With<PrimaryWindow>>();
+    let handle_wrapper = windows.single(world).expect("Failed to get primary window");
+
+    let mut renderer = VulkanRenderer::new_multi_mesh(
+        handle_wrapper,
+        "shaders/mesh_mvp.vert.spv",
+        "shaders/mesh.frag.spv",
+        Vec::new(),
+    ).expect("Failed to create Vulkan renderer");
+
+    renderer.add_uv_transform_pipeline(
+        "uv",
+        "shaders/mesh_uv_transform.vert.spv",
+        "shaders/mesh_textured.frag.spv",
+        true,
+        vk::CullModeFlags::NONE,
+        vk::FrontFace::COUNTER_CLOCKWISE,
+    ).expect("Failed to add UV transform pipeline");
+
+    // A conveyor belt: the texture tiles 4 times along it and scrolls half a tile per second
+    let belt = renderer.add_mesh(&primitives::plane(1.0, 4.0, 1)).expect("Failed to add belt");
+    renderer.set_mesh_pipeline(belt, "uv");
+    renderer.set_mesh_texture_from_file_with_address_modes(belt, TEXTURE_PATH, SamplerAddressModes::REPEAT)
+        .expect("Failed to load belt texture");
+    renderer.set_mesh_uv_transform(belt, Vec2::new(1.0, 4.0), Vec2::ZERO).expect("Failed to set belt UVs");
+    renderer.set_mesh_uv_scroll(belt, Vec2::new(0.0, 0.5)).expect("Failed to scroll belt UVs");
+    renderer.update_mesh_transforms(belt, vec![Mat4::from_translation(Vec3::new(-0.8, 0.0, 0.0))]);
+
+    // The top left quarter of the texture, like one cell of an atlas. Clamped, so it doesn't
+    // bleed in texels from the other cells.
+    let cell = renderer.add_mesh(&primitives::plane(1.0, 1.0, 1)).expect("Failed to add atlas cell");
+    renderer.set_mesh_pipeline(cell, "uv");
+    renderer.set_mesh_texture_from_file_with_address_modes(cell, TEXTURE_PATH, SamplerAddressModes::CLAMP_TO_EDGE)
+        .expect("Failed to load atlas texture");
+    renderer.set_mesh_uv_transform(cell, Vec2::splat(0.5), Vec2::ZERO).expect("Failed to set atlas UVs");
+    renderer.update_mesh_transforms(cell, vec![Mat4::from_translation(Vec3::new(0.8, 0.0, 0.0))]);
+
+    world.insert_non_send_resource(VulkanContext {
+        renderer,
+        fps_logger: FpsLogger::new(),
+    });
+}
+
+fn render_frame(
+    vulkan: Option<NonSendMut<VulkanContext>>,
+    time: Res<Time>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+) {
+    let Some(mut vulkan) = vulkan else {
+        return;
+    };
+
+    vulkan.fps_logger.update(&time);
+
+    let window = windows.single().expect("Failed to get primary window");
+    let width = window.physical_width();
+    let height = window.physical_height();
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // The belt scrolls on the renderer's own clock, nothing to update per frame
+    let view = Mat4::look_at_rh(Vec3::new(0.0, 2.5, 2.5), Vec3::ZERO, Vec3::NEG_Y);
+    let proj = Mat4::perspective_rh(60.0_f32.to_radians(), width as f32 / height as f32, 0.1, 100.0);
+    vulkan.renderer.render_frame_with_camera_multi(view, proj);
+}
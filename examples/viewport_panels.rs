@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, RawHandleWrapperHolder};
+use bevy::math::{Mat4, Vec3};
+
+use vulkan_bevy_renderer::{
+    setup_bevy_app,
+    vulkan_renderer_unified::VulkanRenderer,
+    gltf_loader::GltfData,
+    fps_logger::FpsLogger,
+    ash,
+};
+
+// Width of the egui panel in physical pixels, the scene gets the rest of the window
+const PANEL_WIDTH: u32 = 280;
+
+fn main() {
+    let mut app = setup_bevy_app();
+
+    app.add_systems(PostStartup, setup_vulkan_renderer)
+        .add_systems(
+            Update,
+            render_frame,
+        )
+        .run();
+}
+
+// Non-send, so the renderer stays on the main thread with the window
+struct VulkanContext {
+    renderer: VulkanRenderer,
+    fps_logger: FpsLogger,
+    panel_on_left: bool,
+}
+
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn setup_vulkan_renderer(world: &mut World) {
+    let mut windows = world.query_filtered::<(&RawHandleWrapperHolder, &Window), With<PrimaryWindow>>();
+    let (handle_wrapper, window) = windows.single(world).expect("Failed to get primary window");
+
+    println!("Window size: {:?}x{:?}", window.physical_width(), window.physical_height());
+    println!("Press Tab to move the panel to the other side");
+
+    let mesh_data = GltfData::load_from_file("assets/red_grapes_wjbgdiz_low.glb")
+        .expect("Failed to load GLB file")
+        .mesh_data;
+
+    let mut renderer = VulkanRenderer::new_from_mesh_data_with_winding(
+        handle_wrapper,
+        "shaders/mesh_mvp.vert.spv",
+        "shaders/mesh.frag.spv",
+        &mesh_data,
+        1,
+        Some(ash::vk::FrontFace::CLOCKWISE), // Use clockwise winding for grapes
+    ).expect("Failed to create Vulkan renderer");
+
+    // mesh_mvp.vert uses the projection passed to the renderer
+    renderer.add_pipeline_with_texture_and_winding(
+        "mvp",
+        "shaders/mesh_mvp.vert.spv",
+        "shaders/mesh.frag.spv",
+        false,
+        ash::vk::FrontFace::CLOCKWISE,
+    ).expect("Failed to add mvp pipeline");
+    let mesh_index = renderer.add_mesh(&mesh_data).expect("Failed to add grapes mesh");
+    renderer.set_mesh_pipeline(mesh_index, "mvp");
+    renderer.update_mesh_transforms(mesh_index, vec![Mat4::IDENTITY]);
+
+    let render_pass = renderer.get_present_render_pass();
+    renderer.initialize_egui(render_pass)
+        .expect("Failed to initialize egui");
+
+    world.insert_non_send_resource(VulkanContext {
+        renderer,
+        fps_logger: FpsLogger::new(),
+        panel_on_left: true,
+    });
+}
+
+fn render_frame(
+    vulkan: Option<NonSendMut<VulkanContext>>,
+    time: Res<Time>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    let Some(mut vulkan) = vulkan else {
+        return;
+    };
+    
+    vulkan.fps_logger.update(&time);
+
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        vulkan.panel_on_left = !vulkan.panel_on_left;
+    }
+
+    let window = windows.single().expect("Failed to get primary window");
+    let width = window.physical_width();
+    let height = window.physical_height();
+    if width <= PANEL_WIDTH || height == 0 {
+        return;
+    }
+
+    // The scene viewport fills the window next to the panel
+    let panel_on_left = vulkan.panel_on_left;
+    let viewport_x = if panel_on_left { PANEL_WIDTH } else { 0 };
+    vulkan.renderer.set_viewport(viewport_x, 0, width - PANEL_WIDTH, height)
+        .expect("Failed to set viewport");
+
+    let stats = vulkan.renderer.last_frame_stats();
+    let egui_output = vulkan.renderer.get_egui_context().map(|ctx| {
+        let raw_input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            time: Some(time.elapsed_secs_f64()),
+            predicted_dt: time.delta_secs(),
+            ..Default::default()
+        };
+        ctx.begin_pass(raw_input);
+
+        let panel = if panel_on_left {
+            egui::SidePanel::left("scene_panel")
+        } else {
+            egui::SidePanel::right("scene_panel")
+        };
+        panel.exact_width(PANEL_WIDTH as f32).resizable(false).show(ctx, |ui| {
+            ui.heading("Scene");
+            ui.separator();
+            ui.label(format!("Viewport: {}x{} at x = {}", width - PANEL_WIDTH, height, viewport_x));
+            ui.label(format!("Draw calls: {}", stats.draw_calls));
+            ui.label(format!("Triangles: {}", stats.triangles));
+            ui.label(format!("Pipeline switches: {}", stats.pipeline_switches));
+            ui.separator();
+            ui.label("Tab moves this panel to the other side");
+        });
+
+        ctx.end_pass()
+    });
+
+    // Built for the whole window, the renderer corrects it to the viewport's aspect ratio
+    let angle = time.elapsed_secs() * 0.5;
+    let view = Mat4::look_at_rh(
+        Vec3::new(3.0 * angle.cos(), 1.0, 3.0 * angle.sin()),
+        Vec3::ZERO,
+        Vec3::NEG_Y, // Vulkan's clip space y points down
+    );
+    let proj = Mat4::perspective_rh(
+        45.0_f32.to_radians(),
+        width as f32 / height as f32,
+        0.1,
+        100.0,
+    );
+
+    vulkan.renderer.render_frame_with_egui(view, proj, egui_output);
+}
@@ -35,6 +35,13 @@ vec3 getNormalFromMap(sampler2D normalMap, vec2 uv, vec3 worldPos, vec3 normal)
     return normalize(TBN * tangentNormal);
 }
 
+// Discards fragments of alpha tested (cutout) materials below the threshold
+void alphaCutout(float alpha, float threshold) {
+    if (alpha < threshold) {
+        discard;
+    }
+}
+
 // Simple directional lighting calculation
 vec3 calculateSimpleDirectionalLight(vec3 normal, vec3 lightDir, vec3 baseColor) {
     float diff = max(dot(normalize(normal), normalize(lightDir)), 0.0);
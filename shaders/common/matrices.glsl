@@ -3,9 +3,8 @@
 #ifndef MATRICES_GLSL
 #define MATRICES_GLSL
 
-mat4 getProjectionMatrix() {
-    // Adjust for typical 16:9 aspect ratio (1920x1080)
-    float aspectRatio = 16.0 / 9.0;
+// aspectRatio is width over height of the target, pass the live one so the image doesn't stretch
+mat4 getProjectionMatrix(float aspectRatio) {
     return mat4(
         1.0 / aspectRatio, 0.0, 0.0, 0.0,
         0.0, 1.0, 0.0, 0.0,
@@ -14,10 +13,8 @@ mat4 getProjectionMatrix() {
     );
 }
 
-mat4 getProjectionMatrixVulkan() {
+mat4 getProjectionMatrixVulkan(float aspectRatio) {
     // Y-axis flipped for Vulkan's coordinate system
-    // Adjust for typical 16:9 aspect ratio (1920x1080)
-    float aspectRatio = 16.0 / 9.0;
     return mat4(
         1.0 / aspectRatio, 0.0, 0.0, 0.0,
         0.0, -1.0, 0.0, 0.0,
@@ -0,0 +1,29 @@
+// Morph targets (blend shapes), see VulkanRenderer::set_morph_weights. The deltas are stored
+// per vertex, then per target, as a position and a normal delta padded to vec4s.
+
+#ifndef MORPH_GLSL
+#define MORPH_GLSL
+
+layout(set = 0, binding = 3) readonly buffer MorphDeltas {
+    vec4 deltas[];
+} morphDeltas;
+
+layout(set = 0, binding = 4) readonly buffer MorphWeights {
+    float weights[];
+} morphWeights;
+
+// Adds each target's weighted deltas to the base position and normal. A target count of 0 (meshes
+// without morph targets) leaves them as they are and never reads the buffers.
+void applyMorphTargets(uint vertexIndex, uint targetCount, inout vec3 position, inout vec3 normal) {
+    for (uint morphTarget = 0; morphTarget < targetCount; morphTarget++) {
+        float weight = morphWeights.weights[morphTarget];
+        if (weight == 0.0) {
+            continue;
+        }
+        uint base = (vertexIndex * targetCount + morphTarget) * 2;
+        position += morphDeltas.deltas[base].xyz * weight;
+        normal += morphDeltas.deltas[base + 1].xyz * weight;
+    }
+}
+
+#endif
@@ -0,0 +1,74 @@
+// Cascaded shadow map sampling, matches ShadowCascadeUniforms in src/shadow_cascades.rs
+
+#ifndef SHADOWS_GLSL
+#define SHADOWS_GLSL
+
+const int MAX_SHADOW_CASCADES = 4;
+
+struct ShadowCascades {
+    mat4 viewProj[MAX_SHADOW_CASCADES];
+    vec4 splitDepths;
+    uint count;
+    float blendBand;
+    // In texels of the sampled cascade
+    float normalBias;
+    uint pcfKernelSize;
+};
+
+// First cascade whose range contains the view space distance
+int selectShadowCascade(ShadowCascades cascades, float viewDepth) {
+    for (int i = 0; i < int(cascades.count) - 1; i++) {
+        if (viewDepth < cascades.splitDepths[i]) {
+            return i;
+        }
+    }
+    return int(cascades.count) - 1;
+}
+
+// 1.0 lit, 0.0 shadowed. The map is a depth array with one layer per cascade and compare enabled.
+// worldNormal moves the position normalBias texels off the surface, pcfKernelSize texels square
+// are averaged around it.
+float sampleShadowCascade(sampler2DArrayShadow shadowMap, ShadowCascades cascades, int cascade, vec3 worldPos, vec3 worldNormal, float bias) {
+    mat4 viewProj = cascades.viewProj[cascade];
+    vec2 mapSize = vec2(textureSize(shadowMap, 0).xy);
+    // The light projection is orthographic, so its first row's length is 2 / the cascade's width
+    float texelWorldSize = 2.0 / (length(vec3(viewProj[0][0], viewProj[1][0], viewProj[2][0])) * mapSize.x);
+    vec4 lightClip = viewProj * vec4(worldPos + worldNormal * cascades.normalBias * texelWorldSize, 1.0);
+    vec3 lightNdc = lightClip.xyz / lightClip.w;
+    vec2 uv = lightNdc.xy * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || lightNdc.z > 1.0) {
+        return 1.0;
+    }
+
+    int radius = int(max(cascades.pcfKernelSize, 1u)) / 2;
+    float lit = 0.0;
+    for (int y = -radius; y <= radius; y++) {
+        for (int x = -radius; x <= radius; x++) {
+            vec2 offset = vec2(x, y) / mapSize;
+            lit += texture(shadowMap, vec4(uv + offset, float(cascade), lightNdc.z - bias));
+        }
+    }
+    float taps = float(2 * radius + 1);
+    return lit / (taps * taps);
+}
+
+// Picks the cascade by view depth, and blends into the next one over the last blendBand
+// fraction of a cascade's range so the seams don't show
+float cascadedShadow(sampler2DArrayShadow shadowMap, ShadowCascades cascades, vec3 worldPos, vec3 worldNormal, float viewDepth, float bias) {
+    int cascade = selectShadowCascade(cascades, viewDepth);
+    float shadow = sampleShadowCascade(shadowMap, cascades, cascade, worldPos, worldNormal, bias);
+
+    if (cascades.blendBand > 0.0 && cascade < int(cascades.count) - 1) {
+        float rangeStart = cascade == 0 ? 0.0 : cascades.splitDepths[cascade - 1];
+        float rangeEnd = cascades.splitDepths[cascade];
+        float bandStart = rangeEnd - (rangeEnd - rangeStart) * cascades.blendBand;
+        float blend = smoothstep(bandStart, rangeEnd, viewDepth);
+        if (blend > 0.0) {
+            float next = sampleShadowCascade(shadowMap, cascades, cascade + 1, worldPos, worldNormal, bias);
+            shadow = mix(shadow, next, blend);
+        }
+    }
+    return shadow;
+}
+
+#endif // SHADOWS_GLSL
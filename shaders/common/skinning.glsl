@@ -0,0 +1,29 @@
+// Dual quaternion skinning, selected with SkinningMode::DualQuaternion. The renderer packs each
+// joint's dual quaternion into the first two columns of its joint matrix: real part, then dual part.
+
+#ifndef SKINNING_GLSL
+#define SKINNING_GLSL
+
+// Blends the joints' dual quaternions and applies the result to a position. Unlike blended
+// matrices the blend stays a rotation plus translation, so twisted joints keep their volume.
+vec3 dualQuaternionSkin(mat4 joints[4], vec4 weights, vec3 position) {
+    vec4 blendReal = vec4(0.0);
+    vec4 blendDual = vec4(0.0);
+    for (int i = 0; i < 4; i++) {
+        // q and -q are the same rotation, keep every joint in the first one's hemisphere
+        // so the blend goes the short way around
+        float weight = dot(joints[i][0], joints[0][0]) < 0.0 ? -weights[i] : weights[i];
+        blendReal += joints[i][0] * weight;
+        blendDual += joints[i][1] * weight;
+    }
+    float len = length(blendReal);
+    blendReal /= len;
+    blendDual /= len;
+
+    vec3 r = blendReal.xyz;
+    vec3 rotated = position + 2.0 * cross(r, cross(r, position) + blendReal.w * position);
+    vec3 translation = 2.0 * (blendReal.w * blendDual.xyz - blendDual.w * r + cross(r, blendDual.xyz));
+    return rotated + translation;
+}
+
+#endif
@@ -27,4 +27,21 @@ vec3 getSkyColor(vec3 direction) {
     return color;
 }
 
+// Two color gradient with a soft sun disk, driven by runtime parameters.
+// sunDirection points towards the sun, intensity 0 hides it.
+vec3 getSkyGradient(vec3 direction, vec3 horizonColor, vec3 zenithColor, vec3 sunDirection, float sunIntensity) {
+    float height = clamp(direction.y, 0.0, 1.0);
+    vec3 color = mix(horizonColor, zenithColor, smoothstep(0.0, 1.0, sqrt(height)));
+    
+    // Darken below the horizon
+    color *= mix(0.5, 1.0, clamp(direction.y * 4.0 + 1.0, 0.0, 1.0));
+    
+    float sunDot = max(dot(direction, sunDirection), 0.0);
+    float disk = smoothstep(0.9990, 0.9997, sunDot);
+    float glow = pow(sunDot, 64.0) * 0.35;
+    color += vec3(1.0, 0.95, 0.85) * (disk + glow) * sunIntensity;
+    
+    return color;
+}
+
 #endif // SKY_GLSL
\ No newline at end of file
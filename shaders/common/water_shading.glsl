@@ -0,0 +1,61 @@
+// Water surface shading shared by water.frag and water_reflection.frag
+
+#ifndef WATER_SHADING_GLSL
+#define WATER_SHADING_GLSL
+
+#include "lighting.glsl"
+#include "constants.glsl"
+
+// Surface normal facing the camera. eyeDir points from the surface to the camera.
+vec3 waterNormal(vec3 interpolatedNormal, vec3 eyeDir, bool twoSided) {
+    vec3 normal = normalize(interpolatedNormal);
+
+    // Ensure normal points up
+    if (normal.y < 0.0) {
+        normal = -normal;
+    }
+
+    if (twoSided) {
+        // Seen from below the underside faces down
+        if (!gl_FrontFacing) {
+            normal = -normal;
+        }
+        // At the waterline the camera looks along the surface and interpolated normals of the
+        // visible side can still point away from it, bend them back so lighting doesn't flip
+        float facing = dot(normal, eyeDir);
+        if (facing < 0.0) {
+            normal = normalize(normal - eyeDir * (facing - 0.001));
+        }
+    }
+    return normal;
+}
+
+// Lit water color, reflecting reflectedColor towards grazing angles
+vec3 shadeWater(vec3 worldPos, float waterLevel, vec3 normal, vec3 eyeDir, vec3 reflectedColor) {
+    // Light direction (normalized)
+    vec3 lightDir = normalize(LIGHT_POSITION);
+
+    // Enhanced water color variation
+    float heightFactor = (worldPos.y - waterLevel + 3.0) / 6.0;
+    vec3 waterDeep = vec3(0.05, 0.15, 0.4);
+    vec3 waterShallow = vec3(0.3, 0.8, 1.0);
+    vec3 simpleWater = mix(waterDeep, waterShallow, clamp(heightFactor, 0.0, 1.0));
+
+    // Enhanced lighting
+    float ndotl = max(dot(normal, lightDir), 0.0);
+    vec3 litWater = simpleWater * (0.4 + 0.8 * ndotl);
+
+    // Add depth-based darkening
+    float depthDarkening = smoothstep(0.0, 0.5, 1.0 - heightFactor);
+    vec3 darkenedWater = mix(litWater, litWater * 0.3, depthDarkening);
+
+    // Simple Fresnel reflection
+    float fresnelEffect = fresnel(normal, eyeDir, 2.0);
+    vec3 finalColor = mix(darkenedWater, reflectedColor, fresnelEffect * 0.3);
+
+    // Add specular highlights
+    float spec = specularEnergy(normal, lightDir, eyeDir, 80.0);
+    return finalColor + LIGHT_COLOR_WARM * spec * 0.8;
+}
+
+#endif // WATER_SHADING_GLSL
@@ -0,0 +1,46 @@
+// Shared by the tessellated water shaders (water_tess.vert, water.tesc, water.tese)
+
+#ifndef WATER_TESSELLATION_GLSL
+#define WATER_TESSELLATION_GLSL
+
+#include "matrices.glsl"
+
+// Matches WaterTessellationPushConstants: the fluid push constants plus tessellation settings
+layout(push_constant) uniform PushConstants {
+    float time;
+    float cameraPositionX;
+    float cameraPositionY;
+    float cameraPositionZ;
+    vec2 resolution;
+    float waterLevel;
+    float gridScale;
+    float nearFadeStart;
+    float nearFadeEnd;
+    float reflectionPass;
+    float padding;
+    float maxLevel;
+    float targetEdgePixels;
+    float displacementScale;
+    float heightTiling;
+} push;
+
+vec3 getWaterCameraPosition() {
+    return vec3(push.cameraPositionX, push.cameraPositionY, push.cameraPositionZ);
+}
+
+// Same camera as water.vert
+mat4 getWaterViewProjection() {
+    mat4 view = getViewMatrix(-getWaterCameraPosition());
+    
+    float aspectRatio = push.resolution.x / push.resolution.y;
+    mat4 projection = mat4(
+        1.0 / aspectRatio, 0.0, 0.0, 0.0,
+        0.0, -1.0, 0.0, 0.0,
+        0.0, 0.0, -1.0, -1.0,
+        0.0, 0.0, -0.2, 0.0
+    );
+    
+    return projection * view;
+}
+
+#endif // WATER_TESSELLATION_GLSL
@@ -2,8 +2,10 @@
 
 #include "common/matrices.glsl"
 
+// Offsets of the renderer's PushConstants, which has the target's size in resolution
 layout(push_constant) uniform PushConstants {
     float time;
+    layout(offset = 16) vec2 resolution;
 } push;
 
 layout(location = 0) out vec3 fragNormal;
@@ -72,7 +74,7 @@ void main() {
     angle = push.time * 0.7 + 0.5236;
     mat4 rotationX = getRotationMatrixX(angle);
     
-    mat4 projection = getProjectionMatrix();
+    mat4 projection = getProjectionMatrix(push.resolution.x / push.resolution.y);
     
     vec4 worldPos = rotationX * rotationY * vec4(position, 1.0);
     fragPos = worldPos.xyz;
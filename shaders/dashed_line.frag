@@ -0,0 +1,22 @@
+#version 450
+
+// Keeps the first dash pixels of every dash + gap along the line
+
+layout(location = 0) noperspective in float fragArcLength;
+layout(location = 1) in vec4 fragColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    float dash;
+    float gap;
+} pc;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float period = pc.dash + pc.gap;
+    if (period > 0.0 && mod(fragArcLength, period) >= pc.dash) {
+        discard;
+    }
+    outColor = fragColor;
+}
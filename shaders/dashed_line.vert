@@ -0,0 +1,22 @@
+#version 450
+
+// Line for dashed_lines.rs, passing the arc length in pixels on to the fragment shader
+
+layout(location = 0) in vec4 inPositionArcLength;
+layout(location = 1) in vec4 inColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    float dash;
+    float gap;
+} pc;
+
+// Pixels are linear on screen, so the arc length mustn't be perspective corrected
+layout(location = 0) noperspective out float fragArcLength;
+layout(location = 1) out vec4 fragColor;
+
+void main() {
+    gl_Position = pc.viewProj * vec4(inPositionArcLength.xyz, 1.0);
+    fragArcLength = inPositionArcLength.w;
+    fragColor = inColor;
+}
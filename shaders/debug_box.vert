@@ -0,0 +1,19 @@
+#version 450
+
+// Box edges for debug_boxes.rs, the unit cube's corners stretched from each instance's min to max
+
+layout(location = 0) in vec3 inCorner;
+layout(location = 1) in vec3 inMin;
+layout(location = 2) in vec3 inMax;
+layout(location = 3) in vec4 inColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+} pc;
+
+layout(location = 0) out vec4 fragColor;
+
+void main() {
+    gl_Position = pc.viewProj * vec4(mix(inMin, inMax, inCorner), 1.0);
+    fragColor = inColor;
+}
@@ -0,0 +1,39 @@
+#version 450
+
+// Debug views, see VulkanRenderer::set_debug_view. The present pass shows the scene image
+// without exposure or tonemapping while one is on.
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in float fragViewDepth;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+    // Matches DebugViewPushConstants on the Rust side, in the cutout threshold's slot
+    layout(offset = 208) uint mode;  // 0 = overdraw, 1 = normals, 2 = depth
+    float depthNear;
+    float depthFar;
+} pc;
+
+layout(location = 0) out vec4 outColor;
+
+// The present pass encodes to sRGB, decoding first puts the values themselves on screen
+vec3 srgbToLinear(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(color, vec3(0.04045)));
+}
+
+void main() {
+    if (pc.mode == 0u) {
+        // Added up by the overdraw pipelines' blending, the present pass maps the count to a heatmap
+        outColor = vec4(1.0);
+    } else if (pc.mode == 1u) {
+        outColor = vec4(srgbToLinear(normalize(fragNormal) * 0.5 + 0.5), 1.0);
+    } else {
+        float depth = clamp((fragViewDepth - pc.depthNear) / (pc.depthFar - pc.depthNear), 0.0, 1.0);
+        outColor = vec4(srgbToLinear(vec3(depth)), 1.0);
+    }
+}
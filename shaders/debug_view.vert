@@ -0,0 +1,24 @@
+#version 450
+
+// Mesh drawn per transform in a debug view, see VulkanRenderer::set_debug_view
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out float fragViewDepth;
+
+void main() {
+    vec4 viewPos = pc.view * pc.model * vec4(inPosition, 1.0);
+    fragNormal = mat3(pc.model) * inNormal;
+    fragViewDepth = -viewPos.z;
+
+    gl_Position = pc.proj * viewPos;
+}
@@ -0,0 +1,27 @@
+#version 450
+
+// Debug view of an instanced mesh with a transform per instance (InstanceData)
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+
+// Instance attributes, the color at location 8 isn't used
+layout(location = 4) in mat4 instanceTransform;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out float fragViewDepth;
+
+void main() {
+    vec4 viewPos = pc.view * instanceTransform * vec4(inPosition, 1.0);
+    fragNormal = mat3(instanceTransform) * inNormal;
+    fragViewDepth = -viewPos.z;
+
+    gl_Position = pc.proj * viewPos;
+}
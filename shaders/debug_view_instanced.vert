@@ -0,0 +1,28 @@
+#version 450
+
+// Debug view of an instanced mesh with one position per instance
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+
+// Instance attributes
+layout(location = 4) in vec3 instancePos;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out float fragViewDepth;
+
+void main() {
+    vec4 worldPos = pc.model * vec4(inPosition, 1.0) + vec4(instancePos, 0.0);
+    vec4 viewPos = pc.view * worldPos;
+    fragNormal = mat3(pc.model) * inNormal;
+    fragViewDepth = -viewPos.z;
+
+    gl_Position = pc.proj * viewPos;
+}
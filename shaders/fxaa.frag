@@ -0,0 +1,172 @@
+#version 450
+
+// FXAA, see VulkanRenderer::set_antialiasing. Runs after the other post effects on the HDR
+// image, with luma taken from a tonemapped estimate so edges are found where they show on
+// screen. A compact version of FXAA 3.11's quality path: find the edge direction, walk along
+// the edge to both of its ends, and blend across it by how close the pixel is to an end.
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform sampler2D inputImage;
+
+// Matches PostEffectPushConstants, params are FxaaSettings
+layout(push_constant) uniform PushConstants {
+    vec2 texelSize;
+    float params[16];  // params[0] = subpixel, params[1] = edge threshold, params[2] = edge threshold min
+} pc;
+
+// Steps taken along the edge after the first one, in pixels
+const int SEARCH_STEPS = 7;
+const float SEARCH_STEP_SIZES[SEARCH_STEPS] = float[](1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+// The post effect sampler is nearest, the edge search reads between pixels so blend here
+vec3 sampleBilinear(vec2 uv) {
+    vec2 pixel = uv / pc.texelSize - 0.5;
+    vec2 base = floor(pixel);
+    vec2 f = pixel - base;
+    ivec2 maxTexel = textureSize(inputImage, 0) - 1;
+    ivec2 p = ivec2(base);
+    vec3 a = texelFetch(inputImage, clamp(p, ivec2(0), maxTexel), 0).rgb;
+    vec3 b = texelFetch(inputImage, clamp(p + ivec2(1, 0), ivec2(0), maxTexel), 0).rgb;
+    vec3 c = texelFetch(inputImage, clamp(p + ivec2(0, 1), ivec2(0), maxTexel), 0).rgb;
+    vec3 d = texelFetch(inputImage, clamp(p + ivec2(1, 1), ivec2(0), maxTexel), 0).rgb;
+    return mix(mix(a, b, f.x), mix(c, d, f.x), f.y);
+}
+
+// Reinhard tonemapped, then roughly gamma encoded, which is what FXAA's thresholds expect
+float luma(vec3 color) {
+    float l = dot(color, vec3(0.299, 0.587, 0.114));
+    return sqrt(l / (1.0 + l));
+}
+
+float lumaAt(vec2 uv) {
+    return luma(sampleBilinear(uv));
+}
+
+// textureOffset needs a constant offset, a function parameter isn't one
+#define LUMA_OFFSET(x, y) luma(textureOffset(inputImage, fragTexCoord, ivec2(x, y)).rgb)
+
+void main() {
+    vec2 uv = fragTexCoord;
+    float subpixel = pc.params[0];
+    float edgeThreshold = pc.params[1];
+    float edgeThresholdMin = pc.params[2];
+
+    // fragTexCoord is a pixel center, so nearest sampling reads exact pixels. -y is up.
+    vec3 colorCenter = texture(inputImage, uv).rgb;
+    float lumaCenter = luma(colorCenter);
+    float lumaN = LUMA_OFFSET(0, -1);
+    float lumaS = LUMA_OFFSET(0, 1);
+    float lumaW = LUMA_OFFSET(-1, 0);
+    float lumaE = LUMA_OFFSET(1, 0);
+
+    float lumaMin = min(lumaCenter, min(min(lumaN, lumaS), min(lumaW, lumaE)));
+    float lumaMax = max(lumaCenter, max(max(lumaN, lumaS), max(lumaW, lumaE)));
+    float range = lumaMax - lumaMin;
+    // Low contrast, or dark enough that aliasing doesn't show
+    if (range < max(edgeThresholdMin, lumaMax * edgeThreshold)) {
+        outColor = vec4(colorCenter, 1.0);
+        return;
+    }
+
+    float lumaNW = LUMA_OFFSET(-1, -1);
+    float lumaNE = LUMA_OFFSET(1, -1);
+    float lumaSW = LUMA_OFFSET(-1, 1);
+    float lumaSE = LUMA_OFFSET(1, 1);
+    float lumaNS = lumaN + lumaS;
+    float lumaWE = lumaW + lumaE;
+    float lumaWestCorners = lumaNW + lumaSW;
+    float lumaEastCorners = lumaNE + lumaSE;
+    float lumaNorthCorners = lumaNW + lumaNE;
+    float lumaSouthCorners = lumaSW + lumaSE;
+
+    // A horizontal edge changes most from north to south
+    float edgeHorizontal = abs(-2.0 * lumaW + lumaWestCorners)
+        + abs(-2.0 * lumaCenter + lumaNS) * 2.0
+        + abs(-2.0 * lumaE + lumaEastCorners);
+    float edgeVertical = abs(-2.0 * lumaN + lumaNorthCorners)
+        + abs(-2.0 * lumaCenter + lumaWE) * 2.0
+        + abs(-2.0 * lumaS + lumaSouthCorners);
+    bool horizontal = edgeHorizontal >= edgeVertical;
+
+    // Which side of the pixel the edge is on
+    float luma1 = horizontal ? lumaN : lumaW;
+    float luma2 = horizontal ? lumaS : lumaE;
+    float gradient1 = luma1 - lumaCenter;
+    float gradient2 = luma2 - lumaCenter;
+    bool steepest1 = abs(gradient1) >= abs(gradient2);
+    float gradientScaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    float stepLength = horizontal ? pc.texelSize.y : pc.texelSize.x;
+    float lumaLocalAverage;
+    if (steepest1) {
+        stepLength = -stepLength;
+        lumaLocalAverage = 0.5 * (luma1 + lumaCenter);
+    } else {
+        lumaLocalAverage = 0.5 * (luma2 + lumaCenter);
+    }
+
+    // Walk along the edge, half a pixel towards it, until the luma no longer matches the edge's
+    vec2 edgeUv = uv;
+    if (horizontal) {
+        edgeUv.y += stepLength * 0.5;
+    } else {
+        edgeUv.x += stepLength * 0.5;
+    }
+    vec2 offset = horizontal ? vec2(pc.texelSize.x, 0.0) : vec2(0.0, pc.texelSize.y);
+    vec2 uv1 = edgeUv - offset;
+    vec2 uv2 = edgeUv + offset;
+    float lumaEnd1 = lumaAt(uv1) - lumaLocalAverage;
+    float lumaEnd2 = lumaAt(uv2) - lumaLocalAverage;
+    bool reached1 = abs(lumaEnd1) >= gradientScaled;
+    bool reached2 = abs(lumaEnd2) >= gradientScaled;
+    if (!reached1) {
+        uv1 -= offset;
+    }
+    if (!reached2) {
+        uv2 += offset;
+    }
+    for (int i = 0; i < SEARCH_STEPS && !(reached1 && reached2); i++) {
+        if (!reached1) {
+            lumaEnd1 = lumaAt(uv1) - lumaLocalAverage;
+            reached1 = abs(lumaEnd1) >= gradientScaled;
+            if (!reached1) {
+                uv1 -= offset * SEARCH_STEP_SIZES[i];
+            }
+        }
+        if (!reached2) {
+            lumaEnd2 = lumaAt(uv2) - lumaLocalAverage;
+            reached2 = abs(lumaEnd2) >= gradientScaled;
+            if (!reached2) {
+                uv2 += offset * SEARCH_STEP_SIZES[i];
+            }
+        }
+    }
+
+    // Blend more the closer the pixel is to the edge's nearer end, if the luma there changes
+    // the way the center's does
+    float distance1 = horizontal ? uv.x - uv1.x : uv.y - uv1.y;
+    float distance2 = horizontal ? uv2.x - uv.x : uv2.y - uv.y;
+    bool nearer1 = distance1 < distance2;
+    float edgeLength = distance1 + distance2;
+    float pixelOffset = 0.5 - min(distance1, distance2) / edgeLength;
+    bool centerSmaller = lumaCenter < lumaLocalAverage;
+    bool correctVariation = ((nearer1 ? lumaEnd1 : lumaEnd2) < 0.0) != centerSmaller;
+    float finalOffset = correctVariation ? pixelOffset : 0.0;
+
+    // Subpixel aliasing: single pixel features get blended by their contrast with the 3x3 average
+    float lumaAverage = (2.0 * (lumaNS + lumaWE) + lumaWestCorners + lumaEastCorners) / 12.0;
+    float subpixelContrast = clamp(abs(lumaAverage - lumaCenter) / range, 0.0, 1.0);
+    float subpixelBlend = (-2.0 * subpixelContrast + 3.0) * subpixelContrast * subpixelContrast;
+    finalOffset = max(finalOffset, subpixelBlend * subpixelBlend * subpixel);
+
+    vec2 finalUv = uv;
+    if (horizontal) {
+        finalUv.y += finalOffset * stepLength;
+    } else {
+        finalUv.x += finalOffset * stepLength;
+    }
+    outColor = vec4(sampleBilinear(finalUv), 1.0);
+}
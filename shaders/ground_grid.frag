@@ -0,0 +1,30 @@
+#version 450
+
+// Grid lines every spacing world units, about a pixel wide at any distance, fading out with the
+// distance from the camera
+
+layout(location = 0) in vec3 fragWorldPosition;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    vec4 color;
+    vec2 center;
+    float spacing;
+    float extent;
+    float fadeDistance;
+} pc;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec2 coord = fragWorldPosition.xz / pc.spacing;
+    // Grid units per pixel, so lines stay the same width on screen and are antialiased over one pixel
+    vec2 width = fwidth(coord);
+    vec2 lineDistance = abs(fract(coord - 0.5) - 0.5) / width;
+    float line = 1.0 - min(min(lineDistance.x, lineDistance.y), 1.0);
+    // Cells smaller than a couple of pixels would only alias, fade them out instead
+    line *= 1.0 - clamp(max(width.x, width.y) - 0.5, 0.0, 1.0);
+
+    float fade = 1.0 - smoothstep(0.0, pc.fadeDistance, distance(fragWorldPosition.xz, pc.center));
+    outColor = vec4(pc.color.rgb, pc.color.a * line * fade);
+}
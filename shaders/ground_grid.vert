@@ -0,0 +1,26 @@
+#version 450
+
+// Quad on the y = 0 plane for ground_grid.rs, centered under the camera so the grid never ends
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    vec4 color;
+    // xz under the camera, the quad's center
+    vec2 center;
+    float spacing;
+    float extent;
+    float fadeDistance;
+} pc;
+
+layout(location = 0) out vec3 fragWorldPosition;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    vec2 xz = pc.center + CORNERS[gl_VertexIndex] * pc.extent;
+    fragWorldPosition = vec3(xz.x, 0.0, xz.y);
+    gl_Position = pc.viewProj * vec4(fragWorldPosition, 1.0);
+}
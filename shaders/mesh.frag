@@ -9,12 +9,20 @@ layout(location = 3) in vec4 fragColor;
 
 layout(location = 0) out vec4 outColor;
 
+// Set by double sided variants of the pipeline, see VulkanRenderer::set_mesh_double_sided
+layout(constant_id = 0) const bool TWO_SIDED_LIGHTING = false;
+
 void main() {
     vec3 lightDir = normalize(vec3(0.5, 1.0, 0.8));
     vec3 normal = normalize(fragNormal);
+    // Back faces are lit from their own side
+    if (TWO_SIDED_LIGHTING && !gl_FrontFacing) {
+        normal = -normal;
+    }
     
     float diff = calculateDiffuse(normal, lightDir);
-    vec3 ambient = vec3(0.3, 0.2, 0.3);
+    // The alpha also carries ambient occlusion baked by MeshData::bake_vertex_ao
+    vec3 ambient = vec3(0.3, 0.2, 0.3) * fragColor.a;
     vec3 diffuse = vec3(0.8, 0.3, 0.4) * diff;
     
     vec3 viewDir = normalize(-fragPos);
@@ -0,0 +1,42 @@
+#version 450
+
+#include "common/lighting.glsl"
+#include "common/materials.glsl"
+
+// Alpha tested mesh for pipelines from add_cutout_pipeline, e.g. with mesh_mvp.vert.
+// The renderer pushes the mesh's threshold from set_mesh_alpha_cutoff right after the MVP block.
+
+layout(binding = 0) uniform sampler2D texSampler;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+    float alphaCutoff;
+} pc;
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec3 fragPos;
+layout(location = 2) in vec2 fragUV;
+layout(location = 3) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec4 texColor = texture(texSampler, fragUV) * fragColor;
+    alphaCutout(texColor.a, pc.alphaCutoff);
+    
+    // Both sides of a leaf or fence are lit
+    vec3 normal = normalize(fragNormal);
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+    
+    vec3 lightDir = normalize(vec3(0.5, 1.0, 0.8));
+    float diff = calculateDiffuse(normal, lightDir);
+    vec3 color = texColor.rgb * (0.3 + diff);
+    
+    // Kept fragments are written opaque, they are drawn with the opaque meshes
+    outColor = vec4(color, 1.0);
+}
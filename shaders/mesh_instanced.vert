@@ -6,10 +6,12 @@ layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inNormal;
 
 // Instance attributes
-layout(location = 2) in vec3 instancePos;
+layout(location = 4) in vec3 instancePos;
 
+// Offsets of the renderer's PushConstants, which has the target's size in resolution
 layout(push_constant) uniform PushConstants {
     float time;
+    layout(offset = 16) vec2 resolution;
 } push;
 
 layout(location = 0) out vec3 fragNormal;
@@ -31,7 +33,7 @@ void main() {
         instancePos.x, instancePos.y, instancePos.z, 1.0
     );
     
-    mat4 projection = getProjectionMatrix();
+    mat4 projection = getProjectionMatrix(push.resolution.x / push.resolution.y);
     
     vec4 worldPos = translation * rotationY * vec4(position, 1.0);
     fragPos = worldPos.xyz;
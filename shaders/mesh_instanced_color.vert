@@ -0,0 +1,35 @@
+#version 450
+
+// Instanced mesh with a transform and color per instance (InstanceData), drawn with the multi
+// mesh path's view and projection push constants. The push constant model is ignored.
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUV;
+layout(location = 3) in vec4 inColor;
+
+// Instance attributes
+layout(location = 4) in mat4 instanceTransform;
+layout(location = 8) in vec4 instanceColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec3 fragPos;
+layout(location = 2) out vec2 fragUV;
+layout(location = 3) out vec4 fragColor;
+
+void main() {
+    vec4 worldPos = instanceTransform * vec4(inPosition, 1.0);
+    fragPos = worldPos.xyz;
+    fragNormal = mat3(instanceTransform) * inNormal;
+    fragUV = inUV;
+    fragColor = inColor * instanceColor * pc.baseColor;
+    
+    gl_Position = pc.proj * pc.view * worldPos;
+}
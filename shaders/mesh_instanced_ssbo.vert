@@ -0,0 +1,37 @@
+#version 450
+
+// Instanced mesh reading its model matrix from a storage buffer (add_mesh_instanced_ssbo),
+// drawn with the multi mesh path's view and projection push constants. The push constant
+// model is ignored.
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUV;
+layout(location = 3) in vec4 inColor;
+
+layout(set = 0, binding = 0) readonly buffer InstanceMatrices {
+    mat4 instanceTransforms[];
+};
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec3 fragPos;
+layout(location = 2) out vec2 fragUV;
+layout(location = 3) out vec4 fragColor;
+
+void main() {
+    mat4 instanceTransform = instanceTransforms[gl_InstanceIndex];
+    vec4 worldPos = instanceTransform * vec4(inPosition, 1.0);
+    fragPos = worldPos.xyz;
+    fragNormal = mat3(instanceTransform) * inNormal;
+    fragUV = inUV;
+    fragColor = inColor * pc.baseColor;
+    
+    gl_Position = pc.proj * pc.view * worldPos;
+}
@@ -0,0 +1,28 @@
+#version 450
+
+#include "common/lighting.glsl"
+
+layout(binding = 0) uniform sampler2DArray texSampler;
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec3 fragPos;
+layout(location = 2) in vec2 fragUV;
+layout(location = 3) in vec4 fragColor;
+layout(location = 4) flat in uint fragTextureIndex;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // Out of range layers use the last one instead of an undefined sample
+    uint lastLayer = uint(textureSize(texSampler, 0).z) - 1u;
+    uint layer = min(fragTextureIndex, lastLayer);
+    vec4 texColor = texture(texSampler, vec3(fragUV, float(layer))) * fragColor;
+    
+    vec3 lightDir = normalize(vec3(0.5, 1.0, 0.8));
+    vec3 normal = normalize(fragNormal);
+    float diff = calculateDiffuse(normal, lightDir);
+    vec3 ambient = vec3(0.3) * texColor.rgb;
+    vec3 diffuse = texColor.rgb * diff;
+    
+    outColor = vec4(ambient + diffuse, texColor.a);
+}
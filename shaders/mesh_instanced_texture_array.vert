@@ -0,0 +1,38 @@
+#version 450
+
+// mesh_instanced_color.vert passing each instance's texture array layer (InstanceData) on to
+// mesh_instanced_texture_array.frag
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUV;
+layout(location = 3) in vec4 inColor;
+
+// Instance attributes
+layout(location = 4) in mat4 instanceTransform;
+layout(location = 8) in vec4 instanceColor;
+layout(location = 9) in uint instanceTextureIndex;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec3 fragPos;
+layout(location = 2) out vec2 fragUV;
+layout(location = 3) out vec4 fragColor;
+layout(location = 4) flat out uint fragTextureIndex;
+
+void main() {
+    vec4 worldPos = instanceTransform * vec4(inPosition, 1.0);
+    fragPos = worldPos.xyz;
+    fragNormal = mat3(instanceTransform) * inNormal;
+    fragUV = inUV;
+    fragColor = inColor * instanceColor * pc.baseColor;
+    fragTextureIndex = instanceTextureIndex;
+    
+    gl_Position = pc.proj * pc.view * worldPos;
+}
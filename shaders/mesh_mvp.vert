@@ -0,0 +1,30 @@
+#version 450
+
+// Regular mesh drawn with the multi mesh path's model, view and projection push constants
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUV;
+layout(location = 3) in vec4 inColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec3 fragPos;
+layout(location = 2) out vec2 fragUV;
+layout(location = 3) out vec4 fragColor;
+
+void main() {
+    vec4 worldPos = pc.model * vec4(inPosition, 1.0);
+    fragPos = worldPos.xyz;
+    fragNormal = mat3(pc.model) * inNormal;
+    fragUV = inUV;
+    fragColor = inColor * pc.baseColor;
+    
+    gl_Position = pc.proj * pc.view * worldPos;
+}
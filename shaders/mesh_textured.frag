@@ -10,9 +10,16 @@ layout(location = 2) in vec2 fragUV;
 
 layout(location = 0) out vec4 outColor;
 
+// Set by double sided variants of the pipeline, see VulkanRenderer::set_mesh_double_sided
+layout(constant_id = 0) const bool TWO_SIDED_LIGHTING = false;
+
 void main() {
     vec3 lightDir = normalize(vec3(0.5, 1.0, 0.8));
     vec3 normal = normalize(fragNormal);
+    // Back faces are lit from their own side
+    if (TWO_SIDED_LIGHTING && !gl_FrontFacing) {
+        normal = -normal;
+    }
     
     // Sample texture
     vec3 texColor = texture(texSampler, fragUV).rgb;
@@ -7,8 +7,10 @@ layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inNormal;
 layout(location = 2) in vec2 inUV;
 
+// Offsets of the renderer's PushConstants, which has the target's size in resolution
 layout(push_constant) uniform PushConstants {
     float time;
+    layout(offset = 16) vec2 resolution;
 } push;
 
 layout(location = 0) out vec3 fragNormal;
@@ -25,7 +27,7 @@ void main() {
     mat4 rotationY = getRotationMatrixY(angle);
     mat4 rotationX = getRotationMatrixX(PI); // 180 degree rotation to flip vertically
     
-    mat4 projection = getProjectionMatrix();
+    mat4 projection = getProjectionMatrix(push.resolution.x / push.resolution.y);
     
     vec4 worldPos = rotationY * rotationX * vec4(position, 1.0);
     fragPos = worldPos.xyz;
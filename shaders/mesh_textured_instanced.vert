@@ -7,10 +7,12 @@ layout(location = 1) in vec3 inNormal;
 layout(location = 2) in vec2 inUV;
 
 // Instance attributes
-layout(location = 3) in vec3 instancePos;
+layout(location = 4) in vec3 instancePos;
 
+// Offsets of the renderer's PushConstants, which has the target's size in resolution
 layout(push_constant) uniform PushConstants {
     float time;
+    layout(offset = 16) vec2 resolution;
 } push;
 
 layout(location = 0) out vec3 fragNormal;
@@ -33,7 +35,7 @@ void main() {
         instancePos.x, instancePos.y, instancePos.z, 1.0
     );
     
-    mat4 projection = getProjectionMatrix();
+    mat4 projection = getProjectionMatrix(push.resolution.x / push.resolution.y);
     
     vec4 worldPos = translation * rotationY * vec4(position, 1.0);
     fragPos = worldPos.xyz;
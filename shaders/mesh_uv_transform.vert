@@ -0,0 +1,34 @@
+#version 450
+
+// mesh_mvp.vert with scaled and offset UVs, for pipelines from add_uv_transform_pipeline.
+// The renderer folds the mesh's scroll into the offset, see set_mesh_uv_scroll.
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUV;
+layout(location = 3) in vec4 inColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+    // The cutout threshold sits at offset 208, the UV transform takes the wind block's place at 224
+    layout(offset = 224) vec4 uvScaleOffset;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec3 fragPos;
+layout(location = 2) out vec2 fragUV;
+layout(location = 3) out vec4 fragColor;
+
+void main() {
+    vec4 worldPos = pc.model * vec4(inPosition, 1.0);
+    fragPos = worldPos.xyz;
+    fragNormal = mat3(pc.model) * inNormal;
+    // Per vertex, so the fragment shader's sampler still wraps or clamps the interpolated UVs
+    fragUV = inUV * pc.uvScaleOffset.xy + pc.uvScaleOffset.zw;
+    fragColor = inColor * pc.baseColor;
+
+    gl_Position = pc.proj * pc.view * worldPos;
+}
@@ -0,0 +1,5 @@
+#version 450
+
+// Nothing to shade, the occlusion query only counts samples that pass the depth test
+void main() {
+}
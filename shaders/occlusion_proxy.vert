@@ -0,0 +1,17 @@
+#version 450
+
+// Bounding box proxy for occlusion queries. Only depth testing matters,
+// color and depth writes are disabled in the pipeline.
+
+layout(location = 0) in vec3 inPosition;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+void main() {
+    gl_Position = pc.proj * pc.view * pc.model * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450
+
+// Round soft particle, fading out toward the edge of its quad. Drawn additively, the blend
+// multiplies the color by alpha.
+
+layout(location = 0) in vec2 fragCorner;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float distanceSquared = dot(fragCorner, fragCorner);
+    if (distanceSquared > 1.0) {
+        discard;
+    }
+    float alpha = fragColor.a * (1.0 - distanceSquared);
+    outColor = vec4(fragColor.rgb, alpha);
+}
@@ -0,0 +1,36 @@
+#version 450
+
+// Camera facing quad per particle, read per instance from the particle buffer
+
+layout(location = 0) in vec4 inPositionLife;
+layout(location = 1) in float inSize;
+layout(location = 2) in vec4 inColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 view;
+    mat4 proj;
+} pc;
+
+layout(location = 0) out vec2 fragCorner;
+layout(location = 1) out vec4 fragColor;
+
+const vec2 corners[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(1.0, 1.0), vec2(-1.0, 1.0), vec2(-1.0, -1.0)
+);
+
+void main() {
+    fragCorner = corners[gl_VertexIndex];
+    fragColor = inColor;
+
+    // Dead particles collapse to a point outside the clip volume
+    if (inPositionLife.w <= 0.0) {
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
+
+    // Offset in view space so the quad always faces the camera
+    vec4 viewPosition = pc.view * vec4(inPositionLife.xyz, 1.0);
+    viewPosition.xy += fragCorner * inSize * 0.5;
+    gl_Position = pc.proj * viewPosition;
+}
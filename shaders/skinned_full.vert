@@ -1,5 +1,8 @@
 #version 450
 
+#include "common/skinning.glsl"
+#include "common/morph.glsl"
+
 // Vertex attributes
 layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inNormal;
@@ -8,11 +11,23 @@ layout(location = 3) in vec4 inColor;
 layout(location = 4) in uvec4 inJointIndices;
 layout(location = 5) in vec4 inJointWeights;
 
+// Set by the renderer from the rig's joint count. Rigs too big for a uniform buffer are read
+// from the storage buffer at binding 2 instead, the uniform array then has a single entry.
+layout(constant_id = 0) const uint JOINT_COUNT = 128;
+layout(constant_id = 1) const bool JOINTS_IN_STORAGE_BUFFER = false;
+// Set by the pipeline's SkinningMode, the joint matrices then hold dual quaternions
+layout(constant_id = 2) const bool DUAL_QUATERNION_SKINNING = false;
+
 // Uniform buffer for joint matrices
 layout(set = 0, binding = 0) uniform JointMatrices {
-    mat4 joints[128];
+    mat4 joints[JOINT_COUNT];
 } jointMatrices;
 
+// The same joint matrices as a storage buffer
+layout(set = 0, binding = 2) readonly buffer JointStorage {
+    mat4 joints[];
+} jointStorage;
+
 // Uniform buffer for camera matrices
 layout(set = 0, binding = 1) uniform CameraMatrices {
     mat4 view;
@@ -22,8 +37,17 @@ layout(set = 0, binding = 1) uniform CameraMatrices {
 // Push constants
 layout(push_constant) uniform PushConstants {
     float time;
+    // 0 for meshes without morph targets
+    uint morphTargetCount;
 } push;
 
+mat4 jointMatrix(uint index) {
+    if (JOINTS_IN_STORAGE_BUFFER) {
+        return jointStorage.joints[index];
+    }
+    return jointMatrices.joints[index];
+}
+
 // Output to fragment shader
 layout(location = 0) out vec3 fragNormal;
 layout(location = 1) out vec3 fragPos;
@@ -31,6 +55,11 @@ layout(location = 2) out vec2 fragUV;
 layout(location = 3) out vec4 fragColor;
 
 void main() {
+    // Morph targets are applied in bind pose, before skinning
+    vec3 position = inPosition;
+    vec3 normal = inNormal;
+    applyMorphTargets(uint(gl_VertexIndex), push.morphTargetCount, position, normal);
+    
     // Check if vertex has any skinning weights
     float totalWeight = inJointWeights.x + inJointWeights.y + inJointWeights.z + inJointWeights.w;
     
@@ -43,38 +72,48 @@ void main() {
             normalizedWeights = inJointWeights / totalWeight;
         }
         
-        // Apply skinning with all joints animated
-        mat4 skinMatrix = mat4(0.0);
+        if (DUAL_QUATERNION_SKINNING) {
+            mat4 joints[4] = mat4[4](
+                jointMatrix(inJointIndices.x),
+                jointMatrix(inJointIndices.y),
+                jointMatrix(inJointIndices.z),
+                jointMatrix(inJointIndices.w)
+            );
+            skinnedPos = vec4(dualQuaternionSkin(joints, normalizedWeights, position), 1.0);
+        } else {
+            // Apply skinning with all joints animated
+            mat4 skinMatrix = mat4(0.0);
         
-        // Process each joint influence with normalized weights
-        if (normalizedWeights.x > 0.0) {
-            skinMatrix += jointMatrices.joints[inJointIndices.x] * normalizedWeights.x;
-        }
+            // Process each joint influence with normalized weights
+            if (normalizedWeights.x > 0.0) {
+                skinMatrix += jointMatrix(inJointIndices.x) * normalizedWeights.x;
+            }
         
-        if (normalizedWeights.y > 0.0) {
-            skinMatrix += jointMatrices.joints[inJointIndices.y] * normalizedWeights.y;
-        }
+            if (normalizedWeights.y > 0.0) {
+                skinMatrix += jointMatrix(inJointIndices.y) * normalizedWeights.y;
+            }
         
-        if (normalizedWeights.z > 0.0) {
-            skinMatrix += jointMatrices.joints[inJointIndices.z] * normalizedWeights.z;
-        }
+            if (normalizedWeights.z > 0.0) {
+                skinMatrix += jointMatrix(inJointIndices.z) * normalizedWeights.z;
+            }
         
-        if (normalizedWeights.w > 0.0) {
-            skinMatrix += jointMatrices.joints[inJointIndices.w] * normalizedWeights.w;
-        }
+            if (normalizedWeights.w > 0.0) {
+                skinMatrix += jointMatrix(inJointIndices.w) * normalizedWeights.w;
+            }
         
-        // Apply the skin matrix
-        skinnedPos = skinMatrix * vec4(inPosition, 1.0);
+            // Apply the skin matrix
+            skinnedPos = skinMatrix * vec4(position, 1.0);
+        }
     } else {
         // No skinning - use original position
-        skinnedPos = vec4(inPosition, 1.0);
+        skinnedPos = vec4(position, 1.0);
     }
     
     // Scale the model to be visible
     vec3 scaledPos = skinnedPos.xyz * 100.0;
     
     fragPos = scaledPos;
-    fragNormal = normalize(inNormal);
+    fragNormal = normalize(normal);
     fragUV = inUV;
     
     // Use the vertex color from the model
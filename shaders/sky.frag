@@ -5,10 +5,34 @@ layout(location = 1) in vec3 fragRayDir;
 
 layout(location = 0) out vec4 outColor;
 
+// Matches SkyPushConstants on the Rust side
+layout(push_constant) uniform PushConstants {
+    float time;
+    float cameraPositionX;
+    float cameraPositionY;
+    float cameraPositionZ;
+    vec2 resolution;
+    float waterLevel;
+    float gridScale;
+    float nearFadeStart;
+    float nearFadeEnd;
+    float reflectionPass;
+    float padding;
+    vec4 horizonColor;  // Alpha > 0 enables the runtime gradient
+    vec4 zenithColor;
+    vec4 sunDirection;  // xyz towards the sun, w intensity
+} pc;
+
 // Include the common sky function
 #include "common/sky.glsl"
 
 void main() {
-    vec3 skyColor = getSkyColor(normalize(fragRayDir));
+    vec3 direction = normalize(fragRayDir);
+    vec3 skyColor;
+    if (pc.horizonColor.a > 0.0) {
+        skyColor = getSkyGradient(direction, pc.horizonColor.rgb, pc.zenithColor.rgb, pc.sunDirection.xyz, pc.sunDirection.w);
+    } else {
+        skyColor = getSkyColor(direction);
+    }
     outColor = vec4(skyColor, 1.0);
 }
\ No newline at end of file
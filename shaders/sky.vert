@@ -16,11 +16,17 @@ layout(push_constant) uniform PushConstants {
     vec2 resolution;
     float waterLevel;
     float gridScale;
+    float nearFadeStart;
+    float nearFadeEnd;
+    float reflectionPass;
+    float padding;
 } pc;
 
 void main() {
     vec2 pos = positions[gl_VertexIndex];
-    gl_Position = vec4(pos, 0.999, 1.0); // Place at far depth
+    // z = w puts the sky exactly on the far plane, drawn with LESS_OR_EQUAL it stays behind
+    // everything however far the camera sees
+    gl_Position = vec4(pos, 1.0, 1.0);
     fragTexCoord = pos * 0.5 + 0.5;
     
     // Simple ray direction calculation for sky - flip Y to match expected gradient
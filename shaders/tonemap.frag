@@ -0,0 +1,86 @@
+#version 450
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Linear HDR scene color
+layout(set = 0, binding = 0) uniform sampler2D hdrImage;
+
+// Matches TonemapPushConstants on the Rust side
+layout(push_constant) uniform PushConstants {
+    uint mode;         // 0 = none (clamp), 1 = Reinhard, 2 = ACES, 3 = overdraw heatmap
+    float exposure;
+    uint encodeSrgb;   // 1 when the swapchain won't encode to sRGB itself
+    float sharpness;   // UpscaleFilter::Sharpen, 0 for plain bilinear
+} pc;
+
+// The sampler is linear, so a scene rendered below the swapchain resolution is upscaled
+// bilinearly. Sharpening subtracts the neighbours one scene pixel away, an unsharp mask.
+vec3 sampleScene(vec2 uv) {
+    vec3 color = texture(hdrImage, uv).rgb;
+    if (pc.sharpness > 0.0) {
+        vec2 texel = 1.0 / vec2(textureSize(hdrImage, 0));
+        vec3 neighbours = texture(hdrImage, uv + vec2(texel.x, 0.0)).rgb
+            + texture(hdrImage, uv - vec2(texel.x, 0.0)).rgb
+            + texture(hdrImage, uv + vec2(0.0, texel.y)).rgb
+            + texture(hdrImage, uv - vec2(0.0, texel.y)).rgb;
+        color = max(color + pc.sharpness * (color - neighbours * 0.25), 0.0);
+    }
+    return color;
+}
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 acesFilmic(vec3 x) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+// Overdraw debug view: red holds the number of fragments drawn to the pixel. One layer is
+// blue, then cyan, green, yellow and red from five layers on. Pixels nothing was drawn to
+// stay black.
+vec3 overdrawHeatmap(float count) {
+    const vec3 ramp[5] = vec3[](
+        vec3(0.0, 0.0, 1.0),
+        vec3(0.0, 1.0, 1.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(1.0, 0.0, 0.0)
+    );
+    if (count < 0.5) {
+        return vec3(0.0);
+    }
+    float position = clamp(count - 1.0, 0.0, 4.0);
+    int lower = int(position);
+    return mix(ramp[lower], ramp[min(lower + 1, 4)], position - float(lower));
+}
+
+vec3 linearToSrgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+void main() {
+    vec3 color = sampleScene(fragTexCoord) * pc.exposure;
+
+    if (pc.mode == 3u) {
+        color = overdrawHeatmap(color.r);
+    } else if (pc.mode == 1u) {
+        color = color / (1.0 + color);
+    } else if (pc.mode == 2u) {
+        color = acesFilmic(color);
+    } else {
+        color = clamp(color, 0.0, 1.0);
+    }
+
+    if (pc.encodeSrgb == 1u) {
+        color = linearToSrgb(color);
+    }
+
+    outColor = vec4(color, 1.0);
+}
@@ -0,0 +1,26 @@
+#version 450
+
+// Example post effect, see VulkanRenderer::add_post_effect
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Output of the previous pass, linear HDR color
+layout(set = 0, binding = 0) uniform sampler2D inputImage;
+
+// Matches PostEffectPushConstants on the Rust side
+layout(push_constant) uniform PushConstants {
+    vec2 texelSize;
+    float params[16];  // params[0] = strength, 0 leaves the image unchanged
+} pc;
+
+void main() {
+    vec3 color = texture(inputImage, fragTexCoord).rgb;
+
+    // Darken towards the corners, which are 0.5 away from the center in both directions
+    vec2 fromCenter = fragTexCoord - 0.5;
+    float vignette = 1.0 - pc.params[0] * dot(fromCenter, fromCenter) * 2.0;
+
+    outColor = vec4(color * clamp(vignette, 0.0, 1.0), 1.0);
+}
@@ -14,6 +14,10 @@ layout(push_constant) uniform PushConstants {
     vec2 resolution;
     float waterLevel;
     float gridScale;
+    float nearFadeStart;
+    float nearFadeEnd;
+    float reflectionPass;
+    float padding;
 } push;
 
 layout(location = 0) out vec3 fragWorldPos;
@@ -40,4 +44,11 @@ void main() {
     fragUV = inUV;
     
     gl_Position = projection * view * vec4(worldPos, 1.0);
+    
+    // The water reflection pass looks up from below the water. Moving the near plane onto the
+    // surface (an oblique projection, which for this frustum leaves z as the height above the
+    // water) keeps everything under the water out of the reflection.
+    if (push.reflectionPass > 0.0) {
+        gl_Position.z = worldPos.y - push.waterLevel;
+    }
 }
\ No newline at end of file
@@ -14,6 +14,10 @@ layout(push_constant) uniform PushConstants {
     vec2 resolution;
     float waterLevel;
     float gridScale;
+    float nearFadeStart;
+    float nearFadeEnd;
+    float reflectionPass;
+    float padding;
 } push;
 
 layout(location = 0) out vec3 fragWorldPos;
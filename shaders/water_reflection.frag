@@ -0,0 +1,55 @@
+#version 450
+
+#include "common/water_shading.glsl"
+
+layout(location = 0) in vec3 fragWorldPos;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec2 fragUV;
+layout(location = 3) in vec3 fragCameraPos;
+layout(location = 4) in float fragTime;
+layout(location = 5) in float fragWaterLevel;
+
+layout(location = 0) out vec4 outColor;
+
+// The scene above the water seen from the camera mirrored below it, see
+// VulkanRenderer::enable_water_reflections
+layout(set = 0, binding = 0) uniform sampler2D reflectionTexture;
+
+// The start of PushConstants, same as water.frag
+layout(push_constant) uniform PushConstants {
+    float time;
+    float cameraPositionX;
+    float cameraPositionY;
+    float cameraPositionZ;
+    vec2 resolution;
+    float waterLevel;
+    float gridScale;
+    float nearFadeStart;
+    float nearFadeEnd;
+} push;
+
+// Set by pipelines without culling, see VulkanRenderer::is_two_sided_lighting
+layout(constant_id = 0) const bool TWO_SIDED_LIGHTING = false;
+
+// How far waves shift the reflection, in screen widths per unit of normal tilt
+const float REFLECTION_DISTORTION = 0.03;
+
+void main() {
+    vec3 eyeDir = normalize(fragCameraPos - fragWorldPos);
+    vec3 normal = waterNormal(fragNormal, eyeDir, TWO_SIDED_LIGHTING);
+
+    // The mirrored camera looks the same way from below, so the reflection of this pixel is at
+    // the same screen position flipped upside down
+    vec2 screenUV = gl_FragCoord.xy / push.resolution;
+    vec2 reflectionUV = vec2(screenUV.x, 1.0 - screenUV.y) + normal.xz * REFLECTION_DISTORTION;
+    vec3 reflectedColor = texture(reflectionTexture, reflectionUV).rgb;
+    vec3 finalColor = shadeWater(fragWorldPos, fragWaterLevel, normal, eyeDir, reflectedColor);
+
+    // Near fade only scales the surface's own alpha, keep it out of anything seen through it
+    float nearFade = 1.0;
+    if (push.nearFadeEnd > push.nearFadeStart) {
+        nearFade = smoothstep(push.nearFadeStart, push.nearFadeEnd, length(fragCameraPos - fragWorldPos));
+    }
+
+    outColor = vec4(finalColor, 0.8 * nearFade); // Semi-transparent water
+}
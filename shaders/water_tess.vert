@@ -0,0 +1,17 @@
+#version 450
+
+// Passes the water surface through in world space, water.tesc and water.tese do the projection
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUV;
+
+layout(location = 0) out vec3 outWorldPos;
+layout(location = 1) out vec3 outNormal;
+layout(location = 2) out vec2 outUV;
+
+void main() {
+    outWorldPos = inPosition;
+    outNormal = inNormal;
+    outUV = inUV;
+}
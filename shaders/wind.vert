@@ -0,0 +1,51 @@
+#version 450
+
+// Mesh swaying in the wind, for pipelines from add_wind_pipeline. Vertices are pushed along the
+// wind direction by a sine wave, weighted by their height above the mesh's origin so the base
+// stays planted.
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUV;
+layout(location = 3) in vec4 inColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+    // The cutout threshold sits at offset 208, wind starts at 224 (see set_mesh_wind)
+    layout(offset = 224) vec4 windDirectionAmplitude;
+    vec4 windFrequencyTime;
+} pc;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec3 fragPos;
+layout(location = 2) out vec2 fragUV;
+layout(location = 3) out vec4 fragColor;
+
+const float TAU = 6.28318530718;
+
+void main() {
+    vec3 direction = pc.windDirectionAmplitude.xyz;
+    float amplitude = pc.windDirectionAmplitude.w;
+    float frequency = pc.windFrequencyTime.x;
+    float time = pc.windFrequencyTime.y;
+
+    vec4 worldPos = pc.model * vec4(inPosition, 1.0);
+    // The phase shifts across the ground, so a field of grass ripples instead of swaying in step
+    float phase = TAU * frequency * time + dot(worldPos.xz, vec2(0.35, 0.2));
+    float sway = amplitude * sin(phase);
+    worldPos.xyz += direction * sway * max(inPosition.y, 0.0);
+    fragPos = worldPos.xyz;
+
+    // The sway shears the mesh along the wind, tilting normals the opposite way keeps the lighting
+    // close. Approximate: it assumes the mesh's up is world up and ignores the phase change
+    // across the mesh.
+    vec3 normal = normalize(mat3(pc.model) * inNormal);
+    fragNormal = normal - vec3(0.0, sway * dot(direction, normal), 0.0);
+    fragUV = inUV;
+    fragColor = inColor * pc.baseColor;
+
+    gl_Position = pc.proj * pc.view * worldPos;
+}
@@ -0,0 +1,16 @@
+#version 450
+
+// Flat overlay color, pushed in place of the mesh's base color
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = pc.baseColor;
+}
@@ -0,0 +1,16 @@
+#version 450
+
+// Edges of a mesh drawn over its solid fill, see set_mesh_wireframe_overlay
+
+layout(location = 0) in vec3 inPosition;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+void main() {
+    gl_Position = pc.proj * pc.view * pc.model * vec4(inPosition, 1.0);
+}
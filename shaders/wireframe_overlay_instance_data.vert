@@ -0,0 +1,19 @@
+#version 450
+
+// Wireframe overlay of an instanced mesh with a transform per instance (InstanceData)
+
+layout(location = 0) in vec3 inPosition;
+
+// Instance attributes, the color at location 8 isn't used
+layout(location = 4) in mat4 instanceTransform;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+void main() {
+    gl_Position = pc.proj * pc.view * instanceTransform * vec4(inPosition, 1.0);
+}
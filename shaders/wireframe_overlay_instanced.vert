@@ -0,0 +1,20 @@
+#version 450
+
+// Wireframe overlay of an instanced mesh with one position per instance
+
+layout(location = 0) in vec3 inPosition;
+
+// Instance attributes
+layout(location = 1) in vec3 instancePos;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 baseColor;
+} pc;
+
+void main() {
+    vec4 worldPos = pc.model * vec4(inPosition, 1.0) + vec4(instancePos, 0.0);
+    gl_Position = pc.proj * pc.view * worldPos;
+}
@@ -0,0 +1,163 @@
+use bevy_math::{Mat4, Quat, Vec3};
+use tracing::warn;
+use bevy_transform::components::Transform;
+
+// How values between two keyframes are found, as in glTF
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    Step,
+    // Hermite spline, values holds (in tangent, value, out tangent) per keyframe
+    CubicSpline,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Keyframes<T> {
+    pub times: Vec<f32>,
+    pub values: Vec<T>,
+    pub interpolation: Interpolation,
+}
+
+// Translation, rotation and scale keyframes of one joint, matched to skeletons by joint name.
+// Properties without keyframes keep the skeleton's rest pose.
+#[derive(Clone, Debug, Default)]
+pub struct JointChannel {
+    pub joint_name: String,
+    pub translations: Keyframes<Vec3>,
+    pub rotations: Keyframes<Quat>,
+    pub scales: Keyframes<Vec3>,
+}
+
+// Keyframes for any number of joints, independent of a mesh or skeleton, see
+// gltf_loader::load_animations
+#[derive(Clone, Debug, Default)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    // Time of the last keyframe in seconds
+    pub duration: f32,
+    pub channels: Vec<JointChannel>,
+}
+
+// Joints of a skin in the order its joint matrices use, see gltf_loader::load_skeleton.
+// parents and inverse_bind_matrices go to VulkanRenderer::set_bone_hierarchy.
+#[derive(Clone, Debug, Default)]
+pub struct Skeleton {
+    pub joint_names: Vec<String>,
+    pub parents: Vec<Option<usize>>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+    // Local transform of each joint when nothing animates it
+    pub rest_pose: Vec<Transform>,
+}
+
+impl Skeleton {
+    pub fn joint_index(&self, name: &str) -> Option<usize> {
+        self.joint_names.iter().position(|joint_name| joint_name == name)
+    }
+}
+
+// A clip bound to a skeleton by joint name, so one clip can drive every character sharing the
+// skeleton's joint names. Samples local transforms for VulkanRenderer::upload_bone_local_transforms.
+pub struct AnimationSampler {
+    clip: AnimationClip,
+    // Skeleton joint each channel drives, None for channels whose joint the skeleton lacks
+    channel_joints: Vec<Option<usize>>,
+    rest_pose: Vec<Transform>,
+    unmatched_joints: Vec<String>,
+}
+
+impl AnimationSampler {
+    // Channels for joints the skeleton doesn't have are ignored, with a warning listing them,
+    // since a misnamed joint would otherwise just stay in its rest pose without any sign why
+    pub fn new(clip: &AnimationClip, skeleton: &Skeleton) -> Self {
+        let channel_joints: Vec<Option<usize>> = clip.channels.iter()
+            .map(|channel| skeleton.joint_index(&channel.joint_name))
+            .collect();
+        let unmatched_joints: Vec<String> = clip.channels.iter().zip(&channel_joints)
+            .filter(|(_, joint)| joint.is_none())
+            .map(|(channel, _)| channel.joint_name.clone())
+            .collect();
+        if !unmatched_joints.is_empty() {
+            warn!(
+                "Animation '{}' has {} joints the skeleton doesn't, they're ignored: {}",
+                clip.name.as_deref().unwrap_or("unnamed"),
+                unmatched_joints.len(),
+                unmatched_joints.join(", ")
+            );
+        }
+        Self {
+            clip: clip.clone(),
+            channel_joints,
+            rest_pose: skeleton.rest_pose.clone(),
+            unmatched_joints,
+        }
+    }
+
+    // Names of the clip's joints the skeleton doesn't have
+    pub fn unmatched_joints(&self) -> &[String] {
+        &self.unmatched_joints
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.clip.duration
+    }
+
+    // Local transform of every skeleton joint at time seconds, looping over the clip's duration
+    pub fn sample(&self, time: f32) -> Vec<Transform> {
+        let time = if self.clip.duration > 0.0 { time.rem_euclid(self.clip.duration) } else { 0.0 };
+        let mut pose = self.rest_pose.clone();
+        for (channel, joint) in self.clip.channels.iter().zip(&self.channel_joints) {
+            let Some(transform) = joint.and_then(|joint| pose.get_mut(joint)) else {
+                continue;
+            };
+            if let Some(translation) = sample_keyframes(&channel.translations, time, Vec3::lerp) {
+                transform.translation = translation;
+            }
+            if let Some(rotation) = sample_keyframes(&channel.rotations, time, Quat::slerp) {
+                transform.rotation = rotation.normalize();
+            }
+            if let Some(scale) = sample_keyframes(&channel.scales, time, Vec3::lerp) {
+                transform.scale = scale;
+            }
+        }
+        pose
+    }
+}
+
+// None without keyframes. Times before the first or after the last keyframe hold its value.
+fn sample_keyframes<T>(keyframes: &Keyframes<T>, time: f32, lerp: impl Fn(T, T, f32) -> T) -> Option<T>
+where
+    T: Copy + std::ops::Add<Output = T> + std::ops::Mul<f32, Output = T>,
+{
+    let times = &keyframes.times;
+    let cubic = keyframes.interpolation == Interpolation::CubicSpline;
+    let value = |index: usize| if cubic { keyframes.values[index * 3 + 1] } else { keyframes.values[index] };
+    let last = times.len().checked_sub(1)?;
+    if keyframes.values.len() < times.len() * if cubic { 3 } else { 1 } {
+        return None;
+    }
+
+    let next = times.partition_point(|&keyframe_time| keyframe_time <= time);
+    if next == 0 {
+        return Some(value(0));
+    }
+    if next > last {
+        return Some(value(last));
+    }
+    let previous = next - 1;
+    let span = times[next] - times[previous];
+    let t = if span > 0.0 { (time - times[previous]) / span } else { 0.0 };
+    Some(match keyframes.interpolation {
+        Interpolation::Step => value(previous),
+        Interpolation::Linear => lerp(value(previous), value(next), t),
+        Interpolation::CubicSpline => {
+            let out_tangent = keyframes.values[previous * 3 + 2] * span;
+            let in_tangent = keyframes.values[next * 3] * span;
+            let (t2, t3) = (t * t, t * t * t);
+            value(previous) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + out_tangent * (t3 - 2.0 * t2 + t)
+                + value(next) * (-2.0 * t3 + 3.0 * t2)
+                + in_tangent * (t3 - t2)
+        }
+    })
+}
@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+use bevy::window::{WindowPlugin, Window};
+use bevy::asset::{AssetPlugin, };
+use bevy::gltf::{GltfPlugin};
+use bevy::log::LogPlugin;
+use bevy::render::RenderPlugin;
+use bevy::render::texture::ImagePlugin;
+use bevy::render::settings::WgpuSettings;
+use bevy::pbr::PbrPlugin;
+use bevy::core_pipeline::CorePipelinePlugin;
+use bevy::scene::ScenePlugin;
+use bevy::transform::TransformPlugin;
+use bevy::animation::AnimationPlugin;
+use bevy::winit::{WinitPlugin, WakeUp};
+use bevy::a11y::AccessibilityPlugin;
+use bevy::input::InputPlugin;
+use bevy::input::keyboard::KeyboardFocusLost;
+use bevy::window::WindowFocused;
+
+pub fn setup_bevy_app() -> App {
+    setup_bevy_app_with_window(2560.0, 1440.0, "Flo Engine Example")
+}
+
+pub fn setup_bevy_app_with_window(width: f32, height: f32, title: &str) -> App {
+    std::env::set_var("RUST_BACKTRACE", "0");
+
+    let mut app = App::new();
+    app
+        .add_event::<KeyboardFocusLost>()
+        .add_plugins((
+            MinimalPlugins,
+            // Honors RUST_LOG, e.g. RUST_LOG=vulkan_bevy_renderer=debug
+            LogPlugin::default(),
+            AssetPlugin::default(),
+            WindowPlugin {
+                primary_window: Some(Window {
+                    title: title.to_string(),
+                    resolution: (width, height).into(),
+                    ..default()
+                }),
+                ..default()
+            },
+            AccessibilityPlugin,
+            InputPlugin::default(),
+            WinitPlugin::<WakeUp>::default(),
+            TransformPlugin,
+            RenderPlugin {
+                render_creation: WgpuSettings {
+                    backends: None,
+                    ..default()
+                }
+                .into(),
+                ..default()
+            },
+            ImagePlugin::default(),
+            CorePipelinePlugin::default(),
+            PbrPlugin::default(),
+            ScenePlugin,
+            GltfPlugin::default(),
+            AnimationPlugin,
+        ))
+        .init_resource::<FocusSettings>()
+        .add_systems(PreUpdate, pause_on_focus_change);
+
+    app
+}
+
+#[derive(Resource, Default)]
+pub struct FocusSettings {
+    // Pause virtual time while the window is unfocused. Anything driven by Res<Time> (water sim,
+    // animation players, FixedUpdate) stops, systems can also skip with run_if(not(paused)).
+    pub pause_when_unfocused: bool,
+}
+
+// Pauses and resumes Time<Virtual> on focus changes. Only undoes its own pause, so time paused
+// by the app stays paused when focus comes back.
+pub fn pause_on_focus_change(
+    settings: Res<FocusSettings>,
+    mut focus_events: EventReader<WindowFocused>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+    mut paused_by_focus: Local<bool>,
+) {
+    for event in focus_events.read() {
+        if !event.focused {
+            if settings.pause_when_unfocused && !virtual_time.is_paused() {
+                virtual_time.pause();
+                *paused_by_focus = true;
+            }
+        } else if *paused_by_focus {
+            virtual_time.unpause();
+            *paused_by_focus = false;
+            // Virtual time doesn't advance while paused, but drop any partial step left from
+            // before so FixedUpdate resumes on a clean step instead of catching up
+            let overstep = fixed_time.overstep();
+            fixed_time.discard_overstep(overstep);
+        }
+    }
+}
\ No newline at end of file
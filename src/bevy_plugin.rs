@@ -0,0 +1,199 @@
+use bevy::ecs::event::EventCursor;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy::window::{PrimaryWindow, RawHandleWrapperHolder, WindowClosing, WindowResized};
+use std::collections::{HashMap, HashSet};
+use crate::mesh::MeshData;
+use crate::vulkan_renderer_unified::VulkanRenderer;
+
+// Runs a multi mesh VulkanRenderer for the primary window from ECS data: entities with a
+// FloMesh are drawn at their GlobalTransform from the FloCamera entity's point of view. The
+// renderer is a non-send FloRenderer resource, so systems can still call it directly.
+pub struct FloRenderPlugin {
+    pub vert_shader_path: String,
+    pub frag_shader_path: String,
+}
+
+impl Default for FloRenderPlugin {
+    fn default() -> Self {
+        Self {
+            vert_shader_path: "shaders/mesh_mvp.vert.spv".to_string(),
+            frag_shader_path: "shaders/mesh.frag.spv".to_string(),
+        }
+    }
+}
+
+// Shaders of the renderer's default pipeline, read when the renderer is created
+#[derive(Resource, Clone)]
+struct FloShaders {
+    vert_shader_path: String,
+    frag_shader_path: String,
+}
+
+impl Plugin for FloRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FloShaders {
+            vert_shader_path: self.vert_shader_path.clone(),
+            frag_shader_path: self.frag_shader_path.clone(),
+        })
+        .add_systems(PostStartup, create_renderer)
+        .add_systems(
+            PostUpdate,
+            (upload_meshes, resize_swapchain, render_frame, shutdown_renderer)
+                .chain()
+                .after(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+// Non-send, so the renderer stays on the main thread with the window
+pub struct FloRenderer {
+    pub renderer: VulkanRenderer,
+    // Meshes given transforms last frame, cleared when their entities are gone
+    drawn_meshes: HashSet<usize>,
+}
+
+// Perspective camera the frame is rendered from, at the entity's GlobalTransform. Only the
+// first one found is used.
+#[derive(Component, Clone, Copy, Debug)]
+#[require(Transform)]
+pub struct FloCamera {
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for FloCamera {
+    fn default() -> Self {
+        Self { fov_y: 60.0_f32.to_radians(), near: 0.1, far: 1000.0 }
+    }
+}
+
+// Geometry added to the renderer the first frame it's seen, then replaced by a FloMesh
+#[derive(Component)]
+#[require(Transform)]
+pub struct FloMeshData(pub MeshData);
+
+// Renderer mesh drawn at the entity's GlobalTransform. Entities sharing a mesh index are drawn
+// as instances of it.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+#[require(Transform)]
+pub struct FloMesh(pub usize);
+
+// Exclusive systems run on the main thread, where non-send resources have to be inserted
+fn create_renderer(world: &mut World) {
+    let shaders = world.resource::<FloShaders>().clone();
+    let mut windows = world.query_filtered::<&RawHandleWrapperHolder, With<PrimaryWindow>>();
+    let Ok(handle_wrapper) = windows.single(world) else {
+        error!("FloRenderPlugin needs a primary window");
+        return;
+    };
+    match VulkanRenderer::new_multi_mesh(handle_wrapper, &shaders.vert_shader_path, &shaders.frag_shader_path, Vec::new()) {
+        Ok(renderer) => world.insert_non_send_resource(FloRenderer { renderer, drawn_meshes: HashSet::new() }),
+        Err(e) => error!("Failed to create Vulkan renderer: {}", e),
+    }
+}
+
+fn upload_meshes(
+    mut commands: Commands,
+    flo: Option<NonSendMut<FloRenderer>>,
+    meshes: Query<(Entity, &FloMeshData)>,
+) {
+    let Some(mut flo) = flo else {
+        return;
+    };
+    for (entity, FloMeshData(mesh_data)) in &meshes {
+        match flo.renderer.add_mesh(mesh_data) {
+            Ok(mesh_index) => {
+                commands.entity(entity).remove::<FloMeshData>().insert(FloMesh(mesh_index));
+            }
+            Err(e) => {
+                error!("Failed to add mesh for {}: {}", entity, e);
+                commands.entity(entity).remove::<FloMeshData>();
+            }
+        }
+    }
+}
+
+// Swapchains can't be created for a minimized window, so a resize waits until it's restored
+fn resize_swapchain(
+    flo: Option<NonSendMut<FloRenderer>>,
+    mut resized: EventReader<WindowResized>,
+    windows: Query<(Entity, &Window), With<PrimaryWindow>>,
+    mut pending: Local<bool>,
+) {
+    let Ok((primary, window)) = windows.single() else {
+        resized.clear();
+        return;
+    };
+    *pending |= resized.read().any(|event| event.window == primary);
+    let Some(mut flo) = flo else {
+        return;
+    };
+    let (width, height) = (window.physical_width(), window.physical_height());
+    if !*pending || width == 0 || height == 0 {
+        return;
+    }
+    *pending = false;
+    if let Err(e) = flo.renderer.resize() {
+        error!("Failed to resize the swapchain: {}", e);
+    }
+    flo.renderer.update_egui_swapchain(width, height, window.scale_factor());
+}
+
+fn render_frame(
+    flo: Option<NonSendMut<FloRenderer>>,
+    cameras: Query<(&FloCamera, &GlobalTransform)>,
+    meshes: Query<(&FloMesh, &GlobalTransform)>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+) {
+    let Some(mut flo) = flo else {
+        return;
+    };
+    if windows.single().is_ok_and(|window| window.physical_width() == 0 || window.physical_height() == 0) {
+        return;
+    }
+    let Some((camera, camera_transform)) = cameras.iter().next() else {
+        return;
+    };
+
+    let mut transforms: HashMap<usize, Vec<Mat4>> = HashMap::new();
+    for (&FloMesh(mesh_index), transform) in &meshes {
+        transforms.entry(mesh_index).or_default().push(transform.compute_matrix());
+    }
+    let flo = &mut *flo;
+    for &mesh_index in flo.drawn_meshes.iter().filter(|mesh_index| !transforms.contains_key(mesh_index)) {
+        flo.renderer.update_mesh_transforms(mesh_index, Vec::new());
+    }
+    flo.drawn_meshes = transforms.keys().copied().collect();
+    for (mesh_index, mesh_transforms) in transforms {
+        flo.renderer.update_mesh_transforms(mesh_index, mesh_transforms);
+    }
+
+    let view = camera_transform.compute_matrix().inverse();
+    let proj = flo.renderer.perspective(camera.fov_y, camera.near, camera.far);
+    flo.renderer.render_frame_with_camera_multi(view, proj);
+}
+
+// Shuts the renderer down when the app exits or the primary window is about to close, while the
+// window still exists. Left to drop with the world, the surface could outlive its window.
+fn shutdown_renderer(
+    world: &mut World,
+    mut exits: Local<EventCursor<AppExit>>,
+    mut closings: Local<EventCursor<WindowClosing>>,
+) {
+    let mut windows = world.query_filtered::<Entity, With<PrimaryWindow>>();
+    let primary = windows.single(world).ok();
+    let exiting = exits.read(world.resource::<Events<AppExit>>()).next().is_some();
+    let closing = closings
+        .read(world.resource::<Events<WindowClosing>>())
+        .any(|event| Some(event.window) == primary);
+    if !exiting && !closing {
+        return;
+    }
+    if let Some(flo) = world.remove_non_send_resource::<FloRenderer>() {
+        if let Err(e) = flo.renderer.shutdown() {
+            error!("Vulkan renderer shut down after an error: {}", e);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use ash::{vk, Instance};
+use tracing::error;
+use bevy_math::Mat4;
+use std::mem;
+use crate::constants::*;
+use crate::error::FloError;
+use crate::vulkan_common::*;
+use crate::leak_check;
+
+const BONE_SHADER_PATH: &str = "shaders/bone_hierarchy.comp.spv";
+const BONE_WORKGROUP_SIZE: u32 = 64;
+
+// Compute pipeline shared by every mesh with a bone hierarchy
+pub struct BoneHierarchyPipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+}
+
+impl BoneHierarchyPipeline {
+    pub fn new(device: &ash::Device) -> Result<Self, FloError> {
+        let storage_binding = |binding: u32| vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE);
+        let bindings = [
+            storage_binding(0),  // Local transforms
+            storage_binding(1),  // Parent indices
+            storage_binding(2),  // Inverse bind matrices
+            storage_binding(3),  // Joint buffer
+        ];
+        let descriptor_set_layout = create_descriptor_set_layout(device, &bindings)?;
+
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(mem::size_of::<u32>() as u32)];
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let layout = leak_check::created(device, unsafe { device.create_pipeline_layout(&layout_info, None)? });
+
+        let shader_code = read_shader_file(BONE_SHADER_PATH)?;
+        let shader_module = create_shader_module(device, &shader_code)?;
+
+        let entry_name = std::ffi::CString::new("main").unwrap();
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(&entry_name);
+        let pipeline_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = leak_check::created(device, unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+                .map_err(|(_, e)| e)?[0]
+        });
+
+        unsafe {
+            leak_check::destroy(device, shader_module);
+        }
+
+        Ok(Self { pipeline, layout, descriptor_set_layout })
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            leak_check::destroy(device, self.pipeline);
+            leak_check::destroy(device, self.layout);
+            leak_check::destroy(device, self.descriptor_set_layout);
+        }
+    }
+}
+
+// Buffers and descriptor sets of the compute path. Parents and inverse bind matrices are
+// uploaded once, local transforms every frame they change.
+struct BoneGpuResources {
+    parents_buffer: (vk::Buffer, vk::DeviceMemory),
+    inverse_bind_buffer: (vk::Buffer, vk::DeviceMemory),
+    // One per frame in flight, so the host never writes what a previous frame's dispatch reads
+    local_buffers: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+// Turns per bone local transforms into the skinning matrices of a mesh's joint buffer. Each
+// bone's invocation walks up its parent chain, so bones don't wait on each other.
+pub struct BoneHierarchy {
+    // -1 for root bones
+    parents: Vec<i32>,
+    inverse_bind_matrices: Vec<Mat4>,
+    // Latest local transforms, written to the current frame's buffer when recording
+    local_matrices: Vec<Mat4>,
+    dirty: bool,
+    // None when computing on the CPU
+    gpu: Option<BoneGpuResources>,
+}
+
+impl BoneHierarchy {
+    // joint_offset is where the mesh's matrices start in joint_buffer, non zero for meshes
+    // packed into the joint arena
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        bone_pipeline: Option<&BoneHierarchyPipeline>,
+        joint_buffer: vk::Buffer,
+        joint_offset: vk::DeviceSize,
+        parents: &[Option<usize>],
+        inverse_bind_matrices: &[Mat4],
+    ) -> Result<Self, FloError> {
+        if parents.len() != inverse_bind_matrices.len() {
+            return Err(format!(
+                "{} bone parents but {} inverse bind matrices", parents.len(), inverse_bind_matrices.len()
+            ).into());
+        }
+        if let Some(bone) = parents.iter().position(|parent| parent.is_some_and(|parent| parent >= parents.len())) {
+            return Err(format!("Parent of bone {} is out of bounds", bone).into());
+        }
+
+        let parents: Vec<i32> = parents.iter().map(|parent| parent.map_or(-1, |parent| parent as i32)).collect();
+        let gpu = match bone_pipeline {
+            Some(bone_pipeline) => Some(create_gpu_resources(
+                instance,
+                device,
+                physical_device,
+                bone_pipeline,
+                joint_buffer,
+                joint_offset,
+                &parents,
+                inverse_bind_matrices,
+            )?),
+            None => None,
+        };
+
+        Ok(Self {
+            local_matrices: vec![Mat4::IDENTITY; parents.len()],
+            parents,
+            inverse_bind_matrices: inverse_bind_matrices.to_vec(),
+            dirty: false,
+            gpu,
+        })
+    }
+
+    pub fn bone_count(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_gpu(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    // Returns the joint matrices to write right away when computing on the CPU,
+    // else they're computed by the next frame's dispatch
+    pub fn set_local_matrices(&mut self, local_matrices: Vec<Mat4>) -> Option<Vec<Mat4>> {
+        if self.gpu.is_none() {
+            return Some(self.joint_matrices(&local_matrices));
+        }
+        self.local_matrices = local_matrices;
+        self.dirty = true;
+        None
+    }
+
+    // Joint matrices for local transforms computed on the CPU, same result as the compute pass
+    pub fn joint_matrices(&self, local_matrices: &[Mat4]) -> Vec<Mat4> {
+        compute_joint_matrices(local_matrices, &self.parents, &self.inverse_bind_matrices)
+    }
+
+    // Writes the latest local transforms for this frame and dispatches the compute pass if
+    // they changed. Must be recorded outside a render pass, between record_joint_write_barrier
+    // and record_joint_read_barrier, with the bone pipeline bound.
+    pub fn record_dispatch(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize, bone_pipeline: &BoneHierarchyPipeline) {
+        let Some(gpu) = self.gpu.as_ref() else {
+            return;
+        };
+        if !self.dirty || self.parents.is_empty() {
+            return;
+        }
+        self.dirty = false;
+
+        write_mapped(device, gpu.local_buffers[frame].1, bytemuck::cast_slice(&self.local_matrices));
+
+        let bone_count = self.parents.len() as u32;
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                bone_pipeline.layout,
+                0,
+                &[gpu.descriptor_sets[frame]],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                bone_pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&bone_count),
+            );
+            device.cmd_dispatch(command_buffer, bone_count.div_ceil(BONE_WORKGROUP_SIZE), 1, 1);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        let Some(gpu) = self.gpu.as_ref() else {
+            return;
+        };
+        destroy_buffer(device, gpu.parents_buffer.0, gpu.parents_buffer.1);
+        destroy_buffer(device, gpu.inverse_bind_buffer.0, gpu.inverse_bind_buffer.1);
+        for &(buffer, memory) in &gpu.local_buffers {
+            destroy_buffer(device, buffer, memory);
+        }
+        unsafe {
+            leak_check::destroy(device, gpu.descriptor_pool);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_gpu_resources(
+    instance: &Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    bone_pipeline: &BoneHierarchyPipeline,
+    joint_buffer: vk::Buffer,
+    joint_offset: vk::DeviceSize,
+    parents: &[i32],
+    inverse_bind_matrices: &[Mat4],
+) -> Result<BoneGpuResources, FloError> {
+    // Empty storage buffers aren't allowed, so size for at least one bone
+    let bone_count = parents.len().max(1);
+    let matrices_size = (bone_count * mem::size_of::<Mat4>()) as vk::DeviceSize;
+    let host_buffer = |size: vk::DeviceSize| create_buffer(
+        instance,
+        device,
+        physical_device,
+        size,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    );
+
+    let parents_buffer = host_buffer((bone_count * mem::size_of::<i32>()) as vk::DeviceSize)?;
+    write_mapped(device, parents_buffer.1, bytemuck::cast_slice(parents));
+    let inverse_bind_buffer = host_buffer(matrices_size)?;
+    write_mapped(device, inverse_bind_buffer.1, bytemuck::cast_slice(inverse_bind_matrices));
+    let mut local_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        local_buffers.push(host_buffer(matrices_size)?);
+    }
+
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::STORAGE_BUFFER,
+        descriptor_count: 4 * MAX_FRAMES_IN_FLIGHT as u32,
+    }];
+    let descriptor_pool = create_descriptor_pool(device, MAX_FRAMES_IN_FLIGHT as u32, &pool_sizes)?;
+    let layouts = vec![bone_pipeline.descriptor_set_layout; MAX_FRAMES_IN_FLIGHT];
+    let descriptor_sets = allocate_descriptor_sets(device, descriptor_pool, &layouts)?;
+
+    for (&descriptor_set, &(local_buffer, _)) in descriptor_sets.iter().zip(&local_buffers) {
+        let buffer_infos = [(local_buffer, 0), (parents_buffer.0, 0), (inverse_bind_buffer.0, 0), (joint_buffer, joint_offset)]
+            .map(|(buffer, offset)| [vk::DescriptorBufferInfo { buffer, offset, range: vk::WHOLE_SIZE }]);
+        let writes: Vec<vk::WriteDescriptorSet> = buffer_infos.iter().enumerate().map(|(binding, info)| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(binding as u32)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(info)
+        }).collect();
+
+        unsafe {
+            device.update_descriptor_sets(&writes, &[]);
+        }
+    }
+
+    Ok(BoneGpuResources {
+        parents_buffer,
+        inverse_bind_buffer,
+        local_buffers,
+        descriptor_pool,
+        descriptor_sets,
+    })
+}
+
+// Skinning matrix of each bone: parent chain of local transforms times its inverse bind matrix.
+// Walks the chain per bone like the shader so the results match, bounded by the bone count in
+// case the parents form a cycle.
+pub fn compute_joint_matrices(local_matrices: &[Mat4], parents: &[i32], inverse_bind_matrices: &[Mat4]) -> Vec<Mat4> {
+    (0..local_matrices.len()).map(|bone| {
+        let mut world = local_matrices[bone];
+        let mut parent = parents[bone];
+        let mut depth = 0;
+        while parent >= 0 && depth < local_matrices.len() {
+            world = local_matrices[parent as usize] * world;
+            parent = parents[parent as usize];
+            depth += 1;
+        }
+        world * inverse_bind_matrices[bone]
+    }).collect()
+}
+
+// The previous frame's skinning may still read the joint buffers the dispatches write
+pub fn record_joint_write_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::VERTEX_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[],
+        );
+    }
+}
+
+// Between the dispatches and the skinned draws reading the joint buffers
+pub fn record_joint_read_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    let barrier = vk::MemoryBarrier::default()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::UNIFORM_READ | vk::AccessFlags::SHADER_READ);
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::VERTEX_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        );
+    }
+}
+
+fn write_mapped(device: &ash::Device, memory: vk::DeviceMemory, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    unsafe {
+        match device.map_memory(memory, 0, bytes.len() as vk::DeviceSize, vk::MemoryMapFlags::empty()) {
+            Ok(data) => {
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, bytes.len());
+                device.unmap_memory(memory);
+            }
+            Err(e) => error!("Failed to map bone buffer: {}", e),
+        }
+    }
+}
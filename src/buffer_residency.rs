@@ -0,0 +1,170 @@
+use ash::{vk, Instance};
+use tracing::warn;
+use std::ptr::NonNull;
+use crate::error::FloError;
+use crate::vulkan_common::{create_buffer, destroy_buffer};
+
+// Discrete GPUs without resizable BAR only expose this much of their memory to the host
+const BAR_WINDOW_SIZE: vk::DeviceSize = 256 * 1024 * 1024;
+
+const HOST_VISIBLE_DEVICE_LOCAL: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::from_raw(
+    vk::MemoryPropertyFlags::DEVICE_LOCAL.as_raw()
+        | vk::MemoryPropertyFlags::HOST_VISIBLE.as_raw()
+        | vk::MemoryPropertyFlags::HOST_COHERENT.as_raw(),
+);
+
+// Where a mesh's vertex and index buffers live, see VulkanRenderer::add_mesh_with_residency
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BufferResidency {
+    // Device local memory filled through staging copies, the fastest for the GPU to read
+    #[default]
+    DeviceLocal,
+    // Host visible device local memory (resizable BAR) written directly by the CPU, for
+    // geometry updated every frame. Falls back to DeviceLocal without rebar_available.
+    HostVisibleDeviceLocal,
+}
+
+// Where an instanced mesh's instance buffer lives, see VulkanRenderer::add_mesh_instanced_with_memory.
+// Updates are copied in through a staging buffer at the start of the next frame either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InstanceMemory {
+    // Host visible memory the GPU reads across the bus every frame. Cheap to set up, fine for
+    // small or rarely updated instance sets.
+    #[default]
+    HostVisible,
+    // Device local memory the GPU reads at full speed, for large crowds. A copy of the data is
+    // kept on the CPU since the buffer can't be mapped to read it back.
+    DeviceLocal,
+}
+
+// Whether the device has a host visible device local heap larger than the 256 MiB BAR window,
+// i.e. resizable BAR is on. Integrated GPUs usually qualify since all their memory is both.
+pub fn rebar_available(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    properties.memory_types[..properties.memory_type_count as usize].iter().any(|memory_type| {
+        memory_type.property_flags.contains(HOST_VISIBLE_DEVICE_LOCAL)
+            && properties.memory_heaps[memory_type.heap_index as usize].size > BAR_WINDOW_SIZE
+    })
+}
+
+// Creates a buffer in host visible device local memory and writes data straight into it
+pub fn create_host_visible_buffer(
+    instance: &Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    usage: vk::BufferUsageFlags,
+    data: &[u8],
+) -> Result<(vk::Buffer, vk::DeviceMemory), FloError> {
+    let (buffer, memory) = create_buffer(instance, device, physical_device, data.len() as vk::DeviceSize, usage, HOST_VISIBLE_DEVICE_LOCAL)?;
+    unsafe {
+        match device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) {
+            Ok(mapped) => {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), mapped as *mut u8, data.len());
+                device.unmap_memory(memory);
+            }
+            Err(e) => {
+                destroy_buffer(device, buffer, memory);
+                return Err(e.into());
+            }
+        }
+    }
+    Ok((buffer, memory))
+}
+
+struct MappedCopy {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    data: NonNull<u8>,
+    stale: bool,  // Hasn't received the latest write yet
+}
+
+// A vertex buffer in host visible device local memory, with one persistently mapped copy per
+// frame in flight. A copy is only written when its frame starts recording, after the frame's
+// fence was waited on, so the CPU never writes a copy the GPU may be reading.
+pub struct MappedVertexBuffer {
+    copies: Vec<MappedCopy>,
+    size: usize,
+    latest: Vec<u8>,
+}
+
+// The mappings are only written through &mut self, so the buffer can move between threads
+// like the rest of the renderer
+unsafe impl Send for MappedVertexBuffer {}
+
+impl MappedVertexBuffer {
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        frame_count: usize,
+        data: &[u8],
+    ) -> Result<Self, FloError> {
+        let mut mapped_buffer = Self {
+            copies: Vec::with_capacity(frame_count),
+            size: data.len(),
+            latest: data.to_vec(),
+        };
+        for _ in 0..frame_count {
+            let (buffer, memory) = match create_buffer(
+                instance,
+                device,
+                physical_device,
+                data.len() as vk::DeviceSize,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                HOST_VISIBLE_DEVICE_LOCAL,
+            ) {
+                Ok(created) => created,
+                Err(e) => {
+                    mapped_buffer.destroy(device);
+                    return Err(e);
+                }
+            };
+            let mapped = unsafe { device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) };
+            let Some(mapped) = mapped.ok().and_then(|mapped| NonNull::new(mapped as *mut u8)) else {
+                destroy_buffer(device, buffer, memory);
+                mapped_buffer.destroy(device);
+                return Err("Failed to map host visible vertex buffer".into());
+            };
+            mapped_buffer.copies.push(MappedCopy { buffer, memory, data: mapped, stale: true });
+        }
+        Ok(mapped_buffer)
+    }
+
+    pub fn contains(&self, buffer: vk::Buffer) -> bool {
+        self.copies.iter().any(|copy| copy.buffer == buffer)
+    }
+
+    // Keeps the data until every copy has it. Data beyond the buffer's size is dropped.
+    pub fn write(&mut self, data: &[u8]) {
+        if data.len() > self.size {
+            warn!("Vertex data is {} bytes but the buffer holds {}, the rest is dropped", data.len(), self.size);
+        }
+        let len = data.len().min(self.size);
+        self.latest[..len].copy_from_slice(&data[..len]);
+        for copy in &mut self.copies {
+            copy.stale = true;
+        }
+    }
+
+    // Brings the copy for a frame up to date and returns it. Only call when the frame starts
+    // recording.
+    pub fn frame_buffer(&mut self, frame: usize) -> vk::Buffer {
+        let copy = &mut self.copies[frame];
+        if copy.stale {
+            unsafe {
+                std::ptr::copy_nonoverlapping(self.latest.as_ptr(), copy.data.as_ptr(), self.size);
+            }
+            copy.stale = false;
+        }
+        copy.buffer
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        for copy in &self.copies {
+            unsafe {
+                device.unmap_memory(copy.memory);
+            }
+            destroy_buffer(device, copy.buffer, copy.memory);
+        }
+    }
+}
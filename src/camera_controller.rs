@@ -1,11 +1,18 @@
 // Copied from https://github.com/DGriffin91/bevy_basic_camera
 
+#[cfg(feature = "bevy")]
 use bevy::{
+    input::keyboard::KeyboardFocusLost,
     input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
     prelude::*,
 };
+// Camera and CameraShake are also built without Bevy
+use bevy_math::{EulerRot, Mat4, Quat, Vec3};
+use bevy_transform::components::Transform;
+use crate::random::SeededRng;
 
 /// Provides basic movement functionality to the attached camera
+#[cfg(feature = "bevy")]
 #[derive(Component, Clone)]
 pub struct CameraController {
     pub enabled: bool,
@@ -32,9 +39,28 @@ pub struct CameraController {
     pub lock_y: bool,
 }
 
+#[cfg(feature = "bevy")]
 impl CameraController {
+    // Starts in orbit mode around the focus, e.g. VulkanRenderer::scene_centroid of a loaded model
+    pub fn with_orbit_focus(mut self, focus: Vec3) -> Self {
+        self.orbit_mode = true;
+        self.orbit_focus = focus;
+        self
+    }
+
+    // Switches to orbiting the focus and turns the camera toward it, keeping its position
+    pub fn focus_on(&mut self, focus: Vec3, transform: &mut Transform) {
+        self.orbit_mode = true;
+        self.orbit_focus = focus;
+        if transform.translation != focus {
+            transform.look_at(focus, Vec3::Y);
+            // Pitch and yaw are read back from the new rotation on the next update
+            self.initialized = false;
+        }
+    }
+
     pub fn print_controls(self) -> Self {
-        println!(
+        info!(
             "
 ===============================
 ======= Camera Controls =======
@@ -62,6 +88,7 @@ impl CameraController {
     }
 }
 
+#[cfg(feature = "bevy")]
 impl Default for CameraController {
     fn default() -> Self {
         Self {
@@ -91,14 +118,16 @@ impl Default for CameraController {
     }
 }
 
+#[cfg(feature = "bevy")]
 pub fn camera_controller(
     time: Res<Time>,
     mut mouse_events: EventReader<MouseMotion>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
     mut scroll_evr: EventReader<MouseWheel>,
     key_input: Res<ButtonInput<KeyCode>>,
+    mut focus_lost_events: EventReader<KeyboardFocusLost>,
     mut move_toggled: Local<bool>,
-    mut query: Query<(&mut Transform, &mut CameraController), With<Camera>>,
+    mut query: Query<(&mut Transform, &mut CameraController), With<bevy::prelude::Camera>>,
 ) {
     let dt = time.delta_secs();
 
@@ -109,6 +138,18 @@ pub fn camera_controller(
             options.pitch = pitch;
             options.initialized = true;
         }
+
+        // Keys held when the window lost focus never get a release event, so drop all held
+        // movement and mouse look instead of drifting until focus comes back
+        if !focus_lost_events.is_empty() {
+            focus_lost_events.clear();
+            options.velocity = Vec3::ZERO;
+            *move_toggled = false;
+            mouse_events.clear();
+            scroll_evr.clear();
+            return;
+        }
+
         if !options.enabled {
             return;
         }
@@ -224,13 +265,303 @@ pub fn camera_controller(
     }
 }
 
+// How a shake's strength falls off over its duration. Both reach exactly zero at the end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShakeDecay {
+    Linear,
+    // Falls off quickly at first, faster for larger rates
+    Exponential(f32),
+}
+
+impl Default for ShakeDecay {
+    fn default() -> Self {
+        ShakeDecay::Exponential(4.0)
+    }
+}
+
+impl ShakeDecay {
+    // Strength at progress (0 to 1) through a shake
+    pub fn envelope(self, progress: f32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            ShakeDecay::Linear => 1.0 - progress,
+            ShakeDecay::Exponential(rate) if rate > 0.0 => {
+                // Shifted and rescaled so it starts at 1 and ends at 0 instead of approaching it
+                let end = (-rate).exp();
+                ((-rate * progress).exp() - end) / (1.0 - end)
+            }
+            ShakeDecay::Exponential(_) => 1.0 - progress,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Shake {
+    intensity: f32,
+    duration: f32,
+    elapsed: f32,
+    seed: u64,
+}
+
+// Additive shake for explosions, footsteps and the like, attached next to the CameraController.
+// The controlled transform is never changed: whatever builds the view reads it through
+// CameraShake::apply, so the camera can't drift and is back on its transform exactly once
+// every shake has ended. Shakes overlap by summing, each decaying on its own.
+#[cfg_attr(feature = "bevy", derive(Component))]
+#[derive(Clone, Debug)]
+pub struct CameraShake {
+    // Largest position offset at intensity 1, in world units
+    pub max_translation: f32,
+    // Largest rotation offset at intensity 1, in radians per axis
+    pub max_rotation: f32,
+    // Noise samples per second, higher shakes faster
+    pub frequency: f32,
+    pub decay: ShakeDecay,
+    // Seeds the noise, so the same shakes added at the same times always move the same way
+    pub seed: u32,
+    shakes: Vec<Shake>,
+    shakes_added: u32,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        Self {
+            max_translation: 0.3,
+            max_rotation: 0.05,
+            frequency: 15.0,
+            decay: ShakeDecay::default(),
+            seed: 0,
+            shakes: Vec::new(),
+            shakes_added: 0,
+        }
+    }
+}
+
+impl CameraShake {
+    // Starts a shake lasting duration seconds. Intensity scales the offsets, 1 reaching
+    // max_translation and max_rotation.
+    pub fn add_shake(&mut self, intensity: f32, duration: f32) {
+        if intensity <= 0.0 || duration <= 0.0 {
+            return;
+        }
+        // Each shake gets its own noise so overlapping ones don't move in lockstep
+        let seed = SeededRng::new(((self.seed as u64) << 32) | self.shakes_added as u64).next_u64();
+        self.shakes_added = self.shakes_added.wrapping_add(1);
+        self.shakes.push(Shake { intensity, duration, elapsed: 0.0, seed });
+    }
+
+    // Advances every shake and drops the ones that ended, run by camera_shake
+    pub fn update(&mut self, dt: f32) {
+        for shake in &mut self.shakes {
+            shake.elapsed += dt;
+        }
+        self.shakes.retain(|shake| shake.elapsed < shake.duration);
+    }
+
+    pub fn is_shaking(&self) -> bool {
+        !self.shakes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.shakes.clear();
+    }
+
+    // Sum of the active shakes' offsets, in the camera's local space
+    pub fn offset(&self) -> (Vec3, Quat) {
+        let mut translation = Vec3::ZERO;
+        let mut angles = Vec3::ZERO;
+        for shake in &self.shakes {
+            let strength = shake.intensity * self.decay.envelope(shake.elapsed / shake.duration);
+            let t = shake.elapsed * self.frequency;
+            // Channels sit above the noise cells in the seed, so they never share a value
+            let channel = |index: u64| value_noise(shake.seed ^ (index << 32), t);
+            translation += Vec3::new(channel(0), channel(1), channel(2)) * strength * self.max_translation;
+            angles += Vec3::new(channel(3), channel(4), channel(5)) * strength * self.max_rotation;
+        }
+        (translation, Quat::from_euler(EulerRot::YXZ, angles.y, angles.x, angles.z))
+    }
+
+    // The transform to view from: transform with the shake on top, or transform itself
+    // when nothing is shaking
+    pub fn apply(&self, transform: &Transform) -> Transform {
+        if self.shakes.is_empty() {
+            return *transform;
+        }
+        let (translation, rotation) = self.offset();
+        Transform {
+            translation: transform.translation + transform.rotation * translation,
+            rotation: transform.rotation * rotation,
+            scale: transform.scale,
+        }
+    }
+}
+
+// Smoothly interpolated random values in -1..1 at whole t, the same for the same seed
+fn value_noise(seed: u64, t: f32) -> f32 {
+    let cell = t.floor();
+    let fraction = t - cell;
+    let smooth = fraction * fraction * (3.0 - 2.0 * fraction);
+    // The first SplitMix value of a seed is a well mixed hash of it
+    let lattice = |cell: u64| SeededRng::new(seed ^ cell).range_f32(-1.0..1.0);
+    let a = lattice(cell as u64);
+    let b = lattice(cell as u64 + 1);
+    a + (b - a) * smooth
+}
+
+#[cfg(feature = "bevy")]
+pub fn camera_shake(time: Res<Time>, mut query: Query<&mut CameraShake>) {
+    let dt = time.delta_secs();
+    for mut shake in &mut query {
+        if shake.is_shaking() {
+            shake.update(dt);
+        }
+    }
+}
+
+// View and projection matrices for render_frame_with_camera and friends, in Vulkan's
+// conventions: depth maps to 0..1 (or 1..0 with reverse-Z) and clip space y points down, so a
+// point above the target lands in the upper half of the screen at negative NDC y.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    pub target: Vec3,
+    // World up, +Y. Kept as is, the Y flip is in the projection.
+    pub up: Vec3,
+    // Vertical field of view in radians
+    pub fov_y: f32,
+    // Width over height, e.g. VulkanRenderer::aspect_ratio
+    pub aspect_ratio: f32,
+    pub near: f32,
+    pub far: f32,
+    // Has to match the renderer's depth convention, see VulkanRenderer::set_reverse_z
+    pub reverse_z: bool,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vec3::new(0.0, 0.0, 5.0),
+            target: Vec3::ZERO,
+            up: Vec3::Y,
+            fov_y: 60.0_f32.to_radians(),
+            aspect_ratio: 16.0 / 9.0,
+            near: 0.1,
+            far: 1000.0,
+            reverse_z: false,
+        }
+    }
+}
+
+impl Camera {
+    pub fn new(position: Vec3, target: Vec3) -> Self {
+        Self { position, target, ..Default::default() }
+    }
+
+    // Looks along the transform's forward, e.g. a CameraController's transform after
+    // CameraShake::apply
+    pub fn from_transform(transform: &Transform) -> Self {
+        Self {
+            position: transform.translation,
+            target: transform.translation + *transform.forward(),
+            up: *transform.up(),
+            ..Default::default()
+        }
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_at_rh(self.position, self.target, self.up)
+    }
+
+    pub fn projection_matrix(&self) -> Mat4 {
+        // perspective_rh already maps depth to 0..1, swapping the planes reverses it
+        let mut proj = if self.reverse_z {
+            Mat4::perspective_rh(self.fov_y, self.aspect_ratio, self.far, self.near)
+        } else {
+            Mat4::perspective_rh(self.fov_y, self.aspect_ratio, self.near, self.far)
+        };
+        proj.y_axis.y = -proj.y_axis.y;
+        proj
+    }
+
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection_matrix() * self.view_matrix()
+    }
+}
+
 /// Simple flying camera plugin.
 /// In order to function, the [`CameraController`] component should be attached to the camera entity.
+#[cfg(feature = "bevy")]
 #[derive(Default)]
 pub struct CameraControllerPlugin;
 
+#[cfg(feature = "bevy")]
 impl Plugin for CameraControllerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, camera_controller);
+        app.add_systems(Update, (camera_controller, camera_shake));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_math::Vec4Swizzles;
+
+    fn ndc(camera: &Camera, point: Vec3) -> Vec3 {
+        let clip = camera.view_projection() * point.extend(1.0);
+        clip.xyz() / clip.w
+    }
+
+    #[test]
+    fn points_project_to_vulkan_ndc() {
+        let mut camera = Camera {
+            fov_y: 90.0_f32.to_radians(),
+            aspect_ratio: 2.0,
+            ..Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO)
+        };
+        let target = ndc(&camera, Vec3::ZERO);
+        assert!(target.x.abs() < 1e-6 && target.y.abs() < 1e-6);
+        // Up and right of the target, 5 units away with tan(fov / 2) = 1: up is negative y
+        let corner = ndc(&camera, Vec3::new(1.0, 1.0, 0.0));
+        assert!(corner.abs_diff_eq(Vec3::new(0.1, -0.2, target.z), 1e-6));
+        // The near plane is at depth 0 and the far one at 1, reverse-Z swaps them
+        let near = Vec3::new(0.0, 0.0, 4.9);
+        let far = Vec3::new(0.0, 0.0, -995.0);
+        assert!(ndc(&camera, near).z.abs() < 1e-4);
+        assert!((ndc(&camera, far).z - 1.0).abs() < 1e-4);
+
+        camera.reverse_z = true;
+        assert!((ndc(&camera, near).z - 1.0).abs() < 1e-4);
+        assert!(ndc(&camera, far).z.abs() < 1e-4);
+    }
+
+    #[test]
+    fn same_seed_shakes_the_same_way() {
+        let shaken = |seed: u32| {
+            let mut shake = CameraShake { seed, ..Default::default() };
+            shake.add_shake(1.0, 2.0);
+            shake.add_shake(0.5, 1.0);
+            (0..30).map(|_| {
+                shake.update(1.0 / 60.0);
+                shake.offset()
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(shaken(7), shaken(7));
+        assert_ne!(shaken(7), shaken(8));
+    }
+
+    #[test]
+    fn apply_returns_the_transform_once_the_shake_ended() {
+        let transform = Transform::from_xyz(1.0, 2.0, 3.0).looking_at(Vec3::ZERO, Vec3::Y);
+        for decay in [ShakeDecay::Linear, ShakeDecay::Exponential(4.0)] {
+            assert_eq!(decay.envelope(1.0), 0.0);
+            let mut shake = CameraShake { decay, ..Default::default() };
+            shake.add_shake(1.0, 0.5);
+            shake.update(0.25);
+            assert_ne!(shake.apply(&transform), transform);
+
+            shake.update(0.25);
+            assert!(!shake.is_shaking());
+            assert_eq!(shake.apply(&transform), transform);
+        }
     }
 }
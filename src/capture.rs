@@ -0,0 +1,190 @@
+use std::io::Write;
+use std::path::Path;
+use ash::{vk, Instance};
+use crate::error::FloError;
+use crate::texture::{begin_single_time_commands, end_single_time_commands};
+use crate::vulkan_common::{create_buffer, destroy_buffer};
+
+// Pixel format VulkanRenderer::capture_render_target returns
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaptureFormat {
+    // Clamped to 0..=1 and sRGB encoded, ready to save as a PNG. No tonemapping is applied.
+    #[default]
+    Rgba8,
+    // The HDR values as they are in the target, before tonemapping and the sRGB encode
+    Rgba16Float,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CapturedPixels {
+    Rgba8(Vec<u8>),
+    // Widened to f32 from the target's half floats
+    RgbaFloat(Vec<f32>),
+}
+
+// Rows top to bottom, 4 channels per pixel
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: CapturedPixels,
+}
+
+impl CapturedImage {
+    // Writes a float capture as a little endian Portable Float Map (RGB, alpha dropped), which
+    // most HDR viewers and image libraries open
+    pub fn write_pfm(&self, path: impl AsRef<Path>) -> Result<(), FloError> {
+        let CapturedPixels::RgbaFloat(ref pixels) = self.pixels else {
+            return Err("PFM files hold float pixels, capture with CaptureFormat::Rgba16Float".into());
+        };
+        let mut bytes = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        // PFM rows run bottom to top
+        for row in pixels.chunks_exact(self.width as usize * 4).rev() {
+            for pixel in row.chunks_exact(4) {
+                for channel in &pixel[..3] {
+                    bytes.extend_from_slice(&channel.to_le_bytes());
+                }
+            }
+        }
+        std::fs::File::create(path)?.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+// Copies a HDR_FORMAT (R16G16B16A16_SFLOAT) color image in SHADER_READ_ONLY_OPTIMAL, the scene
+// render pass's final layout, to the host and leaves it in that layout. The image needs
+// TRANSFER_SRC usage.
+#[allow(clippy::too_many_arguments)]
+pub fn capture_color_image(
+    instance: &Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    format: CaptureFormat,
+) -> Result<CapturedImage, FloError> {
+    let texel_count = extent.width as usize * extent.height as usize * 4;
+    // Tightly packed rows: 4 half floats, 8 bytes per pixel
+    let size = (texel_count * 2) as vk::DeviceSize;
+    let (buffer, memory) = create_buffer(
+        instance,
+        device,
+        physical_device,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    let halves = copy_image_to_buffer(device, command_pool, queue, image, extent, buffer).and_then(|()| unsafe {
+        let data = device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())?;
+        let halves = std::slice::from_raw_parts(data as *const u16, texel_count).to_vec();
+        device.unmap_memory(memory);
+        Ok(halves)
+    });
+    destroy_buffer(device, buffer, memory);
+    let halves = halves?;
+
+    let pixels = match format {
+        CaptureFormat::Rgba8 => CapturedPixels::Rgba8(
+            halves.chunks_exact(4).flat_map(|pixel| {
+                let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]].map(f16_to_f32);
+                [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a]
+                    .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+            }).collect(),
+        ),
+        CaptureFormat::Rgba16Float => CapturedPixels::RgbaFloat(halves.into_iter().map(f16_to_f32).collect()),
+    };
+    Ok(CapturedImage { width: extent.width, height: extent.height, pixels })
+}
+
+fn copy_image_to_buffer(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    buffer: vk::Buffer,
+) -> Result<(), FloError> {
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let to_transfer = vk::ImageMemoryBarrier::default()
+        .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+    let to_shader_read = vk::ImageMemoryBarrier::default()
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+    let region = vk::BufferImageCopy::default()
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 });
+
+    let command_buffer = begin_single_time_commands(device, command_pool)?;
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer],
+        );
+        device.cmd_copy_image_to_buffer(command_buffer, image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, buffer, &[region]);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_shader_read],
+        );
+    }
+    // Waits for the queue, so the buffer is ready to map afterwards
+    end_single_time_commands(device, command_pool, queue, command_buffer)?;
+    Ok(())
+}
+
+// IEEE 754 binary16 to f32, including subnormals, infinities and NaN
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
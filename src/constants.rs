@@ -1,7 +1,12 @@
+use ash::vk;
+
 // Vulkan configuration constants
 pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
 pub const ENABLE_VALIDATION_LAYERS: bool = false;
 
+// The scene is rendered in this format, then tonemapped to the swapchain by the present pass
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
 // Clear color constants
 pub const CLEAR_COLOR_DEFAULT: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
 pub const CLEAR_COLOR_MAGENTA: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
@@ -9,3 +14,6 @@ pub const CLEAR_COLOR_MAGENTA: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
 // Depth clear value
 pub const DEPTH_CLEAR_VALUE: f32 = 1.0;
 pub const STENCIL_CLEAR_VALUE: u32 = 0;
+
+// Occlusion queries available per frame in flight
+pub const MAX_OCCLUSION_QUERIES: u32 = 1024;
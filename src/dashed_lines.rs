@@ -0,0 +1,239 @@
+use ash::vk;
+use bevy_math::{Mat4, Vec2, Vec3, Vec4};
+use std::mem;
+use crate::error::FloError;
+use crate::memory_pool::DynamicBufferRing;
+use crate::vulkan_common::*;
+use crate::leak_check;
+
+const DASHED_LINE_VERT_SHADER_PATH: &str = "shaders/dashed_line.vert.spv";
+const DASHED_LINE_FRAG_SHADER_PATH: &str = "shaders/dashed_line.frag.spv";
+
+// One end of a line, matches the inputs of shaders/dashed_line.vert. arc_length is the
+// distance along the line in pixels, which the fragment shader turns into dashes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub arc_length: f32,
+    pub color: [f32; 4],
+}
+
+// Lengths in pixels, so dashes look the same on a wall across the map as on one next to the camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DashPattern {
+    pub dash: f32,
+    pub gap: f32,
+}
+
+impl Default for DashPattern {
+    fn default() -> Self {
+        Self { dash: 8.0, gap: 6.0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DashedLinePushConstants {
+    view_proj: [f32; 16],
+    dash: f32,
+    gap: f32,
+}
+
+// Immediate mode dashed lines, e.g. outlines of a building placement preview. Lines queued
+// during a frame are drawn by that frame and then dropped, so they have to be queued again
+// every frame they should stay visible. Their vertices go into the renderer's dynamic buffer ring.
+pub struct DashedLines {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    lines: Vec<(Vec3, Vec3, [f32; 4])>,
+    pattern: DashPattern,
+}
+
+impl DashedLines {
+    pub fn new(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+        with_depth_test: bool,
+    ) -> Result<Self, FloError> {
+        let push_constants = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(mem::size_of::<DashedLinePushConstants>() as u32);
+        // Lines test depth so buildings hide them, but don't write it over what's behind them
+        let (pipeline, layout) = PipelineBuilder::new(
+            device.clone(),
+            DASHED_LINE_VERT_SHADER_PATH,
+            DASHED_LINE_FRAG_SHADER_PATH,
+            extent,
+            render_pass,
+        )?
+        .with_vertex_input(vec![line_binding_description()], line_attribute_descriptions())
+        .with_push_constants(vec![push_constants])
+        .with_topology(vk::PrimitiveTopology::LINE_LIST)
+        .with_depth_test(with_depth_test)
+        .with_depth_write(false)
+        .with_cull_mode(vk::CullModeFlags::NONE)
+        .with_blend_mode(BlendMode::AlphaBlend)
+        .build()?;
+
+        Ok(Self {
+            pipeline,
+            layout,
+            lines: Vec::new(),
+            pattern: DashPattern::default(),
+        })
+    }
+
+    pub fn queue_line(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) {
+        self.lines.push((start, end, color));
+    }
+
+    pub fn set_pattern(&mut self, pattern: DashPattern) {
+        self.pattern = pattern;
+    }
+
+    pub fn pattern(&self) -> DashPattern {
+        self.pattern
+    }
+
+    // Draws and clears the queued lines. Arc lengths are measured in pixels of a viewport of
+    // viewport_size with the same view and proj, so the pattern doesn't stretch with distance.
+    // Must be recorded inside the scene pass.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_draw(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        ring: &mut DynamicBufferRing,
+        view: Mat4,
+        proj: Mat4,
+        viewport_size: Vec2,
+        reverse_z: bool,
+        depth_compare_op: vk::CompareOp,
+    ) -> Result<(), FloError> {
+        let lines = mem::take(&mut self.lines);
+        let view_proj = proj * view;
+        let vertices = line_vertices(&lines, view_proj, viewport_size, reverse_z);
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        let (buffer, offset) = ring.write(bytemuck::cast_slice(&vertices))?;
+
+        let push_constants = DashedLinePushConstants {
+            view_proj: view_proj.to_cols_array(),
+            dash: self.pattern.dash.max(0.0),
+            gap: self.pattern.gap.max(0.0),
+        };
+        bind_graphics_pipeline(device, command_buffer, self.pipeline, depth_compare_op);
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[offset]);
+            device.cmd_draw(command_buffer, vertices.len() as u32, 1, 0, 0);
+        }
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            leak_check::destroy(device, self.pipeline);
+            leak_check::destroy(device, self.layout);
+        }
+    }
+}
+
+// The 12 edges of the box between min and max
+pub fn box_edges(min: Vec3, max: Vec3) -> [(Vec3, Vec3); 12] {
+    let corner = |x: bool, y: bool, z: bool| Vec3::new(
+        if x { max.x } else { min.x },
+        if y { max.y } else { min.y },
+        if z { max.z } else { min.z },
+    );
+    [
+        // Bottom
+        (corner(false, false, false), corner(true, false, false)),
+        (corner(true, false, false), corner(true, false, true)),
+        (corner(true, false, true), corner(false, false, true)),
+        (corner(false, false, true), corner(false, false, false)),
+        // Top
+        (corner(false, true, false), corner(true, true, false)),
+        (corner(true, true, false), corner(true, true, true)),
+        (corner(true, true, true), corner(false, true, true)),
+        (corner(false, true, true), corner(false, true, false)),
+        // Sides
+        (corner(false, false, false), corner(false, true, false)),
+        (corner(true, false, false), corner(true, true, false)),
+        (corner(true, false, true), corner(true, true, true)),
+        (corner(false, false, true), corner(false, true, true)),
+    ]
+}
+
+// Two vertices per line with the arc length in pixels, 0 at the start so each line begins
+// with a dash. Lines are cut where they cross the near plane, since points behind the camera
+// have no position on screen. Cut lines measure from where they enter the view, so their
+// dashes slide along while the camera moves.
+fn line_vertices(lines: &[(Vec3, Vec3, [f32; 4])], view_proj: Mat4, viewport_size: Vec2, reverse_z: bool) -> Vec<LineVertex> {
+    // Signed distance to the near plane in clip space, z = 0 or z = w with reverse-Z
+    let near_distance = |clip: Vec4| if reverse_z { clip.w - clip.z } else { clip.z };
+    let to_pixels = |clip: Vec4| (clip.truncate().truncate() / clip.w * 0.5 + 0.5) * viewport_size;
+
+    let mut vertices = Vec::with_capacity(lines.len() * 2);
+    for &(start, end, color) in lines {
+        let (mut start, mut end) = (start, end);
+        let (mut start_clip, mut end_clip) = (view_proj * start.extend(1.0), view_proj * end.extend(1.0));
+        let (start_distance, end_distance) = (near_distance(start_clip), near_distance(end_clip));
+        if start_distance < 0.0 && end_distance < 0.0 {
+            continue;
+        }
+        if start_distance < 0.0 || end_distance < 0.0 {
+            let t = start_distance / (start_distance - end_distance);
+            let cut = start.lerp(end, t);
+            let cut_clip = start_clip.lerp(end_clip, t);
+            if start_distance < 0.0 {
+                (start, start_clip) = (cut, cut_clip);
+            } else {
+                (end, end_clip) = (cut, cut_clip);
+            }
+        }
+        // A degenerate projection can still leave w at 0
+        if start_clip.w <= 0.0 || end_clip.w <= 0.0 {
+            continue;
+        }
+
+        let length = to_pixels(start_clip).distance(to_pixels(end_clip));
+        vertices.push(LineVertex { position: start.to_array(), arc_length: 0.0, color });
+        vertices.push(LineVertex { position: end.to_array(), arc_length: length, color });
+    }
+    vertices
+}
+
+fn line_binding_description() -> vk::VertexInputBindingDescription {
+    vk::VertexInputBindingDescription::default()
+        .binding(0)
+        .stride(mem::size_of::<LineVertex>() as u32)
+        .input_rate(vk::VertexInputRate::VERTEX)
+}
+
+fn line_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+    vec![
+        // Position and arc length
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(memoffset::offset_of!(LineVertex, position) as u32),
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(memoffset::offset_of!(LineVertex, color) as u32),
+    ]
+}
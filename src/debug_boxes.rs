@@ -0,0 +1,200 @@
+use ash::{vk, Instance};
+use bevy_math::{Mat4, Vec3};
+use std::mem;
+use crate::dashed_lines::box_edges;
+use crate::error::FloError;
+use crate::memory_pool::DynamicBufferRing;
+use crate::vulkan_common::*;
+use crate::leak_check;
+
+const DEBUG_BOX_VERT_SHADER_PATH: &str = "shaders/debug_box.vert.spv";
+const DEBUG_BOX_FRAG_SHADER_PATH: &str = "shaders/debug_box.frag.spv";
+
+// Two corners per edge of the unit cube
+const UNIT_CUBE_VERTICES: u32 = 24;
+
+// Per instance inputs of shaders/debug_box.vert
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BoxInstance {
+    min: [f32; 3],
+    max: [f32; 3],
+    color: [f32; 4],
+}
+
+// Immediate mode wireframe boxes for debugging spatial structures, e.g. the cells of an octree.
+// All boxes queued during a frame are one instanced line draw of a unit cube, stretched to each
+// box in the vertex shader, then dropped. The instances go into the renderer's dynamic buffer ring.
+pub struct DebugBoxes {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    unit_cube: (vk::Buffer, vk::DeviceMemory),
+    boxes: Vec<BoxInstance>,
+}
+
+impl DebugBoxes {
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+        with_depth_test: bool,
+    ) -> Result<Self, FloError> {
+        let push_constants = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(mem::size_of::<Mat4>() as u32);
+        // Tested against depth so geometry hides the boxes behind it, without writing it
+        let (pipeline, layout) = PipelineBuilder::new(
+            device.clone(),
+            DEBUG_BOX_VERT_SHADER_PATH,
+            DEBUG_BOX_FRAG_SHADER_PATH,
+            extent,
+            render_pass,
+        )?
+        .with_vertex_input(box_binding_descriptions(), box_attribute_descriptions())
+        .with_push_constants(vec![push_constants])
+        .with_topology(vk::PrimitiveTopology::LINE_LIST)
+        .with_depth_test(with_depth_test)
+        .with_depth_write(false)
+        .with_cull_mode(vk::CullModeFlags::NONE)
+        .with_blend_mode(BlendMode::AlphaBlend)
+        .build()?;
+
+        let corners: Vec<[f32; 3]> = box_edges(Vec3::ZERO, Vec3::ONE)
+            .into_iter()
+            .flat_map(|(start, end)| [start.to_array(), end.to_array()])
+            .collect();
+        let bytes: &[u8] = bytemuck::cast_slice(&corners);
+        let unit_cube = create_buffer(
+            instance,
+            device,
+            physical_device,
+            bytes.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+        .and_then(|(buffer, memory)| match write_mapped(device, memory, bytes) {
+            Ok(()) => Ok((buffer, memory)),
+            Err(e) => {
+                destroy_buffer(device, buffer, memory);
+                Err(e)
+            }
+        });
+        let unit_cube = match unit_cube {
+            Ok(unit_cube) => unit_cube,
+            Err(e) => {
+                unsafe {
+                    leak_check::destroy(device, pipeline);
+                    leak_check::destroy(device, layout);
+                }
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
+            pipeline,
+            layout,
+            unit_cube,
+            boxes: Vec::new(),
+        })
+    }
+
+    pub fn queue_boxes(&mut self, boxes: &[(Vec3, Vec3, [f32; 4])]) {
+        self.boxes.extend(boxes.iter().map(|&(min, max, color)| BoxInstance {
+            min: min.to_array(),
+            max: max.to_array(),
+            color,
+        }));
+    }
+
+    // Draws and clears the queued boxes. Must be recorded inside the scene pass.
+    pub fn record_draw(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        ring: &mut DynamicBufferRing,
+        view: Mat4,
+        proj: Mat4,
+        depth_compare_op: vk::CompareOp,
+    ) -> Result<(), FloError> {
+        let boxes = mem::take(&mut self.boxes);
+        if boxes.is_empty() {
+            return Ok(());
+        }
+
+        let (buffer, offset) = ring.write(bytemuck::cast_slice(&boxes))?;
+
+        bind_graphics_pipeline(device, command_buffer, self.pipeline, depth_compare_op);
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                bytemuck::bytes_of(&(proj * view).to_cols_array()),
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.unit_cube.0, buffer], &[0, offset]);
+            device.cmd_draw(command_buffer, UNIT_CUBE_VERTICES, boxes.len() as u32, 0, 0);
+        }
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        destroy_buffer(device, self.unit_cube.0, self.unit_cube.1);
+        unsafe {
+            leak_check::destroy(device, self.pipeline);
+            leak_check::destroy(device, self.layout);
+        }
+    }
+}
+
+fn write_mapped(device: &ash::Device, memory: vk::DeviceMemory, bytes: &[u8]) -> Result<(), FloError> {
+    unsafe {
+        let data = device.map_memory(memory, 0, bytes.len() as vk::DeviceSize, vk::MemoryMapFlags::empty())?;
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, bytes.len());
+        device.unmap_memory(memory);
+    }
+    Ok(())
+}
+
+// Binding 0 is the unit cube's corners, binding 1 the boxes
+fn box_binding_descriptions() -> Vec<vk::VertexInputBindingDescription> {
+    vec![
+        vk::VertexInputBindingDescription::default()
+            .binding(0)
+            .stride(mem::size_of::<[f32; 3]>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX),
+        vk::VertexInputBindingDescription::default()
+            .binding(1)
+            .stride(mem::size_of::<BoxInstance>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE),
+    ]
+}
+
+fn box_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+    vec![
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0),
+        vk::VertexInputAttributeDescription::default()
+            .binding(1)
+            .location(1)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(memoffset::offset_of!(BoxInstance, min) as u32),
+        vk::VertexInputAttributeDescription::default()
+            .binding(1)
+            .location(2)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(memoffset::offset_of!(BoxInstance, max) as u32),
+        // Per instance color
+        vk::VertexInputAttributeDescription::default()
+            .binding(1)
+            .location(3)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(memoffset::offset_of!(BoxInstance, color) as u32),
+    ]
+}
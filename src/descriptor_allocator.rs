@@ -0,0 +1,173 @@
+use ash::vk;
+use crate::error::FloError;
+use crate::leak_check;
+
+// Size of each pool block the allocator creates, set with RendererConfig::descriptor_pool_sizes.
+// Requests bigger than a block get a block scaled up to fit them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DescriptorPoolSizes {
+    pub max_sets: u32,
+    pub combined_image_samplers: u32,
+    pub uniform_buffers: u32,
+    pub storage_buffers: u32,
+    // Joint bindings of skinned meshes that don't fit in the joint arena
+    pub uniform_buffers_dynamic: u32,
+    pub storage_buffers_dynamic: u32,
+}
+
+impl Default for DescriptorPoolSizes {
+    fn default() -> Self {
+        Self {
+            max_sets: 256,
+            combined_image_samplers: 256,
+            uniform_buffers: 256,
+            storage_buffers: 128,
+            uniform_buffers_dynamic: 32,
+            storage_buffers_dynamic: 32,
+        }
+    }
+}
+
+impl DescriptorPoolSizes {
+    fn scaled(&self, factor: u32) -> Self {
+        Self {
+            max_sets: self.max_sets * factor,
+            combined_image_samplers: self.combined_image_samplers * factor,
+            uniform_buffers: self.uniform_buffers * factor,
+            storage_buffers: self.storage_buffers * factor,
+            uniform_buffers_dynamic: self.uniform_buffers_dynamic * factor,
+            storage_buffers_dynamic: self.storage_buffers_dynamic * factor,
+        }
+    }
+}
+
+// Sets allocated from the allocator, shown in VulkanRenderer::memory_report
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DescriptorUsage {
+    pub sets: u32,
+    pub peak_sets: u32,
+    pub pool_blocks: usize,
+}
+
+struct PoolBlock {
+    pool: vk::DescriptorPool,
+    sets: u32,
+}
+
+// Growable descriptor pool shared by everything that allocates sets at runtime (mesh textures,
+// skinned meshes), instead of a pool per call. When every block is out of memory for a request
+// a new block is created, and freed sets go back to the block they came from.
+pub struct DescriptorAllocator {
+    sizes: DescriptorPoolSizes,
+    blocks: Vec<PoolBlock>,
+    peak_sets: u32,
+}
+
+impl DescriptorAllocator {
+    pub fn new(sizes: DescriptorPoolSizes) -> Self {
+        Self { sizes, blocks: Vec::new(), peak_sets: 0 }
+    }
+
+    // Only affects blocks created after this
+    pub fn set_pool_sizes(&mut self, sizes: DescriptorPoolSizes) {
+        self.sizes = sizes;
+    }
+
+    // Sets for layouts and the pool they came from, which free needs back
+    pub fn allocate(
+        &mut self,
+        device: &ash::Device,
+        set_layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<(vk::DescriptorPool, Vec<vk::DescriptorSet>), FloError> {
+        // Newest blocks first, older ones are more likely full
+        for index in (0..self.blocks.len()).rev() {
+            match allocate_from(device, self.blocks[index].pool, set_layouts) {
+                Ok(sets) => return Ok(self.allocated(index, sets)),
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let factor = (set_layouts.len() as u32).div_ceil(self.sizes.max_sets.max(1)).max(1);
+        let pool = create_pool_block(device, &self.sizes.scaled(factor))?;
+        self.blocks.push(PoolBlock { pool, sets: 0 });
+        // A fresh block can only fail if the layouts use a descriptor type blocks don't hold
+        let sets = allocate_from(device, pool, set_layouts).map_err(|e| {
+            FloError::from(format!("Failed to allocate {} descriptor sets from a new pool block: {:?}", set_layouts.len(), e))
+        })?;
+        Ok(self.allocated(self.blocks.len() - 1, sets))
+    }
+
+    fn allocated(&mut self, block: usize, sets: Vec<vk::DescriptorSet>) -> (vk::DescriptorPool, Vec<vk::DescriptorSet>) {
+        self.blocks[block].sets += sets.len() as u32;
+        self.peak_sets = self.peak_sets.max(self.live_sets());
+        (self.blocks[block].pool, sets)
+    }
+
+    // Returns sets to their block. False when pool isn't one of this allocator's blocks, so
+    // callers can destroy pools they created themselves.
+    pub fn free(&mut self, device: &ash::Device, pool: vk::DescriptorPool, sets: &[vk::DescriptorSet]) -> bool {
+        let Some(block) = self.blocks.iter_mut().find(|block| block.pool == pool) else {
+            return false;
+        };
+        if !sets.is_empty() {
+            unsafe {
+                let _ = device.free_descriptor_sets(pool, sets);
+            }
+        }
+        block.sets = block.sets.saturating_sub(sets.len() as u32);
+        true
+    }
+
+    pub fn usage(&self) -> DescriptorUsage {
+        DescriptorUsage {
+            sets: self.live_sets(),
+            peak_sets: self.peak_sets,
+            pool_blocks: self.blocks.len(),
+        }
+    }
+
+    fn live_sets(&self) -> u32 {
+        self.blocks.iter().map(|block| block.sets).sum()
+    }
+
+    // Destroying a block frees every set still allocated from it
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for block in self.blocks.drain(..) {
+            unsafe {
+                leak_check::destroy(device, block.pool);
+            }
+        }
+    }
+}
+
+fn allocate_from(
+    device: &ash::Device,
+    pool: vk::DescriptorPool,
+    set_layouts: &[vk::DescriptorSetLayout],
+) -> Result<Vec<vk::DescriptorSet>, vk::Result> {
+    let alloc_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(pool)
+        .set_layouts(set_layouts);
+    unsafe { device.allocate_descriptor_sets(&alloc_info) }
+}
+
+// FREE_DESCRIPTOR_SET so single sets can go back to the block
+fn create_pool_block(device: &ash::Device, sizes: &DescriptorPoolSizes) -> Result<vk::DescriptorPool, FloError> {
+    let pool_sizes: Vec<vk::DescriptorPoolSize> = [
+        (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, sizes.combined_image_samplers),
+        (vk::DescriptorType::UNIFORM_BUFFER, sizes.uniform_buffers),
+        (vk::DescriptorType::STORAGE_BUFFER, sizes.storage_buffers),
+        (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, sizes.uniform_buffers_dynamic),
+        (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, sizes.storage_buffers_dynamic),
+    ]
+    .into_iter()
+    .filter(|&(_, count)| count > 0)
+    .map(|(ty, descriptor_count)| vk::DescriptorPoolSize { ty, descriptor_count })
+    .collect();
+    let pool_info = vk::DescriptorPoolCreateInfo::default()
+        .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+        .pool_sizes(&pool_sizes)
+        .max_sets(sizes.max_sets.max(1));
+    Ok(leak_check::created(device, unsafe { device.create_descriptor_pool(&pool_info, None)? }))
+}
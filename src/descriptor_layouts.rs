@@ -0,0 +1,83 @@
+use ash::vk;
+use crate::error::FloError;
+use crate::vulkan_common::create_descriptor_set_layout;
+use crate::leak_check;
+
+// Descriptor sets shared between pipelines, at fixed set indices. Vulkan keeps a bound set
+// valid across a pipeline switch only if both layouts match for every set up to it (and have
+// identical push constant ranges), so the shared sets come first and a pipeline's own sets
+// start at FIRST_PIPELINE_SET. Gaps are filled with empty layouts by PipelineBuilder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SharedSet {
+    // Uniform buffer, binding 0, vertex and fragment stages
+    Camera,
+    // Uniform buffer, binding 0, fragment stage
+    Lights,
+    // Combined image sampler, binding 0, fragment stage
+    ShadowMap,
+}
+
+impl SharedSet {
+    pub const ALL: [SharedSet; 3] = [SharedSet::Camera, SharedSet::Lights, SharedSet::ShadowMap];
+
+    pub const fn index(self) -> u32 {
+        match self {
+            SharedSet::Camera => 0,
+            SharedSet::Lights => 1,
+            SharedSet::ShadowMap => 2,
+        }
+    }
+}
+
+// Set index of a pipeline's first own set when it uses any shared set.
+// Devices only guarantee 4 bound sets, so this leaves one for the pipeline.
+pub const FIRST_PIPELINE_SET: u32 = SharedSet::ALL.len() as u32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SharedDescriptorLayout {
+    pub set: SharedSet,
+    pub layout: vk::DescriptorSetLayout,
+}
+
+// One layout per shared set, owned by the renderer (see VulkanRenderer::shared_descriptor_layout)
+pub struct SharedDescriptorLayouts {
+    layouts: [vk::DescriptorSetLayout; 3],
+}
+
+impl SharedDescriptorLayouts {
+    pub fn new(device: &ash::Device) -> Result<Self, FloError> {
+        let mut layouts = [vk::DescriptorSetLayout::null(); 3];
+        for set in SharedSet::ALL {
+            let (descriptor_type, stage_flags) = match set {
+                SharedSet::Camera => (
+                    vk::DescriptorType::UNIFORM_BUFFER,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                ),
+                SharedSet::Lights => (vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::FRAGMENT),
+                SharedSet::ShadowMap => (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT),
+            };
+            let binding = vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(1)
+                .stage_flags(stage_flags);
+            layouts[set.index() as usize] = create_descriptor_set_layout(device, &[binding])?;
+        }
+        Ok(Self { layouts })
+    }
+
+    pub fn get(&self, set: SharedSet) -> SharedDescriptorLayout {
+        SharedDescriptorLayout {
+            set,
+            layout: self.layouts[set.index() as usize],
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        for &layout in &self.layouts {
+            unsafe {
+                leak_check::destroy(device, layout);
+            }
+        }
+    }
+}
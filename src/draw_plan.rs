@@ -0,0 +1,456 @@
+use ash::vk;
+use bevy_math::{Mat4, Vec3};
+use crate::constants::MAX_OCCLUSION_QUERIES;
+use crate::mesh::MeshTopology;
+use crate::vulkan_common::{BlendMode, GEOMETRY_SUBPASS};
+use crate::vulkan_renderer_unified::MeshEntry;
+
+// Pipeline that draws the bounding boxes of hidden occlusion culled meshes
+pub const OCCLUSION_PROXY_PIPELINE: &str = "occlusion_proxy";
+
+// Pipelines that draw wireframe overlays (see set_mesh_wireframe_overlay) of meshes drawn per
+// transform, of instanced meshes with a position per instance and of ones with InstanceData
+pub const WIREFRAME_OVERLAY_PIPELINE: &str = "wireframe_overlay";
+pub const WIREFRAME_OVERLAY_INSTANCED_PIPELINE: &str = "wireframe_overlay_instanced";
+pub const WIREFRAME_OVERLAY_INSTANCE_DATA_PIPELINE: &str = "wireframe_overlay_instance_data";
+
+// The overlay pipeline matching how the mesh's instances are stored
+pub fn wireframe_overlay_pipeline(mesh: &MeshEntry) -> &'static str {
+    if !mesh.use_instancing {
+        WIREFRAME_OVERLAY_PIPELINE
+    } else if mesh.instance_data.is_some() {
+        WIREFRAME_OVERLAY_INSTANCE_DATA_PIPELINE
+    } else {
+        WIREFRAME_OVERLAY_INSTANCED_PIPELINE
+    }
+}
+
+// What multi mesh frames show instead of the shaded scene, see VulkanRenderer::set_debug_view
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DebugView {
+    #[default]
+    None,
+    // Number of fragments that passed the depth test at each pixel as a heatmap. Meshes still
+    // test and write depth in draw order, so front to back sorting shows up as less overdraw.
+    Overdraw,
+    // World space normals, mapped from -1..1 to 0..1
+    Normals,
+    // View space depth, black at near and white at far and beyond
+    Depth { near: f32, far: f32 },
+}
+
+// Pipelines that draw meshes in debug views, indexed like wireframe overlays: meshes drawn per
+// transform, instanced meshes with a position per instance and ones with InstanceData. Overdraw
+// adds up its fragments, the others are opaque.
+pub const DEBUG_OVERDRAW_PIPELINES: [&str; 3] = ["debug_overdraw", "debug_overdraw_instanced", "debug_overdraw_instance_data"];
+pub const DEBUG_VIEW_PIPELINES: [&str; 3] = ["debug_view", "debug_view_instanced", "debug_view_instance_data"];
+
+// The pipeline the mesh is drawn with in the debug view, None when it's off or for meshes the
+// debug pipelines can't draw (skinned, deinterleaved, strips and fans, instance matrices in a
+// storage buffer, lighting subpass), which are left out while it's on
+pub fn debug_view_pipeline(debug_view: DebugView, mesh: &MeshEntry) -> Option<&'static str> {
+    let pipelines = match debug_view {
+        DebugView::None => return None,
+        DebugView::Overdraw => DEBUG_OVERDRAW_PIPELINES,
+        DebugView::Normals | DebugView::Depth { .. } => DEBUG_VIEW_PIPELINES,
+    };
+    if mesh.is_skinned || mesh.vertex_streams.is_some() || mesh.topology != MeshTopology::TriangleList
+        || mesh.instance_matrices.is_some() || mesh.subpass != GEOMETRY_SUBPASS {
+        return None;
+    }
+    Some(if !mesh.use_instancing {
+        pipelines[0]
+    } else if mesh.instance_data.is_none() {
+        pipelines[1]
+    } else {
+        pipelines[2]
+    })
+}
+
+// The pipeline the mesh is drawn with, the double sided variant of its own when it has one
+pub fn mesh_pipeline_name(mesh: &MeshEntry) -> &str {
+    mesh.double_sided_pipeline.as_deref()
+        .or(mesh.pipeline_name.as_deref())
+        .unwrap_or("default")
+}
+
+// What planning needs to know about a mesh, without its buffers, so plans can be built and
+// checked without a device. The renderer fills one per mesh with MeshDrawInfo::new.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeshDrawInfo<'a> {
+    // See mesh_pipeline_name
+    pub pipeline_name: &'a str,
+    pub blend_mode: BlendMode,
+    pub descriptor_set: Option<vk::DescriptorSet>,
+    // Opaque meshes with a depth bias are drawn after the other opaque meshes, like decals
+    pub depth_bias: bool,
+    pub use_instancing: bool,
+    pub instance_count: u32,
+    // Transforms of a mesh drawn per transform, only the first is used for sorting
+    pub transform_count: usize,
+    pub first_transform: Option<Mat4>,
+    // See MeshEntry::local_bounds and MeshEntry::instance_bounds
+    pub local_bounds: Option<([f32; 3], [f32; 3])>,
+    pub instance_bounds: Option<([f32; 3], [f32; 3])>,
+    pub is_skinned: bool,
+    pub joint_offset: u32,
+    pub occlusion_culling: bool,
+    pub occlusion_visible: bool,
+    pub has_occlusion_proxy: bool,
+    // Pipeline of the mesh's wireframe overlay, None without one
+    pub wireframe_overlay: Option<&'static str>,
+    // Pipeline the mesh is drawn with in the debug view, see debug_view_pipeline
+    pub debug_pipeline: Option<&'static str>,
+}
+
+impl<'a> MeshDrawInfo<'a> {
+    pub fn new(
+        mesh: &'a MeshEntry,
+        blend_mode: BlendMode,
+        descriptor_set: Option<vk::DescriptorSet>,
+        debug_view: DebugView,
+    ) -> Self {
+        Self {
+            pipeline_name: mesh_pipeline_name(mesh),
+            blend_mode,
+            descriptor_set,
+            depth_bias: mesh.depth_bias.is_some(),
+            use_instancing: mesh.use_instancing,
+            instance_count: mesh.instance_count,
+            transform_count: mesh.transforms.len(),
+            first_transform: mesh.transforms.first().copied(),
+            local_bounds: mesh.local_bounds,
+            instance_bounds: mesh.instance_bounds,
+            is_skinned: mesh.is_skinned,
+            joint_offset: mesh.joint_offset,
+            occlusion_culling: mesh.occlusion_culling,
+            occlusion_visible: mesh.occlusion_visible,
+            has_occlusion_proxy: mesh.occlusion_proxy_buffer.is_some(),
+            wireframe_overlay: mesh.wireframe_overlay.map(|_| wireframe_overlay_pipeline(mesh)),
+            debug_pipeline: debug_view_pipeline(debug_view, mesh),
+        }
+    }
+}
+
+// One step of recording the scene's meshes. The plan is built without touching the device and
+// then executed against a command buffer, so the decisions in it can be checked without a GPU.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DrawCommand {
+    // Binds the named pipeline, names that don't exist fall back to the default pipeline
+    BindPipeline(String),
+    // Skinned sets carry the mesh's joint_offset for both dynamic joint bindings
+    BindDescriptorSet(vk::DescriptorSet, Option<u32>),
+    // Draws a hidden occlusion culled mesh's bounding box once per transform under a query
+    DrawOcclusionProxy { mesh: usize, query: u32 },
+    // One draw of all of an instanced mesh's instances, or of the ones that survived GPU culling
+    DrawInstanced { mesh: usize },
+    // One draw per transform, under a query for occlusion culled meshes while queries last
+    DrawTransforms { mesh: usize, query: Option<u32> },
+    // Draws the mesh's edges in its overlay color right after its solid draw, with all of its
+    // instances or transforms
+    DrawWireframeOverlay { mesh: usize },
+}
+
+// Orders meshes for drawing: opaque meshes first, then opaque decals (meshes with a depth bias,
+// see mark_as_decal) so the surfaces they lie on are already drawn, then order independent
+// blending (additive, multiply), then alpha blended meshes from back to front by
+// transparency_sort_depth. Within
+// each group meshes are grouped by pipeline, then by descriptor set, so meshes sharing a
+// material are drawn back to back without rebinding. Pipelines keep the order they first
+// appear in. view is the camera's world to view matrix.
+pub fn plan_draw_order(meshes: &[MeshDrawInfo], view: Mat4) -> Vec<usize> {
+    let mut pipeline_ranks: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+    let mut draw_order: Vec<(u8, u32, usize, u64, usize)> = meshes.iter().enumerate()
+        .map(|(mesh_idx, mesh)| {
+            let next_rank = pipeline_ranks.len();
+            let rank = *pipeline_ranks.entry(mesh.pipeline_name).or_insert(next_rank);
+            let descriptor_set = mesh.descriptor_set.map_or(0, vk::Handle::as_raw);
+
+            let blend_mode = mesh.blend_mode;
+            let (blend_group, depth_key) = if blend_mode.needs_sorting() {
+                // Farthest first. Flipping the sign bit (or every bit of negative depths) makes
+                // the float's bits sort like the float, then inverting them reverses that.
+                let bits = transparency_sort_depth(mesh, view).to_bits();
+                let ascending = if bits >> 31 == 1 { !bits } else { bits | 1 << 31 };
+                (3, !ascending)
+            } else if blend_mode == BlendMode::Opaque {
+                (mesh.depth_bias as u8, 0)
+            } else {
+                (2, 0)
+            };
+            (blend_group, depth_key, rank, descriptor_set, mesh_idx)
+        })
+        .collect();
+    draw_order.sort_unstable();
+
+    draw_order.into_iter().map(|(_, _, _, _, mesh_idx)| mesh_idx).collect()
+}
+
+// Depth along the view direction an alpha blended mesh is sorted by, larger is farther. It's
+// the depth of the center of the mesh's bounding sphere under its first transform (of all
+// instances for instanced meshes), which unlike the distance to the camera keeps parallel
+// layers like stacked quads or windows in order however far off center they are.
+// A sphere containing the camera sorts by its far side instead, so a large mesh around the
+// viewer (a glass dome, a water volume, a quad the camera is right next to) is drawn before
+// the smaller meshes in front of it even when its center is closer. Meshes that intersect each
+// other still can't be ordered per mesh.
+pub fn transparency_sort_depth(mesh: &MeshDrawInfo, view: Mat4) -> f32 {
+    let sphere = |bounds: Option<([f32; 3], [f32; 3])>| {
+        bounds.map_or((Vec3::ZERO, 0.0), |(min, max)| {
+            let (min, max) = (Vec3::from(min), Vec3::from(max));
+            ((min + max) * 0.5, (max - min).length() * 0.5)
+        })
+    };
+    let (local_center, local_radius) = sphere(mesh.local_bounds);
+    let (center, radius) = if mesh.use_instancing {
+        let (instance_center, instance_radius) = sphere(mesh.instance_bounds);
+        (local_center + instance_center, local_radius + instance_radius)
+    } else if let Some(transform) = mesh.first_transform {
+        let scale = transform.x_axis.truncate().length()
+            .max(transform.y_axis.truncate().length())
+            .max(transform.z_axis.truncate().length());
+        (transform.transform_point3(local_center), local_radius * scale)
+    } else {
+        (local_center, local_radius)
+    };
+
+    let view_center = view.transform_point3(center);
+    let depth = -view_center.z;
+    if view_center.length() < radius {
+        depth + radius
+    } else {
+        depth
+    }
+}
+
+// Plans the draws for the meshes in draw_order. Occlusion queries are numbered from
+// first_query, and hidden occlusion culled meshes are drawn in full when has_proxy_pipeline
+// is false. Binds are skipped when the previous mesh already made them. In a debug view meshes
+// are drawn with its pipelines, without descriptor sets or wireframe overlays.
+pub fn plan_mesh_draws(
+    meshes: &[MeshDrawInfo],
+    draw_order: &[usize],
+    first_query: u32,
+    has_proxy_pipeline: bool,
+    debug_view: DebugView,
+) -> Vec<DrawCommand> {
+    let mut plan = Vec::new();
+    let mut next_query = first_query;
+    let mut current_pipeline_name: Option<&str> = None;
+    let mut current_descriptor_set: Option<(vk::DescriptorSet, Option<u32>)> = None;
+
+    for &mesh_idx in draw_order {
+        let mesh = &meshes[mesh_idx];
+
+        // Skip non-instanced meshes with no transforms and instanced meshes with no instances
+        if !mesh.use_instancing && mesh.transform_count == 0 {
+            continue;
+        }
+        if mesh.use_instancing && mesh.instance_count == 0 {
+            continue;
+        }
+        let debug_pipeline = mesh.debug_pipeline.filter(|_| debug_view != DebugView::None);
+        if debug_view != DebugView::None && debug_pipeline.is_none() {
+            continue;
+        }
+
+        // Occlusion culled mesh that was hidden last time: only draw its bounding box under a query
+        if mesh.occlusion_culling && !mesh.occlusion_visible && !mesh.use_instancing
+            && next_query < MAX_OCCLUSION_QUERIES
+            && mesh.has_occlusion_proxy && has_proxy_pipeline {
+            plan.push(DrawCommand::BindPipeline(OCCLUSION_PROXY_PIPELINE.to_string()));
+            current_pipeline_name = Some(OCCLUSION_PROXY_PIPELINE);
+            current_descriptor_set = None;
+            plan.push(DrawCommand::DrawOcclusionProxy { mesh: mesh_idx, query: next_query });
+            next_query += 1;
+            continue;
+        }
+
+        let pipeline_name = match debug_pipeline {
+            Some(name) => name,
+            None => mesh.pipeline_name,
+        };
+        if current_pipeline_name != Some(pipeline_name) {
+            plan.push(DrawCommand::BindPipeline(pipeline_name.to_string()));
+            current_pipeline_name = Some(pipeline_name);
+            // The new layout may not be compatible with the sets bound so far
+            current_descriptor_set = None;
+        }
+
+        if let Some(set) = mesh.descriptor_set.filter(|_| debug_pipeline.is_none()) {
+            // Meshes sharing the joint arena's set still rebind it with their own offset
+            let binding = (set, mesh.is_skinned.then_some(mesh.joint_offset));
+            if current_descriptor_set != Some(binding) {
+                plan.push(DrawCommand::BindDescriptorSet(binding.0, binding.1));
+                current_descriptor_set = Some(binding);
+            }
+        }
+
+        if mesh.use_instancing {
+            plan.push(DrawCommand::DrawInstanced { mesh: mesh_idx });
+        } else {
+            // Visible occlusion culled meshes are drawn under a query to notice when they get hidden
+            let query = if mesh.occlusion_culling && next_query < MAX_OCCLUSION_QUERIES {
+                next_query += 1;
+                Some(next_query - 1)
+            } else {
+                None
+            };
+            plan.push(DrawCommand::DrawTransforms { mesh: mesh_idx, query });
+        }
+
+        if let Some(overlay_pipeline) = mesh.wireframe_overlay.filter(|_| debug_pipeline.is_none()) {
+            plan.push(DrawCommand::BindPipeline(overlay_pipeline.to_string()));
+            current_pipeline_name = Some(overlay_pipeline);
+            current_descriptor_set = None;
+            plan.push(DrawCommand::DrawWireframeOverlay { mesh: mesh_idx });
+        }
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    // A unit cube drawn once at z, in front of a camera at the origin looking down -z
+    fn mesh_at(pipeline_name: &'static str, z: f32) -> MeshDrawInfo<'static> {
+        MeshDrawInfo {
+            pipeline_name,
+            transform_count: 1,
+            first_transform: Some(Mat4::from_translation(Vec3::new(0.0, 0.0, z))),
+            local_bounds: Some(([-0.5; 3], [0.5; 3])),
+            ..Default::default()
+        }
+    }
+
+    fn set(raw: u64) -> Option<vk::DescriptorSet> {
+        Some(vk::DescriptorSet::from_raw(raw))
+    }
+
+    #[test]
+    fn opaque_then_decals_then_blended_back_to_front() {
+        let meshes = [
+            MeshDrawInfo { blend_mode: BlendMode::AlphaBlend, ..mesh_at("glass", -2.0) },
+            MeshDrawInfo { depth_bias: true, ..mesh_at("decal", -5.0) },
+            MeshDrawInfo { blend_mode: BlendMode::Additive, ..mesh_at("glow", -5.0) },
+            MeshDrawInfo { blend_mode: BlendMode::AlphaBlend, ..mesh_at("glass", -10.0) },
+            mesh_at("default", -5.0),
+        ];
+        assert_eq!(plan_draw_order(&meshes, Mat4::IDENTITY), vec![4, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn opaque_meshes_group_by_pipeline_then_descriptor_set() {
+        let meshes = [
+            MeshDrawInfo { descriptor_set: set(2), ..mesh_at("textured", -1.0) },
+            mesh_at("default", -1.0),
+            MeshDrawInfo { descriptor_set: set(1), ..mesh_at("textured", -1.0) },
+            mesh_at("default", -1.0),
+            MeshDrawInfo { descriptor_set: set(2), ..mesh_at("textured", -1.0) },
+        ];
+        // Pipelines keep the order they first appear in
+        assert_eq!(plan_draw_order(&meshes, Mat4::IDENTITY), vec![2, 0, 4, 1, 3]);
+    }
+
+    #[test]
+    fn sort_depth_of_mesh_around_camera_is_its_far_side() {
+        let around = MeshDrawInfo { local_bounds: Some(([-10.0; 3], [10.0; 3])), ..mesh_at("glass", 0.0) };
+        let radius = Vec3::splat(10.0).length();
+        assert!((transparency_sort_depth(&around, Mat4::IDENTITY) - radius).abs() < 1e-4);
+        assert!((transparency_sort_depth(&mesh_at("glass", -3.0), Mat4::IDENTITY) - 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn meshes_with_nothing_to_draw_are_skipped() {
+        let meshes = [
+            MeshDrawInfo { transform_count: 0, ..mesh_at("default", -1.0) },
+            MeshDrawInfo { use_instancing: true, instance_count: 0, ..mesh_at("default", -1.0) },
+            MeshDrawInfo { use_instancing: true, instance_count: 3, transform_count: 0, ..mesh_at("default", -1.0) },
+        ];
+        let plan = plan_mesh_draws(&meshes, &[0, 1, 2], 0, true, DebugView::None);
+        assert_eq!(plan, vec![
+            DrawCommand::BindPipeline("default".to_string()),
+            DrawCommand::DrawInstanced { mesh: 2 },
+        ]);
+    }
+
+    #[test]
+    fn binds_are_only_recorded_when_they_change() {
+        let meshes = [
+            MeshDrawInfo { descriptor_set: set(1), ..mesh_at("textured", -1.0) },
+            MeshDrawInfo { descriptor_set: set(1), ..mesh_at("textured", -1.0) },
+            MeshDrawInfo { descriptor_set: set(2), ..mesh_at("textured", -1.0) },
+            // Skinned meshes sharing a set rebind it with their own joint offset
+            MeshDrawInfo { descriptor_set: set(3), is_skinned: true, joint_offset: 0, ..mesh_at("skinned", -1.0) },
+            MeshDrawInfo { descriptor_set: set(3), is_skinned: true, joint_offset: 256, ..mesh_at("skinned", -1.0) },
+        ];
+        let plan = plan_mesh_draws(&meshes, &[0, 1, 2, 3, 4], 0, true, DebugView::None);
+        assert_eq!(plan, vec![
+            DrawCommand::BindPipeline("textured".to_string()),
+            DrawCommand::BindDescriptorSet(vk::DescriptorSet::from_raw(1), None),
+            DrawCommand::DrawTransforms { mesh: 0, query: None },
+            DrawCommand::DrawTransforms { mesh: 1, query: None },
+            DrawCommand::BindDescriptorSet(vk::DescriptorSet::from_raw(2), None),
+            DrawCommand::DrawTransforms { mesh: 2, query: None },
+            DrawCommand::BindPipeline("skinned".to_string()),
+            DrawCommand::BindDescriptorSet(vk::DescriptorSet::from_raw(3), Some(0)),
+            DrawCommand::DrawTransforms { mesh: 3, query: None },
+            DrawCommand::BindDescriptorSet(vk::DescriptorSet::from_raw(3), Some(256)),
+            DrawCommand::DrawTransforms { mesh: 4, query: None },
+        ]);
+    }
+
+    #[test]
+    fn occlusion_culled_meshes_draw_proxies_while_hidden() {
+        let hidden = MeshDrawInfo { occlusion_culling: true, has_occlusion_proxy: true, ..mesh_at("default", -1.0) };
+        let visible = MeshDrawInfo { occlusion_visible: true, ..hidden.clone() };
+        let meshes = [hidden.clone(), visible, hidden];
+
+        let plan = plan_mesh_draws(&meshes, &[0, 1, 2], 5, true, DebugView::None);
+        assert_eq!(plan, vec![
+            DrawCommand::BindPipeline(OCCLUSION_PROXY_PIPELINE.to_string()),
+            DrawCommand::DrawOcclusionProxy { mesh: 0, query: 5 },
+            DrawCommand::BindPipeline("default".to_string()),
+            DrawCommand::DrawTransforms { mesh: 1, query: Some(6) },
+            DrawCommand::BindPipeline(OCCLUSION_PROXY_PIPELINE.to_string()),
+            DrawCommand::DrawOcclusionProxy { mesh: 2, query: 7 },
+        ]);
+
+        // Without the proxy pipeline hidden meshes are drawn in full
+        let plan = plan_mesh_draws(&meshes, &[0], 0, false, DebugView::None);
+        assert_eq!(plan[1], DrawCommand::DrawTransforms { mesh: 0, query: Some(0) });
+    }
+
+    #[test]
+    fn wireframe_overlays_follow_their_mesh_outside_debug_views() {
+        let meshes = [
+            MeshDrawInfo {
+                descriptor_set: set(1),
+                wireframe_overlay: Some(WIREFRAME_OVERLAY_PIPELINE),
+                debug_pipeline: Some(DEBUG_VIEW_PIPELINES[0]),
+                ..mesh_at("textured", -1.0)
+            },
+            mesh_at("textured", -1.0),
+        ];
+        let plan = plan_mesh_draws(&meshes, &[0, 1], 0, true, DebugView::None);
+        assert_eq!(plan, vec![
+            DrawCommand::BindPipeline("textured".to_string()),
+            DrawCommand::BindDescriptorSet(vk::DescriptorSet::from_raw(1), None),
+            DrawCommand::DrawTransforms { mesh: 0, query: None },
+            DrawCommand::BindPipeline(WIREFRAME_OVERLAY_PIPELINE.to_string()),
+            DrawCommand::DrawWireframeOverlay { mesh: 0 },
+            DrawCommand::BindPipeline("textured".to_string()),
+            DrawCommand::DrawTransforms { mesh: 1, query: None },
+        ]);
+
+        // The debug view draws the mesh it can with its own pipeline and leaves the other out
+        let plan = plan_mesh_draws(&meshes, &[0, 1], 0, true, DebugView::Normals);
+        assert_eq!(plan, vec![
+            DrawCommand::BindPipeline(DEBUG_VIEW_PIPELINES[0].to_string()),
+            DrawCommand::DrawTransforms { mesh: 0, query: None },
+        ]);
+    }
+}
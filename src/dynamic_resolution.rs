@@ -0,0 +1,56 @@
+use ash::vk;
+
+// Range VulkanRenderer::set_resolution_scale clamps to
+pub const MIN_RESOLUTION_SCALE: f32 = 0.5;
+pub const MAX_RESOLUTION_SCALE: f32 = 1.0;
+
+// Frames between automatic adjustments. GPU times trail the rendered frame by
+// MAX_FRAMES_IN_FLIGHT, so this leaves time to measure the new scale before changing it again.
+pub const ADJUST_INTERVAL_FRAMES: u32 = 30;
+
+// Scales change in steps of this, so jitter in the frame time doesn't recreate the targets
+const SCALE_STEP: f32 = 0.05;
+
+// Automatic resolution scaling, see VulkanRenderer::set_dynamic_resolution
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DynamicResolution {
+    // GPU frame time to stay under
+    pub target_frame_ms: f32,
+    // Bounds within MIN_RESOLUTION_SCALE..=MAX_RESOLUTION_SCALE
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+impl Default for DynamicResolution {
+    // 60 fps with some headroom
+    fn default() -> Self {
+        Self {
+            target_frame_ms: 14.0,
+            min_scale: MIN_RESOLUTION_SCALE,
+            max_scale: MAX_RESOLUTION_SCALE,
+        }
+    }
+}
+
+impl DynamicResolution {
+    // Scale expected to bring the GPU frame time just under the target, taking the time to grow
+    // with the pixel count, the square of the scale. Frames between 80% of the target and the
+    // target keep the current scale.
+    pub fn next_scale(&self, scale: f32, gpu_frame_ms: f32) -> f32 {
+        if gpu_frame_ms <= self.target_frame_ms && gpu_frame_ms >= self.target_frame_ms * 0.8 {
+            return scale;
+        }
+        let ideal = scale * (self.target_frame_ms / gpu_frame_ms.max(0.001)).sqrt();
+        let min_scale = self.min_scale.max(MIN_RESOLUTION_SCALE);
+        let max_scale = self.max_scale.clamp(min_scale, MAX_RESOLUTION_SCALE);
+        ((ideal / SCALE_STEP).floor() * SCALE_STEP).clamp(min_scale, max_scale)
+    }
+}
+
+// Size of the scene targets for a swapchain extent and scale, at least one pixel
+pub fn scaled_extent(extent: vk::Extent2D, scale: f32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: ((extent.width as f32 * scale).round() as u32).max(1),
+        height: ((extent.height as f32 * scale).round() as u32).max(1),
+    }
+}
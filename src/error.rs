@@ -0,0 +1,112 @@
+use ash::vk;
+use std::fmt;
+
+// Crate wide error type. Vulkan and IO errors keep their original value so callers can
+// match on them (e.g. vk::Result::ERROR_OUT_OF_DATE_KHR) instead of parsing messages.
+#[derive(Debug)]
+pub enum FloError {
+    Vulkan(vk::Result),
+    Io(std::io::Error),
+    ShaderCompile(String),
+    UnsupportedDevice(String),
+    InvalidMeshIndex(usize),
+    // The Vulkan library couldn't be loaded, e.g. no driver installed
+    NoVulkanLoader(String),
+    // Vulkan loaded but reports no devices (or no compatible driver)
+    NoPhysicalDevices,
+    // No device has queues for both graphics and presenting to the window
+    NoSuitableGpu,
+    // ERROR_SURFACE_LOST_KHR, the window's surface has to be recreated, see VulkanRenderer::recover
+    SurfaceLost,
+    // ERROR_DEVICE_LOST, e.g. after a driver update or GPU reset. The renderer has to be recreated.
+    DeviceLost,
+    // Anything else, e.g. invalid arguments or errors from image decoding
+    Other(String),
+}
+
+impl fmt::Display for FloError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FloError::Vulkan(result) => write!(f, "Vulkan error: {:?}", result),
+            FloError::Io(e) => write!(f, "IO error: {}", e),
+            FloError::ShaderCompile(message) => write!(f, "Invalid shader: {}", message),
+            FloError::UnsupportedDevice(message) => write!(f, "Unsupported device: {}", message),
+            FloError::InvalidMeshIndex(index) => write!(f, "Mesh index {} out of bounds", index),
+            FloError::NoVulkanLoader(message) => write!(f, "Vulkan is not installed: {}", message),
+            FloError::NoPhysicalDevices => write!(f, "No Vulkan capable GPU found"),
+            FloError::NoSuitableGpu => write!(f, "Failed to find suitable GPU"),
+            FloError::SurfaceLost => write!(f, "Window surface lost"),
+            FloError::DeviceLost => write!(f, "GPU device lost"),
+            FloError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for FloError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FloError::Vulkan(result) => Some(result),
+            FloError::Io(e) => Some(e),
+            FloError::SurfaceLost => Some(&vk::Result::ERROR_SURFACE_LOST_KHR),
+            FloError::DeviceLost => Some(&vk::Result::ERROR_DEVICE_LOST),
+            _ => None,
+        }
+    }
+}
+
+// Losses get their own variants so apps can match on them without knowing the raw results
+impl From<vk::Result> for FloError {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_SURFACE_LOST_KHR => FloError::SurfaceLost,
+            vk::Result::ERROR_DEVICE_LOST => FloError::DeviceLost,
+            result => FloError::Vulkan(result),
+        }
+    }
+}
+
+impl FloError {
+    pub fn is_lost(&self) -> bool {
+        matches!(self, FloError::SurfaceLost | FloError::DeviceLost)
+    }
+}
+
+impl From<std::io::Error> for FloError {
+    fn from(e: std::io::Error) -> Self {
+        FloError::Io(e)
+    }
+}
+
+impl From<std::ffi::NulError> for FloError {
+    fn from(e: std::ffi::NulError) -> Self {
+        FloError::Other(e.to_string())
+    }
+}
+
+impl From<image::ImageError> for FloError {
+    fn from(e: image::ImageError) -> Self {
+        FloError::Other(e.to_string())
+    }
+}
+
+// Errors from the modules that still return Box<dyn Error>
+impl From<Box<dyn std::error::Error>> for FloError {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        match e.downcast::<FloError>() {
+            Ok(e) => *e,
+            Err(e) => FloError::Other(e.to_string()),
+        }
+    }
+}
+
+impl From<String> for FloError {
+    fn from(message: String) -> Self {
+        FloError::Other(message)
+    }
+}
+
+impl From<&str> for FloError {
+    fn from(message: &str) -> Self {
+        FloError::Other(message.to_string())
+    }
+}
@@ -17,7 +17,7 @@ impl FpsLogger {
         self.fps_frame_count += 1;
 
         if self.frame_count == 1 {
-            println!("Starting render loop...");
+            info!("Starting render loop");
         }
 
         let current_time = time.elapsed_secs();
@@ -27,7 +27,7 @@ impl FpsLogger {
             let fps = self.fps_frame_count as f32 / time_since_last_fps;
             let frame_time_ms = time_since_last_fps * 1000.0 / self.fps_frame_count as f32;
 
-            println!("FPS: {:.1} | Frame Time: {:.2}ms", fps, frame_time_ms);
+            info!("FPS: {:.1} | Frame Time: {:.2}ms", fps, frame_time_ms);
 
             self.last_fps_time = current_time;
             self.fps_frame_count = 0;
@@ -0,0 +1,75 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// thread::sleep wakes up late by up to the scheduler's tick, so the last part of the wait is
+// spent spinning instead
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+// Weight of the newest frame in the smoothed frame time
+const SMOOTHING: f32 = 0.1;
+
+// Caps the frame rate regardless of the present mode, see VulkanRenderer::set_target_fps
+#[derive(Default)]
+pub struct FramePacer {
+    target_fps: Option<u32>,
+    // When the next frame may end, None until the first paced frame
+    deadline: Option<Instant>,
+    last_frame_end: Option<Instant>,
+    frame_ms: Option<f32>,
+}
+
+// Target and achieved frame times, see VulkanRenderer::frame_pacing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FramePacing {
+    // None when uncapped
+    pub target_frame_ms: Option<f32>,
+    // Smoothed time between the ends of the last frames, None before the second frame
+    pub achieved_frame_ms: Option<f32>,
+}
+
+impl FramePacer {
+    // None or Some(0) runs uncapped
+    pub fn set_target_fps(&mut self, target_fps: Option<u32>) {
+        self.target_fps = target_fps.filter(|&fps| fps > 0);
+        self.deadline = None;
+    }
+
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_fps
+    }
+
+    pub fn pacing(&self) -> FramePacing {
+        FramePacing {
+            target_frame_ms: self.target_fps.map(|fps| 1000.0 / fps as f32),
+            achieved_frame_ms: self.frame_ms,
+        }
+    }
+
+    // Blocks until a frame interval has passed since the previous call's deadline. Deadlines
+    // advance by whole intervals so oversleeping one frame doesn't slow the average, but a
+    // frame that ran over starts from now instead of rushing the next ones to catch up.
+    pub fn wait(&mut self) {
+        if let Some(fps) = self.target_fps {
+            let interval = Duration::from_secs_f64(1.0 / fps as f64);
+            let now = Instant::now();
+            let deadline = match self.deadline {
+                Some(deadline) if deadline > now => deadline,
+                _ => now,
+            };
+            if let Some(sleep) = deadline.checked_duration_since(now + SPIN_MARGIN) {
+                thread::sleep(sleep);
+            }
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+            self.deadline = Some(deadline + interval);
+        }
+
+        let end = Instant::now();
+        if let Some(last) = self.last_frame_end {
+            let ms = end.duration_since(last).as_secs_f32() * 1000.0;
+            self.frame_ms = Some(self.frame_ms.map_or(ms, |smoothed| smoothed + (ms - smoothed) * SMOOTHING));
+        }
+        self.last_frame_end = Some(end);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::constants::MAX_FRAMES_IN_FLIGHT;
+use crate::error::FloError;
+use crate::vulkan_renderer_unified::VulkanRenderer;
+
+// One copy of a user resource per frame in flight, e.g. a uniform buffer a pre draw hook or
+// record_parallel binds. Each frame writes its own copy, so the CPU never changes data a frame
+// still on the GPU reads. The pool only hands the copies out, destroying them is up to the owner
+// (see into_inner).
+pub struct FramePool<T> {
+    copies: Vec<T>,
+    // Slot current last returned
+    latest: usize,
+}
+
+impl<T> FramePool<T> {
+    // Calls create once per frame in flight slot, 0..MAX_FRAMES_IN_FLIGHT
+    pub fn new(create: impl FnMut(usize) -> T) -> Self {
+        Self { copies: (0..MAX_FRAMES_IN_FLIGHT).map(create).collect(), latest: 0 }
+    }
+    
+    // Like new for fallible resources. Copies created before the failure are dropped, so ones
+    // that need destroying should clean up after themselves in create.
+    pub fn try_new<E>(create: impl FnMut(usize) -> Result<T, E>) -> Result<Self, E> {
+        let copies = (0..MAX_FRAMES_IN_FLIGHT).map(create).collect::<Result<_, _>>()?;
+        Ok(Self { copies, latest: 0 })
+    }
+    
+    // The copy the next rendered frame uses, once the GPU is done with it. Call between frames,
+    // before render_frame_*, see VulkanRenderer::wait_for_current_frame.
+    pub fn current(&mut self, renderer: &VulkanRenderer) -> Result<&mut T, FloError> {
+        let frame = renderer.wait_for_current_frame()?;
+        Ok(self.for_frame(frame))
+    }
+    
+    // Like current for a frame slot the caller already waited on. Frames past the last slot wrap
+    // around, so a running frame count picks the same copy as its slot.
+    pub fn for_frame(&mut self, frame: usize) -> &mut T {
+        self.latest = frame % self.copies.len();
+        &mut self.copies[self.latest]
+    }
+    
+    // The copy current returned last, for hooks recording the frame it was written for. Hooks
+    // run while the renderer has the frame, so they read it without waiting.
+    pub fn latest(&self) -> &T {
+        &self.copies[self.latest]
+    }
+    
+    // Copy of a frame slot without waiting, e.g. to fill all of them up front
+    pub fn get(&self, frame: usize) -> Option<&T> {
+        self.copies.get(frame)
+    }
+    
+    pub fn get_mut(&mut self, frame: usize) -> Option<&mut T> {
+        self.copies.get_mut(frame)
+    }
+    
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.copies.iter()
+    }
+    
+    // All copies, e.g. to destroy them after VulkanRenderer::wait_idle
+    pub fn into_inner(self) -> Vec<T> {
+        self.copies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn every_frame_in_flight_gets_its_own_copy() {
+        let mut pool = FramePool::new(|frame| frame * 10);
+        assert_eq!(pool.iter().copied().collect::<Vec<_>>(), (0..MAX_FRAMES_IN_FLIGHT).map(|frame| frame * 10).collect::<Vec<_>>());
+        assert_eq!(pool.get(MAX_FRAMES_IN_FLIGHT), None);
+        
+        *pool.for_frame(1) += 1;
+        assert_eq!(*pool.latest(), 11);
+        assert_eq!(pool.get(1), Some(&11));
+        // The other slots are untouched
+        assert_eq!(pool.get(0), Some(&0));
+    }
+    
+    #[test]
+    fn frame_counts_wrap_around_the_slots() {
+        let mut pool = FramePool::new(|_| Vec::new());
+        for frame in 0..3 * MAX_FRAMES_IN_FLIGHT {
+            pool.for_frame(frame).push(frame);
+            assert_eq!(pool.latest().last(), Some(&frame));
+        }
+        for (slot, frames) in pool.into_inner().into_iter().enumerate() {
+            assert_eq!(frames, vec![slot, slot + MAX_FRAMES_IN_FLIGHT, slot + 2 * MAX_FRAMES_IN_FLIGHT]);
+        }
+    }
+    
+    #[test]
+    fn try_new_stops_at_the_first_failure() {
+        let mut created = Vec::new();
+        let pool = FramePool::try_new(|frame| {
+            created.push(frame);
+            if frame == 1 { Err("out of memory") } else { Ok(frame) }
+        });
+        assert_eq!(pool.err(), Some("out of memory"));
+        assert_eq!(created, vec![0, 1]);
+        
+        let pool = FramePool::try_new(Ok::<_, ()>).unwrap();
+        assert_eq!(pool.into_inner(), (0..MAX_FRAMES_IN_FLIGHT).collect::<Vec<_>>());
+    }
+}
@@ -1,6 +1,68 @@
 use crate::{mesh::MeshData, texture::TextureData, mesh::Vertex};
+use crate::animation::{AnimationClip, Interpolation, JointChannel, Keyframes, Skeleton};
+use crate::skinned_mesh::{MorphTarget, MorphTargets};
+use crate::vulkan_common::{BlendMode, MaterialMode};
+use bevy_math::{Mat3, Mat4, Quat, Vec3};
+use tracing::{debug, info, warn};
+use bevy_transform::components::Transform;
 use gltf;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+// Material description shared by the mesh loaders (glTF and OBJ)
+#[derive(Clone, Debug)]
+pub struct MaterialInfo {
+    pub name: Option<String>,
+    pub base_color: [f32; 4],
+    pub base_color_texture: Option<PathBuf>,
+    // glTF doubleSided, meshes loaded with it are drawn without culling
+    pub double_sided: bool,
+    pub alpha_mode: AlphaMode,
+    // Threshold for AlphaMode::Mask, glTF's default is 0.5
+    pub alpha_cutoff: f32,
+}
+
+impl Default for MaterialInfo {
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            double_sided: false,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+        }
+    }
+}
+
+impl MaterialInfo {
+    // Mask materials are cutouts, see VulkanRenderer::set_mesh_material
+    pub fn material_mode(&self) -> MaterialMode {
+        match self.alpha_mode {
+            AlphaMode::Mask => MaterialMode::Cutout { threshold: self.alpha_cutoff },
+            AlphaMode::Opaque | AlphaMode::Blend => MaterialMode::Opaque,
+        }
+    }
+
+    // Blend mode of the pipeline the material's meshes should be drawn with
+    pub fn blend_mode(&self) -> BlendMode {
+        match self.alpha_mode {
+            AlphaMode::Blend => BlendMode::AlphaBlend,
+            AlphaMode::Opaque | AlphaMode::Mask => BlendMode::Opaque,
+        }
+    }
+}
+
+// glTF alphaMode: how a material's base color alpha is used
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    // Alpha is ignored
+    #[default]
+    Opaque,
+    // Fragments below alpha_cutoff are discarded, the rest are opaque
+    Mask,
+    // Alpha blended over what's behind
+    Blend,
+}
 
 pub struct GltfData {
     pub mesh_data: MeshData,
@@ -25,14 +87,14 @@ impl GltfData {
     
     fn extract_texture(images: &[gltf::image::Data]) -> Option<TextureData> {
         if images.is_empty() {
-            println!("No textures found in GLB file, using default color");
+            debug!("No textures found in GLB file, using default color");
             return None;
         }
         
-        println!("Found {} textures in GLB file", images.len());
+        debug!("Found {} textures in GLB file", images.len());
         
         let image = images.first()?;
-        println!("Using texture with dimensions {}x{}, format: {:?}", 
+        debug!("Using texture with dimensions {}x{}, format: {:?}", 
                  image.width, image.height, image.format);
         
         let rgba_pixels = match image.format {
@@ -48,7 +110,7 @@ impl GltfData {
                 rgba
             },
             _ => {
-                println!("Unsupported texture format, using placeholder");
+                warn!("Unsupported texture format, using placeholder");
                 return Some(TextureData::placeholder());
             }
         };
@@ -60,69 +122,487 @@ impl GltfData {
         let mut combined_vertices = Vec::new();
         let mut combined_indices = Vec::new();
         let mut vertex_offset = 0u32;
+        // Primitives are combined into one mesh, which is drawn double sided if any of them is
+        let mut double_sided = false;
         
         for mesh in document.meshes() {
-            println!("Processing mesh: {:?}", mesh.name());
+            debug!("Processing mesh: {:?}", mesh.name());
             
             for primitive in mesh.primitives() {
-                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-                
-                // Get material color if available
-                let material = primitive.material();
-                let material_color = material.pbr_metallic_roughness().base_color_factor();
-                
-                let positions: Vec<[f32; 3]> = reader
-                    .read_positions()
-                    .ok_or("Mesh should have positions")?
-                    .collect();
-                
-                let normals: Vec<[f32; 3]> = reader
-                    .read_normals()
-                    .map(|iter| iter.collect())
-                    .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; positions.len()]);
-                
-                let uvs: Vec<[f32; 2]> = reader
-                    .read_tex_coords(0)
-                    .map(|iter| iter.into_f32().collect())
-                    .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
-                
-                // Check if vertex colors exist, otherwise use material color
-                let colors: Vec<[f32; 4]> = reader
-                    .read_colors(0)
-                    .map(|iter| iter.into_rgba_f32().collect())
-                    .unwrap_or_else(|| vec![material_color; positions.len()]);
-                
-                for i in 0..positions.len() {
-                    combined_vertices.push(Vertex::with_color(
-                        positions[i],
-                        normals[i],
-                        uvs[i],
-                        colors[i],
-                    ));
-                }
-                
-                if let Some(indices_reader) = reader.read_indices() {
-                    let indices: Vec<u32> = indices_reader.into_u32().collect();
-                    for triangle in indices.chunks(3) {
-                        if triangle.len() == 3 {
-                            combined_indices.push(triangle[0] + vertex_offset);
-                            combined_indices.push(triangle[2] + vertex_offset);
-                            combined_indices.push(triangle[1] + vertex_offset);
-                        }
-                    }
-                }
-                
-                vertex_offset += positions.len() as u32;
+                double_sided |= primitive.material().double_sided();
+                let (vertices, indices) = read_primitive(&primitive, buffers)?;
+                combined_indices.extend(indices.iter().map(|index| index + vertex_offset));
+                vertex_offset += vertices.len() as u32;
+                combined_vertices.extend(vertices);
             }
         }
         
-        println!("Loaded mesh with {} vertices and {} indices", 
+        info!("Loaded mesh with {} vertices and {} indices", 
                  combined_vertices.len(), combined_indices.len());
         
         if combined_vertices.is_empty() {
             return Err("No mesh data found in GLB file".to_string());
         }
         
-        Ok(MeshData::new(combined_vertices, combined_indices))
+        Ok(MeshData::new(combined_vertices, combined_indices).with_double_sided(double_sided))
+    }
+}
+
+// One mesh per primitive with its material, like obj_loader::load_obj, so each keeps its own
+// cull and alpha mode. Apply the material with VulkanRenderer::set_mesh_material.
+pub fn load_primitives<P: AsRef<Path>>(path: P) -> Result<Vec<(MeshData, MaterialInfo)>, String> {
+    let path = path.as_ref();
+    let (document, buffers, _) = gltf::import(path)
+        .map_err(|e| format!("Failed to load GLB file: {}", e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut result = Vec::new();
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            let material = material_info(&primitive.material(), base_dir);
+            let (vertices, indices) = read_primitive(&primitive, &buffers)?;
+            debug!("Loaded glTF primitive {:?} with {} vertices and {} indices, material {:?}",
+                     mesh.name(), vertices.len(), indices.len(), material.name);
+            let mesh_data = MeshData::new(vertices, indices).with_double_sided(material.double_sided);
+            result.push((mesh_data, material));
+        }
+    }
+
+    if result.is_empty() {
+        return Err(format!("No mesh data found in glTF file {}", path.display()));
+    }
+    Ok(result)
+}
+
+// A glTF scene's node graph with the mesh primitives placed by it, see load_scene
+#[derive(Default)]
+pub struct SceneData {
+    // Parents before their children
+    pub nodes: Vec<SceneNode>,
+    pub meshes: Vec<SceneMesh>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneNode {
+    pub name: Option<String>,
+    // Index into SceneData::nodes, None for the scene's root nodes
+    pub parent: Option<usize>,
+    // Relative to the parent
+    pub local_transform: Mat4,
+    // Local transforms of the node and its ancestors composed
+    pub world_transform: Mat4,
+}
+
+// One primitive of a node's mesh, with its vertices still in the mesh's own space
+pub struct SceneMesh {
+    pub mesh_data: MeshData,
+    pub material: MaterialInfo,
+    // Index into SceneData::nodes
+    pub node: usize,
+    // The node's world transform
+    pub world_transform: Mat4,
+}
+
+impl SceneMesh {
+    // Inverse-transpose of the world transform, which keeps normals perpendicular to the surface
+    // when the node chain scales non-uniformly. Transforming normals by the world transform
+    // itself skews them.
+    pub fn normal_matrix(&self) -> Mat3 {
+        Mat3::from_mat4(self.world_transform).inverse().transpose()
+    }
+}
+
+// Every mesh primitive of the file's default scene (else its first, else all root nodes) with
+// its node's world transform, composed down the node graph, instead of all at the origin like
+// load_primitives. A mesh used by several nodes is loaded once per node.
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<SceneData, String> {
+    let path = path.as_ref();
+    let (document, buffers, _) = gltf::import(path)
+        .map_err(|e| format!("Failed to load glTF file: {}", e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    
+    let roots: Vec<gltf::Node> = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene.nodes().collect(),
+        None => {
+            let mut has_parent = vec![false; document.nodes().len()];
+            for child in document.nodes().flat_map(|node| node.children()) {
+                has_parent[child.index()] = true;
+            }
+            document.nodes().filter(|node| !has_parent[node.index()]).collect()
+        }
+    };
+    
+    let mut scene = SceneData::default();
+    // Depth first, so parents are pushed before their children
+    let mut stack: Vec<(gltf::Node, Option<usize>)> = roots.into_iter().rev().map(|node| (node, None)).collect();
+    while let Some((node, parent)) = stack.pop() {
+        let local_transform = Mat4::from_cols_array_2d(&node.transform().matrix());
+        let world_transform = match parent {
+            Some(parent) => scene.nodes[parent].world_transform * local_transform,
+            None => local_transform,
+        };
+        let index = scene.nodes.len();
+        scene.nodes.push(SceneNode {
+            name: node.name().map(str::to_string),
+            parent,
+            local_transform,
+            world_transform,
+        });
+        
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                let material = material_info(&primitive.material(), base_dir);
+                let (vertices, indices) = read_primitive(&primitive, &buffers)?;
+                scene.meshes.push(SceneMesh {
+                    mesh_data: MeshData::new(vertices, indices).with_double_sided(material.double_sided),
+                    material,
+                    node: index,
+                    world_transform,
+                });
+            }
+        }
+        stack.extend(node.children().collect::<Vec<_>>().into_iter().rev().map(|child| (child, Some(index))));
+    }
+    
+    if scene.meshes.is_empty() {
+        return Err(format!("No mesh data found in glTF file {}", path.display()));
+    }
+    info!("Loaded glTF scene with {} nodes and {} mesh primitives", scene.nodes.len(), scene.meshes.len());
+    Ok(scene)
+}
+
+// Morph targets of every primitive, in the same order as load_primitives. Primitives without
+// any get empty MorphTargets. Vertices line up with the primitive's own, so attach them to a
+// SkinnedMeshData built from the same primitive with SkinnedMeshData::with_morph_targets.
+pub fn load_morph_targets<P: AsRef<Path>>(path: P) -> Result<Vec<MorphTargets>, String> {
+    let (document, buffers, _) = gltf::import(path.as_ref())
+        .map_err(|e| format!("Failed to load glTF file: {}", e))?;
+    
+    let mut result = Vec::new();
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let vertex_count = reader.read_positions().map_or(0, |positions| positions.len());
+            let targets: Vec<MorphTarget> = reader
+                .read_morph_targets()
+                .map(|(positions, normals, _)| MorphTarget {
+                    // Targets may leave out positions and only move normals
+                    positions: positions.map_or_else(|| vec![[0.0; 3]; vertex_count], |iter| iter.collect()),
+                    normals: normals.map(|iter| iter.collect()).unwrap_or_default(),
+                })
+                .collect();
+            // Default weights are set on the mesh, zero when missing
+            let mut weights = mesh.weights().map(<[f32]>::to_vec).unwrap_or_default();
+            weights.resize(targets.len(), 0.0);
+            if !targets.is_empty() {
+                debug!("Loaded {} morph targets for glTF primitive {:?}", targets.len(), mesh.name());
+            }
+            result.push(MorphTargets { targets, weights });
+        }
+    }
+    Ok(result)
+}
+
+fn material_info(material: &gltf::Material, base_dir: &Path) -> MaterialInfo {
+    let pbr = material.pbr_metallic_roughness();
+    // Only textures in separate files have a path, embedded ones are read by GltfData
+    let base_color_texture = pbr.base_color_texture().and_then(|info| match info.texture().source().source() {
+        gltf::image::Source::Uri { uri, .. } => Some(base_dir.join(uri)),
+        gltf::image::Source::View { .. } => None,
+    });
+    MaterialInfo {
+        name: material.name().map(str::to_string),
+        base_color: pbr.base_color_factor(),
+        base_color_texture,
+        double_sided: material.double_sided(),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+    }
+}
+
+// Vertices and indices of one primitive. Vertex colors fall back to the material's base
+// color, and triangles are flipped to match the renderer's winding.
+fn read_primitive(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<(Vec<Vertex>, Vec<u32>), String> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let material_color = primitive.material().pbr_metallic_roughness().base_color_factor();
+
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .ok_or("Mesh should have positions")?
+        .collect();
+
+    let normals: Vec<[f32; 3]> = reader
+        .read_normals()
+        .map(|iter| iter.collect())
+        .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; positions.len()]);
+
+    let uvs: Vec<[f32; 2]> = reader
+        .read_tex_coords(0)
+        .map(|iter| iter.into_f32().collect())
+        .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
+
+    // Check if vertex colors exist, otherwise use material color
+    let colors: Vec<[f32; 4]> = reader
+        .read_colors(0)
+        .map(|iter| iter.into_rgba_f32().collect())
+        .unwrap_or_else(|| vec![material_color; positions.len()]);
+
+    let vertices = (0..positions.len())
+        .map(|i| Vertex::with_color(positions[i], normals[i], uvs[i], colors[i]))
+        .collect();
+
+    let mut indices = Vec::new();
+    if let Some(indices_reader) = reader.read_indices() {
+        let primitive_indices: Vec<u32> = indices_reader.into_u32().collect();
+        for triangle in primitive_indices.chunks(3) {
+            if triangle.len() == 3 {
+                indices.extend_from_slice(&[triangle[0], triangle[2], triangle[1]]);
+            }
+        }
+    } else {
+        // Without indices the vertices are the triangle list, flipped the same way. The vertices
+        // keep their order so morph targets still line up with them.
+        for first in (0..positions.len() as u32 / 3 * 3).step_by(3) {
+            indices.extend_from_slice(&[first, first + 2, first + 1]);
+        }
+    }
+    Ok((vertices, indices))
+}
+// Every animation in a glTF file, e.g. a clip file without meshes shared between characters.
+// Channels are keyed by the name of the node they animate, which is how they're matched to a
+// skeleton (see animation::AnimationSampler). Channels on unnamed nodes and morph target
+// weights are skipped.
+pub fn load_animations<P: AsRef<Path>>(path: P) -> Result<Vec<AnimationClip>, String> {
+    let (document, buffers, _) = gltf::import(path.as_ref())
+        .map_err(|e| format!("Failed to load glTF file: {}", e))?;
+    
+    let mut clips = Vec::new();
+    for animation in document.animations() {
+        let mut clip = AnimationClip { name: animation.name().map(str::to_string), ..Default::default() };
+        let mut skipped = 0;
+        for channel in animation.channels() {
+            let Some(joint_name) = channel.target().node().name() else {
+                skipped += 1;
+                continue;
+            };
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = match reader.read_inputs() {
+                Some(inputs) => inputs.collect(),
+                None => continue,
+            };
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+            clip.duration = times.iter().copied().fold(clip.duration, f32::max);
+            
+            // Properties of one node can come from separate channels
+            let index = match clip.channels.iter().position(|existing| existing.joint_name == joint_name) {
+                Some(index) => index,
+                None => {
+                    clip.channels.push(JointChannel { joint_name: joint_name.to_string(), ..Default::default() });
+                    clip.channels.len() - 1
+                }
+            };
+            let joint_channel = &mut clip.channels[index];
+            match reader.read_outputs() {
+                Some(gltf::animation::util::ReadOutputs::Translations(values)) => {
+                    joint_channel.translations = Keyframes { times, values: values.map(Vec3::from).collect(), interpolation };
+                }
+                Some(gltf::animation::util::ReadOutputs::Rotations(values)) => {
+                    joint_channel.rotations = Keyframes { times, values: values.into_f32().map(Quat::from_array).collect(), interpolation };
+                }
+                Some(gltf::animation::util::ReadOutputs::Scales(values)) => {
+                    joint_channel.scales = Keyframes { times, values: values.map(Vec3::from).collect(), interpolation };
+                }
+                _ => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            warn!("Skipped {} channels of animation {:?} (unnamed nodes or morph targets)", skipped, clip.name);
+        }
+        clips.push(clip);
+    }
+    
+    info!("Loaded {} animations", clips.len());
+    Ok(clips)
+}
+
+// Joints of the file's first skin with their names, parents, inverse bind matrices and rest
+// pose, for retargeting clips from load_animations onto it
+pub fn load_skeleton<P: AsRef<Path>>(path: P) -> Result<Skeleton, String> {
+    let (document, buffers, _) = gltf::import(path.as_ref())
+        .map_err(|e| format!("Failed to load glTF file: {}", e))?;
+    let skin = document.skins().next().ok_or("No skin found in glTF file")?;
+    
+    let joints: Vec<gltf::Node> = skin.joints().collect();
+    let joint_nodes: Vec<usize> = joints.iter().map(gltf::Node::index).collect();
+    let mut parent_nodes = vec![None; document.nodes().len()];
+    for node in document.nodes() {
+        for child in node.children() {
+            parent_nodes[child.index()] = Some(node.index());
+        }
+    }
+    
+    let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+    // Missing inverse bind matrices are identities, as in glTF
+    let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+        Some(matrices) => matrices.map(|matrix| Mat4::from_cols_array_2d(&matrix)).collect(),
+        None => vec![Mat4::IDENTITY; joints.len()],
+    };
+    
+    Ok(Skeleton {
+        // Unnamed joints get a placeholder no clip will match
+        joint_names: joints.iter()
+            .map(|joint| joint.name().map_or_else(|| format!("joint{}", joint.index()), str::to_string))
+            .collect(),
+        parents: joints.iter()
+            .map(|joint| parent_nodes[joint.index()].and_then(|parent| joint_nodes.iter().position(|&node| node == parent)))
+            .collect(),
+        inverse_bind_matrices,
+        rest_pose: joints.iter()
+            .map(|joint| {
+                let (translation, rotation, scale) = joint.transform().decomposed();
+                Transform {
+                    translation: Vec3::from(translation),
+                    rotation: Quat::from_array(rotation),
+                    scale: Vec3::from(scale),
+                }
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Writes a glTF file whose meshes all use one unindexed triangle from a separate .bin, with
+    // the rest of the top level JSON (meshes, materials, nodes...) given by the test
+    fn write_fixture(name: &str, rest: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flo_gltf_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let positions: [f32; 9] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let bytes: Vec<u8> = positions.iter().flat_map(|value| value.to_le_bytes()).collect();
+        std::fs::write(dir.join("triangle.bin"), bytes).unwrap();
+        
+        let json = format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "uri": "triangle.bin", "byteLength": 36 }}],
+            "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+            "accessors": [{{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                            "min": [0, 0, 0], "max": [1, 1, 0] }}],
+            {}
+        }}"#, rest);
+        let path = dir.join(format!("{}.gltf", name));
+        std::fs::write(&path, json).unwrap();
+        path
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn combined_mesh_is_double_sided_if_any_primitive_is() {
+        let path = write_fixture("double_sided", r#"
+            "materials": [{ "doubleSided": true }, {}],
+            "meshes": [{ "primitives": [
+                { "attributes": { "POSITION": 0 }, "material": 1 },
+                { "attributes": { "POSITION": 0 }, "material": 0 }
+            ] }]
+        "#);
+        let gltf = GltfData::load_from_file(&path).unwrap();
+        assert_eq!(gltf.mesh_data.vertices.len(), 6);
+        // Flipped to the renderer's winding, the second primitive after the first's vertices
+        assert_eq!(gltf.mesh_data.indices, vec![0, 2, 1, 3, 5, 4]);
+        assert!(gltf.mesh_data.double_sided);
+        
+        let path = write_fixture("single_sided", r#"
+            "materials": [{ "doubleSided": false }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }]
+        "#);
+        assert!(!GltfData::load_from_file(&path).unwrap().mesh_data.double_sided);
+    }
+    
+    #[test]
+    fn primitives_keep_their_own_material() {
+        let path = write_fixture("materials", r#"
+            "materials": [
+                { "name": "leaves", "alphaMode": "MASK", "alphaCutoff": 0.3, "doubleSided": true,
+                  "pbrMetallicRoughness": { "baseColorFactor": [0.2, 0.8, 0.2, 1.0] } },
+                { "name": "glass", "alphaMode": "BLEND" },
+                { "name": "bark", "alphaMode": "MASK" }
+            ],
+            "meshes": [
+                { "primitives": [
+                    { "attributes": { "POSITION": 0 }, "material": 0 },
+                    { "attributes": { "POSITION": 0 }, "material": 1 }
+                ] },
+                { "primitives": [
+                    { "attributes": { "POSITION": 0 }, "material": 2 },
+                    { "attributes": { "POSITION": 0 } }
+                ] }
+            ]
+        "#);
+        let primitives = load_primitives(&path).unwrap();
+        let names: Vec<_> = primitives.iter().map(|(_, material)| material.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("leaves"), Some("glass"), Some("bark"), None]);
+        
+        let (leaves_mesh, leaves) = &primitives[0];
+        assert_eq!(leaves.alpha_mode, AlphaMode::Mask);
+        assert_eq!(leaves.material_mode(), MaterialMode::Cutout { threshold: 0.3 });
+        assert_eq!(leaves.blend_mode(), BlendMode::Opaque);
+        assert!(leaves.double_sided && leaves_mesh.double_sided);
+        // Without vertex colors the vertices take the base color
+        assert_eq!(leaves_mesh.vertices[0].color, [0.2, 0.8, 0.2, 1.0]);
+        
+        let (glass_mesh, glass) = &primitives[1];
+        assert_eq!(glass.alpha_mode, AlphaMode::Blend);
+        assert_eq!(glass.blend_mode(), BlendMode::AlphaBlend);
+        assert_eq!(glass.material_mode(), MaterialMode::Opaque);
+        assert!(!glass.double_sided && !glass_mesh.double_sided);
+        
+        // glTF's defaults: a 0.5 cutoff, and opaque single sided without a material
+        assert_eq!(primitives[2].1.material_mode(), MaterialMode::Cutout { threshold: 0.5 });
+        let (_, default) = &primitives[3];
+        assert_eq!(default.alpha_mode, AlphaMode::Opaque);
+        assert!(!default.double_sided);
+    }
+    
+    #[test]
+    fn scene_nodes_compose_their_parents_transforms() {
+        // The default scene is the second one, the first one's node is never reached
+        let path = write_fixture("scene", r#"
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "nodes": [
+                { "name": "root", "translation": [1, 0, 0], "children": [1] },
+                { "name": "child", "scale": [2, 2, 2], "mesh": 0, "children": [2] },
+                { "name": "grandchild", "translation": [0, 0, 3], "mesh": 0 },
+                { "name": "other", "translation": [0, 5, 0], "mesh": 0 },
+                { "name": "unused", "mesh": 0 }
+            ],
+            "scenes": [{ "nodes": [4] }, { "nodes": [0, 3] }],
+            "scene": 1
+        "#);
+        let scene = load_scene(&path).unwrap();
+        let nodes: Vec<_> = scene.nodes.iter().map(|node| (node.name.as_deref().unwrap(), node.parent)).collect();
+        assert_eq!(nodes, vec![("root", None), ("child", Some(0)), ("grandchild", Some(1)), ("other", None)]);
+        
+        let grandchild = &scene.nodes[2];
+        assert_eq!(grandchild.local_transform, Mat4::from_translation(Vec3::new(0.0, 0.0, 3.0)));
+        // The parent's scale applies to the child's translation
+        let expected = Mat4::from_translation(Vec3::X) * Mat4::from_scale(Vec3::splat(2.0)) * grandchild.local_transform;
+        assert!(grandchild.world_transform.abs_diff_eq(expected, 1e-6));
+        assert!(grandchild.world_transform.transform_point3(Vec3::ZERO).abs_diff_eq(Vec3::new(1.0, 0.0, 6.0), 1e-6));
+        
+        let mesh_nodes: Vec<usize> = scene.meshes.iter().map(|mesh| mesh.node).collect();
+        assert_eq!(mesh_nodes, vec![1, 2, 3]);
+        for mesh in &scene.meshes {
+            assert_eq!(mesh.world_transform, scene.nodes[mesh.node].world_transform);
+            assert_eq!(mesh.mesh_data.vertices.len(), 3);
+        }
+        assert!(scene.meshes[0].normal_matrix().abs_diff_eq(Mat3::from_diagonal(Vec3::splat(0.5)), 1e-6));
+    }
+}
@@ -0,0 +1,110 @@
+use ash::vk;
+use bevy_math::Mat4;
+use std::mem;
+use crate::error::FloError;
+use crate::vulkan_common::*;
+use crate::leak_check;
+
+const GROUND_GRID_VERT_SHADER_PATH: &str = "shaders/ground_grid.vert.spv";
+const GROUND_GRID_FRAG_SHADER_PATH: &str = "shaders/ground_grid.frag.spv";
+
+// See VulkanRenderer::enable_ground_grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroundGridSettings {
+    // World units between lines
+    pub spacing: f32,
+    // How far the grid reaches from the point under the camera
+    pub extent: f32,
+    pub color: [f32; 4],
+    // Distance from the camera at which the lines have faded out completely
+    pub fade_distance: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GroundGridPushConstants {
+    view_proj: [f32; 16],
+    color: [f32; 4],
+    center: [f32; 2],
+    spacing: f32,
+    extent: f32,
+    fade_distance: f32,
+}
+
+// Reference grid on the y = 0 plane. It's a quad following the camera with the lines computed
+// per pixel in world space, so it needs no vertex buffer and looks endless once it fades out.
+pub struct GroundGrid {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    pub settings: GroundGridSettings,
+}
+
+impl GroundGrid {
+    pub fn new(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+        with_depth_test: bool,
+        settings: GroundGridSettings,
+    ) -> Result<Self, FloError> {
+        let push_constants = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(mem::size_of::<GroundGridPushConstants>() as u32);
+        // The plane tests and writes depth like a floor, so meshes in front hide it and meshes
+        // below it stay hidden behind it
+        let (pipeline, layout) = PipelineBuilder::new(
+            device.clone(),
+            GROUND_GRID_VERT_SHADER_PATH,
+            GROUND_GRID_FRAG_SHADER_PATH,
+            extent,
+            render_pass,
+        )?
+        .with_push_constants(vec![push_constants])
+        .with_depth_test(with_depth_test)
+        .with_depth_write(with_depth_test)
+        .with_cull_mode(vk::CullModeFlags::NONE)
+        .with_blend_mode(BlendMode::AlphaBlend)
+        .build()?;
+
+        Ok(Self { pipeline, layout, settings })
+    }
+
+    // Must be recorded inside the scene pass
+    pub fn record_draw(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        view: Mat4,
+        proj: Mat4,
+        depth_compare_op: vk::CompareOp,
+    ) {
+        let camera_position = view.inverse().w_axis;
+        let push_constants = GroundGridPushConstants {
+            view_proj: (proj * view).to_cols_array(),
+            color: self.settings.color,
+            center: [camera_position.x, camera_position.z],
+            spacing: self.settings.spacing,
+            extent: self.settings.extent,
+            fade_distance: self.settings.fade_distance,
+        };
+        bind_graphics_pipeline(device, command_buffer, self.pipeline, depth_compare_op);
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_draw(command_buffer, 6, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            leak_check::destroy(device, self.pipeline);
+            leak_check::destroy(device, self.layout);
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use ash::{vk, Instance};
+use tracing::{error, warn};
+use bevy_math::{Mat4, Vec4};
+use std::mem;
+use crate::constants::*;
+use crate::vulkan_common::*;
+use crate::error::FloError;
+use crate::leak_check;
+
+const CULL_SHADER_PATH: &str = "shaders/instance_cull.comp.spv";
+const CULL_WORKGROUP_SIZE: u32 = 64;
+// Instance buffers hold one tightly packed vec3 position per instance
+const INSTANCE_STRIDE: usize = mem::size_of::<[f32; 3]>();
+
+// Inward facing frustum planes as (normal, distance), same layout as the shader's uniform
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Frustum {
+    pub planes: [[f32; 4]; 6],
+}
+
+impl Frustum {
+    // Extracts the planes from a combined projection * view matrix (Vulkan 0..1 clip depth).
+    // Works for reverse-Z too since only the roles of the near and far planes swap.
+    pub fn from_view_proj(view_proj: Mat4) -> Self {
+        let row = |i: usize| view_proj.row(i);
+        let planes = [
+            row(3) + row(0),  // Left
+            row(3) - row(0),  // Right
+            row(3) + row(1),  // Bottom
+            row(3) - row(1),  // Top
+            row(2),           // Near
+            row(3) - row(2),  // Far
+        ];
+
+        let normalize = |plane: Vec4| {
+            let length = plane.truncate().length();
+            if length > 0.0 { plane / length } else { plane }
+        };
+
+        Self {
+            planes: planes.map(|plane| normalize(plane).to_array()),
+        }
+    }
+
+    pub fn intersects_sphere(&self, center: [f32; 3], radius: f32) -> bool {
+        self.planes.iter().all(|plane| {
+            plane[0] * center[0] + plane[1] * center[1] + plane[2] * center[2] + plane[3] >= -radius
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullPushConstants {
+    instance_count: u32,
+    radius: f32,
+}
+
+// Compute pipeline shared by every GPU culled mesh
+pub struct InstanceCullPipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+}
+
+impl InstanceCullPipeline {
+    pub fn new(device: &ash::Device) -> Result<Self, FloError> {
+        let storage_binding = |binding: u32| vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE);
+        let bindings = [
+            storage_binding(0),  // Source instances
+            storage_binding(1),  // Compacted instances
+            storage_binding(2),  // Indirect draw command
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(3)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+        ];
+        let descriptor_set_layout = create_descriptor_set_layout(device, &bindings)?;
+
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(mem::size_of::<CullPushConstants>() as u32)];
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let layout = leak_check::created(device, unsafe { device.create_pipeline_layout(&layout_info, None)? });
+
+        let shader_code = read_shader_file(CULL_SHADER_PATH)?;
+        let shader_module = create_shader_module(device, &shader_code)?;
+
+        let entry_name = std::ffi::CString::new("main").unwrap();
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(&entry_name);
+        let pipeline_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = leak_check::created(device, unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+                .map_err(|(_, e)| e)?[0]
+        });
+
+        unsafe {
+            leak_check::destroy(device, shader_module);
+        }
+
+        Ok(Self { pipeline, layout, descriptor_set_layout })
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            leak_check::destroy(device, self.pipeline);
+            leak_check::destroy(device, self.layout);
+            leak_check::destroy(device, self.descriptor_set_layout);
+        }
+    }
+}
+
+// Compute culling needs the graphics queue to also accept dispatches, since the
+// cull and the indirect draw are recorded into the same command buffer
+pub fn gpu_culling_supported(instance: &Instance, physical_device: vk::PhysicalDevice, queue_family_index: u32) -> bool {
+    let queue_families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    queue_families
+        .get(queue_family_index as usize)
+        .map(|family| family.queue_flags.contains(vk::QueueFlags::COMPUTE))
+        .unwrap_or(false)
+}
+
+// Buffers owned by one frame in flight, so culling for the next frame never
+// overwrites data the previous frame is still drawing from
+struct CullFrameResources {
+    output_buffer: vk::Buffer,
+    output_memory: vk::DeviceMemory,
+    // GPU path only
+    indirect_buffer: Option<(vk::Buffer, vk::DeviceMemory)>,
+    frustum_buffer: Option<(vk::Buffer, vk::DeviceMemory)>,
+    descriptor_set: vk::DescriptorSet,
+    // CPU path only, number of instances written to output_buffer this frame
+    visible_count: u32,
+}
+
+// Frustum culling state for one instanced mesh. Surviving instances are compacted
+// into a per-frame buffer that replaces the mesh's instance buffer at draw time.
+pub struct InstanceCulling {
+    gpu: bool,
+    radius: f32,
+    capacity: u32,
+    // Kept for the CPU fallback and to know how many instances to test
+    instance_positions: Vec<[f32; 3]>,
+    descriptor_pool: Option<vk::DescriptorPool>,
+    frames: Vec<CullFrameResources>,
+}
+
+impl InstanceCulling {
+    // Pass the shared compute pipeline to cull on the GPU, or None to fall back to culling on the CPU
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        cull_pipeline: Option<&InstanceCullPipeline>,
+        source_instance_buffer: vk::Buffer,
+        instance_positions: Vec<[f32; 3]>,
+        radius: f32,
+    ) -> Result<Self, FloError> {
+        let capacity = instance_positions.len().max(1) as u32;
+        let output_size = (capacity as usize * INSTANCE_STRIDE) as vk::DeviceSize;
+
+        let (output_usage, output_properties) = if cull_pipeline.is_some() {
+            (vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+        } else {
+            (vk::BufferUsageFlags::VERTEX_BUFFER, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT)
+        };
+
+        let mut frames = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let (output_buffer, output_memory) = create_buffer(
+                instance, device, physical_device, output_size, output_usage, output_properties,
+            )?;
+
+            let (indirect_buffer, frustum_buffer) = if cull_pipeline.is_some() {
+                let indirect = create_buffer(
+                    instance,
+                    device,
+                    physical_device,
+                    mem::size_of::<vk::DrawIndexedIndirectCommand>() as vk::DeviceSize,
+                    vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )?;
+                let frustum = create_buffer(
+                    instance,
+                    device,
+                    physical_device,
+                    mem::size_of::<Frustum>() as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )?;
+                (Some(indirect), Some(frustum))
+            } else {
+                (None, None)
+            };
+
+            frames.push(CullFrameResources {
+                output_buffer,
+                output_memory,
+                indirect_buffer,
+                frustum_buffer,
+                descriptor_set: vk::DescriptorSet::null(),
+                visible_count: 0,
+            });
+        }
+
+        let descriptor_pool = if let Some(cull_pipeline) = cull_pipeline {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 3 * MAX_FRAMES_IN_FLIGHT as u32,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+                },
+            ];
+            let pool = create_descriptor_pool(device, MAX_FRAMES_IN_FLIGHT as u32, &pool_sizes)?;
+            let layouts = vec![cull_pipeline.descriptor_set_layout; MAX_FRAMES_IN_FLIGHT];
+            let sets = allocate_descriptor_sets(device, pool, &layouts)?;
+
+            for (frame, descriptor_set) in frames.iter_mut().zip(sets) {
+                frame.descriptor_set = descriptor_set;
+
+                let (indirect_buffer, _) = frame.indirect_buffer.unwrap();
+                let (frustum_buffer, _) = frame.frustum_buffer.unwrap();
+                let buffer_infos = [
+                    [vk::DescriptorBufferInfo { buffer: source_instance_buffer, offset: 0, range: vk::WHOLE_SIZE }],
+                    [vk::DescriptorBufferInfo { buffer: frame.output_buffer, offset: 0, range: vk::WHOLE_SIZE }],
+                    [vk::DescriptorBufferInfo { buffer: indirect_buffer, offset: 0, range: vk::WHOLE_SIZE }],
+                    [vk::DescriptorBufferInfo { buffer: frustum_buffer, offset: 0, range: vk::WHOLE_SIZE }],
+                ];
+                let writes: Vec<vk::WriteDescriptorSet> = buffer_infos.iter().enumerate().map(|(binding, info)| {
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(binding as u32)
+                        .descriptor_type(if binding == 3 {
+                            vk::DescriptorType::UNIFORM_BUFFER
+                        } else {
+                            vk::DescriptorType::STORAGE_BUFFER
+                        })
+                        .buffer_info(info)
+                }).collect();
+
+                unsafe {
+                    device.update_descriptor_sets(&writes, &[]);
+                }
+            }
+
+            Some(pool)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            gpu: cull_pipeline.is_some(),
+            radius,
+            capacity,
+            instance_positions,
+            descriptor_pool,
+            frames,
+        })
+    }
+
+    pub fn is_gpu(&self) -> bool {
+        self.gpu
+    }
+
+    // The output buffers aren't resized, so instances past the count culling was enabled with are dropped
+    pub fn set_instance_positions(&mut self, mut instance_positions: Vec<[f32; 3]>) {
+        if instance_positions.len() > self.capacity as usize {
+            warn!("Culled mesh grew from {} to {} instances, re-enable culling to draw them all",
+                      self.capacity, instance_positions.len());
+            instance_positions.truncate(self.capacity as usize);
+        }
+        self.instance_positions = instance_positions;
+    }
+
+    // Same swap remove as VulkanRenderer::remove_instance
+    pub fn swap_remove_instance(&mut self, index: usize) {
+        if index < self.instance_positions.len() {
+            self.instance_positions.swap_remove(index);
+        }
+    }
+
+    // Must be recorded outside a render pass. Host writes (frustum uniform, CPU culled
+    // instances) are made visible to the GPU by the queue submit.
+    pub fn record_reset(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize, frustum: &Frustum, index_count: u32) {
+        let radius = self.radius;
+        let frame_resources = &mut self.frames[frame];
+
+        if !self.gpu {
+            let visible: Vec<[f32; 3]> = self.instance_positions.iter()
+                .copied()
+                .filter(|&position| frustum.intersects_sphere(position, radius))
+                .collect();
+            frame_resources.visible_count = visible.len() as u32;
+            if !visible.is_empty() {
+                write_mapped(device, frame_resources.output_memory, bytemuck::cast_slice(&visible));
+            }
+            return;
+        }
+
+        if let Some((_, frustum_memory)) = frame_resources.frustum_buffer {
+            write_mapped(device, frustum_memory, bytemuck::bytes_of(frustum));
+        }
+
+        // The shader bumps instanceCount with an atomic, so start every frame from zero
+        if let Some((indirect_buffer, _)) = frame_resources.indirect_buffer {
+            let command = [index_count, 0, 0, 0, 0];
+            unsafe {
+                device.cmd_update_buffer(command_buffer, indirect_buffer, 0, bytemuck::cast_slice(&command));
+            }
+        }
+    }
+
+    pub fn record_dispatch(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize, cull_pipeline: &InstanceCullPipeline) {
+        if !self.gpu || self.instance_positions.is_empty() {
+            return;
+        }
+
+        let push_constants = CullPushConstants {
+            instance_count: self.instance_positions.len() as u32,
+            radius: self.radius,
+        };
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                cull_pipeline.layout,
+                0,
+                &[self.frames[frame].descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                cull_pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_dispatch(command_buffer, push_constants.instance_count.div_ceil(CULL_WORKGROUP_SIZE), 1, 1);
+        }
+    }
+
+    // Binds the compacted instances at binding 1 and draws the survivors
+    pub fn record_draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize, index_count: u32) {
+        let frame_resources = &self.frames[frame];
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 1, &[frame_resources.output_buffer], &[0]);
+
+            if let Some((indirect_buffer, _)) = frame_resources.indirect_buffer {
+                device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    indirect_buffer,
+                    0,
+                    1,
+                    mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32,
+                );
+            } else if frame_resources.visible_count > 0 {
+                device.cmd_draw_indexed(command_buffer, index_count, frame_resources.visible_count, 0, 0, 0);
+            }
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        for frame in &self.frames {
+            destroy_buffer(device, frame.output_buffer, frame.output_memory);
+            if let Some((buffer, memory)) = frame.indirect_buffer {
+                destroy_buffer(device, buffer, memory);
+            }
+            if let Some((buffer, memory)) = frame.frustum_buffer {
+                destroy_buffer(device, buffer, memory);
+            }
+        }
+        if let Some(pool) = self.descriptor_pool {
+            unsafe {
+                leak_check::destroy(device, pool);
+            }
+        }
+    }
+}
+
+// Barrier between the indirect command resets and the cull dispatches
+pub fn record_reset_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    let barrier = vk::MemoryBarrier::default()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        );
+    }
+}
+
+// Barrier between the cull dispatches and the indirect draws reading their output
+pub fn record_draw_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    let barrier = vk::MemoryBarrier::default()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::VERTEX_ATTRIBUTE_READ);
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        );
+    }
+}
+
+fn write_mapped(device: &ash::Device, memory: vk::DeviceMemory, bytes: &[u8]) {
+    unsafe {
+        match device.map_memory(memory, 0, bytes.len() as vk::DeviceSize, vk::MemoryMapFlags::empty()) {
+            Ok(data) => {
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, bytes.len());
+                device.unmap_memory(memory);
+            }
+            Err(e) => error!("Failed to map culling buffer: {}", e),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+// Back to front sorting of an instanced mesh's instances, see
+// VulkanRenderer::set_mesh_instance_sort. Depths are quantized into integer keys and radix
+// sorted, so the cost grows linearly with the instance count instead of n log n like a
+// comparison sort, which matters at thousands of particles or foliage cards per frame.
+
+// Bits sorted per counting pass, 256 buckets each
+const RADIX_BITS: u32 = 8;
+const RADIX_BUCKETS: usize = 1 << RADIX_BITS;
+
+// How precisely instance depths are ordered. The depth range of the mesh's instances is split
+// into 2^key_bits buckets, so at the default 16 bits instances spread over 100 meters land in
+// buckets 1.5mm deep. Instances in the same bucket keep the order they were given, which can
+// draw a pair that close together in the wrong order. More bits narrow the buckets but take
+// more passes over the instances, one per 8 bits: 8 bits is a single pass, 32 bits four.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstanceSort {
+    // 1 to 32
+    pub key_bits: u32,
+}
+
+impl Default for InstanceSort {
+    fn default() -> Self {
+        Self { key_bits: 16 }
+    }
+}
+
+// Instance indices ordered from the largest depth (farthest) to the smallest. Equal keys keep
+// their relative order. NaN depths sort as the farthest, infinite ones clamp to either end of
+// the finite range.
+pub fn sort_back_to_front(depths: &[f32], key_bits: u32) -> Vec<u32> {
+    let mut order: Vec<u32> = (0..depths.len() as u32).collect();
+    let (near, far) = depths.iter()
+        .filter(|depth| depth.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(near, far), &depth| (near.min(depth), far.max(depth)));
+    if near >= far {
+        return order;
+    }
+
+    // Key 0 is the farthest, so ascending keys run back to front
+    let key_bits = key_bits.clamp(1, 32);
+    let max_key = ((1u64 << key_bits) - 1) as f64;
+    let scale = max_key / (far - near) as f64;
+    let keys: Vec<u32> = depths.iter()
+        .map(|&depth| ((far - depth) as f64 * scale).clamp(0.0, max_key) as u32)
+        .collect();
+
+    // Least significant digit first, each pass stable, so ties from earlier passes hold
+    let mut sorted = vec![0u32; order.len()];
+    for shift in (0..key_bits).step_by(RADIX_BITS as usize) {
+        let digit = |index: u32| (keys[index as usize] >> shift) as usize & (RADIX_BUCKETS - 1);
+        let mut offsets = [0usize; RADIX_BUCKETS];
+        for &index in &order {
+            offsets[digit(index)] += 1;
+        }
+        // Every key has the same digit here, the pass wouldn't move anything
+        if offsets.contains(&order.len()) {
+            continue;
+        }
+        let mut start = 0;
+        for offset in offsets.iter_mut() {
+            let count = *offset;
+            *offset = start;
+            start += count;
+        }
+        for &index in &order {
+            let bucket = &mut offsets[digit(index)];
+            sorted[*bucket] = index;
+            *bucket += 1;
+        }
+        std::mem::swap(&mut order, &mut sorted);
+    }
+    order
+}
@@ -0,0 +1,110 @@
+use ash::{vk, Instance};
+use bevy_math::Mat4;
+use crate::error::FloError;
+use crate::leak_check;
+use crate::memory_pool::FreeList;
+use crate::vulkan_common::{create_buffer, destroy_buffer};
+
+// Largest joint range of one packed mesh, and the range its dynamic uniform binding covers.
+// 16 KiB (256 joints) is the smallest maxUniformBufferRange devices may report.
+pub const JOINT_ARENA_SLOT_SIZE: vk::DeviceSize = 16 * 1024;
+
+// Joint matrices of many skinned meshes in one host visible buffer, read through a single
+// descriptor set bound with a dynamic offset per mesh, instead of a joint buffer, camera buffer
+// and descriptor sets per mesh. Sized by RendererConfig::skinned_joint_arena_size.
+pub struct JointArena {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    // view + proj, shared by every packed mesh
+    pub camera_buffer: vk::Buffer,
+    pub camera_memory: vk::DeviceMemory,
+    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_set: vk::DescriptorSet,
+    // Dynamic offsets have to be multiples of both the uniform and storage offset alignments
+    alignment: vk::DeviceSize,
+    free_list: FreeList,
+}
+
+impl JointArena {
+    // Creates the buffers for a descriptor set the caller has allocated with the skinned layout,
+    // and takes ownership of the set's pool and layout. The caller writes the set.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        size: vk::DeviceSize,
+        descriptor_pool: vk::DescriptorPool,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        descriptor_set: vk::DescriptorSet,
+    ) -> Result<Self, FloError> {
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+        let alignment = limits.min_uniform_buffer_offset_alignment
+            .max(limits.min_storage_buffer_offset_alignment)
+            .max(1);
+
+        // The last mesh's binding still covers a whole slot past its offset
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            physical_device,
+            size + JOINT_ARENA_SLOT_SIZE,
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let camera = create_buffer(
+            instance,
+            device,
+            physical_device,
+            (std::mem::size_of::<Mat4>() * 2) as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+        let (camera_buffer, camera_memory) = match camera {
+            Ok(camera) => camera,
+            Err(e) => {
+                destroy_buffer(device, buffer, memory);
+                return Err(e);
+            }
+        };
+
+        let mut free_list = FreeList::default();
+        free_list.add_chunk(0, size);
+        Ok(Self {
+            buffer,
+            memory,
+            camera_buffer,
+            camera_memory,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_set,
+            alignment,
+            free_list,
+        })
+    }
+
+    // (offset, size) of a range for joint_count joints, None when they don't fit in a slot or
+    // the arena is full
+    pub fn allocate(&mut self, joint_count: usize) -> Option<(u32, vk::DeviceSize)> {
+        let size = (joint_count.max(1) * std::mem::size_of::<Mat4>()) as vk::DeviceSize;
+        if size > JOINT_ARENA_SLOT_SIZE {
+            return None;
+        }
+        let (_, offset, size) = self.free_list.allocate(size, self.alignment)?;
+        Some((offset as u32, size))
+    }
+
+    pub fn free(&mut self, offset: u32, size: vk::DeviceSize) {
+        self.free_list.free(0, offset as vk::DeviceSize, size);
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        destroy_buffer(device, self.buffer, self.memory);
+        destroy_buffer(device, self.camera_buffer, self.camera_memory);
+        unsafe {
+            leak_check::destroy(device, self.descriptor_pool);
+            leak_check::destroy(device, self.descriptor_set_layout);
+        }
+    }
+}
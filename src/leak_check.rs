@@ -0,0 +1,152 @@
+use ash::vk::{self, Handle};
+#[cfg(feature = "leak-check")]
+use std::collections::HashMap;
+#[cfg(feature = "leak-check")]
+use std::sync::{Mutex, OnceLock};
+
+// Device objects the crate creates and destroys itself. All of them should be created through
+// created and destroyed through destroy, so that with the leak-check feature dropping a
+// renderer can check that everything created on its device was destroyed.
+pub trait DeviceObject: Handle + Copy {
+    // The Vulkan destroy call alone, use destroy so the object is counted
+    unsafe fn destroy_untracked(self, device: &ash::Device);
+}
+
+macro_rules! device_objects {
+    ($($handle:ty => $destroy:ident),* $(,)?) => {
+        $(
+            impl DeviceObject for $handle {
+                unsafe fn destroy_untracked(self, device: &ash::Device) {
+                    device.$destroy(self, None);
+                }
+            }
+        )*
+    };
+}
+
+device_objects! {
+    vk::Buffer => destroy_buffer,
+    vk::Image => destroy_image,
+    vk::ImageView => destroy_image_view,
+    vk::Sampler => destroy_sampler,
+    vk::DeviceMemory => free_memory,
+    vk::DescriptorPool => destroy_descriptor_pool,
+    vk::DescriptorSetLayout => destroy_descriptor_set_layout,
+    vk::Pipeline => destroy_pipeline,
+    vk::PipelineLayout => destroy_pipeline_layout,
+    vk::ShaderModule => destroy_shader_module,
+    vk::RenderPass => destroy_render_pass,
+    vk::Framebuffer => destroy_framebuffer,
+    vk::Semaphore => destroy_semaphore,
+    vk::Fence => destroy_fence,
+    vk::QueryPool => destroy_query_pool,
+    vk::CommandPool => destroy_command_pool,
+}
+
+// Live objects by device and type. Counts go negative when an object is destroyed twice.
+#[cfg(feature = "leak-check")]
+type LiveCounts = HashMap<u64, HashMap<vk::ObjectType, isize>>;
+
+#[cfg(feature = "leak-check")]
+fn live_counts() -> &'static Mutex<LiveCounts> {
+    static COUNTS: OnceLock<Mutex<LiveCounts>> = OnceLock::new();
+    COUNTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+#[cfg(feature = "leak-check")]
+fn count<T: DeviceObject>(device: &ash::Device, object: T, change: isize) {
+    if object.as_raw() == 0 {
+        return;
+    }
+    if let Ok(mut counts) = live_counts().lock() {
+        *counts.entry(device.handle().as_raw()).or_default().entry(T::TYPE).or_default() += change;
+    }
+}
+
+// Records an object created on the device. Returns it, so creation calls can be wrapped.
+#[cfg_attr(not(feature = "leak-check"), allow(unused_variables))]
+pub fn created<T: DeviceObject>(device: &ash::Device, object: T) -> T {
+    #[cfg(feature = "leak-check")]
+    count(device, object, 1);
+    object
+}
+
+// Destroys an object created on the device. Null handles are ignored, like Vulkan does.
+pub unsafe fn destroy<T: DeviceObject>(device: &ash::Device, object: T) {
+    #[cfg(feature = "leak-check")]
+    count(device, object, -1);
+    object.destroy_untracked(device);
+}
+
+// Object types with objects still alive on the device and how many, or with a negative count
+// if they were destroyed more often than created. Always empty without the leak-check feature.
+#[cfg_attr(not(feature = "leak-check"), allow(unused_variables))]
+pub fn live_objects(device: &ash::Device) -> Vec<(vk::ObjectType, isize)> {
+    #[cfg(feature = "leak-check")]
+    if let Ok(counts) = live_counts().lock() {
+        let mut live: Vec<_> = counts.get(&device.handle().as_raw())
+            .map(|types| types.iter().filter(|(_, &count)| count != 0).map(|(&object_type, &count)| (object_type, count)).collect())
+            .unwrap_or_default();
+        live.sort();
+        return live;
+    }
+    Vec::new()
+}
+
+// Called when the device is destroyed: panics listing the object types that leaked, unless
+// already panicking. Does nothing without the leak-check feature.
+pub fn assert_no_leaks(device: &ash::Device) {
+    let live = live_objects(device);
+    #[cfg(feature = "leak-check")]
+    if let Ok(mut counts) = live_counts().lock() {
+        counts.remove(&device.handle().as_raw());
+    }
+    if live.is_empty() || std::thread::panicking() {
+        return;
+    }
+    let leaks: Vec<String> = live.iter().map(|(object_type, count)| format!("{:?}: {}", object_type, count)).collect();
+    panic!("Vulkan objects leaked when destroying the device: {}", leaks.join(", "));
+}
+
+#[cfg(all(test, feature = "leak-check"))]
+mod tests {
+    use super::*;
+    use std::ffi::c_void;
+
+    unsafe extern "system" fn destroy_buffer(_: vk::Device, _: vk::Buffer, _: *const vk::AllocationCallbacks) {}
+
+    // A device whose only command is a vkDestroyBuffer that does nothing. Counts are kept per
+    // device handle, so each test takes its own.
+    fn fake_device(handle: u64) -> ash::Device {
+        unsafe {
+            ash::Device::load_with(
+                |name| if name == c"vkDestroyBuffer" { destroy_buffer as *const c_void } else { std::ptr::null() },
+                vk::Device::from_raw(handle),
+            )
+        }
+    }
+
+    #[test]
+    fn live_objects_count_what_was_not_destroyed() {
+        let device = fake_device(0x1eac);
+        let first = created(&device, vk::Buffer::from_raw(1));
+        let second = created(&device, vk::Buffer::from_raw(2));
+        // Null handles aren't counted
+        created(&device, vk::Buffer::null());
+        assert_eq!(live_objects(&device), vec![(vk::ObjectType::BUFFER, 2)]);
+
+        unsafe { destroy(&device, first) };
+        assert_eq!(live_objects(&device), vec![(vk::ObjectType::BUFFER, 1)]);
+        unsafe { destroy(&device, second) };
+        assert!(live_objects(&device).is_empty());
+        assert_no_leaks(&device);
+    }
+
+    #[test]
+    #[should_panic(expected = "BUFFER: 1")]
+    fn leaked_objects_panic_when_the_device_goes() {
+        let device = fake_device(0x1eac + 1);
+        created(&device, vk::Buffer::from_raw(1));
+        assert_no_leaks(&device);
+    }
+}
@@ -1,77 +1,60 @@
 pub mod constants;
+pub mod error;
 pub mod vulkan_common;
+pub mod descriptor_layouts;
+pub mod descriptor_allocator;
+pub mod parallel_recording;
 pub mod vulkan_renderer_unified;
 pub mod mesh;
+pub mod mesh_simplify;
+pub mod primitives;
 pub mod skinned_mesh;
 pub mod mesh_textured;
 pub mod texture;
+pub mod texture_streamer;
 pub mod gltf_loader;
+pub mod obj_loader;
+pub mod random;
+#[cfg(feature = "bevy")]
 pub mod utils;
+#[cfg(feature = "bevy")]
 pub mod fps_logger;
 pub mod camera_controller;
 pub mod egui_integration;
 pub mod memory_pool;
+pub mod frame_pool;
+pub mod leak_check;
+pub mod buffer_residency;
+pub mod instance_culling;
+pub mod instance_sort;
+pub mod draw_plan;
+pub mod shadow_cascades;
+pub mod bone_hierarchy;
+pub mod joint_arena;
+pub mod animation;
+pub mod particles;
+pub mod water;
+pub mod water_reflections;
+pub mod dashed_lines;
+pub mod ground_grid;
+pub mod debug_boxes;
+pub mod render_target;
+pub mod previous_frame;
+pub mod capture;
+pub mod tonemap;
+pub mod dynamic_resolution;
+pub mod frame_pacing;
+pub mod post_process;
+pub mod render_settings;
+pub mod scene;
+#[cfg(feature = "bevy")]
+pub mod bevy_plugin;
+// setup_bevy_app and the focus handling it installs
+#[cfg(feature = "bevy")]
+mod bevy_app;
 
 // Re-export ash for use in consuming applications
 pub use ash;
-
-use bevy::prelude::*;
-use bevy::window::{WindowPlugin, Window};
-use bevy::asset::{AssetPlugin, };
-use bevy::gltf::{GltfPlugin};
-use bevy::render::RenderPlugin;
-use bevy::render::texture::ImagePlugin;
-use bevy::render::settings::WgpuSettings;
-use bevy::pbr::PbrPlugin;
-use bevy::core_pipeline::CorePipelinePlugin;
-use bevy::scene::ScenePlugin;
-use bevy::transform::TransformPlugin;
-use bevy::animation::AnimationPlugin;
-use bevy::winit::{WinitPlugin, WakeUp};
-use bevy::a11y::AccessibilityPlugin;
-use bevy::input::InputPlugin;
-use bevy::input::keyboard::KeyboardFocusLost;
-
-pub fn setup_bevy_app() -> App {
-    setup_bevy_app_with_window(2560.0, 1440.0, "Flo Engine Example")
-}
-
-pub fn setup_bevy_app_with_window(width: f32, height: f32, title: &str) -> App {
-    std::env::set_var("RUST_BACKTRACE", "0");
-
-    let mut app = App::new();
-    app
-        .add_event::<KeyboardFocusLost>()
-        .add_plugins((
-            MinimalPlugins,
-            AssetPlugin::default(),
-            WindowPlugin {
-                primary_window: Some(Window {
-                    title: title.to_string(),
-                    resolution: (width, height).into(),
-                    ..default()
-                }),
-                ..default()
-            },
-            AccessibilityPlugin,
-            InputPlugin::default(),
-            WinitPlugin::<WakeUp>::default(),
-            TransformPlugin,
-            RenderPlugin {
-                render_creation: WgpuSettings {
-                    backends: None,
-                    ..default()
-                }
-                .into(),
-                ..default()
-            },
-            ImagePlugin::default(),
-            CorePipelinePlugin::default(),
-            PbrPlugin::default(),
-            ScenePlugin,
-            GltfPlugin::default(),
-            AnimationPlugin,
-        ));
-
-    app
-}
\ No newline at end of file
+pub use error::FloError;
+#[cfg(feature = "bevy")]
+pub use bevy_app::*;
@@ -1,289 +1,479 @@
-use ash::vk;
-use std::collections::HashMap;
-
-pub struct MemoryPool {
-    device: ash::Device,
-    allocations: Vec<MemoryAllocation>,
-    free_regions: Vec<FreeRegion>,
-    allocation_size: vk::DeviceSize,
-    memory_type_index: u32,
-    total_allocated: usize,
-}
-
-struct MemoryAllocation {
-    memory: vk::DeviceMemory,
-    #[allow(dead_code)]
-    size: vk::DeviceSize,
-}
-
-#[derive(Clone)]
-struct FreeRegion {
-    allocation_index: usize,
-    offset: vk::DeviceSize,
-    size: vk::DeviceSize,
-}
-
-#[derive(Clone)]
-pub struct MemoryBlock {
-    pub memory: vk::DeviceMemory,
-    pub offset: vk::DeviceSize,
-    pub size: vk::DeviceSize,
-    allocation_index: usize,
-    pool_memory_type: u32,
-}
-
-impl MemoryPool {
-    pub fn new(
-        device: ash::Device,
-        memory_type_index: u32,
-        allocation_size: vk::DeviceSize,
-    ) -> Self {
-        Self {
-            device,
-            allocations: Vec::new(),
-            free_regions: Vec::new(),
-            allocation_size: allocation_size.max(256 * 1024 * 1024), // Min 256MB per allocation
-            memory_type_index,
-            total_allocated: 0,
-        }
-    }
-
-    pub fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Result<MemoryBlock, Box<dyn std::error::Error>> {
-        let aligned_size = ((size + alignment - 1) / alignment) * alignment;
-        
-        // Try to find a free region that fits
-        for (i, region) in self.free_regions.iter().enumerate() {
-            let aligned_offset = ((region.offset + alignment - 1) / alignment) * alignment;
-            let padding = aligned_offset - region.offset;
-            
-            if region.size >= aligned_size + padding {
-                let block = MemoryBlock {
-                    memory: self.allocations[region.allocation_index].memory,
-                    offset: aligned_offset,
-                    size: aligned_size,
-                    allocation_index: region.allocation_index,
-                    pool_memory_type: self.memory_type_index,
-                };
-                
-                // Update or remove the free region
-                if region.size > aligned_size + padding {
-                    self.free_regions[i] = FreeRegion {
-                        allocation_index: region.allocation_index,
-                        offset: aligned_offset + aligned_size,
-                        size: region.size - aligned_size - padding,
-                    };
-                } else {
-                    self.free_regions.remove(i);
-                }
-                
-                return Ok(block);
-            }
-        }
-        
-        // Need to allocate a new chunk
-        let chunk_size = self.allocation_size.max(aligned_size);
-        self.allocate_new_chunk(chunk_size)?;
-        
-        // Retry allocation (should succeed now)
-        self.allocate(size, alignment)
-    }
-
-    fn allocate_new_chunk(&mut self, size: vk::DeviceSize) -> Result<(), Box<dyn std::error::Error>> {
-        let alloc_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(size)
-            .memory_type_index(self.memory_type_index);
-        
-        let memory = unsafe { self.device.allocate_memory(&alloc_info, None)? };
-        
-        let allocation_index = self.allocations.len();
-        
-        self.allocations.push(MemoryAllocation {
-            memory,
-            size,
-        });
-        
-        self.free_regions.push(FreeRegion {
-            allocation_index,
-            offset: 0,
-            size,
-        });
-        
-        self.total_allocated += 1;
-        println!("Memory pool: Allocated chunk {} ({:.2} MB), total allocations: {}", 
-                 allocation_index, size as f64 / (1024.0 * 1024.0), self.total_allocated);
-        
-        Ok(())
-    }
-
-    pub fn free(&mut self, block: MemoryBlock) {
-        // Add the freed block back to free regions (simplified - doesn't coalesce)
-        self.free_regions.push(FreeRegion {
-            allocation_index: block.allocation_index,
-            offset: block.offset,
-            size: block.size,
-        });
-        
-        // TODO: Implement coalescing of adjacent free regions
-    }
-
-    pub fn destroy(&mut self) {
-        unsafe {
-            for allocation in &self.allocations {
-                self.device.free_memory(allocation.memory, None);
-            }
-        }
-        self.allocations.clear();
-        self.free_regions.clear();
-    }
-}
-
-pub struct MemoryPoolManager {
-    device: ash::Device,
-    pools: HashMap<u32, MemoryPool>,
-    staging_buffer: Option<vk::Buffer>,
-    staging_memory: Option<vk::DeviceMemory>,
-    staging_size: vk::DeviceSize,
-}
-
-impl MemoryPoolManager {
-    pub fn new(device: ash::Device) -> Self {
-        Self {
-            device,
-            pools: HashMap::new(),
-            staging_buffer: None,
-            staging_memory: None,
-            staging_size: 0,
-        }
-    }
-    
-    pub fn get_staging_buffer(
-        &mut self,
-        instance: &ash::Instance,
-        physical_device: vk::PhysicalDevice,
-        required_size: vk::DeviceSize,
-    ) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
-        // If we need a larger staging buffer, destroy the old one and create a new one
-        if self.staging_size < required_size {
-            // Clean up old staging buffer if it exists
-            if let Some(buffer) = self.staging_buffer {
-                unsafe {
-                    self.device.destroy_buffer(buffer, None);
-                }
-            }
-            if let Some(memory) = self.staging_memory {
-                unsafe {
-                    self.device.free_memory(memory, None);
-                }
-            }
-            
-            // Create a new staging buffer that's at least 16MB or the required size
-            let size = required_size.max(16 * 1024 * 1024);
-            
-            let buffer_info = vk::BufferCreateInfo::default()
-                .size(size)
-                .usage(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE);
-            
-            let buffer = unsafe { self.device.create_buffer(&buffer_info, None)? };
-            
-            let mem_requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
-            
-            let memory_type_index = crate::vulkan_common::find_memory_type(
-                instance,
-                physical_device,
-                mem_requirements.memory_type_bits,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            )?;
-            
-            let alloc_info = vk::MemoryAllocateInfo::default()
-                .allocation_size(size)
-                .memory_type_index(memory_type_index);
-            
-            let memory = unsafe { self.device.allocate_memory(&alloc_info, None)? };
-            unsafe { self.device.bind_buffer_memory(buffer, memory, 0)? };
-            
-            self.staging_buffer = Some(buffer);
-            self.staging_memory = Some(memory);
-            self.staging_size = size;
-            
-            println!("Created reusable staging buffer ({:.2} MB)", size as f64 / (1024.0 * 1024.0));
-        }
-        
-        Ok((self.staging_buffer.unwrap(), self.staging_memory.unwrap()))
-    }
-
-    pub fn allocate_buffer(
-        &mut self,
-        buffer: vk::Buffer,
-        memory_requirements: vk::MemoryRequirements,
-        memory_type_index: u32,
-    ) -> Result<MemoryBlock, Box<dyn std::error::Error>> {
-        let pool = self.pools.entry(memory_type_index).or_insert_with(|| {
-            MemoryPool::new(self.device.clone(), memory_type_index, 256 * 1024 * 1024)
-        });
-        
-        let block = pool.allocate(memory_requirements.size, memory_requirements.alignment)?;
-        
-        unsafe {
-            self.device.bind_buffer_memory(buffer, block.memory, block.offset)?;
-        }
-        
-        Ok(block)
-    }
-
-    pub fn allocate_image(
-        &mut self,
-        image: vk::Image,
-        memory_requirements: vk::MemoryRequirements,
-        memory_type_index: u32,
-    ) -> Result<MemoryBlock, Box<dyn std::error::Error>> {
-        let pool = self.pools.entry(memory_type_index).or_insert_with(|| {
-            MemoryPool::new(self.device.clone(), memory_type_index, 256 * 1024 * 1024)
-        });
-        
-        let block = pool.allocate(memory_requirements.size, memory_requirements.alignment)?;
-        
-        unsafe {
-            self.device.bind_image_memory(image, block.memory, block.offset)?;
-        }
-        
-        Ok(block)
-    }
-
-    pub fn free_buffer(&mut self, block: MemoryBlock) {
-        if let Some(pool) = self.pools.get_mut(&block.pool_memory_type) {
-            pool.free(block);
-        }
-    }
-
-    pub fn destroy(&mut self) {
-        // Clean up staging buffer
-        if let Some(buffer) = self.staging_buffer {
-            unsafe {
-                self.device.destroy_buffer(buffer, None);
-            }
-        }
-        if let Some(memory) = self.staging_memory {
-            unsafe {
-                self.device.free_memory(memory, None);
-            }
-        }
-        
-        // Clean up pools
-        for pool in self.pools.values_mut() {
-            pool.destroy();
-        }
-        self.pools.clear();
-    }
-
-    pub fn get_stats(&self) -> String {
-        let mut total_allocations = 0;
-        let total_pools = self.pools.len();
-        
-        for (_type_index, pool) in &self.pools {
-            total_allocations += pool.total_allocated;
-        }
-        
-        format!("Memory pools: {}, Total GPU allocations: {}", total_pools, total_allocations)
-    }
-}
\ No newline at end of file
+use ash::{vk, Instance};
+use tracing::debug;
+use std::collections::HashMap;
+use std::ptr::NonNull;
+use crate::error::FloError;
+use crate::vulkan_common::{allocate_device_memory, create_buffer, destroy_buffer, free_device_memory, MemoryUsage};
+use crate::leak_check;
+
+// Offsets handed out by DynamicBufferRing, enough for any vertex attribute or index format
+const DYNAMIC_BUFFER_ALIGNMENT: vk::DeviceSize = 16;
+
+pub struct MemoryPool {
+    device: ash::Device,
+    allocations: Vec<MemoryAllocation>,
+    free_list: FreeList,
+    allocation_size: vk::DeviceSize,
+    memory_type_index: u32,
+    total_allocated: usize,
+}
+
+struct MemoryAllocation {
+    memory: vk::DeviceMemory,
+    #[allow(dead_code)]
+    size: vk::DeviceSize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FreeRegion {
+    pub allocation_index: usize,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+// Offset bookkeeping of a MemoryPool, without the Vulkan calls: which ranges of which chunk
+// are free. Chunks are numbered in the order they're added. First fit, freed ranges are merged
+// with the free ranges right before and after them, so freeing everything leaves one range per
+// chunk again.
+#[derive(Default)]
+pub struct FreeList {
+    regions: Vec<FreeRegion>,
+}
+
+impl FreeList {
+    pub fn add_chunk(&mut self, allocation_index: usize, size: vk::DeviceSize) {
+        self.regions.push(FreeRegion { allocation_index, offset: 0, size });
+    }
+
+    // (allocation index, offset) of a range of size bytes rounded up to the alignment, or None
+    // when no free region fits it. Padding in front of the aligned offset stays free.
+    pub fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<(usize, vk::DeviceSize, vk::DeviceSize)> {
+        let alignment = alignment.max(1);
+        let aligned_size = size.div_ceil(alignment) * alignment;
+        let i = self.regions.iter().position(|region| {
+            let padding = region.offset.div_ceil(alignment) * alignment - region.offset;
+            region.size >= aligned_size + padding
+        })?;
+
+        let region = self.regions.swap_remove(i);
+        let aligned_offset = region.offset.div_ceil(alignment) * alignment;
+        let padding = aligned_offset - region.offset;
+        if padding > 0 {
+            self.regions.push(FreeRegion { allocation_index: region.allocation_index, offset: region.offset, size: padding });
+        }
+        let remaining = region.size - aligned_size - padding;
+        if remaining > 0 {
+            self.regions.push(FreeRegion {
+                allocation_index: region.allocation_index,
+                offset: aligned_offset + aligned_size,
+                size: remaining,
+            });
+        }
+        Some((region.allocation_index, aligned_offset, aligned_size))
+    }
+
+    pub fn free(&mut self, allocation_index: usize, mut offset: vk::DeviceSize, mut size: vk::DeviceSize) {
+        if let Some(i) = self.regions.iter().position(|region| region.allocation_index == allocation_index && region.offset + region.size == offset) {
+            let before = self.regions.swap_remove(i);
+            offset = before.offset;
+            size += before.size;
+        }
+        if let Some(i) = self.regions.iter().position(|region| region.allocation_index == allocation_index && region.offset == offset + size) {
+            size += self.regions.swap_remove(i).size;
+        }
+        self.regions.push(FreeRegion { allocation_index, offset, size });
+    }
+
+    pub fn regions(&self) -> &[FreeRegion] {
+        &self.regions
+    }
+
+    pub fn free_bytes(&self) -> vk::DeviceSize {
+        self.regions.iter().map(|region| region.size).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryBlock {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    allocation_index: usize,
+    pool_memory_type: u32,
+}
+
+impl MemoryPool {
+    pub fn new(
+        device: ash::Device,
+        memory_type_index: u32,
+        allocation_size: vk::DeviceSize,
+    ) -> Self {
+        Self {
+            device,
+            allocations: Vec::new(),
+            free_list: FreeList::default(),
+            allocation_size: allocation_size.max(256 * 1024 * 1024), // Min 256MB per allocation
+            memory_type_index,
+            total_allocated: 0,
+        }
+    }
+
+    pub fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Result<MemoryBlock, Box<dyn std::error::Error>> {
+        let range = match self.free_list.allocate(size, alignment) {
+            Some(range) => range,
+            None => {
+                // A new chunk starts at offset 0, which any alignment fits
+                let aligned_size = size.div_ceil(alignment.max(1)) * alignment.max(1);
+                self.allocate_new_chunk(self.allocation_size.max(aligned_size))?;
+                self.free_list.allocate(size, alignment).ok_or("New memory pool chunk doesn't fit the allocation")?
+            }
+        };
+        let (allocation_index, offset, size) = range;
+        Ok(MemoryBlock {
+            memory: self.allocations[allocation_index].memory,
+            offset,
+            size,
+            allocation_index,
+            pool_memory_type: self.memory_type_index,
+        })
+    }
+
+    fn allocate_new_chunk(&mut self, size: vk::DeviceSize) -> Result<(), Box<dyn std::error::Error>> {
+        let alloc_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(self.memory_type_index);
+        
+        let memory = unsafe { allocate_device_memory(&self.device, &alloc_info, MemoryUsage::Pool)? };
+        
+        let allocation_index = self.allocations.len();
+        
+        self.allocations.push(MemoryAllocation {
+            memory,
+            size,
+        });
+        
+        self.free_list.add_chunk(allocation_index, size);
+        
+        self.total_allocated += 1;
+        debug!("Memory pool: Allocated chunk {} ({:.2} MB), total allocations: {}", 
+                 allocation_index, size as f64 / (1024.0 * 1024.0), self.total_allocated);
+        
+        Ok(())
+    }
+
+    pub fn free(&mut self, block: MemoryBlock) {
+        self.free_list.free(block.allocation_index, block.offset, block.size);
+    }
+
+    pub fn destroy(&mut self) {
+        unsafe {
+            for allocation in &self.allocations {
+                free_device_memory(&self.device, allocation.memory);
+            }
+        }
+        self.allocations.clear();
+        self.free_list.clear();
+    }
+}
+
+pub struct MemoryPoolManager {
+    device: ash::Device,
+    pools: HashMap<u32, MemoryPool>,
+    staging_buffer: Option<vk::Buffer>,
+    staging_memory: Option<vk::DeviceMemory>,
+    staging_size: vk::DeviceSize,
+}
+
+impl MemoryPoolManager {
+    pub fn new(device: ash::Device) -> Self {
+        Self {
+            device,
+            pools: HashMap::new(),
+            staging_buffer: None,
+            staging_memory: None,
+            staging_size: 0,
+        }
+    }
+    
+    pub fn get_staging_buffer(
+        &mut self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        required_size: vk::DeviceSize,
+    ) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
+        // If we need a larger staging buffer, destroy the old one and create a new one
+        if self.staging_size < required_size {
+            // Clean up old staging buffer if it exists
+            if let Some(buffer) = self.staging_buffer {
+                unsafe {
+                    leak_check::destroy(&self.device, buffer);
+                }
+            }
+            if let Some(memory) = self.staging_memory {
+                unsafe {
+                    free_device_memory(&self.device, memory);
+                }
+            }
+            
+            // Create a new staging buffer that's at least 16MB or the required size
+            let size = required_size.max(16 * 1024 * 1024);
+            
+            let buffer_info = vk::BufferCreateInfo::default()
+                .size(size)
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            
+            let buffer = leak_check::created(&self.device, unsafe { self.device.create_buffer(&buffer_info, None)? });
+            
+            let mem_requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+            
+            let memory_type_index = crate::vulkan_common::find_memory_type(
+                instance,
+                physical_device,
+                mem_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            
+            let alloc_info = vk::MemoryAllocateInfo::default()
+                .allocation_size(size)
+                .memory_type_index(memory_type_index);
+            
+            let memory = unsafe { allocate_device_memory(&self.device, &alloc_info, MemoryUsage::Staging)? };
+            unsafe { self.device.bind_buffer_memory(buffer, memory, 0)? };
+            
+            self.staging_buffer = Some(buffer);
+            self.staging_memory = Some(memory);
+            self.staging_size = size;
+            
+            debug!("Created reusable staging buffer ({:.2} MB)", size as f64 / (1024.0 * 1024.0));
+        }
+        
+        Ok((self.staging_buffer.unwrap(), self.staging_memory.unwrap()))
+    }
+
+    pub fn allocate_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        memory_requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+    ) -> Result<MemoryBlock, Box<dyn std::error::Error>> {
+        let pool = self.pools.entry(memory_type_index).or_insert_with(|| {
+            MemoryPool::new(self.device.clone(), memory_type_index, 256 * 1024 * 1024)
+        });
+        
+        let block = pool.allocate(memory_requirements.size, memory_requirements.alignment)?;
+        
+        unsafe {
+            self.device.bind_buffer_memory(buffer, block.memory, block.offset)?;
+        }
+        
+        Ok(block)
+    }
+
+    pub fn allocate_image(
+        &mut self,
+        image: vk::Image,
+        memory_requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+    ) -> Result<MemoryBlock, Box<dyn std::error::Error>> {
+        let pool = self.pools.entry(memory_type_index).or_insert_with(|| {
+            MemoryPool::new(self.device.clone(), memory_type_index, 256 * 1024 * 1024)
+        });
+        
+        let block = pool.allocate(memory_requirements.size, memory_requirements.alignment)?;
+        
+        unsafe {
+            self.device.bind_image_memory(image, block.memory, block.offset)?;
+        }
+        
+        Ok(block)
+    }
+
+    pub fn free_buffer(&mut self, block: MemoryBlock) {
+        if let Some(pool) = self.pools.get_mut(&block.pool_memory_type) {
+            pool.free(block);
+        }
+    }
+
+    pub fn destroy(&mut self) {
+        // Clean up staging buffer
+        if let Some(buffer) = self.staging_buffer.take() {
+            unsafe {
+                leak_check::destroy(&self.device, buffer);
+            }
+        }
+        if let Some(memory) = self.staging_memory.take() {
+            unsafe {
+                free_device_memory(&self.device, memory);
+            }
+        }
+        
+        // Clean up pools
+        for pool in self.pools.values_mut() {
+            pool.destroy();
+        }
+        self.pools.clear();
+        self.staging_size = 0;
+    }
+
+    pub fn get_stats(&self) -> String {
+        let mut total_allocations = 0;
+        let mut free_bytes = 0;
+        let total_pools = self.pools.len();
+        
+        for (_type_index, pool) in &self.pools {
+            total_allocations += pool.total_allocated;
+            free_bytes += pool.free_list.free_bytes();
+        }
+        
+        format!("Memory pools: {}, Total GPU allocations: {}, Free: {:.1} MB",
+                total_pools, total_allocations, free_bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+// Scratch space for geometry rebuilt every frame, e.g. dashed lines and debug boxes. One
+// persistently mapped host visible buffer is split into a region of budget bytes per frame in
+// flight. Writes during a frame are bump allocated from its region, and begin_frame recycles
+// them all at once after the frame's fence was waited on, so the CPU never overwrites data a
+// frame in flight still draws from.
+pub struct DynamicBufferRing {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: NonNull<u8>,
+    budget: vk::DeviceSize,
+    // Bytes used of each frame's region
+    used: Vec<vk::DeviceSize>,
+    frame: usize,
+}
+
+// The mapping is only written through &mut self, so the ring can move between threads like the
+// rest of the renderer
+unsafe impl Send for DynamicBufferRing {}
+
+impl DynamicBufferRing {
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        frame_count: usize,
+        budget: vk::DeviceSize,
+    ) -> Result<Self, FloError> {
+        let budget = budget.div_ceil(DYNAMIC_BUFFER_ALIGNMENT).max(1) * DYNAMIC_BUFFER_ALIGNMENT;
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            physical_device,
+            budget * frame_count as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mapped = unsafe { device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) };
+        let Some(mapped) = mapped.ok().and_then(|mapped| NonNull::new(mapped as *mut u8)) else {
+            destroy_buffer(device, buffer, memory);
+            return Err("Failed to map dynamic buffer ring".into());
+        };
+        debug!("Created dynamic buffer ring, {} frames of {:.2} MB", frame_count, budget as f64 / (1024.0 * 1024.0));
+        Ok(Self { buffer, memory, mapped, budget, used: vec![0; frame_count], frame: 0 })
+    }
+
+    // Starts writing into frame's region, dropping what it held. Only call once the frame's
+    // fence was waited on.
+    pub fn begin_frame(&mut self, frame: usize) {
+        self.frame = frame;
+        self.used[frame] = 0;
+    }
+
+    // Copies bytes into the current frame's region and returns the buffer and offset to bind
+    // them at. Fails when the frame has used up its budget.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(vk::Buffer, vk::DeviceSize), FloError> {
+        let used = self.used[self.frame];
+        let size = bytes.len() as vk::DeviceSize;
+        if used + size > self.budget {
+            return Err(format!(
+                "Dynamic buffer ring needs {} bytes this frame but its budget is {}, raise RendererConfig::dynamic_buffer_budget",
+                used + size, self.budget
+            ).into());
+        }
+        let offset = self.frame as vk::DeviceSize * self.budget + used;
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.mapped.as_ptr().add(offset as usize), bytes.len());
+        }
+        self.used[self.frame] = (used + size).div_ceil(DYNAMIC_BUFFER_ALIGNMENT) * DYNAMIC_BUFFER_ALIGNMENT;
+        Ok((self.buffer, offset))
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.unmap_memory(self.memory);
+        }
+        destroy_buffer(device, self.buffer, self.memory);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(size: vk::DeviceSize) -> FreeList {
+        let mut free_list = FreeList::default();
+        free_list.add_chunk(0, size);
+        free_list
+    }
+
+    #[test]
+    fn allocations_are_first_fit_and_disjoint() {
+        let mut free_list = chunk(1024);
+        assert_eq!(free_list.allocate(100, 1), Some((0, 0, 100)));
+        assert_eq!(free_list.allocate(200, 1), Some((0, 100, 200)));
+        assert_eq!(free_list.free_bytes(), 1024 - 300);
+    }
+
+    #[test]
+    fn allocations_round_up_to_the_alignment() {
+        let mut free_list = chunk(1024);
+        assert_eq!(free_list.allocate(10, 1), Some((0, 0, 10)));
+        // The 6 bytes of padding in front stay free
+        assert_eq!(free_list.allocate(20, 16), Some((0, 16, 32)));
+        assert_eq!(free_list.allocate(4, 4), Some((0, 12, 4)));
+        assert_eq!(free_list.free_bytes(), 1024 - 10 - 32 - 4);
+    }
+
+    #[test]
+    fn exhausted_list_returns_none() {
+        let mut free_list = chunk(256);
+        assert!(free_list.allocate(257, 1).is_none());
+        assert_eq!(free_list.allocate(256, 1), Some((0, 0, 256)));
+        assert!(free_list.allocate(1, 1).is_none());
+
+        // A second chunk takes what the first can't
+        free_list.add_chunk(1, 512);
+        assert_eq!(free_list.allocate(300, 1), Some((1, 0, 300)));
+    }
+
+    #[test]
+    fn freed_neighbours_coalesce() {
+        let mut free_list = chunk(300);
+        let blocks: Vec<_> = (0..3).map(|_| free_list.allocate(100, 1).unwrap()).collect();
+        assert!(free_list.regions().is_empty());
+
+        // Freed out of order, the middle one bridges the other two
+        for &(allocation_index, offset, size) in [&blocks[0], &blocks[2], &blocks[1]] {
+            free_list.free(allocation_index, offset, size);
+        }
+        assert_eq!(free_list.regions(), &[FreeRegion { allocation_index: 0, offset: 0, size: 300 }]);
+        assert_eq!(free_list.allocate(300, 1), Some((0, 0, 300)));
+    }
+
+    #[test]
+    fn ranges_of_different_chunks_stay_apart() {
+        let mut free_list = FreeList::default();
+        free_list.add_chunk(0, 100);
+        free_list.add_chunk(1, 100);
+        let first = free_list.allocate(100, 1).unwrap();
+        free_list.free(first.0, first.1, first.2);
+        assert_eq!(free_list.regions().len(), 2);
+    }
+}
@@ -63,16 +63,551 @@ impl Vertex {
     }
 }
 
+// Vertex layout of a mesh at binding 0, for VulkanRenderer::add_mesh_with_format,
+// add_pipeline_with_vertex_format and new_mesh_with_format. Implement attribute_descriptions
+// for custom vertices, e.g. with a second uv set or bending weights; the locations have to
+// match the vertex shader's inputs.
+pub trait VertexFormat: Copy {
+    fn attribute_descriptions() -> Vec<ash::vk::VertexInputAttributeDescription>;
+
+    fn stride() -> u32 {
+        std::mem::size_of::<Self>() as u32
+    }
+
+    fn binding_description() -> ash::vk::VertexInputBindingDescription {
+        ash::vk::VertexInputBindingDescription::default()
+            .binding(0)
+            .stride(Self::stride())
+            .input_rate(ash::vk::VertexInputRate::VERTEX)
+    }
+
+    // Local position for the mesh's culling bounds, formats without one are never culled
+    fn position(&self) -> Option<[f32; 3]> {
+        None
+    }
+}
+
+impl VertexFormat for Vertex {
+    fn attribute_descriptions() -> Vec<ash::vk::VertexInputAttributeDescription> {
+        Vertex::get_attribute_descriptions()
+    }
+
+    fn position(&self) -> Option<[f32; 3]> {
+        Some(self.position)
+    }
+}
+
+// First location past the given attributes, where instance attributes appended after a vertex
+// format go. Growing the vertex layout then moves them along instead of aliasing its attributes.
+pub fn next_attribute_location(attributes: &[ash::vk::VertexInputAttributeDescription]) -> u32 {
+    attributes.iter().map(|attribute| attribute.location + 1).max().unwrap_or(0)
+}
+
+// Per instance data of meshes added with VulkanRenderer::add_mesh_instanced_with_colors, at
+// binding 1 after the Vertex attributes: the transform's columns at locations 4 to 7, the
+// color at 8 and the texture array layer at 9. Meshes added with add_mesh_instanced keep
+// position only instances, so the existing instanced shaders still work.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceData {
+    pub transform: [f32; 16],
+    pub color: [f32; 4],
+    // Layer of the mesh's texture array, see VulkanRenderer::set_mesh_texture_array
+    pub texture_index: u32,
+}
+
+impl InstanceData {
+    pub fn new(transform: bevy_math::Mat4, color: [f32; 4]) -> Self {
+        Self {
+            transform: transform.to_cols_array(),
+            color,
+            texture_index: 0,
+        }
+    }
+    
+    pub fn get_binding_description() -> ash::vk::VertexInputBindingDescription {
+        ash::vk::VertexInputBindingDescription::default()
+            .binding(1)
+            .stride(std::mem::size_of::<InstanceData>() as u32)
+            .input_rate(ash::vk::VertexInputRate::INSTANCE)
+    }
+    
+    pub fn get_attribute_descriptions() -> Vec<ash::vk::VertexInputAttributeDescription> {
+        let column_size = std::mem::size_of::<[f32; 4]>() as u32;
+        // A mat4 attribute takes one location per column
+        let mut attributes: Vec<_> = (0..4)
+            .map(|column| {
+                ash::vk::VertexInputAttributeDescription::default()
+                    .binding(1)
+                    .location(4 + column)
+                    .format(ash::vk::Format::R32G32B32A32_SFLOAT)
+                    .offset(column * column_size)
+            })
+            .collect();
+        // Color
+        attributes.push(
+            ash::vk::VertexInputAttributeDescription::default()
+                .binding(1)
+                .location(8)
+                .format(ash::vk::Format::R32G32B32A32_SFLOAT)
+                .offset(memoffset::offset_of!(InstanceData, color) as u32),
+        );
+        // Texture array layer
+        attributes.push(
+            ash::vk::VertexInputAttributeDescription::default()
+                .binding(1)
+                .location(9)
+                .format(ash::vk::Format::R32_UINT)
+                .offset(memoffset::offset_of!(InstanceData, texture_index) as u32),
+        );
+        attributes
+    }
+}
+
+// How a mesh's vertex attributes are stored on the GPU. Deinterleaved keeps positions,
+// normals and uvs in their own buffers at bindings 0, 1 and 2, so a dynamic mesh can
+// reupload only the stream that changed. Vertex colors aren't kept in that layout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshLayout {
+    #[default]
+    Interleaved,
+    Deinterleaved,
+}
+
+impl MeshLayout {
+    pub fn get_binding_descriptions(&self) -> Vec<ash::vk::VertexInputBindingDescription> {
+        match self {
+            MeshLayout::Interleaved => vec![Vertex::get_binding_description()],
+            MeshLayout::Deinterleaved => {
+                let strides = [
+                    std::mem::size_of::<[f32; 3]>(),  // Position
+                    std::mem::size_of::<[f32; 3]>(),  // Normal
+                    std::mem::size_of::<[f32; 2]>(),  // UV
+                ];
+                strides.iter().enumerate()
+                    .map(|(binding, &stride)| {
+                        ash::vk::VertexInputBindingDescription::default()
+                            .binding(binding as u32)
+                            .stride(stride as u32)
+                            .input_rate(ash::vk::VertexInputRate::VERTEX)
+                    })
+                    .collect()
+            }
+        }
+    }
+    
+    pub fn get_attribute_descriptions(&self) -> Vec<ash::vk::VertexInputAttributeDescription> {
+        match self {
+            MeshLayout::Interleaved => Vertex::get_attribute_descriptions(),
+            MeshLayout::Deinterleaved => {
+                let formats = [
+                    ash::vk::Format::R32G32B32_SFLOAT,  // Position
+                    ash::vk::Format::R32G32B32_SFLOAT,  // Normal
+                    ash::vk::Format::R32G32_SFLOAT,  // UV
+                ];
+                // One attribute per binding, with the same locations as the interleaved layout
+                formats.iter().enumerate()
+                    .map(|(index, &format)| {
+                        ash::vk::VertexInputAttributeDescription::default()
+                            .binding(index as u32)
+                            .location(index as u32)
+                            .format(format)
+                            .offset(0)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// Index value that ends the current strip or fan, the next index starts a new one.
+// Fixed by the UINT32 index type every mesh is drawn with.
+pub const PRIMITIVE_RESTART_INDEX: u32 = u32::MAX;
+
+// How a mesh's indices (or vertices, without indices) are assembled into triangles.
+// Strips and fans can be split with PRIMITIVE_RESTART_INDEX. The pipeline a mesh is drawn
+// with has to be built with the same topology (see PipelineBuilder::with_topology).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshTopology {
+    #[default]
+    TriangleList,
+    TriangleStrip,
+    TriangleFan,
+}
+
+impl MeshTopology {
+    pub fn to_vk(self) -> ash::vk::PrimitiveTopology {
+        match self {
+            MeshTopology::TriangleList => ash::vk::PrimitiveTopology::TRIANGLE_LIST,
+            MeshTopology::TriangleStrip => ash::vk::PrimitiveTopology::TRIANGLE_STRIP,
+            MeshTopology::TriangleFan => ash::vk::PrimitiveTopology::TRIANGLE_FAN,
+        }
+    }
+}
+
+// Default reach of baked ambient occlusion, as a fraction of the mesh's bounds diagonal
+const AO_RADIUS_FRACTION: f32 = 0.2;
+
 pub struct MeshData {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
+    pub topology: MeshTopology,
+    // Both sides are drawn and lit, e.g. from glTF's doubleSided. Applied when the mesh is
+    // added, see VulkanRenderer::set_mesh_double_sided.
+    pub double_sided: bool,
 }
 
 impl MeshData {
     pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
-        Self { vertices, indices }
+        Self { vertices, indices, topology: MeshTopology::TriangleList, double_sided: false }
+    }
+    
+    pub fn with_topology(mut self, topology: MeshTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+    
+    pub fn with_double_sided(mut self, double_sided: bool) -> Self {
+        self.double_sided = double_sided;
+        self
+    }
+    
+    // Multiplies every UV, e.g. to tile a texture across a large primitive
+    pub fn with_uv_scale(mut self, scale: [f32; 2]) -> Self {
+        for vertex in &mut self.vertices {
+            vertex.uv = [vertex.uv[0] * scale[0], vertex.uv[1] * scale[1]];
+        }
+        self
+    }
+    
+    // Axis aligned (min, max) of the vertex positions, both zero for a mesh without vertices.
+    // Walks every vertex, the renderer keeps its own copy per added mesh (MeshEntry::local_bounds).
+    pub fn bounds(&self) -> (bevy_math::Vec3, bevy_math::Vec3) {
+        let first = match self.vertices.first() {
+            Some(vertex) => bevy_math::Vec3::from(vertex.position),
+            None => return (bevy_math::Vec3::ZERO, bevy_math::Vec3::ZERO),
+        };
+        
+        self.vertices.iter().fold((first, first), |(min, max), vertex| {
+            let position = bevy_math::Vec3::from(vertex.position);
+            (min.min(position), max.max(position))
+        })
+    }
+    
+    // Center of the surface, the triangles' centers weighted by their area. Unlike the average
+    // of the vertices it doesn't move toward densely tessellated parts. Falls back to the vertex
+    // average when all triangles are degenerate, zero for a mesh without vertices.
+    pub fn centroid(&self) -> bevy_math::Vec3 {
+        let (weighted_sum, area) = self.triangles().iter().fold((bevy_math::Vec3::ZERO, 0.0), |(sum, area), triangle| {
+            let [a, b, c] = triangle.map(|index| bevy_math::Vec3::from(self.vertices[index as usize].position));
+            let triangle_area = (b - a).cross(c - a).length() * 0.5;
+            (sum + (a + b + c) / 3.0 * triangle_area, area + triangle_area)
+        });
+        if area > 0.0 {
+            return weighted_sum / area;
+        }
+        if self.vertices.is_empty() {
+            return bevy_math::Vec3::ZERO;
+        }
+        let vertex_sum: bevy_math::Vec3 = self.vertices.iter().map(|vertex| bevy_math::Vec3::from(vertex.position)).sum();
+        vertex_sum / self.vertices.len() as f32
+    }
+    
+    pub fn surface_area(&self) -> f32 {
+        self.triangles().iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.map(|index| bevy_math::Vec3::from(self.vertices[index as usize].position));
+                (b - a).cross(c - a).length() * 0.5
+            })
+            .sum()
+    }
+    
+    // Vertex indices of each triangle, following the topology and primitive restarts. Meshes
+    // without indices use their vertices in order. Out of range indices are skipped.
+    pub(crate) fn triangles(&self) -> Vec<[u32; 3]> {
+        let sequential: Vec<u32>;
+        let indices = if self.indices.is_empty() {
+            sequential = (0..self.vertices.len() as u32).collect();
+            &sequential
+        } else {
+            &self.indices
+        };
+        
+        let mut triangles = Vec::new();
+        match self.topology {
+            MeshTopology::TriangleList => {
+                triangles.extend(indices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]));
+            }
+            MeshTopology::TriangleStrip => {
+                for strip in indices.split(|&index| index == PRIMITIVE_RESTART_INDEX) {
+                    // Every odd triangle of a strip is drawn with its last two corners swapped,
+                    // so all of them wind the same way
+                    triangles.extend(strip.windows(3).enumerate().map(|(i, triangle)| if i % 2 == 0 {
+                        [triangle[0], triangle[1], triangle[2]]
+                    } else {
+                        [triangle[0], triangle[2], triangle[1]]
+                    }));
+                }
+            }
+            MeshTopology::TriangleFan => {
+                for fan in indices.split(|&index| index == PRIMITIVE_RESTART_INDEX) {
+                    if let Some((&center, rim)) = fan.split_first() {
+                        triangles.extend(rim.windows(2).map(|edge| [center, edge[0], edge[1]]));
+                    }
+                }
+            }
+        }
+        triangles.retain(|triangle| triangle.iter().all(|&index| (index as usize) < self.vertices.len()));
+        triangles
+    }
+    
+    // Winding of the triangles relative to the vertex normals: COUNTER_CLOCKWISE when the
+    // right-handed face normals (b - a) x (c - a) point the same way as the vertex normals, like
+    // glTF and OBJ, CLOCKWISE when most point against them. Triangles vote with their area.
+    // Meshes without usable normals count as COUNTER_CLOCKWISE.
+    pub fn detect_winding(&self) -> ash::vk::FrontFace {
+        let agreement: f32 = self.triangles().iter()
+            .map(|&triangle| self.winding_agreement(triangle))
+            .sum();
+        if agreement < 0.0 {
+            ash::vk::FrontFace::CLOCKWISE
+        } else {
+            ash::vk::FrontFace::COUNTER_CLOCKWISE
+        }
+    }
+    
+    // Flips every triangle whose face normal points against its vertex normals, so the whole mesh
+    // winds COUNTER_CLOCKWISE in the sense of detect_winding and draws with the same pipeline as
+    // other imports. Returns the number of flipped triangles. Only triangle lists are fixed, since
+    // single triangles of strips and fans can't be flipped on their own. Triangles whose vertex
+    // normals lie in their plane, or are missing, are left as they are.
+    pub fn fix_winding(&mut self) -> usize {
+        if self.topology != MeshTopology::TriangleList {
+            return 0;
+        }
+        let vertex_count = self.vertices.len();
+        let mut flipped = 0;
+        for first in (0..self.triangle_list_len()).step_by(3) {
+            let triangle = if self.indices.is_empty() {
+                [first as u32, first as u32 + 1, first as u32 + 2]
+            } else {
+                [self.indices[first], self.indices[first + 1], self.indices[first + 2]]
+            };
+            if triangle.iter().any(|&index| index as usize >= vertex_count) || self.winding_agreement(triangle) >= 0.0 {
+                continue;
+            }
+            // Swapping two corners reverses the winding, meshes without indices swap the vertices
+            if self.indices.is_empty() {
+                self.vertices.swap(first + 1, first + 2);
+            } else {
+                self.indices.swap(first + 1, first + 2);
+            }
+            flipped += 1;
+        }
+        flipped
+    }
+    
+    // Turns the mesh inside out: normals are negated and every triangle's winding reversed, so
+    // a closed mesh (a sky dome, a room) is seen from inside with the same pipeline and cull
+    // mode it'd be seen from outside with
+    pub fn invert(&mut self) {
+        for vertex in &mut self.vertices {
+            vertex.normal = vertex.normal.map(|component| -component);
+        }
+        match self.topology {
+            MeshTopology::TriangleList => {
+                for first in (0..self.triangle_list_len()).step_by(3) {
+                    if self.indices.is_empty() {
+                        self.vertices.swap(first + 1, first + 2);
+                    } else {
+                        self.indices.swap(first + 1, first + 2);
+                    }
+                }
+            }
+            // Repeating a strip's first vertex adds a degenerate triangle and shifts every other
+            // triangle's parity, which reverses their winding
+            MeshTopology::TriangleStrip if self.indices.is_empty() => {
+                if let Some(&first) = self.vertices.first() {
+                    self.vertices.insert(0, first);
+                }
+            }
+            MeshTopology::TriangleStrip => {
+                let mut indices = Vec::with_capacity(self.indices.len() + self.indices.len() / 4);
+                for strip in self.indices.split(|&index| index == PRIMITIVE_RESTART_INDEX) {
+                    if let Some(&first) = strip.first() {
+                        indices.push(first);
+                    }
+                    indices.extend_from_slice(strip);
+                    indices.push(PRIMITIVE_RESTART_INDEX);
+                }
+                indices.pop();
+                self.indices = indices;
+            }
+            // Reversing the rim around the center vertex reverses the winding
+            MeshTopology::TriangleFan if self.indices.is_empty() => {
+                if self.vertices.len() > 1 {
+                    self.vertices[1..].reverse();
+                }
+            }
+            MeshTopology::TriangleFan => {
+                for fan in self.indices.split_mut(|&index| index == PRIMITIVE_RESTART_INDEX) {
+                    if fan.len() > 1 {
+                        fan[1..].reverse();
+                    }
+                }
+            }
+        }
+    }
+    
+    // Drops triangles with an area of at most epsilon², the area of a square with epsilon sides,
+    // and rewrites the mesh as indices of the remaining triangles with unused vertices removed.
+    // With weld, vertices within epsilon of an earlier vertex with the same normal, uv and color
+    // are merged into it first, so triangles the weld collapses go too while UV and normal seams
+    // stay split. Returns the number of dropped triangles. Only triangle lists are cleaned, strips
+    // use degenerate triangles on purpose to join their pieces.
+    pub fn remove_degenerate_triangles(&mut self, epsilon: f32, weld: bool) -> usize {
+        if self.topology != MeshTopology::TriangleList {
+            return 0;
+        }
+        
+        // Each vertex's index after welding. Positions are bucketed in cells at least epsilon
+        // wide, so a match is in the same or a neighboring cell.
+        let mut welded: Vec<u32> = (0..self.vertices.len() as u32).collect();
+        if weld {
+            let cell_size = epsilon.max(f32::EPSILON);
+            let mut cells: std::collections::HashMap<[i64; 3], Vec<u32>> = std::collections::HashMap::new();
+            for (index, vertex) in self.vertices.iter().enumerate() {
+                let [x, y, z] = vertex.position.map(|component| (component / cell_size).floor() as i64);
+                let position = bevy_math::Vec3::from(vertex.position);
+                let matching = (-1..=1)
+                    .flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [x + dx, y + dy, z + dz])))
+                    .filter_map(|neighbor| cells.get(&neighbor))
+                    .flatten()
+                    .copied()
+                    .find(|&other| {
+                        let other = &self.vertices[other as usize];
+                        bevy_math::Vec3::from(other.position).distance(position) <= epsilon
+                            && other.normal == vertex.normal
+                            && other.uv == vertex.uv
+                            && other.color == vertex.color
+                    });
+                match matching {
+                    Some(other) => welded[index] = other,
+                    None => cells.entry([x, y, z]).or_default().push(index as u32),
+                }
+            }
+        }
+        
+        let min_area = epsilon * epsilon;
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut removed = 0;
+        for triangle in self.triangles() {
+            // Welded corners make the area zero, so collapsed triangles fail the same test
+            let triangle = triangle.map(|index| welded[index as usize]);
+            let [a, b, c] = triangle.map(|index| bevy_math::Vec3::from(self.vertices[index as usize].position));
+            if (b - a).cross(c - a).length() * 0.5 <= min_area {
+                removed += 1;
+                continue;
+            }
+            for index in triangle {
+                if remap[index as usize] == u32::MAX {
+                    remap[index as usize] = vertices.len() as u32;
+                    vertices.push(self.vertices[index as usize]);
+                }
+                indices.push(remap[index as usize]);
+            }
+        }
+        self.vertices = vertices;
+        self.indices = indices;
+        removed
     }
     
+    // Bakes ambient occlusion into the vertex color alpha, which mesh.frag multiplies into its
+    // ambient term: 1 is open, 0 fully occluded. Hits count within AO_RADIUS_FRACTION of the
+    // bounds diagonal, see bake_vertex_ao_with_radius.
+    pub fn bake_vertex_ao(&mut self, samples: u32) {
+        let (min, max) = self.bounds();
+        self.bake_vertex_ao_with_radius(samples, (max - min).length() * AO_RADIUS_FRACTION);
+    }
+    
+    // Casts samples cosine weighted rays over each vertex's normal hemisphere against the mesh's
+    // own triangles. A ray's occlusion falls off linearly to 0 at radius, so only nearby geometry
+    // darkens a vertex. Triangles using the vertex are skipped, so it doesn't shadow itself.
+    // Brute force, meant for static meshes baked once. Rays escape through holes of meshes that
+    // aren't closed, which only leaves those vertices lighter. Vertices without a normal get 1.
+    pub fn bake_vertex_ao_with_radius(&mut self, samples: u32, radius: f32) {
+        if samples == 0 || radius <= 0.0 {
+            return;
+        }
+        let triangles: Vec<([u32; 3], [bevy_math::Vec3; 3])> = self.triangles().into_iter()
+            .map(|triangle| (triangle, triangle.map(|index| bevy_math::Vec3::from(self.vertices[index as usize].position))))
+            .collect();
+        // Cosine weighted directions around +z, turned onto each normal below
+        let directions: Vec<bevy_math::Vec3> = (0..samples)
+            .map(|i| {
+                let u = (i as f32 + 0.5) / samples as f32;
+                let phi = std::f32::consts::TAU * (i.reverse_bits() as f32 / 2f32.powi(32));
+                let r = u.sqrt();
+                bevy_math::Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u).sqrt())
+            })
+            .collect();
+        // Average center of the triangles around each vertex
+        let mut neighborhoods = vec![(bevy_math::Vec3::ZERO, 0.0); self.vertices.len()];
+        for (indices, corners) in &triangles {
+            for &index in indices {
+                let (sum, count) = &mut neighborhoods[index as usize];
+                *sum += (corners[0] + corners[1] + corners[2]) / 3.0;
+                *count += 1.0;
+            }
+        }
+        let offset = radius * 1e-4;
+        
+        for (vertex_index, vertex) in self.vertices.iter_mut().enumerate() {
+            let normal = bevy_math::Vec3::from(vertex.normal).normalize_or_zero();
+            if normal == bevy_math::Vec3::ZERO {
+                vertex.color[3] = 1.0;
+                continue;
+            }
+            let (tangent, bitangent) = normal.any_orthonormal_pair();
+            // Off the surface and into the vertex's own triangles, so a ray leaving through a
+            // face that meets it at a hard edge hits that face instead of grazing its border
+            let position = bevy_math::Vec3::from(vertex.position);
+            let (sum, count) = neighborhoods[vertex_index];
+            let inward = (sum - position * count).normalize_or_zero();
+            let origin = position + normal * offset + inward * offset;
+            
+            let occlusion: f32 = directions.iter()
+                .map(|local| {
+                    let direction = tangent * local.x + bitangent * local.y + normal * local.z;
+                    let nearest = triangles.iter()
+                        .filter(|(indices, _)| !indices.contains(&(vertex_index as u32)))
+                        .filter_map(|(_, corners)| ray_triangle_distance(origin, direction, corners))
+                        .fold(radius, f32::min);
+                    1.0 - nearest / radius
+                })
+                .sum();
+            vertex.color[3] = 1.0 - occlusion / samples as f32;
+        }
+    }
+    
+    fn triangle_list_len(&self) -> usize {
+        let len = if self.indices.is_empty() { self.vertices.len() } else { self.indices.len() };
+        len - len % 3
+    }
+    
+    // Positive when the triangle's face normal agrees with its vertex normals, negative when it
+    // points against them, scaled by twice the area
+    fn winding_agreement(&self, triangle: [u32; 3]) -> f32 {
+        let [a, b, c] = triangle.map(|index| &self.vertices[index as usize]);
+        let face_normal = (bevy_math::Vec3::from(b.position) - bevy_math::Vec3::from(a.position))
+            .cross(bevy_math::Vec3::from(c.position) - bevy_math::Vec3::from(a.position));
+        let vertex_normal = bevy_math::Vec3::from(a.normal) + bevy_math::Vec3::from(b.normal) + bevy_math::Vec3::from(c.normal);
+        face_normal.dot(vertex_normal.normalize_or_zero())
+    }
+    
+    #[cfg(feature = "bevy")]
     pub fn from_bevy_mesh(mesh: &bevy::render::mesh::Mesh) -> Option<Self> {
         use bevy::render::mesh::VertexAttributeValues;
         
@@ -111,8 +646,395 @@ impl MeshData {
             vertices.push(Vertex::with_color(position, normal, uv, color));
         }
         
-        let indices = mesh.indices()?.iter().map(|i| i as u32).collect();
+        let topology = match mesh.primitive_topology() {
+            bevy::render::mesh::PrimitiveTopology::TriangleList => MeshTopology::TriangleList,
+            bevy::render::mesh::PrimitiveTopology::TriangleStrip => MeshTopology::TriangleStrip,
+            _ => return None,
+        };
+        
+        // 16 bit strips restart at u16::MAX, which is an ordinary index once widened. Meshes
+        // without indices keep none and are drawn from their vertices in order.
+        let indices = match mesh.indices() {
+            Some(bevy_indices) => {
+                let restart_index = match bevy_indices {
+                    bevy::render::mesh::Indices::U16(_) => u16::MAX as usize,
+                    bevy::render::mesh::Indices::U32(_) => u32::MAX as usize,
+                };
+                bevy_indices.iter()
+                    .map(|i| if topology != MeshTopology::TriangleList && i == restart_index { PRIMITIVE_RESTART_INDEX } else { i as u32 })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
         
-        Some(MeshData::new(vertices, indices))
+        Some(MeshData::new(vertices, indices).with_topology(topology))
     }
-}
\ No newline at end of file
+    
+    // Bake each mesh's transform into its vertices and combine everything into one mesh,
+    // so static geometry can be drawn from a single buffer with a single draw call.
+    // The result is an indexed triangle list: strips and fans are split into their triangles
+    // and meshes without indices use their vertices in order. It's double sided if any mesh is.
+    pub fn merge(meshes: &[(MeshData, bevy_math::Mat4)]) -> MeshData {
+        let triangles: Vec<Vec<[u32; 3]>> = meshes.iter().map(|(mesh, _)| mesh.triangles()).collect();
+        let total_vertices: usize = meshes.iter().map(|(mesh, _)| mesh.vertices.len()).sum();
+        let total_indices: usize = triangles.iter().map(|triangles| triangles.len() * 3).sum();
+        assert!(
+            total_vertices <= u32::MAX as usize,
+            "Merged mesh has {} vertices, more than u32 indices can address",
+            total_vertices
+        );
+        
+        let mut vertices = Vec::with_capacity(total_vertices);
+        let mut indices = Vec::with_capacity(total_indices);
+        
+        for ((mesh, transform), triangles) in meshes.iter().zip(&triangles) {
+            let vertex_offset = vertices.len() as u32;
+            
+            // Normals need the inverse-transpose so non-uniform scale doesn't skew them
+            let normal_matrix = bevy_math::Mat3::from_mat4(*transform).inverse().transpose();
+            
+            for vertex in &mesh.vertices {
+                let position = transform.transform_point3(bevy_math::Vec3::from(vertex.position));
+                let normal = (normal_matrix * bevy_math::Vec3::from(vertex.normal)).normalize_or_zero();
+                vertices.push(Vertex::with_color(position.into(), normal.into(), vertex.uv, vertex.color));
+            }
+            
+            // Mirroring transforms flip the triangle winding, swap two indices to keep it
+            let mirrored = transform.determinant() < 0.0;
+            for triangle in triangles {
+                if mirrored {
+                    indices.extend_from_slice(&[triangle[0] + vertex_offset, triangle[2] + vertex_offset, triangle[1] + vertex_offset]);
+                } else {
+                    indices.extend_from_slice(&[triangle[0] + vertex_offset, triangle[1] + vertex_offset, triangle[2] + vertex_offset]);
+                }
+            }
+        }
+        
+        MeshData::new(vertices, indices).with_double_sided(meshes.iter().any(|(mesh, _)| mesh.double_sided))
+    }
+}
+
+// Distance along the ray to a triangle (Möller-Trumbore), from either side. None for misses,
+// hits behind the origin and degenerate triangles.
+fn ray_triangle_distance(origin: bevy_math::Vec3, direction: bevy_math::Vec3, [a, b, c]: &[bevy_math::Vec3; 3]) -> Option<f32> {
+    let edge1 = *b - *a;
+    let edge2 = *c - *a;
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = origin - *a;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(q) * inverse;
+    (distance > 0.0).then_some(distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_math::{Mat4, Vec3};
+    
+    fn vertex(position: [f32; 3]) -> Vertex {
+        Vertex::new(position, [0.0, 0.0, 1.0], [0.0, 0.0])
+    }
+    
+    // A unit quad in the xy plane as four vertices, counter-clockwise from (0, 0)
+    fn quad_vertices() -> Vec<Vertex> {
+        vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([1.0, 1.0, 0.0]), vertex([0.0, 1.0, 0.0])]
+    }
+    
+    #[test]
+    fn bounds_enclose_every_vertex() {
+        let mesh = MeshData::new(
+            vec![vertex([1.0, -2.0, 3.0]), vertex([-4.0, 5.0, 0.5]), vertex([2.0, 0.0, -6.0])],
+            vec![0, 1, 2],
+        );
+        assert_eq!(mesh.bounds(), (Vec3::new(-4.0, -2.0, -6.0), Vec3::new(2.0, 5.0, 3.0)));
+    }
+    
+    #[test]
+    fn bounds_of_empty_mesh_are_zero() {
+        let mesh = MeshData::new(Vec::new(), Vec::new());
+        assert_eq!(mesh.bounds(), (Vec3::ZERO, Vec3::ZERO));
+    }
+    
+    #[test]
+    fn centroid_weights_triangles_by_area() {
+        // A 2 by 1 rectangle whose left half is split into 4 by 4 quads and right half is one quad
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut add_quad = |min: [f32; 2], size: f32| {
+            let first = vertices.len() as u32;
+            for [x, y] in [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]] {
+                vertices.push(vertex([min[0] + x * size, min[1] + y * size, 0.0]));
+            }
+            indices.extend([0, 1, 2, 0, 2, 3].map(|index| first + index));
+        };
+        for row in 0..4 {
+            for column in 0..4 {
+                add_quad([column as f32 * 0.25, row as f32 * 0.25], 0.25);
+            }
+        }
+        add_quad([1.0, 0.0], 1.0);
+        let mesh = MeshData::new(vertices, indices);
+        
+        assert!((mesh.centroid() - Vec3::new(1.0, 0.5, 0.0)).length() < 1e-5);
+        // The dense half pulls the plain vertex average towards it
+        let vertex_average = mesh.vertices.iter().map(|vertex| Vec3::from(vertex.position)).sum::<Vec3>() / mesh.vertices.len() as f32;
+        assert!(vertex_average.x < 0.7);
+    }
+    
+    #[test]
+    fn centroid_without_area_averages_the_vertices() {
+        let line = MeshData::new(vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([5.0, 0.0, 0.0])], vec![0, 1, 2]);
+        assert_eq!(line.centroid(), Vec3::new(2.0, 0.0, 0.0));
+        assert_eq!(MeshData::new(Vec::new(), Vec::new()).centroid(), Vec3::ZERO);
+    }
+    
+    #[test]
+    fn merge_mixes_topologies_and_unindexed_meshes() {
+        let list = MeshData::new(quad_vertices(), vec![0, 1, 2, 0, 2, 3]);
+        let unindexed = MeshData::new(vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([0.0, 1.0, 0.0])], Vec::new());
+        let strip = MeshData::new(
+            vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([0.0, 1.0, 0.0]), vertex([1.0, 1.0, 0.0])],
+            Vec::new(),
+        ).with_topology(MeshTopology::TriangleStrip);
+        let fan = MeshData::new(quad_vertices(), vec![0, 1, 2, 3]).with_topology(MeshTopology::TriangleFan);
+        
+        let offset = Mat4::from_translation(Vec3::X * 10.0);
+        let merged = MeshData::merge(&[
+            (list, Mat4::IDENTITY),
+            (unindexed, offset),
+            (strip, Mat4::IDENTITY),
+            (fan, Mat4::IDENTITY),
+        ]);
+        
+        assert_eq!(merged.topology, MeshTopology::TriangleList);
+        assert_eq!(merged.vertices.len(), 4 + 3 + 4 + 4);
+        assert_eq!(merged.triangles().len(), 2 + 1 + 2 + 2);
+        assert_eq!(&merged.indices[6..9], &[4, 5, 6]);
+        assert_eq!(merged.vertices[4].position, [10.0, 0.0, 0.0]);
+        // Every triangle still faces +z, the way its normals point
+        assert_eq!(merged.detect_winding(), ash::vk::FrontFace::COUNTER_CLOCKWISE);
+        assert!((merged.surface_area() - (1.0 + 0.5 + 1.0 + 1.0)).abs() < 1e-5);
+    }
+    
+    #[test]
+    fn merge_keeps_winding_of_mirrored_meshes() {
+        let mesh = MeshData::new(quad_vertices(), vec![0, 1, 2, 0, 2, 3]);
+        let merged = MeshData::merge(&[(mesh, Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0)))]);
+        
+        assert_eq!(merged.indices, vec![0, 2, 1, 0, 3, 2]);
+        // The normals stay +z and the swapped corners face +z too
+        assert_eq!(merged.vertices[0].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(merged.detect_winding(), ash::vk::FrontFace::COUNTER_CLOCKWISE);
+    }
+    
+    #[test]
+    fn merge_of_nothing_is_empty() {
+        let merged = MeshData::merge(&[]);
+        assert!(merged.vertices.is_empty());
+        assert!(merged.indices.is_empty());
+    }
+    
+    #[test]
+    fn strip_triangles_alternate_corners() {
+        let strip = MeshData::new(quad_vertices(), vec![0, 1, 3, 2, PRIMITIVE_RESTART_INDEX, 0, 1, 2])
+            .with_topology(MeshTopology::TriangleStrip);
+        // Restarting begins a new strip, which counts parity from its own first triangle
+        assert_eq!(strip.triangles(), vec![[0, 1, 3], [1, 2, 3], [0, 1, 2]]);
+        
+        let unindexed = MeshData::new(quad_vertices(), Vec::new()).with_topology(MeshTopology::TriangleStrip);
+        assert_eq!(unindexed.triangles(), vec![[0, 1, 2], [1, 3, 2]]);
+    }
+    
+    #[test]
+    fn fan_triangles_share_the_first_vertex() {
+        let fan = MeshData::new(quad_vertices(), vec![0, 1, 2, 3, PRIMITIVE_RESTART_INDEX, 3, 0, 1])
+            .with_topology(MeshTopology::TriangleFan);
+        assert_eq!(fan.triangles(), vec![[0, 1, 2], [0, 2, 3], [3, 0, 1]]);
+    }
+    
+    #[test]
+    fn winding_of_strips_counts_every_triangle() {
+        // Without alternating, the second triangle would outvote the first
+        let mut strip = MeshData::new(
+            vec![vertex([0.0, 0.0, 0.0]), vertex([2.0, 0.0, 0.0]), vertex([0.0, 1.0, 0.0]), vertex([2.0, 2.0, 0.0])],
+            Vec::new(),
+        ).with_topology(MeshTopology::TriangleStrip);
+        assert_eq!(strip.detect_winding(), ash::vk::FrontFace::COUNTER_CLOCKWISE);
+        
+        for vertex in &mut strip.vertices {
+            vertex.normal = [0.0, 0.0, -1.0];
+        }
+        assert_eq!(strip.detect_winding(), ash::vk::FrontFace::CLOCKWISE);
+        // Strips aren't fixed triangle by triangle
+        assert_eq!(strip.fix_winding(), 0);
+    }
+    
+    #[test]
+    fn fix_winding_flips_back_the_reversed_triangles() {
+        let mut cube = crate::primitives::cuboid(1.0, 2.0, 3.0, false);
+        let original = cube.indices.clone();
+        // More than half of the 12 triangles, so the mesh as a whole reads as clockwise
+        for first in (0..7 * 3).step_by(3) {
+            cube.indices.swap(first + 1, first + 2);
+        }
+        assert_eq!(cube.detect_winding(), ash::vk::FrontFace::CLOCKWISE);
+        
+        assert_eq!(cube.fix_winding(), 7);
+        assert_eq!(cube.detect_winding(), ash::vk::FrontFace::COUNTER_CLOCKWISE);
+        assert_eq!(cube.indices, original);
+        assert_eq!(cube.fix_winding(), 0);
+    }
+    
+    #[test]
+    fn instance_attributes_follow_the_vertex_attributes() {
+        // Vertex with a second uv set after the usual four attributes
+        #[derive(Clone, Copy)]
+        struct TwoUvVertex;
+        
+        impl VertexFormat for TwoUvVertex {
+            fn attribute_descriptions() -> Vec<ash::vk::VertexInputAttributeDescription> {
+                let mut attributes = Vertex::attribute_descriptions();
+                attributes.push(
+                    ash::vk::VertexInputAttributeDescription::default()
+                        .binding(0)
+                        .location(4)
+                        .format(ash::vk::Format::R32G32_SFLOAT)
+                        .offset(48),
+                );
+                attributes
+            }
+        }
+        
+        // mesh_instanced.vert and mesh_textured_instanced.vert read the instance position there
+        assert_eq!(next_attribute_location(&Vertex::attribute_descriptions()), 4);
+        
+        // The extra attribute pushes the instance position along instead of sharing location 4
+        let mut attributes = TwoUvVertex::attribute_descriptions();
+        let instance_location = next_attribute_location(&attributes);
+        assert_eq!(instance_location, 5);
+        attributes.push(ash::vk::VertexInputAttributeDescription::default().binding(1).location(instance_location));
+        let mut locations: Vec<u32> = attributes.iter().map(|attribute| attribute.location).collect();
+        locations.sort_unstable();
+        locations.dedup();
+        assert_eq!(locations.len(), attributes.len());
+        
+        // Out of order locations still end up past the highest one
+        let unordered: Vec<_> = [3, 0, 1].into_iter()
+            .map(|location| ash::vk::VertexInputAttributeDescription::default().location(location))
+            .collect();
+        assert_eq!(next_attribute_location(&unordered), 4);
+        assert_eq!(next_attribute_location(&[]), 0);
+    }
+    
+    #[test]
+    fn degenerate_triangles_are_dropped_and_unused_vertices_compacted() {
+        let mut vertices = quad_vertices();
+        // A collinear triangle, a sliver thinner than epsilon and a vertex nothing uses
+        vertices.extend([vertex([2.0, 0.0, 0.0]), vertex([3.0, 0.0, 0.0]), vertex([4.0, 0.0, 0.0]), vertex([0.0, 1e-4, 0.0]), vertex([9.0, 9.0, 0.0])]);
+        let mut mesh = MeshData::new(vertices, vec![0, 1, 2, 4, 5, 6, 0, 1, 7, 0, 2, 3]);
+        
+        assert_eq!(mesh.remove_degenerate_triangles(0.01, false), 2);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.vertices.iter().map(|vertex| vertex.position).collect::<Vec<_>>(), quad_vertices().iter().map(|vertex| vertex.position).collect::<Vec<_>>());
+        // Nothing left to drop
+        assert_eq!(mesh.remove_degenerate_triangles(0.01, false), 0);
+        
+        let mut unindexed = MeshData::new(vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([2.0, 0.0, 0.0])], Vec::new());
+        assert_eq!(unindexed.remove_degenerate_triangles(0.01, false), 1);
+        assert!(unindexed.vertices.is_empty());
+    }
+    
+    #[test]
+    fn welding_collapses_triangles_but_keeps_seams() {
+        // Corners 1 and 2 are 0.01 apart, so the triangle's area is above 0.05² until they weld
+        let corners = || vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([1.0, 0.01, 0.0])];
+        let mut mesh = MeshData::new(corners(), vec![0, 1, 2]);
+        assert_eq!(mesh.remove_degenerate_triangles(0.05, false), 0);
+        assert_eq!(mesh.remove_degenerate_triangles(0.05, true), 1);
+        assert!(mesh.indices.is_empty());
+        
+        // A different uv marks a seam, which isn't welded
+        let mut seam = corners();
+        seam[2].uv = [1.0, 1.0];
+        let mut mesh = MeshData::new(seam, vec![0, 1, 2]);
+        assert_eq!(mesh.remove_degenerate_triangles(0.05, true), 0);
+        assert_eq!(mesh.vertices.len(), 3);
+    }
+    
+    #[test]
+    fn strips_keep_their_degenerate_triangles() {
+        let mut strip = MeshData::new(quad_vertices(), vec![0, 1, 3, 3, 2]).with_topology(MeshTopology::TriangleStrip);
+        assert_eq!(strip.remove_degenerate_triangles(0.01, true), 0);
+        assert_eq!(strip.indices, vec![0, 1, 3, 3, 2]);
+    }
+    
+    #[test]
+    fn baked_ao_darkens_vertices_under_a_roof() {
+        // A unit floor facing up with a roof of the same size facing down at the given height
+        let covered_floor = |height: f32| {
+            let mut vertices = quad_vertices();
+            vertices.extend(quad_vertices().into_iter().map(|mut vertex| {
+                vertex.position[2] = height;
+                vertex.normal = [0.0, 0.0, -1.0];
+                vertex
+            }));
+            let mut mesh = MeshData::new(vertices, vec![0, 1, 2, 0, 2, 3, 4, 6, 5, 4, 7, 6]);
+            mesh.bake_vertex_ao_with_radius(64, 2.0);
+            mesh
+        };
+        let floor_ao = |mesh: &MeshData| mesh.vertices[..4].iter().map(|vertex| vertex.color[3]).sum::<f32>() / 4.0;
+        
+        let mut open = MeshData::new(quad_vertices(), vec![0, 1, 2, 0, 2, 3]);
+        open.bake_vertex_ao_with_radius(64, 2.0);
+        assert!(open.vertices.iter().all(|vertex| vertex.color[3] == 1.0));
+        
+        let low = covered_floor(0.25);
+        let high = covered_floor(1.0);
+        assert!(low.vertices.iter().all(|vertex| vertex.color[3] < 1.0 && vertex.color[3] >= 0.0));
+        // A closer roof blocks more rays and hits them sooner
+        assert!(floor_ao(&low) < floor_ao(&high));
+        assert!(floor_ao(&high) < 1.0);
+    }
+    
+    #[test]
+    fn invert_reverses_every_topology() {
+        // Twice the signed area of the triangles seen from +z
+        let facing_area = |mesh: &MeshData| -> f32 {
+            mesh.triangles().iter().map(|triangle| {
+                let [a, b, c] = triangle.map(|index| Vec3::from(mesh.vertices[index as usize].position));
+                (b - a).cross(c - a).z
+            }).sum()
+        };
+        let strip_vertices = [0, 1, 3, 2].map(|index| quad_vertices()[index]).to_vec();
+        let meshes = [
+            MeshData::new(quad_vertices(), vec![0, 1, 2, 0, 2, 3]),
+            MeshData::new(strip_vertices, Vec::new()).with_topology(MeshTopology::TriangleStrip),
+            MeshData::new(quad_vertices(), vec![0, 1, 3, 2, PRIMITIVE_RESTART_INDEX, 0, 1, 2]).with_topology(MeshTopology::TriangleStrip),
+            MeshData::new(quad_vertices(), Vec::new()).with_topology(MeshTopology::TriangleFan),
+            MeshData::new(quad_vertices(), vec![0, 1, 2, 3]).with_topology(MeshTopology::TriangleFan),
+        ];
+        for mut mesh in meshes {
+            let area = facing_area(&mesh);
+            assert!(area > 0.0);
+            
+            mesh.invert();
+            assert_eq!(facing_area(&mesh), -area, "{:?}", mesh.topology);
+            assert!(mesh.vertices.iter().all(|vertex| vertex.normal == [0.0, 0.0, -1.0]));
+            // Both the normals and the winding flipped, so they still agree
+            assert_eq!(mesh.detect_winding(), ash::vk::FrontFace::COUNTER_CLOCKWISE);
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use bevy_math::{DVec3, Vec3};
+use crate::mesh::MeshData;
+
+// Border edges (open boundaries and uv/normal seams) get a plane through them perpendicular to
+// their triangle, weighted by this times their squared length, so collapses keep them in place
+const BORDER_WEIGHT: f64 = 100.0;
+
+impl MeshData {
+    // Quadric error metric decimation (Garland and Heckbert) down to about target_ratio of the
+    // triangles, e.g. to generate LODs. Vertices are only ever collapsed onto a neighbor, so no
+    // attributes are interpolated, and the cheapest collapses go first. Triangles come out as a
+    // triangle list with only the vertices still in use.
+    //
+    // Vertices split by uv or normal seams (same position, different attributes) are only moved
+    // along the seam, and open boundaries along the boundary, so neither tears open. Vertices on
+    // non-manifold edges are never moved. Collapses that would flip a triangle or pinch the
+    // surface are skipped, so meshes with few valid collapses stop above the target.
+    pub fn simplify(&self, target_ratio: f32) -> MeshData {
+        let triangles = self.triangles();
+        let target = (triangles.len() as f32 * target_ratio.clamp(0.0, 1.0)).ceil() as usize;
+        let mut simplifier = Simplifier::new(&self.vertices.iter().map(|vertex| Vec3::from(vertex.position)).collect::<Vec<_>>(), triangles);
+        simplifier.collapse_until(target);
+
+        // Keep the vertices still referenced, in their original order
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::new();
+        let mut indices = Vec::with_capacity(simplifier.live_triangles * 3);
+        for (triangle, _) in simplifier.triangles.iter().zip(&simplifier.removed).filter(|&(_, &removed)| !removed) {
+            for &index in triangle {
+                if remap[index as usize] == u32::MAX {
+                    remap[index as usize] = vertices.len() as u32;
+                    vertices.push(self.vertices[index as usize]);
+                }
+                indices.push(remap[index as usize]);
+            }
+        }
+        MeshData::new(vertices, indices).with_double_sided(self.double_sided)
+    }
+}
+
+// Symmetric 4x4 matrix summing squared distances to planes, upper triangle row by row
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: DVec3, point: DVec3, weight: f64) -> Self {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        let d = -normal.dot(point);
+        Quadric([
+            a * a, a * b, a * c, a * d,
+            b * b, b * c, b * d,
+            c * c, c * d,
+            d * d,
+        ].map(|value| value * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+// Moving position `from` onto position `to`. The versions are those of both positions when the
+// candidate was queued, it's stale once either changed.
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed so the heap pops the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+// Collapses work on positions, vertices welded by exact position, while triangles keep their
+// original vertex indices so seams can be told apart and attributes carried over
+struct Simplifier {
+    positions: Vec<DVec3>,
+    vertex_position: Vec<u32>,
+    triangles: Vec<[u32; 3]>,
+    removed: Vec<bool>,
+    live_triangles: usize,
+    // Triangles around each position, including removed ones, which are skipped
+    position_triangles: Vec<Vec<u32>>,
+    quadrics: Vec<Quadric>,
+    collapsed: Vec<bool>,
+    locked: Vec<bool>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(vertex_positions: &[Vec3], triangles: Vec<[u32; 3]>) -> Self {
+        let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+        let mut positions = Vec::new();
+        let vertex_position: Vec<u32> = vertex_positions.iter()
+            .map(|position| *welded.entry(position.to_array().map(f32::to_bits)).or_insert_with(|| {
+                positions.push(position.as_dvec3());
+                positions.len() as u32 - 1
+            }))
+            .collect();
+
+        let position_count = positions.len();
+        let mut simplifier = Self {
+            positions,
+            vertex_position,
+            removed: vec![false; triangles.len()],
+            live_triangles: triangles.len(),
+            triangles,
+            position_triangles: vec![Vec::new(); position_count],
+            quadrics: vec![Quadric::default(); position_count],
+            collapsed: vec![false; position_count],
+            locked: vec![false; position_count],
+            versions: vec![0; position_count],
+            heap: BinaryHeap::new(),
+        };
+
+        for (triangle_index, triangle) in simplifier.triangles.iter().enumerate() {
+            let corners = triangle.map(|index| simplifier.vertex_position[index as usize]);
+            // Degenerate triangles from welding get removed right away
+            if corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2] {
+                simplifier.removed[triangle_index] = true;
+                simplifier.live_triangles -= 1;
+                continue;
+            }
+            for corner in corners {
+                simplifier.position_triangles[corner as usize].push(triangle_index as u32);
+            }
+
+            // Area weighted, so big triangles aren't outvoted by many small ones
+            let [a, b, c] = corners.map(|corner| simplifier.positions[corner as usize]);
+            let cross = (b - a).cross(c - a);
+            let area = cross.length() * 0.5;
+            if area > 0.0 {
+                let quadric = Quadric::from_plane(cross.normalize(), a, area);
+                for corner in corners {
+                    simplifier.quadrics[corner as usize].add(&quadric);
+                }
+            }
+        }
+
+        for from in 0..position_count as u32 {
+            for to in simplifier.neighbors(from) {
+                // Each edge once, borders get their constraint planes here
+                if from < to {
+                    let edge_triangles = simplifier.edge_triangles(from, to);
+                    if edge_triangles.len() > 2 {
+                        simplifier.locked[from as usize] = true;
+                        simplifier.locked[to as usize] = true;
+                    } else if simplifier.is_border_edge(from, to) {
+                        simplifier.add_border_quadric(from, to, edge_triangles[0]);
+                    }
+                }
+            }
+        }
+        for from in 0..position_count as u32 {
+            for to in simplifier.neighbors(from) {
+                simplifier.queue_collapse(from, to);
+            }
+        }
+        simplifier
+    }
+
+    fn collapse_until(&mut self, target_triangles: usize) {
+        while self.live_triangles > target_triangles {
+            let Some(collapse) = self.heap.pop() else {
+                break;
+            };
+            let (from, to) = (collapse.from as usize, collapse.to as usize);
+            if self.collapsed[from] || self.collapsed[to] || collapse.versions != (self.versions[from], self.versions[to]) {
+                continue;
+            }
+            if let Some(vertex_map) = self.check_collapse(collapse.from, collapse.to) {
+                self.apply_collapse(collapse.from, collapse.to, &vertex_map);
+            }
+        }
+    }
+
+    // The vertex each of from's vertices becomes, or None when the collapse would tear a
+    // border, change the topology or flip a triangle
+    fn check_collapse(&self, from: u32, to: u32) -> Option<HashMap<u32, u32>> {
+        if self.locked[from as usize] {
+            return None;
+        }
+        let edge_triangles = self.edge_triangles(from, to);
+        if edge_triangles.is_empty() {
+            return None;
+        }
+
+        // Border positions only slide along their border
+        let neighbors = self.neighbors(from);
+        let from_on_border = neighbors.iter().any(|&neighbor| self.is_border_edge(from, neighbor));
+        if from_on_border && !self.is_border_edge(from, to) {
+            return None;
+        }
+
+        // Link condition: only the triangles on the edge may share both neighbors, otherwise the
+        // collapse pinches the surface into a non-manifold edge
+        let to_neighbors = self.neighbors(to);
+        let shared = neighbors.iter().filter(|neighbor| to_neighbors.contains(neighbor)).count();
+        if shared != edge_triangles.len() {
+            return None;
+        }
+
+        // Each vertex at from takes the attributes of the vertex at to it shares a triangle with
+        let mut vertex_map = HashMap::new();
+        for &triangle_index in self.live_triangles_of(from).iter() {
+            let triangle = self.triangles[triangle_index as usize];
+            let from_vertex = *triangle.iter().find(|&&index| self.vertex_position[index as usize] == from)?;
+            if vertex_map.contains_key(&from_vertex) {
+                continue;
+            }
+            let to_vertex = self.live_triangles_of(from).iter()
+                .map(|&other| self.triangles[other as usize])
+                .filter(|other| other.contains(&from_vertex))
+                .find_map(|other| other.into_iter().find(|&index| self.vertex_position[index as usize] == to))?;
+            vertex_map.insert(from_vertex, to_vertex);
+        }
+
+        // The triangles that stay mustn't turn over
+        let target = self.positions[to as usize];
+        for &triangle_index in self.live_triangles_of(from).iter() {
+            let corners = self.triangles[triangle_index as usize].map(|index| self.vertex_position[index as usize]);
+            if corners.contains(&to) {
+                continue;
+            }
+            let before = corners.map(|corner| self.positions[corner as usize]);
+            let after = corners.map(|corner| if corner == from { target } else { self.positions[corner as usize] });
+            let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+            let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+            if normal_after.length_squared() <= f64::EPSILON * normal_before.length_squared()
+                || normal_before.dot(normal_after) <= 0.0 {
+                return None;
+            }
+        }
+        Some(vertex_map)
+    }
+
+    fn apply_collapse(&mut self, from: u32, to: u32, vertex_map: &HashMap<u32, u32>) {
+        for triangle_index in self.live_triangles_of(from) {
+            let triangle = &mut self.triangles[triangle_index as usize];
+            if triangle.iter().any(|&index| self.vertex_position[index as usize] == to) {
+                self.removed[triangle_index as usize] = true;
+                self.live_triangles -= 1;
+                continue;
+            }
+            for index in triangle.iter_mut() {
+                if let Some(&mapped) = vertex_map.get(index) {
+                    *index = mapped;
+                }
+            }
+            self.position_triangles[to as usize].push(triangle_index);
+        }
+        self.position_triangles[from as usize].clear();
+        self.collapsed[from as usize] = true;
+
+        let quadric = self.quadrics[from as usize];
+        self.quadrics[to as usize].add(&quadric);
+        // Every collapse into or out of to has a new cost
+        self.versions[to as usize] += 1;
+        for neighbor in self.neighbors(to) {
+            self.queue_collapse(neighbor, to);
+            self.queue_collapse(to, neighbor);
+        }
+    }
+
+    fn queue_collapse(&mut self, from: u32, to: u32) {
+        if self.locked[from as usize] {
+            return;
+        }
+        let mut quadric = self.quadrics[from as usize];
+        quadric.add(&self.quadrics[to as usize]);
+        self.heap.push(Collapse {
+            cost: quadric.error(self.positions[to as usize]),
+            from,
+            to,
+            versions: (self.versions[from as usize], self.versions[to as usize]),
+        });
+    }
+
+    // Plane through the edge, perpendicular to the triangle it borders
+    fn add_border_quadric(&mut self, from: u32, to: u32, triangle_index: u32) {
+        let [a, b, c] = self.triangles[triangle_index as usize].map(|index| self.positions[self.vertex_position[index as usize] as usize]);
+        let face_normal = (b - a).cross(c - a);
+        let (start, end) = (self.positions[from as usize], self.positions[to as usize]);
+        let edge = end - start;
+        let normal = edge.cross(face_normal).normalize_or_zero();
+        if normal == DVec3::ZERO {
+            return;
+        }
+        let quadric = Quadric::from_plane(normal, start, BORDER_WEIGHT * edge.length_squared());
+        self.quadrics[from as usize].add(&quadric);
+        self.quadrics[to as usize].add(&quadric);
+    }
+
+    // Open boundary, non-manifold, or a seam where the two triangles use different vertices
+    fn is_border_edge(&self, from: u32, to: u32) -> bool {
+        let edge_triangles = self.edge_triangles(from, to);
+        if edge_triangles.len() != 2 {
+            return true;
+        }
+        let vertex_at = |triangle_index: u32, position: u32| {
+            self.triangles[triangle_index as usize].into_iter()
+                .find(|&index| self.vertex_position[index as usize] == position)
+        };
+        vertex_at(edge_triangles[0], from) != vertex_at(edge_triangles[1], from)
+            || vertex_at(edge_triangles[0], to) != vertex_at(edge_triangles[1], to)
+    }
+
+    fn live_triangles_of(&self, position: u32) -> Vec<u32> {
+        self.position_triangles[position as usize].iter()
+            .copied()
+            .filter(|&triangle_index| !self.removed[triangle_index as usize])
+            .collect()
+    }
+
+    fn edge_triangles(&self, from: u32, to: u32) -> Vec<u32> {
+        self.live_triangles_of(from).into_iter()
+            .filter(|&triangle_index| {
+                self.triangles[triangle_index as usize].iter().any(|&index| self.vertex_position[index as usize] == to)
+            })
+            .collect()
+    }
+
+    fn neighbors(&self, position: u32) -> Vec<u32> {
+        let mut neighbors: Vec<u32> = self.live_triangles_of(position).into_iter()
+            .flat_map(|triangle_index| self.triangles[triangle_index as usize])
+            .map(|index| self.vertex_position[index as usize])
+            .filter(|&neighbor| neighbor != position)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives;
+
+    // 2x2 plane with a uv seam down x = 0, the right half's seam vertices are copies with other uvs
+    fn plane_with_seam() -> MeshData {
+        let mut mesh = primitives::plane(2.0, 2.0, 16);
+        let mut seam_copies = HashMap::new();
+        for triangle in 0..mesh.indices.len() / 3 {
+            let corners = &mesh.indices[triangle * 3..triangle * 3 + 3];
+            if corners.iter().map(|&index| mesh.vertices[index as usize].position[0]).sum::<f32>() <= 0.0 {
+                continue;
+            }
+            for corner in 0..3 {
+                let index = mesh.indices[triangle * 3 + corner];
+                let vertex = mesh.vertices[index as usize];
+                if vertex.position[0] == 0.0 {
+                    let copy = *seam_copies.entry(index).or_insert_with(|| {
+                        let mut copy = vertex;
+                        copy.uv[0] = 1.0;
+                        mesh.vertices.push(copy);
+                        mesh.vertices.len() as u32 - 1
+                    });
+                    mesh.indices[triangle * 3 + corner] = copy;
+                }
+            }
+        }
+        mesh
+    }
+
+    // Edges used by a single triangle, by vertex index so both sides of a seam show up
+    fn open_edges(mesh: &MeshData) -> Vec<(Vec3, Vec3)> {
+        let mut edge_counts: HashMap<(u32, u32), u32> = HashMap::new();
+        for triangle in mesh.triangles() {
+            for corner in 0..3 {
+                let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+                *edge_counts.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+        edge_counts.into_iter()
+            .filter(|&(_, count)| count == 1)
+            .map(|((a, b), _)| (Vec3::from(mesh.vertices[a as usize].position), Vec3::from(mesh.vertices[b as usize].position)))
+            .collect()
+    }
+
+    #[test]
+    fn simplified_plane_keeps_its_border_and_seam() {
+        let mesh = plane_with_seam();
+        let simplified = mesh.simplify(0.25);
+
+        let triangle_count = simplified.triangles().len();
+        assert!(triangle_count <= mesh.triangles().len() / 4, "{} triangles left", triangle_count);
+        assert_eq!(simplified.bounds(), mesh.bounds());
+
+        // The outline and both sides of the seam are still there in full, only straighter
+        let edges = open_edges(&simplified);
+        for (a, b) in &edges {
+            let on_border = |axis: usize| a[axis].abs() == 1.0 && b[axis] == a[axis];
+            let on_seam = a.x == 0.0 && b.x == 0.0;
+            assert!(on_border(0) || on_border(2) || on_seam, "open edge {} to {} is inside the plane", a, b);
+        }
+        let length: f32 = edges.iter().map(|(a, b)| a.distance(*b)).sum();
+        assert!((length - 12.0).abs() < 1e-4, "open edges are {} long", length);
+
+        // No triangle reaches across the seam, so its uvs stay split
+        for triangle in simplified.triangles() {
+            let xs = triangle.map(|index| simplified.vertices[index as usize].position[0]);
+            assert!(xs.iter().all(|&x| x <= 0.0) || xs.iter().all(|&x| x >= 0.0), "triangle across the seam at {:?}", xs);
+            let right = xs.iter().any(|&x| x > 0.0);
+            for index in triangle {
+                let vertex = &simplified.vertices[index as usize];
+                if vertex.position[0] == 0.0 {
+                    assert_eq!(vertex.uv[0] == 1.0, right);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ratio_of_one_keeps_every_triangle() {
+        let mesh = primitives::plane(2.0, 2.0, 4);
+        assert_eq!(mesh.simplify(1.0).triangles().len(), mesh.triangles().len());
+    }
+}
@@ -1,3 +1,5 @@
+use crate::mesh::VertexFormat;
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct TexturedVertex {
@@ -54,6 +56,16 @@ impl TexturedVertex {
     }
 }
 
+impl VertexFormat for TexturedVertex {
+    fn attribute_descriptions() -> Vec<ash::vk::VertexInputAttributeDescription> {
+        TexturedVertex::get_attribute_descriptions()
+    }
+
+    fn position(&self) -> Option<[f32; 3]> {
+        Some(self.position)
+    }
+}
+
 pub struct TexturedMeshData {
     pub vertices: Vec<TexturedVertex>,
     pub indices: Vec<u32>,
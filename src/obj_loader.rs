@@ -0,0 +1,301 @@
+use crate::{gltf_loader::MaterialInfo, mesh::MeshData, mesh::Vertex};
+use tracing::{debug, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Vertices and indices collected for one material while parsing faces
+struct MaterialGroup {
+    material_name: Option<String>,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    // (position, uv, normal) index triple -> vertex index, used to deduplicate
+    vertex_lookup: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+    // Vertices that had no normal in the file and need one generated
+    missing_normals: Vec<bool>,
+}
+
+// Loads an OBJ file and returns one indexed mesh per material used by its faces
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Vec<(MeshData, MaterialInfo)>, String> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read OBJ file {}: {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut materials: HashMap<String, MaterialInfo> = HashMap::new();
+
+    let mut groups: Vec<MaterialGroup> = Vec::new();
+    let mut group_lookup: HashMap<Option<String>, usize> = HashMap::new();
+    let mut current_material: Option<String> = None;
+
+    for (line_number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next().unwrap_or("");
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.push(parse_floats::<3>(&args, line_number)?),
+            "vn" => normals.push(parse_floats::<3>(&args, line_number)?),
+            "vt" => {
+                let uv = parse_floats::<2>(&args, line_number)?;
+                // OBJ puts the UV origin at the bottom left, Vulkan samples from the top left
+                uvs.push([uv[0], 1.0 - uv[1]]);
+            }
+            "mtllib" => {
+                // Material library names may contain spaces
+                let mtl_path = base_dir.join(args.join(" "));
+                match load_mtl(&mtl_path, base_dir) {
+                    Ok(loaded) => materials.extend(loaded),
+                    Err(e) => warn!("{}, using default materials", e),
+                }
+            }
+            "usemtl" => {
+                current_material = if args.is_empty() { None } else { Some(args.join(" ")) };
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(format!("OBJ line {}: face needs at least 3 vertices", line_number + 1));
+                }
+
+                let group_index = *group_lookup.entry(current_material.clone()).or_insert_with(|| {
+                    groups.push(MaterialGroup {
+                        material_name: current_material.clone(),
+                        vertices: Vec::new(),
+                        indices: Vec::new(),
+                        vertex_lookup: HashMap::new(),
+                        missing_normals: Vec::new(),
+                    });
+                    groups.len() - 1
+                });
+                let group = &mut groups[group_index];
+
+                let mut face_vertices = Vec::with_capacity(args.len());
+                for arg in &args {
+                    let key = parse_face_vertex(arg, positions.len(), uvs.len(), normals.len(), line_number)?;
+                    let vertex_index = match group.vertex_lookup.get(&key) {
+                        Some(&index) => index,
+                        None => {
+                            let index = group.vertices.len() as u32;
+                            group.vertices.push(Vertex::new(
+                                positions[key.0],
+                                key.2.map(|n| normals[n]).unwrap_or([0.0, 0.0, 0.0]),
+                                key.1.map(|t| uvs[t]).unwrap_or([0.0, 0.0]),
+                            ));
+                            group.missing_normals.push(key.2.is_none());
+                            group.vertex_lookup.insert(key, index);
+                            index
+                        }
+                    };
+                    face_vertices.push(vertex_index);
+                }
+
+                // Triangulate polygons as a fan around the first vertex.
+                // Same winding flip as gltf_loader so both feed add_mesh the same way.
+                for i in 1..face_vertices.len() - 1 {
+                    group.indices.push(face_vertices[0]);
+                    group.indices.push(face_vertices[i + 1]);
+                    group.indices.push(face_vertices[i]);
+                }
+            }
+            // Objects, groups and smoothing groups don't affect the output, meshes are split by material only
+            _ => {}
+        }
+    }
+
+    if groups.is_empty() {
+        return Err(format!("No faces found in OBJ file {}", path.display()));
+    }
+
+    let mut result = Vec::with_capacity(groups.len());
+    for mut group in groups {
+        generate_missing_normals(&mut group);
+
+        let material = match &group.material_name {
+            Some(name) => materials.get(name).cloned().unwrap_or_else(|| {
+                warn!("Material {} not found in MTL, using default", name);
+                MaterialInfo { name: Some(name.clone()), ..Default::default() }
+            }),
+            None => MaterialInfo::default(),
+        };
+
+        for vertex in &mut group.vertices {
+            vertex.color = material.base_color;
+        }
+
+        debug!("Loaded OBJ group {:?} with {} vertices and {} indices",
+                 material.name, group.vertices.len(), group.indices.len());
+
+        result.push((MeshData::new(group.vertices, group.indices), material));
+    }
+
+    Ok(result)
+}
+
+fn parse_floats<const N: usize>(args: &[&str], line_number: usize) -> Result<[f32; N], String> {
+    if args.len() < N {
+        return Err(format!("OBJ line {}: expected {} values, found {}", line_number + 1, N, args.len()));
+    }
+
+    let mut values = [0.0; N];
+    for i in 0..N {
+        values[i] = args[i].parse::<f32>()
+            .map_err(|e| format!("OBJ line {}: invalid number {}: {}", line_number + 1, args[i], e))?;
+    }
+    Ok(values)
+}
+
+// Parses a face vertex in the form v, v/vt, v//vn or v/vt/vn into zero-based indices
+fn parse_face_vertex(
+    arg: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+    line_number: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = arg.split('/');
+
+    let position = parts.next()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| format!("OBJ line {}: face vertex {} has no position index", line_number + 1, arg))?;
+    let position = resolve_index(position, position_count, line_number)?;
+
+    let uv = match parts.next() {
+        Some(s) if !s.is_empty() => Some(resolve_index(s, uv_count, line_number)?),
+        _ => None,
+    };
+
+    let normal = match parts.next() {
+        Some(s) if !s.is_empty() => Some(resolve_index(s, normal_count, line_number)?),
+        _ => None,
+    };
+
+    Ok((position, uv, normal))
+}
+
+// OBJ indices are 1-based, negative indices count back from the most recent element
+fn resolve_index(value: &str, count: usize, line_number: usize) -> Result<usize, String> {
+    let index = value.parse::<i64>()
+        .map_err(|e| format!("OBJ line {}: invalid index {}: {}", line_number + 1, value, e))?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("OBJ line {}: index {} out of range ({} elements)", line_number + 1, index, count));
+    }
+
+    Ok(resolved as usize)
+}
+
+// Area weighted smooth normals for vertices that were declared without one
+fn generate_missing_normals(group: &mut MaterialGroup) {
+    if !group.missing_normals.iter().any(|&missing| missing) {
+        return;
+    }
+
+    let mut accumulated = vec![[0.0f32; 3]; group.vertices.len()];
+    for triangle in group.indices.chunks(3) {
+        let a = group.vertices[triangle[0] as usize].position;
+        let b = group.vertices[triangle[1] as usize].position;
+        let c = group.vertices[triangle[2] as usize].position;
+
+        // Indices are stored flipped (0, 2, 1), so cross in that order for the outward normal
+        let ab = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        let ac = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let face_normal = [
+            ab[1] * ac[2] - ab[2] * ac[1],
+            ab[2] * ac[0] - ab[0] * ac[2],
+            ab[0] * ac[1] - ab[1] * ac[0],
+        ];
+
+        for &index in triangle {
+            let n = &mut accumulated[index as usize];
+            n[0] += face_normal[0];
+            n[1] += face_normal[1];
+            n[2] += face_normal[2];
+        }
+    }
+
+    for (i, vertex) in group.vertices.iter_mut().enumerate() {
+        if !group.missing_normals[i] {
+            continue;
+        }
+        let n = accumulated[i];
+        let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        vertex.normal = if length > 0.0 {
+            [n[0] / length, n[1] / length, n[2] / length]
+        } else {
+            [0.0, 1.0, 0.0]
+        };
+    }
+}
+
+// Parses the subset of MTL we can use: diffuse color, opacity and diffuse texture
+fn load_mtl(path: &Path, base_dir: &Path) -> Result<HashMap<String, MaterialInfo>, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read MTL file {}: {}", path.display(), e))?;
+
+    let mut materials = HashMap::new();
+    let mut current: Option<MaterialInfo> = None;
+
+    for line in source.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next().unwrap_or("");
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "newmtl" => {
+                if let Some(material) = current.take() {
+                    materials.insert(material.name.clone().unwrap_or_default(), material);
+                }
+                current = Some(MaterialInfo { name: Some(args.join(" ")), ..Default::default() });
+            }
+            "Kd" => {
+                if let Some(material) = current.as_mut() {
+                    for i in 0..3.min(args.len()) {
+                        material.base_color[i] = args[i].parse().unwrap_or(1.0);
+                    }
+                }
+            }
+            "d" => {
+                if let (Some(material), Some(value)) = (current.as_mut(), args.first()) {
+                    material.base_color[3] = value.parse().unwrap_or(1.0);
+                }
+            }
+            "Tr" => {
+                if let (Some(material), Some(value)) = (current.as_mut(), args.first()) {
+                    material.base_color[3] = 1.0 - value.parse::<f32>().unwrap_or(0.0);
+                }
+            }
+            "map_Kd" => {
+                // Texture options like -s or -o come before the file name, which is always last
+                if let (Some(material), Some(file)) = (current.as_mut(), args.last()) {
+                    material.base_color_texture = Some(base_dir.join(file.replace('\\', "/")));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(material) = current.take() {
+        materials.insert(material.name.clone().unwrap_or_default(), material);
+    }
+
+    Ok(materials)
+}
@@ -0,0 +1,132 @@
+use std::ops::Range;
+
+use ash::vk;
+use crate::constants::MAX_FRAMES_IN_FLIGHT;
+use crate::error::FloError;
+use crate::leak_check;
+use crate::vulkan_common::{begin_secondary_command_buffer, bind_graphics_pipeline, create_command_pool, create_command_buffers_with_level};
+
+// What a recording thread draws into, see VulkanRenderer::record_parallel
+pub struct RecordContext<'a> {
+    pub device: &'a ash::Device,
+    // Secondary buffer continuing the scene pass, with the scene viewport and scissor set
+    pub command_buffer: vk::CommandBuffer,
+    // This thread's contiguous chunk of 0..item_count
+    pub items: Range<usize>,
+    pub thread: usize,
+    pub depth_compare_op: vk::CompareOp,
+}
+
+impl RecordContext<'_> {
+    // Pipelines take the depth compare op as dynamic state, which secondaries don't inherit
+    pub fn bind_pipeline(&self, pipeline: vk::Pipeline) {
+        bind_graphics_pipeline(self.device, self.command_buffer, pipeline, self.depth_compare_op);
+    }
+}
+
+// Render pass and dynamic state every thread's secondary buffer begins with
+#[derive(Clone, Copy, Debug)]
+pub struct SecondaryTarget {
+    pub render_pass: vk::RenderPass,
+    // Null when the swapchain image isn't known yet, which the spec allows
+    pub framebuffer: vk::Framebuffer,
+    pub extent: vk::Extent2D,
+    pub viewport: vk::Viewport,
+    pub scissor: vk::Rect2D,
+    pub depth_compare_op: vk::CompareOp,
+}
+
+// A command pool per recording thread per frame in flight, since a pool can only be used by one
+// thread at a time. Each thread resets its whole pool and records one secondary buffer.
+pub struct ParallelRecorder {
+    // [frame in flight][thread]
+    frames: Vec<Vec<(vk::CommandPool, vk::CommandBuffer)>>,
+}
+
+impl ParallelRecorder {
+    pub fn new(device: &ash::Device, queue_family_index: u32, thread_count: usize) -> Result<Self, FloError> {
+        let thread_count = thread_count.max(1);
+        let mut frames = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let mut threads = Vec::with_capacity(thread_count);
+            for _ in 0..thread_count {
+                let pool = create_command_pool(device, queue_family_index)?;
+                let command_buffer = create_command_buffers_with_level(device, pool, 1, vk::CommandBufferLevel::SECONDARY)?[0];
+                threads.push((pool, command_buffer));
+            }
+            frames.push(threads);
+        }
+        Ok(Self { frames })
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.frames[0].len()
+    }
+
+    // Splits 0..item_count into a chunk per thread and records them on scoped threads, returning
+    // the secondary buffers in chunk order. The caller makes sure the frame's previous submission
+    // finished, since its pools are reset.
+    pub fn record<F>(
+        &self,
+        device: &ash::Device,
+        frame: usize,
+        target: &SecondaryTarget,
+        item_count: usize,
+        record: F,
+    ) -> Result<Vec<vk::CommandBuffer>, FloError>
+    where
+        F: Fn(&RecordContext) + Sync,
+    {
+        let threads = &self.frames[frame];
+        let chunk_size = item_count.div_ceil(threads.len()).max(1);
+        let record = &record;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = threads.iter().enumerate()
+                .map(|(thread, &(pool, command_buffer))| {
+                    let start = (thread * chunk_size).min(item_count);
+                    let items = start..((thread + 1) * chunk_size).min(item_count);
+                    (items, thread, pool, command_buffer)
+                })
+                .filter(|(items, ..)| !items.is_empty())
+                .map(|(items, thread, pool, command_buffer)| {
+                    scope.spawn(move || -> Result<vk::CommandBuffer, vk::Result> {
+                        unsafe {
+                            device.reset_command_pool(pool, vk::CommandPoolResetFlags::empty())?;
+                        }
+                        begin_secondary_command_buffer(device, command_buffer, target.render_pass, target.framebuffer, target.extent)?;
+                        unsafe {
+                            device.cmd_set_viewport(command_buffer, 0, &[target.viewport]);
+                            device.cmd_set_scissor(command_buffer, 0, &[target.scissor]);
+                        }
+                        record(&RecordContext {
+                            device,
+                            command_buffer,
+                            items,
+                            thread,
+                            depth_compare_op: target.depth_compare_op,
+                        });
+                        unsafe {
+                            device.end_command_buffer(command_buffer)?;
+                        }
+                        Ok(command_buffer)
+                    })
+                })
+                .collect();
+            handles.into_iter()
+                .map(|handle| match handle.join() {
+                    Ok(result) => result.map_err(FloError::from),
+                    Err(_) => Err(FloError::from("A command recording thread panicked")),
+                })
+                .collect()
+        })
+    }
+
+    // Destroying the pools frees their command buffers
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for (pool, _) in self.frames.drain(..).flatten() {
+            unsafe {
+                leak_check::destroy(device, pool);
+            }
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use ash::{vk, Instance};
+use bevy_math::Mat4;
+use tracing::error;
+use std::mem;
+use crate::constants::*;
+use crate::error::FloError;
+use crate::vulkan_common::*;
+use crate::leak_check;
+
+const SIMULATE_SHADER_PATH: &str = "shaders/particles.comp.spv";
+const PARTICLE_VERT_SHADER_PATH: &str = "shaders/particle.vert.spv";
+const PARTICLE_FRAG_SHADER_PATH: &str = "shaders/particle.frag.spv";
+const PARTICLE_WORKGROUP_SIZE: u32 = 64;
+
+// Size of the particle buffer. Spawning past it overwrites the oldest particles.
+pub const MAX_PARTICLES: u32 = 65536;
+
+// Longest step the simulation takes, so a stalled frame doesn't fling particles through the ground
+const MAX_PARTICLE_STEP: f32 = 0.1;
+
+// One particle as stored in the storage buffer, matches Particle in shaders/particles.comp.
+// Particles with no life left aren't simulated or drawn.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Particle {
+    pub position: [f32; 3],
+    pub life: f32,  // Seconds left
+    pub velocity: [f32; 3],
+    pub size: f32,  // World space width of the particle's quad
+    pub color: [f32; 4],  // Linear, alpha fades out over the quad
+}
+
+impl Particle {
+    pub fn new(position: [f32; 3], velocity: [f32; 3], life: f32, size: f32, color: [f32; 4]) -> Self {
+        Self { position, life, velocity, size, color }
+    }
+}
+
+// What the simulation applies to every particle each frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParticleForces {
+    pub gravity: [f32; 3],
+    // Fraction of velocity lost per second
+    pub drag: f32,
+    // Particles bounce off the plane y = ground_height, e.g. 0.0 or the water level
+    pub ground_height: f32,
+    // Vertical speed kept after a bounce, 0.0 stops particles on the plane
+    pub bounce: f32,
+}
+
+impl Default for ParticleForces {
+    fn default() -> Self {
+        Self {
+            gravity: [0.0, -9.81, 0.0],
+            drag: 0.1,
+            ground_height: 0.0,
+            bounce: 0.4,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SimulatePushConstants {
+    gravity: [f32; 3],
+    drag: f32,
+    ground_height: f32,
+    bounce: f32,
+    delta_time: f32,
+    particle_count: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawPushConstants {
+    view: [f32; 16],
+    proj: [f32; 16],
+}
+
+// GPU particles: a storage buffer the compute pass integrates each frame, drawn as camera
+// facing quads straight from the same buffer as per instance vertex data. New particles are
+// written into the buffer like a ring, so spawning never waits on the GPU.
+pub struct ParticleSystem {
+    simulate_pipeline: vk::Pipeline,
+    simulate_layout: vk::PipelineLayout,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    draw_pipeline: vk::Pipeline,
+    draw_layout: vk::PipelineLayout,
+    particle_buffer: (vk::Buffer, vk::DeviceMemory),
+    // One per frame in flight, so the host never writes spawns a previous frame is copying
+    spawn_buffers: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    pending_spawns: Vec<Particle>,
+    next_slot: u32,
+    // Slots that have ever held a particle, the rest are skipped by the dispatch and draw
+    used_slots: u32,
+    forces: ParticleForces,
+    // The buffer starts out as garbage and is zeroed by the first frame
+    cleared: bool,
+    // Time of the last step, None before the first
+    last_time: Option<f32>,
+}
+
+impl ParticleSystem {
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+        with_depth_test: bool,
+    ) -> Result<Self, FloError> {
+        let buffer_size = (MAX_PARTICLES as usize * mem::size_of::<Particle>()) as vk::DeviceSize;
+        let particle_buffer = create_buffer(
+            instance,
+            device,
+            physical_device,
+            buffer_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let mut spawn_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            spawn_buffers.push(create_buffer(
+                instance,
+                device,
+                physical_device,
+                buffer_size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?);
+        }
+
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE);
+        let descriptor_set_layout = create_descriptor_set_layout(device, &[binding])?;
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+        }];
+        let descriptor_pool = create_descriptor_pool(device, 1, &pool_sizes)?;
+        let descriptor_set = allocate_descriptor_sets(device, descriptor_pool, &[descriptor_set_layout])?[0];
+        let buffer_info = [vk::DescriptorBufferInfo { buffer: particle_buffer.0, offset: 0, range: vk::WHOLE_SIZE }];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_info);
+        unsafe {
+            device.update_descriptor_sets(&[write], &[]);
+        }
+
+        let (simulate_pipeline, simulate_layout) = create_simulate_pipeline(device, descriptor_set_layout)?;
+
+        // Particles are unsorted, additive blending looks the same in any order
+        let draw_push_constants = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(mem::size_of::<DrawPushConstants>() as u32);
+        let (draw_pipeline, draw_layout) = PipelineBuilder::new(
+            device.clone(),
+            PARTICLE_VERT_SHADER_PATH,
+            PARTICLE_FRAG_SHADER_PATH,
+            extent,
+            render_pass,
+        )?
+        .with_vertex_input(vec![particle_binding_description()], particle_attribute_descriptions())
+        .with_push_constants(vec![draw_push_constants])
+        .with_depth_test(with_depth_test)
+        .with_depth_write(false)
+        .with_cull_mode(vk::CullModeFlags::NONE)
+        .with_blend_mode(BlendMode::Additive)
+        .build()?;
+
+        Ok(Self {
+            simulate_pipeline,
+            simulate_layout,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            draw_pipeline,
+            draw_layout,
+            particle_buffer,
+            spawn_buffers,
+            pending_spawns: Vec::new(),
+            next_slot: 0,
+            used_slots: 0,
+            forces: ParticleForces::default(),
+            cleared: false,
+            last_time: None,
+        })
+    }
+
+    // Queued for the next frame. Only the last MAX_PARTICLES are kept.
+    pub fn spawn(&mut self, particles: &[Particle]) {
+        self.pending_spawns.extend_from_slice(particles);
+        let excess = self.pending_spawns.len().saturating_sub(MAX_PARTICLES as usize);
+        self.pending_spawns.drain(..excess);
+    }
+
+    pub fn set_forces(&mut self, forces: ParticleForces) {
+        self.forces = forces;
+    }
+
+    pub fn forces(&self) -> ParticleForces {
+        self.forces
+    }
+
+    // Copies this frame's spawns into the ring and steps every used slot to time (the
+    // renderer's elapsed time, so particles stop while it's paused). Must be recorded outside
+    // a render pass, before the draw.
+    pub fn record_simulate(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize, time: f32) {
+        let delta_time = self.last_time.map_or(0.0, |last_time| time - last_time);
+        self.last_time = Some(time);
+        if self.used_slots == 0 && self.pending_spawns.is_empty() {
+            return;
+        }
+        let particle_buffer = self.particle_buffer.0;
+        let stride = mem::size_of::<Particle>() as vk::DeviceSize;
+
+        // The previous frame's draw and dispatch may still be using the buffer
+        record_barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        unsafe {
+            if !self.cleared {
+                device.cmd_fill_buffer(command_buffer, particle_buffer, 0, vk::WHOLE_SIZE, 0);
+                self.cleared = true;
+                record_barrier(
+                    device,
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                );
+            }
+
+            let spawns = mem::take(&mut self.pending_spawns);
+            if !spawns.is_empty() {
+                let spawn_memory = self.spawn_buffers[frame].1;
+                write_mapped(device, spawn_memory, bytemuck::cast_slice(&spawns));
+
+                // Up to the end of the buffer, then wrapping around to the start
+                let count = spawns.len() as u32;
+                let first = count.min(MAX_PARTICLES - self.next_slot);
+                let mut copies = vec![vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: self.next_slot as vk::DeviceSize * stride,
+                    size: first as vk::DeviceSize * stride,
+                }];
+                if count > first {
+                    copies.push(vk::BufferCopy {
+                        src_offset: first as vk::DeviceSize * stride,
+                        dst_offset: 0,
+                        size: (count - first) as vk::DeviceSize * stride,
+                    });
+                }
+                device.cmd_copy_buffer(command_buffer, self.spawn_buffers[frame].0, particle_buffer, &copies);
+                self.next_slot = (self.next_slot + count) % MAX_PARTICLES;
+                self.used_slots = (self.used_slots + count).min(MAX_PARTICLES);
+            }
+        }
+
+        record_barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        let push_constants = SimulatePushConstants {
+            gravity: self.forces.gravity,
+            drag: self.forces.drag,
+            ground_height: self.forces.ground_height,
+            bounce: self.forces.bounce,
+            delta_time: delta_time.clamp(0.0, MAX_PARTICLE_STEP),
+            particle_count: self.used_slots,
+        };
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.simulate_pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.simulate_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.simulate_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_dispatch(command_buffer, self.used_slots.div_ceil(PARTICLE_WORKGROUP_SIZE), 1, 1);
+        }
+
+        // The draw reads the integrated particles as vertex data
+        record_barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        );
+    }
+
+    // Draws every used slot as a quad, dead particles collapse to nothing in the vertex shader.
+    // Must be recorded inside the scene pass, after record_simulate.
+    pub fn record_draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, view: Mat4, proj: Mat4, depth_compare_op: vk::CompareOp) {
+        if self.used_slots == 0 {
+            return;
+        }
+        let push_constants = DrawPushConstants {
+            view: view.to_cols_array(),
+            proj: proj.to_cols_array(),
+        };
+        bind_graphics_pipeline(device, command_buffer, self.draw_pipeline, depth_compare_op);
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                self.draw_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.particle_buffer.0], &[0]);
+            device.cmd_draw(command_buffer, 6, self.used_slots, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        destroy_buffer(device, self.particle_buffer.0, self.particle_buffer.1);
+        for &(buffer, memory) in &self.spawn_buffers {
+            destroy_buffer(device, buffer, memory);
+        }
+        unsafe {
+            leak_check::destroy(device, self.simulate_pipeline);
+            leak_check::destroy(device, self.simulate_layout);
+            leak_check::destroy(device, self.draw_pipeline);
+            leak_check::destroy(device, self.draw_layout);
+            leak_check::destroy(device, self.descriptor_pool);
+            leak_check::destroy(device, self.descriptor_set_layout);
+        }
+    }
+}
+
+fn create_simulate_pipeline(
+    device: &ash::Device,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> Result<(vk::Pipeline, vk::PipelineLayout), FloError> {
+    let push_constant_ranges = [vk::PushConstantRange::default()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(mem::size_of::<SimulatePushConstants>() as u32)];
+    let set_layouts = [descriptor_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::default()
+        .set_layouts(&set_layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    let layout = leak_check::created(device, unsafe { device.create_pipeline_layout(&layout_info, None)? });
+
+    let shader_code = read_shader_file(SIMULATE_SHADER_PATH)?;
+    let shader_module = create_shader_module(device, &shader_code)?;
+
+    let entry_name = std::ffi::CString::new("main").unwrap();
+    let stage = vk::PipelineShaderStageCreateInfo::default()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(&entry_name);
+    let pipeline_info = vk::ComputePipelineCreateInfo::default()
+        .stage(stage)
+        .layout(layout);
+
+    let pipeline = leak_check::created(device, unsafe {
+        device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+            .map_err(|(_, e)| e)?[0]
+    });
+
+    unsafe {
+        leak_check::destroy(device, shader_module);
+    }
+
+    Ok((pipeline, layout))
+}
+
+// The particle buffer is bound per instance, each particle draws a 6 vertex quad
+fn particle_binding_description() -> vk::VertexInputBindingDescription {
+    vk::VertexInputBindingDescription::default()
+        .binding(0)
+        .stride(mem::size_of::<Particle>() as u32)
+        .input_rate(vk::VertexInputRate::INSTANCE)
+}
+
+fn particle_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+    vec![
+        // Position and life
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(memoffset::offset_of!(Particle, position) as u32),
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32_SFLOAT)
+            .offset(memoffset::offset_of!(Particle, size) as u32),
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(memoffset::offset_of!(Particle, color) as u32),
+    ]
+}
+
+fn record_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+) {
+    let barrier = vk::MemoryBarrier::default()
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access);
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        );
+    }
+}
+
+fn write_mapped(device: &ash::Device, memory: vk::DeviceMemory, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    unsafe {
+        match device.map_memory(memory, 0, bytes.len() as vk::DeviceSize, vk::MemoryMapFlags::empty()) {
+            Ok(data) => {
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, bytes.len());
+                device.unmap_memory(memory);
+            }
+            Err(e) => error!("Failed to map particle spawn buffer: {}", e),
+        }
+    }
+}
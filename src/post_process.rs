@@ -0,0 +1,363 @@
+use ash::{vk, Instance};
+use crate::constants::HDR_FORMAT;
+use crate::error::FloError;
+use crate::vulkan_common::*;
+use crate::leak_check;
+
+// Identifies an effect added with VulkanRenderer::add_post_effect
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PostEffectId(pub(crate) usize);
+
+pub const MAX_POST_EFFECT_PARAMS: usize = 16;
+
+// Pushed to every effect's fragment shader, declare the same block in the shader
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PostEffectPushConstants {
+    // 1 / target size, for sampling neighbouring pixels
+    pub texel_size: [f32; 2],
+    pub params: [f32; MAX_POST_EFFECT_PARAMS],
+}
+
+// Anti-aliasing of the scene, see VulkanRenderer::set_antialiasing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AaMode {
+    #[default]
+    None,
+    // A fullscreen pass after the post effects, tuned with FxaaSettings
+    Fxaa,
+    // Samples per pixel
+    Msaa(u32),
+}
+
+// Tuning of the FXAA pass, the defaults are FXAA 3.11's default quality settings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FxaaSettings {
+    // How much single pixel detail is softened, 0 keeps it sharp and 1 is softest
+    pub subpixel: f32,
+    // Contrast a pixel needs to its neighbours to count as an edge, relative to the brightest
+    pub edge_threshold: f32,
+    // Contrast below which dark areas are skipped
+    pub edge_threshold_min: f32,
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self {
+            subpixel: 0.75,
+            edge_threshold: 0.166,
+            edge_threshold_min: 0.0833,
+        }
+    }
+}
+
+impl FxaaSettings {
+    fn params(&self) -> [f32; 3] {
+        [self.subpixel, self.edge_threshold, self.edge_threshold_min]
+    }
+}
+
+struct PostEffect {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    params: [f32; MAX_POST_EFFECT_PARAMS],
+    enabled: bool,
+}
+
+// Fullscreen passes run between the scene pass and the present pass. Each effect samples the
+// previous pass's output and writes one of two HDR ping-pong targets, the first samples the
+// scene's HDR image and the present pass tonemaps the last one.
+// Targets use HDR_FORMAT and the scene's render pass layouts: UNDEFINED while being drawn,
+// SHADER_READ_ONLY_OPTIMAL once the pass ends, with the pass's dependencies covering the reads.
+pub struct PostProcessChain {
+    render_pass: vk::RenderPass,
+    extent: vk::Extent2D,
+    // Ping-pong pair per swapchain image, since frames in flight use different images
+    targets: Vec<[Target; 2]>,
+    framebuffers: Vec<[vk::Framebuffer; 2]>,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    // Per swapchain image: the scene HDR image, then ping-pong target 0 and 1
+    descriptor_sets: Vec<[vk::DescriptorSet; 3]>,
+    effects: Vec<PostEffect>,
+    // Runs after the effects when enabled, created on first set_fxaa
+    fxaa: Option<PostEffect>,
+}
+
+impl PostProcessChain {
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        hdr_image_views: &[vk::ImageView],
+        extent: vk::Extent2D,
+    ) -> Result<Self, FloError> {
+        let render_pass = create_render_pass(device, HDR_FORMAT, None)?;
+        let (targets, framebuffers) =
+            create_targets(instance, device, physical_device, render_pass, hdr_image_views.len(), extent)?;
+
+        // Same size as the targets, so nearest sampling reads exact pixels
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+        let sampler = leak_check::created(device, unsafe { device.create_sampler(&sampler_info, None)? });
+
+        // Laid out like the present pass's set, so the present pass can sample the last target
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let descriptor_set_layout = create_descriptor_set_layout(device, &[binding])?;
+
+        let set_count = hdr_image_views.len() as u32 * 3;
+        let pool_size = vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(set_count);
+        let descriptor_pool = create_descriptor_pool(device, set_count, &[pool_size])?;
+
+        let mut descriptor_sets = Vec::with_capacity(hdr_image_views.len());
+        for _ in hdr_image_views {
+            let sets = allocate_descriptor_sets(device, descriptor_pool, &[descriptor_set_layout; 3])?;
+            descriptor_sets.push([sets[0], sets[1], sets[2]]);
+        }
+
+        let chain = Self {
+            render_pass,
+            extent,
+            targets,
+            framebuffers,
+            sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_sets,
+            effects: Vec::new(),
+            fxaa: None,
+        };
+        chain.write_descriptor_sets(device, hdr_image_views);
+        Ok(chain)
+    }
+
+    // Recreates the targets at the scene's new render extent, with the scene's new HDR images
+    // as the first effect's input. The device has to be idle.
+    pub fn resize(
+        &mut self,
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        hdr_image_views: &[vk::ImageView],
+        extent: vk::Extent2D,
+    ) -> Result<(), FloError> {
+        let (targets, framebuffers) =
+            create_targets(instance, device, physical_device, self.render_pass, hdr_image_views.len(), extent)?;
+        self.destroy_targets(device);
+        self.targets = targets;
+        self.framebuffers = framebuffers;
+        self.extent = extent;
+        self.write_descriptor_sets(device, hdr_image_views);
+        Ok(())
+    }
+
+    fn write_descriptor_sets(&self, device: &ash::Device, hdr_image_views: &[vk::ImageView]) {
+        for ((sets, &hdr_image_view), pair) in self.descriptor_sets.iter().zip(hdr_image_views).zip(&self.targets) {
+            for (&descriptor_set, image_view) in sets.iter().zip([hdr_image_view, pair[0].2, pair[1].2]) {
+                update_descriptor_sets_texture(device, descriptor_set, image_view, self.sampler, 0);
+            }
+        }
+    }
+
+    fn destroy_targets(&self, device: &ash::Device) {
+        unsafe {
+            for &framebuffer in self.framebuffers.iter().flatten() {
+                leak_check::destroy(device, framebuffer);
+            }
+        }
+        for &(image, memory, view) in self.targets.iter().flatten() {
+            destroy_image(device, image, memory, view);
+        }
+    }
+
+    pub fn add_effect(
+        &mut self,
+        device: &ash::Device,
+        frag_shader_path: &str,
+        params: &[f32],
+    ) -> Result<PostEffectId, FloError> {
+        let effect = self.create_effect(device, frag_shader_path, params)?;
+        self.effects.push(effect);
+        Ok(PostEffectId(self.effects.len() - 1))
+    }
+
+    fn create_effect(&self, device: &ash::Device, frag_shader_path: &str, params: &[f32]) -> Result<PostEffect, FloError> {
+        let params = effect_params(params)?;
+
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(std::mem::size_of::<PostEffectPushConstants>() as u32);
+
+        // Reuses the present pass's fullscreen triangle
+        let (pipeline, pipeline_layout) = PipelineBuilder::new(
+            device.clone(),
+            "shaders/tonemap.vert.spv",
+            frag_shader_path,
+            self.extent,
+            self.render_pass,
+        )?
+            .with_push_constants(vec![push_constant_range])
+            .with_descriptor_sets(vec![self.descriptor_set_layout])
+            .with_cull_mode(vk::CullModeFlags::NONE)
+            .build()?;
+
+        Ok(PostEffect {
+            pipeline,
+            pipeline_layout,
+            params,
+            enabled: true,
+        })
+    }
+
+    // None turns FXAA off, the pipeline is kept for turning it back on
+    pub fn set_fxaa(&mut self, device: &ash::Device, settings: Option<FxaaSettings>) -> Result<(), FloError> {
+        match (settings, &mut self.fxaa) {
+            (None, Some(fxaa)) => fxaa.enabled = false,
+            (None, None) => {}
+            (Some(settings), Some(fxaa)) => {
+                fxaa.params = effect_params(&settings.params())?;
+                fxaa.enabled = true;
+            }
+            (Some(settings), None) => {
+                self.fxaa = Some(self.create_effect(device, "shaders/fxaa.frag.spv", &settings.params())?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_params(&mut self, effect: PostEffectId, params: &[f32]) -> Result<(), FloError> {
+        let params = effect_params(params)?;
+        let effect = self.effects.get_mut(effect.0).ok_or("Unknown post effect")?;
+        effect.params = params;
+        Ok(())
+    }
+
+    pub fn set_enabled(&mut self, effect: PostEffectId, enabled: bool) {
+        if let Some(effect) = self.effects.get_mut(effect.0) {
+            effect.enabled = enabled;
+        }
+    }
+
+    // Records the enabled effects after the scene pass and returns the set the present pass
+    // should sample: the scene's HDR image when no effect is enabled, else the last target.
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, image_index: usize) -> vk::DescriptorSet {
+        let descriptor_sets = &self.descriptor_sets[image_index];
+        let mut source = descriptor_sets[0];
+
+        let clear_values = [vk::ClearValue {
+            color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] },
+        }];
+        let effects = self.effects.iter().chain(self.fxaa.iter());
+        for (pass, effect) in effects.filter(|effect| effect.enabled).enumerate() {
+            let target = pass % 2;
+            let push_constants = PostEffectPushConstants {
+                texel_size: [1.0 / self.extent.width as f32, 1.0 / self.extent.height as f32],
+                params: effect.params,
+            };
+
+            let render_pass_info = vk::RenderPassBeginInfo::default()
+                .render_pass(self.render_pass)
+                .framebuffer(self.framebuffers[image_index][target])
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: self.extent,
+                })
+                .clear_values(&clear_values);
+
+            unsafe {
+                device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+                set_viewport_and_scissor(device, command_buffer, self.extent);
+
+                // No depth testing, so there's no compare op to set
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, effect.pipeline);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    effect.pipeline_layout,
+                    0,
+                    &[source],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    effect.pipeline_layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    bytemuck::bytes_of(&push_constants),
+                );
+                device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                end_scene_render_pass(device, command_buffer);
+            }
+
+            source = descriptor_sets[1 + target];
+        }
+
+        source
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        for effect in self.effects.iter().chain(self.fxaa.iter()) {
+            destroy_pipeline(device, effect.pipeline, effect.pipeline_layout);
+        }
+        unsafe {
+            leak_check::destroy(device, self.descriptor_pool);
+            leak_check::destroy(device, self.descriptor_set_layout);
+            leak_check::destroy(device, self.sampler);
+        }
+        self.destroy_targets(device);
+        unsafe {
+            leak_check::destroy(device, self.render_pass);
+        }
+    }
+}
+
+// Image, memory and view of one ping-pong target
+type Target = (vk::Image, vk::DeviceMemory, vk::ImageView);
+
+// Ping-pong pair and their framebuffers per swapchain image
+fn create_targets(
+    instance: &Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    render_pass: vk::RenderPass,
+    image_count: usize,
+    extent: vk::Extent2D,
+) -> Result<(Vec<[Target; 2]>, Vec<[vk::Framebuffer; 2]>), FloError> {
+    let mut targets = Vec::with_capacity(image_count);
+    let mut framebuffers = Vec::with_capacity(image_count);
+    for _ in 0..image_count {
+        let ping = create_color_target(instance, device, physical_device, HDR_FORMAT, extent)?;
+        let pong = create_color_target(instance, device, physical_device, HDR_FORMAT, extent)?;
+        let pair_framebuffers = create_framebuffers_no_depth(device, &[ping.2, pong.2], render_pass, extent)?;
+        targets.push([ping, pong]);
+        framebuffers.push([pair_framebuffers[0], pair_framebuffers[1]]);
+    }
+    Ok((targets, framebuffers))
+}
+
+// Unused params are pushed as 0
+fn effect_params(params: &[f32]) -> Result<[f32; MAX_POST_EFFECT_PARAMS], FloError> {
+    if params.len() > MAX_POST_EFFECT_PARAMS {
+        return Err(format!("Post effects take at most {} params, got {}", MAX_POST_EFFECT_PARAMS, params.len()).into());
+    }
+    let mut padded = [0.0; MAX_POST_EFFECT_PARAMS];
+    padded[..params.len()].copy_from_slice(params);
+    Ok(padded)
+}
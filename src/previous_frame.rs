@@ -0,0 +1,174 @@
+use ash::{vk, Instance};
+use crate::error::FloError;
+use crate::render_target::RenderTarget;
+use crate::texture::{begin_single_time_commands, end_single_time_commands};
+use crate::vulkan_common::end_scene_render_pass;
+
+// Copy of the last frame's HDR scene color, sampled by meshes through the set 0 texture binding.
+// See VulkanRenderer::enable_previous_frame_access.
+pub(crate) struct PreviousFrame {
+    // Color only, its render pass only clears it
+    pub target: RenderTarget,
+}
+
+impl PreviousFrame {
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        color_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<Self, FloError> {
+        let target = RenderTarget::new(instance, device, physical_device, color_format, None, extent)?;
+        let previous_frame = Self { target };
+        if let Err(e) = previous_frame.clear(device, command_pool, queue) {
+            previous_frame.destroy(device);
+            return Err(e);
+        }
+        Ok(previous_frame)
+    }
+
+    // Until a frame was copied in the target is all zeros, alpha 0 included, which shaders
+    // take as no history
+    fn clear(&self, device: &ash::Device, command_pool: vk::CommandPool, queue: vk::Queue) -> Result<(), FloError> {
+        let clear_values = [vk::ClearValue {
+            color: vk::ClearColorValue { float32: [0.0; 4] },
+        }];
+        let render_pass_info = vk::RenderPassBeginInfo::default()
+            .render_pass(self.target.render_pass)
+            .framebuffer(self.target.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.target.extent,
+            })
+            .clear_values(&clear_values);
+
+        let command_buffer = begin_single_time_commands(device, command_pool)?;
+        unsafe {
+            device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+            end_scene_render_pass(device, command_buffer);
+        }
+        end_single_time_commands(device, command_pool, queue, command_buffer)?;
+        Ok(())
+    }
+
+    // Copies the scene image, in SHADER_READ_ONLY_OPTIMAL after the scene pass, into the target
+    // and leaves both in that layout. Draws earlier in the command buffer sample the last frame.
+    pub fn record_copy(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, scene_image: vk::Image) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let barrier = |image, old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::default()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(subresource_range)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+        };
+        let to_transfer = [
+            barrier(
+                scene_image,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            barrier(
+                self.target.color_image,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::SHADER_READ,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        ];
+        let to_shader_read = [
+            barrier(
+                scene_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            barrier(
+                self.target.color_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+            ),
+        ];
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let region = vk::ImageCopy::default()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(vk::Extent3D { width: self.target.extent.width, height: self.target.extent.height, depth: 1 });
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer,
+            );
+            device.cmd_copy_image(
+                command_buffer,
+                scene_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.target.color_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_shader_read,
+            );
+        }
+    }
+
+    // Follows the scene's render extent, dropping the history. The device has to be idle.
+    #[allow(clippy::too_many_arguments)]
+    pub fn resize(
+        &mut self,
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        color_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<(), FloError> {
+        if self.target.extent == extent {
+            return Ok(());
+        }
+        let resized = Self::new(instance, device, physical_device, command_pool, queue, color_format, extent)?;
+        std::mem::replace(self, resized).destroy(device);
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        self.target.destroy(device);
+    }
+}
@@ -0,0 +1,230 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+use bevy_math::{Vec2, Vec3};
+use crate::mesh::{MeshData, Vertex};
+
+// Procedural meshes for the renderer, centered on the origin with Y up. Triangles wind
+// COUNTER_CLOCKWISE seen from outside (see MeshData::detect_winding), UVs have their origin at
+// the top left. `segments` counts the divisions around the Y axis, `rings` the ones along it.
+// Use MeshData::with_uv_scale to tile textures and MeshData::invert for a sky dome or room.
+
+// Axis aligned box. With scale_uvs each face's UVs cover the face's share of the largest side,
+// so a texture keeps its aspect on every face instead of stretching to each one.
+pub fn cuboid(width: f32, height: f32, depth: f32, scale_uvs: bool) -> MeshData {
+    let half = Vec3::new(width, height, depth) * 0.5;
+    let largest = width.max(height).max(depth).max(f32::EPSILON);
+    // Normal, right and up of each face, right x up is the normal
+    let faces = [
+        (Vec3::Z, Vec3::X, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+        (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+        (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+        (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+    ];
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, right, up) in faces {
+        let right_extent = (right * half).abs().max_element();
+        let up_extent = (up * half).abs().max_element();
+        let (u_max, v_max) = if scale_uvs {
+            (right_extent * 2.0 / largest, up_extent * 2.0 / largest)
+        } else {
+            (1.0, 1.0)
+        };
+        let center = normal * half;
+        let first = vertices.len() as u32;
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let position = center + right * (x * right_extent) + up * (y * up_extent);
+            let uv = [(x + 1.0) * 0.5 * u_max, (1.0 - y) * 0.5 * v_max];
+            vertices.push(Vertex::new(position.to_array(), normal.to_array(), uv));
+        }
+        indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+    MeshData::new(vertices, indices)
+}
+
+// Flat grid in the XZ plane facing +Y, with subdivisions cells per side
+pub fn plane(width: f32, depth: f32, subdivisions: u32) -> MeshData {
+    let subdivisions = subdivisions.max(1);
+    let vertices_per_side = subdivisions + 1;
+    let mut vertices = Vec::with_capacity((vertices_per_side * vertices_per_side) as usize);
+    for z in 0..vertices_per_side {
+        for x in 0..vertices_per_side {
+            let u = x as f32 / subdivisions as f32;
+            let v = z as f32 / subdivisions as f32;
+            vertices.push(Vertex::new([(u - 0.5) * width, 0.0, (v - 0.5) * depth], [0.0, 1.0, 0.0], [u, v]));
+        }
+    }
+
+    let mut indices = Vec::with_capacity((subdivisions * subdivisions * 6) as usize);
+    for z in 0..subdivisions {
+        for x in 0..subdivisions {
+            let top_left = z * vertices_per_side + x;
+            let top_right = top_left + 1;
+            let bottom_left = top_left + vertices_per_side;
+            let bottom_right = bottom_left + 1;
+            indices.extend_from_slice(&[top_left, bottom_left, top_right, top_right, bottom_left, bottom_right]);
+        }
+    }
+    MeshData::new(vertices, indices)
+}
+
+// UV sphere with rings bands from pole to pole
+pub fn sphere(radius: f32, segments: u32, rings: u32) -> MeshData {
+    let profile = arc(radius, 0.0, -FRAC_PI_2, FRAC_PI_2, rings.max(2)).collect();
+    revolve(&[profile], segments)
+}
+
+// Closed cylinder of the given height, with flat caps
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> MeshData {
+    let half_height = height * 0.5;
+    revolve(&[
+        disk(radius, -half_height, false),
+        vec![
+            ProfilePoint { radius, y: -half_height, normal: Vec2::X },
+            ProfilePoint { radius, y: half_height, normal: Vec2::X },
+        ],
+        disk(radius, half_height, true),
+    ], segments)
+}
+
+// Cone with its base at -height / 2 and its tip at height / 2
+pub fn cone(radius: f32, height: f32, segments: u32) -> MeshData {
+    let half_height = height * 0.5;
+    let normal = Vec2::new(height, radius).normalize_or_zero();
+    revolve(&[
+        disk(radius, -half_height, false),
+        vec![
+            ProfilePoint { radius, y: -half_height, normal },
+            ProfilePoint { radius: 0.0, y: half_height, normal },
+        ],
+    ], segments)
+}
+
+// Ring around the Y axis. The tube's cross section is split into rings bands.
+pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(3);
+    let profile = (0..=rings).map(|ring| {
+        let (sin, cos) = (ring as f32 / rings as f32 * TAU).sin_cos();
+        ProfilePoint { radius: major_radius + cos * minor_radius, y: sin * minor_radius, normal: Vec2::new(cos, sin) }
+    }).collect();
+    revolve(&[profile], segments)
+}
+
+// Cylinder of the given length between two hemispheres, so its total height is
+// length + 2 * radius. rings is the number of bands per hemisphere.
+pub fn capsule(radius: f32, length: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(1);
+    let half_length = length * 0.5;
+    let profile = arc(radius, -half_length, -FRAC_PI_2, 0.0, rings)
+        .chain(arc(radius, half_length, 0.0, FRAC_PI_2, rings))
+        .collect();
+    revolve(&[profile], segments)
+}
+
+// A point of the outline revolved around the Y axis, with its normal in the (radius, y) plane
+#[derive(Clone, Copy)]
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: Vec2,
+}
+
+// Points on a circle of the radius around (0, center_y), from angle `from` to `to` above the
+// horizontal
+fn arc(radius: f32, center_y: f32, from: f32, to: f32, steps: u32) -> impl Iterator<Item = ProfilePoint> {
+    (0..=steps).map(move |step| {
+        let angle = from + (to - from) * step as f32 / steps as f32;
+        // cos(FRAC_PI_2) isn't exactly zero in f32, poles have to land on the axis
+        let normal = if angle.abs() >= FRAC_PI_2 - 1e-4 {
+            Vec2::new(0.0, angle.signum())
+        } else {
+            Vec2::new(angle.cos(), angle.sin())
+        };
+        ProfilePoint { radius: normal.x * radius, y: center_y + normal.y * radius, normal }
+    })
+}
+
+// Flat cap at y, from the center out for a bottom cap and from the rim in for a top one so
+// both wind towards their normal
+fn disk(radius: f32, y: f32, facing_up: bool) -> Vec<ProfilePoint> {
+    let normal = if facing_up { Vec2::Y } else { Vec2::NEG_Y };
+    let center = ProfilePoint { radius: 0.0, y, normal };
+    let rim = ProfilePoint { radius, y, normal };
+    if facing_up { vec![rim, center] } else { vec![center, rim] }
+}
+
+// Sweeps each profile once around the Y axis. Profiles are separate so hard edges (a
+// cylinder's rims) get their own vertices. U runs around the axis, V from 1 at a profile's
+// first point to 0 at its last, by length along the profile.
+fn revolve(profiles: &[Vec<ProfilePoint>], segments: u32) -> MeshData {
+    let segments = segments.max(3);
+    let row_len = segments + 1;
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for profile in profiles {
+        let mut lengths = vec![0.0];
+        for pair in profile.windows(2) {
+            let step = Vec2::new(pair[1].radius - pair[0].radius, pair[1].y - pair[0].y).length();
+            lengths.push(lengths[lengths.len() - 1] + step);
+        }
+        let total_length = lengths[lengths.len() - 1].max(f32::EPSILON);
+
+        let first = vertices.len() as u32;
+        for (point, length) in profile.iter().zip(&lengths) {
+            for segment in 0..row_len {
+                let u = segment as f32 / segments as f32;
+                let (sin, cos) = if segment == segments { (0.0, 1.0) } else { (u * TAU).sin_cos() };
+                vertices.push(Vertex::new(
+                    [point.radius * sin, point.y, point.radius * cos],
+                    [point.normal.x * sin, point.normal.y, point.normal.x * cos],
+                    [u, 1.0 - length / total_length],
+                ));
+            }
+        }
+
+        for (ring, pair) in profile.windows(2).enumerate() {
+            for segment in 0..segments {
+                let a = first + ring as u32 * row_len + segment;
+                let b = a + 1;
+                let c = a + row_len;
+                let d = c + 1;
+                // A ring on the axis is a single point, which leaves one triangle of the quad
+                if pair[0].radius > 0.0 {
+                    indices.extend_from_slice(&[a, b, c]);
+                }
+                if pair[1].radius > 0.0 {
+                    indices.extend_from_slice(&[b, d, c]);
+                }
+            }
+        }
+    }
+    MeshData::new(vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Vertex count, index count and winding of each shape at low tessellation
+    #[test]
+    fn shapes_have_the_expected_vertex_and_index_counts() {
+        let shapes = [
+            ("cuboid", cuboid(1.0, 2.0, 3.0, true), 24, 36),
+            ("plane", plane(2.0, 2.0, 4), 25, 4 * 4 * 6),
+            // Pole bands have one triangle per segment
+            ("sphere", sphere(1.0, 8, 4), 5 * 9, (8 + 16 + 16 + 8) * 3),
+            ("cylinder", cylinder(1.0, 2.0, 8), 6 * 9, (8 + 16 + 8) * 3),
+            ("cone", cone(1.0, 2.0, 8), 4 * 9, (8 + 8) * 3),
+            ("torus", torus(2.0, 0.5, 8, 6), 7 * 9, 6 * 16 * 3),
+            ("capsule", capsule(0.5, 1.0, 8, 2), 6 * 9, (8 + 16 + 16 + 16 + 8) * 3),
+        ];
+        for (name, mesh, vertex_count, index_count) in shapes {
+            assert_eq!(mesh.vertices.len(), vertex_count, "{}", name);
+            assert_eq!(mesh.indices.len(), index_count, "{}", name);
+            assert!(mesh.indices.iter().all(|&index| (index as usize) < vertex_count), "{}", name);
+            assert_eq!(mesh.detect_winding(), ash::vk::FrontFace::COUNTER_CLOCKWISE, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// Small deterministic RNG for procedural generation (SplitMix64). Implemented here rather than
+// borrowed from a crate so the sequence for a seed never changes with a dependency update, and
+// floats are built from integer bits with exact operations so every platform gets the same values.
+#[derive(Clone, Debug)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1), 24 random bits so every value is exactly representable
+    pub fn f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range_f32(&mut self, range: std::ops::Range<f32>) -> f32 {
+        range.start + (range.end - range.start) * self.f32()
+    }
+
+    // Uniform in range, range must not be empty
+    pub fn range_usize(&mut self, range: std::ops::Range<usize>) -> usize {
+        assert!(range.start < range.end, "Empty range");
+        // Multiply-shift instead of modulo to avoid the bias towards low values
+        let len = (range.end - range.start) as u128;
+        range.start + ((self.next_u64() as u128 * len) >> 64) as usize
+    }
+}
+
+// Random positions inside the box (min, max), e.g. for instanced meshes.
+// The same seed always gives the same positions, drawn x, y, z per position in order.
+pub fn scatter_positions(seed: u64, count: usize, min: [f32; 3], max: [f32; 3]) -> Vec<[f32; 3]> {
+    let mut rng = SeededRng::new(seed);
+    (0..count)
+        .map(|_| {
+            let x = rng.range_f32(min[0]..max[0]);
+            let y = rng.range_f32(min[1]..max[1]);
+            let z = rng.range_f32(min[2]..max[2]);
+            [x, y, z]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_sequence() {
+        let mut a = SeededRng::new(42);
+        let mut b = SeededRng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(SeededRng::new(1).next_u64(), SeededRng::new(2).next_u64());
+    }
+
+    #[test]
+    fn sequence_never_changes() {
+        // SplitMix64's published outputs for seed 0, a changed constant or shift breaks this
+        let mut rng = SeededRng::new(0);
+        assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(rng.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+        assert_eq!(rng.next_u64(), 0x06C4_5D18_8009_454F);
+    }
+
+    #[test]
+    fn floats_stay_in_range() {
+        let mut rng = SeededRng::new(7);
+        for _ in 0..1000 {
+            let value = rng.f32();
+            assert!((0.0..1.0).contains(&value));
+            assert!((-3.0..5.0).contains(&rng.range_f32(-3.0..5.0)));
+            assert!((10..13).contains(&rng.range_usize(10..13)));
+        }
+    }
+
+    #[test]
+    fn scatter_positions_are_reproducible_and_inside_bounds() {
+        let min = [-10.0, -7.0, -5.0];
+        let max = [10.0, 7.0, 5.0];
+        let positions = scatter_positions(3, 500, min, max);
+        assert_eq!(positions, scatter_positions(3, 500, min, max));
+        assert_ne!(positions, scatter_positions(4, 500, min, max));
+        for position in &positions {
+            for axis in 0..3 {
+                assert!(position[axis] >= min[axis] && position[axis] < max[axis]);
+            }
+        }
+        // A longer scatter starts with the same positions
+        assert_eq!(&scatter_positions(3, 600, min, max)[..500], &positions[..]);
+    }
+}
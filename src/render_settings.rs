@@ -0,0 +1,69 @@
+use crate::post_process::AaMode;
+use crate::shadow_cascades::CascadeSettings;
+use crate::vulkan_common::{DeviceSelection, PresentMode};
+
+// Graphics options an application exposes to players, saved and loaded with any serde format.
+// See VulkanRenderer::new_multi_mesh_with_settings and apply_settings.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    // Forces PresentMode::Fifo when on
+    pub vsync: bool,
+    // Used when vsync is off
+    pub present_mode: PresentMode,
+    // MSAA levels are AaMode::Msaa, which the renderer doesn't support yet
+    pub antialiasing: AaMode,
+    // Anisotropic filtering of textures loaded afterwards, 1 turns it off
+    pub max_anisotropy: f32,
+    pub shadow_quality: ShadowQuality,
+    // Only takes effect when the renderer is created
+    pub device: DeviceSelection,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            vsync: false,
+            present_mode: PresentMode::default(),
+            antialiasing: AaMode::None,
+            max_anisotropy: 16.0,
+            shadow_quality: ShadowQuality::default(),
+            device: DeviceSelection::Auto,
+        }
+    }
+}
+
+impl RenderSettings {
+    // The mode the swapchain is created with
+    pub fn swapchain_present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::Fifo
+        } else {
+            self.present_mode
+        }
+    }
+}
+
+// Presets for shadow_cascades. The renderer has no shadow pass, applications read
+// cascade_settings for the one they set up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ShadowQuality {
+    Off,
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ShadowQuality {
+    // None when shadows are off
+    pub fn cascade_settings(self) -> Option<CascadeSettings> {
+        let (count, resolution) = match self {
+            ShadowQuality::Off => return None,
+            ShadowQuality::Low => (2, 1024),
+            ShadowQuality::Medium => (3, 2048),
+            ShadowQuality::High => (4, 4096),
+        };
+        Some(CascadeSettings { count, resolution, ..CascadeSettings::default() })
+    }
+}
@@ -0,0 +1,127 @@
+use ash::{vk, Instance};
+use crate::error::FloError;
+use crate::vulkan_common::*;
+use crate::leak_check;
+
+// Identifies a render target created with VulkanRenderer::create_render_target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RenderTargetId(pub(crate) usize);
+
+// Offscreen color (+ depth) image the scene can be drawn into and then sampled like a
+// texture, for in-world screens, mirrors and the like. Created with the main render pass's
+// attachment formats, so every pipeline created for the scene can draw into it as well.
+pub struct RenderTarget {
+    pub extent: vk::Extent2D,
+    pub color_image: vk::Image,
+    pub color_image_memory: vk::DeviceMemory,
+    pub color_image_view: vk::ImageView,
+    // Null when the renderer was created without depth
+    pub depth_image: vk::Image,
+    pub depth_image_memory: vk::DeviceMemory,
+    pub depth_image_view: vk::ImageView,
+    pub render_pass: vk::RenderPass,
+    pub framebuffer: vk::Framebuffer,
+    pub sampler: vk::Sampler,
+    // Single combined image sampler set, laid out like a mesh texture's
+    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+impl RenderTarget {
+    pub fn new(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        color_format: vk::Format,
+        depth_format: Option<vk::Format>,
+        extent: vk::Extent2D,
+    ) -> Result<Self, FloError> {
+        if extent.width == 0 || extent.height == 0 {
+            return Err(format!("Render target size {}x{} is empty", extent.width, extent.height).into());
+        }
+
+        let (color_image, color_image_memory, color_image_view) =
+            create_color_target(instance, device, physical_device, color_format, extent)?;
+
+        let (depth_image, depth_image_memory, depth_image_view) = if let Some(depth_format) = depth_format {
+            create_depth_resources(instance, device, physical_device, extent, depth_format)?
+        } else {
+            (vk::Image::null(), vk::DeviceMemory::null(), vk::ImageView::null())
+        };
+
+        let render_pass = create_render_pass(device, color_format, depth_format)?;
+
+        let attachments = if depth_format.is_some() {
+            vec![color_image_view, depth_image_view]
+        } else {
+            vec![color_image_view]
+        };
+        let framebuffer_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = leak_check::created(device, unsafe { device.create_framebuffer(&framebuffer_info, None)? });
+
+        // Clamp so the edges of a screen don't bleed into each other
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR);
+        let sampler = leak_check::created(device, unsafe { device.create_sampler(&sampler_info, None)? });
+
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let descriptor_set_layout = create_descriptor_set_layout(device, &[binding])?;
+
+        let pool_size = vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1);
+        let descriptor_pool = create_descriptor_pool(device, 1, &[pool_size])?;
+
+        let descriptor_set = allocate_descriptor_sets(device, descriptor_pool, &[descriptor_set_layout])?[0];
+        update_descriptor_sets_texture(device, descriptor_set, color_image_view, sampler, 0);
+
+        Ok(Self {
+            extent,
+            color_image,
+            color_image_memory,
+            color_image_view,
+            depth_image,
+            depth_image_memory,
+            depth_image_view,
+            render_pass,
+            framebuffer,
+            sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_set,
+        })
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            leak_check::destroy(device, self.descriptor_pool);
+            leak_check::destroy(device, self.descriptor_set_layout);
+            leak_check::destroy(device, self.sampler);
+            leak_check::destroy(device, self.framebuffer);
+            leak_check::destroy(device, self.render_pass);
+        }
+        destroy_image(device, self.color_image, self.color_image_memory, self.color_image_view);
+        if self.depth_image != vk::Image::null() {
+            destroy_image(device, self.depth_image, self.depth_image_memory, self.depth_image_view);
+        }
+    }
+}
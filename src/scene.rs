@@ -0,0 +1,377 @@
+use bevy_math::Mat4;
+use crate::error::FloError;
+use crate::mesh::MeshData;
+use crate::vulkan_renderer_unified::{SkyGradient, VulkanRenderer};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::warn;
+
+// Stable reference to a mesh spawned through a Scene. The generation changes every time
+// the slot is freed, so a handle kept around after despawn is rejected instead of
+// silently pointing at whatever mesh reuses the slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle {
+    index: usize,
+    generation: u32,
+}
+
+impl MeshHandle {
+    // Index of the mesh in the renderer, for calling low level renderer methods directly
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Material {
+    pub pipeline_name: Option<String>,
+    pub base_color: [f32; 4],
+    pub texture_path: Option<String>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            pipeline_name: None,
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            texture_path: None,
+        }
+    }
+}
+
+// The file and mesh a mesh was spawned from with spawn_asset, which scene files refer to
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MeshAsset {
+    // OBJ, glTF or GLB
+    pub path: String,
+    // Index into the meshes load_obj or load_primitives return for the file
+    #[serde(default)]
+    pub primitive: usize,
+}
+
+// View and projection the application renders the scene with, kept by the scene so they're
+// saved with it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneCamera {
+    pub view: Mat4,
+    pub proj: Mat4,
+}
+
+// A mesh Scene::load couldn't re-import
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailedAsset {
+    pub path: String,
+    pub error: String,
+}
+
+// What Scene::save writes. Matrices are column major.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SceneFile {
+    #[serde(default)]
+    meshes: Vec<SceneFileMesh>,
+    #[serde(default)]
+    camera: Option<SceneFileCamera>,
+    #[serde(default)]
+    sky_gradient: Option<SkyGradient>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SceneFileMesh {
+    asset: MeshAsset,
+    transform: [f32; 16],
+    #[serde(default)]
+    material: Material,
+    visible: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SceneFileCamera {
+    view: [f32; 16],
+    proj: [f32; 16],
+}
+
+struct Slot {
+    generation: u32,
+    alive: bool,
+    // Meshes added to the renderer before the scene was created aren't handed out or reused
+    owned: bool,
+    transform: Mat4,
+    visible: bool,
+    // Both are only kept for save
+    asset: Option<MeshAsset>,
+    material: Material,
+}
+
+// Handle based layer over VulkanRenderer's mesh indices. Slots are 1:1 with renderer
+// mesh indices, and freed slots are refilled in place with replace_mesh.
+pub struct Scene {
+    renderer: VulkanRenderer,
+    slots: Vec<Slot>,
+    free_slots: Vec<usize>,
+    camera: Option<SceneCamera>,
+}
+
+impl Scene {
+    pub fn new(renderer: VulkanRenderer) -> Self {
+        let slots = (0..renderer.get_mesh_count())
+            .map(|_| Slot {
+                generation: 0,
+                alive: true,
+                owned: false,
+                transform: Mat4::IDENTITY,
+                visible: true,
+                asset: None,
+                material: Material::default(),
+            })
+            .collect();
+
+        Self {
+            renderer,
+            slots,
+            free_slots: Vec::new(),
+            camera: None,
+        }
+    }
+
+    pub fn renderer(&self) -> &VulkanRenderer {
+        &self.renderer
+    }
+
+    // Escape hatch for rendering and anything the scene doesn't wrap.
+    // Adding or removing meshes through it directly will desync the scene's slots.
+    pub fn renderer_mut(&mut self) -> &mut VulkanRenderer {
+        &mut self.renderer
+    }
+
+    pub fn into_renderer(self) -> VulkanRenderer {
+        self.renderer
+    }
+
+    pub fn spawn(&mut self, mesh_data: &MeshData, transform: Mat4) -> Result<MeshHandle, FloError> {
+        let index = if let Some(index) = self.free_slots.pop() {
+            if let Err(e) = self.renderer.replace_mesh(index, mesh_data) {
+                self.free_slots.push(index);
+                return Err(e);
+            }
+            index
+        } else {
+            let index = self.renderer.add_mesh(mesh_data)?;
+            // The renderer appends, so this keeps slots and mesh indices lined up
+            debug_assert_eq!(index, self.slots.len());
+            self.slots.push(Slot {
+                generation: 0,
+                alive: false,
+                owned: true,
+                transform: Mat4::IDENTITY,
+                visible: true,
+                asset: None,
+                material: Material::default(),
+            });
+            index
+        };
+
+        let slot = &mut self.slots[index];
+        slot.alive = true;
+        slot.transform = transform;
+        slot.visible = true;
+        slot.asset = None;
+        slot.material = Material::default();
+        let handle = MeshHandle { index, generation: slot.generation };
+
+        self.renderer.update_mesh_transforms(index, vec![transform]);
+        Ok(handle)
+    }
+
+    // Spawns one mesh of an asset file. Unlike spawn, the mesh is saved in scene files.
+    pub fn spawn_asset(&mut self, asset: MeshAsset, transform: Mat4) -> Result<MeshHandle, FloError> {
+        let meshes = load_mesh_asset(&asset.path)?;
+        let mesh_data = meshes.get(asset.primitive)
+            .ok_or_else(|| format!("{} has no mesh {}", asset.path, asset.primitive))?;
+        let handle = self.spawn(mesh_data, transform)?;
+        self.slots[handle.index].asset = Some(asset);
+        Ok(handle)
+    }
+
+    pub fn despawn(&mut self, handle: MeshHandle) -> Result<(), FloError> {
+        self.check(handle)?;
+
+        self.renderer.remove_mesh(handle.index);
+
+        let slot = &mut self.slots[handle.index];
+        slot.alive = false;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(handle.index);
+        Ok(())
+    }
+
+    pub fn is_alive(&self, handle: MeshHandle) -> bool {
+        self.check(handle).is_ok()
+    }
+
+    pub fn set_transform(&mut self, handle: MeshHandle, transform: Mat4) -> Result<(), FloError> {
+        self.check(handle)?;
+
+        let slot = &mut self.slots[handle.index];
+        slot.transform = transform;
+        if slot.visible {
+            self.renderer.update_mesh_transforms(handle.index, vec![transform]);
+        }
+        Ok(())
+    }
+
+    pub fn transform(&self, handle: MeshHandle) -> Result<Mat4, FloError> {
+        self.check(handle)?;
+        Ok(self.slots[handle.index].transform)
+    }
+
+    pub fn set_material(&mut self, handle: MeshHandle, material: &Material) -> Result<(), FloError> {
+        self.check(handle)?;
+
+        if let Some(ref pipeline_name) = material.pipeline_name {
+            self.renderer.set_mesh_pipeline(handle.index, pipeline_name);
+        }
+        self.renderer.set_mesh_color(handle.index, material.base_color);
+        if let Some(ref texture_path) = material.texture_path {
+            self.renderer.set_mesh_texture_from_file(handle.index, texture_path)?;
+        }
+        self.slots[handle.index].material = material.clone();
+        Ok(())
+    }
+
+    // Hidden meshes keep their transform and GPU buffers, they're just skipped when drawing
+    pub fn set_visible(&mut self, handle: MeshHandle, visible: bool) -> Result<(), FloError> {
+        self.check(handle)?;
+
+        let slot = &mut self.slots[handle.index];
+        slot.visible = visible;
+        // The renderer skips meshes without transforms
+        let transforms = if visible { vec![slot.transform] } else { Vec::new() };
+        self.renderer.update_mesh_transforms(handle.index, transforms);
+        Ok(())
+    }
+
+    pub fn set_camera(&mut self, camera: Option<SceneCamera>) {
+        self.camera = camera;
+    }
+
+    pub fn camera(&self) -> Option<SceneCamera> {
+        self.camera
+    }
+
+    // Writes the meshes spawned with spawn_asset, with their transforms, materials and
+    // visibility, the camera and the sky gradient as JSON. Meshes spawned from MeshData have
+    // no file to refer to and are left out.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), FloError> {
+        let mut meshes = Vec::new();
+        for (index, slot) in self.slots.iter().enumerate().filter(|(_, slot)| slot.owned && slot.alive) {
+            let Some(ref asset) = slot.asset else {
+                warn!("Mesh {} wasn't spawned from an asset and isn't saved", index);
+                continue;
+            };
+            meshes.push(SceneFileMesh {
+                asset: asset.clone(),
+                transform: slot.transform.to_cols_array(),
+                material: slot.material.clone(),
+                visible: slot.visible,
+            });
+        }
+        let scene_file = SceneFile {
+            meshes,
+            camera: self.camera.map(|camera| SceneFileCamera {
+                view: camera.view.to_cols_array(),
+                proj: camera.proj.to_cols_array(),
+            }),
+            sky_gradient: self.renderer.sky_gradient(),
+        };
+
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(&scene_file)
+            .map_err(|e| format!("Failed to serialize scene file {}: {}", path.display(), e))?;
+        // Io keeps the error kind, e.g. NotFound for a missing directory
+        std::fs::write(path, json)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to write scene file {}: {}", path.display(), e)))?;
+        Ok(())
+    }
+
+    // Replaces the scene's meshes, camera and sky gradient with a file written by save.
+    // Meshes added to the renderer before the scene was created stay. Meshes whose asset
+    // can't be loaded are skipped and returned, one entry per path and error, the rest of
+    // the scene still loads.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<FailedAsset>, FloError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to read scene file {}: {}", path.display(), e)))?;
+        let scene_file: SceneFile = serde_json::from_str(&source)
+            .map_err(|e| format!("Failed to parse scene file {}: {}", path.display(), e))?;
+
+        let spawned: Vec<MeshHandle> = self.slots.iter().enumerate()
+            .filter(|(_, slot)| slot.owned && slot.alive)
+            .map(|(index, slot)| MeshHandle { index, generation: slot.generation })
+            .collect();
+        for handle in spawned {
+            self.despawn(handle)?;
+        }
+
+        // Files are loaded once however many meshes refer to them
+        let mut assets: HashMap<String, Result<Vec<MeshData>, String>> = HashMap::new();
+        let mut failed: Vec<FailedAsset> = Vec::new();
+        for mesh in scene_file.meshes {
+            let asset_path = mesh.asset.path.clone();
+            let meshes = assets.entry(asset_path.clone())
+                .or_insert_with(|| load_mesh_asset(&asset_path));
+            let result = match meshes {
+                Ok(meshes) => match meshes.get(mesh.asset.primitive) {
+                    Some(mesh_data) => self.spawn(mesh_data, Mat4::from_cols_array(&mesh.transform))
+                        .map_err(|e| e.to_string()),
+                    None => Err(format!("{} has no mesh {}", asset_path, mesh.asset.primitive)),
+                },
+                Err(e) => Err(e.clone()),
+            };
+            let result = result.and_then(|handle| {
+                self.slots[handle.index].asset = Some(mesh.asset);
+                self.set_material(handle, &mesh.material)
+                    .and_then(|_| self.set_visible(handle, mesh.visible))
+                    .map_err(|e| e.to_string())
+            });
+            if let Err(error) = result {
+                let failure = FailedAsset { path: asset_path, error };
+                if !failed.contains(&failure) {
+                    warn!("Failed to load {} for the scene: {}", failure.path, failure.error);
+                    failed.push(failure);
+                }
+            }
+        }
+
+        self.camera = scene_file.camera.map(|camera| SceneCamera {
+            view: Mat4::from_cols_array(&camera.view),
+            proj: Mat4::from_cols_array(&camera.proj),
+        });
+        match scene_file.sky_gradient {
+            Some(sky) => self.renderer.set_sky_gradient(sky.horizon, sky.zenith, sky.sun_direction, sky.sun_intensity),
+            None => self.renderer.clear_sky_gradient(),
+        }
+        Ok(failed)
+    }
+
+    fn check(&self, handle: MeshHandle) -> Result<(), FloError> {
+        match self.slots.get(handle.index) {
+            Some(slot) if slot.owned && slot.alive && slot.generation == handle.generation => Ok(()),
+            Some(_) => Err(format!("Stale mesh handle {:?}", handle).into()),
+            None => Err(FloError::InvalidMeshIndex(handle.index)),
+        }
+    }
+}
+
+// Every mesh of an OBJ or glTF file, in the order their loaders return them
+fn load_mesh_asset(path: &str) -> Result<Vec<MeshData>, String> {
+    let extension = Path::new(path).extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let meshes = match extension.as_deref() {
+        Some("obj") => crate::obj_loader::load_obj(path)?,
+        Some("gltf") | Some("glb") => crate::gltf_loader::load_primitives(path)?,
+        _ => return Err(format!("Unsupported mesh file {}, expected .obj, .gltf or .glb", path)),
+    };
+    Ok(meshes.into_iter().map(|(mesh_data, _)| mesh_data).collect())
+}
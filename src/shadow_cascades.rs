@@ -0,0 +1,212 @@
+use bevy_math::{Mat4, Vec3, Vec4};
+
+// Layers of the shadow map array, and entries in ShadowCascadeUniforms
+pub const MAX_SHADOW_CASCADES: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CascadeSettings {
+    // 2 to MAX_SHADOW_CASCADES
+    pub count: usize,
+    // 0 splits the depth range evenly, 1 logarithmically. Between the two keeps near cascades
+    // sharp without the far ones getting too long.
+    pub split_lambda: f32,
+    // Width and height of each shadow map layer in texels, used to snap the cascades
+    pub resolution: u32,
+    // How far behind a cascade (towards the light) casters are still rendered into it
+    pub caster_distance: f32,
+    // Fraction of a cascade's depth range blended with the next one, 0 for hard seams
+    pub blend_band: f32,
+}
+
+impl Default for CascadeSettings {
+    fn default() -> Self {
+        Self {
+            count: 2,
+            split_lambda: 0.75,
+            resolution: 2048,
+            caster_distance: 50.0,
+            blend_band: 0.0,
+        }
+    }
+}
+
+// Tuning of the application's shadow pass and sampling, see VulkanRenderer::set_shadow_config.
+// Too little bias gives shadow acne (stripes on lit surfaces), too much detaches shadows from
+// their casters (peter-panning).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowConfig {
+    // Width and height of each shadow map layer in texels
+    pub resolution: u32,
+    // Rasterizer depth bias of the shadow pass, in the depth format's smallest steps, and the
+    // extra bias per unit of depth slope. Pass both to cmd_set_depth_bias (see depth_bias_factors)
+    // for pipelines built with_depth_bias.
+    pub depth_bias: f32,
+    pub slope_bias: f32,
+    // How far the sampled position moves along the surface normal, in shadow map texels of the
+    // cascade it's sampled from, so the offset follows each cascade's texel size
+    pub normal_bias: f32,
+    // Width of the square of texels filtered per sample (1 is a single compare), rounded up
+    // to an odd number by the shader
+    pub pcf_kernel_size: u32,
+    // 1 to MAX_SHADOW_CASCADES
+    pub cascade_count: usize,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            depth_bias: 1.25,
+            slope_bias: 1.75,
+            normal_bias: 1.0,
+            pcf_kernel_size: 3,
+            cascade_count: 2,
+        }
+    }
+}
+
+impl ShadowConfig {
+    // Default cascade settings with this config's count and resolution
+    pub fn cascade_settings(&self) -> CascadeSettings {
+        CascadeSettings {
+            count: self.cascade_count.clamp(1, MAX_SHADOW_CASCADES),
+            resolution: self.resolution,
+            ..CascadeSettings::default()
+        }
+    }
+
+    // (constant, clamp, slope) for cmd_set_depth_bias. With reverse-Z depth grows towards the
+    // camera, so the bias pushes the other way.
+    pub fn depth_bias_factors(&self, reverse_z: bool) -> (f32, f32, f32) {
+        let sign = if reverse_z { -1.0 } else { 1.0 };
+        (sign * self.depth_bias, 0.0, sign * self.slope_bias)
+    }
+}
+
+// Camera the cascades are fitted to, the same values its projection was built from
+#[derive(Clone, Copy, Debug)]
+pub struct CascadeCamera {
+    pub view: Mat4,
+    pub fov_y: f32,
+    pub aspect: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowCascade {
+    // World space to the cascade's light clip space, used to render its layer and to sample it
+    pub view_proj: Mat4,
+    // View space distance where the cascade ends
+    pub split_far: f32,
+}
+
+// std140 layout of the cascades for shaders, see shaders/common/shadows.glsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowCascadeUniforms {
+    pub view_proj: [[f32; 16]; MAX_SHADOW_CASCADES],
+    pub split_depths: [f32; MAX_SHADOW_CASCADES],
+    pub cascade_count: u32,
+    pub blend_band: f32,
+    // In texels, see ShadowConfig
+    pub normal_bias: f32,
+    pub pcf_kernel_size: u32,
+}
+
+impl ShadowCascadeUniforms {
+    // Single compare per sample without normal offset
+    pub fn new(cascades: &[ShadowCascade], blend_band: f32) -> Self {
+        let mut uniforms = Self {
+            view_proj: [Mat4::IDENTITY.to_cols_array(); MAX_SHADOW_CASCADES],
+            split_depths: [f32::MAX; MAX_SHADOW_CASCADES],
+            cascade_count: cascades.len().min(MAX_SHADOW_CASCADES) as u32,
+            blend_band,
+            normal_bias: 0.0,
+            pcf_kernel_size: 1,
+        };
+        for (i, cascade) in cascades.iter().take(MAX_SHADOW_CASCADES).enumerate() {
+            uniforms.view_proj[i] = cascade.view_proj.to_cols_array();
+            uniforms.split_depths[i] = cascade.split_far;
+        }
+        uniforms
+    }
+
+    // Filtered with the config's PCF kernel and offset by its normal bias
+    pub fn with_config(cascades: &[ShadowCascade], blend_band: f32, config: &ShadowConfig) -> Self {
+        Self {
+            normal_bias: config.normal_bias,
+            pcf_kernel_size: config.pcf_kernel_size.max(1),
+            ..Self::new(cascades, blend_band)
+        }
+    }
+}
+
+// View space distances where each cascade ends, the last one is far
+pub fn cascade_splits(near: f32, far: f32, count: usize, split_lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let fraction = i as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let uniform = near + (far - near) * fraction;
+            split_lambda * logarithmic + (1.0 - split_lambda) * uniform
+        })
+        .collect()
+}
+
+// Splits the camera's frustum into depth ranges and fits a light orthographic projection to
+// each. light_direction points from the light into the scene.
+pub fn compute_cascades(camera: &CascadeCamera, light_direction: Vec3, settings: &CascadeSettings) -> Vec<ShadowCascade> {
+    let count = settings.count.clamp(1, MAX_SHADOW_CASCADES);
+    let splits = cascade_splits(camera.near, camera.far, count, settings.split_lambda);
+    let camera_to_world = camera.view.inverse();
+    let light_direction = light_direction.normalize();
+    let up = if light_direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+
+    let mut split_near = camera.near;
+    splits.iter().map(|&split_far| {
+        let corners = frustum_corners(camera, camera_to_world, split_near, split_far);
+        split_near = split_far;
+
+        // A bounding sphere keeps the cascade's size fixed while the camera turns, so the
+        // texels don't change size and shadow edges don't swim
+        let center = corners.iter().copied().sum::<Vec3>() / corners.len() as f32;
+        let radius = corners.iter().map(|corner| corner.distance(center)).fold(0.0, f32::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        // Snap the center to whole texels in light space so moving the camera moves the
+        // cascade in texel steps, which keeps edges from crawling
+        let texel_size = 2.0 * radius / settings.resolution as f32;
+        let light_rotation = Mat4::look_to_rh(Vec3::ZERO, light_direction, up);
+        let light_center = light_rotation.transform_point3(center);
+        let snapped = Vec3::new(
+            (light_center.x / texel_size).floor() * texel_size,
+            (light_center.y / texel_size).floor() * texel_size,
+            light_center.z,
+        );
+        let center = light_rotation.inverse().transform_point3(snapped);
+
+        let eye = center - light_direction * (radius + settings.caster_distance);
+        let light_view = Mat4::look_to_rh(eye, light_direction, up);
+        let light_proj = Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, 2.0 * radius + settings.caster_distance);
+        ShadowCascade {
+            view_proj: light_proj * light_view,
+            split_far,
+        }
+    }).collect()
+}
+
+// World space corners of the camera frustum between two view space distances
+fn frustum_corners(camera: &CascadeCamera, camera_to_world: Mat4, near: f32, far: f32) -> [Vec3; 8] {
+    let tan_half_fov = (camera.fov_y * 0.5).tan();
+    let mut corners = [Vec3::ZERO; 8];
+    for (i, distance) in [near, far].into_iter().enumerate() {
+        let half_height = distance * tan_half_fov;
+        let half_width = half_height * camera.aspect;
+        for (j, (x, y)) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].into_iter().enumerate() {
+            let corner = Vec4::new(x * half_width, y * half_height, -distance, 1.0);
+            corners[i * 4 + j] = (camera_to_world * corner).truncate();
+        }
+    }
+    corners
+}
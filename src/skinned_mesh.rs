@@ -1,4 +1,5 @@
-use bevy::math::Mat4;
+use bevy_math::{Mat4, Vec4};
+use crate::mesh::VertexFormat;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -79,10 +80,18 @@ impl SkinnedVertex {
     }
 }
 
+// No position, the bind pose doesn't bound the animated mesh
+impl VertexFormat for SkinnedVertex {
+    fn attribute_descriptions() -> Vec<ash::vk::VertexInputAttributeDescription> {
+        SkinnedVertex::get_attribute_descriptions()
+    }
+}
+
 pub struct SkinnedMeshData {
     pub vertices: Vec<SkinnedVertex>,
     pub indices: Vec<u32>,
     pub joint_matrices: Vec<Mat4>,
+    pub morph_targets: Option<MorphTargets>,
 }
 
 impl SkinnedMeshData {
@@ -91,6 +100,130 @@ impl SkinnedMeshData {
             vertices,
             indices,
             joint_matrices,
+            morph_targets: None,
+        }
+    }
+    
+    pub fn with_morph_targets(mut self, morph_targets: MorphTargets) -> Self {
+        self.morph_targets = Some(morph_targets);
+        self
+    }
+}
+
+// Most morph targets a mesh can have. The vertex shader loops over every target per vertex, and
+// glTF exporters rarely write more than a few dozen (e.g. the 52 ARKit face shapes).
+pub const MAX_MORPH_TARGETS: usize = 64;
+
+// Offsets from the base mesh, one per vertex. Normals may be empty for position only targets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MorphTarget {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+}
+
+// A mesh's blend shapes, the vertex shader draws base + sum(weight * delta) before skinning.
+// Loaded with gltf_loader::load_morph_targets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MorphTargets {
+    pub targets: Vec<MorphTarget>,
+    // Initial weights, one per target
+    pub weights: Vec<f32>,
+}
+
+// Deltas as the morph delta buffer stores them: per vertex, per target, a position then a normal
+// delta, each padded to a vec4 for std430
+pub fn morph_delta_buffer_data(morph_targets: &MorphTargets, vertex_count: usize) -> Result<Vec<[f32; 4]>, String> {
+    if morph_targets.targets.len() > MAX_MORPH_TARGETS {
+        return Err(format!(
+            "Mesh has {} morph targets, at most {} are supported",
+            morph_targets.targets.len(), MAX_MORPH_TARGETS
+        ));
+    }
+    for target in &morph_targets.targets {
+        if target.positions.len() != vertex_count || (!target.normals.is_empty() && target.normals.len() != vertex_count) {
+            return Err(format!("Morph target deltas don't match the mesh's {} vertices", vertex_count));
+        }
+    }
+    
+    let mut data = Vec::with_capacity(vertex_count * morph_targets.targets.len() * 2);
+    for vertex in 0..vertex_count {
+        for target in &morph_targets.targets {
+            let [x, y, z] = target.positions[vertex];
+            let [nx, ny, nz] = target.normals.get(vertex).copied().unwrap_or_default();
+            data.push([x, y, z, 0.0]);
+            data.push([nx, ny, nz, 0.0]);
+        }
+    }
+    Ok(data)
+}
+// How a skinned pipeline blends its joints. Linear blending averages the joint matrices, which
+// collapses volume at twisted joints (elbows, shoulders). Dual quaternion blending keeps it but
+// drops any scale in the joint matrices.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SkinningMode {
+    #[default]
+    LinearBlend,
+    DualQuaternion,
+}
+
+// Joint matrices as the joint buffer stores them for a mode. Dual quaternions are packed into
+// the first two columns of a matrix (real part, then dual part) so the buffer layout is the same.
+pub fn joint_buffer_data(joint_matrices: &[Mat4], mode: SkinningMode) -> Vec<Mat4> {
+    match mode {
+        SkinningMode::LinearBlend => joint_matrices.to_vec(),
+        SkinningMode::DualQuaternion => joint_matrices.iter().map(|&matrix| {
+            let (_, rotation, translation) = matrix.to_scale_rotation_translation();
+            // Dual part 0.5 * t * r, with t the translation as a pure quaternion
+            let axis = rotation.xyz();
+            let dual_axis = (translation * rotation.w + translation.cross(axis)) * 0.5;
+            let dual = dual_axis.extend(-translation.dot(axis) * 0.5);
+            Mat4::from_cols(Vec4::from(rotation), dual, Vec4::ZERO, Vec4::ZERO)
+        }).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_math::Vec3;
+    
+    fn targets() -> MorphTargets {
+        MorphTargets {
+            targets: vec![
+                MorphTarget { positions: vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]], normals: vec![[0.0, 0.0, 1.0], [0.0, 0.0, 2.0]] },
+                // Position only
+                MorphTarget { positions: vec![[0.0, 0.0, 3.0], [4.0, 0.0, 0.0]], normals: Vec::new() },
+            ],
+            weights: vec![0.0, 0.0],
         }
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn morph_deltas_blend_like_the_vertex_shader() {
+        let data = morph_delta_buffer_data(&targets(), 2).unwrap();
+        assert_eq!(data.len(), 2 * 2 * 2);
+        
+        // applyMorphTargets in shaders/common/morph.glsl
+        let weights = [0.5, 2.0];
+        let morph = |vertex: usize, position: Vec3| -> Vec3 {
+            (0..weights.len()).fold(position, |position, target| {
+                let base = (vertex * weights.len() + target) * 2;
+                position + Vec4::from(data[base]).truncate() * weights[target]
+            })
+        };
+        assert_eq!(morph(0, Vec3::ZERO), Vec3::new(0.5, 0.0, 6.0));
+        assert_eq!(morph(1, Vec3::ONE), Vec3::new(9.0, 1.5, 1.0));
+        // The second vertex's normal deltas, position only targets have zero ones
+        assert_eq!(data[5], [0.0, 0.0, 2.0, 0.0]);
+        assert_eq!(data[7], [0.0; 4]);
+    }
+    
+    #[test]
+    fn morph_deltas_reject_mismatched_and_too_many_targets() {
+        assert!(morph_delta_buffer_data(&targets(), 3).is_err());
+        
+        let target = MorphTarget { positions: vec![[0.0; 3]], normals: Vec::new() };
+        let too_many = MorphTargets { targets: vec![target; MAX_MORPH_TARGETS + 1], weights: Vec::new() };
+        assert!(morph_delta_buffer_data(&too_many, 1).is_err());
+    }
+}
@@ -1,4 +1,7 @@
 use ash::{vk, Instance};
+use crate::error::FloError;
+use crate::vulkan_common::{allocate_device_memory, free_device_memory, MemoryUsage, SamplerAddressModes};
+use crate::leak_check;
 
 pub struct TextureData {
     pub pixels: Vec<u8>,
@@ -27,6 +30,133 @@ impl TextureData {
     }
 }
 
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+// Identifier, 9 header fields and the dfd/kvd/sgd index, after which the level index starts
+const KTX2_LEVEL_INDEX_OFFSET: usize = 80;
+
+// The layers and mip levels of a texture array as stored in a KTX2 file, uploaded as is without
+// resizing or conversion. Every layer shares the format and dimensions.
+pub struct TextureArrayData {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub layer_count: u32,
+    // One entry per mip level starting at the full size, each holding every layer back to back
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureArrayData {
+    pub fn from_ktx2(path: &str) -> Result<Self, FloError> {
+        let bytes = std::fs::read(path)?;
+        Self::from_ktx2_bytes(&bytes).map_err(|e| format!("{}: {}", path, e).into())
+    }
+
+    pub fn from_ktx2_bytes(bytes: &[u8]) -> Result<Self, FloError> {
+        if bytes.len() < KTX2_LEVEL_INDEX_OFFSET || bytes[..12] != KTX2_IDENTIFIER {
+            return Err("Not a KTX2 file".into());
+        }
+        let read_u32 = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let read_u64 = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+        let vk_format = read_u32(12);
+        let width = read_u32(20);
+        let height = read_u32(24);
+        let depth = read_u32(28);
+        let layer_count = read_u32(32).max(1);
+        let face_count = read_u32(36);
+        let level_count = read_u32(40).max(1);
+        let supercompression = read_u32(44);
+
+        // Basis Universal and other transcoded textures have no Vulkan format until transcoded
+        if vk_format == 0 {
+            return Err("KTX2 texture has no Vulkan format, transcoded textures aren't supported".into());
+        }
+        if supercompression != 0 {
+            return Err(format!("KTX2 supercompression scheme {} isn't supported", supercompression).into());
+        }
+        if width == 0 || height == 0 || depth > 1 {
+            return Err(format!("KTX2 texture is {}x{}x{}, only 2D textures are supported", width, height, depth).into());
+        }
+        if face_count != 1 {
+            return Err("KTX2 cubemaps aren't supported as texture arrays".into());
+        }
+        let max_levels = 32 - width.max(height).leading_zeros();
+        if level_count > max_levels {
+            return Err(format!("KTX2 texture has {} mip levels but {}x{} only allows {}", level_count, width, height, max_levels).into());
+        }
+
+        let index_end = KTX2_LEVEL_INDEX_OFFSET + level_count as usize * 24;
+        if bytes.len() < index_end {
+            return Err("KTX2 level index is truncated".into());
+        }
+
+        let mut levels = Vec::with_capacity(level_count as usize);
+        for level in 0..level_count as usize {
+            let entry = KTX2_LEVEL_INDEX_OFFSET + level * 24;
+            let offset = read_u64(entry) as usize;
+            let length = read_u64(entry + 8) as usize;
+            let data = offset.checked_add(length)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| format!("KTX2 mip level {} lies outside the file", level))?;
+
+            // Layers of a level are stored back to back, so the level splits evenly into one
+            // image per layer, each no larger than the same layer one level up
+            if length == 0 || length % layer_count as usize != 0 {
+                return Err(format!("KTX2 mip level {} is {} bytes, which doesn't split into {} layers", level, length, layer_count).into());
+            }
+            if let Some(previous) = levels.last().map(Vec::len) {
+                if length > previous {
+                    return Err(format!("KTX2 mip level {} is larger than the level above it", level).into());
+                }
+            }
+            levels.push(data.to_vec());
+        }
+
+        Ok(Self {
+            format: vk::Format::from_raw(vk_format as i32),
+            width,
+            height,
+            layer_count,
+            levels,
+        })
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.levels.len() as u32
+    }
+}
+
+// How a texture's values are decoded when sampled. Colors (base color, emissive) are stored
+// as sRGB, data maps (normal, roughness, metallic, AO, height) must be sampled as is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn format(self) -> vk::Format {
+        match self {
+            ColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
+// sRGB encoded color, as picked in a color picker, to the linear value shaders and clears
+// take. Alpha is already linear and kept as is.
+pub fn srgb_to_linear(color: [f32; 4]) -> [f32; 4] {
+    let decode = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [decode(color[0]), decode(color[1]), decode(color[2]), color[3]]
+}
+
 pub struct Texture {
     pub image: vk::Image,
     pub memory: vk::DeviceMemory,
@@ -41,6 +171,7 @@ impl Texture {
         command_pool: vk::CommandPool,
         queue: vk::Queue,
         path: &str,
+        color_space: ColorSpace,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         // Load image from file using image crate
         let img = image::open(path)?;
@@ -49,7 +180,7 @@ impl Texture {
         let pixels = rgba.into_raw();
         
         let texture_data = TextureData::new(pixels, width, height);
-        Self::create(instance, device, physical_device, command_pool, queue, &texture_data)
+        Self::create(instance, device, physical_device, command_pool, queue, &texture_data, color_space)
     }
     
     pub fn create(
@@ -59,7 +190,9 @@ impl Texture {
         command_pool: vk::CommandPool,
         queue: vk::Queue,
         texture_data: &TextureData,
+        color_space: ColorSpace,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        let format = color_space.format();
         let image_size = (texture_data.width * texture_data.height * 4) as vk::DeviceSize;
         
         // Create staging buffer
@@ -86,7 +219,7 @@ impl Texture {
             physical_device,
             texture_data.width,
             texture_data.height,
-            vk::Format::R8G8B8A8_SRGB,
+            format,
             vk::ImageTiling::OPTIMAL,
             vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
@@ -98,7 +231,7 @@ impl Texture {
             command_pool,
             queue,
             image,
-            vk::Format::R8G8B8A8_SRGB,
+            format,
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
         )?;
@@ -118,39 +251,39 @@ impl Texture {
             command_pool,
             queue,
             image,
-            vk::Format::R8G8B8A8_SRGB,
+            format,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
         )?;
         
         // Cleanup staging buffer
         unsafe {
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_memory, None);
+            leak_check::destroy(device, staging_buffer);
+            free_device_memory(device, staging_memory);
         }
         
         // Create image view
-        let view = create_image_view(device, image, vk::Format::R8G8B8A8_SRGB)?;
+        let view = create_image_view(device, image, format)?;
         
         Ok(Self { image, memory, view })
     }
     
     pub fn destroy(&self, device: &ash::Device) {
         unsafe {
-            device.destroy_image_view(self.view, None);
-            device.destroy_image(self.image, None);
-            device.free_memory(self.memory, None);
+            leak_check::destroy(device, self.view);
+            leak_check::destroy(device, self.image);
+            free_device_memory(device, self.memory);
         }
     }
 }
 
-pub fn create_texture_sampler(device: &ash::Device) -> Result<vk::Sampler, Box<dyn std::error::Error>> {
+pub fn create_texture_sampler(device: &ash::Device, address_modes: SamplerAddressModes) -> Result<vk::Sampler, Box<dyn std::error::Error>> {
     let sampler_info = vk::SamplerCreateInfo::default()
         .mag_filter(vk::Filter::LINEAR)
         .min_filter(vk::Filter::LINEAR)
-        .address_mode_u(vk::SamplerAddressMode::REPEAT)
-        .address_mode_v(vk::SamplerAddressMode::REPEAT)
-        .address_mode_w(vk::SamplerAddressMode::REPEAT)
+        .address_mode_u(address_modes.u)
+        .address_mode_v(address_modes.v)
+        .address_mode_w(address_modes.w)
         .anisotropy_enable(true)
         .max_anisotropy(16.0)
         .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
@@ -162,7 +295,7 @@ pub fn create_texture_sampler(device: &ash::Device) -> Result<vk::Sampler, Box<d
         .min_lod(0.0)
         .max_lod(0.0);
     
-    let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+    let sampler = leak_check::created(device, unsafe { device.create_sampler(&sampler_info, None)? });
     Ok(sampler)
 }
 
@@ -179,7 +312,7 @@ fn create_buffer(
         .usage(usage)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
     
-    let buffer = unsafe { device.create_buffer(&buffer_info, None)? };
+    let buffer = leak_check::created(device, unsafe { device.create_buffer(&buffer_info, None)? });
     
     let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
     
@@ -192,7 +325,7 @@ fn create_buffer(
             properties,
         )?);
     
-    let buffer_memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+    let buffer_memory = unsafe { allocate_device_memory(device, &alloc_info, MemoryUsage::from_buffer_usage(usage))? };
     
     unsafe { device.bind_buffer_memory(buffer, buffer_memory, 0)? };
     
@@ -226,7 +359,7 @@ fn create_image(
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
         .samples(vk::SampleCountFlags::TYPE_1);
     
-    let image = unsafe { device.create_image(&image_info, None)? };
+    let image = leak_check::created(device, unsafe { device.create_image(&image_info, None)? });
     
     let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
     
@@ -239,7 +372,7 @@ fn create_image(
             properties,
         )?);
     
-    let image_memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+    let image_memory = unsafe { allocate_device_memory(device, &alloc_info, MemoryUsage::Texture)? };
     
     unsafe { device.bind_image_memory(image, image_memory, 0)? };
     
@@ -409,7 +542,7 @@ pub fn create_image_view(
             layer_count: 1,
         });
     
-    let image_view = unsafe { device.create_image_view(&view_info, None)? };
+    let image_view = leak_check::created(device, unsafe { device.create_image_view(&view_info, None)? });
     
     Ok(image_view)
 }
@@ -430,4 +563,31 @@ fn find_memory_type(
     }
     
     Err("Failed to find suitable memory type".into())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn srgb_to_linear_known_values() {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+        let [r, g, b, a] = srgb_to_linear([0.0, 1.0, 0.5, 0.25]);
+        assert!(close(r, 0.0) && close(g, 1.0));
+        // Mid grey in sRGB is about a fifth of the light
+        assert!(close(b, 0.21404), "{b}");
+        // Alpha isn't encoded
+        assert_eq!(a, 0.25);
+        // The linear segment near black
+        assert!(close(srgb_to_linear([0.04, 0.0, 0.0, 1.0])[0], 0.04 / 12.92));
+    }
+    
+    #[test]
+    fn srgb_to_linear_is_continuous_and_increasing() {
+        let values: Vec<f32> = (0..=255).map(|i| srgb_to_linear([i as f32 / 255.0, 0.0, 0.0, 1.0])[0]).collect();
+        assert!(values.windows(2).all(|pair| pair[1] > pair[0]));
+        let below = srgb_to_linear([0.04045, 0.0, 0.0, 1.0])[0];
+        let above = srgb_to_linear([0.04046, 0.0, 0.0, 1.0])[0];
+        assert!((above - below).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::texture::TextureData;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use threadpool::ThreadPool;
+
+// A texture decoded on a worker thread, waiting for its Vulkan upload
+pub struct DecodedTexture {
+    pub mesh_index: usize,
+    pub path: String,
+    pub result: Result<TextureData, String>,
+}
+
+// Decodes texture files on background threads.
+// Workers only do disk IO and image decoding. The decoded pixels come back over a channel
+// and the renderer uploads them on the main thread, since that's where the graphics queue
+// and command pool live. Nothing Vulkan related ever crosses the thread boundary.
+pub struct TextureStreamer {
+    // None decodes on the calling thread inside request
+    pool: Option<ThreadPool>,
+    sender: Sender<DecodedTexture>,
+    receiver: Receiver<DecodedTexture>,
+    // Latest requested path per mesh, so an older request finishing late doesn't win
+    pending: HashMap<usize, String>,
+}
+
+impl TextureStreamer {
+    // 0 threads decodes synchronously, results are still collected by poll_completed
+    pub fn new(threads: usize) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            pool: (threads > 0).then(|| ThreadPool::new(threads)),
+            sender,
+            receiver,
+            pending: HashMap::new(),
+        }
+    }
+
+    // Queue a texture file to be decoded for a mesh, returns immediately
+    pub fn request(&mut self, mesh_index: usize, path: &str) {
+        self.pending.insert(mesh_index, path.to_string());
+
+        let sender = self.sender.clone();
+        let path = path.to_string();
+        let decode = move || {
+            let result = image::open(&path)
+                .map(|img| {
+                    let rgba = img.to_rgba8();
+                    let (width, height) = rgba.dimensions();
+                    TextureData::new(rgba.into_raw(), width, height)
+                })
+                .map_err(|e| format!("Failed to decode texture {}: {}", path, e));
+
+            // The receiver only goes away when the renderer is dropped
+            let _ = sender.send(DecodedTexture { mesh_index, path, result });
+        };
+        match self.pool {
+            Some(ref pool) => pool.execute(decode),
+            None => decode(),
+        }
+    }
+
+    // Forget any pending request for a mesh, its result will be discarded
+    pub fn cancel(&mut self, mesh_index: usize) {
+        self.pending.remove(&mesh_index);
+    }
+
+    // Collect decoded textures without blocking. Stale results for meshes that were
+    // re-requested or cancelled since are dropped here.
+    pub fn poll_completed(&mut self) -> Vec<DecodedTexture> {
+        let mut completed = Vec::new();
+        while let Ok(decoded) = self.receiver.try_recv() {
+            if self.pending.get(&decoded.mesh_index) == Some(&decoded.path) {
+                self.pending.remove(&decoded.mesh_index);
+                completed.push(decoded);
+            }
+        }
+        completed
+    }
+
+    // Blocks until every pending request has been decoded, then returns them like poll_completed
+    pub fn wait_completed(&mut self) -> Vec<DecodedTexture> {
+        let mut completed = self.poll_completed();
+        while !self.pending.is_empty() {
+            // Every request sends exactly one result and the streamer holds a sender, so this
+            // only fails if a worker panicked
+            let Ok(decoded) = self.receiver.recv() else { break };
+            if self.pending.get(&decoded.mesh_index) == Some(&decoded.path) {
+                self.pending.remove(&decoded.mesh_index);
+                completed.push(decoded);
+            }
+        }
+        completed
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
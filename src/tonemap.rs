@@ -0,0 +1,265 @@
+use ash::vk;
+use crate::error::FloError;
+use crate::vulkan_common::*;
+use crate::leak_check;
+
+// How the HDR scene color is mapped into the swapchain's 0..1 range
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tonemap {
+    // Exposure then clamp, looks the same as drawing straight to the swapchain at exposure 1
+    #[default]
+    None,
+    Reinhard,
+    Aces,
+}
+
+// How the present pass scales a scene rendered below the swapchain resolution up to it, see
+// VulkanRenderer::set_resolution_scale
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UpscaleFilter {
+    #[default]
+    Bilinear,
+    // Bilinear plus an unsharp mask at the scene's resolution, to win back some of the detail
+    // upscaling blurs. 0 is plain bilinear, around 0.5 is a moderate sharpen.
+    Sharpen { sharpness: f32 },
+}
+
+impl UpscaleFilter {
+    fn sharpness(self) -> f32 {
+        match self {
+            UpscaleFilter::Bilinear => 0.0,
+            UpscaleFilter::Sharpen { sharpness } => sharpness.max(0.0),
+        }
+    }
+}
+
+// TonemapPushConstants::mode past the Tonemap values
+const OVERDRAW_HEATMAP_MODE: u32 = 3;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TonemapPushConstants {
+    pub mode: u32,
+    pub exposure: f32,
+    // 1 when the swapchain isn't an _SRGB format, so the shader has to encode itself
+    pub encode_srgb: u32,
+    // Unsharp mask strength, 0 when the scene is at full resolution or upscaled bilinearly
+    pub sharpness: f32,
+}
+
+// Last pass of every frame: draws a fullscreen triangle that samples the HDR scene image,
+// tonemaps it and writes it to the swapchain image. egui is drawn in this pass as well,
+// after tonemapping, so UI colors aren't affected by exposure.
+pub struct PresentPass {
+    pub render_pass: vk::RenderPass,
+    // One per swapchain image
+    pub framebuffers: Vec<vk::Framebuffer>,
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub sampler: vk::Sampler,
+    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    // One per HDR image, indexed like the framebuffers
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    encode_srgb: bool,
+}
+
+impl PresentPass {
+    pub fn new(
+        device: &ash::Device,
+        swapchain_format: vk::Format,
+        swapchain_image_views: &[vk::ImageView],
+        hdr_image_views: &[vk::ImageView],
+        extent: vk::Extent2D,
+    ) -> Result<Self, FloError> {
+        let render_pass = create_present_render_pass(device, swapchain_format)?;
+        let framebuffers = create_framebuffers_no_depth(device, swapchain_image_views, render_pass, extent)?;
+
+        // Linear upscales a scene rendered below the swapchain resolution, at the same size
+        // pixel centers read exact pixels
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+        let sampler = leak_check::created(device, unsafe { device.create_sampler(&sampler_info, None)? });
+
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let descriptor_set_layout = create_descriptor_set_layout(device, &[binding])?;
+
+        let set_count = hdr_image_views.len() as u32;
+        let pool_size = vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(set_count);
+        let descriptor_pool = create_descriptor_pool(device, set_count, &[pool_size])?;
+
+        let layouts = vec![descriptor_set_layout; hdr_image_views.len()];
+        let descriptor_sets = allocate_descriptor_sets(device, descriptor_pool, &layouts)?;
+
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(std::mem::size_of::<TonemapPushConstants>() as u32);
+
+        let (pipeline, pipeline_layout) = PipelineBuilder::new(
+            device.clone(),
+            "shaders/tonemap.vert.spv",
+            "shaders/tonemap.frag.spv",
+            extent,
+            render_pass,
+        )?
+            .with_push_constants(vec![push_constant_range])
+            .with_descriptor_sets(vec![descriptor_set_layout])
+            .with_cull_mode(vk::CullModeFlags::NONE)
+            .build()?;
+
+        let encode_srgb = !matches!(
+            swapchain_format,
+            vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+        );
+
+        let present = Self {
+            render_pass,
+            framebuffers,
+            pipeline,
+            pipeline_layout,
+            sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_sets,
+            encode_srgb,
+        };
+        present.set_sources(device, hdr_image_views);
+        Ok(present)
+    }
+
+    // Points descriptor_sets at the HDR images, e.g. after they were recreated at another size.
+    // The sets mustn't be in use by a pending frame.
+    pub fn set_sources(&self, device: &ash::Device, hdr_image_views: &[vk::ImageView]) {
+        for (&descriptor_set, &image_view) in self.descriptor_sets.iter().zip(hdr_image_views) {
+            update_descriptor_sets_texture(device, descriptor_set, image_view, self.sampler, 0);
+        }
+    }
+
+    // Begins the present pass on the swapchain image and draws the tonemapped scene. The pass
+    // is left open so overlays can be drawn on top, the caller ends it.
+    // source is one of descriptor_sets, or a post effect's output laid out the same way, and
+    // is scaled to the swapchain's extent with the upscale filter. With overdraw_heatmap the
+    // source's red channel is shown as a heatmap instead of tonemapped, see DebugView::Overdraw.
+    #[allow(clippy::too_many_arguments)]
+    pub fn begin(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        source: vk::DescriptorSet,
+        extent: vk::Extent2D,
+        tonemap: Tonemap,
+        exposure: f32,
+        upscale_filter: UpscaleFilter,
+        overdraw_heatmap: bool,
+    ) {
+        let push_constants = TonemapPushConstants {
+            mode: if overdraw_heatmap { OVERDRAW_HEATMAP_MODE } else { tonemap as u32 },
+            exposure,
+            encode_srgb: self.encode_srgb as u32,
+            sharpness: upscale_filter.sharpness(),
+        };
+
+        let render_pass_info = vk::RenderPassBeginInfo::default()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffers[image_index])
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            });
+
+        unsafe {
+            device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+            set_viewport_and_scissor(device, command_buffer, extent);
+
+            // No depth testing, so there's no compare op to set
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[source],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        destroy_pipeline(device, self.pipeline, self.pipeline_layout);
+        unsafe {
+            leak_check::destroy(device, self.descriptor_pool);
+            leak_check::destroy(device, self.descriptor_set_layout);
+            leak_check::destroy(device, self.sampler);
+            for &framebuffer in &self.framebuffers {
+                leak_check::destroy(device, framebuffer);
+            }
+            leak_check::destroy(device, self.render_pass);
+        }
+    }
+}
+
+// Every pixel is overwritten by the fullscreen triangle, so the previous contents are discarded
+fn create_present_render_pass(device: &ash::Device, swapchain_format: vk::Format) -> Result<vk::RenderPass, FloError> {
+    let color_attachment = vk::AttachmentDescription::default()
+        .format(swapchain_format)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+
+    let color_attachment_refs = [vk::AttachmentReference::default()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+
+    let subpasses = [vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs)];
+
+    // end_frame waits on the image acquire semaphore at color attachment output.
+    // Reading the HDR image is covered by the scene render pass's outgoing dependency.
+    let dependencies = [vk::SubpassDependency::default()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)];
+
+    let attachments = [color_attachment];
+    let render_pass_info = vk::RenderPassCreateInfo::default()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies);
+
+    let render_pass = leak_check::created(device, unsafe { device.create_render_pass(&render_pass_info, None)? });
+
+    Ok(render_pass)
+}
@@ -12,6 +12,8 @@ use bevy::{
 };
 
 use crate::skinned_mesh::{SkinnedVertex, SkinnedMeshData};
+// Moved to random so they build without Bevy, kept here for existing imports
+pub use crate::random::{SeededRng, scatter_positions};
 
 // Resource to store extracted mesh data
 #[derive(Resource, Default)]
@@ -51,7 +53,7 @@ pub fn extract_mesh_data(
                 // Only consider meshes with skinning data
                 if skinned_mesh.is_some() {
                     let vertex_count = mesh.count_vertices();
-                    println!("Found skinned mesh with {} vertices", vertex_count);
+                    debug!("Found skinned mesh with {} vertices", vertex_count);
                     
                     if vertex_count > best_vertex_count {
                         best_mesh = Some((mesh, skinned_mesh));
@@ -62,23 +64,23 @@ pub fn extract_mesh_data(
         }
         
         if let Some((mesh, skinned_mesh)) = best_mesh {
-            println!("Using best skinned mesh with {} vertices", best_vertex_count);
+            info!("Using best skinned mesh with {} vertices", best_vertex_count);
             // Convert Bevy mesh to our Vulkan mesh format
             match convert_bevy_mesh_to_vulkan(mesh, skinned_mesh) {
                 Ok(vulkan_mesh_data) => {
-                    println!("Successfully extracted mesh with {} vertices and {} indices",
+                    info!("Extracted mesh with {} vertices and {} indices",
                         vulkan_mesh_data.vertices.len(),
                         vulkan_mesh_data.indices.len());
                     
                     if skinned_mesh.is_some() {
-                        println!("Mesh has skinning data with {} joints", 
+                        debug!("Mesh has skinning data with {} joints", 
                             vulkan_mesh_data.joint_matrices.len());
                     }
                     
                     // Log first vertex and some random vertices for debugging
                     if !vulkan_mesh_data.vertices.is_empty() {
                         let v = &vulkan_mesh_data.vertices[0];
-                        println!("First vertex: pos={:?}, joints={:?}, weights={:?}", 
+                        debug!("First vertex: pos={:?}, joints={:?}, weights={:?}", 
                             v.position, v.joint_indices, v.joint_weights);
                         
                         // Check for vertices with all zero weights or unusual patterns
@@ -90,31 +92,29 @@ pub fn extract_mesh_data(
                             if total_weight < 0.001 {
                                 zero_weight_count += 1;
                                 if i < 10 {
-                                    println!("Vertex {} has zero weights: pos={:?}", i, v.position);
+                                    debug!("Vertex {} has zero weights: pos={:?}", i, v.position);
                                 }
                             } else if v.joint_weights[1] < 0.001 && v.joint_weights[2] < 0.001 && v.joint_weights[3] < 0.001 {
                                 single_joint_count += 1;
                             }
                         }
-                        println!("Found {} vertices with zero weights", zero_weight_count);
-                        println!("Found {} vertices bound to single joint", single_joint_count);
+                        debug!("Found {} vertices with zero weights", zero_weight_count);
+                        debug!("Found {} vertices bound to single joint", single_joint_count);
                     }
                     
                     extracted_data.mesh_data = Some(vulkan_mesh_data);
                 }
                 Err(e) => {
-                    eprintln!("Failed to convert mesh: {}", e);
+                    error!("Failed to convert mesh: {}", e);
                 }
             }
         } else {
-            println!("No skinned meshes found!");
+            warn!("No skinned meshes found");
         }
     }
     
     // Always update joint matrices if we have skinned mesh data
     if extracted_data.mesh_data.is_some() {
-        static mut FIRST_LOG: bool = true;
-        
         for (_mesh_handle, skinned_mesh) in mesh_query.iter() {
             if let Some(skinned) = skinned_mesh {
                 if let Some(ref mut mesh_data) = extracted_data.mesh_data {
@@ -139,26 +139,9 @@ pub fn extract_mesh_data(
                                 };
                                 
                                 mesh_data.joint_matrices[i] = final_matrix;
-                                
-                                // Check if matrices are changing
-                                // unsafe {
-                                //     if i == 5 {  // Check joint 5 which should be animated
-                                //         if let Some(last) = LAST_MATRIX_0 {
-                                //             if last != final_matrix {
-                                //                 println!("Joint 5 is animating! New matrix: {:?}", final_matrix);
-                                //             }
-                                //         }
-                                //         LAST_MATRIX_0 = Some(final_matrix);
-                                //     }
-                                    
-                                //     if FIRST_LOG && i < 3 {
-                                //         println!("Joint {} initial matrix (with inverse bind pose): {:?}", i, mesh_data.joint_matrices[i]);
-                                //     }
-                                // }
                             }
                         }
                     }
-                    unsafe { FIRST_LOG = false; }
                 }
                 break; // Only process first skinned mesh
             }
@@ -279,11 +262,11 @@ fn convert_bevy_mesh_to_vulkan(
     
     // Initialize joint matrices - will be updated each frame
     let joint_matrices = if let Some(skinned) = skinned_mesh {
-        println!("Initializing {} joint matrices", skinned.joints.len());
+        debug!("Initializing {} joint matrices", skinned.joints.len());
         vec![Mat4::IDENTITY; skinned.joints.len()]
     } else {
-        println!("No skinned mesh, using single identity matrix");
-        vec![Mat4::IDENTITY; 128]  // Full set of identity matrices
+        debug!("No skinned mesh, using single identity matrix");
+        vec![Mat4::IDENTITY]
     };
     
     Ok(SkinnedMeshData::new(vertices, indices, joint_matrices))
@@ -296,13 +279,10 @@ pub fn animate_joints(
     colonist_gltf: Res<MeshGltf>,
     mut animation_players: Query<(Entity, &mut AnimationPlayer)>,
     mut commands: Commands,
+    mut animation_started: Local<bool>,
 ) {
-    static mut ANIMATION_STARTED: bool = false;
-    
-    unsafe {
-        if ANIMATION_STARTED {
-            return;
-        }
+    if *animation_started {
+        return;
     }
     
     // Get the GLTF asset
@@ -318,11 +298,9 @@ pub fn animate_joints(
                 
                 // Play the animation
                 player.play(node_index).repeat();
-                unsafe {
-                    ANIMATION_STARTED = true;
-                }
-                println!("Started playing animation from GLB file");
+                *animation_started = true;
+                info!("Started playing animation from GLB file");
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,14 +1,27 @@
-use ash::{vk, Instance, Entry};
+use ash::{vk, vk::Handle, Instance, Entry};
 use ash::khr;
-use std::collections::HashSet;
+use ash::ext::debug_utils;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::mem;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::ffi::CString;
+use tracing::{debug, info, warn};
+#[cfg(feature = "bevy")]
 use bevy::window::RawHandleWrapperHolder;
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
 
 use crate::constants::*;
+use crate::error::FloError;
 use crate::memory_pool::{MemoryPoolManager, MemoryBlock};
+use crate::tonemap::PresentPass;
+use crate::frame_pacing::FramePacer;
+use crate::descriptor_layouts::{SharedDescriptorLayout, FIRST_PIPELINE_SET};
+use crate::descriptor_allocator::DescriptorUsage;
+use crate::leak_check;
+use crate::mesh::VertexFormat;
 
+#[derive(Clone, Copy)]
 pub struct QueueFamilyIndices {
     pub graphics_family: Option<u32>,
     pub present_family: Option<u32>,
@@ -56,21 +69,332 @@ pub fn find_queue_families(
     indices
 }
 
+// Environment variable overriding the GPU, read by VulkanCore::new: an index into
+// enumerate_devices, or part of a device name, e.g. FLO_GPU=1 or FLO_GPU=nvidia
+pub const DEVICE_SELECTION_ENV: &str = "FLO_GPU";
+
+// Which GPU the device is created on. Auto picks the best scoring one that can render and
+// present to the window, see DeviceInfo::score.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DeviceSelection {
+    #[default]
+    Auto,
+    // Position in enumerate_devices
+    Index(usize),
+    // Case insensitive part of the device name, the best scoring match is used
+    Name(String),
+}
+
+impl DeviceSelection {
+    // The selection in FLO_GPU, Auto when it's unset or empty
+    pub fn from_env() -> Self {
+        match std::env::var(DEVICE_SELECTION_ENV) {
+            Ok(value) if !value.trim().is_empty() => match value.trim().parse() {
+                Ok(index) => DeviceSelection::Index(index),
+                Err(_) => DeviceSelection::Name(value.trim().to_string()),
+            },
+            _ => DeviceSelection::Auto,
+        }
+    }
+    
+    fn matches(&self, info: &DeviceInfo) -> bool {
+        match self {
+            DeviceSelection::Auto => true,
+            DeviceSelection::Index(index) => info.index == *index,
+            DeviceSelection::Name(name) => info.name.to_lowercase().contains(&name.to_lowercase()),
+        }
+    }
+}
+
+// A GPU as reported by the driver, e.g. for a device picker
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    // Position in enumerate_devices, for DeviceSelection::Index
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    // Size of the device local heaps. Integrated GPUs report shared system memory here.
+    pub vram_bytes: u64,
+    pub api_version: u32,
+    // Has a graphics queue. Presenting depends on the window and is checked when creating the core.
+    pub supports_graphics: bool,
+    // Ways a multisampled depth attachment can be resolved in a subpass (core in Vulkan 1.2,
+    // VK_KHR_depth_stencil_resolve before), empty when the device has none
+    pub depth_resolve_modes: vk::ResolveModeFlags,
+}
+
+impl DeviceInfo {
+    fn query(instance: &Instance, index: usize, device: vk::PhysicalDevice) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(device) };
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(device) };
+        let vram_bytes = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum();
+        let supports_graphics = unsafe { instance.get_physical_device_queue_family_properties(device) }
+            .iter()
+            .any(|queue_family| queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS));
+        // Left zeroed by devices that don't know the struct
+        let mut resolve_properties = vk::PhysicalDeviceDepthStencilResolveProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut resolve_properties);
+        unsafe { instance.get_physical_device_properties2(device, &mut properties2) };
+        
+        Self {
+            index,
+            name: properties.device_name_as_c_str()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            device_type: properties.device_type,
+            vram_bytes,
+            api_version: properties.api_version,
+            supports_graphics,
+            depth_resolve_modes: resolve_properties.supported_depth_resolve_modes,
+        }
+    }
+    
+    // Higher is preferred: discrete over integrated over virtual GPUs over the rest (e.g.
+    // software rasterizers), then more VRAM between GPUs of the same type
+    pub fn score(&self) -> u64 {
+        let type_score: u64 = match self.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 3,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 2,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 1,
+            _ => 0,
+        };
+        (type_score << 48) + (self.vram_bytes >> 20)
+    }
+}
+
+// Optional features and key limits of the GPU a core runs on, e.g. to build quality settings
+// or hide options the device can't run, see VulkanRenderer::device_capabilities. The features
+// the renderer uses (anisotropy, tessellation, wireframes, sample shading) are enabled on the
+// device when supported. The others only say what the device supports, the renderer doesn't
+// enable them yet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceCapabilities {
+    pub geometry_shader: bool,
+    pub tessellation_shader: bool,
+    // More than one draw per indirect draw call
+    pub multi_draw_indirect: bool,
+    pub sampler_anisotropy: bool,
+    // vk::PolygonMode::LINE, which wireframe overlays need
+    pub fill_mode_non_solid: bool,
+    // See PipelineBuilder::with_sample_shading
+    pub sample_rate_shading: bool,
+    // Partially bound, runtime sized arrays of sampled images indexed non-uniformly, what
+    // bindless textures need (VK_EXT_descriptor_indexing, core in Vulkan 1.2)
+    pub descriptor_indexing: bool,
+    // Cull mode, front face and depth state set per draw instead of per pipeline
+    // (VK_EXT_extended_dynamic_state, core in Vulkan 1.3)
+    pub extended_dynamic_state: bool,
+    pub timeline_semaphore: bool,
+    // Bytes of push constants a pipeline layout can hold, at least 128
+    pub max_push_constants_size: u32,
+    pub max_sampler_anisotropy: f32,
+    // Largest width or height of a 2D texture (maxImageDimension2D)
+    pub max_texture_size: u32,
+    // Highest sample count color and depth attachments both support, TYPE_1 without MSAA
+    pub max_msaa_samples: vk::SampleCountFlags,
+    // Supported by the core's surface, Fifo always is
+    pub present_modes: Vec<PresentMode>,
+}
+
+impl DeviceCapabilities {
+    fn query(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        surface_loader: &khr::surface::Instance,
+        surface: vk::SurfaceKHR,
+    ) -> Result<Self, FloError> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let limits = properties.limits;
+        // Left zeroed by devices that don't know the structs
+        let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut extended_dynamic_state = vk::PhysicalDeviceExtendedDynamicStateFeaturesEXT::default();
+        let mut timeline_semaphore = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+        let mut features2 = vk::PhysicalDeviceFeatures2::default()
+            .push_next(&mut descriptor_indexing)
+            .push_next(&mut extended_dynamic_state)
+            .push_next(&mut timeline_semaphore);
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+        let features = features2.features;
+        
+        let sample_counts = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        let max_msaa_samples = [
+            vk::SampleCountFlags::TYPE_64,
+            vk::SampleCountFlags::TYPE_32,
+            vk::SampleCountFlags::TYPE_16,
+            vk::SampleCountFlags::TYPE_8,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_2,
+        ]
+        .into_iter()
+        .find(|&count| sample_counts.contains(count))
+        .unwrap_or(vk::SampleCountFlags::TYPE_1);
+        
+        Ok(Self {
+            geometry_shader: features.geometry_shader == vk::TRUE,
+            tessellation_shader: features.tessellation_shader == vk::TRUE,
+            multi_draw_indirect: features.multi_draw_indirect == vk::TRUE,
+            sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+            fill_mode_non_solid: features.fill_mode_non_solid == vk::TRUE,
+            sample_rate_shading: features.sample_rate_shading == vk::TRUE,
+            descriptor_indexing: descriptor_indexing.runtime_descriptor_array == vk::TRUE
+                && descriptor_indexing.descriptor_binding_partially_bound == vk::TRUE
+                && descriptor_indexing.shader_sampled_image_array_non_uniform_indexing == vk::TRUE,
+            // Vulkan 1.3 devices have it without the extension's feature
+            extended_dynamic_state: extended_dynamic_state.extended_dynamic_state == vk::TRUE
+                || properties.api_version >= vk::API_VERSION_1_3,
+            timeline_semaphore: timeline_semaphore.timeline_semaphore == vk::TRUE,
+            max_push_constants_size: limits.max_push_constants_size,
+            max_sampler_anisotropy: limits.max_sampler_anisotropy,
+            max_texture_size: limits.max_image_dimension2_d,
+            max_msaa_samples,
+            present_modes: supported_present_modes(surface_loader, surface, physical_device)?,
+        })
+    }
+}
+
+// The GPUs Vulkan reports, in the order DeviceSelection::Index refers to. Needs no window,
+// so it can fill a picker before the renderer is created.
+pub fn enumerate_devices() -> Result<Vec<DeviceInfo>, FloError> {
+    let entry = load_vulkan_entry()?;
+    let instance = create_windowless_instance(&entry)?;
+    
+    let result = unsafe { instance.enumerate_physical_devices() }
+        .map(|devices| {
+            devices.iter()
+                .enumerate()
+                .map(|(index, &device)| DeviceInfo::query(&instance, index, device))
+                .collect()
+        })
+        .map_err(FloError::from);
+    
+    unsafe { instance.destroy_instance(None) };
+    result
+}
+
+// Picks the GPU the selection allows that scores best, runs Vulkan 1.3 and has queues for
+// graphics and for presenting to the surface
 pub fn pick_physical_device(
     instance: &Instance,
     surface_loader: &khr::surface::Instance,
     surface: vk::SurfaceKHR,
-) -> Result<(vk::PhysicalDevice, QueueFamilyIndices), Box<dyn std::error::Error>> {
+    selection: &DeviceSelection,
+) -> Result<(vk::PhysicalDevice, QueueFamilyIndices), FloError> {
     let devices = unsafe { instance.enumerate_physical_devices()? };
+    if devices.is_empty() {
+        return Err(FloError::NoPhysicalDevices);
+    }
+    
+    let infos: Vec<DeviceInfo> = devices.iter()
+        .enumerate()
+        .map(|(index, &device)| DeviceInfo::query(instance, index, device))
+        .collect();
     
-    for device in devices {
+    let mut best: Option<(u64, vk::PhysicalDevice, QueueFamilyIndices)> = None;
+    for (info, &device) in infos.iter().zip(&devices) {
+        // Pipelines set depth compare op, depth test and depth bias enables dynamically, which
+        // is core in 1.3 (VK_EXT_extended_dynamic_state and VK_EXT_extended_dynamic_state2 before)
+        if !selection.matches(info) || info.api_version < vk::API_VERSION_1_3 {
+            continue;
+        }
         let indices = find_queue_families(instance, surface_loader, surface, device);
-        if indices.is_complete() {
-            return Ok((device, indices));
+        if !indices.is_complete() {
+            continue;
         }
+        let score = info.score();
+        if !matches!(best, Some((best_score, ..)) if best_score >= score) {
+            best = Some((score, device, indices));
+        }
+    }
+    
+    if let Some((_, device, indices)) = best {
+        return Ok((device, indices));
+    }
+    if !infos.iter().any(|info| selection.matches(info)) {
+        let available: Vec<String> = infos.iter().map(|info| format!("{}: {}", info.index, info.name)).collect();
+        return Err(FloError::UnsupportedDevice(format!(
+            "No GPU matches {:?}, available GPUs are {}",
+            selection,
+            available.join(", "),
+        )));
+    }
+    let mut matching = infos.iter().filter(|info| selection.matches(info));
+    if matching.clone().all(|info| info.api_version < vk::API_VERSION_1_3) {
+        let info = matching.next().unwrap();
+        return Err(FloError::UnsupportedDevice(format!(
+            "{} supports Vulkan {}.{}, the renderer needs 1.3",
+            info.name,
+            vk::api_version_major(info.api_version),
+            vk::api_version_minor(info.api_version),
+        )));
+    }
+    Err(FloError::NoSuitableGpu)
+}
+
+fn load_vulkan_entry() -> Result<Entry, FloError> {
+    unsafe { Entry::load() }.map_err(|e| FloError::NoVulkanLoader(e.to_string()))
+}
+
+// The loader reports a missing or unusable driver as an incompatible driver when creating the instance
+fn instance_creation_error(result: vk::Result) -> FloError {
+    match result {
+        vk::Result::ERROR_INCOMPATIBLE_DRIVER => FloError::NoPhysicalDevices,
+        result => FloError::Vulkan(result),
     }
+}
+
+// Instance extensions needed to create a surface for the given display
+fn surface_extensions(display_handle: RawDisplayHandle) -> Result<Vec<*const std::ffi::c_char>, FloError> {
+    let platform_extension = match display_handle {
+        RawDisplayHandle::Windows(_) => khr::win32_surface::NAME,
+        RawDisplayHandle::Wayland(_) => khr::wayland_surface::NAME,
+        RawDisplayHandle::Xlib(_) => khr::xlib_surface::NAME,
+        RawDisplayHandle::Xcb(_) => khr::xcb_surface::NAME,
+        RawDisplayHandle::Android(_) => khr::android_surface::NAME,
+        RawDisplayHandle::AppKit(_) | RawDisplayHandle::UiKit(_) => ash::ext::metal_surface::NAME,
+        other => {
+            return Err(FloError::UnsupportedDevice(format!("No Vulkan surface for display {:?}", other)));
+        }
+    };
+    Ok(vec![khr::surface::NAME.as_ptr(), platform_extension.as_ptr()])
+}
+
+// Instance without surface extensions, for querying devices before there is a window
+fn create_windowless_instance(entry: &Entry) -> Result<Instance, FloError> {
+    let app_info = vk::ApplicationInfo::default().api_version(vk::API_VERSION_1_3);
+    let create_info = vk::InstanceCreateInfo::default().application_info(&app_info);
+    unsafe { entry.create_instance(&create_info, None) }.map_err(instance_creation_error)
+}
+
+// Checks for a Vulkan loader and a device with a graphics queue, without a window.
+// Presenting depends on the window's surface, so VulkanCore::new can still fail with
+// FloError::NoSuitableGpu on a machine that passes this check.
+pub fn check_vulkan_support() -> Result<(), FloError> {
+    let entry = load_vulkan_entry()?;
+    let instance = create_windowless_instance(&entry)?;
+    
+    let result = match unsafe { instance.enumerate_physical_devices() } {
+        Ok(devices) if devices.is_empty() => Err(FloError::NoPhysicalDevices),
+        Ok(devices) => {
+            let has_graphics_queue = devices.iter().any(|&device| {
+                unsafe { instance.get_physical_device_queue_family_properties(device) }
+                    .iter()
+                    .any(|queue_family| queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            });
+            if has_graphics_queue {
+                Ok(())
+            } else {
+                Err(FloError::NoSuitableGpu)
+            }
+        }
+        Err(e) => Err(e.into()),
+    };
     
-    Err("Failed to find suitable GPU".into())
+    unsafe { instance.destroy_instance(None) };
+    result
 }
 
 pub fn create_logical_device(
@@ -78,7 +402,7 @@ pub fn create_logical_device(
     physical_device: vk::PhysicalDevice,
     indices: &QueueFamilyIndices,
     enable_anisotropy: bool,
-) -> Result<ash::Device, Box<dyn std::error::Error>> {
+) -> Result<ash::Device, FloError> {
     let mut unique_queue_families = HashSet::new();
     unique_queue_families.insert(indices.graphics_family.unwrap());
     unique_queue_families.insert(indices.present_family.unwrap());
@@ -97,6 +421,16 @@ pub fn create_logical_device(
     if enable_anisotropy {
         device_features = device_features.sampler_anisotropy(true);
     }
+    // Optional, pipelines that need it check tessellation_supported first
+    if tessellation_supported(instance, physical_device) {
+        device_features = device_features.tessellation_shader(true);
+    }
+    if wireframe_supported(instance, physical_device) {
+        device_features = device_features.fill_mode_non_solid(true);
+    }
+    if sample_rate_shading_supported(instance, physical_device) {
+        device_features = device_features.sample_rate_shading(true);
+    }
     
     let device_extensions = vec![khr::swapchain::NAME.as_ptr()];
     
@@ -110,6 +444,92 @@ pub fn create_logical_device(
     Ok(device)
 }
 
+pub fn tessellation_supported(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    features.tessellation_shader == vk::TRUE
+}
+
+// Whether texture samplers can filter anisotropically, see VulkanCore::max_anisotropy
+pub fn anisotropy_supported(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    features.sampler_anisotropy == vk::TRUE
+}
+
+// Whether pipelines can use vk::PolygonMode::LINE, e.g. for wireframe overlays
+pub fn wireframe_supported(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    features.fill_mode_non_solid == vk::TRUE
+}
+
+// Whether pipelines can shade per sample, see PipelineBuilder::with_sample_shading
+pub fn sample_rate_shading_supported(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    features.sample_rate_shading == vk::TRUE
+}
+
+// COLOR_ATTACHMENT, which the present pass needs, plus the extra usages the surface supports.
+// Unsupported ones are dropped with a warning so callers can check what they got.
+pub fn supported_swapchain_usage(
+    surface_loader: &khr::surface::Instance,
+    surface: vk::SurfaceKHR,
+    physical_device: vk::PhysicalDevice,
+    extra_usage: vk::ImageUsageFlags,
+) -> Result<vk::ImageUsageFlags, FloError> {
+    let capabilities = unsafe {
+        surface_loader.get_physical_device_surface_capabilities(physical_device, surface)?
+    };
+    let supported = extra_usage & capabilities.supported_usage_flags;
+    if supported != extra_usage {
+        warn!(
+            "Swapchain images don't support {:?} on this surface, creating them without it",
+            extra_usage & !capabilities.supported_usage_flags,
+        );
+    }
+    Ok(vk::ImageUsageFlags::COLOR_ATTACHMENT | supported)
+}
+
+// image_usage has to be supported by the surface, see supported_swapchain_usage
+// Swapchain present mode, see CoreOptions::present_mode. Falls back to Fifo, which every
+// surface supports, when the requested one isn't available.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PresentMode {
+    // Waits for vertical blank, no tearing
+    Fifo,
+    // Replaces the waiting image instead of blocking, no tearing with lower latency than Fifo.
+    // Falls back to Immediate before Fifo.
+    #[default]
+    Mailbox,
+    // Presents right away and can tear. Falls back to Mailbox before Fifo.
+    Immediate,
+}
+
+impl PresentMode {
+    const ALL: [PresentMode; 3] = [PresentMode::Fifo, PresentMode::Mailbox, PresentMode::Immediate];
+    
+    fn preference(self) -> [vk::PresentModeKHR; 3] {
+        match self {
+            PresentMode::Fifo => [vk::PresentModeKHR::FIFO; 3],
+            PresentMode::Mailbox => [vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::FIFO],
+            PresentMode::Immediate => [vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO],
+        }
+    }
+}
+
+// The PresentModes the surface has without falling back to another one
+fn supported_present_modes(
+    surface_loader: &khr::surface::Instance,
+    surface: vk::SurfaceKHR,
+    physical_device: vk::PhysicalDevice,
+) -> Result<Vec<PresentMode>, FloError> {
+    let surface_modes = unsafe {
+        surface_loader.get_physical_device_surface_present_modes(physical_device, surface)?
+    };
+    Ok(PresentMode::ALL.into_iter()
+        .filter(|mode| surface_modes.contains(&mode.preference()[0]))
+        .collect())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn create_swapchain(
     _instance: &Instance,
     surface_loader: &khr::surface::Instance,
@@ -117,7 +537,9 @@ pub fn create_swapchain(
     physical_device: vk::PhysicalDevice,
     swapchain_loader: &khr::swapchain::Device,
     indices: &QueueFamilyIndices,
-) -> Result<(vk::SwapchainKHR, Vec<vk::Image>, vk::Format, vk::Extent2D), Box<dyn std::error::Error>> {
+    image_usage: vk::ImageUsageFlags,
+    requested_present_mode: PresentMode,
+) -> Result<(vk::SwapchainKHR, Vec<vk::Image>, vk::Format, vk::Extent2D), FloError> {
     let capabilities = unsafe {
         surface_loader.get_physical_device_surface_capabilities(physical_device, surface)?
     };
@@ -135,16 +557,12 @@ pub fn create_swapchain(
         .find(|f| f.format == vk::Format::B8G8R8A8_SRGB && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
         .unwrap_or(&formats[0]);
     
-    // Try to use MAILBOX (triple buffering) for best performance without tearing
-    // Falls back to IMMEDIATE (no v-sync) if MAILBOX not available
-    // Falls back to FIFO (v-sync) as last resort since it's always available
-    let present_mode = present_modes
-        .iter()
-        .find(|&&mode| mode == vk::PresentModeKHR::MAILBOX)
-        .or_else(|| present_modes.iter().find(|&&mode| mode == vk::PresentModeKHR::IMMEDIATE))
-        .unwrap_or(&vk::PresentModeKHR::FIFO);
+    let present_mode = requested_present_mode.preference()
+        .into_iter()
+        .find(|mode| present_modes.contains(mode))
+        .unwrap_or(vk::PresentModeKHR::FIFO);
     
-    println!("Selected present mode: {:?}", present_mode);
+    info!("Selected present mode: {:?}", present_mode);
     
     let extent = capabilities.current_extent;
     
@@ -159,7 +577,7 @@ pub fn create_swapchain(
         .image_color_space(surface_format.color_space)
         .image_extent(extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+        .image_usage(image_usage);
     
     let queue_family_indices = [indices.graphics_family.unwrap(), indices.present_family.unwrap()];
     
@@ -174,7 +592,7 @@ pub fn create_swapchain(
     create_info = create_info
         .pre_transform(capabilities.current_transform)
         .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-        .present_mode(*present_mode)
+        .present_mode(present_mode)
         .clipped(true);
     
     let swapchain = unsafe { swapchain_loader.create_swapchain(&create_info, None)? };
@@ -187,7 +605,7 @@ pub fn create_image_views(
     device: &ash::Device,
     swapchain_images: &[vk::Image],
     swapchain_format: vk::Format,
-) -> Result<Vec<vk::ImageView>, Box<dyn std::error::Error>> {
+) -> Result<Vec<vk::ImageView>, FloError> {
     let mut image_views = Vec::with_capacity(swapchain_images.len());
     
     for &image in swapchain_images {
@@ -209,24 +627,29 @@ pub fn create_image_views(
                 layer_count: 1,
             });
         
-        let image_view = unsafe { device.create_image_view(&create_info, None)? };
+        let image_view = leak_check::created(device, unsafe { device.create_image_view(&create_info, None)? });
         image_views.push(image_view);
     }
     
     Ok(image_views)
 }
 
+// First supported depth format, only ones with a stencil aspect when with_stencil is set
 pub fn find_depth_format(
     instance: &Instance,
     physical_device: vk::PhysicalDevice,
-) -> Result<vk::Format, Box<dyn std::error::Error>> {
-    let candidates = vec![
+    with_stencil: bool,
+) -> Result<vk::Format, FloError> {
+    let candidates = [
         vk::Format::D32_SFLOAT,
         vk::Format::D32_SFLOAT_S8_UINT,
         vk::Format::D24_UNORM_S8_UINT,
     ];
     
     for format in candidates {
+        if with_stencil && !has_stencil_component(format) {
+            continue;
+        }
         let props = unsafe {
             instance.get_physical_device_format_properties(physical_device, format)
         };
@@ -236,7 +659,24 @@ pub fn find_depth_format(
         }
     }
     
-    Err("Failed to find supported depth format".into())
+    let kind = if with_stencil { "depth stencil" } else { "depth" };
+    Err(FloError::UnsupportedDevice(format!("no supported {} format", kind)))
+}
+
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D16_UNORM_S8_UINT | vk::Format::S8_UINT
+    )
+}
+
+// Aspects of a depth attachment view, combined formats need the stencil aspect too
+pub fn depth_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    if has_stencil_component(format) {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else {
+        vk::ImageAspectFlags::DEPTH
+    }
 }
 
 pub fn create_depth_resources(
@@ -244,9 +684,8 @@ pub fn create_depth_resources(
     device: &ash::Device,
     physical_device: vk::PhysicalDevice,
     extent: vk::Extent2D,
-) -> Result<(vk::Image, vk::DeviceMemory, vk::ImageView), Box<dyn std::error::Error>> {
-    let depth_format = find_depth_format(instance, physical_device)?;
-    
+    depth_format: vk::Format,
+) -> Result<(vk::Image, vk::DeviceMemory, vk::ImageView), FloError> {
     let image_info = vk::ImageCreateInfo::default()
         .image_type(vk::ImageType::TYPE_2D)
         .extent(vk::Extent3D {
@@ -259,11 +698,11 @@ pub fn create_depth_resources(
         .format(depth_format)
         .tiling(vk::ImageTiling::OPTIMAL)
         .initial_layout(vk::ImageLayout::UNDEFINED)
-        .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+        .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT)
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
         .samples(vk::SampleCountFlags::TYPE_1);
     
-    let image = unsafe { device.create_image(&image_info, None)? };
+    let image = leak_check::created(device, unsafe { device.create_image(&image_info, None)? });
     
     let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
     
@@ -276,7 +715,7 @@ pub fn create_depth_resources(
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
         )?);
     
-    let image_memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+    let image_memory = unsafe { allocate_device_memory(device, &alloc_info, MemoryUsage::Attachment)? };
     
     unsafe { device.bind_image_memory(image, image_memory, 0)? };
     
@@ -285,14 +724,78 @@ pub fn create_depth_resources(
         .view_type(vk::ImageViewType::TYPE_2D)
         .format(depth_format)
         .subresource_range(vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            aspect_mask: depth_aspect_mask(depth_format),
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        });
+    
+    let image_view = leak_check::created(device, unsafe { device.create_image_view(&view_info, None)? });
+    
+    Ok((image, image_memory, image_view))
+}
+
+// Color attachment that can also be sampled, for the HDR scene images and render targets
+pub fn create_color_target(
+    instance: &Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+    extent: vk::Extent2D,
+) -> Result<(vk::Image, vk::DeviceMemory, vk::ImageView), FloError> {
+    let image_info = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        // TRANSFER_SRC for crate::capture readbacks, TRANSFER_DST for VulkanRenderer::enable_previous_frame_access
+        .usage(
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+        )
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::TYPE_1);
+    
+    let image = leak_check::created(device, unsafe { device.create_image(&image_info, None)? });
+    
+    let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+    
+    let alloc_info = vk::MemoryAllocateInfo::default()
+        .allocation_size(mem_requirements.size)
+        .memory_type_index(find_memory_type(
+            instance,
+            physical_device,
+            mem_requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?);
+    
+    let image_memory = unsafe { allocate_device_memory(device, &alloc_info, MemoryUsage::Attachment)? };
+    
+    unsafe { device.bind_image_memory(image, image_memory, 0)? };
+    
+    let view_info = vk::ImageViewCreateInfo::default()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
             base_mip_level: 0,
             level_count: 1,
             base_array_layer: 0,
             layer_count: 1,
         });
     
-    let image_view = unsafe { device.create_image_view(&view_info, None)? };
+    let image_view = leak_check::created(device, unsafe { device.create_image_view(&view_info, None)? });
     
     Ok((image, image_memory, image_view))
 }
@@ -302,7 +805,7 @@ pub fn find_memory_type(
     physical_device: vk::PhysicalDevice,
     type_filter: u32,
     properties: vk::MemoryPropertyFlags,
-) -> Result<u32, Box<dyn std::error::Error>> {
+) -> Result<u32, FloError> {
     let mem_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
     
     for i in 0..mem_properties.memory_type_count {
@@ -312,7 +815,62 @@ pub fn find_memory_type(
         }
     }
     
-    Err("Failed to find suitable memory type".into())
+    Err(FloError::UnsupportedDevice("no suitable memory type".to_string()))
+}
+
+// What the scene pass draws into, see VulkanCore::render_extent
+struct SceneTargets {
+    depth_image: vk::Image,
+    depth_image_memory: vk::DeviceMemory,
+    depth_image_view: vk::ImageView,
+    hdr_images: Vec<vk::Image>,
+    hdr_image_memories: Vec<vk::DeviceMemory>,
+    hdr_image_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+// One HDR image and framebuffer per swapchain image, sharing one depth buffer if there's a
+// depth format
+fn create_scene_targets(
+    instance: &Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    depth_format: Option<vk::Format>,
+    render_pass: vk::RenderPass,
+    image_count: usize,
+    extent: vk::Extent2D,
+) -> Result<SceneTargets, FloError> {
+    let (depth_image, depth_image_memory, depth_image_view) = if let Some(depth_format) = depth_format {
+        create_depth_resources(instance, device, physical_device, extent, depth_format)?
+    } else {
+        (vk::Image::null(), vk::DeviceMemory::null(), vk::ImageView::null())
+    };
+    
+    let mut hdr_images = Vec::with_capacity(image_count);
+    let mut hdr_image_memories = Vec::with_capacity(image_count);
+    let mut hdr_image_views = Vec::with_capacity(image_count);
+    for _ in 0..image_count {
+        let (image, memory, view) = create_color_target(instance, device, physical_device, HDR_FORMAT, extent)?;
+        hdr_images.push(image);
+        hdr_image_memories.push(memory);
+        hdr_image_views.push(view);
+    }
+    
+    let framebuffers = if depth_format.is_some() {
+        create_framebuffers(device, &hdr_image_views, depth_image_view, render_pass, extent)?
+    } else {
+        create_framebuffers_no_depth(device, &hdr_image_views, render_pass, extent)?
+    };
+    
+    Ok(SceneTargets {
+        depth_image,
+        depth_image_memory,
+        depth_image_view,
+        hdr_images,
+        hdr_image_memories,
+        hdr_image_views,
+        framebuffers,
+    })
 }
 
 pub fn create_framebuffers(
@@ -321,7 +879,7 @@ pub fn create_framebuffers(
     depth_image_view: vk::ImageView,
     render_pass: vk::RenderPass,
     extent: vk::Extent2D,
-) -> Result<Vec<vk::Framebuffer>, Box<dyn std::error::Error>> {
+) -> Result<Vec<vk::Framebuffer>, FloError> {
     let mut framebuffers = Vec::with_capacity(image_views.len());
     
     for &image_view in image_views {
@@ -334,7 +892,7 @@ pub fn create_framebuffers(
             .height(extent.height)
             .layers(1);
         
-        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None)? };
+        let framebuffer = leak_check::created(device, unsafe { device.create_framebuffer(&framebuffer_info, None)? });
         framebuffers.push(framebuffer);
     }
     
@@ -344,12 +902,12 @@ pub fn create_framebuffers(
 pub fn create_command_pool(
     device: &ash::Device,
     queue_family_index: u32,
-) -> Result<vk::CommandPool, Box<dyn std::error::Error>> {
+) -> Result<vk::CommandPool, FloError> {
     let pool_info = vk::CommandPoolCreateInfo::default()
         .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
         .queue_family_index(queue_family_index);
     
-    let command_pool = unsafe { device.create_command_pool(&pool_info, None)? };
+    let command_pool = leak_check::created(device, unsafe { device.create_command_pool(&pool_info, None)? });
     
     Ok(command_pool)
 }
@@ -358,10 +916,19 @@ pub fn create_command_buffers(
     device: &ash::Device,
     command_pool: vk::CommandPool,
     count: usize,
-) -> Result<Vec<vk::CommandBuffer>, Box<dyn std::error::Error>> {
+) -> Result<Vec<vk::CommandBuffer>, FloError> {
+    create_command_buffers_with_level(device, command_pool, count, vk::CommandBufferLevel::PRIMARY)
+}
+
+pub fn create_command_buffers_with_level(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    count: usize,
+    level: vk::CommandBufferLevel,
+) -> Result<Vec<vk::CommandBuffer>, FloError> {
     let alloc_info = vk::CommandBufferAllocateInfo::default()
         .command_pool(command_pool)
-        .level(vk::CommandBufferLevel::PRIMARY)
+        .level(level)
         .command_buffer_count(count as u32);
     
     let command_buffers = unsafe { device.allocate_command_buffers(&alloc_info)? };
@@ -371,7 +938,7 @@ pub fn create_command_buffers(
 
 pub fn create_sync_objects(
     device: &ash::Device,
-) -> Result<(Vec<vk::Semaphore>, Vec<vk::Semaphore>, Vec<vk::Fence>), Box<dyn std::error::Error>> {
+) -> Result<(Vec<vk::Semaphore>, Vec<vk::Semaphore>, Vec<vk::Fence>), FloError> {
     let semaphore_info = vk::SemaphoreCreateInfo::default();
     let fence_info = vk::FenceCreateInfo::default()
         .flags(vk::FenceCreateFlags::SIGNALED);
@@ -381,23 +948,98 @@ pub fn create_sync_objects(
     let mut in_flight_fences = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
     
     for _ in 0..MAX_FRAMES_IN_FLIGHT {
-        image_available_semaphores.push(unsafe { device.create_semaphore(&semaphore_info, None)? });
-        render_finished_semaphores.push(unsafe { device.create_semaphore(&semaphore_info, None)? });
-        in_flight_fences.push(unsafe { device.create_fence(&fence_info, None)? });
+        image_available_semaphores.push(leak_check::created(device, unsafe { device.create_semaphore(&semaphore_info, None)? }));
+        render_finished_semaphores.push(leak_check::created(device, unsafe { device.create_semaphore(&semaphore_info, None)? }));
+        in_flight_fences.push(leak_check::created(device, unsafe { device.create_fence(&fence_info, None)? }));
     }
     
     Ok((image_available_semaphores, render_finished_semaphores, in_flight_fences))
 }
 
-pub fn create_shader_module(device: &ash::Device, code: &[u8]) -> Result<vk::ShaderModule, Box<dyn std::error::Error>> {
+// One occlusion query pool per frame in flight, so results are read back once that
+// frame's fence has signaled instead of stalling on the frame just submitted
+pub fn create_occlusion_query_pools(device: &ash::Device) -> Result<Vec<vk::QueryPool>, FloError> {
+    let pool_info = vk::QueryPoolCreateInfo::default()
+        .query_type(vk::QueryType::OCCLUSION)
+        .query_count(MAX_OCCLUSION_QUERIES);
+    
+    let mut pools = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        pools.push(leak_check::created(device, unsafe { device.create_query_pool(&pool_info, None)? }));
+    }
+    
+    Ok(pools)
+}
+
+// Two timestamp queries per frame in flight, written at the start and end of the frame's
+// command buffer, see VulkanCore::gpu_frame_time_ms
+pub fn create_timestamp_query_pools(device: &ash::Device) -> Result<Vec<vk::QueryPool>, FloError> {
+    let pool_info = vk::QueryPoolCreateInfo::default()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count(2);
+    
+    let mut pools = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        pools.push(leak_check::created(device, unsafe { device.create_query_pool(&pool_info, None)? }));
+    }
+    
+    Ok(pools)
+}
+
+// Nanoseconds per timestamp tick and the number of valid bits in a timestamp for the queue
+// family, None if it can't write timestamps
+fn timestamp_support(instance: &Instance, physical_device: vk::PhysicalDevice, queue_family: u32) -> Option<(f32, u32)> {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    let valid_bits = families.get(queue_family as usize).map_or(0, |family| family.timestamp_valid_bits);
+    let period = properties.limits.timestamp_period;
+    (valid_bits > 0 && period > 0.0).then_some((period, valid_bits))
+}
+
+// Reads a compiled shader, naming the file in the error since a missing .spv is usually a
+// shader that wasn't compiled
+pub fn read_shader_file(path: &str) -> Result<Vec<u8>, FloError> {
+    std::fs::read(path)
+        .map_err(|e| FloError::Io(std::io::Error::new(e.kind(), format!("Failed to read shader {}: {}", path, e))))
+}
+
+pub fn create_shader_module(device: &ash::Device, code: &[u8]) -> Result<vk::ShaderModule, FloError> {
+    // SPIR-V is a stream of 32 bit words starting with the magic number and a 4 word header
+    const SPIRV_MAGIC: u32 = 0x0723_0203;
+    const HEADER_BYTES: usize = 5 * 4;
+    if code.is_empty() {
+        return Err(FloError::ShaderCompile("SPIR-V file is empty".to_string()));
+    }
+    if code.len() % 4 != 0 {
+        return Err(FloError::ShaderCompile(format!(
+            "SPIR-V size {} is not a multiple of 4, the file is probably truncated",
+            code.len()
+        )));
+    }
+    if code.len() < HEADER_BYTES {
+        return Err(FloError::ShaderCompile(format!("SPIR-V size {} is shorter than its header", code.len())));
+    }
+    // The magic number tells the byte order the words were written in, the driver wants host order
+    let magic = [code[0], code[1], code[2], code[3]];
+    let from_bytes: fn([u8; 4]) -> u32 = if u32::from_le_bytes(magic) == SPIRV_MAGIC {
+        u32::from_le_bytes
+    } else if u32::from_be_bytes(magic) == SPIRV_MAGIC {
+        u32::from_be_bytes
+    } else {
+        return Err(FloError::ShaderCompile(format!(
+            "missing SPIR-V magic number (found {:#010x}), is this a compiled .spv file?",
+            u32::from_le_bytes(magic)
+        )));
+    };
+    
     let code_u32: Vec<u32> = code.chunks_exact(4)
-        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .map(|chunk| from_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
         .collect();
     
     let create_info = vk::ShaderModuleCreateInfo::default()
         .code(&code_u32);
     
-    let shader_module = unsafe { device.create_shader_module(&create_info, None)? };
+    let shader_module = leak_check::created(device, unsafe { device.create_shader_module(&create_info, None)? });
     
     Ok(shader_module)
 }
@@ -409,13 +1051,13 @@ pub fn create_buffer(
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
+) -> Result<(vk::Buffer, vk::DeviceMemory), FloError> {
     let buffer_info = vk::BufferCreateInfo::default()
         .size(size)
         .usage(usage)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
     
-    let buffer = unsafe { device.create_buffer(&buffer_info, None)? };
+    let buffer = leak_check::created(device, unsafe { device.create_buffer(&buffer_info, None)? });
     
     let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
     
@@ -428,7 +1070,7 @@ pub fn create_buffer(
             properties,
         )?);
     
-    let buffer_memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+    let buffer_memory = unsafe { allocate_device_memory(device, &alloc_info, MemoryUsage::from_buffer_usage(usage))? };
     
     unsafe { device.bind_buffer_memory(buffer, buffer_memory, 0)? };
     
@@ -442,7 +1084,7 @@ pub fn copy_buffer(
     src_buffer: vk::Buffer,
     dst_buffer: vk::Buffer,
     size: vk::DeviceSize,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), FloError> {
     let alloc_info = vk::CommandBufferAllocateInfo::default()
         .level(vk::CommandBufferLevel::PRIMARY)
         .command_pool(command_pool)
@@ -483,7 +1125,7 @@ pub fn create_vertex_buffer<T: Copy>(
     command_pool: vk::CommandPool,
     queue: vk::Queue,
     vertices: &[T],
-) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
+) -> Result<(vk::Buffer, vk::DeviceMemory), FloError> {
     let buffer_size = (mem::size_of::<T>() * vertices.len()) as vk::DeviceSize;
     
     let (staging_buffer, staging_buffer_memory) = create_buffer(
@@ -513,8 +1155,8 @@ pub fn create_vertex_buffer<T: Copy>(
     copy_buffer(device, command_pool, queue, staging_buffer, vertex_buffer, buffer_size)?;
     
     unsafe {
-        device.destroy_buffer(staging_buffer, None);
-        device.free_memory(staging_buffer_memory, None);
+        leak_check::destroy(device, staging_buffer);
+        free_device_memory(device, staging_buffer_memory);
     }
     
     Ok((vertex_buffer, vertex_buffer_memory))
@@ -527,7 +1169,7 @@ pub fn create_index_buffer(
     command_pool: vk::CommandPool,
     queue: vk::Queue,
     indices: &[u32],
-) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
+) -> Result<(vk::Buffer, vk::DeviceMemory), FloError> {
     let buffer_size = (mem::size_of::<u32>() * indices.len()) as vk::DeviceSize;
     
     let (staging_buffer, staging_buffer_memory) = create_buffer(
@@ -557,8 +1199,8 @@ pub fn create_index_buffer(
     copy_buffer(device, command_pool, queue, staging_buffer, index_buffer, buffer_size)?;
     
     unsafe {
-        device.destroy_buffer(staging_buffer, None);
-        device.free_memory(staging_buffer_memory, None);
+        leak_check::destroy(device, staging_buffer);
+        free_device_memory(device, staging_buffer_memory);
     }
     
     Ok((index_buffer, index_buffer_memory))
@@ -573,13 +1215,13 @@ pub fn create_buffer_pooled(
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Buffer, MemoryBlock), Box<dyn std::error::Error>> {
+) -> Result<(vk::Buffer, MemoryBlock), FloError> {
     let buffer_info = vk::BufferCreateInfo::default()
         .size(size)
         .usage(usage)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
     
-    let buffer = unsafe { device.create_buffer(&buffer_info, None)? };
+    let buffer = leak_check::created(device, unsafe { device.create_buffer(&buffer_info, None)? });
     
     let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
     
@@ -603,7 +1245,7 @@ pub fn create_vertex_buffer_pooled<T: Copy>(
     queue: vk::Queue,
     memory_pool: &mut MemoryPoolManager,
     vertices: &[T],
-) -> Result<(vk::Buffer, MemoryBlock), Box<dyn std::error::Error>> {
+) -> Result<(vk::Buffer, MemoryBlock), FloError> {
     let buffer_size = (mem::size_of::<T>() * vertices.len()) as vk::DeviceSize;
     
     // Use reusable staging buffer from pool
@@ -645,7 +1287,7 @@ pub fn create_index_buffer_pooled(
     queue: vk::Queue,
     memory_pool: &mut MemoryPoolManager,
     indices: &[u32],
-) -> Result<(vk::Buffer, MemoryBlock), Box<dyn std::error::Error>> {
+) -> Result<(vk::Buffer, MemoryBlock), FloError> {
     let buffer_size = (mem::size_of::<u32>() * indices.len()) as vk::DeviceSize;
     
     // Use reusable staging buffer from pool
@@ -721,9 +1363,10 @@ pub fn record_command_buffer_simple(
             .clear_values(&clear_values);
         
         device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+        set_viewport_and_scissor(device, command_buffer, extent);
         
         if graphics_pipeline != vk::Pipeline::null() {
-            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, graphics_pipeline);
+            bind_graphics_pipeline(device, command_buffer, graphics_pipeline, vk::CompareOp::LESS);
             
             let elapsed = start_time.elapsed().as_secs_f32();
             let time_data = [elapsed];
@@ -740,7 +1383,7 @@ pub fn record_command_buffer_simple(
             device.cmd_draw(command_buffer, vertex_count, 1, 0, 0);
         }
         
-        device.cmd_end_render_pass(command_buffer);
+        end_scene_render_pass(device, command_buffer);
         
         device
             .end_command_buffer(command_buffer)
@@ -792,9 +1435,10 @@ pub fn record_command_buffer_indexed(
             .clear_values(&clear_values);
         
         device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+        set_viewport_and_scissor(device, command_buffer, extent);
         
         if graphics_pipeline != vk::Pipeline::null() {
-            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, graphics_pipeline);
+            bind_graphics_pipeline(device, command_buffer, graphics_pipeline, vk::CompareOp::LESS);
             
             let vertex_buffers = [vertex_buffer];
             let offsets = [0];
@@ -816,7 +1460,7 @@ pub fn record_command_buffer_indexed(
             device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
         }
         
-        device.cmd_end_render_pass(command_buffer);
+        end_scene_render_pass(device, command_buffer);
         
         device
             .end_command_buffer(command_buffer)
@@ -824,6 +1468,78 @@ pub fn record_command_buffer_indexed(
     }
 }
 
+// Time pushed to animated shaders, which can be paused, slowed down or sped up. Wall clock time
+// since the last change is added at the current scale, so time never jumps or runs backwards
+// when the scale changes or it resumes.
+#[derive(Clone, Copy, Debug)]
+pub struct SceneClock {
+    accumulated: f64,  // Seconds up to last_change
+    last_change: Instant,
+    scale: f32,
+    paused: bool,
+}
+
+impl Default for SceneClock {
+    fn default() -> Self {
+        Self {
+            accumulated: 0.0,
+            last_change: Instant::now(),
+            scale: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl SceneClock {
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed_secs_f64() as f32
+    }
+    
+    fn elapsed_secs_f64(&self) -> f64 {
+        if self.paused {
+            self.accumulated
+        } else {
+            self.accumulated + self.last_change.elapsed().as_secs_f64() * f64::from(self.scale)
+        }
+    }
+    
+    // Folds the time so far into accumulated before the scale or pause state changes
+    fn checkpoint(&mut self) {
+        self.accumulated = self.elapsed_secs_f64();
+        self.last_change = Instant::now();
+    }
+    
+    pub fn pause(&mut self) {
+        self.checkpoint();
+        self.paused = true;
+    }
+    
+    pub fn resume(&mut self) {
+        self.checkpoint();
+        self.paused = false;
+    }
+    
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    
+    // Negative scales are clamped to 0 so time stays monotonic
+    pub fn set_scale(&mut self, scale: f32) {
+        self.checkpoint();
+        self.scale = scale.max(0.0);
+    }
+    
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+    
+    // Back to 0, keeping the scale and pause state
+    pub fn reset(&mut self) {
+        self.accumulated = 0.0;
+        self.last_change = Instant::now();
+    }
+}
+
 pub struct VulkanCore {
     pub _entry: Entry,
     pub instance: Instance,
@@ -838,102 +1554,352 @@ pub struct VulkanCore {
     pub swapchain_images: Vec<vk::Image>,
     pub swapchain_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
+    // COLOR_ATTACHMENT and the supported extra usages from CoreOptions
+    pub swapchain_image_usage: vk::ImageUsageFlags,
+    // Requested mode, the swapchain may have fallen back to another one
+    pub present_mode: PresentMode,
     pub swapchain_image_views: Vec<vk::ImageView>,
+    // Size of the depth buffer, HDR images and scene framebuffers. The swapchain extent unless
+    // set_render_extent shrank them, the present pass scales the HDR image to the swapchain.
+    pub render_extent: vk::Extent2D,
+    // None without a depth buffer
+    pub depth_format: Option<vk::Format>,
     pub depth_image: vk::Image,
     pub depth_image_memory: vk::DeviceMemory,
     pub depth_image_view: vk::ImageView,
+    // The scene is drawn into these (one per swapchain image) and tonemapped to the swapchain
+    // image by the present pass
+    pub hdr_images: Vec<vk::Image>,
+    pub hdr_image_memories: Vec<vk::DeviceMemory>,
+    pub hdr_image_views: Vec<vk::ImageView>,
     pub render_pass: vk::RenderPass,
+    // Compatible with render_pass but loads the color and depth the frame's earlier scene pass
+    // left in the framebuffer, for passes drawn over it (see VulkanRenderer::queue_overlay_pass).
+    // The framebuffer has to have been through render_pass first, its contents and layouts are
+    // undefined before that.
+    pub load_render_pass: vk::RenderPass,
     pub framebuffers: Vec<vk::Framebuffer>,
+    pub present: PresentPass,
     pub command_pool: vk::CommandPool,
     pub command_buffers: Vec<vk::CommandBuffer>,
     pub image_available_semaphores: Vec<vk::Semaphore>,
     pub render_finished_semaphores: Vec<vk::Semaphore>,
     pub in_flight_fences: Vec<vk::Fence>,
     pub current_frame: usize,
-    pub start_time: Instant,
+    // Swapchain image acquired by the last begin_frame
+    pub current_image_index: u32,
+    // Time for animated shaders, see get_elapsed_time
+    pub clock: SceneClock,
     pub queue_family_indices: QueueFamilyIndices,
+    pub occlusion_query_pools: Vec<vk::QueryPool>,
+    pub timestamp_query_pools: Vec<vk::QueryPool>,
+    // Tick length in nanoseconds and valid bits of the graphics queue's timestamps, None if
+    // it can't write them
+    pub timestamp_support: Option<(f32, u32)>,
+    // Whether each frame in flight's timestamps were written since its pool was last reset
+    pub timestamps_written: [bool; MAX_FRAMES_IN_FLIGHT],
+    pub gpu_frame_time_ms: Option<f32>,
+    // Only loaded with validation layers on, naming objects is a no-op otherwise
+    pub debug_utils: Option<debug_utils::Device>,
+    // ERROR_SURFACE_LOST_KHR or ERROR_DEVICE_LOST once a frame hit either, begin_frame returns it
+    // without touching the device until recreate_surface
+    pub lost: Option<vk::Result>,
+    // Anisotropic filtering of textures created from now on, clamped to the device limit. 1 or
+    // less turns it off.
+    pub max_anisotropy: f32,
+    // Sleeps at the end of end_frame to cap the frame rate, uncapped by default
+    pub frame_pacer: FramePacer,
+    // Queried at creation, present modes again by recreate_surface
+    pub capabilities: DeviceCapabilities,
+    // Keeps instance and device alive, which other cores may be using too (see SharedDevice)
+    device_owner: SharedDevice,
 }
 
-impl VulkanCore {
-    pub fn new(
-        handle_wrapper: &RawHandleWrapperHolder,
-        with_depth: bool,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let entry = unsafe { Entry::load() }.expect("Failed to load Vulkan entry");
-        
-        let raw_handle = handle_wrapper.0.lock().unwrap();
-        let raw_handle_ref = raw_handle.as_ref().expect("Window handle not available");
-        
-        let display_handle = raw_handle_ref.get_display_handle();
-        let window_handle = raw_handle_ref.get_window_handle();
-        
-        let app_name = CString::new("Vulkan Bevy Renderer")?;
-        let engine_name = CString::new("No Engine")?;
-        
-        let app_info = vk::ApplicationInfo::default()
-            .application_name(&app_name)
-            .application_version(vk::make_api_version(0, 1, 0, 0))
-            .engine_name(&engine_name)
-            .engine_version(vk::make_api_version(0, 1, 0, 0))
-            .api_version(vk::API_VERSION_1_3);
-        
-        let mut extensions = ash_window::enumerate_required_extensions(display_handle)?.to_vec();
-        extensions.push(khr::surface::NAME.as_ptr());
-        
-        let layer_names: Vec<CString> = if ENABLE_VALIDATION_LAYERS {
-            vec![CString::new("VK_LAYER_KHRONOS_validation")?]
-        } else {
-            vec![]
-        };
-        let layer_names_raw: Vec<*const i8> = layer_names
-            .iter()
-            .map(|raw_name| raw_name.as_ptr())
-            .collect();
-        
-        let create_info = vk::InstanceCreateInfo::default()
-            .application_info(&app_info)
-            .enabled_extension_names(&extensions)
-            .enabled_layer_names(&layer_names_raw);
-        
-        let instance = unsafe { entry.create_instance(&create_info, None)? };
-        
-        let surface = unsafe {
-            ash_window::create_surface(&entry, &instance, display_handle, window_handle, None)?
-        };
-        let surface_loader = khr::surface::Instance::new(&entry, &instance);
-        
-        let (physical_device, indices) = pick_physical_device(&instance, &surface_loader, surface)?;
-        let device = create_logical_device(&instance, physical_device, &indices, false)?;
-        
-        let graphics_queue = unsafe { device.get_device_queue(indices.graphics_family.unwrap(), 0) };
-        let present_queue = unsafe { device.get_device_queue(indices.present_family.unwrap(), 0) };
-        
-        let swapchain_loader = khr::swapchain::Device::new(&instance, &device);
-        let (swapchain, swapchain_images, swapchain_format, swapchain_extent) = 
-            create_swapchain(&instance, &surface_loader, surface, physical_device, &swapchain_loader, &indices)?;
-        let swapchain_image_views = create_image_views(&device, &swapchain_images, swapchain_format)?;
-        
-        let (depth_image, depth_image_memory, depth_image_view) = if with_depth {
-            create_depth_resources(&instance, &device, physical_device, swapchain_extent)?
-        } else {
-            (vk::Image::null(), vk::DeviceMemory::null(), vk::ImageView::null())
+// Instance, device and queues one or more VulkanCores are created on. Every core holds one and
+// hands out clones with VulkanCore::shared_device, so a second window (a thumbnail view, a tool
+// window) can get its swapchain and resources from the same device through
+// CoreOptions::shared_device instead of creating its own. The device and instance are destroyed
+// with the last core or clone holding them. Vulkan queues aren't thread safe, so cores sharing
+// a device have to submit from one thread at a time.
+#[derive(Clone)]
+pub struct SharedDevice {
+    inner: Arc<DeviceOwner>,
+}
+
+struct DeviceOwner {
+    entry: Entry,
+    instance: Instance,
+    physical_device: vk::PhysicalDevice,
+    device: ash::Device,
+    queue_family_indices: QueueFamilyIndices,
+    graphics_queue: vk::Queue,
+    present_queue: vk::Queue,
+}
+
+impl SharedDevice {
+    pub fn device(&self) -> &ash::Device {
+        &self.inner.device
+    }
+    
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.inner.physical_device
+    }
+    
+    // Number of cores and clones holding the device, it's destroyed when this drops to 0
+    pub fn holder_count(&self) -> usize {
+        Arc::strong_count(&self.inner)
+    }
+}
+
+impl std::fmt::Debug for SharedDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedDevice")
+            .field("device", &self.inner.device.handle())
+            .field("physical_device", &self.inner.physical_device)
+            .finish()
+    }
+}
+
+// Every core on the device has destroyed its objects by now, so leaks are checked here and not
+// per core
+impl Drop for DeviceOwner {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            leak_check::assert_no_leaks(&self.device);
+            // A later device can get the same handle
+            if let Ok(mut build_times) = pipeline_build_times().lock() {
+                build_times.remove(&self.device.handle().as_raw());
+            }
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+// Choices made when creating a VulkanCore
+#[derive(Clone, Debug)]
+pub struct CoreOptions {
+    // GPU to create the device on, defaults to the one in FLO_GPU or the best scoring one
+    pub device: DeviceSelection,
+    // Usages for the swapchain images on top of COLOR_ATTACHMENT, e.g. TRANSFER_SRC to read
+    // the presented image back or STORAGE for compute post processing. Usages the surface
+    // doesn't support are dropped with a warning, see VulkanCore::swapchain_image_usage.
+    pub extra_swapchain_usage: vk::ImageUsageFlags,
+    // Pick a depth format with a stencil aspect, for pipelines with PipelineBuilder::with_stencil.
+    // Only applies with depth, creating the core fails if the device has no such format.
+    pub stencil: bool,
+    // Can be changed later with VulkanCore::set_present_mode
+    pub present_mode: PresentMode,
+    // Creates the core on another core's device instead of a new instance and device, see
+    // SharedDevice. device is ignored then. Creating the core fails if the device's present
+    // queue can't present to the new window's surface.
+    pub shared_device: Option<SharedDevice>,
+}
+
+impl Default for CoreOptions {
+    fn default() -> Self {
+        Self {
+            device: DeviceSelection::from_env(),
+            extra_swapchain_usage: vk::ImageUsageFlags::empty(),
+            stencil: false,
+            present_mode: PresentMode::default(),
+            shared_device: None,
+        }
+    }
+}
+
+// The window a surface is created for: Bevy's RawHandleWrapperHolder, or the raw handles of a
+// window created some other way, so the renderer's constructors work with and without Bevy
+pub trait WindowHandles {
+    fn raw_handles(&self) -> Result<(RawDisplayHandle, RawWindowHandle), FloError>;
+}
+
+#[cfg(feature = "bevy")]
+impl WindowHandles for RawHandleWrapperHolder {
+    fn raw_handles(&self) -> Result<(RawDisplayHandle, RawWindowHandle), FloError> {
+        let raw_handle = self.0.lock().unwrap();
+        let raw_handle_ref = raw_handle.as_ref().ok_or("Window handle not available")?;
+        Ok((raw_handle_ref.get_display_handle(), raw_handle_ref.get_window_handle()))
+    }
+}
+
+impl WindowHandles for (RawDisplayHandle, RawWindowHandle) {
+    fn raw_handles(&self) -> Result<(RawDisplayHandle, RawWindowHandle), FloError> {
+        Ok(*self)
+    }
+}
+
+impl VulkanCore {
+    pub fn new(
+        handle_wrapper: &impl WindowHandles,
+        with_depth: bool,
+    ) -> Result<Self, FloError> {
+        Self::new_with_options(handle_wrapper, with_depth, &CoreOptions::default())
+    }
+    
+    pub fn new_with_options(
+        handle_wrapper: &impl WindowHandles,
+        with_depth: bool,
+        options: &CoreOptions,
+    ) -> Result<Self, FloError> {
+        let (display_handle, window_handle) = handle_wrapper.raw_handles()?;
+        Self::from_raw_handles_with_options(display_handle, window_handle, with_depth, options)
+    }
+    
+    // For windows not created by Bevy, e.g. winit or SDL used directly. The window must
+    // outlive the VulkanCore since the surface is created from it.
+    pub fn from_raw_handles(
+        display_handle: RawDisplayHandle,
+        window_handle: RawWindowHandle,
+        with_depth: bool,
+    ) -> Result<Self, FloError> {
+        Self::from_raw_handles_with_options(display_handle, window_handle, with_depth, &CoreOptions::default())
+    }
+    
+    pub fn from_raw_handles_with_options(
+        display_handle: RawDisplayHandle,
+        window_handle: RawWindowHandle,
+        with_depth: bool,
+        options: &CoreOptions,
+    ) -> Result<Self, FloError> {
+        let (device_owner, surface, surface_loader) = match options.shared_device {
+            Some(ref shared) => {
+                let owner = &shared.inner;
+                let surface = unsafe {
+                    ash_window::create_surface(&owner.entry, &owner.instance, display_handle, window_handle, None)?
+                };
+                let surface_loader = khr::surface::Instance::new(&owner.entry, &owner.instance);
+                let present_family = owner.queue_family_indices.present_family.unwrap();
+                let can_present = unsafe {
+                    surface_loader.get_physical_device_surface_support(owner.physical_device, present_family, surface)
+                };
+                if !can_present.unwrap_or(false) {
+                    unsafe { surface_loader.destroy_surface(surface, None) };
+                    return Err(FloError::UnsupportedDevice(
+                        "The shared device's present queue can't present to this window".to_string(),
+                    ));
+                }
+                (shared.clone(), surface, surface_loader)
+            }
+            None => {
+                let entry = load_vulkan_entry()?;
+                
+                let app_name = CString::new("Vulkan Bevy Renderer")?;
+                let engine_name = CString::new("No Engine")?;
+                
+                let app_info = vk::ApplicationInfo::default()
+                    .application_name(&app_name)
+                    .application_version(vk::make_api_version(0, 1, 0, 0))
+                    .engine_name(&engine_name)
+                    .engine_version(vk::make_api_version(0, 1, 0, 0))
+                    .api_version(vk::API_VERSION_1_3);
+                
+                let mut extensions = surface_extensions(display_handle)?;
+                if ENABLE_VALIDATION_LAYERS {
+                    extensions.push(debug_utils::NAME.as_ptr());
+                }
+                
+                let layer_names: Vec<CString> = if ENABLE_VALIDATION_LAYERS {
+                    vec![CString::new("VK_LAYER_KHRONOS_validation")?]
+                } else {
+                    vec![]
+                };
+                let layer_names_raw: Vec<*const i8> = layer_names
+                    .iter()
+                    .map(|raw_name| raw_name.as_ptr())
+                    .collect();
+                
+                let create_info = vk::InstanceCreateInfo::default()
+                    .application_info(&app_info)
+                    .enabled_extension_names(&extensions)
+                    .enabled_layer_names(&layer_names_raw);
+                
+                let instance = unsafe { entry.create_instance(&create_info, None) }.map_err(instance_creation_error)?;
+                
+                let surface = unsafe {
+                    ash_window::create_surface(&entry, &instance, display_handle, window_handle, None)?
+                };
+                let surface_loader = khr::surface::Instance::new(&entry, &instance);
+                
+                let (physical_device, indices) = pick_physical_device(&instance, &surface_loader, surface, &options.device)?;
+                let device = create_logical_device(&instance, physical_device, &indices, anisotropy_supported(&instance, physical_device))?;
+                
+                let graphics_queue = unsafe { device.get_device_queue(indices.graphics_family.unwrap(), 0) };
+                let present_queue = unsafe { device.get_device_queue(indices.present_family.unwrap(), 0) };
+                
+                let owner = DeviceOwner {
+                    entry,
+                    instance,
+                    physical_device,
+                    device,
+                    queue_family_indices: indices,
+                    graphics_queue,
+                    present_queue,
+                };
+                (SharedDevice { inner: Arc::new(owner) }, surface, surface_loader)
+            }
         };
+        let entry = device_owner.inner.entry.clone();
+        let instance = device_owner.inner.instance.clone();
+        let physical_device = device_owner.inner.physical_device;
+        let device = device_owner.inner.device.clone();
+        let indices = device_owner.inner.queue_family_indices;
+        let graphics_queue = device_owner.inner.graphics_queue;
+        let present_queue = device_owner.inner.present_queue;
         
-        let render_pass = create_render_pass(&instance, &device, physical_device, swapchain_format, with_depth)?;
+        let swapchain_loader = khr::swapchain::Device::new(&instance, &device);
+        let swapchain_image_usage =
+            supported_swapchain_usage(&surface_loader, surface, physical_device, options.extra_swapchain_usage)?;
+        let (swapchain, swapchain_images, swapchain_format, swapchain_extent) = 
+            create_swapchain(&instance, &surface_loader, surface, physical_device, &swapchain_loader, &indices, swapchain_image_usage, options.present_mode)?;
+        let swapchain_image_views = create_image_views(&device, &swapchain_images, swapchain_format)?;
         
-        let framebuffers = if with_depth {
-            create_framebuffers(&device, &swapchain_image_views, depth_image_view, render_pass, swapchain_extent)?
+        let depth_format = if with_depth {
+            Some(find_depth_format(&instance, physical_device, options.stencil)?)
         } else {
-            create_framebuffers_no_depth(&device, &swapchain_image_views, render_pass, swapchain_extent)?
+            None
         };
         
+        let render_pass = create_render_pass(&device, HDR_FORMAT, depth_format)?;
+        let load_render_pass = create_render_pass_with_load_op(&device, HDR_FORMAT, depth_format, vk::AttachmentLoadOp::LOAD)?;
+        
+        let scene_targets = create_scene_targets(
+            &instance,
+            &device,
+            physical_device,
+            depth_format,
+            render_pass,
+            swapchain_images.len(),
+            swapchain_extent,
+        )?;
+        
+        let present = PresentPass::new(
+            &device,
+            swapchain_format,
+            &swapchain_image_views,
+            &scene_targets.hdr_image_views,
+            swapchain_extent,
+        )?;
+        
         let command_pool = create_command_pool(&device, indices.graphics_family.unwrap())?;
         let command_buffers = create_command_buffers(&device, command_pool, swapchain_images.len())?;
         
         let (image_available_semaphores, render_finished_semaphores, in_flight_fences) = 
             create_sync_objects(&device)?;
         
-        Ok(Self {
+        let occlusion_query_pools = create_occlusion_query_pools(&device)?;
+        let timestamp_query_pools = create_timestamp_query_pools(&device)?;
+        let timestamp_support = timestamp_support(&instance, physical_device, indices.graphics_family.unwrap());
+        let capabilities = DeviceCapabilities::query(&instance, physical_device, &surface_loader, surface)?;
+        
+        let debug_utils = if ENABLE_VALIDATION_LAYERS {
+            Some(debug_utils::Device::new(&instance, &device))
+        } else {
+            None
+        };
+        
+        let core = Self {
             _entry: entry,
             instance,
             surface,
@@ -947,24 +1913,156 @@ impl VulkanCore {
             swapchain_images,
             swapchain_format,
             swapchain_extent,
+            swapchain_image_usage,
+            present_mode: options.present_mode,
             swapchain_image_views,
-            depth_image,
-            depth_image_memory,
-            depth_image_view,
+            render_extent: swapchain_extent,
+            depth_format,
+            depth_image: scene_targets.depth_image,
+            depth_image_memory: scene_targets.depth_image_memory,
+            depth_image_view: scene_targets.depth_image_view,
+            hdr_images: scene_targets.hdr_images,
+            hdr_image_memories: scene_targets.hdr_image_memories,
+            hdr_image_views: scene_targets.hdr_image_views,
             render_pass,
-            framebuffers,
+            load_render_pass,
+            framebuffers: scene_targets.framebuffers,
+            present,
             command_pool,
             command_buffers,
             image_available_semaphores,
             render_finished_semaphores,
             in_flight_fences,
             current_frame: 0,
-            start_time: Instant::now(),
+            current_image_index: 0,
+            clock: SceneClock::default(),
             queue_family_indices: indices,
-        })
+            occlusion_query_pools,
+            timestamp_query_pools,
+            timestamp_support,
+            timestamps_written: [false; MAX_FRAMES_IN_FLIGHT],
+            gpu_frame_time_ms: None,
+            debug_utils,
+            lost: None,
+            max_anisotropy: 16.0,
+            frame_pacer: FramePacer::default(),
+            capabilities,
+            device_owner,
+        };
+        
+        for (i, &image) in core.swapchain_images.iter().enumerate() {
+            core.set_debug_name(image, &format!("swapchain image[{}]", i));
+        }
+        core.name_scene_targets();
+        core.set_debug_name(core.render_pass, "main render pass");
+        core.set_debug_name(core.load_render_pass, "main load render pass");
+        core.set_debug_name(core.present.render_pass, "present render pass");
+        core.set_debug_name(core.present.pipeline, "tonemap pipeline");
+        
+        Ok(core)
+    }
+    
+    // The device this core was created on, to create another core (another window's renderer)
+    // on it through CoreOptions::shared_device
+    pub fn shared_device(&self) -> SharedDevice {
+        self.device_owner.clone()
+    }
+    
+    fn name_scene_targets(&self) {
+        if self.depth_format.is_some() {
+            self.set_debug_name(self.depth_image, "depth image");
+        }
+        for (i, &image) in self.hdr_images.iter().enumerate() {
+            self.set_debug_name(image, &format!("hdr image[{}]", i));
+        }
+    }
+    
+    // Recreates the depth buffer, HDR images and scene framebuffers at the new size, e.g. to
+    // render the scene below the swapchain resolution, and points the present pass at the new
+    // HDR images. Waits for the device to go idle. Anything else sampling the HDR images or
+    // recorded against the framebuffers has to be updated by the caller.
+    pub fn set_render_extent(&mut self, extent: vk::Extent2D) -> Result<(), FloError> {
+        if extent == self.render_extent {
+            return Ok(());
+        }
+        if let Some(lost) = self.lost {
+            return Err(lost.into());
+        }
+        unsafe {
+            self.device.device_wait_idle()?;
+        }
+        let scene_targets = create_scene_targets(
+            &self.instance,
+            &self.device,
+            self.physical_device,
+            self.depth_format,
+            self.render_pass,
+            self.swapchain_images.len(),
+            extent,
+        )?;
+        self.destroy_scene_targets();
+        
+        self.render_extent = extent;
+        self.depth_image = scene_targets.depth_image;
+        self.depth_image_memory = scene_targets.depth_image_memory;
+        self.depth_image_view = scene_targets.depth_image_view;
+        self.hdr_images = scene_targets.hdr_images;
+        self.hdr_image_memories = scene_targets.hdr_image_memories;
+        self.hdr_image_views = scene_targets.hdr_image_views;
+        self.framebuffers = scene_targets.framebuffers;
+        self.present.set_sources(&self.device, &self.hdr_image_views);
+        self.name_scene_targets();
+        Ok(())
+    }
+    
+    fn destroy_scene_targets(&mut self) {
+        unsafe {
+            for framebuffer in self.framebuffers.drain(..) {
+                leak_check::destroy(&self.device, framebuffer);
+            }
+        }
+        for i in 0..self.hdr_images.len() {
+            destroy_image(&self.device, self.hdr_images[i], self.hdr_image_memories[i], self.hdr_image_views[i]);
+        }
+        self.hdr_images.clear();
+        self.hdr_image_memories.clear();
+        self.hdr_image_views.clear();
+        if self.depth_image_view != vk::ImageView::null() {
+            destroy_image(&self.device, self.depth_image, self.depth_image_memory, self.depth_image_view);
+            self.depth_image = vk::Image::null();
+            self.depth_image_memory = vk::DeviceMemory::null();
+            self.depth_image_view = vk::ImageView::null();
+        }
+    }
+    
+    // Labels a Vulkan object so validation messages name it instead of printing a bare handle
+    pub fn set_debug_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        if let Some(ref debug_utils) = self.debug_utils {
+            let name = match CString::new(name) {
+                Ok(name) => name,
+                Err(_) => return,
+            };
+            
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+                .object_handle(handle)
+                .object_name(&name);
+            unsafe {
+                if let Err(e) = debug_utils.set_debug_utils_object_name(&name_info) {
+                    warn!("Failed to set debug name {:?}: {}", name, e);
+                }
+            }
+        }
+    }
+    
+    pub fn begin_frame(&mut self) -> Result<u32, FloError> {
+        if let Some(result) = self.lost {
+            return Err(result.into());
+        }
+        let image_index = self.acquire_frame();
+        self.note_loss(image_index)
     }
     
-    pub fn begin_frame(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
+    fn acquire_frame(&mut self) -> Result<u32, FloError> {
         unsafe {
             self.device.wait_for_fences(
                 &[self.in_flight_fences[self.current_frame]], 
@@ -979,6 +2077,7 @@ impl VulkanCore {
                 vk::Fence::null(),
             )?;
             
+            self.current_image_index = image_index;
             self.device.reset_fences(&[self.in_flight_fences[self.current_frame]])?;
             self.device.reset_command_buffer(
                 self.command_buffers[image_index as usize],
@@ -989,7 +2088,13 @@ impl VulkanCore {
         }
     }
     
-    pub fn end_frame(&mut self, image_index: u32) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn end_frame(&mut self, image_index: u32) -> Result<(), FloError> {
+        let submitted = self.submit_frame(image_index);
+        self.frame_pacer.wait();
+        self.note_loss(submitted)
+    }
+    
+    fn submit_frame(&mut self, image_index: u32) -> Result<(), FloError> {
         unsafe {
             let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
             let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
@@ -1023,68 +2128,411 @@ impl VulkanCore {
         Ok(())
     }
     
+    // Remembers a surface or device loss so later frames don't record into a dead swapchain
+    fn note_loss<T>(&mut self, result: Result<T, FloError>) -> Result<T, FloError> {
+        if let Err(ref e) = result {
+            if e.is_lost() && self.lost.is_none() {
+                warn!("{}, skipping frames until it's recovered", e);
+                self.lost = Some(match e {
+                    FloError::SurfaceLost => vk::Result::ERROR_SURFACE_LOST_KHR,
+                    _ => vk::Result::ERROR_DEVICE_LOST,
+                });
+            }
+        }
+        result
+    }
+    
+    // Recreates the surface for the same window after ERROR_SURFACE_LOST_KHR, then the swapchain,
+    // its image views and the present pass framebuffers. Everything else (HDR targets, pipelines,
+    // meshes) is kept, so the new swapchain has to match the old one's image count, format and
+    // extent, otherwise this errors and the renderer has to be recreated. A lost device can't be
+    // recovered here.
+    pub fn recreate_surface(
+        &mut self,
+        display_handle: RawDisplayHandle,
+        window_handle: RawWindowHandle,
+    ) -> Result<(), FloError> {
+        if self.lost == Some(vk::Result::ERROR_DEVICE_LOST) {
+            return Err(FloError::DeviceLost);
+        }
+        
+        self.destroy_swapchain();
+        unsafe {
+            self.surface_loader.destroy_surface(self.surface, None);
+            self.surface = vk::SurfaceKHR::null();
+            
+            self.surface = ash_window::create_surface(&self._entry, &self.instance, display_handle, window_handle, None)?;
+            let present_family = self.queue_family_indices.present_family.unwrap();
+            if !self.surface_loader.get_physical_device_surface_support(self.physical_device, present_family, self.surface)? {
+                return Err(FloError::NoSuitableGpu);
+            }
+        }
+        self.capabilities.present_modes = supported_present_modes(&self.surface_loader, self.surface, self.physical_device)?;
+        
+        self.rebuild_swapchain(false)?;
+        self.lost = None;
+        Ok(())
+    }
+    
+    // Recreates the swapchain with the new mode, e.g. Fifo for vsync. The same restrictions as
+    // recreate_surface apply, the image count, format and extent have to stay the same.
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) -> Result<(), FloError> {
+        if present_mode == self.present_mode {
+            return Ok(());
+        }
+        if let Some(lost) = self.lost {
+            return Err(lost.into());
+        }
+        self.present_mode = present_mode;
+        self.destroy_swapchain();
+        self.rebuild_swapchain(false)
+    }
+    
+    // Recreates the swapchain at the surface's current size after the window was resized. The
+    // scene targets keep their extent, follow with set_render_extent. A zero sized (minimized)
+    // window can't have a swapchain, wait until it's restored.
+    pub fn resize_swapchain(&mut self) -> Result<(), FloError> {
+        if let Some(lost) = self.lost {
+            return Err(lost.into());
+        }
+        self.destroy_swapchain();
+        self.rebuild_swapchain(true)
+    }
+    
+    // Handles are cleared as they're destroyed, so Drop is still safe if recreating fails
+    fn destroy_swapchain(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            for framebuffer in self.present.framebuffers.drain(..) {
+                leak_check::destroy(&self.device, framebuffer);
+            }
+            for image_view in self.swapchain_image_views.drain(..) {
+                leak_check::destroy(&self.device, image_view);
+            }
+            self.swapchain_loader.destroy_swapchain(self.swapchain, None);
+            self.swapchain = vk::SwapchainKHR::null();
+        }
+    }
+    
+    // Swapchain, image views, present framebuffers and sync objects for the current surface.
+    // Only a resize may change the extent.
+    fn rebuild_swapchain(&mut self, resize: bool) -> Result<(), FloError> {
+        let (swapchain, swapchain_images, swapchain_format, swapchain_extent) = create_swapchain(
+            &self.instance,
+            &self.surface_loader,
+            self.surface,
+            self.physical_device,
+            &self.swapchain_loader,
+            &self.queue_family_indices,
+            self.swapchain_image_usage,
+            self.present_mode,
+        )?;
+        self.swapchain = swapchain;
+        if swapchain_images.len() != self.swapchain_images.len()
+            || swapchain_format != self.swapchain_format
+            || (!resize && swapchain_extent != self.swapchain_extent)
+        {
+            return Err(format!(
+                "Recreated swapchain has {} {:?} images of {}x{} instead of {} {:?} images of {}x{}, recreate the renderer",
+                swapchain_images.len(), swapchain_format, swapchain_extent.width, swapchain_extent.height,
+                self.swapchain_images.len(), self.swapchain_format, self.swapchain_extent.width, self.swapchain_extent.height,
+            ).into());
+        }
+        self.swapchain_images = swapchain_images;
+        self.swapchain_extent = swapchain_extent;
+        self.swapchain_image_views = create_image_views(&self.device, &self.swapchain_images, swapchain_format)?;
+        self.present.framebuffers = create_framebuffers_no_depth(
+            &self.device,
+            &self.swapchain_image_views,
+            self.present.render_pass,
+            swapchain_extent,
+        )?;
+        
+        // The failed acquire or present can leave semaphores signaled that nothing will wait on
+        let (image_available_semaphores, render_finished_semaphores, in_flight_fences) = create_sync_objects(&self.device)?;
+        unsafe {
+            for i in 0..MAX_FRAMES_IN_FLIGHT {
+                leak_check::destroy(&self.device, self.image_available_semaphores[i]);
+                leak_check::destroy(&self.device, self.render_finished_semaphores[i]);
+                leak_check::destroy(&self.device, self.in_flight_fences[i]);
+            }
+        }
+        self.image_available_semaphores = image_available_semaphores;
+        self.render_finished_semaphores = render_finished_semaphores;
+        self.in_flight_fences = in_flight_fences;
+        self.current_frame = 0;
+        
+        for (i, &image) in self.swapchain_images.iter().enumerate() {
+            self.set_debug_name(image, &format!("swapchain image[{}]", i));
+        }
+        Ok(())
+    }
+    
+    // The GPU the device was created on
+    pub fn device_info(&self) -> Result<DeviceInfo, FloError> {
+        let devices = unsafe { self.instance.enumerate_physical_devices()? };
+        let index = devices.iter().position(|&device| device == self.physical_device).unwrap_or(0);
+        Ok(DeviceInfo::query(&self.instance, index, self.physical_device))
+    }
+    
+    // Scaled animation time in seconds, stands still while paused
     pub fn get_elapsed_time(&self) -> f32 {
-        self.start_time.elapsed().as_secs_f32()
+        self.clock.elapsed()
+    }
+    
+    pub fn pause(&mut self) {
+        self.clock.pause();
+    }
+    
+    pub fn resume(&mut self) {
+        self.clock.resume();
+    }
+    
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.clock.set_scale(scale);
+    }
+    
+    pub fn reset_time(&mut self) {
+        self.clock.reset();
+    }
+    
+    // Sample counts for the first query_count queries of the current frame's pool.
+    // Only valid after begin_frame has waited on this frame's fence. Returns None if the
+    // results aren't available (e.g. the frame was never submitted) rather than blocking.
+    pub fn read_occlusion_query_results(&self, query_count: u32) -> Option<Vec<u64>> {
+        if query_count == 0 {
+            return Some(Vec::new());
+        }
+        
+        let mut results = vec![0u64; query_count.min(MAX_OCCLUSION_QUERIES) as usize];
+        unsafe {
+            self.device.get_query_pool_results(
+                self.occlusion_query_pools[self.current_frame],
+                0,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64,
+            ).ok()?;
+        }
+        Some(results)
+    }
+    
+    // Starts timing the current frame on the GPU. Call right after beginning the frame's command
+    // buffer and outside a render pass. Reads the time of the last frame that used this frame's
+    // queries first, so gpu_frame_time_ms lags MAX_FRAMES_IN_FLIGHT frames behind.
+    pub fn cmd_begin_gpu_timer(&mut self, command_buffer: vk::CommandBuffer) {
+        let Some((period, valid_bits)) = self.timestamp_support else {
+            return;
+        };
+        let pool = self.timestamp_query_pools[self.current_frame];
+        
+        if self.timestamps_written[self.current_frame] {
+            let mut timestamps = [0u64; 2];
+            let read = unsafe {
+                self.device.get_query_pool_results(pool, 0, &mut timestamps, vk::QueryResultFlags::TYPE_64)
+            };
+            if read.is_ok() {
+                // Timestamps wrap around at valid_bits
+                let mask = if valid_bits >= 64 { u64::MAX } else { (1u64 << valid_bits) - 1 };
+                let ticks = timestamps[1].wrapping_sub(timestamps[0]) & mask;
+                self.gpu_frame_time_ms = Some((ticks as f64 * f64::from(period) / 1_000_000.0) as f32);
+            }
+        }
+        
+        unsafe {
+            self.device.cmd_reset_query_pool(command_buffer, pool, 0, 2);
+            self.device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, pool, 0);
+        }
+    }
+    
+    // Ends timing the current frame, call right before ending its command buffer
+    pub fn cmd_end_gpu_timer(&mut self, command_buffer: vk::CommandBuffer) {
+        if self.timestamp_support.is_none() {
+            return;
+        }
+        let pool = self.timestamp_query_pools[self.current_frame];
+        unsafe {
+            self.device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, pool, 1);
+        }
+        self.timestamps_written[self.current_frame] = true;
     }
 }
 
+// Runs after VulkanRenderer's Drop, since the core is one of its fields. The swapchain goes
+// before the device, and the surface after it but before the instance.
 impl Drop for VulkanCore {
     fn drop(&mut self) {
         unsafe {
             let _ = self.device.device_wait_idle();
             
             for i in 0..MAX_FRAMES_IN_FLIGHT {
-                self.device.destroy_semaphore(self.image_available_semaphores[i], None);
-                self.device.destroy_semaphore(self.render_finished_semaphores[i], None);
-                self.device.destroy_fence(self.in_flight_fences[i], None);
+                leak_check::destroy(&self.device, self.image_available_semaphores[i]);
+                leak_check::destroy(&self.device, self.render_finished_semaphores[i]);
+                leak_check::destroy(&self.device, self.in_flight_fences[i]);
             }
             
-            self.device.destroy_command_pool(self.command_pool, None);
-            
-            for &framebuffer in &self.framebuffers {
-                self.device.destroy_framebuffer(framebuffer, None);
+            for &pool in &self.occlusion_query_pools {
+                leak_check::destroy(&self.device, pool);
+            }
+            for &pool in &self.timestamp_query_pools {
+                leak_check::destroy(&self.device, pool);
             }
             
-            self.device.destroy_render_pass(self.render_pass, None);
+            leak_check::destroy(&self.device, self.command_pool);
             
-            if self.depth_image_view != vk::ImageView::null() {
-                self.device.destroy_image_view(self.depth_image_view, None);
-                self.device.destroy_image(self.depth_image, None);
-                self.device.free_memory(self.depth_image_memory, None);
-            }
+            self.present.destroy(&self.device);
+            self.destroy_scene_targets();
+            
+            leak_check::destroy(&self.device, self.render_pass);
+            leak_check::destroy(&self.device, self.load_render_pass);
             
             for &image_view in &self.swapchain_image_views {
-                self.device.destroy_image_view(image_view, None);
+                leak_check::destroy(&self.device, image_view);
             }
             
             self.swapchain_loader.destroy_swapchain(self.swapchain, None);
-            self.device.destroy_device(None);
             self.surface_loader.destroy_surface(self.surface, None);
-            self.instance.destroy_instance(None);
+            // The device and instance go with the last holder of device_owner, dropped after this
         }
     }
 }
 
 // Helper functions for common cleanup patterns
+// What a device memory allocation is used for, for memory accounting
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryUsage {
+    Vertex,
+    Index,
+    Uniform,
+    Texture,
+    Staging,
+    Attachment,  // Depth and other render targets
+    Pool,        // Chunks owned by MemoryPoolManager, sub-allocated separately
+    Other,
+}
+
+impl MemoryUsage {
+    pub fn from_buffer_usage(usage: vk::BufferUsageFlags) -> Self {
+        if usage.contains(vk::BufferUsageFlags::VERTEX_BUFFER) {
+            MemoryUsage::Vertex
+        } else if usage.contains(vk::BufferUsageFlags::INDEX_BUFFER) {
+            MemoryUsage::Index
+        } else if usage.contains(vk::BufferUsageFlags::UNIFORM_BUFFER) {
+            MemoryUsage::Uniform
+        } else if usage == vk::BufferUsageFlags::TRANSFER_SRC {
+            MemoryUsage::Staging
+        } else {
+            MemoryUsage::Other
+        }
+    }
+}
+
+// Bytes of device memory currently allocated, by usage
+#[derive(Clone, Debug, Default)]
+pub struct MemoryReport {
+    pub vertex: vk::DeviceSize,
+    pub index: vk::DeviceSize,
+    pub uniform: vk::DeviceSize,
+    pub texture: vk::DeviceSize,
+    pub staging: vk::DeviceSize,
+    pub attachment: vk::DeviceSize,
+    pub pool: vk::DeviceSize,
+    pub other: vk::DeviceSize,
+    pub allocation_count: usize,
+    // Sets from the renderer's descriptor allocator, filled in by VulkanRenderer::memory_report
+    pub descriptors: DescriptorUsage,
+}
+
+impl MemoryReport {
+    pub fn total(&self) -> vk::DeviceSize {
+        self.vertex + self.index + self.uniform + self.texture + self.staging + self.attachment + self.pool + self.other
+    }
+}
+
+impl std::fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mb = |bytes: vk::DeviceSize| bytes as f64 / (1024.0 * 1024.0);
+        write!(
+            f,
+            "{} allocations, {:.2} MB total (vertex {:.2}, index {:.2}, uniform {:.2}, texture {:.2}, staging {:.2}, attachment {:.2}, pool {:.2}, other {:.2})",
+            self.allocation_count, mb(self.total()), mb(self.vertex), mb(self.index), mb(self.uniform),
+            mb(self.texture), mb(self.staging), mb(self.attachment), mb(self.pool), mb(self.other),
+        )?;
+        write!(
+            f,
+            ", {} descriptor sets (peak {}) in {} pool blocks",
+            self.descriptors.sets, self.descriptors.peak_sets, self.descriptors.pool_blocks,
+        )
+    }
+}
+
+// Live allocations, keyed by handle so frees don't need to know the size or usage.
+// Process wide, so with several renderers the report covers all of them.
+fn memory_allocations() -> &'static Mutex<HashMap<vk::DeviceMemory, (MemoryUsage, vk::DeviceSize)>> {
+    static ALLOCATIONS: OnceLock<Mutex<HashMap<vk::DeviceMemory, (MemoryUsage, vk::DeviceSize)>>> = OnceLock::new();
+    ALLOCATIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// allocate_memory that records the allocation in the memory report.
+// All device memory should go through this and free_device_memory.
+pub unsafe fn allocate_device_memory(
+    device: &ash::Device,
+    alloc_info: &vk::MemoryAllocateInfo,
+    usage: MemoryUsage,
+) -> Result<vk::DeviceMemory, vk::Result> {
+    let memory = leak_check::created(device, device.allocate_memory(alloc_info, None)?);
+    if let Ok(mut allocations) = memory_allocations().lock() {
+        allocations.insert(memory, (usage, alloc_info.allocation_size));
+    }
+    Ok(memory)
+}
+
+pub unsafe fn free_device_memory(device: &ash::Device, memory: vk::DeviceMemory) {
+    if let Ok(mut allocations) = memory_allocations().lock() {
+        allocations.remove(&memory);
+    }
+    leak_check::destroy(device, memory);
+}
+
+pub fn memory_report() -> MemoryReport {
+    let mut report = MemoryReport::default();
+    if let Ok(allocations) = memory_allocations().lock() {
+        report.allocation_count = allocations.len();
+        for &(usage, size) in allocations.values() {
+            let total = match usage {
+                MemoryUsage::Vertex => &mut report.vertex,
+                MemoryUsage::Index => &mut report.index,
+                MemoryUsage::Uniform => &mut report.uniform,
+                MemoryUsage::Texture => &mut report.texture,
+                MemoryUsage::Staging => &mut report.staging,
+                MemoryUsage::Attachment => &mut report.attachment,
+                MemoryUsage::Pool => &mut report.pool,
+                MemoryUsage::Other => &mut report.other,
+            };
+            *total += size;
+        }
+    }
+    report
+}
+
 pub fn destroy_buffer(device: &ash::Device, buffer: vk::Buffer, memory: vk::DeviceMemory) {
     unsafe {
-        device.destroy_buffer(buffer, None);
-        device.free_memory(memory, None);
+        leak_check::destroy(device, buffer);
+        free_device_memory(device, memory);
     }
 }
 
 pub fn destroy_image(device: &ash::Device, image: vk::Image, memory: vk::DeviceMemory, view: vk::ImageView) {
     unsafe {
-        device.destroy_image_view(view, None);
-        device.destroy_image(image, None);
-        device.free_memory(memory, None);
+        leak_check::destroy(device, view);
+        leak_check::destroy(device, image);
+        free_device_memory(device, memory);
     }
 }
 
 pub fn destroy_pipeline(device: &ash::Device, pipeline: vk::Pipeline, layout: vk::PipelineLayout) {
     unsafe {
-        device.destroy_pipeline(pipeline, None);
-        device.destroy_pipeline_layout(layout, None);
+        leak_check::destroy(device, pipeline);
+        leak_check::destroy(device, layout);
     }
 }
 
@@ -1100,23 +2548,23 @@ pub fn create_descriptor_pool(
     device: &ash::Device,
     max_sets: u32,
     pool_sizes: &[vk::DescriptorPoolSize],
-) -> Result<vk::DescriptorPool, Box<dyn std::error::Error>> {
+) -> Result<vk::DescriptorPool, FloError> {
     let pool_info = vk::DescriptorPoolCreateInfo::default()
         .pool_sizes(pool_sizes)
         .max_sets(max_sets);
     
-    let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+    let descriptor_pool = leak_check::created(device, unsafe { device.create_descriptor_pool(&pool_info, None)? });
     Ok(descriptor_pool)
 }
 
 pub fn create_descriptor_set_layout(
     device: &ash::Device,
     bindings: &[vk::DescriptorSetLayoutBinding],
-) -> Result<vk::DescriptorSetLayout, Box<dyn std::error::Error>> {
+) -> Result<vk::DescriptorSetLayout, FloError> {
     let layout_info = vk::DescriptorSetLayoutCreateInfo::default()
         .bindings(bindings);
     
-    let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+    let set_layout = leak_check::created(device, unsafe { device.create_descriptor_set_layout(&layout_info, None)? });
     Ok(set_layout)
 }
 
@@ -1124,7 +2572,7 @@ pub fn allocate_descriptor_sets(
     device: &ash::Device,
     descriptor_pool: vk::DescriptorPool,
     set_layouts: &[vk::DescriptorSetLayout],
-) -> Result<Vec<vk::DescriptorSet>, Box<dyn std::error::Error>> {
+) -> Result<Vec<vk::DescriptorSet>, FloError> {
     let alloc_info = vk::DescriptorSetAllocateInfo::default()
         .descriptor_pool(descriptor_pool)
         .set_layouts(set_layouts);
@@ -1163,7 +2611,7 @@ pub fn create_framebuffers_no_depth(
     image_views: &[vk::ImageView],
     render_pass: vk::RenderPass,
     extent: vk::Extent2D,
-) -> Result<Vec<vk::Framebuffer>, Box<dyn std::error::Error>> {
+) -> Result<Vec<vk::Framebuffer>, FloError> {
     let mut framebuffers = Vec::with_capacity(image_views.len());
     
     for &image_view in image_views {
@@ -1176,29 +2624,51 @@ pub fn create_framebuffers_no_depth(
             .height(extent.height)
             .layers(1);
         
-        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None)? };
+        let framebuffer = leak_check::created(device, unsafe { device.create_framebuffer(&framebuffer_info, None)? });
         framebuffers.push(framebuffer);
     }
     
     Ok(framebuffers)
 }
 
+// Subpasses of every pass made by create_render_pass. Meshes draw in the geometry subpass
+// unless moved with set_mesh_subpass. The lighting subpass draws into the same color
+// attachment and reads depth as an input attachment instead of writing it.
+pub const GEOMETRY_SUBPASS: u32 = 0;
+pub const LIGHTING_SUBPASS: u32 = 1;
+pub const SCENE_SUBPASS_COUNT: u32 = 2;
+
+// Scene render pass. The color attachment (HDR_FORMAT for the main pass) ends up ready to be
+// sampled, by the present pass or by meshes showing a render target. Passes that draw the
+// same pipelines have to be created with the same depth format. End it with
+// end_scene_render_pass, which steps through the subpasses that weren't used.
 pub fn create_render_pass(
-    instance: &Instance,
     device: &ash::Device,
-    physical_device: vk::PhysicalDevice,
-    swapchain_format: vk::Format,
-    with_depth: bool,
-) -> Result<vk::RenderPass, Box<dyn std::error::Error>> {
+    color_format: vk::Format,
+    depth_format: Option<vk::Format>,
+) -> Result<vk::RenderPass, FloError> {
+    create_render_pass_with_load_op(device, color_format, depth_format, vk::AttachmentLoadOp::CLEAR)
+}
+
+// Like create_render_pass, with LOAD keeping what an earlier pass of the same format drew
+// instead of clearing. The passes differ only in load ops and initial layouts, so they're
+// compatible and share framebuffers and pipelines.
+pub fn create_render_pass_with_load_op(
+    device: &ash::Device,
+    color_format: vk::Format,
+    depth_format: Option<vk::Format>,
+    load_op: vk::AttachmentLoadOp,
+) -> Result<vk::RenderPass, FloError> {
+    let load = load_op == vk::AttachmentLoadOp::LOAD;
     let color_attachment = vk::AttachmentDescription::default()
-        .format(swapchain_format)
+        .format(color_format)
         .samples(vk::SampleCountFlags::TYPE_1)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .load_op(load_op)
         .store_op(vk::AttachmentStoreOp::STORE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        .initial_layout(if load { vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL } else { vk::ImageLayout::UNDEFINED })
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
     
     let color_attachment_ref = vk::AttachmentReference::default()
         .attachment(0)
@@ -1211,17 +2681,31 @@ pub fn create_render_pass(
         .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
         .color_attachments(&color_attachment_refs);
     
+    // The lighting subpass writes the same color attachment. Depth stays bound for testing but
+    // in a read only layout, which is what lets it be read as input attachment 0 at the same time.
+    let mut lighting_subpass = vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs);
+    
     let depth_attachment_ref;
-    if with_depth {
-        let depth_format = find_depth_format(instance, physical_device)?;
+    let depth_read_only_ref;
+    let depth_input_refs;
+    if let Some(depth_format) = depth_format {
+        // Stencil is cleared or loaded with depth. Both are stored so a later LOAD pass can
+        // test against them.
+        let (stencil_load_op, stencil_store_op) = if has_stencil_component(depth_format) {
+            (load_op, vk::AttachmentStoreOp::STORE)
+        } else {
+            (vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::DONT_CARE)
+        };
         let depth_attachment = vk::AttachmentDescription::default()
             .format(depth_format)
             .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .load_op(load_op)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(stencil_load_op)
+            .stencil_store_op(stencil_store_op)
+            .initial_layout(if load { vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL } else { vk::ImageLayout::UNDEFINED })
             .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
         
         attachments.push(depth_attachment);
@@ -1231,37 +2715,170 @@ pub fn create_render_pass(
             .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
         
         subpass_builder = subpass_builder.depth_stencil_attachment(&depth_attachment_ref);
+        
+        depth_read_only_ref = vk::AttachmentReference::default()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL);
+        depth_input_refs = [depth_read_only_ref];
+        lighting_subpass = lighting_subpass
+            .depth_stencil_attachment(&depth_read_only_ref)
+            .input_attachments(&depth_input_refs);
     }
     
     let subpass = subpass_builder;
     
-    let mut dependency = vk::SubpassDependency::default()
-        .src_subpass(vk::SUBPASS_EXTERNAL)
-        .dst_subpass(0)
-        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .src_access_mask(vk::AccessFlags::empty())
-        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
-    
-    if with_depth {
-        dependency = dependency
-            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+    let mut dependencies = [
+        // The previous frame's present pass may still be sampling the image we're about to clear
+        vk::SubpassDependency::default()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+        // The lighting subpass blends over the geometry's color and reads its depth, per pixel
+        vk::SubpassDependency::default()
+            .src_subpass(GEOMETRY_SUBPASS)
+            .dst_subpass(LIGHTING_SUBPASS)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::INPUT_ATTACHMENT_READ,
+            )
+            .dependency_flags(vk::DependencyFlags::BY_REGION),
+        // And the next pass samples what we drew
+        vk::SubpassDependency::default()
+            .src_subpass(LIGHTING_SUBPASS)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+    
+    if depth_format.is_some() {
+        dependencies[0] = dependencies[0]
+            .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
             .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
             .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
     }
+    if load {
+        // A LOAD pass reads what the pass before it wrote, not just overwrites it
+        dependencies[0] = dependencies[0]
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            );
+    }
     
-    let subpasses = [subpass];
-    let dependencies = [dependency];
+    let subpasses = [subpass, lighting_subpass];
     let render_pass_info = vk::RenderPassCreateInfo::default()
         .attachments(&attachments)
         .subpasses(&subpasses)
         .dependencies(&dependencies);
     
-    let render_pass = unsafe { device.create_render_pass(&render_pass_info, None)? };
+    let render_pass = leak_check::created(device, unsafe { device.create_render_pass(&render_pass_info, None)? });
     
     Ok(render_pass)
 }
 
+// Ends a pass made by create_render_pass that is still in its geometry subpass
+pub fn end_scene_render_pass(device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    unsafe {
+        device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+        device.cmd_end_render_pass(command_buffer);
+    }
+}
+
+
+// Color blending of a pipeline. AlphaBlend and PremultipliedAlpha depend on what's already
+// drawn behind them, so their meshes are drawn last and back to front. Additive and Multiply
+// give the same result in any order, their meshes are only drawn after the opaque ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    AlphaBlend,
+    Additive,
+    // Colors already multiplied by alpha, e.g. textures exported premultiplied
+    PremultipliedAlpha,
+    Multiply,
+}
+
+impl BlendMode {
+    // Whether meshes drawn with this mode have to be sorted back to front
+    pub fn needs_sorting(self) -> bool {
+        matches!(self, BlendMode::AlphaBlend | BlendMode::PremultipliedAlpha)
+    }
+}
+
+// How a mesh's fragments are kept. Cutout meshes discard fragments with an alpha below the
+// threshold (foliage, fences), so they keep writing depth and are drawn with the opaque meshes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MaterialMode {
+    #[default]
+    Opaque,
+    Cutout { threshold: f32 },
+}
+
+impl MaterialMode {
+    // Threshold pushed for the fragment shader, None when nothing is discarded
+    pub fn alpha_cutoff(self) -> Option<f32> {
+        match self {
+            MaterialMode::Opaque => None,
+            MaterialMode::Cutout { threshold } => Some(threshold),
+        }
+    }
+}
+
+// Smoother edges for cutout pipelines under MSAA, see VulkanRenderer::add_cutout_pipeline_with_antialiasing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CutoutAntialiasing {
+    // Fraction of the samples shaded separately, 1.0 shades every sample. None shades once per pixel.
+    pub min_sample_shading: Option<f32>,
+    pub alpha_to_coverage: bool,
+}
+
+// Stages PipelineBuilder can specialize
+const SPECIALIZABLE_STAGES: [vk::ShaderStageFlags; 4] = [
+    vk::ShaderStageFlags::VERTEX,
+    vk::ShaderStageFlags::TESSELLATION_CONTROL,
+    vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+    vk::ShaderStageFlags::FRAGMENT,
+];
+
+struct StageSpecialization {
+    stage: vk::ShaderStageFlags,
+    data: Vec<u8>,
+    entries: Vec<vk::SpecializationMapEntry>,
+}
 
 pub struct PipelineBuilder {
     device: ash::Device,
@@ -1271,13 +2888,29 @@ pub struct PipelineBuilder {
     vertex_attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
     push_constant_ranges: Vec<vk::PushConstantRange>,
     descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    shared_descriptor_layouts: Vec<SharedDescriptorLayout>,
     extent: vk::Extent2D,
     render_pass: vk::RenderPass,
     with_depth_test: bool,
     cull_mode: vk::CullModeFlags,
     front_face: vk::FrontFace,
     polygon_mode: vk::PolygonMode,
-    with_alpha_blending: bool,
+    depth_bias: bool,
+    stencil: Option<vk::StencilOpState>,
+    blend_mode: BlendMode,
+    with_depth_write: bool,
+    color_write_mask: vk::ColorComponentFlags,
+    topology: vk::PrimitiveTopology,
+    // Specialization constant values and their map entries, for stages that have any
+    specializations: Vec<StageSpecialization>,
+    // Control and evaluation shader code, None for no tessellation
+    tessellation_shader_code: Option<(Vec<u8>, Vec<u8>)>,
+    patch_control_points: u32,
+    min_sample_shading: Option<f32>,
+    alpha_to_coverage: bool,
+    subpass: u32,
+    // Shown in the pipeline build report, the shader file names unless set with with_name
+    name: String,
 }
 
 impl PipelineBuilder {
@@ -1287,9 +2920,11 @@ impl PipelineBuilder {
         frag_shader_path: &str,
         extent: vk::Extent2D,
         render_pass: vk::RenderPass,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let vert_shader_code = std::fs::read(vert_shader_path)?;
-        let frag_shader_code = std::fs::read(frag_shader_path)?;
+    ) -> Result<Self, FloError> {
+        let vert_shader_code = read_shader_file(vert_shader_path)?;
+        let frag_shader_code = read_shader_file(frag_shader_path)?;
+        let file_name = |path: &str| std::path::Path::new(path).file_name()
+            .map_or_else(|| path.to_string(), |name| name.to_string_lossy().into_owned());
         
         Ok(Self {
             device,
@@ -1299,16 +2934,35 @@ impl PipelineBuilder {
             vertex_attribute_descriptions: Vec::new(),
             push_constant_ranges: Vec::new(),
             descriptor_set_layouts: Vec::new(),
+            shared_descriptor_layouts: Vec::new(),
             extent,
             render_pass,
             with_depth_test: false,
             cull_mode: vk::CullModeFlags::BACK,
             front_face: vk::FrontFace::COUNTER_CLOCKWISE,
             polygon_mode: vk::PolygonMode::FILL,
-            with_alpha_blending: false,
+            depth_bias: false,
+            stencil: None,
+            blend_mode: BlendMode::Opaque,
+            with_depth_write: true,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            specializations: Vec::new(),
+            tessellation_shader_code: None,
+            patch_control_points: 0,
+            min_sample_shading: None,
+            alpha_to_coverage: false,
+            subpass: GEOMETRY_SUBPASS,
+            name: format!("{} + {}", file_name(vert_shader_path), file_name(frag_shader_path)),
         })
     }
     
+    // Name of the pipeline in the build report, see pipeline_build_report
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+    
     pub fn with_vertex_input(
         mut self,
         binding_descriptions: Vec<vk::VertexInputBindingDescription>,
@@ -1319,16 +2973,31 @@ impl PipelineBuilder {
         self
     }
     
+    // Vertex input of a single VertexFormat at binding 0
+    pub fn with_vertex_format<T: VertexFormat>(self) -> Self {
+        self.with_vertex_input(vec![T::binding_description()], T::attribute_descriptions())
+    }
+    
     pub fn with_push_constants(mut self, ranges: Vec<vk::PushConstantRange>) -> Self {
         self.push_constant_ranges = ranges;
         self
     }
     
+    // The pipeline's own sets, starting at set 0, or at FIRST_PIPELINE_SET when
+    // the pipeline also uses shared sets (see with_shared_descriptor_layout)
     pub fn with_descriptor_sets(mut self, layouts: Vec<vk::DescriptorSetLayout>) -> Self {
         self.descriptor_set_layouts = layouts;
         self
     }
     
+    // Places a shared layout at its fixed set index, so descriptor sets bound there stay
+    // bound when switching between pipelines that share it
+    pub fn with_shared_descriptor_layout(mut self, shared: SharedDescriptorLayout) -> Self {
+        self.shared_descriptor_layouts.retain(|existing| existing.set != shared.set);
+        self.shared_descriptor_layouts.push(shared);
+        self
+    }
+    
     pub fn with_depth_test(mut self, enable: bool) -> Self {
         self.with_depth_test = enable;
         self
@@ -1349,37 +3018,205 @@ impl PipelineBuilder {
         self
     }
     
-    pub fn with_alpha_blending(mut self, enable: bool) -> Self {
-        self.with_alpha_blending = enable;
-        self
+    // Offsets depth by the factors set with cmd_set_depth_bias before drawing, they're dynamic
+    // state so the sign can follow reverse-Z
+    pub fn with_depth_bias(mut self, enable: bool) -> Self {
+        self.depth_bias = enable;
+        self
+    }
+    
+    // Stencil test and ops for both faces. The reference is dynamic state, the renderer sets
+    // each mesh's (see set_mesh_stencil_reference). The render pass needs a depth format with
+    // stencil, see CoreOptions::stencil.
+    pub fn with_stencil(mut self, state: vk::StencilOpState) -> Self {
+        self.stencil = Some(state);
+        self
+    }
+    
+    // Runs the fragment shader for at least this fraction of a pixel's samples instead of once,
+    // so alpha tested edges are resolved per sample. Costs up to one shader invocation per
+    // sample. Needs the sampleRateShading feature (see sample_rate_shading_supported) and only
+    // changes anything in multisampled render passes.
+    pub fn with_sample_shading(mut self, min_sample_shading: f32) -> Self {
+        self.min_sample_shading = Some(min_sample_shading.clamp(0.0, 1.0));
+        self
+    }
+    
+    // Turns the fragment's alpha into sample coverage, fading cutout edges over the samples
+    pub fn with_alpha_to_coverage(mut self, enable: bool) -> Self {
+        self.alpha_to_coverage = enable;
+        self
+    }
+    
+    pub fn with_blend_mode(mut self, mode: BlendMode) -> Self {
+        self.blend_mode = mode;
+        self
+    }
+    
+    #[deprecated(note = "use with_blend_mode(BlendMode::AlphaBlend)")]
+    pub fn with_alpha_blending(self, enable: bool) -> Self {
+        self.with_blend_mode(if enable { BlendMode::AlphaBlend } else { BlendMode::Opaque })
+    }
+    
+    pub fn with_depth_write(mut self, enable: bool) -> Self {
+        self.with_depth_write = enable;
+        self
+    }
+    
+    // Subpass of the scene pass the pipeline draws in. Depth is read only in LIGHTING_SUBPASS,
+    // so pipelines built for it never write depth whatever with_depth_write says.
+    pub fn with_subpass(mut self, subpass: u32) -> Self {
+        self.subpass = subpass;
+        self
+    }
+    
+    pub fn with_color_write_mask(mut self, mask: vk::ColorComponentFlags) -> Self {
+        self.color_write_mask = mask;
+        self
+    }
+    
+    // Strip and fan topologies get primitive restart, so one draw can hold several strips
+    // separated by mesh::PRIMITIVE_RESTART_INDEX. Ignored with tessellation, which draws patches.
+    pub fn with_topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+    
+    // Specialization constants as (constant_id, value) pairs for every stage in stages, so one
+    // SPIR-V binary can be built with e.g. different joint counts or feature toggles. Values are
+    // 32 bits: pass bools as vk::Bool32 and floats with f32::to_bits. Replaces the stage's
+    // earlier constants.
+    pub fn with_specialization(self, stages: vk::ShaderStageFlags, constants: &[(u32, u32)]) -> Self {
+        let data = constants.iter().flat_map(|&(_, value)| value.to_ne_bytes()).collect();
+        let entries = constants.iter().enumerate()
+            .map(|(index, &(constant_id, _))| vk::SpecializationMapEntry {
+                constant_id,
+                offset: (index * 4) as u32,
+                size: 4,
+            })
+            .collect();
+        self.with_raw_specialization(stages, data, entries)
+    }
+    
+    // Values for the vertex shader's specialization constants, entries index into data
+    pub fn with_vertex_specialization(self, data: Vec<u8>, entries: Vec<vk::SpecializationMapEntry>) -> Self {
+        self.with_raw_specialization(vk::ShaderStageFlags::VERTEX, data, entries)
+    }
+    
+    // Values for the fragment shader's specialization constants, entries index into data
+    pub fn with_fragment_specialization(self, data: Vec<u8>, entries: Vec<vk::SpecializationMapEntry>) -> Self {
+        self.with_raw_specialization(vk::ShaderStageFlags::FRAGMENT, data, entries)
+    }
+    
+    // Same as with_specialization with the data and map entries laid out by the caller, e.g.
+    // for constants that aren't 32 bits
+    pub fn with_raw_specialization(
+        mut self,
+        stages: vk::ShaderStageFlags,
+        data: Vec<u8>,
+        entries: Vec<vk::SpecializationMapEntry>,
+    ) -> Self {
+        for stage in SPECIALIZABLE_STAGES {
+            if !stages.contains(stage) {
+                continue;
+            }
+            self.specializations.retain(|specialization| specialization.stage != stage);
+            self.specializations.push(StageSpecialization { stage, data: data.clone(), entries: entries.clone() });
+        }
+        self
+    }
+    
+    // Empty for stages without constants, the shader's defaults apply then
+    fn specialization_info(&self, stage: vk::ShaderStageFlags) -> vk::SpecializationInfo<'_> {
+        match self.specializations.iter().find(|specialization| specialization.stage == stage) {
+            Some(specialization) => vk::SpecializationInfo::default()
+                .map_entries(&specialization.entries)
+                .data(&specialization.data),
+            None => vk::SpecializationInfo::default(),
+        }
+    }
+    
+    // Draws patches of patch_control_points vertices through the given tessellation stages
+    // instead of triangles. The device needs the tessellationShader feature (see tessellation_supported).
+    pub fn with_tessellation(
+        mut self,
+        tess_control_shader_path: &str,
+        tess_evaluation_shader_path: &str,
+        patch_control_points: u32,
+    ) -> Result<Self, FloError> {
+        let tess_control_shader_code = read_shader_file(tess_control_shader_path)?;
+        let tess_evaluation_shader_code = read_shader_file(tess_evaluation_shader_path)?;
+        self.tessellation_shader_code = Some((tess_control_shader_code, tess_evaluation_shader_code));
+        self.patch_control_points = patch_control_points;
+        Ok(self)
     }
     
-    pub fn build(self) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn std::error::Error>> {
+    pub fn build(self) -> Result<(vk::Pipeline, vk::PipelineLayout), FloError> {
+        let with_depth_write = self.with_depth_write && self.subpass == GEOMETRY_SUBPASS;
         unsafe {
             let vert_shader_module = create_shader_module(&self.device, &self.vert_shader_code)?;
             let frag_shader_module = create_shader_module(&self.device, &self.frag_shader_code)?;
             
             let main_name = CString::new("main")?;
             
+            let vert_specialization_info = self.specialization_info(vk::ShaderStageFlags::VERTEX);
+            let frag_specialization_info = self.specialization_info(vk::ShaderStageFlags::FRAGMENT);
+            let control_specialization_info = self.specialization_info(vk::ShaderStageFlags::TESSELLATION_CONTROL);
+            let evaluation_specialization_info = self.specialization_info(vk::ShaderStageFlags::TESSELLATION_EVALUATION);
+            
             let vert_shader_stage_info = vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::VERTEX)
                 .module(vert_shader_module)
-                .name(&main_name);
+                .name(&main_name)
+                .specialization_info(&vert_specialization_info);
             
             let frag_shader_stage_info = vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::FRAGMENT)
                 .module(frag_shader_module)
-                .name(&main_name);
+                .name(&main_name)
+                .specialization_info(&frag_specialization_info);
+            
+            let mut shader_stages = vec![vert_shader_stage_info, frag_shader_stage_info];
             
-            let shader_stages = [vert_shader_stage_info, frag_shader_stage_info];
+            let tessellation_modules = match self.tessellation_shader_code {
+                Some((ref control_code, ref evaluation_code)) => {
+                    let control_module = create_shader_module(&self.device, control_code)?;
+                    let evaluation_module = create_shader_module(&self.device, evaluation_code)?;
+                    shader_stages.push(vk::PipelineShaderStageCreateInfo::default()
+                        .stage(vk::ShaderStageFlags::TESSELLATION_CONTROL)
+                        .module(control_module)
+                        .name(&main_name)
+                        .specialization_info(&control_specialization_info));
+                    shader_stages.push(vk::PipelineShaderStageCreateInfo::default()
+                        .stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
+                        .module(evaluation_module)
+                        .name(&main_name)
+                        .specialization_info(&evaluation_specialization_info));
+                    Some((control_module, evaluation_module))
+                }
+                None => None,
+            };
             
             let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::default()
                 .vertex_binding_descriptions(&self.vertex_binding_descriptions)
                 .vertex_attribute_descriptions(&self.vertex_attribute_descriptions);
             
+            let topology = if tessellation_modules.is_some() {
+                vk::PrimitiveTopology::PATCH_LIST
+            } else {
+                self.topology
+            };
+            // List topologies can't restart without an extension
+            let primitive_restart = matches!(
+                topology,
+                vk::PrimitiveTopology::TRIANGLE_STRIP | vk::PrimitiveTopology::TRIANGLE_FAN | vk::PrimitiveTopology::LINE_STRIP
+            );
             let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
-                .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-                .primitive_restart_enable(false);
+                .topology(topology)
+                .primitive_restart_enable(primitive_restart);
+            
+            let tessellation_state = vk::PipelineTessellationStateCreateInfo::default()
+                .patch_control_points(self.patch_control_points);
             
             let viewport = vk::Viewport::default()
                 .x(0.0)
@@ -1406,26 +3243,60 @@ impl PipelineBuilder {
                 .line_width(1.0)
                 .cull_mode(self.cull_mode)
                 .front_face(self.front_face)
-                .depth_bias_enable(false);
+                .depth_bias_enable(self.depth_bias);
             
             let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
-                .sample_shading_enable(false)
+                .sample_shading_enable(self.min_sample_shading.is_some())
+                .min_sample_shading(self.min_sample_shading.unwrap_or(0.0))
+                .alpha_to_coverage_enable(self.alpha_to_coverage)
                 .rasterization_samples(vk::SampleCountFlags::TYPE_1);
             
-            let color_blend_attachment = if self.with_alpha_blending {
-                vk::PipelineColorBlendAttachmentState::default()
-                    .color_write_mask(vk::ColorComponentFlags::RGBA)
-                    .blend_enable(true)
-                    .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-                    .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                    .color_blend_op(vk::BlendOp::ADD)
-                    .src_alpha_blend_factor(vk::BlendFactor::ONE)
-                    .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-                    .alpha_blend_op(vk::BlendOp::ADD)
-            } else {
-                vk::PipelineColorBlendAttachmentState::default()
-                    .color_write_mask(vk::ColorComponentFlags::RGBA)
-                    .blend_enable(false)
+            // (src color, dst color, src alpha, dst alpha) factors, all combined with ADD
+            let blend_factors = match self.blend_mode {
+                BlendMode::Opaque => None,
+                BlendMode::AlphaBlend => Some((
+                    vk::BlendFactor::SRC_ALPHA,
+                    vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                    vk::BlendFactor::ONE,
+                    vk::BlendFactor::ZERO,
+                )),
+                // Alpha is left as it was, light doesn't cover anything
+                BlendMode::Additive => Some((
+                    vk::BlendFactor::SRC_ALPHA,
+                    vk::BlendFactor::ONE,
+                    vk::BlendFactor::ZERO,
+                    vk::BlendFactor::ONE,
+                )),
+                BlendMode::PremultipliedAlpha => Some((
+                    vk::BlendFactor::ONE,
+                    vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                    vk::BlendFactor::ONE,
+                    vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                )),
+                BlendMode::Multiply => Some((
+                    vk::BlendFactor::DST_COLOR,
+                    vk::BlendFactor::ZERO,
+                    vk::BlendFactor::ZERO,
+                    vk::BlendFactor::ONE,
+                )),
+            };
+            let color_blend_attachment = match blend_factors {
+                Some((src_color, dst_color, src_alpha, dst_alpha)) => {
+                    vk::PipelineColorBlendAttachmentState::default()
+                        .color_write_mask(self.color_write_mask)
+                        .blend_enable(true)
+                        .src_color_blend_factor(src_color)
+                        .dst_color_blend_factor(dst_color)
+                        .color_blend_op(vk::BlendOp::ADD)
+                        .src_alpha_blend_factor(src_alpha)
+                        .dst_alpha_blend_factor(dst_alpha)
+                        .alpha_blend_op(vk::BlendOp::ADD)
+                }
+                None => {
+                    vk::PipelineColorBlendAttachmentState::default()
+                        .color_write_mask(self.color_write_mask)
+                        .blend_enable(false)
+                }
             };
             
             let attachments = [color_blend_attachment];
@@ -1433,22 +3304,75 @@ impl PipelineBuilder {
                 .logic_op_enable(false)
                 .attachments(&attachments);
             
-            let depth_stencil = if self.with_depth_test {
+            let mut depth_stencil = if self.with_depth_test {
                 vk::PipelineDepthStencilStateCreateInfo::default()
                     .depth_test_enable(true)
-                    .depth_write_enable(true)
-                    .depth_compare_op(vk::CompareOp::LESS)
+                    .depth_write_enable(with_depth_write)
+                    .depth_compare_op(vk::CompareOp::LESS) // Overridden by dynamic state at bind time
                     .depth_bounds_test_enable(false)
                     .stencil_test_enable(false)
             } else {
                 vk::PipelineDepthStencilStateCreateInfo::default()
             };
+            if let Some(stencil) = self.stencil {
+                depth_stencil = depth_stencil
+                    .stencil_test_enable(true)
+                    .front(stencil)
+                    .back(stencil);
+            }
+            
+            // Viewport and scissor are set after beginning the render pass (see set_viewport_and_scissor)
+            // so the same pipeline can draw into render targets of any size.
+            // Depth compare op is set by bind_graphics_pipeline so reverse-Z can be toggled at runtime,
+            // depth test and write enables so meshes can turn them off (see set_mesh_depth_test),
+            // and depth bias so decals can turn it on (see set_mesh_depth_bias)
+            let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+            if self.with_depth_test {
+                dynamic_states.push(vk::DynamicState::DEPTH_COMPARE_OP);
+                dynamic_states.push(vk::DynamicState::DEPTH_TEST_ENABLE);
+                if with_depth_write {
+                    dynamic_states.push(vk::DynamicState::DEPTH_WRITE_ENABLE);
+                }
+                if !self.depth_bias {
+                    dynamic_states.push(vk::DynamicState::DEPTH_BIAS_ENABLE);
+                }
+            }
+            if self.depth_bias || self.with_depth_test {
+                dynamic_states.push(vk::DynamicState::DEPTH_BIAS);
+            }
+            if self.stencil.is_some() {
+                dynamic_states.push(vk::DynamicState::STENCIL_REFERENCE);
+            }
+            let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
+                .dynamic_states(&dynamic_states);
+            
+            // Shared sets take their fixed indices, unused ones get an empty layout that's only
+            // needed while creating the pipeline layout
+            let mut empty_layouts = Vec::new();
+            let set_layouts = if self.shared_descriptor_layouts.is_empty() {
+                self.descriptor_set_layouts.clone()
+            } else {
+                let mut set_layouts = vec![vk::DescriptorSetLayout::null(); FIRST_PIPELINE_SET as usize];
+                for shared in &self.shared_descriptor_layouts {
+                    set_layouts[shared.set.index() as usize] = shared.layout;
+                }
+                for layout in set_layouts.iter_mut().filter(|layout| **layout == vk::DescriptorSetLayout::null()) {
+                    *layout = create_descriptor_set_layout(&self.device, &[])?;
+                    empty_layouts.push(*layout);
+                }
+                set_layouts.extend(&self.descriptor_set_layouts);
+                set_layouts
+            };
             
             let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
-                .set_layouts(&self.descriptor_set_layouts)
+                .set_layouts(&set_layouts)
                 .push_constant_ranges(&self.push_constant_ranges);
             
-            let pipeline_layout = self.device.create_pipeline_layout(&pipeline_layout_info, None)?;
+            let pipeline_layout = self.device.create_pipeline_layout(&pipeline_layout_info, None);
+            for layout in empty_layouts {
+                leak_check::destroy(&self.device, layout);
+            }
+            let pipeline_layout = leak_check::created(&self.device, pipeline_layout?);
             
             let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
                 .stages(&shader_stages)
@@ -1459,21 +3383,205 @@ impl PipelineBuilder {
                 .multisample_state(&multisampling)
                 .depth_stencil_state(&depth_stencil)
                 .color_blend_state(&color_blending)
+                .dynamic_state(&dynamic_state)
                 .layout(pipeline_layout)
                 .render_pass(self.render_pass)
-                .subpass(0);
+                .subpass(self.subpass);
+            let pipeline_info = if tessellation_modules.is_some() {
+                pipeline_info.tessellation_state(&tessellation_state)
+            } else {
+                pipeline_info
+            };
             
+            // Only timed with debug logging on, so release builds skip the clock and the lock
+            let start = tracing::enabled!(tracing::Level::DEBUG).then(Instant::now);
             let pipelines = self.device.create_graphics_pipelines(
                 vk::PipelineCache::null(),
                 &[pipeline_info],
                 None,
             ).map_err(|e| e.1)?;
+            if let Some(start) = start {
+                record_pipeline_build(&self.device, &self.name, start.elapsed());
+            }
             
-            self.device.destroy_shader_module(vert_shader_module, None);
-            self.device.destroy_shader_module(frag_shader_module, None);
+            leak_check::destroy(&self.device, vert_shader_module);
+            leak_check::destroy(&self.device, frag_shader_module);
+            if let Some((control_module, evaluation_module)) = tessellation_modules {
+                leak_check::destroy(&self.device, control_module);
+                leak_check::destroy(&self.device, evaluation_module);
+            }
             
-            Ok((pipelines[0], pipeline_layout))
+            Ok((leak_check::created(&self.device, pipelines[0]), pipeline_layout))
+        }
+    }
+}
+
+// How long create_graphics_pipelines took for one pipeline, see pipeline_build_report
+#[derive(Clone, Debug)]
+pub struct PipelineBuildTime {
+    pub name: String,
+    pub duration: Duration,
+}
+
+// Build times by device, like leak_check's counts, so each renderer reports its own pipelines
+fn pipeline_build_times() -> &'static Mutex<HashMap<u64, Vec<PipelineBuildTime>>> {
+    static BUILD_TIMES: OnceLock<Mutex<HashMap<u64, Vec<PipelineBuildTime>>>> = OnceLock::new();
+    BUILD_TIMES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn record_pipeline_build(device: &ash::Device, name: &str, duration: Duration) {
+    debug!("Built pipeline '{}' in {:.2} ms", name, duration.as_secs_f64() * 1000.0);
+    if let Ok(mut build_times) = pipeline_build_times().lock() {
+        build_times.entry(device.handle().as_raw()).or_default()
+            .push(PipelineBuildTime { name: name.to_string(), duration });
+    }
+}
+
+// Every pipeline built on the device so far in build order, rebuilds included. Empty unless
+// debug logging was enabled for this crate while they were built.
+pub fn pipeline_build_report(device: &ash::Device) -> Vec<PipelineBuildTime> {
+    pipeline_build_times().lock()
+        .ok()
+        .and_then(|build_times| build_times.get(&device.handle().as_raw()).cloned())
+        .unwrap_or_default()
+}
+
+// Begins a secondary command buffer that continues subpass 0 of render_pass. Dynamic state
+// isn't inherited from the primary, so the viewport and scissor are set here. Simultaneous
+// use lets a buffer be replayed while a previous frame executing it is still in flight.
+pub fn begin_secondary_command_buffer(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+) -> Result<(), vk::Result> {
+    let inheritance_info = vk::CommandBufferInheritanceInfo::default()
+        .render_pass(render_pass)
+        .subpass(0)
+        .framebuffer(framebuffer);
+    let begin_info = vk::CommandBufferBeginInfo::default()
+        .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE | vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
+        .inheritance_info(&inheritance_info);
+    
+    unsafe {
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+    }
+    set_viewport_and_scissor(device, command_buffer, extent);
+    Ok(())
+}
+
+// Full extent viewport and scissor, pipelines keep both as dynamic state
+pub fn set_viewport_and_scissor(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    extent: vk::Extent2D,
+) {
+    let viewport = vk::Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: extent.width as f32,
+        height: extent.height as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    };
+    let scissor = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    };
+    
+    unsafe {
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+    }
+}
+
+// Attachments to clear when a pass begins, combined with |. See clear_attachments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ClearFlags(u32);
+
+impl ClearFlags {
+    pub const NONE: Self = Self(0);
+    pub const COLOR: Self = Self(1);
+    pub const DEPTH: Self = Self(1 << 1);
+    pub const STENCIL: Self = Self(1 << 2);
+    pub const DEPTH_STENCIL: Self = Self(Self::DEPTH.0 | Self::STENCIL.0);
+    pub const ALL: Self = Self(Self::COLOR.0 | Self::DEPTH_STENCIL.0);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for ClearFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for ClearFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+// Clears the flagged attachments inside rect with cmd_clear_attachments, on top of whatever the
+// render pass's load ops (fixed when it was created) did at its start. Has to be recorded in a
+// subpass that writes them, the scene pass's lighting subpass only reads depth. Depth and stencil
+// are skipped without a depth format, stencil also when the format has none.
+pub fn clear_attachments(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    flags: ClearFlags,
+    rect: vk::Rect2D,
+    color: [f32; 4],
+    depth: f32,
+    depth_format: Option<vk::Format>,
+) {
+    let mut clears = Vec::new();
+    if flags.contains(ClearFlags::COLOR) {
+        clears.push(vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                color: vk::ClearColorValue { float32: color },
+            },
+        });
+    }
+    if let Some(depth_format) = depth_format {
+        let mut aspect_mask = vk::ImageAspectFlags::empty();
+        if flags.contains(ClearFlags::DEPTH) {
+            aspect_mask |= vk::ImageAspectFlags::DEPTH;
+        }
+        if flags.contains(ClearFlags::STENCIL) && has_stencil_component(depth_format) {
+            aspect_mask |= vk::ImageAspectFlags::STENCIL;
         }
+        if !aspect_mask.is_empty() {
+            clears.push(vk::ClearAttachment {
+                aspect_mask,
+                color_attachment: 0,
+                clear_value: vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue { depth, stencil: STENCIL_CLEAR_VALUE },
+                },
+            });
+        }
+    }
+    if clears.is_empty() {
+        return;
+    }
+    let clear_rect = vk::ClearRect {
+        rect,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    unsafe {
+        device.cmd_clear_attachments(command_buffer, &clears, &[clear_rect]);
     }
 }
 
@@ -1492,6 +3600,8 @@ pub struct RenderConfig<'a> {
     pub push_constant_data: Option<&'a [u8]>,
     pub push_constant_stages: vk::ShaderStageFlags,
     pub clear_color: [f32; 4],
+    pub depth_clear_value: f32,
+    pub depth_compare_op: vk::CompareOp,
 }
 
 impl<'a> Default for RenderConfig<'a> {
@@ -1504,10 +3614,34 @@ impl<'a> Default for RenderConfig<'a> {
             push_constant_data: None,
             push_constant_stages: vk::ShaderStageFlags::VERTEX,
             clear_color: CLEAR_COLOR_MAGENTA,
+            depth_clear_value: DEPTH_CLEAR_VALUE,
+            depth_compare_op: vk::CompareOp::LESS,
         }
     }
 }
 
+// Pipelines built with depth testing take the compare op as dynamic state (core in Vulkan 1.3),
+// so switching to reverse-Z doesn't require rebuilding them. Use this instead of cmd_bind_pipeline.
+// Depth test and write are turned back on and depth bias off, pipelines built without them
+// dynamic ignore it. Pipelines built with_depth_bias set their bias after binding.
+pub fn bind_graphics_pipeline(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    pipeline: vk::Pipeline,
+    depth_compare_op: vk::CompareOp,
+) {
+    unsafe {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_set_depth_compare_op(command_buffer, depth_compare_op);
+        device.cmd_set_depth_test_enable(command_buffer, true);
+        device.cmd_set_depth_write_enable(command_buffer, true);
+        device.cmd_set_depth_bias_enable(command_buffer, false);
+        device.cmd_set_depth_bias(command_buffer, 0.0, 0.0, 0.0);
+    }
+}
+
+// Records the scene pass. The command buffer is left open so the caller can record the
+// present pass after it, and end it.
 pub fn record_command_buffer_unified(
     device: &ash::Device,
     command_buffer: vk::CommandBuffer,
@@ -1537,7 +3671,7 @@ pub fn record_command_buffer_unified(
         if has_depth {
             clear_values.push(vk::ClearValue {
                 depth_stencil: vk::ClearDepthStencilValue {
-                    depth: DEPTH_CLEAR_VALUE,
+                    depth: config.depth_clear_value,
                     stencil: STENCIL_CLEAR_VALUE,
                 },
             });
@@ -1553,9 +3687,10 @@ pub fn record_command_buffer_unified(
             .clear_values(&clear_values);
         
         device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+        set_viewport_and_scissor(device, command_buffer, extent);
         
         if pipeline != vk::Pipeline::null() {
-            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            bind_graphics_pipeline(device, command_buffer, pipeline, config.depth_compare_op);
             
             // Bind descriptor sets if provided
             if !config.descriptor_sets.is_empty() {
@@ -1606,11 +3741,7 @@ pub fn record_command_buffer_unified(
             }
         }
         
-        device.cmd_end_render_pass(command_buffer);
-        
-        device
-            .end_command_buffer(command_buffer)
-            .expect("Failed to record command buffer");
+        end_scene_render_pass(device, command_buffer);
     }
 }
 
@@ -1623,7 +3754,7 @@ pub fn create_textured_vertex_buffer(
     command_pool: vk::CommandPool,
     graphics_queue: vk::Queue,
     vertices: &[crate::mesh_textured::TexturedVertex],
-) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
+) -> Result<(vk::Buffer, vk::DeviceMemory), FloError> {
     let buffer_size = (std::mem::size_of::<crate::mesh_textured::TexturedVertex>() * vertices.len()) as vk::DeviceSize;
     
     // Create staging buffer
@@ -1658,8 +3789,8 @@ pub fn create_textured_vertex_buffer(
     
     // Clean up staging buffer
     unsafe {
-        device.destroy_buffer(staging_buffer, None);
-        device.free_memory(staging_buffer_memory, None);
+        leak_check::destroy(device, staging_buffer);
+        free_device_memory(device, staging_buffer_memory);
     }
     
     Ok((vertex_buffer, vertex_buffer_memory))
@@ -1676,7 +3807,7 @@ pub fn create_texture_array(
     textures: &[crate::texture::TextureData],
     width: u32,
     height: u32,
-) -> Result<(vk::Image, vk::DeviceMemory), Box<dyn std::error::Error>> {
+) -> Result<(vk::Image, vk::DeviceMemory), FloError> {
     
     let layer_count = textures.len() as u32;
     let image_size = (width * height * 4) as vk::DeviceSize;
@@ -1778,7 +3909,7 @@ pub fn create_texture_array(
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
         .samples(vk::SampleCountFlags::TYPE_1);
     
-    let texture_image = unsafe { device.create_image(&image_info, None)? };
+    let texture_image = leak_check::created(device, unsafe { device.create_image(&image_info, None)? });
     
     // Allocate memory for image
     let mem_requirements = unsafe { device.get_image_memory_requirements(texture_image) };
@@ -1793,7 +3924,7 @@ pub fn create_texture_array(
         .allocation_size(mem_requirements.size)
         .memory_type_index(mem_type_index);
     
-    let texture_image_memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+    let texture_image_memory = unsafe { allocate_device_memory(device, &alloc_info, MemoryUsage::Texture)? };
     unsafe { device.bind_image_memory(texture_image, texture_image_memory, 0)? };
     
     // Transition image layout and copy from staging buffer
@@ -1830,13 +3961,167 @@ pub fn create_texture_array(
     
     // Clean up staging buffer
     unsafe {
-        device.destroy_buffer(staging_buffer, None);
-        device.free_memory(staging_buffer_memory, None);
+        leak_check::destroy(device, staging_buffer);
+        free_device_memory(device, staging_buffer_memory);
     }
     
     Ok((texture_image, texture_image_memory))
 }
 
+// Uploads the layers and mip levels of a KTX2 texture array as they are, one copy per level
+pub fn create_texture_array_with_mips(
+    instance: &ash::Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    command_pool: vk::CommandPool,
+    graphics_queue: vk::Queue,
+    data: &crate::texture::TextureArrayData,
+) -> Result<(vk::Image, vk::DeviceMemory), FloError> {
+    let format_properties = unsafe { instance.get_physical_device_format_properties(physical_device, data.format) };
+    if !format_properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+        return Err(format!("Texture array format {:?} can't be sampled on this device", data.format).into());
+    }
+
+    let mip_levels = data.mip_levels();
+    let total_size: usize = data.levels.iter().map(Vec::len).sum();
+
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        instance,
+        device,
+        physical_device,
+        total_size as vk::DeviceSize,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    // Levels go into the staging buffer back to back, each copy region starts at its level
+    let mut regions = Vec::with_capacity(data.levels.len());
+    unsafe {
+        let mapped = device.map_memory(staging_buffer_memory, 0, total_size as vk::DeviceSize, vk::MemoryMapFlags::empty())? as *mut u8;
+        let mut offset = 0;
+        for (level, level_data) in data.levels.iter().enumerate() {
+            std::ptr::copy_nonoverlapping(level_data.as_ptr(), mapped.add(offset), level_data.len());
+            regions.push(vk::BufferImageCopy::default()
+                .buffer_offset(offset as vk::DeviceSize)
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: level as u32,
+                    base_array_layer: 0,
+                    layer_count: data.layer_count,
+                })
+                .image_extent(vk::Extent3D {
+                    width: (data.width >> level).max(1),
+                    height: (data.height >> level).max(1),
+                    depth: 1,
+                }));
+            offset += level_data.len();
+        }
+        device.unmap_memory(staging_buffer_memory);
+    }
+
+    let image_info = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D {
+            width: data.width,
+            height: data.height,
+            depth: 1,
+        })
+        .mip_levels(mip_levels)
+        .array_layers(data.layer_count)
+        .format(data.format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::TYPE_1);
+
+    let texture_image = match unsafe { device.create_image(&image_info, None) } {
+        Ok(image) => leak_check::created(device, image),
+        Err(e) => {
+            destroy_buffer(device, staging_buffer, staging_buffer_memory);
+            return Err(format!("Failed to create {:?} texture array with {} layers: {}", data.format, data.layer_count, e).into());
+        }
+    };
+
+    let mem_requirements = unsafe { device.get_image_memory_requirements(texture_image) };
+    let mem_type_index = find_memory_type(
+        instance,
+        physical_device,
+        mem_requirements.memory_type_bits,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    let alloc_info = vk::MemoryAllocateInfo::default()
+        .allocation_size(mem_requirements.size)
+        .memory_type_index(mem_type_index);
+
+    let texture_image_memory = unsafe { allocate_device_memory(device, &alloc_info, MemoryUsage::Texture)? };
+    unsafe { device.bind_image_memory(texture_image, texture_image_memory, 0)? };
+
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: mip_levels,
+        base_array_layer: 0,
+        layer_count: data.layer_count,
+    };
+
+    // Every level and layer is transitioned, copied and made readable in one submission
+    let command_buffer = begin_single_time_commands(device, command_pool)?;
+    unsafe {
+        let to_transfer = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(texture_image)
+            .subresource_range(subresource_range)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer],
+        );
+
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging_buffer,
+            texture_image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions,
+        );
+
+        let to_shader_read = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(texture_image)
+            .subresource_range(subresource_range)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_shader_read],
+        );
+    }
+    end_single_time_commands(device, command_pool, graphics_queue, command_buffer)?;
+
+    destroy_buffer(device, staging_buffer, staging_buffer_memory);
+
+    Ok((texture_image, texture_image_memory))
+}
+
 pub fn transition_image_layout_array(
     device: &ash::Device,
     command_pool: vk::CommandPool,
@@ -1845,7 +4130,7 @@ pub fn transition_image_layout_array(
     old_layout: vk::ImageLayout,
     new_layout: vk::ImageLayout,
     layer_count: u32,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), FloError> {
     
     let command_buffer = begin_single_time_commands(device, command_pool)?;
     
@@ -1907,7 +4192,7 @@ pub fn copy_buffer_to_image_array(
     width: u32,
     height: u32,
     layer_count: u32,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), FloError> {
     
     let command_buffer = begin_single_time_commands(device, command_pool)?;
     
@@ -1950,21 +4235,23 @@ pub fn copy_buffer_to_image_array(
 pub fn create_texture_array_view(
     device: &ash::Device,
     image: vk::Image,
+    format: vk::Format,
     layer_count: u32,
-) -> Result<vk::ImageView, Box<dyn std::error::Error>> {
+    mip_levels: u32,
+) -> Result<vk::ImageView, FloError> {
     let view_info = vk::ImageViewCreateInfo::default()
         .image(image)
         .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
-        .format(vk::Format::R8G8B8A8_SRGB)
+        .format(format)
         .subresource_range(vk::ImageSubresourceRange {
             aspect_mask: vk::ImageAspectFlags::COLOR,
             base_mip_level: 0,
-            level_count: 1,
+            level_count: mip_levels,
             base_array_layer: 0,
             layer_count,
         });
     
-    let image_view = unsafe { device.create_image_view(&view_info, None)? };
+    let image_view = leak_check::created(device, unsafe { device.create_image_view(&view_info, None)? });
     
     Ok(image_view)
 }
@@ -1977,10 +4264,27 @@ pub fn create_texture_image(
     command_pool: vk::CommandPool,
     queue: vk::Queue,
     path: &str,
-) -> Result<(vk::Image, vk::DeviceMemory), Box<dyn std::error::Error>> {
+) -> Result<(vk::Image, vk::DeviceMemory), FloError> {
     let image_data = image::open(path)?.to_rgba8();
     let (width, height) = image_data.dimensions();
+    create_texture_image_from_rgba(instance, device, physical_device, command_pool, queue, image_data.as_raw(), width, height)
+}
+
+// Upload already decoded RGBA8 pixels into a sampled image
+pub fn create_texture_image_from_rgba(
+    instance: &ash::Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+) -> Result<(vk::Image, vk::DeviceMemory), FloError> {
     let size = (width * height * 4) as vk::DeviceSize;
+    if pixels.len() < size as usize {
+        return Err(format!("Texture data too small: {} bytes for {}x{}", pixels.len(), width, height).into());
+    }
     
     // Create staging buffer
     let (staging_buffer, staging_memory) = create_buffer(
@@ -1995,7 +4299,7 @@ pub fn create_texture_image(
     // Copy image data to staging buffer
     unsafe {
         let data = device.map_memory(staging_memory, 0, size, vk::MemoryMapFlags::empty())?;
-        std::ptr::copy_nonoverlapping(image_data.as_raw().as_ptr(), data as *mut u8, size as usize);
+        std::ptr::copy_nonoverlapping(pixels.as_ptr(), data as *mut u8, size as usize);
         device.unmap_memory(staging_memory);
     }
     
@@ -2012,7 +4316,7 @@ pub fn create_texture_image(
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
         .samples(vk::SampleCountFlags::TYPE_1);
     
-    let image = unsafe { device.create_image(&image_info, None)? };
+    let image = leak_check::created(device, unsafe { device.create_image(&image_info, None)? });
     
     let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
     let alloc_info = vk::MemoryAllocateInfo::default()
@@ -2024,7 +4328,7 @@ pub fn create_texture_image(
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
         )?);
     
-    let image_memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+    let image_memory = unsafe { allocate_device_memory(device, &alloc_info, MemoryUsage::Texture)? };
     unsafe { device.bind_image_memory(image, image_memory, 0)? };
     
     // Transition and copy
@@ -2034,8 +4338,8 @@ pub fn create_texture_image(
     
     // Cleanup staging
     unsafe {
-        device.destroy_buffer(staging_buffer, None);
-        device.free_memory(staging_memory, None);
+        leak_check::destroy(device, staging_buffer);
+        free_device_memory(device, staging_memory);
     }
     
     Ok((image, image_memory))
@@ -2044,7 +4348,7 @@ pub fn create_texture_image(
 pub fn create_texture_image_view(
     device: &ash::Device,
     image: vk::Image,
-) -> Result<vk::ImageView, Box<dyn std::error::Error>> {
+) -> Result<vk::ImageView, FloError> {
     let view_info = vk::ImageViewCreateInfo::default()
         .image(image)
         .view_type(vk::ImageViewType::TYPE_2D)
@@ -2057,32 +4361,106 @@ pub fn create_texture_image_view(
             layer_count: 1,
         });
     
-    let image_view = unsafe { device.create_image_view(&view_info, None)? };
+    let image_view = leak_check::created(device, unsafe { device.create_image_view(&view_info, None)? });
     Ok(image_view)
 }
 
+// Address modes of a texture sampler along u, v and w. Defaults to CLAMP_TO_EDGE, which keeps
+// atlases and UI textures from bleeding in texels from the opposite edge; UVs outside 0..1 that
+// should tile, like the wall UVs of create_scaled_uv_cuboid in fluid_sim_bevy, need REPEAT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerAddressModes {
+    pub u: vk::SamplerAddressMode,
+    pub v: vk::SamplerAddressMode,
+    pub w: vk::SamplerAddressMode,
+}
+
+impl SamplerAddressModes {
+    pub const REPEAT: Self = Self::uniform(vk::SamplerAddressMode::REPEAT);
+    pub const CLAMP_TO_EDGE: Self = Self::uniform(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+
+    pub const fn uniform(mode: vk::SamplerAddressMode) -> Self {
+        Self { u: mode, v: mode, w: mode }
+    }
+}
+
+impl Default for SamplerAddressModes {
+    fn default() -> Self {
+        Self::CLAMP_TO_EDGE
+    }
+}
+
+// How sharply a texture sampler filters. max_anisotropy is clamped to the device limit and 1
+// turns anisotropic filtering off. mip_lod_bias is added to the mip level the sampler picks:
+// negative values choose sharper levels at the cost of aliasing, positive ones blurrier levels.
+// It's clamped to maxSamplerLodBias and only matters for textures with mip levels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerFiltering {
+    pub max_anisotropy: f32,
+    pub mip_lod_bias: f32,
+}
+
+impl Default for SamplerFiltering {
+    fn default() -> Self {
+        Self { max_anisotropy: 16.0, mip_lod_bias: 0.0 }
+    }
+}
+
 pub fn create_texture_sampler(
     instance: &ash::Instance,
     device: &ash::Device,
     physical_device: vk::PhysicalDevice,
-) -> Result<vk::Sampler, Box<dyn std::error::Error>> {
-    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    max_anisotropy: f32,
+    address_modes: SamplerAddressModes,
+) -> Result<vk::Sampler, FloError> {
+    create_mipmapped_texture_sampler(instance, device, physical_device, 1, max_anisotropy, address_modes)
+}
+
+// Samples every mip level of a texture with mip_levels levels
+pub fn create_mipmapped_texture_sampler(
+    instance: &ash::Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    mip_levels: u32,
+    max_anisotropy: f32,
+    address_modes: SamplerAddressModes,
+) -> Result<vk::Sampler, FloError> {
+    let filtering = SamplerFiltering { max_anisotropy, ..Default::default() };
+    create_filtered_texture_sampler(instance, device, physical_device, mip_levels, address_modes, filtering)
+}
+
+// create_mipmapped_texture_sampler with a mip LOD bias
+pub fn create_filtered_texture_sampler(
+    instance: &ash::Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    mip_levels: u32,
+    address_modes: SamplerAddressModes,
+    filtering: SamplerFiltering,
+) -> Result<vk::Sampler, FloError> {
+    let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+    // The device only has the feature enabled when it supports it
+    let max_anisotropy = filtering.max_anisotropy.min(limits.max_sampler_anisotropy);
+    let anisotropy = max_anisotropy > 1.0 && anisotropy_supported(instance, physical_device);
+    let mip_lod_bias = filtering.mip_lod_bias.clamp(-limits.max_sampler_lod_bias, limits.max_sampler_lod_bias);
     
     let sampler_info = vk::SamplerCreateInfo::default()
         .mag_filter(vk::Filter::LINEAR)
         .min_filter(vk::Filter::LINEAR)
-        .address_mode_u(vk::SamplerAddressMode::REPEAT)
-        .address_mode_v(vk::SamplerAddressMode::REPEAT)
-        .address_mode_w(vk::SamplerAddressMode::REPEAT)
-        .anisotropy_enable(true)
-        .max_anisotropy(properties.limits.max_sampler_anisotropy)
+        .address_mode_u(address_modes.u)
+        .address_mode_v(address_modes.v)
+        .address_mode_w(address_modes.w)
+        .anisotropy_enable(anisotropy)
+        .max_anisotropy(max_anisotropy.max(1.0))
         .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
         .unnormalized_coordinates(false)
         .compare_enable(false)
         .compare_op(vk::CompareOp::ALWAYS)
-        .mipmap_mode(vk::SamplerMipmapMode::LINEAR);
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .mip_lod_bias(mip_lod_bias)
+        .max_lod((mip_levels - 1) as f32);
     
-    let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+    let sampler = leak_check::created(device, unsafe { device.create_sampler(&sampler_info, None)? });
     Ok(sampler)
 }
 
@@ -2093,7 +4471,7 @@ pub fn transition_image_layout(
     image: vk::Image,
     old_layout: vk::ImageLayout,
     new_layout: vk::ImageLayout,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), FloError> {
     
     let command_buffer = begin_single_time_commands(device, command_pool)?;
     
@@ -2154,7 +4532,7 @@ pub fn copy_buffer_to_image(
     image: vk::Image,
     width: u32,
     height: u32,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), FloError> {
     
     let command_buffer = begin_single_time_commands(device, command_pool)?;
     
@@ -2185,3 +4563,103 @@ pub fn copy_buffer_to_image(
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_void;
+    
+    // The words of the last shader module created on the fake device
+    static CREATED_CODE: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+    
+    unsafe extern "system" fn fake_create_shader_module(
+        _: vk::Device,
+        create_info: *const vk::ShaderModuleCreateInfo<'_>,
+        _: *const vk::AllocationCallbacks<'_>,
+        shader_module: *mut vk::ShaderModule,
+    ) -> vk::Result {
+        let create_info = &*create_info;
+        let code = std::slice::from_raw_parts(create_info.p_code, create_info.code_size / 4);
+        *CREATED_CODE.lock().unwrap() = code.to_vec();
+        *shader_module = vk::ShaderModule::from_raw(1);
+        vk::Result::SUCCESS
+    }
+    
+    // A device whose only command is a vkCreateShaderModule that records the code it was given
+    fn fake_device() -> ash::Device {
+        unsafe {
+            ash::Device::load_with(
+                |name| if name == c"vkCreateShaderModule" { fake_create_shader_module as *const c_void } else { std::ptr::null() },
+                vk::Device::from_raw(0x5b1d),
+            )
+        }
+    }
+    
+    // The 5 word header of an empty module, magic number first
+    const HEADER: [u32; 5] = [0x0723_0203, 0x0001_0000, 0x000d_000b, 1, 0];
+    
+    fn shader_error(code: &[u8]) -> String {
+        match create_shader_module(&fake_device(), code) {
+            Err(FloError::ShaderCompile(message)) => message,
+            other => panic!("expected a shader error, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn malformed_spirv_is_rejected_before_the_driver() {
+        let code: Vec<u8> = HEADER.iter().flat_map(|word| word.to_le_bytes()).collect();
+        
+        assert!(shader_error(&[]).contains("empty"));
+        assert!(shader_error(&code[..code.len() - 1]).contains("truncated"));
+        assert!(shader_error(&code[..8]).contains("shorter than its header"));
+        let mut glsl = b"#version 450\n".to_vec();
+        glsl.resize(HEADER.len() * 4, b' ');
+        assert!(shader_error(&glsl).contains("magic number"));
+    }
+    
+    #[test]
+    fn either_byte_order_reaches_the_driver_in_host_order() {
+        let device = fake_device();
+        for to_bytes in [u32::to_le_bytes, u32::to_be_bytes] {
+            let code: Vec<u8> = HEADER.iter().flat_map(|&word| to_bytes(word)).collect();
+            create_shader_module(&device, &code).unwrap();
+            assert_eq!(*CREATED_CODE.lock().unwrap(), HEADER);
+        }
+    }
+    
+    #[test]
+    fn build_report_lists_each_devices_builds_in_order() {
+        // Handles no other test uses, the report is shared by the whole process
+        let device = |handle| unsafe { ash::Device::load_with(|_| std::ptr::null(), vk::Device::from_raw(handle)) };
+        let (first, second, unused) = (device(0x7e01), device(0x7e02), device(0x7e03));
+        record_pipeline_build(&first, "water", Duration::from_millis(12));
+        record_pipeline_build(&second, "sky", Duration::from_millis(3));
+        record_pipeline_build(&first, "tonemap.vert.spv + fxaa.frag.spv", Duration::from_micros(1500));
+        // Rebuilds are listed again
+        record_pipeline_build(&first, "water", Duration::from_millis(4));
+        
+        let report = |device| pipeline_build_report(device).into_iter()
+            .map(|build| (build.name, build.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(report(&first), vec![
+            ("water".to_string(), Duration::from_millis(12)),
+            ("tonemap.vert.spv + fxaa.frag.spv".to_string(), Duration::from_micros(1500)),
+            ("water".to_string(), Duration::from_millis(4)),
+        ]);
+        assert_eq!(report(&second), vec![("sky".to_string(), Duration::from_millis(3))]);
+        assert!(report(&unused).is_empty());
+    }
+    
+    #[test]
+    fn pipelines_are_named_after_their_shader_files() {
+        let builder = PipelineBuilder::new(
+            fake_device(),
+            "shaders/tonemap.vert.spv",
+            "shaders/fxaa.frag.spv",
+            vk::Extent2D { width: 1, height: 1 },
+            vk::RenderPass::null(),
+        ).unwrap();
+        assert_eq!(builder.name, "tonemap.vert.spv + fxaa.frag.spv");
+        assert_eq!(builder.with_name("post").name, "post");
+    }
+}
@@ -1,17 +1,50 @@
 use ash::vk;
-use bevy::prelude::*;
-use bevy::window::RawHandleWrapperHolder;
-use bevy::math::{Mat4, Vec3};
+use bevy_math::{Mat4, Vec2, Vec3};
+use bevy_transform::components::Transform;
+use tracing::{debug, error, info, trace, warn};
 use std::mem;
 use memoffset::offset_of;
 use crate::vulkan_common::*;
 use crate::constants::*;
-use crate::mesh::{Vertex, MeshData};
-use crate::skinned_mesh::{SkinnedVertex, SkinnedMeshData};
+use crate::error::FloError;
+use crate::gltf_loader::MaterialInfo;
+use crate::mesh::{self, Vertex, VertexFormat, InstanceData, MeshData, MeshLayout, MeshTopology};
+use crate::skinned_mesh::{self, SkinnedVertex, SkinnedMeshData, SkinningMode};
 use crate::mesh_textured::{TexturedMeshData, TexturedVertex};
-use crate::texture::{TextureData, Texture};
+use crate::texture::{ColorSpace, TextureArrayData, TextureData, Texture};
 use crate::egui_integration::EguiIntegration;
-use crate::memory_pool::{MemoryPoolManager, MemoryBlock};
+use crate::memory_pool::{DynamicBufferRing, MemoryPoolManager, MemoryBlock};
+use crate::buffer_residency::{self, BufferResidency, InstanceMemory, MappedVertexBuffer};
+use crate::texture_streamer::{DecodedTexture, TextureStreamer};
+use crate::draw_plan::{
+    mesh_pipeline_name, plan_draw_order, plan_mesh_draws, wireframe_overlay_pipeline, DebugView, DrawCommand, MeshDrawInfo,
+    OCCLUSION_PROXY_PIPELINE, WIREFRAME_OVERLAY_INSTANCED_PIPELINE, WIREFRAME_OVERLAY_INSTANCE_DATA_PIPELINE,
+    DEBUG_OVERDRAW_PIPELINES, DEBUG_VIEW_PIPELINES,
+};
+use crate::instance_culling::{self, Frustum, InstanceCulling, InstanceCullPipeline};
+use crate::instance_sort::{self, InstanceSort};
+use crate::bone_hierarchy::{self, BoneHierarchy, BoneHierarchyPipeline};
+use crate::joint_arena::{JointArena, JOINT_ARENA_SLOT_SIZE};
+use crate::particles::{Particle, ParticleForces, ParticleSystem};
+use crate::dashed_lines::{self, DashPattern, DashedLines};
+use crate::ground_grid::{GroundGrid, GroundGridSettings};
+use crate::debug_boxes::DebugBoxes;
+use crate::render_target::{RenderTarget, RenderTargetId};
+use crate::capture::{self, CaptureFormat, CapturedImage};
+use crate::tonemap::{Tonemap, UpscaleFilter};
+use crate::dynamic_resolution::{self, DynamicResolution, MAX_RESOLUTION_SCALE, MIN_RESOLUTION_SCALE};
+use crate::frame_pacing::FramePacing;
+use crate::post_process::{AaMode, FxaaSettings, PostEffectId, PostProcessChain};
+use crate::render_settings::RenderSettings;
+use crate::shadow_cascades::ShadowConfig;
+use crate::descriptor_layouts::{SharedDescriptorLayout, SharedDescriptorLayouts, SharedSet};
+use crate::descriptor_allocator::{DescriptorAllocator, DescriptorPoolSizes};
+use crate::parallel_recording::{ParallelRecorder, RecordContext, SecondaryTarget};
+use crate::water::WaterData;
+use crate::previous_frame::PreviousFrame;
+use crate::water_reflections::{WaterPipelineDesc, WaterReflectionQuality, WaterReflections, WATER_REFLECTION_FRAG_SHADER_PATH};
+use crate::leak_check;
+use crate::camera_controller::Camera;
 
 // Optional resources for different renderer configurations
 pub struct BufferResources {
@@ -59,6 +92,8 @@ pub struct SkinnedMeshResources {
     pub index_count: u32,
     pub joint_uniform_buffer: vk::Buffer,
     pub joint_uniform_memory: vk::DeviceMemory,
+    // Joints the buffer was sized for, from the mesh's joint_matrices
+    pub joint_count: usize,
     pub camera_uniform_buffer: vk::Buffer,
     pub camera_uniform_memory: vk::DeviceMemory,
     pub descriptor_pool: vk::DescriptorPool,
@@ -83,10 +118,20 @@ pub struct MeshEntry {
     pub transforms: Vec<Mat4>,  // Transform matrices for instances of this mesh
     pub pipeline_name: Option<String>,  // Optional pipeline name for this mesh
     pub texture_resources: Option<TextureResources>,  // Optional texture for this mesh
+    // Sampler address modes of textures streamed in by set_mesh_texture_from_file
+    pub texture_address_modes: SamplerAddressModes,
+    // Anisotropy and mip LOD bias of its texture samplers, None uses the renderer's anisotropy
+    // (see set_mesh_texture_filtering)
+    pub texture_filtering: Option<SamplerFiltering>,
     // Instance buffer for GPU instancing (optional)
     pub instance_buffer: Option<vk::Buffer>,
     pub instance_buffer_memory: Option<vk::DeviceMemory>,
     pub instance_memory_block: Option<MemoryBlock>,  // Some if using memory pool
+    // Memory instance buffers are created in, see add_mesh_instanced_with_memory
+    pub instance_memory: InstanceMemory,
+    // What a DeviceLocal instance buffer holds once the queued uploads are copied in, for
+    // reading instances back. None for host visible buffers, which are read directly.
+    pub instance_shadow: Option<Vec<u8>>,
     pub instance_count: u32,
     pub use_instancing: bool,  // If true, use GPU instancing instead of iterating transforms
     pub base_color: [f32; 4],  // Base color for this mesh (used in shaders via push constants)
@@ -94,19 +139,239 @@ pub struct MeshEntry {
     pub joint_matrices: Option<Vec<Mat4>>,  // Joint matrices for this mesh (if skinned)
     pub joint_buffer: Option<vk::Buffer>,  // Buffer to store joint matrices on GPU
     pub joint_buffer_memory: Option<vk::DeviceMemory>,
+    // Start of the mesh's joints in its joint buffer, bound as the skinned set's dynamic offset
+    pub joint_offset: u32,
+    // Bytes the mesh takes in the shared joint arena, whose joint buffer, camera buffer and set
+    // it borrows. None when it has its own.
+    pub joint_arena_size: Option<vk::DeviceSize>,
     pub is_skinned: bool,  // Whether this mesh uses skeletal animation
     // Descriptor sets for skinned mesh (joint matrices + camera uniforms)
     pub skinned_descriptor_pool: Option<vk::DescriptorPool>,
     pub skinned_descriptor_set_layout: Option<vk::DescriptorSetLayout>,
     pub skinned_descriptor_sets: Option<Vec<vk::DescriptorSet>>,
     pub camera_uniform_buffer: Option<vk::Buffer>,
-    pub camera_uniform_memory: Option<vk::DeviceMemory>
+    pub camera_uniform_memory: Option<vk::DeviceMemory>,
+    // Morph targets of a skinned mesh (see set_morph_weights), 0 without any. Both buffers are
+    // storage buffers in the skinned descriptor sets.
+    pub morph_target_count: u32,
+    pub morph_delta_buffer: Option<vk::Buffer>,
+    pub morph_delta_memory: Option<vk::DeviceMemory>,
+    pub morph_weight_buffer: Option<vk::Buffer>,
+    pub morph_weight_memory: Option<vk::DeviceMemory>,
+    // Local space bounds (min, max), None when unknown (e.g. skinned meshes)
+    pub local_bounds: Option<([f32; 3], [f32; 3])>,
+    // Local space area weighted centroid and surface area, None when unknown like the bounds
+    pub local_centroid: Option<([f32; 3], f32)>,
+    // Occlusion culling (opt-in per mesh, see set_mesh_occlusion_culling)
+    pub occlusion_culling: bool,
+    pub occlusion_visible: bool,  // Result of the last occlusion query that came back
+    pub occlusion_proxy_buffer: Option<vk::Buffer>,  // Bounding box drawn while hidden
+    pub occlusion_proxy_memory: Option<vk::DeviceMemory>,
+    // Frustum culling of instances (opt-in per instanced mesh, see enable_gpu_culling)
+    pub instance_culling: Option<InstanceCulling>,
+    // Back to front order of the instances, redone every frame (opt-in per instanced mesh, see
+    // set_mesh_instance_sort)
+    pub instance_sort: Option<InstanceSort>,
+    // Position only instances in the order they were given, since the instance buffer holds
+    // them sorted. Instances with InstanceData keep theirs in instance_data.
+    pub unsorted_instance_positions: Vec<[f32; 3]>,
+    // Joint matrices computed from local bone transforms (opt-in per skinned mesh, see set_bone_hierarchy)
+    pub bone_hierarchy: Option<BoneHierarchy>,
+    // Normal and uv buffers of a deinterleaved mesh, vertex_buffer then only holds positions
+    pub vertex_streams: Option<VertexStreams>,
+    // (min, max) of the instance positions, instanced meshes' local_bounds are offset by these
+    pub instance_bounds: Option<([f32; 3], [f32; 3])>,
+    // Samples this render target instead of texture_resources (see set_mesh_render_target_texture)
+    pub render_target: Option<RenderTargetId>,
+    // Samples the last frame's scene color instead (see set_mesh_previous_frame_texture)
+    pub previous_frame_texture: bool,
+    pub vertex_count: u32,
+    pub draw_mode: MeshDrawMode,
+    // Has to match the topology of the pipeline the mesh is drawn with
+    pub topology: MeshTopology,
+    // Added to the time pushed for this mesh so animated meshes don't move in sync
+    pub time_offset: f32,
+    // Turn off depth testing or writing for this mesh only, see set_mesh_depth_test
+    pub depth_test: bool,
+    pub depth_write: bool,
+    // Compare op for standard depth, mirrored with reverse-Z. None uses the renderer's.
+    pub depth_compare_op: Option<vk::CompareOp>,
+    // (constant, slope) depth bias towards the camera, see set_mesh_depth_bias
+    pub depth_bias: Option<(f32, f32)>,
+    // Subpass of the scene pass the mesh is drawn in, see set_mesh_subpass
+    pub subpass: u32,
+    // Owns the vertex buffers of a BufferResidency::HostVisibleDeviceLocal mesh, one per
+    // stream. vertex_buffer and vertex_streams point at the copies for the current frame.
+    pub mapped_vertex_buffers: Vec<MappedVertexBuffer>,
+    // (vertex, index) bytes of the buffers of a mesh from reserve_mesh_capacity, None for
+    // meshes whose buffers are sized to their data
+    pub reserved_capacity: Option<(vk::DeviceSize, vk::DeviceSize)>,
+    // Pushed at custom_push_offset after the built in push constants, see set_mesh_push_constants
+    pub custom_push_constants: Vec<u8>,
+    pub custom_push_offset: u32,
+    // Transform and color per instance of meshes added with add_mesh_instanced_with_colors,
+    // kept so either can be updated alone. None for position only instances.
+    pub instance_data: Option<Vec<InstanceData>>,
+    // Cutout meshes get their alpha threshold pushed after the MVP block, see set_mesh_alpha_cutoff
+    pub material_mode: MaterialMode,
+    // Color of the edges drawn over the mesh, see set_mesh_wireframe_overlay
+    pub wireframe_overlay: Option<[f32; 4]>,
+    // Dynamic stencil reference for pipelines from add_stencil_pipeline
+    pub stencil_reference: u32,
+    // Exposes the instance buffer as model matrices to the vertex shader, see add_mesh_instanced_ssbo
+    pub instance_matrices: Option<InstanceMatrixDescriptors>,
+    // Sway pushed for pipelines from add_wind_pipeline, see set_mesh_wind
+    pub wind: Option<WindParams>,
+    // Pushed for pipelines from add_uv_transform_pipeline, see set_mesh_uv_transform
+    pub uv_transform: Option<UvTransform>,
+    // No-cull variant of the mesh's pipeline it's drawn with instead, see set_mesh_double_sided
+    pub double_sided_pipeline: Option<String>,
+    // Mesh whose first transform transforms are relative to, see set_mesh_parent. transforms
+    // then holds the resolved world transforms and local_transforms the ones given.
+    pub parent: Option<usize>,
+    pub local_transforms: Vec<Mat4>,
+}
+
+// Meshes added without indices have no index buffer and are drawn straight from their vertices
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshDrawMode {
+    #[default]
+    Indexed,
+    NonIndexed,
+}
+
+// Attribute buffers of a MeshLayout::Deinterleaved mesh besides its positions
+struct PendingVertexUpload {
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    data: Vec<u8>,
+}
+
+pub struct VertexStreams {
+    pub normal_buffer: vk::Buffer,
+    pub normal_memory_block: Option<MemoryBlock>,  // None for mapped meshes
+    pub uv_buffer: vk::Buffer,
+    pub uv_memory_block: Option<MemoryBlock>,
+}
+
+// Set 0 of meshes from add_mesh_instanced_ssbo, a storage buffer binding over their instance buffer
+pub struct InstanceMatrixDescriptors {
+    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+impl Default for MeshEntry {
+    fn default() -> Self {
+        Self {
+            vertex_buffer: vk::Buffer::null(),
+            vertex_buffer_memory: None,
+            vertex_memory_block: None,
+            index_buffer: vk::Buffer::null(),
+            index_buffer_memory: None,
+            index_memory_block: None,
+            index_count: 0,
+            transforms: Vec::new(),
+            pipeline_name: None,
+            texture_resources: None,
+            texture_address_modes: SamplerAddressModes::default(),
+            texture_filtering: None,
+            instance_buffer: None,
+            instance_buffer_memory: None,
+            instance_memory_block: None,
+            instance_memory: InstanceMemory::default(),
+            instance_shadow: None,
+            instance_count: 0,
+            use_instancing: false,
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            joint_matrices: None,
+            joint_buffer: None,
+            joint_buffer_memory: None,
+            joint_offset: 0,
+            joint_arena_size: None,
+            is_skinned: false,
+            skinned_descriptor_pool: None,
+            skinned_descriptor_set_layout: None,
+            skinned_descriptor_sets: None,
+            camera_uniform_buffer: None,
+            camera_uniform_memory: None,
+            morph_target_count: 0,
+            morph_delta_buffer: None,
+            morph_delta_memory: None,
+            morph_weight_buffer: None,
+            morph_weight_memory: None,
+            local_bounds: None,
+            local_centroid: None,
+            occlusion_culling: false,
+            occlusion_visible: true,
+            occlusion_proxy_buffer: None,
+            occlusion_proxy_memory: None,
+            instance_culling: None,
+            instance_sort: None,
+            unsorted_instance_positions: Vec::new(),
+            bone_hierarchy: None,
+            vertex_streams: None,
+            instance_bounds: None,
+            render_target: None,
+            previous_frame_texture: false,
+            vertex_count: 0,
+            draw_mode: MeshDrawMode::Indexed,
+            topology: MeshTopology::TriangleList,
+            time_offset: 0.0,
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: None,
+            depth_bias: None,
+            subpass: GEOMETRY_SUBPASS,
+            mapped_vertex_buffers: Vec::new(),
+            reserved_capacity: None,
+            custom_push_constants: Vec::new(),
+            custom_push_offset: 0,
+            instance_data: None,
+            material_mode: MaterialMode::Opaque,
+            wireframe_overlay: None,
+            stencil_reference: 0,
+            instance_matrices: None,
+            wind: None,
+            uv_transform: None,
+            double_sided_pipeline: None,
+            parent: None,
+            local_transforms: Vec::new(),
+        }
+    }
 }
 
 // Structure to hold a pipeline and its layout
 pub struct Pipeline {
     pub pipeline: vk::Pipeline,
     pub layout: vk::PipelineLayout,
+    // Push constant ranges the layout was created with, used to validate pushes
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
+    // Meshes drawn with blending are ordered by it, see BlendMode
+    pub blend_mode: BlendMode,
+    // Format of the joint buffers of skinned meshes drawn with it
+    pub skinning_mode: SkinningMode,
+    // Back faces are drawn and lit from their own side, see is_two_sided_lighting
+    pub two_sided_lighting: bool,
+}
+
+// How a pipeline from add_mesh_pipeline was built, kept to build its double sided variant
+// (see set_mesh_double_sided)
+#[derive(Clone)]
+struct MeshPipelineDesc {
+    vert_shader_path: String,
+    frag_shader_path: String,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    has_texture: bool,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    blend_mode: BlendMode,
+    push_constant_size: u32,
+    stencil: Option<vk::StencilOpState>,
+    antialiasing: CutoutAntialiasing,
+    // GEOMETRY_SUBPASS, or LIGHTING_SUBPASS for pipelines from add_lighting_pipeline
+    subpass: u32,
 }
 
 // Structure to hold textured pipeline resources
@@ -114,6 +379,8 @@ struct TexturedPipelineResources {
     descriptor_set_layout: vk::DescriptorSetLayout,
     descriptor_pool: vk::DescriptorPool,
     descriptor_sets: Vec<vk::DescriptorSet>,
+    // Texture and sampler owned by the pipeline, destroyed along with it
+    texture: Option<(Texture, vk::Sampler)>,
 }
 
 #[repr(C, align(4))]
@@ -126,8 +393,258 @@ pub struct PushConstants {
     pub resolution: [f32; 2],    // offset 16, size 8
     pub water_level: f32,        // offset 24, size 4
     pub grid_scale: f32,         // offset 28, size 4
+    pub near_fade_start: f32,    // offset 32, size 4, see set_water_near_fade
+    pub near_fade_end: f32,      // offset 36, size 4
+    pub reflection_pass: f32,    // offset 40, 1 while drawing the water reflection, see enable_water_reflections
+    pub _padding: f32,           // offset 44, keeps the vec4s after it 16 byte aligned
+}
+
+// Runtime sky colors, see set_sky_gradient
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SkyGradient {
+    pub horizon: [f32; 3],
+    pub zenith: [f32; 3],
+    pub sun_direction: [f32; 3],  // Towards the sun, same convention as the directional light
+    pub sun_intensity: f32,
+}
+
+// Sway of a mesh drawn with a pipeline from add_wind_pipeline, see set_mesh_wind. amplitude is
+// the offset in world units per unit of the vertex's height above the mesh's origin, frequency
+// is in sways per second and direction is in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindParams {
+    pub amplitude: f32,
+    pub frequency: f32,
+    pub direction: Vec3,
+}
+
+impl Default for WindParams {
+    fn default() -> Self {
+        Self { amplitude: 0.05, frequency: 0.5, direction: Vec3::X }
+    }
+}
+
+// UVs of a mesh drawn with a pipeline from add_uv_transform_pipeline become
+// uv * scale + offset + scroll * time, see set_mesh_uv_transform and set_mesh_uv_scroll.
+// scroll is in UV units per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvTransform {
+    pub scale: Vec2,
+    pub offset: Vec2,
+    pub scroll: Vec2,
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self { scale: Vec2::ONE, offset: Vec2::ZERO, scroll: Vec2::ZERO }
+    }
+}
+
+// Push constants for the "sky" pipeline: the fluid constants followed by the gradient
+#[repr(C, align(4))]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkyPushConstants {
+    pub base: PushConstants,       // offset 0, size 48
+    pub horizon_color: [f32; 4],   // offset 48, alpha > 0 enables the gradient
+    pub zenith_color: [f32; 4],    // offset 64
+    pub sun_direction: [f32; 4],   // offset 80, w is the sun intensity
+}
+
+impl SkyPushConstants {
+    pub fn new(base: PushConstants, gradient: Option<&SkyGradient>) -> Self {
+        match gradient {
+            Some(gradient) => {
+                let [x, y, z] = gradient.sun_direction;
+                let length = (x * x + y * y + z * z).sqrt().max(f32::EPSILON);
+                Self {
+                    base,
+                    horizon_color: [gradient.horizon[0], gradient.horizon[1], gradient.horizon[2], 1.0],
+                    zenith_color: [gradient.zenith[0], gradient.zenith[1], gradient.zenith[2], 1.0],
+                    sun_direction: [x / length, y / length, z / length, gradient.sun_intensity],
+                }
+            }
+            // Zero alpha keeps the shader's built in sunset gradient
+            None => Self {
+                base,
+                horizon_color: [0.0; 4],
+                zenith_color: [0.0; 4],
+                sun_direction: [0.0; 4],
+            },
+        }
+    }
+}
+
+// Tessellation settings of a pipeline added with add_tessellated_water_pipeline
+#[derive(Clone, Copy, Debug)]
+pub struct WaterTessellation {
+    pub max_level: f32,           // Most an edge gets subdivided, devices support at least 64
+    pub target_edge_pixels: f32,  // Edges are split down to about this length on screen, 0 for a uniform max_level
+    pub displacement_scale: f32,  // World space height of a white texel in the height texture
+    pub height_tiling: f32,       // Height texture repeats per world unit
+}
+
+impl Default for WaterTessellation {
+    fn default() -> Self {
+        Self {
+            max_level: 16.0,
+            target_edge_pixels: 20.0,
+            displacement_scale: 0.1,
+            height_tiling: 0.25,
+        }
+    }
+}
+
+// Push constants for tessellated water: the fluid constants followed by the tessellation settings
+#[repr(C, align(4))]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct WaterTessellationPushConstants {
+    pub base: PushConstants,       // offset 0, size 48
+    pub max_level: f32,            // offset 48
+    pub target_edge_pixels: f32,   // offset 52
+    pub displacement_scale: f32,   // offset 56
+    pub height_tiling: f32,        // offset 60
+}
+
+impl WaterTessellationPushConstants {
+    pub fn new(base: PushConstants, tessellation: &WaterTessellation) -> Self {
+        Self {
+            base,
+            max_level: tessellation.max_level,
+            target_edge_pixels: tessellation.target_edge_pixels,
+            displacement_scale: tessellation.displacement_scale,
+            height_tiling: tessellation.height_tiling,
+        }
+    }
+}
+
+// Every stage of the tessellated water pipeline reads the push constants
+const WATER_TESSELLATION_STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(
+    vk::ShaderStageFlags::VERTEX.as_raw()
+        | vk::ShaderStageFlags::TESSELLATION_CONTROL.as_raw()
+        | vk::ShaderStageFlags::TESSELLATION_EVALUATION.as_raw()
+        | vk::ShaderStageFlags::FRAGMENT.as_raw(),
+);
+
+// Settings that aren't tied to a constructor, applied with VulkanRenderer::with_config
+#[derive(Clone, Copy, Debug)]
+pub struct RendererConfig {
+    // Threads decoding streamed textures, defaults to min(4, available cores). 0 decodes and
+    // uploads synchronously on the calling thread, e.g. to keep CI runs deterministic.
+    pub upload_threads: usize,
+    // Blocks of the growable pool mesh textures and skinned meshes allocate their sets from
+    pub descriptor_pool_sizes: DescriptorPoolSizes,
+    // Threads record_parallel splits its items across, defaults to the available cores. Read
+    // when record_parallel first runs, which creates a command pool per thread.
+    pub record_threads: usize,
+    // Bytes of the joint arena skinned meshes share (see joint_arena), defaults to 4 MiB. Read
+    // when the first skinned mesh is added. 0 gives every skinned mesh its own buffers and sets.
+    pub skinned_joint_arena_size: vk::DeviceSize,
+    // Bytes per frame in flight of the dynamic buffer ring that immediate mode geometry (dashed
+    // lines, debug boxes) is written into, defaults to 4 MiB. Read on the first immediate draw.
+    pub dynamic_buffer_budget: vk::DeviceSize,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        let available = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Self {
+            upload_threads: available.min(4),
+            descriptor_pool_sizes: DescriptorPoolSizes::default(),
+            record_threads: available,
+            skinned_joint_arena_size: 4 * 1024 * 1024,
+            dynamic_buffer_budget: 4 * 1024 * 1024,
+        }
+    }
+}
+
+// A camera drawn over the scene in the same frame, see VulkanRenderer::queue_overlay_pass
+struct OverlayPass {
+    view: Mat4,
+    proj: Mat4,
+    meshes: Vec<usize>,
+    clear: ClearFlags,
+}
+
+// Counters for the last frame recorded by the multi mesh render path, see
+// VulkanRenderer::last_frame_stats. Instances and triangles of GPU culled meshes count every
+// instance since the survivors are only known on the GPU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub draw_calls: u32,
+    pub triangles: u64,
+    pub instances: u32,
+    pub pipeline_switches: u32,
+    // Occlusion culled meshes that only had their bounding box drawn
+    pub culled_meshes: u32,
+    pub descriptor_binds: u32,
+}
+
+impl FrameStats {
+    // What executing a draw plan records, without a device
+    pub fn from_draw_plan(meshes: &[MeshEntry], plan: &[DrawCommand]) -> Self {
+        let mut stats = Self::default();
+        for command in plan {
+            match *command {
+                DrawCommand::BindPipeline(_) => stats.pipeline_switches += 1,
+                DrawCommand::BindDescriptorSet(..) => stats.descriptor_binds += 1,
+                DrawCommand::DrawOcclusionProxy { mesh, .. } => {
+                    stats.culled_meshes += 1;
+                    stats.draw_calls += meshes[mesh].transforms.len() as u32;
+                }
+                DrawCommand::DrawInstanced { mesh } => {
+                    stats.count_draw(&meshes[mesh], meshes[mesh].instance_count);
+                }
+                DrawCommand::DrawTransforms { mesh, .. } => {
+                    for _ in &meshes[mesh].transforms {
+                        stats.count_draw(&meshes[mesh], 1);
+                    }
+                }
+                DrawCommand::DrawWireframeOverlay { mesh } if meshes[mesh].use_instancing => {
+                    stats.count_draw(&meshes[mesh], meshes[mesh].instance_count);
+                }
+                DrawCommand::DrawWireframeOverlay { mesh } => {
+                    for _ in &meshes[mesh].transforms {
+                        stats.count_draw(&meshes[mesh], 1);
+                    }
+                }
+            }
+        }
+        stats
+    }
+    
+    fn count_draw(&mut self, mesh: &MeshEntry, instance_count: u32) {
+        let vertices = match mesh.draw_mode {
+            MeshDrawMode::Indexed => mesh.index_count,
+            MeshDrawMode::NonIndexed => mesh.vertex_count,
+        };
+        self.draw_calls += 1;
+        self.instances += instance_count;
+        self.triangles += u64::from(vertices / 3) * u64::from(instance_count);
+    }
+}
+
+impl std::ops::AddAssign for FrameStats {
+    fn add_assign(&mut self, other: Self) {
+        self.draw_calls += other.draw_calls;
+        self.triangles += other.triangles;
+        self.instances += other.instances;
+        self.pipeline_switches += other.pipeline_switches;
+        self.culled_meshes += other.culled_meshes;
+        self.descriptor_binds += other.descriptor_binds;
+    }
 }
 
+// See VulkanRenderer::set_pre_draw_hook. Send so the renderer stays Send.
+pub type PreDrawHook = Box<dyn Fn(vk::CommandBuffer, usize, vk::PipelineLayout) + Send>;
+
+// Threading: the renderer is Send but not Sync. Vulkan requires external synchronization of
+// the queue, command pool and the per-frame command buffers and fences, and every method that
+// touches them takes &mut self, so one thread at a time owns it. It can be moved to another
+// thread (e.g. created on one and rendered on another, or shared behind a Mutex), but keeping
+// it on the thread that owns the window is the safe default: some platforms only allow window
+// handles, and so surface and swapchain recreation, on that thread. In Bevy that means a
+// NonSend resource, see the README. Texture streaming decodes files on worker threads and
+// sends plain pixel data back; nothing Vulkan related leaves the owning thread.
 pub struct VulkanRenderer {
     pub(crate) core: VulkanCore,
     pipeline_layout: vk::PipelineLayout,  // Default pipeline layout (for compatibility)
@@ -162,36 +679,165 @@ pub struct VulkanRenderer {
     
     // Textured pipelines (for multi-texture support)
     textured_pipelines: std::collections::HashMap<String, TexturedPipelineResources>,
+    // By pipeline name, for the pipelines double sided meshes can be drawn with
+    mesh_pipeline_descs: std::collections::HashMap<String, MeshPipelineDesc>,
+    texture_streamer: TextureStreamer,
+    config: RendererConfig,
+    // Runtime descriptor sets, see RendererConfig::descriptor_pool_sizes
+    descriptor_allocator: DescriptorAllocator,
+    max_texture_size: Option<u32>,  // User cap on texture array layers, see max_texture_size
+    reverse_z: bool,
+    occlusion_query_meshes: Vec<Vec<usize>>,  // Mesh index per occlusion query, per frame in flight
+    instance_cull_pipeline: Option<InstanceCullPipeline>,  // Created on first enable_gpu_culling
+    bone_hierarchy_pipeline: Option<BoneHierarchyPipeline>,  // Created on first set_bone_hierarchy
+    joint_arena: Option<JointArena>,  // Created on the first skinned mesh that fits in it
+    particles: Option<ParticleSystem>,  // Created on first spawn_particles
+    dashed_lines: Option<DashedLines>,  // Created on first draw_dashed_line
+    ground_grid: Option<GroundGrid>,  // Created by enable_ground_grid
+    debug_boxes: Option<DebugBoxes>,  // Created on first draw_boxes_instanced
+    dynamic_ring: Option<DynamicBufferRing>,  // Created with the first immediate mode renderer
+    // Drawn after the scene by the next multi mesh frame, see queue_overlay_pass
+    overlay_passes: Vec<OverlayPass>,
+    shared_layouts: Option<SharedDescriptorLayouts>,  // Created on first shared_descriptor_layout
+    // Vertex data copied at the start of the next frame (see upload_vertex_data)
+    pending_vertex_uploads: Vec<PendingVertexUpload>,
+    // Host visible (buffer, memory, size) per frame in flight, grown to fit the frame's uploads
+    vertex_staging_buffers: Vec<Option<(vk::Buffer, vk::DeviceMemory, vk::DeviceSize)>>,
+    post_process: Option<PostProcessChain>,  // Created on first add_post_effect or set_antialiasing
+    antialiasing: AaMode,
+    fxaa_settings: FxaaSettings,
+    // Last applied with apply_settings, antialiasing is kept in its own field
+    render_settings: RenderSettings,
+    shadow_config: ShadowConfig,  // Only stored, for the application's shadow pass
+    sky_gradient: Option<SkyGradient>,  // None uses the sky shader's built in gradient
+    render_targets: Vec<Option<RenderTarget>>,  // Indexed by RenderTargetId, None once destroyed
+    water_tessellation: std::collections::HashMap<String, WaterTessellation>,  // By pipeline name
+    water_pipeline_desc: Option<WaterPipelineDesc>,  // Set once a "water" fluid pipeline is added
+    water_reflections: Option<WaterReflections>,
+    previous_frame: Option<PreviousFrame>,  // See enable_previous_frame_access
+    tonemap: Tonemap,
+    exposure: f32,  // HDR scene color is multiplied by this before tonemapping
+    resolution_scale: f32,  // Of the swapchain extent, see set_resolution_scale
+    upscale_filter: UpscaleFilter,
+    dynamic_resolution: Option<DynamicResolution>,
+    frames_until_resolution_update: u32,
+    mesh_hierarchy_dirty: bool,  // Parented meshes' world transforms are resolved before the next frame
+    clear_color: [f32; 4],  // Linear, see set_clear_color
+    static_scene: bool,
+    // Secondary command buffers per swapchain image, allocated on first set_static_scene(true)
+    static_scene_command_buffers: Vec<vk::CommandBuffer>,
+    dynamic_scene_command_buffers: Vec<vk::CommandBuffer>,
+    // View and projection each image's static buffer was recorded with, None when it has to be re-recorded
+    static_scene_recorded_with: Vec<Option<(Mat4, Mat4)>>,
+    // Stats of each image's static buffer, added to the frame's stats whenever it's replayed
+    static_scene_stats: Vec<FrameStats>,
+    // Created on the first record_parallel
+    parallel_recorder: Option<ParallelRecorder>,
+    // Frame in flight and secondary buffers from record_parallel, executed by the next multi mesh frame
+    parallel_draws: Option<(usize, Vec<vk::CommandBuffer>)>,
+    pre_draw_hook: Option<PreDrawHook>,
+    debug_view: DebugView,  // See set_debug_view
+    frame_stats: FrameStats,  // Reset when a frame starts recording
+    // Scene sub-rectangles in pixels, None for the full extent (see set_viewport)
+    viewport: Option<vk::Rect2D>,
+    scissor: Option<vk::Rect2D>,
+    // Keeps the renderer !Sync even if the fields above stop implying it, see the comment above
+    _not_sync: std::marker::PhantomData<std::cell::Cell<()>>,
 }
 
 impl VulkanRenderer {
+    // Whether Vulkan and a GPU with a graphics queue are present, so apps can show a message and
+    // tests can skip instead of failing in the constructors. check_vulkan_support has the reason.
+    pub fn is_available() -> bool {
+        check_vulkan_support().is_ok()
+    }
+    
+    // Replaces the default RendererConfig, call it right after constructing the renderer:
+    // texture loads already in flight are dropped.
+    pub fn with_config(mut self, config: RendererConfig) -> Self {
+        self.texture_streamer = TextureStreamer::new(config.upload_threads);
+        self.descriptor_allocator.set_pool_sizes(config.descriptor_pool_sizes);
+        self.config = config;
+        self
+    }
+    
+    pub fn config(&self) -> &RendererConfig {
+        &self.config
+    }
+    
+    // Applies what can change on a live renderer: a new present mode recreates the swapchain,
+    // anisotropy applies to textures loaded afterwards, and the shadow quality is only stored
+    // for the application's own shadow pass. Returns the names of the fields that need the
+    // renderer to be recreated with new_multi_mesh_with_settings, currently only device.
+    // Unsupported antialiasing modes error before anything changes.
+    pub fn apply_settings(&mut self, settings: &RenderSettings) -> Result<Vec<&'static str>, FloError> {
+        self.set_antialiasing(settings.antialiasing)?;
+        self.core.set_present_mode(settings.swapchain_present_mode())?;
+        self.core.max_anisotropy = settings.max_anisotropy;
+        
+        let mut restart_required = Vec::new();
+        if settings.device != self.render_settings.device {
+            restart_required.push("device");
+        }
+        self.render_settings = RenderSettings {
+            device: self.render_settings.device.clone(),
+            ..settings.clone()
+        };
+        Ok(restart_required)
+    }
+    
+    // The settings in effect, to save along with the application's other options
+    pub fn render_settings(&self) -> RenderSettings {
+        RenderSettings {
+            antialiasing: self.antialiasing,
+            ..self.render_settings.clone()
+        }
+    }
+    
+    // The renderer has no shadow pass, so this is where the application's shadow pass and
+    // shaders read their resolution, biases and PCF kernel from
+    pub fn set_shadow_config(&mut self, config: ShadowConfig) {
+        self.shadow_config = config;
+    }
+    
+    pub fn shadow_config(&self) -> ShadowConfig {
+        self.shadow_config
+    }
+    
+    // Largest width or height of a texture array layer: the device's maxImageDimension2D, or
+    // the max size passed to new_texture_array_with_max_size if that's smaller
+    pub fn max_texture_size(&self) -> u32 {
+        let limits = unsafe { self.core.instance.get_physical_device_properties(self.core.physical_device) }.limits;
+        capped_texture_size(limits.max_image_dimension2_d, self.max_texture_size)
+    }
+    
     // Helper constructor for MeshData
     pub fn new_from_mesh_data(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         mesh_data: &MeshData,
         instance_count: u32,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
         Self::new_from_mesh_data_with_winding(window_handle, vert_shader_path, frag_shader_path, mesh_data, instance_count, None)
     }
 
     pub fn new_from_mesh_data_with_winding(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         mesh_data: &MeshData,
         instance_count: u32,
         front_face: Option<vk::FrontFace>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
         Self::new_mesh_with_winding(
             window_handle,
             vert_shader_path,
             frag_shader_path,
             &mesh_data.vertices,
             &mesh_data.indices,
-            vec![Vertex::get_binding_description()],
-            Vertex::get_attribute_descriptions(),
+            vec![Vertex::binding_description()],
+            Vertex::attribute_descriptions(),
             instance_count,
             front_face,
         )
@@ -199,11 +845,11 @@ impl VulkanRenderer {
     
     // Constructor for simple triangle/cube (no buffers)
     pub fn new_simple(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         vertex_count: u32,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
         let with_depth = vertex_count == 36; // Cube needs depth
         let core = VulkanCore::new(window_handle, with_depth)?;
         
@@ -211,7 +857,7 @@ impl VulkanRenderer {
             vec![vk::PushConstantRange::default()
                 .stage_flags(vk::ShaderStageFlags::VERTEX)
                 .offset(0)
-                .size(4)]
+                .size(mem::size_of::<PushConstants>() as u32)]
         } else {
             Vec::new()
         };
@@ -224,7 +870,7 @@ impl VulkanRenderer {
             core.render_pass,
         )?
         .with_vertex_input(Vec::new(), Vec::new())
-        .with_push_constants(push_constants)
+        .with_push_constants(push_constants.clone())
         .with_depth_test(with_depth)
         .with_cull_mode(vk::CullModeFlags::NONE)
         .with_front_face(vk::FrontFace::COUNTER_CLOCKWISE)
@@ -234,11 +880,15 @@ impl VulkanRenderer {
         pipelines.insert("default".to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: push_constants,
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
         
         let memory_pool = MemoryPoolManager::new(core.device.clone());
         
-        Ok(Self {
+        let renderer = Self {
             core,
             pipeline_layout,
             graphics_pipeline,
@@ -256,12 +906,66 @@ impl VulkanRenderer {
             egui_integration: None,
             water_push_constants: None,
             textured_pipelines: std::collections::HashMap::new(),
-        })
+            mesh_pipeline_descs: std::collections::HashMap::new(),
+            texture_streamer: TextureStreamer::new(RendererConfig::default().upload_threads),
+            config: RendererConfig::default(),
+            descriptor_allocator: DescriptorAllocator::new(DescriptorPoolSizes::default()),
+            max_texture_size: None,
+            reverse_z: false,
+            occlusion_query_meshes: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            instance_cull_pipeline: None,
+            bone_hierarchy_pipeline: None,
+            joint_arena: None,
+            particles: None,
+            dashed_lines: None,
+            ground_grid: None,
+            debug_boxes: None,
+            dynamic_ring: None,
+            overlay_passes: Vec::new(),
+            shared_layouts: None,
+            pending_vertex_uploads: Vec::new(),
+            vertex_staging_buffers: vec![None; MAX_FRAMES_IN_FLIGHT],
+            post_process: None,
+            antialiasing: AaMode::None,
+            render_settings: RenderSettings::default(),
+            shadow_config: ShadowConfig::default(),
+            fxaa_settings: FxaaSettings::default(),
+            sky_gradient: None,
+            render_targets: Vec::new(),
+            water_tessellation: std::collections::HashMap::new(),
+            water_pipeline_desc: None,
+            water_reflections: None,
+            previous_frame: None,
+            tonemap: Tonemap::default(),
+            resolution_scale: MAX_RESOLUTION_SCALE,
+            upscale_filter: UpscaleFilter::default(),
+            dynamic_resolution: None,
+            frames_until_resolution_update: 0,
+            mesh_hierarchy_dirty: false,
+            exposure: 1.0,
+            clear_color: CLEAR_COLOR_MAGENTA,
+            static_scene: false,
+            static_scene_command_buffers: Vec::new(),
+            dynamic_scene_command_buffers: Vec::new(),
+            static_scene_recorded_with: Vec::new(),
+            static_scene_stats: Vec::new(),
+            parallel_recorder: None,
+            pre_draw_hook: None,
+            debug_view: DebugView::None,
+            parallel_draws: None,
+            frame_stats: FrameStats::default(),
+            viewport: None,
+            scissor: None,
+            _not_sync: std::marker::PhantomData,
+        };
+        renderer.set_debug_names();
+        
+        Ok(renderer)
     }
     
     // Constructor for mesh rendering (with buffers)
     pub fn new_mesh<T: Copy>(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         vertices: &[T],
@@ -269,12 +973,33 @@ impl VulkanRenderer {
         binding_descriptions: Vec<vk::VertexInputBindingDescription>,
         attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
         instance_count: u32,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
         Self::new_mesh_with_winding(window_handle, vert_shader_path, frag_shader_path, vertices, indices, binding_descriptions, attribute_descriptions, instance_count, None)
     }
 
+    // new_mesh with the vertex input taken from T
+    pub fn new_mesh_with_format<T: VertexFormat>(
+        window_handle: &impl WindowHandles,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        vertices: &[T],
+        indices: &[u32],
+        instance_count: u32,
+    ) -> Result<Self, FloError> {
+        Self::new_mesh(
+            window_handle,
+            vert_shader_path,
+            frag_shader_path,
+            vertices,
+            indices,
+            vec![T::binding_description()],
+            T::attribute_descriptions(),
+            instance_count,
+        )
+    }
+
     pub fn new_mesh_with_winding<T: Copy>(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         vertices: &[T],
@@ -283,7 +1008,8 @@ impl VulkanRenderer {
         attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
         instance_count: u32,
         _front_face: Option<vk::FrontFace>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
+        validate_mesh_geometry(vertices.len(), indices.len())?;
         let core = VulkanCore::new(window_handle, true)?;
         
         // Create memory pool first
@@ -309,11 +1035,11 @@ impl VulkanRenderer {
             indices,
         )?;
         
-        // Configure push constants for MVP matrices
+        // Configure push constants for the MVP matrices and base color
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .offset(0)
-            .size(mem::size_of::<[f32; 16]>() as u32 * 3);
+            .size(MVP_PUSH_CONSTANTS_SIZE);
         
         let (graphics_pipeline, pipeline_layout) = PipelineBuilder::new(
             core.device.clone(),
@@ -343,9 +1069,13 @@ impl VulkanRenderer {
         pipelines.insert("default".to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
         
-        Ok(Self {
+        let renderer = Self {
             core,
             pipeline_layout,
             graphics_pipeline,
@@ -363,18 +1093,208 @@ impl VulkanRenderer {
             egui_integration: None,
             water_push_constants: None,
             textured_pipelines: std::collections::HashMap::new(),
-        })
+            mesh_pipeline_descs: std::collections::HashMap::new(),
+            texture_streamer: TextureStreamer::new(RendererConfig::default().upload_threads),
+            config: RendererConfig::default(),
+            descriptor_allocator: DescriptorAllocator::new(DescriptorPoolSizes::default()),
+            max_texture_size: None,
+            reverse_z: false,
+            occlusion_query_meshes: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            instance_cull_pipeline: None,
+            bone_hierarchy_pipeline: None,
+            joint_arena: None,
+            particles: None,
+            dashed_lines: None,
+            ground_grid: None,
+            debug_boxes: None,
+            dynamic_ring: None,
+            overlay_passes: Vec::new(),
+            shared_layouts: None,
+            pending_vertex_uploads: Vec::new(),
+            vertex_staging_buffers: vec![None; MAX_FRAMES_IN_FLIGHT],
+            post_process: None,
+            antialiasing: AaMode::None,
+            render_settings: RenderSettings::default(),
+            shadow_config: ShadowConfig::default(),
+            fxaa_settings: FxaaSettings::default(),
+            sky_gradient: None,
+            render_targets: Vec::new(),
+            water_tessellation: std::collections::HashMap::new(),
+            water_pipeline_desc: None,
+            water_reflections: None,
+            previous_frame: None,
+            tonemap: Tonemap::default(),
+            resolution_scale: MAX_RESOLUTION_SCALE,
+            upscale_filter: UpscaleFilter::default(),
+            dynamic_resolution: None,
+            frames_until_resolution_update: 0,
+            mesh_hierarchy_dirty: false,
+            exposure: 1.0,
+            clear_color: CLEAR_COLOR_MAGENTA,
+            static_scene: false,
+            static_scene_command_buffers: Vec::new(),
+            dynamic_scene_command_buffers: Vec::new(),
+            static_scene_recorded_with: Vec::new(),
+            static_scene_stats: Vec::new(),
+            parallel_recorder: None,
+            pre_draw_hook: None,
+            debug_view: DebugView::None,
+            parallel_draws: None,
+            frame_stats: FrameStats::default(),
+            viewport: None,
+            scissor: None,
+            _not_sync: std::marker::PhantomData,
+        };
+        renderer.set_debug_names();
+        
+        Ok(renderer)
     }
     
     // Constructor for textured rendering
     // Constructor for texture array rendering
     pub fn new_texture_array(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        mesh_data: &TexturedMeshData,
+        textures: &[TextureData],
+    ) -> Result<Self, FloError> {
+        Self::new_texture_array_with_max_size(window_handle, vert_shader_path, frag_shader_path, mesh_data, textures, None)
+    }
+
+    pub fn new_texture_array_with_winding(
+        window_handle: &impl WindowHandles,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        mesh_data: &TexturedMeshData,
+        textures: &[TextureData],
+        front_face: Option<vk::FrontFace>,
+    ) -> Result<Self, FloError> {
+        Self::new_texture_array_with_max_size_and_winding(window_handle, vert_shader_path, frag_shader_path, mesh_data, textures, None, front_face)
+    }
+    
+    // Every layer is as big as the largest texture, so a few large textures make every layer
+    // large. max_texture_size caps the layers below the device's maxImageDimension2D to save
+    // memory, None only applies the device limit. Textures over the cap are downscaled with a warning.
+    pub fn new_texture_array_with_max_size(
+        window_handle: &impl WindowHandles,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        mesh_data: &TexturedMeshData,
+        textures: &[TextureData],
+        max_texture_size: Option<u32>,
+    ) -> Result<Self, FloError> {
+        Self::new_texture_array_with_max_size_and_winding(window_handle, vert_shader_path, frag_shader_path, mesh_data, textures, max_texture_size, None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_texture_array_with_max_size_and_winding(
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         mesh_data: &TexturedMeshData,
         textures: &[TextureData],
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+        max_texture_size: Option<u32>,
+        front_face: Option<vk::FrontFace>,
+    ) -> Result<Self, FloError> {
+        let front_face = front_face.unwrap_or(vk::FrontFace::COUNTER_CLOCKWISE);
+        let mut renderer = Self::new_texture_array_with(window_handle, vert_shader_path, frag_shader_path, mesh_data, front_face, |core| {
+            let limits = unsafe { core.instance.get_physical_device_properties(core.physical_device) }.limits;
+            let max_size = capped_texture_size(limits.max_image_dimension2_d, max_texture_size);
+            
+            let max_layers = limits.max_image_array_layers as usize;
+            if textures.len() > max_layers {
+                warn!(
+                    "{} textures but the device allows {} array layers, dropping layers {} and up",
+                    textures.len(), max_layers, max_layers
+                );
+            }
+            let textures = &textures[..textures.len().min(max_layers)];
+            for (layer, texture) in textures.iter().enumerate() {
+                if texture.width > max_size || texture.height > max_size {
+                    warn!(
+                        "Texture array layer {} is {}x{}, downscaling it to fit the max texture size {}",
+                        layer, texture.width, texture.height, max_size
+                    );
+                }
+            }
+            
+            // Layers are resized to the largest texture, capped at the max size
+            let max_width = textures.iter().map(|t| t.width.min(max_size)).max().unwrap_or(1);
+            let max_height = textures.iter().map(|t| t.height.min(max_size)).max().unwrap_or(1);
+            let layer_count = textures.len() as u32;
+
+            let (texture_array, texture_array_memory) = create_texture_array(
+                &core.instance,
+                &core.device,
+                core.physical_device,
+                core.command_pool,
+                core.graphics_queue,
+                textures,
+                max_width,
+                max_height,
+            )?;
+
+            let texture_array_view = create_texture_array_view(&core.device, texture_array, vk::Format::R8G8B8A8_SRGB, layer_count, 1)?;
+            // Model textures keep glTF's default REPEAT wrap
+            let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device, core.max_anisotropy, SamplerAddressModes::REPEAT)?;
+            Ok((texture_array, texture_array_memory, texture_array_view, texture_sampler))
+        })?;
+        renderer.max_texture_size = max_texture_size;
+        Ok(renderer)
+    }
+
+    // Texture array renderer whose layers and mip levels come from a KTX2 file as they are,
+    // in the file's format and size
+    pub fn new_texture_array_from_ktx2(
+        window_handle: &impl WindowHandles,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        mesh_data: &TexturedMeshData,
+        texture_array_data: &TextureArrayData,
+    ) -> Result<Self, FloError> {
+        Self::new_texture_array_with(window_handle, vert_shader_path, frag_shader_path, mesh_data, vk::FrontFace::COUNTER_CLOCKWISE, |core| {
+            let properties = unsafe { core.instance.get_physical_device_properties(core.physical_device) };
+            if texture_array_data.layer_count > properties.limits.max_image_array_layers {
+                return Err(format!(
+                    "Texture array has {} layers but the device allows {}",
+                    texture_array_data.layer_count, properties.limits.max_image_array_layers
+                ).into());
+            }
+
+            let (texture_array, texture_array_memory) = create_texture_array_with_mips(
+                &core.instance,
+                &core.device,
+                core.physical_device,
+                core.command_pool,
+                core.graphics_queue,
+                texture_array_data,
+            )?;
+
+            let mip_levels = texture_array_data.mip_levels();
+            let texture_array_view = create_texture_array_view(
+                &core.device,
+                texture_array,
+                texture_array_data.format,
+                texture_array_data.layer_count,
+                mip_levels,
+            )?;
+            let texture_sampler = create_mipmapped_texture_sampler(&core.instance, &core.device, core.physical_device, mip_levels, core.max_anisotropy, SamplerAddressModes::REPEAT)?;
+            Ok((texture_array, texture_array_memory, texture_array_view, texture_sampler))
+        })
+    }
+
+    // Shared by the texture array constructors, create_array returns the array's image, memory,
+    // view and sampler
+    fn new_texture_array_with(
+        window_handle: &impl WindowHandles,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        mesh_data: &TexturedMeshData,
+        front_face: vk::FrontFace,
+        create_array: impl FnOnce(&VulkanCore) -> Result<(vk::Image, vk::DeviceMemory, vk::ImageView, vk::Sampler), FloError>,
+    ) -> Result<Self, FloError> {
+        validate_mesh_geometry(mesh_data.vertices.len(), mesh_data.indices.len())?;
         let core = VulkanCore::new(window_handle, true)?;
         
         // Create vertex buffer
@@ -398,24 +1318,7 @@ impl VulkanRenderer {
         )?;
         
         // Create texture array
-        const MAX_TEXTURE_SIZE: u32 = 512;
-        let max_width = textures.iter().map(|t| t.width.min(MAX_TEXTURE_SIZE)).max().unwrap_or(1);
-        let max_height = textures.iter().map(|t| t.height.min(MAX_TEXTURE_SIZE)).max().unwrap_or(1);
-        let layer_count = textures.len().min(256) as u32;
-        
-        let (texture_array, texture_array_memory) = create_texture_array(
-            &core.instance,
-            &core.device,
-            core.physical_device,
-            core.command_pool,
-            core.graphics_queue,
-            textures,
-            max_width,
-            max_height,
-        )?;
-        
-        let texture_array_view = create_texture_array_view(&core.device, texture_array, layer_count)?;
-        let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device)?;
+        let (texture_array, texture_array_memory, texture_array_view, texture_sampler) = create_array(&core)?;
         
         // Create descriptor resources
         let binding = vk::DescriptorSetLayoutBinding::default()
@@ -445,8 +1348,8 @@ impl VulkanRenderer {
             .offset(0)
             .size(mem::size_of::<[f32; 16]>() as u32);
         
-        let binding_descriptions = vec![TexturedVertex::get_binding_description()];
-        let attribute_descriptions = TexturedVertex::get_attribute_descriptions();
+        let binding_descriptions = vec![TexturedVertex::binding_description()];
+        let attribute_descriptions = TexturedVertex::attribute_descriptions();
         
         let (graphics_pipeline, pipeline_layout) = PipelineBuilder::new(
             core.device.clone(),
@@ -460,7 +1363,7 @@ impl VulkanRenderer {
         .with_descriptor_sets(vec![descriptor_set_layout])
         .with_depth_test(true)
         .with_cull_mode(vk::CullModeFlags::BACK)
-        .with_front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .with_front_face(front_face)
         .build()?;
         
         let buffers = BufferResources {
@@ -487,11 +1390,15 @@ impl VulkanRenderer {
         pipelines.insert("default".to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
         
         let memory_pool = MemoryPoolManager::new(core.device.clone());
         
-        Ok(Self {
+        let renderer = Self {
             core,
             pipeline_layout,
             graphics_pipeline,
@@ -509,18 +1416,73 @@ impl VulkanRenderer {
             egui_integration: None,
             water_push_constants: None,
             textured_pipelines: std::collections::HashMap::new(),
-        })
+            mesh_pipeline_descs: std::collections::HashMap::new(),
+            texture_streamer: TextureStreamer::new(RendererConfig::default().upload_threads),
+            config: RendererConfig::default(),
+            descriptor_allocator: DescriptorAllocator::new(DescriptorPoolSizes::default()),
+            max_texture_size: None,
+            reverse_z: false,
+            occlusion_query_meshes: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            instance_cull_pipeline: None,
+            bone_hierarchy_pipeline: None,
+            joint_arena: None,
+            particles: None,
+            dashed_lines: None,
+            ground_grid: None,
+            debug_boxes: None,
+            dynamic_ring: None,
+            overlay_passes: Vec::new(),
+            shared_layouts: None,
+            pending_vertex_uploads: Vec::new(),
+            vertex_staging_buffers: vec![None; MAX_FRAMES_IN_FLIGHT],
+            post_process: None,
+            antialiasing: AaMode::None,
+            render_settings: RenderSettings::default(),
+            shadow_config: ShadowConfig::default(),
+            fxaa_settings: FxaaSettings::default(),
+            sky_gradient: None,
+            render_targets: Vec::new(),
+            water_tessellation: std::collections::HashMap::new(),
+            water_pipeline_desc: None,
+            water_reflections: None,
+            previous_frame: None,
+            tonemap: Tonemap::default(),
+            resolution_scale: MAX_RESOLUTION_SCALE,
+            upscale_filter: UpscaleFilter::default(),
+            dynamic_resolution: None,
+            frames_until_resolution_update: 0,
+            mesh_hierarchy_dirty: false,
+            exposure: 1.0,
+            clear_color: CLEAR_COLOR_MAGENTA,
+            static_scene: false,
+            static_scene_command_buffers: Vec::new(),
+            dynamic_scene_command_buffers: Vec::new(),
+            static_scene_recorded_with: Vec::new(),
+            static_scene_stats: Vec::new(),
+            parallel_recorder: None,
+            pre_draw_hook: None,
+            debug_view: DebugView::None,
+            parallel_draws: None,
+            frame_stats: FrameStats::default(),
+            viewport: None,
+            scissor: None,
+            _not_sync: std::marker::PhantomData,
+        };
+        renderer.set_debug_names();
+        
+        Ok(renderer)
     }
     
     // Constructor for instanced textured rendering
     pub fn new_textured_instanced(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         mesh_data: &MeshData,
         texture_path: Option<&str>,
         instance_positions: &[[f32; 3]],
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
+        validate_mesh_geometry(mesh_data.vertices.len(), mesh_data.indices.len())?;
         let core = VulkanCore::new(window_handle, true)?;
         
         // Create vertex buffer for mesh data
@@ -543,39 +1505,18 @@ impl VulkanRenderer {
             &mesh_data.indices,
         )?;
         
-        // Create instance buffer
-        let (instance_buffer, instance_buffer_memory) = create_buffer(
+        // Create instance buffer, skipped for an empty instance list
+        let instance_buffer = create_instance_buffer(
             &core.instance,
             &core.device,
             core.physical_device,
-            (instance_positions.len() * std::mem::size_of::<[f32; 3]>()) as vk::DeviceSize,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            instance_positions,
         )?;
         
-        // Copy instance data to buffer
-        unsafe {
-            let data = core.device.map_memory(
-                instance_buffer_memory,
-                0,
-                (instance_positions.len() * std::mem::size_of::<[f32; 3]>()) as vk::DeviceSize,
-                vk::MemoryMapFlags::empty(),
-            )?;
-            std::ptr::copy_nonoverlapping(
-                instance_positions.as_ptr() as *const u8,
-                data as *mut u8,
-                instance_positions.len() * std::mem::size_of::<[f32; 3]>(),
-            );
-            core.device.unmap_memory(instance_buffer_memory);
-        }
-        
         // Vertex input configuration - need both per-vertex and per-instance attributes
         let binding_descriptions = vec![
             // Per-vertex data
-            vk::VertexInputBindingDescription::default()
-                .binding(0)
-                .stride(std::mem::size_of::<Vertex>() as u32)
-                .input_rate(vk::VertexInputRate::VERTEX),
+            Vertex::binding_description(),
             // Per-instance data
             vk::VertexInputBindingDescription::default()
                 .binding(1)
@@ -583,10 +1524,10 @@ impl VulkanRenderer {
                 .input_rate(vk::VertexInputRate::INSTANCE),
         ];
         
-        let mut attribute_descriptions = Vertex::get_attribute_descriptions();
+        let mut attribute_descriptions = Vertex::attribute_descriptions();
         
-        // Add instance position attribute (location depends on whether we have UVs)
-        let instance_location = if texture_path.is_some() { 3 } else { 2 };
+        // Instance position goes right after the vertex attributes
+        let instance_location = mesh::next_attribute_location(&attribute_descriptions);
         attribute_descriptions.push(
             vk::VertexInputAttributeDescription::default()
                 .binding(1)
@@ -608,7 +1549,7 @@ impl VulkanRenderer {
             )?;
             
             let texture_image_view = crate::vulkan_common::create_texture_image_view(&core.device, texture_image)?;
-            let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device)?;
+            let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device, core.max_anisotropy, SamplerAddressModes::REPEAT)?;
             
             // Create descriptor resources
             let binding = vk::DescriptorSetLayoutBinding::default()
@@ -648,11 +1589,11 @@ impl VulkanRenderer {
             (None, None)
         };
         
-        // Configure push constants for time
+        // Configure push constants for time and the scene's size
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .offset(0)
-            .size(std::mem::size_of::<f32>() as u32);
+            .size(mem::size_of::<PushConstants>() as u32);
         
         // Build pipeline
         let mut pipeline_builder = PipelineBuilder::new(
@@ -680,19 +1621,23 @@ impl VulkanRenderer {
             index_buffer: Some(index_buffer),
             index_buffer_memory: Some(index_buffer_memory),
             index_count: mesh_data.indices.len() as u32,
-            instance_buffer: Some(instance_buffer),
-            instance_buffer_memory: Some(instance_buffer_memory),
+            instance_buffer: instance_buffer.map(|(buffer, _)| buffer),
+            instance_buffer_memory: instance_buffer.map(|(_, memory)| memory),
         };
         
         let mut pipelines = std::collections::HashMap::new();
         pipelines.insert("default".to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
         
         let memory_pool = MemoryPoolManager::new(core.device.clone());
         
-        Ok(Self {
+        let renderer = Self {
             core,
             pipeline_layout,
             graphics_pipeline,
@@ -710,18 +1655,73 @@ impl VulkanRenderer {
             egui_integration: None,
             water_push_constants: None,
             textured_pipelines: std::collections::HashMap::new(),
-        })
+            mesh_pipeline_descs: std::collections::HashMap::new(),
+            texture_streamer: TextureStreamer::new(RendererConfig::default().upload_threads),
+            config: RendererConfig::default(),
+            descriptor_allocator: DescriptorAllocator::new(DescriptorPoolSizes::default()),
+            max_texture_size: None,
+            reverse_z: false,
+            occlusion_query_meshes: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            instance_cull_pipeline: None,
+            bone_hierarchy_pipeline: None,
+            joint_arena: None,
+            particles: None,
+            dashed_lines: None,
+            ground_grid: None,
+            debug_boxes: None,
+            dynamic_ring: None,
+            overlay_passes: Vec::new(),
+            shared_layouts: None,
+            pending_vertex_uploads: Vec::new(),
+            vertex_staging_buffers: vec![None; MAX_FRAMES_IN_FLIGHT],
+            post_process: None,
+            antialiasing: AaMode::None,
+            render_settings: RenderSettings::default(),
+            shadow_config: ShadowConfig::default(),
+            fxaa_settings: FxaaSettings::default(),
+            sky_gradient: None,
+            render_targets: Vec::new(),
+            water_tessellation: std::collections::HashMap::new(),
+            water_pipeline_desc: None,
+            water_reflections: None,
+            previous_frame: None,
+            tonemap: Tonemap::default(),
+            resolution_scale: MAX_RESOLUTION_SCALE,
+            upscale_filter: UpscaleFilter::default(),
+            dynamic_resolution: None,
+            frames_until_resolution_update: 0,
+            mesh_hierarchy_dirty: false,
+            exposure: 1.0,
+            clear_color: CLEAR_COLOR_MAGENTA,
+            static_scene: false,
+            static_scene_command_buffers: Vec::new(),
+            dynamic_scene_command_buffers: Vec::new(),
+            static_scene_recorded_with: Vec::new(),
+            static_scene_stats: Vec::new(),
+            parallel_recorder: None,
+            pre_draw_hook: None,
+            debug_view: DebugView::None,
+            parallel_draws: None,
+            frame_stats: FrameStats::default(),
+            viewport: None,
+            scissor: None,
+            _not_sync: std::marker::PhantomData,
+        };
+        renderer.set_debug_names();
+        
+        Ok(renderer)
     }
     
     pub fn new_textured_instanced_with_winding(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         mesh_data: &MeshData,
         texture_path: Option<&str>,
         instance_positions: &[[f32; 3]],
         front_face: Option<vk::FrontFace>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
+        validate_mesh_geometry(mesh_data.vertices.len(), mesh_data.indices.len())?;
         let core = VulkanCore::new(window_handle, true)?;
         
         // Create vertex buffer for mesh data
@@ -744,39 +1744,18 @@ impl VulkanRenderer {
             &mesh_data.indices,
         )?;
         
-        // Create instance buffer
-        let (instance_buffer, instance_buffer_memory) = create_buffer(
+        // Create instance buffer, skipped for an empty instance list
+        let instance_buffer = create_instance_buffer(
             &core.instance,
             &core.device,
             core.physical_device,
-            (instance_positions.len() * std::mem::size_of::<[f32; 3]>()) as vk::DeviceSize,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            instance_positions,
         )?;
         
-        // Copy instance data to buffer
-        unsafe {
-            let data = core.device.map_memory(
-                instance_buffer_memory,
-                0,
-                (instance_positions.len() * std::mem::size_of::<[f32; 3]>()) as vk::DeviceSize,
-                vk::MemoryMapFlags::empty(),
-            )?;
-            std::ptr::copy_nonoverlapping(
-                instance_positions.as_ptr() as *const u8,
-                data as *mut u8,
-                instance_positions.len() * std::mem::size_of::<[f32; 3]>(),
-            );
-            core.device.unmap_memory(instance_buffer_memory);
-        }
-        
         // Vertex input configuration - need both per-vertex and per-instance attributes
         let binding_descriptions = vec![
             // Per-vertex data
-            vk::VertexInputBindingDescription::default()
-                .binding(0)
-                .stride(std::mem::size_of::<Vertex>() as u32)
-                .input_rate(vk::VertexInputRate::VERTEX),
+            Vertex::binding_description(),
             // Per-instance data
             vk::VertexInputBindingDescription::default()
                 .binding(1)
@@ -784,10 +1763,10 @@ impl VulkanRenderer {
                 .input_rate(vk::VertexInputRate::INSTANCE),
         ];
         
-        let mut attribute_descriptions = Vertex::get_attribute_descriptions();
+        let mut attribute_descriptions = Vertex::attribute_descriptions();
         
-        // Add instance position attribute (location depends on whether we have UVs)
-        let instance_location = if texture_path.is_some() { 3 } else { 2 };
+        // Instance position goes right after the vertex attributes
+        let instance_location = mesh::next_attribute_location(&attribute_descriptions);
         attribute_descriptions.push(
             vk::VertexInputAttributeDescription::default()
                 .binding(1)
@@ -809,7 +1788,7 @@ impl VulkanRenderer {
             )?;
             
             let texture_image_view = crate::vulkan_common::create_texture_image_view(&core.device, texture_image)?;
-            let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device)?;
+            let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device, core.max_anisotropy, SamplerAddressModes::REPEAT)?;
             
             // Create descriptor resources
             let binding = vk::DescriptorSetLayoutBinding::default()
@@ -849,11 +1828,11 @@ impl VulkanRenderer {
             (None, None)
         };
         
-        // Configure push constants for time
+        // Configure push constants for time and the scene's size
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .offset(0)
-            .size(std::mem::size_of::<f32>() as u32);
+            .size(mem::size_of::<PushConstants>() as u32);
         
         // Build pipeline
         let mut pipeline_builder = PipelineBuilder::new(
@@ -881,19 +1860,23 @@ impl VulkanRenderer {
             index_buffer: Some(index_buffer),
             index_buffer_memory: Some(index_buffer_memory),
             index_count: mesh_data.indices.len() as u32,
-            instance_buffer: Some(instance_buffer),
-            instance_buffer_memory: Some(instance_buffer_memory),
+            instance_buffer: instance_buffer.map(|(buffer, _)| buffer),
+            instance_buffer_memory: instance_buffer.map(|(_, memory)| memory),
         };
         
         let mut pipelines = std::collections::HashMap::new();
         pipelines.insert("default".to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
         
         let memory_pool = MemoryPoolManager::new(core.device.clone());
         
-        Ok(Self {
+        let renderer = Self {
             core,
             pipeline_layout,
             graphics_pipeline,
@@ -911,11 +1894,65 @@ impl VulkanRenderer {
             egui_integration: None,
             water_push_constants: None,
             textured_pipelines: std::collections::HashMap::new(),
-        })
+            mesh_pipeline_descs: std::collections::HashMap::new(),
+            texture_streamer: TextureStreamer::new(RendererConfig::default().upload_threads),
+            config: RendererConfig::default(),
+            descriptor_allocator: DescriptorAllocator::new(DescriptorPoolSizes::default()),
+            max_texture_size: None,
+            reverse_z: false,
+            occlusion_query_meshes: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            instance_cull_pipeline: None,
+            bone_hierarchy_pipeline: None,
+            joint_arena: None,
+            particles: None,
+            dashed_lines: None,
+            ground_grid: None,
+            debug_boxes: None,
+            dynamic_ring: None,
+            overlay_passes: Vec::new(),
+            shared_layouts: None,
+            pending_vertex_uploads: Vec::new(),
+            vertex_staging_buffers: vec![None; MAX_FRAMES_IN_FLIGHT],
+            post_process: None,
+            antialiasing: AaMode::None,
+            render_settings: RenderSettings::default(),
+            shadow_config: ShadowConfig::default(),
+            fxaa_settings: FxaaSettings::default(),
+            sky_gradient: None,
+            render_targets: Vec::new(),
+            water_tessellation: std::collections::HashMap::new(),
+            water_pipeline_desc: None,
+            water_reflections: None,
+            previous_frame: None,
+            tonemap: Tonemap::default(),
+            resolution_scale: MAX_RESOLUTION_SCALE,
+            upscale_filter: UpscaleFilter::default(),
+            dynamic_resolution: None,
+            frames_until_resolution_update: 0,
+            mesh_hierarchy_dirty: false,
+            exposure: 1.0,
+            clear_color: CLEAR_COLOR_MAGENTA,
+            static_scene: false,
+            static_scene_command_buffers: Vec::new(),
+            dynamic_scene_command_buffers: Vec::new(),
+            static_scene_recorded_with: Vec::new(),
+            static_scene_stats: Vec::new(),
+            parallel_recorder: None,
+            pre_draw_hook: None,
+            debug_view: DebugView::None,
+            parallel_draws: None,
+            frame_stats: FrameStats::default(),
+            viewport: None,
+            scissor: None,
+            _not_sync: std::marker::PhantomData,
+        };
+        renderer.set_debug_names();
+        
+        Ok(renderer)
     }
     
     pub fn new_textured<T: Copy>(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         vertices: &[T],
@@ -924,7 +1961,7 @@ impl VulkanRenderer {
         attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
         texture_path: &str,
         instance_count: u32,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
         let core = VulkanCore::new(window_handle, true)?;
         
         // Create buffers
@@ -957,7 +1994,7 @@ impl VulkanRenderer {
         )?;
         
         let texture_image_view = crate::vulkan_common::create_texture_image_view(&core.device, texture_image)?;
-        let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device)?;
+        let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device, core.max_anisotropy, SamplerAddressModes::REPEAT)?;
         
         // Create descriptor resources
         let binding = vk::DescriptorSetLayoutBinding::default()
@@ -986,7 +2023,7 @@ impl VulkanRenderer {
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .offset(0)
-            .size(mem::size_of::<[f32; 16]>() as u32 * 3);
+            .size(MVP_PUSH_CONSTANTS_SIZE);
         
         let (graphics_pipeline, pipeline_layout) = PipelineBuilder::new(
             core.device.clone(),
@@ -1027,11 +2064,15 @@ impl VulkanRenderer {
         pipelines.insert("default".to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
         
         let memory_pool = MemoryPoolManager::new(core.device.clone());
         
-        Ok(Self {
+        let renderer = Self {
             core,
             pipeline_layout,
             graphics_pipeline,
@@ -1049,11 +2090,65 @@ impl VulkanRenderer {
             egui_integration: None,
             water_push_constants: None,
             textured_pipelines: std::collections::HashMap::new(),
-        })
-    }
+            mesh_pipeline_descs: std::collections::HashMap::new(),
+            texture_streamer: TextureStreamer::new(RendererConfig::default().upload_threads),
+            config: RendererConfig::default(),
+            descriptor_allocator: DescriptorAllocator::new(DescriptorPoolSizes::default()),
+            max_texture_size: None,
+            reverse_z: false,
+            occlusion_query_meshes: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            instance_cull_pipeline: None,
+            bone_hierarchy_pipeline: None,
+            joint_arena: None,
+            particles: None,
+            dashed_lines: None,
+            ground_grid: None,
+            debug_boxes: None,
+            dynamic_ring: None,
+            overlay_passes: Vec::new(),
+            shared_layouts: None,
+            pending_vertex_uploads: Vec::new(),
+            vertex_staging_buffers: vec![None; MAX_FRAMES_IN_FLIGHT],
+            post_process: None,
+            antialiasing: AaMode::None,
+            render_settings: RenderSettings::default(),
+            shadow_config: ShadowConfig::default(),
+            fxaa_settings: FxaaSettings::default(),
+            sky_gradient: None,
+            render_targets: Vec::new(),
+            water_tessellation: std::collections::HashMap::new(),
+            water_pipeline_desc: None,
+            water_reflections: None,
+            previous_frame: None,
+            tonemap: Tonemap::default(),
+            resolution_scale: MAX_RESOLUTION_SCALE,
+            upscale_filter: UpscaleFilter::default(),
+            dynamic_resolution: None,
+            frames_until_resolution_update: 0,
+            mesh_hierarchy_dirty: false,
+            exposure: 1.0,
+            clear_color: CLEAR_COLOR_MAGENTA,
+            static_scene: false,
+            static_scene_command_buffers: Vec::new(),
+            dynamic_scene_command_buffers: Vec::new(),
+            static_scene_recorded_with: Vec::new(),
+            static_scene_stats: Vec::new(),
+            parallel_recorder: None,
+            pre_draw_hook: None,
+            debug_view: DebugView::None,
+            parallel_draws: None,
+            frame_stats: FrameStats::default(),
+            viewport: None,
+            scissor: None,
+            _not_sync: std::marker::PhantomData,
+        };
+        renderer.set_debug_names();
+        
+        Ok(renderer)
+    }
     
     pub fn new_textured_with_winding<T: Copy>(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         vertices: &[T],
@@ -1063,7 +2158,7 @@ impl VulkanRenderer {
         texture_path: &str,
         instance_count: u32,
         front_face: Option<vk::FrontFace>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, FloError> {
         let core = VulkanCore::new(window_handle, true)?;
         
         // Create buffers
@@ -1096,7 +2191,7 @@ impl VulkanRenderer {
         )?;
         
         let texture_image_view = crate::vulkan_common::create_texture_image_view(&core.device, texture_image)?;
-        let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device)?;
+        let texture_sampler = crate::vulkan_common::create_texture_sampler(&core.instance, &core.device, core.physical_device, core.max_anisotropy, SamplerAddressModes::REPEAT)?;
         
         // Create descriptor resources
         let binding = vk::DescriptorSetLayoutBinding::default()
@@ -1125,7 +2220,7 @@ impl VulkanRenderer {
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .offset(0)
-            .size(mem::size_of::<[f32; 16]>() as u32 * 3);
+            .size(MVP_PUSH_CONSTANTS_SIZE);
         
         let (graphics_pipeline, pipeline_layout) = PipelineBuilder::new(
             core.device.clone(),
@@ -1166,11 +2261,15 @@ impl VulkanRenderer {
         pipelines.insert("default".to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
         
         let memory_pool = MemoryPoolManager::new(core.device.clone());
         
-        Ok(Self {
+        let renderer = Self {
             core,
             pipeline_layout,
             graphics_pipeline,
@@ -1188,21 +2287,117 @@ impl VulkanRenderer {
             egui_integration: None,
             water_push_constants: None,
             textured_pipelines: std::collections::HashMap::new(),
-        })
+            mesh_pipeline_descs: std::collections::HashMap::new(),
+            texture_streamer: TextureStreamer::new(RendererConfig::default().upload_threads),
+            config: RendererConfig::default(),
+            descriptor_allocator: DescriptorAllocator::new(DescriptorPoolSizes::default()),
+            max_texture_size: None,
+            reverse_z: false,
+            occlusion_query_meshes: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            instance_cull_pipeline: None,
+            bone_hierarchy_pipeline: None,
+            joint_arena: None,
+            particles: None,
+            dashed_lines: None,
+            ground_grid: None,
+            debug_boxes: None,
+            dynamic_ring: None,
+            overlay_passes: Vec::new(),
+            shared_layouts: None,
+            pending_vertex_uploads: Vec::new(),
+            vertex_staging_buffers: vec![None; MAX_FRAMES_IN_FLIGHT],
+            post_process: None,
+            antialiasing: AaMode::None,
+            render_settings: RenderSettings::default(),
+            shadow_config: ShadowConfig::default(),
+            fxaa_settings: FxaaSettings::default(),
+            sky_gradient: None,
+            render_targets: Vec::new(),
+            water_tessellation: std::collections::HashMap::new(),
+            water_pipeline_desc: None,
+            water_reflections: None,
+            previous_frame: None,
+            tonemap: Tonemap::default(),
+            resolution_scale: MAX_RESOLUTION_SCALE,
+            upscale_filter: UpscaleFilter::default(),
+            dynamic_resolution: None,
+            frames_until_resolution_update: 0,
+            mesh_hierarchy_dirty: false,
+            exposure: 1.0,
+            clear_color: CLEAR_COLOR_MAGENTA,
+            static_scene: false,
+            static_scene_command_buffers: Vec::new(),
+            dynamic_scene_command_buffers: Vec::new(),
+            static_scene_recorded_with: Vec::new(),
+            static_scene_stats: Vec::new(),
+            parallel_recorder: None,
+            pre_draw_hook: None,
+            debug_view: DebugView::None,
+            parallel_draws: None,
+            frame_stats: FrameStats::default(),
+            viewport: None,
+            scissor: None,
+            _not_sync: std::marker::PhantomData,
+        };
+        renderer.set_debug_names();
+        
+        Ok(renderer)
     }
     
     // Constructor for multi-mesh rendering
     pub fn new_multi_mesh(
-        window_handle: &RawHandleWrapperHolder,
+        window_handle: &impl WindowHandles,
         vert_shader_path: &str,
         frag_shader_path: &str,
         meshes_data: Vec<(&MeshData, Vec<[f32; 3]>)>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let core = VulkanCore::new(window_handle, true)?;
+    ) -> Result<Self, FloError> {
+        Self::new_multi_mesh_with_options(window_handle, vert_shader_path, frag_shader_path, meshes_data, &CoreOptions::default())
+    }
+    
+    // new_multi_mesh with a chosen GPU or extra swapchain image usages
+    // new_multi_mesh_with_options with the device and present mode from the settings, then
+    // apply_settings for the rest. DeviceSelection::Auto still honours FLO_GPU.
+    pub fn new_multi_mesh_with_settings(
+        window_handle: &impl WindowHandles,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        meshes_data: Vec<(&MeshData, Vec<[f32; 3]>)>,
+        settings: &RenderSettings,
+    ) -> Result<Self, FloError> {
+        let options = CoreOptions {
+            device: match settings.device {
+                DeviceSelection::Auto => DeviceSelection::from_env(),
+                ref device => device.clone(),
+            },
+            present_mode: settings.swapchain_present_mode(),
+            ..Default::default()
+        };
+        let mut renderer = Self::new_multi_mesh_with_options(window_handle, vert_shader_path, frag_shader_path, meshes_data, &options)?;
+        renderer.render_settings.device = settings.device.clone();
+        renderer.apply_settings(settings)?;
+        Ok(renderer)
+    }
+    
+    pub fn new_multi_mesh_with_options(
+        window_handle: &impl WindowHandles,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        meshes_data: Vec<(&MeshData, Vec<[f32; 3]>)>,
+        options: &CoreOptions,
+    ) -> Result<Self, FloError> {
+        for (mesh_idx, (mesh_data, _)) in meshes_data.iter().enumerate() {
+            validate_mesh_geometry(mesh_data.vertices.len(), mesh_data.indices.len())
+                .map_err(|e| format!("Mesh {}: {}", mesh_idx, e))?;
+        }
+        let core = VulkanCore::new_with_options(window_handle, true, options)?;
         
         // Create mesh entries
         let mut meshes = Vec::new();
+        let mut double_sided_meshes = Vec::new();
         for (mesh_idx, (mesh_data, positions)) in meshes_data.into_iter().enumerate() {
+            if mesh_data.double_sided {
+                double_sided_meshes.push(mesh_idx);
+            }
             let (vertex_buffer, vertex_buffer_memory) = create_vertex_buffer(
                 &core.instance,
                 &core.device,
@@ -1251,39 +2446,46 @@ impl VulkanRenderer {
                 joint_matrices: None,
                 joint_buffer: None,
                 joint_buffer_memory: None,
+                local_bounds: mesh_bounds(mesh_data),
+                local_centroid: mesh_centroid(mesh_data),
+                ..Default::default()
             });
         }
         
-        // Configure push constants for MVP matrices and mesh ID
-        // view (64) + proj (64) + model (64) + mesh_id_vec4 (16) = 208 bytes
-        let push_constant_range = vk::PushConstantRange::default()
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
-            .offset(0)
-            .size(208);
-        
-        let (graphics_pipeline, pipeline_layout) = PipelineBuilder::new(
-            core.device.clone(),
-            vert_shader_path,
-            frag_shader_path,
-            core.swapchain_extent,
-            core.render_pass,
-        )?
-        .with_vertex_input(vec![Vertex::get_binding_description()], Vertex::get_attribute_descriptions())
-        .with_push_constants(vec![push_constant_range])
-        .with_depth_test(true)
-        .with_cull_mode(vk::CullModeFlags::BACK)
-        .with_front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-        .build()?;
+        // Push constants are MVP matrices and mesh ID, view (64) + proj (64) + model (64) +
+        // mesh_id_vec4 (16) = 208 bytes. Built like add_pipeline's so meshes drawn with it can
+        // be double sided.
+        let default_desc = MeshPipelineDesc {
+            vert_shader_path: vert_shader_path.to_string(),
+            frag_shader_path: frag_shader_path.to_string(),
+            vertex_bindings: vec![Vertex::binding_description()],
+            vertex_attributes: Vertex::attribute_descriptions(),
+            has_texture: false,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            blend_mode: BlendMode::Opaque,
+            push_constant_size: MVP_PUSH_CONSTANTS_SIZE,
+            stencil: None,
+            antialiasing: CutoutAntialiasing::default(),
+            subpass: GEOMETRY_SUBPASS,
+        };
+        let (graphics_pipeline, pipeline_layout, push_constant_range) = build_mesh_pipeline(&core, "default", true, &default_desc, false)?;
+        let mut mesh_pipeline_descs = std::collections::HashMap::new();
+        mesh_pipeline_descs.insert("default".to_string(), default_desc);
         
         let mut pipelines = std::collections::HashMap::new();
         pipelines.insert("default".to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
         
         let memory_pool = MemoryPoolManager::new(core.device.clone());
         
-        Ok(Self {
+        let mut renderer = Self {
             core,
             pipeline_layout,
             graphics_pipeline,
@@ -1301,30 +2503,164 @@ impl VulkanRenderer {
             egui_integration: None,
             water_push_constants: None,
             textured_pipelines: std::collections::HashMap::new(),
-        })
+            mesh_pipeline_descs,
+            texture_streamer: TextureStreamer::new(RendererConfig::default().upload_threads),
+            config: RendererConfig::default(),
+            descriptor_allocator: DescriptorAllocator::new(DescriptorPoolSizes::default()),
+            max_texture_size: None,
+            reverse_z: false,
+            occlusion_query_meshes: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            instance_cull_pipeline: None,
+            bone_hierarchy_pipeline: None,
+            joint_arena: None,
+            particles: None,
+            dashed_lines: None,
+            ground_grid: None,
+            debug_boxes: None,
+            dynamic_ring: None,
+            overlay_passes: Vec::new(),
+            shared_layouts: None,
+            pending_vertex_uploads: Vec::new(),
+            vertex_staging_buffers: vec![None; MAX_FRAMES_IN_FLIGHT],
+            post_process: None,
+            antialiasing: AaMode::None,
+            render_settings: RenderSettings::default(),
+            shadow_config: ShadowConfig::default(),
+            fxaa_settings: FxaaSettings::default(),
+            sky_gradient: None,
+            render_targets: Vec::new(),
+            water_tessellation: std::collections::HashMap::new(),
+            water_pipeline_desc: None,
+            water_reflections: None,
+            previous_frame: None,
+            tonemap: Tonemap::default(),
+            resolution_scale: MAX_RESOLUTION_SCALE,
+            upscale_filter: UpscaleFilter::default(),
+            dynamic_resolution: None,
+            frames_until_resolution_update: 0,
+            mesh_hierarchy_dirty: false,
+            exposure: 1.0,
+            clear_color: CLEAR_COLOR_MAGENTA,
+            static_scene: false,
+            static_scene_command_buffers: Vec::new(),
+            dynamic_scene_command_buffers: Vec::new(),
+            static_scene_recorded_with: Vec::new(),
+            static_scene_stats: Vec::new(),
+            parallel_recorder: None,
+            pre_draw_hook: None,
+            debug_view: DebugView::None,
+            parallel_draws: None,
+            frame_stats: FrameStats::default(),
+            viewport: None,
+            scissor: None,
+            _not_sync: std::marker::PhantomData,
+        };
+        renderer.set_debug_names();
+        for mesh_index in double_sided_meshes {
+            renderer.make_mesh_double_sided(mesh_index);
+        }
+        
+        Ok(renderer)
     }
     
     // Add a new mesh to the renderer
-    pub fn add_mesh(&mut self, mesh_data: &MeshData) -> Result<usize, Box<dyn std::error::Error>> {
-        let (vertex_buffer, vertex_memory_block) = create_vertex_buffer_pooled(
-            &self.core.instance,
-            &self.core.device,
-            self.core.physical_device,
-            self.core.command_pool,
-            self.core.graphics_queue,
-            &mut self.memory_pool,
-            &mesh_data.vertices,
-        )?;
+    pub fn add_mesh(&mut self, mesh_data: &MeshData) -> Result<usize, FloError> {
+        self.add_mesh_with_layout(mesh_data, MeshLayout::Interleaved)
+    }
+    
+    // Add a new mesh with the given vertex layout. Deinterleaved meshes need a pipeline built
+    // for that layout (see add_fluid_pipeline_with_layout) and can update positions alone
+    // with update_mesh_vertices.
+    pub fn add_mesh_with_layout(&mut self, mesh_data: &MeshData, layout: MeshLayout) -> Result<usize, FloError> {
+        self.add_mesh_with_layout_and_residency(mesh_data, layout, BufferResidency::DeviceLocal)
+    }
+    
+    // Add a new interleaved mesh whose buffers live in the given memory, see BufferResidency
+    pub fn add_mesh_with_residency(&mut self, mesh_data: &MeshData, residency: BufferResidency) -> Result<usize, FloError> {
+        self.add_mesh_with_layout_and_residency(mesh_data, MeshLayout::Interleaved, residency)
+    }
+    
+    // HostVisibleDeviceLocal falls back to DeviceLocal when rebar_available is false
+    pub fn add_mesh_with_layout_and_residency(
+        &mut self,
+        mesh_data: &MeshData,
+        layout: MeshLayout,
+        residency: BufferResidency,
+    ) -> Result<usize, FloError> {
+        let draw_mode = mesh_draw_mode(mesh_data)?;
+        if residency == BufferResidency::HostVisibleDeviceLocal && self.rebar_available() {
+            return self.add_mapped_mesh(mesh_data, layout, draw_mode);
+        }
+        let (vertex_buffer, vertex_memory_block, vertex_streams) = match layout {
+            MeshLayout::Interleaved => {
+                let (vertex_buffer, vertex_memory_block) = create_vertex_buffer_pooled(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    self.core.command_pool,
+                    self.core.graphics_queue,
+                    &mut self.memory_pool,
+                    &mesh_data.vertices,
+                )?;
+                (vertex_buffer, vertex_memory_block, None)
+            }
+            MeshLayout::Deinterleaved => {
+                let positions: Vec<[f32; 3]> = mesh_data.vertices.iter().map(|v| v.position).collect();
+                let normals: Vec<[f32; 3]> = mesh_data.vertices.iter().map(|v| v.normal).collect();
+                let uvs: Vec<[f32; 2]> = mesh_data.vertices.iter().map(|v| v.uv).collect();
+                
+                let (position_buffer, position_memory_block) = create_vertex_buffer_pooled(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    self.core.command_pool,
+                    self.core.graphics_queue,
+                    &mut self.memory_pool,
+                    &positions,
+                )?;
+                let (normal_buffer, normal_memory_block) = create_vertex_buffer_pooled(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    self.core.command_pool,
+                    self.core.graphics_queue,
+                    &mut self.memory_pool,
+                    &normals,
+                )?;
+                let (uv_buffer, uv_memory_block) = create_vertex_buffer_pooled(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    self.core.command_pool,
+                    self.core.graphics_queue,
+                    &mut self.memory_pool,
+                    &uvs,
+                )?;
+                
+                let streams = VertexStreams {
+                    normal_buffer,
+                    normal_memory_block: Some(normal_memory_block),
+                    uv_buffer,
+                    uv_memory_block: Some(uv_memory_block),
+                };
+                (position_buffer, position_memory_block, Some(streams))
+            }
+        };
         
-        let (index_buffer, index_memory_block) = create_index_buffer_pooled(
-            &self.core.instance,
-            &self.core.device,
-            self.core.physical_device,
-            self.core.command_pool,
-            self.core.graphics_queue,
-            &mut self.memory_pool,
-            &mesh_data.indices,
-        )?;
+        let (index_buffer, index_memory_block) = if draw_mode == MeshDrawMode::Indexed {
+            let (index_buffer, index_memory_block) = create_index_buffer_pooled(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                self.core.command_pool,
+                self.core.graphics_queue,
+                &mut self.memory_pool,
+                &mesh_data.indices,
+            )?;
+            (index_buffer, Some(index_memory_block))
+        } else {
+            (vk::Buffer::null(), None)
+        };
         
         let mesh_entry = MeshEntry {
             vertex_buffer,
@@ -1332,7 +2668,7 @@ impl VulkanRenderer {
             vertex_memory_block: Some(vertex_memory_block),
             index_buffer,
             index_buffer_memory: None,
-            index_memory_block: Some(index_memory_block),
+            index_memory_block,
             index_count: mesh_data.indices.len() as u32,
             transforms: Vec::new(),
             pipeline_name: None,
@@ -1352,10 +2688,299 @@ impl VulkanRenderer {
             joint_matrices: None,
             joint_buffer: None,
             joint_buffer_memory: None,
+            local_bounds: mesh_bounds(mesh_data),
+            local_centroid: mesh_centroid(mesh_data),
+            vertex_streams,
+            vertex_count: mesh_data.vertices.len() as u32,
+            draw_mode,
+            topology: mesh_data.topology,
+            ..Default::default()
         };
         
         self.meshes.push(mesh_entry);
-        Ok(self.meshes.len() - 1) // Return the index of the new mesh
+        self.mark_static_scene_dirty();
+        let mesh_index = self.meshes.len() - 1;
+        self.set_mesh_debug_names(mesh_index);
+        if mesh_data.double_sided {
+            self.make_mesh_double_sided(mesh_index);
+        }
+        Ok(mesh_index)
+    }
+    
+    // Add a mesh of custom vertices, drawn with a pipeline from add_pipeline_with_vertex_format
+    // for the same T. Non-indexed when indices is empty. Bounds come from VertexFormat::position,
+    // so a format without positions is never culled. The vertex update functions assume the Vertex
+    // layout and don't apply to these meshes.
+    pub fn add_mesh_with_format<T: VertexFormat>(
+        &mut self,
+        vertices: &[T],
+        indices: &[u32],
+        topology: MeshTopology,
+    ) -> Result<usize, FloError> {
+        if vertices.is_empty() {
+            return Err("Mesh has no vertices".into());
+        }
+        let (vertex_buffer, vertex_memory_block) = create_vertex_buffer_pooled(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            self.core.command_pool,
+            self.core.graphics_queue,
+            &mut self.memory_pool,
+            vertices,
+        )?;
+        let (index_buffer, index_memory_block, draw_mode) = if indices.is_empty() {
+            (vk::Buffer::null(), None, MeshDrawMode::NonIndexed)
+        } else {
+            let (index_buffer, index_memory_block) = create_index_buffer_pooled(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                self.core.command_pool,
+                self.core.graphics_queue,
+                &mut self.memory_pool,
+                indices,
+            )?;
+            (index_buffer, Some(index_memory_block), MeshDrawMode::Indexed)
+        };
+        
+        let positions: Option<Vec<Vec3>> = vertices.iter().map(|v| v.position().map(Vec3::from)).collect();
+        let local_bounds = positions.map(|positions| {
+            let (min, max) = positions.iter().fold((positions[0], positions[0]), |(min, max), &p| (min.min(p), max.max(p)));
+            (min.into(), max.into())
+        });
+        
+        self.meshes.push(MeshEntry {
+            vertex_buffer,
+            vertex_memory_block: Some(vertex_memory_block),
+            index_buffer,
+            index_memory_block,
+            index_count: indices.len() as u32,
+            local_bounds,
+            vertex_count: vertices.len() as u32,
+            draw_mode,
+            topology,
+            ..Default::default()
+        });
+        self.mark_static_scene_dirty();
+        self.set_mesh_debug_names(self.meshes.len() - 1);
+        Ok(self.meshes.len() - 1)
+    }
+    
+    // Add an empty interleaved mesh with buffers of a fixed size, for meshes that stream in and
+    // out at a known maximum size. upload_mesh_data fills them without reallocating, so reusing
+    // the mesh avoids the allocations and frees of remove_mesh and add_mesh. Nothing is drawn
+    // until the first upload. index_bytes of 0 only takes meshes without indices.
+    pub fn reserve_mesh_capacity(&mut self, vertex_bytes: vk::DeviceSize, index_bytes: vk::DeviceSize) -> Result<usize, FloError> {
+        if vertex_bytes == 0 {
+            return Err("Reserved meshes need room for vertices".into());
+        }
+        let (vertex_buffer, vertex_memory_block) = create_buffer_pooled(
+            &self.core.device,
+            self.core.physical_device,
+            &self.core.instance,
+            &mut self.memory_pool,
+            vertex_bytes,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let (index_buffer, index_memory_block) = if index_bytes > 0 {
+            let (index_buffer, index_memory_block) = create_buffer_pooled(
+                &self.core.device,
+                self.core.physical_device,
+                &self.core.instance,
+                &mut self.memory_pool,
+                index_bytes,
+                vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+            (index_buffer, Some(index_memory_block))
+        } else {
+            (vk::Buffer::null(), None)
+        };
+        
+        self.meshes.push(MeshEntry {
+            vertex_buffer,
+            vertex_memory_block: Some(vertex_memory_block),
+            index_buffer,
+            index_memory_block,
+            draw_mode: if index_bytes > 0 { MeshDrawMode::Indexed } else { MeshDrawMode::NonIndexed },
+            reserved_capacity: Some((vertex_bytes, index_bytes)),
+            ..Default::default()
+        });
+        self.mark_static_scene_dirty();
+        self.set_mesh_debug_names(self.meshes.len() - 1);
+        Ok(self.meshes.len() - 1)
+    }
+    
+    // Fill a mesh from reserve_mesh_capacity, keeping its pipeline, transforms and the rest.
+    // Errors without changing the mesh when the data doesn't fit its buffers. Like
+    // update_mesh_vertices_full the copies are recorded at the start of the next frame.
+    pub fn upload_mesh_data(&mut self, mesh_index: usize, mesh_data: &MeshData) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        let Some((vertex_capacity, index_capacity)) = self.meshes[mesh_index].reserved_capacity else {
+            return Err(format!("Mesh {} has no reserved capacity, use replace_mesh instead", mesh_index).into());
+        };
+        let draw_mode = mesh_draw_mode(mesh_data)?;
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(&mesh_data.vertices);
+        let index_bytes: &[u8] = bytemuck::cast_slice(&mesh_data.indices);
+        if vertex_bytes.len() as vk::DeviceSize > vertex_capacity || index_bytes.len() as vk::DeviceSize > index_capacity {
+            return Err(format!(
+                "Mesh data needs {} vertex and {} index bytes, mesh {} has room for {} and {}",
+                vertex_bytes.len(), index_bytes.len(), mesh_index, vertex_capacity, index_capacity
+            ).into());
+        }
+        if draw_mode == MeshDrawMode::NonIndexed && self.meshes[mesh_index].instance_culling.is_some() {
+            return Err("GPU culled meshes need indices".into());
+        }
+        
+        let (vertex_buffer, index_buffer) = (self.meshes[mesh_index].vertex_buffer, self.meshes[mesh_index].index_buffer);
+        self.upload_vertex_data(vertex_buffer, vertex_bytes);
+        if draw_mode == MeshDrawMode::Indexed {
+            self.upload_vertex_data(index_buffer, index_bytes);
+        }
+        
+        let mesh = &mut self.meshes[mesh_index];
+        mesh.vertex_count = mesh_data.vertices.len() as u32;
+        mesh.index_count = mesh_data.indices.len() as u32;
+        mesh.draw_mode = draw_mode;
+        mesh.topology = mesh_data.topology;
+        mesh.local_bounds = mesh_bounds(mesh_data);
+        mesh.local_centroid = mesh_centroid(mesh_data);
+        let occlusion_culling = mesh.occlusion_culling;
+        self.mark_static_scene_dirty();
+        
+        // The occlusion proxy is a box around the old bounds
+        if occlusion_culling {
+            self.set_mesh_occlusion_culling(mesh_index, false)?;
+            self.set_mesh_occlusion_culling(mesh_index, true)?;
+        }
+        if mesh_data.double_sided {
+            self.make_mesh_double_sided(mesh_index);
+        }
+        Ok(())
+    }
+    
+    // Writes the mesh straight into host visible device local memory instead of staging it.
+    // Vertex buffers get a persistently mapped copy per frame in flight so update_mesh_vertices
+    // doesn't have to wait for the GPU, the index buffer is written once.
+    fn add_mapped_mesh(&mut self, mesh_data: &MeshData, layout: MeshLayout, draw_mode: MeshDrawMode) -> Result<usize, FloError> {
+        let stream_data: Vec<Vec<u8>> = match layout {
+            MeshLayout::Interleaved => vec![bytemuck::cast_slice(&mesh_data.vertices).to_vec()],
+            MeshLayout::Deinterleaved => {
+                let positions: Vec<[f32; 3]> = mesh_data.vertices.iter().map(|v| v.position).collect();
+                let normals: Vec<[f32; 3]> = mesh_data.vertices.iter().map(|v| v.normal).collect();
+                let uvs: Vec<[f32; 2]> = mesh_data.vertices.iter().map(|v| v.uv).collect();
+                vec![
+                    bytemuck::cast_slice(&positions).to_vec(),
+                    bytemuck::cast_slice(&normals).to_vec(),
+                    bytemuck::cast_slice(&uvs).to_vec(),
+                ]
+            }
+        };
+        
+        let mut mapped_vertex_buffers: Vec<MappedVertexBuffer> = Vec::with_capacity(stream_data.len());
+        for data in &stream_data {
+            match MappedVertexBuffer::new(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                MAX_FRAMES_IN_FLIGHT,
+                data,
+            ) {
+                Ok(mapped_buffer) => mapped_vertex_buffers.push(mapped_buffer),
+                Err(e) => {
+                    for mapped_buffer in &mapped_vertex_buffers {
+                        mapped_buffer.destroy(&self.core.device);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        
+        let (index_buffer, index_buffer_memory) = if draw_mode == MeshDrawMode::Indexed {
+            match buffer_residency::create_host_visible_buffer(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                bytemuck::cast_slice(&mesh_data.indices),
+            ) {
+                Ok((index_buffer, index_buffer_memory)) => (index_buffer, Some(index_buffer_memory)),
+                Err(e) => {
+                    for mapped_buffer in &mapped_vertex_buffers {
+                        mapped_buffer.destroy(&self.core.device);
+                    }
+                    return Err(e);
+                }
+            }
+        } else {
+            (vk::Buffer::null(), None)
+        };
+        
+        let frame = self.core.current_frame;
+        let vertex_buffer = mapped_vertex_buffers[0].frame_buffer(frame);
+        let vertex_streams = if layout == MeshLayout::Deinterleaved {
+            Some(VertexStreams {
+                normal_buffer: mapped_vertex_buffers[1].frame_buffer(frame),
+                normal_memory_block: None,
+                uv_buffer: mapped_vertex_buffers[2].frame_buffer(frame),
+                uv_memory_block: None,
+            })
+        } else {
+            None
+        };
+        
+        self.meshes.push(MeshEntry {
+            vertex_buffer,
+            index_buffer,
+            index_buffer_memory,
+            index_count: mesh_data.indices.len() as u32,
+            local_bounds: mesh_bounds(mesh_data),
+            local_centroid: mesh_centroid(mesh_data),
+            vertex_streams,
+            vertex_count: mesh_data.vertices.len() as u32,
+            draw_mode,
+            topology: mesh_data.topology,
+            mapped_vertex_buffers,
+            ..Default::default()
+        });
+        self.mark_static_scene_dirty();
+        let mesh_index = self.meshes.len() - 1;
+        self.set_mesh_debug_names(mesh_index);
+        if mesh_data.double_sided {
+            self.make_mesh_double_sided(mesh_index);
+        }
+        Ok(mesh_index)
+    }
+    
+    // The GPU the renderer runs on, e.g. to show next to a device picker
+    pub fn device_info(&self) -> Result<DeviceInfo, FloError> {
+        self.core.device_info()
+    }
+    
+    // Optional features and limits of the GPU, to check before asking for something the device
+    // may not have, e.g. skipping tessellated terrain without tessellation_shader or capping an
+    // anisotropy setting at max_sampler_anisotropy
+    pub fn device_capabilities(&self) -> DeviceCapabilities {
+        self.core.capabilities.clone()
+    }
+    
+    // What the swapchain images were created with, see CoreOptions::extra_swapchain_usage
+    pub fn swapchain_image_usage(&self) -> vk::ImageUsageFlags {
+        self.core.swapchain_image_usage
+    }
+    
+    // Whether the depth buffer has a stencil aspect, see CoreOptions::stencil
+    pub fn has_stencil(&self) -> bool {
+        self.core.depth_format.is_some_and(has_stencil_component)
+    }
+    
+    // Whether BufferResidency::HostVisibleDeviceLocal can be used, i.e. resizable BAR is on
+    pub fn rebar_available(&self) -> bool {
+        buffer_residency::rebar_available(&self.core.instance, self.core.physical_device)
     }
     
     // Add a skinned mesh with instancing to the multi-mesh system
@@ -1363,7 +2988,13 @@ impl VulkanRenderer {
         mesh_data: &SkinnedMeshData,
         instance_positions: &[[f32; 3]],
         pipeline_name: Option<String>,
-    ) -> Result<usize, Box<dyn std::error::Error>> {
+    ) -> Result<usize, FloError> {
+        validate_mesh_geometry(mesh_data.vertices.len(), mesh_data.indices.len())?;
+        let morph_targets = mesh_data.morph_targets.as_ref().filter(|morph| !morph.targets.is_empty());
+        let morph_deltas = morph_targets
+            .map(|morph| skinned_mesh::morph_delta_buffer_data(morph, mesh_data.vertices.len()))
+            .transpose()?;
+        
         // Create vertex buffer for skinned mesh
         let (vertex_buffer, vertex_buffer_memory) = create_vertex_buffer(
             &self.core.instance,
@@ -1384,151 +3015,108 @@ impl VulkanRenderer {
             &mesh_data.indices,
         )?;
         
-        // Create instance buffer
-        let (instance_buffer, instance_buffer_memory) = create_buffer(
+        // Create instance buffer, skipped for an empty instance list
+        let instance_buffer = create_instance_buffer(
             &self.core.instance,
             &self.core.device,
             self.core.physical_device,
-            (instance_positions.len() * std::mem::size_of::<[f32; 3]>()) as vk::DeviceSize,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            instance_positions,
         )?;
         
-        // Copy instance data to buffer
-        unsafe {
-            let data = self.core.device.map_memory(
-                instance_buffer_memory,
-                0,
-                (instance_positions.len() * std::mem::size_of::<[f32; 3]>()) as vk::DeviceSize,
-                vk::MemoryMapFlags::empty(),
-            )?;
-            std::ptr::copy_nonoverlapping(
-                instance_positions.as_ptr() as *const u8,
-                data as *mut u8,
-                instance_positions.len() * std::mem::size_of::<[f32; 3]>(),
-            );
-            self.core.device.unmap_memory(instance_buffer_memory);
-        }
-        
-        // Debug: Log what we're sending to GPU
-        println!("Sending {} instance positions to GPU for skinned mesh:", instance_positions.len());
+        debug!("Sending {} instance positions to GPU for skinned mesh", instance_positions.len());
         for (i, pos) in instance_positions.iter().take(3).enumerate() {
-            println!("  GPU Instance {}: [{:.2}, {:.2}, {:.2}]", i, pos[0], pos[1], pos[2]);
+            trace!("  GPU Instance {}: [{:.2}, {:.2}, {:.2}]", i, pos[0], pos[1], pos[2]);
         }
         
-        // Create joint buffer for skinned animation
-        let joint_buffer_size = (std::mem::size_of::<Mat4>() * 128) as vk::DeviceSize; // Support up to 128 joints
-        let (joint_buffer, joint_buffer_memory) = create_buffer(
-            &self.core.instance,
-            &self.core.device,
-            self.core.physical_device,
-            joint_buffer_size,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )?;
-        
-        // Copy initial joint matrices to buffer
-        unsafe {
-            let data = self.core.device.map_memory(
-                joint_buffer_memory,
-                0,
-                joint_buffer_size,
-                vk::MemoryMapFlags::empty(),
-            )? as *mut Mat4;
-            
-            for (i, mat) in mesh_data.joint_matrices.iter().enumerate() {
-                if i >= 128 { break; }
-                data.add(i).write(*mat);
+        let joint_count = mesh_data.joint_matrices.len();
+        let joints_in_storage = joints_in_storage_buffer(&self.core.instance, self.core.physical_device, joint_count);
+        let skinning_mode = self.pipeline_skinning_mode(pipeline_name.as_deref());
+        let joint_data = skinned_mesh::joint_buffer_data(&mesh_data.joint_matrices, skinning_mode);
+        
+        // Morph target buffers, every instance shares the weights
+        let morph_target_count = morph_targets.map_or(0, |morph| morph.targets.len());
+        let morph_delta = morph_deltas
+            .map(|deltas| create_morph_buffer(&self.core.instance, &self.core.device, self.core.physical_device, &deltas))
+            .transpose()?;
+        let morph_weight = morph_targets.map(|morph| {
+            let mut weights = morph.weights.clone();
+            weights.resize(morph_target_count, 0.0);
+            create_morph_buffer(&self.core.instance, &self.core.device, self.core.physical_device, &weights)
+        }).transpose()?;
+        
+        // Meshes without morph targets share the joint arena's buffers and set when their joints
+        // fit, the set's morph bindings are the arena's
+        let arena_range = if morph_targets.is_none() && !joints_in_storage {
+            self.allocate_joint_range(joint_count)?
+        } else {
+            None
+        };
+        let skinned_resources = match (arena_range, self.joint_arena.as_ref()) {
+            (Some((joint_offset, joint_arena_size)), Some(arena)) => {
+                write_joint_matrices(&self.core.device, arena.memory, joint_offset, &joint_data)?;
+                MeshEntry {
+                    joint_buffer: Some(arena.buffer),
+                    joint_buffer_memory: Some(arena.memory),
+                    joint_offset,
+                    joint_arena_size: Some(joint_arena_size),
+                    skinned_descriptor_sets: Some(vec![arena.descriptor_set; self.core.swapchain_images.len()]),
+                    camera_uniform_buffer: Some(arena.camera_buffer),
+                    camera_uniform_memory: Some(arena.camera_memory),
+                    ..Default::default()
+                }
+            }
+            _ => {
+                // Create joint buffer for skinned animation, sized to the rig's joints
+                let (joint_buffer, joint_buffer_memory) = create_joint_buffer(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    &joint_data,
+                )?;
+                
+                // Create camera uniform buffer for skinned mesh
+                let camera_buffer_size = (std::mem::size_of::<Mat4>() * 2) as vk::DeviceSize; // view + proj matrices
+                let (camera_uniform_buffer, camera_uniform_memory) = create_buffer(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    camera_buffer_size,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )?;
+                
+                // Create descriptor set layout for skinned mesh
+                let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &skinned_descriptor_set_layout_bindings())?;
+                
+                // Create descriptor sets from the shared allocator
+                let layouts = vec![descriptor_set_layout; self.core.swapchain_images.len()];
+                let (descriptor_pool, descriptor_sets) = self.descriptor_allocator.allocate(&self.core.device, &layouts)?;
+                
+                // Update descriptor sets with buffer info
+                write_skinned_descriptor_sets(
+                    &self.core.device,
+                    &descriptor_sets,
+                    joint_buffer,
+                    joint_count,
+                    joints_in_storage,
+                    camera_uniform_buffer,
+                    camera_buffer_size,
+                    morph_delta.zip(morph_weight).map(|((delta_buffer, _), (weight_buffer, _))| (delta_buffer, weight_buffer)),
+                );
+                
+                MeshEntry {
+                    joint_buffer: Some(joint_buffer),
+                    joint_buffer_memory: Some(joint_buffer_memory),
+                    skinned_descriptor_pool: Some(descriptor_pool),
+                    skinned_descriptor_set_layout: Some(descriptor_set_layout),
+                    skinned_descriptor_sets: Some(descriptor_sets),
+                    camera_uniform_buffer: Some(camera_uniform_buffer),
+                    camera_uniform_memory: Some(camera_uniform_memory),
+                    ..Default::default()
+                }
             }
-            
-            self.core.device.unmap_memory(joint_buffer_memory);
-        }
-        
-        // Create camera uniform buffer for skinned mesh
-        let camera_buffer_size = (std::mem::size_of::<Mat4>() * 2) as vk::DeviceSize; // view + proj matrices
-        let (camera_uniform_buffer, camera_uniform_memory) = create_buffer(
-            &self.core.instance,
-            &self.core.device,
-            self.core.physical_device,
-            camera_buffer_size,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )?;
-        
-        // Create descriptor pool for skinned mesh
-        let pool_sizes = vec![
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: self.core.swapchain_images.len() as u32 * 2, // joints + camera
-            },
-        ];
-        
-        let pool_info = vk::DescriptorPoolCreateInfo::default()
-            .pool_sizes(&pool_sizes)
-            .max_sets(self.core.swapchain_images.len() as u32);
-        
-        let descriptor_pool = unsafe {
-            self.core.device.create_descriptor_pool(&pool_info, None)?
         };
         
-        // Create descriptor set layout for skinned mesh
-        let bindings = vec![
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .stage_flags(vk::ShaderStageFlags::VERTEX),
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(1)
-                .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .stage_flags(vk::ShaderStageFlags::VERTEX),
-        ];
-        
-        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &bindings)?;
-        
-        // Create descriptor sets
-        let layouts = vec![descriptor_set_layout; self.core.swapchain_images.len()];
-        let alloc_info = vk::DescriptorSetAllocateInfo::default()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(&layouts);
-        
-        let descriptor_sets = unsafe {
-            self.core.device.allocate_descriptor_sets(&alloc_info)?
-        };
-        
-        // Update descriptor sets with buffer info
-        for set in &descriptor_sets {
-            let joint_buffer_info = vk::DescriptorBufferInfo::default()
-                .buffer(joint_buffer)
-                .offset(0)
-                .range(joint_buffer_size);
-            
-            let camera_buffer_info = vk::DescriptorBufferInfo::default()
-                .buffer(camera_uniform_buffer)
-                .offset(0)
-                .range(camera_buffer_size);
-            
-            let descriptor_writes = vec![
-                vk::WriteDescriptorSet::default()
-                    .dst_set(*set)
-                    .dst_binding(0)
-                    .dst_array_element(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(std::slice::from_ref(&joint_buffer_info)),
-                vk::WriteDescriptorSet::default()
-                    .dst_set(*set)
-                    .dst_binding(1)
-                    .dst_array_element(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(std::slice::from_ref(&camera_buffer_info)),
-            ];
-            
-            unsafe {
-                self.core.device.update_descriptor_sets(&descriptor_writes, &[]);
-            }
-        }
-        
         let mesh_entry = MeshEntry {
             vertex_buffer,
             vertex_buffer_memory: Some(vertex_buffer_memory),
@@ -1540,30 +3128,80 @@ impl VulkanRenderer {
             transforms: Vec::new(), // Not used when instancing
             pipeline_name,
             texture_resources: None,
-            instance_buffer: Some(instance_buffer),
-            instance_buffer_memory: Some(instance_buffer_memory),
+            instance_buffer: instance_buffer.map(|(buffer, _)| buffer),
+            instance_buffer_memory: instance_buffer.map(|(_, memory)| memory),
             instance_memory_block: None,
             instance_count: instance_positions.len() as u32,
             use_instancing: true,
             base_color: [1.0, 1.0, 1.0, 1.0],
             joint_matrices: Some(mesh_data.joint_matrices.clone()),
-            joint_buffer: Some(joint_buffer),
-            joint_buffer_memory: Some(joint_buffer_memory),
             is_skinned: true,
-            skinned_descriptor_pool: Some(descriptor_pool),
-            skinned_descriptor_set_layout: Some(descriptor_set_layout),
-            skinned_descriptor_sets: Some(descriptor_sets),
-            camera_uniform_buffer: Some(camera_uniform_buffer),
-            camera_uniform_memory: Some(camera_uniform_memory),
+            morph_target_count: morph_target_count as u32,
+            morph_delta_buffer: morph_delta.map(|(buffer, _)| buffer),
+            morph_delta_memory: morph_delta.map(|(_, memory)| memory),
+            morph_weight_buffer: morph_weight.map(|(buffer, _)| buffer),
+            morph_weight_memory: morph_weight.map(|(_, memory)| memory),
+            ..skinned_resources
         };
         
         let mesh_index = self.meshes.len();
         self.meshes.push(mesh_entry);
-        println!("Added skinned mesh at index {} with is_skinned=true, instance_count={}", 
+        self.mark_static_scene_dirty();
+        self.set_mesh_debug_names(mesh_index);
+        debug!("Added skinned mesh at index {} with is_skinned=true, instance_count={}", 
                  mesh_index, instance_positions.len());
         Ok(mesh_index)
     }
     
+    // (joint_offset, size) of joint_count joints in the joint arena, creating it on first use.
+    // None when the arena is disabled, full or the joints don't fit in one of its slots.
+    fn allocate_joint_range(&mut self, joint_count: usize) -> Result<Option<(u32, vk::DeviceSize)>, FloError> {
+        if self.joint_arena.is_none() && self.config.skinned_joint_arena_size > 0 {
+            self.joint_arena = Some(self.create_joint_arena()?);
+        }
+        Ok(self.joint_arena.as_mut().and_then(|arena| arena.allocate(joint_count)))
+    }
+    
+    fn create_joint_arena(&self) -> Result<JointArena, FloError> {
+        let device = &self.core.device;
+        let descriptor_set_layout = create_descriptor_set_layout(device, &skinned_descriptor_set_layout_bindings())?;
+        let arena = create_descriptor_pool(device, 1, &skinned_descriptor_pool_sizes(1)).and_then(|descriptor_pool| {
+            allocate_descriptor_sets(device, descriptor_pool, &[descriptor_set_layout])
+                .and_then(|sets| JointArena::new(
+                    &self.core.instance,
+                    device,
+                    self.core.physical_device,
+                    self.config.skinned_joint_arena_size,
+                    descriptor_pool,
+                    descriptor_set_layout,
+                    sets[0],
+                ))
+                .inspect_err(|_| unsafe { leak_check::destroy(device, descriptor_pool) })
+        });
+        let arena = match arena {
+            Ok(arena) => arena,
+            Err(e) => {
+                unsafe { leak_check::destroy(device, descriptor_set_layout) };
+                return Err(e);
+            }
+        };
+        
+        // Every binding covers a whole slot from the mesh's offset
+        write_skinned_descriptor_sets(
+            device,
+            &[arena.descriptor_set],
+            arena.buffer,
+            JOINT_ARENA_SLOT_SIZE as usize / std::mem::size_of::<Mat4>(),
+            false,
+            arena.camera_buffer,
+            (std::mem::size_of::<Mat4>() * 2) as vk::DeviceSize,
+            None,
+        );
+        self.core.set_debug_name(arena.buffer, "joint arena");
+        self.core.set_debug_name(arena.descriptor_set, "joint arena descriptor set");
+        Ok(arena)
+    }
+    
     // Update joint matrices for a specific skinned mesh
     pub fn update_mesh_joint_matrices(&mut self, mesh_index: usize, joint_matrices: &[Mat4]) {
         if mesh_index >= self.meshes.len() {
@@ -1575,41 +3213,49 @@ impl VulkanRenderer {
             return;
         }
         
-        // Update the stored matrices
-        if let Some(ref mut stored_matrices) = mesh.joint_matrices {
-            for (i, mat) in joint_matrices.iter().enumerate() {
-                if i < stored_matrices.len() {
-                    stored_matrices[i] = *mat;
-                }
-            }
-        }
+        // Update the stored matrices, the joint buffer was sized to their count
+        let stored_matrices = match mesh.joint_matrices {
+            Some(ref mut stored_matrices) => stored_matrices,
+            None => return,
+        };
+        let joint_count = joint_matrices.len().min(stored_matrices.len());
+        stored_matrices[..joint_count].copy_from_slice(&joint_matrices[..joint_count]);
         
         // Update the GPU buffer
-        if let (Some(buffer_memory), Some(_buffer)) = (mesh.joint_buffer_memory, mesh.joint_buffer) {
-            unsafe {
-                let joint_buffer_size = (std::mem::size_of::<Mat4>() * 128) as vk::DeviceSize;
-                if let Ok(data) = self.core.device.map_memory(
-                    buffer_memory,
-                    0,
-                    joint_buffer_size,
-                    vk::MemoryMapFlags::empty(),
-                ) {
-                    let data_ptr = data as *mut Mat4;
-                    for (i, mat) in joint_matrices.iter().enumerate() {
-                        if i >= 128 { break; }
-                        data_ptr.add(i).write(*mat);
-                    }
-                    self.core.device.unmap_memory(buffer_memory);
-                }
-            }
+        if let Err(e) = self.write_mesh_joints(mesh_index, &joint_matrices[..joint_count]) {
+            error!("Failed to update joint matrices of mesh {}: {:?}", mesh_index, e);
         }
     }
     
-    pub fn replace_mesh(&mut self, mesh_index: usize, mesh_data: &MeshData) -> Result<(), Box<dyn std::error::Error>> {
+    // Writes joint matrices to a mesh's joint buffer in the format its pipeline's SkinningMode reads
+    fn write_mesh_joints(&self, mesh_index: usize, joint_matrices: &[Mat4]) -> Result<(), vk::Result> {
+        let mesh = &self.meshes[mesh_index];
+        let Some(joint_buffer_memory) = mesh.joint_buffer_memory else {
+            return Ok(());
+        };
+        let skinning_mode = self.pipeline_skinning_mode(mesh.pipeline_name.as_deref());
+        write_joint_matrices(&self.core.device, joint_buffer_memory, mesh.joint_offset, &skinned_mesh::joint_buffer_data(joint_matrices, skinning_mode))
+    }
+    
+    // Meshes without a pipeline (or with an unknown one) are drawn with a linear blend pipeline
+    fn pipeline_skinning_mode(&self, pipeline_name: Option<&str>) -> SkinningMode {
+        pipeline_name
+            .and_then(|name| self.pipelines.get(name))
+            .map_or(SkinningMode::LinearBlend, |pipeline| pipeline.skinning_mode)
+    }
+    
+    pub fn replace_mesh(&mut self, mesh_index: usize, mesh_data: &MeshData) -> Result<(), FloError> {
         if mesh_index >= self.meshes.len() {
-            return Err(format!("Mesh index {} out of bounds", mesh_index).into());
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        let draw_mode = mesh_draw_mode(mesh_data)?;
+        if draw_mode == MeshDrawMode::NonIndexed && self.meshes[mesh_index].instance_culling.is_some() {
+            return Err("GPU culled meshes need indices".into());
         }
         
+        self.mark_static_scene_dirty();
+        self.cancel_vertex_uploads(mesh_index);
+        
         // Take the old mesh entry to move its resources
         let old_mesh = std::mem::replace(&mut self.meshes[mesh_index], MeshEntry {
             vertex_buffer: vk::Buffer::null(),
@@ -1637,29 +3283,31 @@ impl VulkanRenderer {
             skinned_descriptor_sets: None,
             camera_uniform_buffer: None,
             camera_uniform_memory: None,
+            ..Default::default()
         });
         
         unsafe {
             // Wait for GPU to finish using the old buffers
             self.core.device.device_wait_idle().map_err(|e| format!("Failed to wait for device idle: {:?}", e))?;
             
-            // Destroy old vertex and index buffers
-            self.core.device.destroy_buffer(old_mesh.vertex_buffer, None);
-            
-            // Free memory - check if using memory pool or direct allocation
-            if let Some(memory) = old_mesh.vertex_buffer_memory {
-                self.core.device.free_memory(memory, None);
-            } else if let Some(block) = old_mesh.vertex_memory_block {
-                self.memory_pool.free_buffer(block);
-            }
+            // Destroy old vertex and index buffers, the replacement is always interleaved
+            destroy_mesh_vertex_buffers(&self.core.device, &mut self.memory_pool, &old_mesh);
             
-            self.core.device.destroy_buffer(old_mesh.index_buffer, None);
+            leak_check::destroy(&self.core.device, old_mesh.index_buffer);
             
             if let Some(memory) = old_mesh.index_buffer_memory {
-                self.core.device.free_memory(memory, None);
+                free_device_memory(&self.core.device, memory);
             } else if let Some(block) = old_mesh.index_memory_block {
                 self.memory_pool.free_buffer(block);
             }
+            
+            // The occlusion proxy is rebuilt below from the new bounds
+            if let Some(proxy_buffer) = old_mesh.occlusion_proxy_buffer {
+                leak_check::destroy(&self.core.device, proxy_buffer);
+            }
+            if let Some(proxy_memory) = old_mesh.occlusion_proxy_memory {
+                free_device_memory(&self.core.device, proxy_memory);
+            }
         }
         
         // Create new buffers
@@ -1672,14 +3320,19 @@ impl VulkanRenderer {
             &mesh_data.vertices,
         )?;
         
-        let (index_buffer, index_buffer_memory) = create_index_buffer(
-            &self.core.instance,
-            &self.core.device,
-            self.core.physical_device,
-            self.core.command_pool,
-            self.core.graphics_queue,
-            &mesh_data.indices,
-        )?;
+        let (index_buffer, index_buffer_memory) = if draw_mode == MeshDrawMode::Indexed {
+            let (index_buffer, index_buffer_memory) = create_index_buffer(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                self.core.command_pool,
+                self.core.graphics_queue,
+                &mesh_data.indices,
+            )?;
+            (index_buffer, Some(index_buffer_memory))
+        } else {
+            (vk::Buffer::null(), None)
+        };
         
         // Replace the mesh entry with preserved properties
         self.meshes[mesh_index] = MeshEntry {
@@ -1687,30 +3340,79 @@ impl VulkanRenderer {
             vertex_buffer_memory: Some(vertex_buffer_memory),
             vertex_memory_block: None,
             index_buffer,
-            index_buffer_memory: Some(index_buffer_memory),
+            index_buffer_memory,
             index_memory_block: None,
             index_count: mesh_data.indices.len() as u32,
             transforms: old_mesh.transforms,
             pipeline_name: old_mesh.pipeline_name,
             texture_resources: old_mesh.texture_resources,
+            texture_address_modes: old_mesh.texture_address_modes,
+            texture_filtering: old_mesh.texture_filtering,
             instance_buffer: old_mesh.instance_buffer,
             instance_buffer_memory: old_mesh.instance_buffer_memory,
             instance_memory_block: old_mesh.instance_memory_block,
+            instance_memory: old_mesh.instance_memory,
+            instance_shadow: old_mesh.instance_shadow,
             instance_count: old_mesh.instance_count,
             use_instancing: old_mesh.use_instancing,
             base_color: old_mesh.base_color,
             joint_matrices: old_mesh.joint_matrices,
             joint_buffer: old_mesh.joint_buffer,
             joint_buffer_memory: old_mesh.joint_buffer_memory,
+            joint_offset: old_mesh.joint_offset,
+            joint_arena_size: old_mesh.joint_arena_size,
             is_skinned: old_mesh.is_skinned,
             skinned_descriptor_pool: old_mesh.skinned_descriptor_pool,
             skinned_descriptor_set_layout: old_mesh.skinned_descriptor_set_layout,
             skinned_descriptor_sets: old_mesh.skinned_descriptor_sets,
             camera_uniform_buffer: old_mesh.camera_uniform_buffer,
             camera_uniform_memory: old_mesh.camera_uniform_memory,
+            // The skinned descriptor sets point at the morph buffers, so they're kept with them
+            morph_target_count: old_mesh.morph_target_count,
+            morph_delta_buffer: old_mesh.morph_delta_buffer,
+            morph_delta_memory: old_mesh.morph_delta_memory,
+            morph_weight_buffer: old_mesh.morph_weight_buffer,
+            morph_weight_memory: old_mesh.morph_weight_memory,
+            local_bounds: mesh_bounds(mesh_data),
+            local_centroid: mesh_centroid(mesh_data),
+            // Culling reads the instance buffer, which is kept, so it carries over as is
+            instance_culling: old_mesh.instance_culling,
+            instance_sort: old_mesh.instance_sort,
+            unsorted_instance_positions: old_mesh.unsorted_instance_positions,
+            // Writes the joint buffer, which is kept too
+            bone_hierarchy: old_mesh.bone_hierarchy,
+            vertex_count: mesh_data.vertices.len() as u32,
+            draw_mode,
+            topology: mesh_data.topology,
+            time_offset: old_mesh.time_offset,
+            depth_test: old_mesh.depth_test,
+            depth_write: old_mesh.depth_write,
+            depth_compare_op: old_mesh.depth_compare_op,
+            depth_bias: old_mesh.depth_bias,
+            subpass: old_mesh.subpass,
+            custom_push_constants: old_mesh.custom_push_constants,
+            custom_push_offset: old_mesh.custom_push_offset,
+            instance_data: old_mesh.instance_data,
+            instance_bounds: old_mesh.instance_bounds,
+            material_mode: old_mesh.material_mode,
+            wireframe_overlay: old_mesh.wireframe_overlay,
+            stencil_reference: old_mesh.stencil_reference,
+            // Points at the instance buffer, which is kept
+            instance_matrices: old_mesh.instance_matrices,
+            wind: old_mesh.wind,
+            uv_transform: old_mesh.uv_transform,
+            double_sided_pipeline: old_mesh.double_sided_pipeline,
+            parent: old_mesh.parent,
+            local_transforms: old_mesh.local_transforms,
+            ..Default::default()
         };
         
-        println!("Replaced mesh at index {} with {} vertices and {} indices", 
+        if old_mesh.occlusion_culling {
+            self.set_mesh_occlusion_culling(mesh_index, true)?;
+        }
+        self.set_mesh_debug_names(mesh_index);
+        
+        debug!("Replaced mesh at index {} with {} vertices and {} indices", 
                  mesh_index, mesh_data.vertices.len(), mesh_data.indices.len());
         
         Ok(())
@@ -1723,7 +3425,23 @@ impl VulkanRenderer {
         instance_positions: Vec<[f32; 3]>,
         texture_path: Option<String>,
         pipeline_name: Option<String>,
-    ) -> Result<usize, Box<dyn std::error::Error>> {
+    ) -> Result<usize, FloError> {
+        // TODO: Implement texture loading
+        let _ = texture_path;
+        self.add_mesh_instanced_with_memory(mesh_data, instance_positions, pipeline_name, InstanceMemory::HostVisible)
+    }
+    
+    // add_mesh_instanced with its instance buffer in the given memory, see InstanceMemory.
+    // DeviceLocal pays off for thousands of instances updated every frame.
+    pub fn add_mesh_instanced_with_memory(
+        &mut self,
+        mesh_data: &MeshData,
+        instance_positions: Vec<[f32; 3]>,
+        pipeline_name: Option<String>,
+        instance_memory: InstanceMemory,
+    ) -> Result<usize, FloError> {
+        validate_mesh_geometry(mesh_data.vertices.len(), mesh_data.indices.len())?;
+        
         // Create vertex buffer using memory pool
         let (vertex_buffer, vertex_memory_block) = create_vertex_buffer_pooled(
             &self.core.instance,
@@ -1746,40 +3464,15 @@ impl VulkanRenderer {
             &mesh_data.indices,
         )?;
         
-        // Create instance buffer using memory pool
+        // Create instance buffer using memory pool. With no instances yet the mesh stays
+        // unrenderable until update_mesh_instance_buffer gives it some.
         let instance_count = instance_positions.len() as u32;
-        let (instance_buffer, instance_memory_block) = create_buffer_pooled(
-            &self.core.device,
-            self.core.physical_device,
-            &self.core.instance,
-            &mut self.memory_pool,
-            (instance_positions.len() * std::mem::size_of::<[f32; 3]>()) as vk::DeviceSize,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )?;
-        
-        // Copy instance data to buffer
-        unsafe {
-            let data = self.core.device.map_memory(
-                instance_memory_block.memory,
-                instance_memory_block.offset,
-                instance_memory_block.size,
-                vk::MemoryMapFlags::empty(),
-            )?;
-            std::ptr::copy_nonoverlapping(
-                instance_positions.as_ptr() as *const u8,
-                data as *mut u8,
-                instance_positions.len() * std::mem::size_of::<[f32; 3]>(),
-            );
-            self.core.device.unmap_memory(instance_memory_block.memory);
-        }
-        
-        // Create texture resources if path provided
-        let texture_resources = if let Some(_path) = texture_path {
-            // Load and create texture (simplified - you may want to add proper error handling)
-            None // TODO: Implement texture loading
+        let (instance_buffer, instance_memory_block, instance_shadow) = if instance_positions.is_empty() {
+            (None, None, None)
         } else {
-            None
+            let (instance_buffer, instance_memory_block, instance_shadow) =
+                self.create_pooled_instance_buffer(bytemuck::cast_slice(&instance_positions), instance_memory)?;
+            (Some(instance_buffer), Some(instance_memory_block), instance_shadow)
         };
         
         let mesh_entry = MeshEntry {
@@ -1792,10 +3485,12 @@ impl VulkanRenderer {
             index_count: mesh_data.indices.len() as u32,
             transforms: Vec::new(),
             pipeline_name,
-            texture_resources,
-            instance_buffer: Some(instance_buffer),
+            texture_resources: None,
+            instance_buffer,
             instance_buffer_memory: None,
-            instance_memory_block: Some(instance_memory_block),
+            instance_memory_block,
+            instance_memory,
+            instance_shadow,
             instance_count,
             use_instancing: true,
             base_color: [1.0, 1.0, 1.0, 1.0], // Default white
@@ -1808,10 +3503,68 @@ impl VulkanRenderer {
             skinned_descriptor_sets: None,
             camera_uniform_buffer: None,
             camera_uniform_memory: None,
+            local_bounds: mesh_bounds(mesh_data),
+            local_centroid: mesh_centroid(mesh_data),
+            instance_bounds: instance_bounds(&instance_positions),
+            ..Default::default()
         };
         
         self.meshes.push(mesh_entry);
-        Ok(self.meshes.len() - 1)
+        self.mark_static_scene_dirty();
+        let mesh_index = self.meshes.len() - 1;
+        self.set_mesh_debug_names(mesh_index);
+        if mesh_data.double_sided {
+            self.make_mesh_double_sided(mesh_index);
+        }
+        Ok(mesh_index)
+    }
+    
+    // Returns the shadow copy of DeviceLocal buffers, see MeshEntry::instance_shadow
+    fn create_pooled_instance_buffer(
+        &mut self,
+        instance_data: &[u8],
+        instance_memory: InstanceMemory,
+    ) -> Result<(vk::Buffer, MemoryBlock, Option<Vec<u8>>), FloError> {
+        let properties = match instance_memory {
+            InstanceMemory::HostVisible => vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            InstanceMemory::DeviceLocal => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        };
+        let (instance_buffer, instance_memory_block) = create_buffer_pooled(
+            &self.core.device,
+            self.core.physical_device,
+            &self.core.instance,
+            &mut self.memory_pool,
+            instance_data.len() as vk::DeviceSize,
+            // Storage usage lets the culling compute shader read it, updates are copied in
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            properties,
+        )?;
+        
+        if instance_memory == InstanceMemory::DeviceLocal {
+            // The block can be larger than the data, and counts are clamped to the block
+            let mut shadow = vec![0u8; (instance_memory_block.size as usize).max(instance_data.len())];
+            shadow[..instance_data.len()].copy_from_slice(instance_data);
+            self.upload_vertex_data(instance_buffer, instance_data);
+            return Ok((instance_buffer, instance_memory_block, Some(shadow)));
+        }
+        
+        // Copy instance data to buffer
+        unsafe {
+            let data = self.core.device.map_memory(
+                instance_memory_block.memory,
+                instance_memory_block.offset,
+                instance_memory_block.size,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            std::ptr::copy_nonoverlapping(
+                instance_data.as_ptr(),
+                data as *mut u8,
+                instance_data.len(),
+            );
+            self.core.device.unmap_memory(instance_memory_block.memory);
+        }
+        
+        Ok((instance_buffer, instance_memory_block, None))
     }
     
     // Update instance buffer for a specific mesh
@@ -1819,9 +3572,38 @@ impl VulkanRenderer {
         &mut self, 
         mesh_index: usize, 
         instance_positions: Vec<[f32; 3]>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), FloError> {
         if mesh_index >= self.meshes.len() {
-            return Err("Invalid mesh index".into());
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        if self.meshes[mesh_index].instance_data.is_some() {
+            return Err("Mesh has colored instances, update them with update_mesh_instance_transforms".into());
+        }
+        if self.meshes[mesh_index].instance_matrices.is_some() {
+            return Err("Mesh has instance matrices, update them with update_instance_matrices_ssbo".into());
+        }
+        
+        self.meshes[mesh_index].instance_bounds = instance_bounds(&instance_positions);
+        if self.meshes[mesh_index].instance_sort.is_some() {
+            // Uploaded as given, the next frame sorts them
+            self.meshes[mesh_index].unsorted_instance_positions = instance_positions.clone();
+        }
+        self.mark_static_scene_dirty();
+        
+        // A mesh added with no instances gets its buffer on the first non-empty update
+        if !instance_positions.is_empty()
+            && self.meshes[mesh_index].use_instancing
+            && self.meshes[mesh_index].instance_buffer.is_none() {
+            let instance_memory = self.meshes[mesh_index].instance_memory;
+            let (instance_buffer, instance_memory_block, instance_shadow) =
+                self.create_pooled_instance_buffer(bytemuck::cast_slice(&instance_positions), instance_memory)?;
+            let mesh = &mut self.meshes[mesh_index];
+            mesh.instance_buffer = Some(instance_buffer);
+            mesh.instance_memory_block = Some(instance_memory_block);
+            mesh.instance_shadow = instance_shadow;
+            mesh.instance_count = instance_positions.len() as u32;
+            self.set_mesh_debug_names(mesh_index);
+            return Ok(());
         }
         
         let mesh = &mut self.meshes[mesh_index];
@@ -1829,434 +3611,3297 @@ impl VulkanRenderer {
         // Update instance count
         mesh.instance_count = instance_positions.len() as u32;
         
-        // Update instance buffer data
-        if let Some(instance_buffer_memory) = mesh.instance_buffer_memory {
-            unsafe {
-                let data = self.core.device.map_memory(
-                    instance_buffer_memory,
-                    0,
-                    (instance_positions.len() * std::mem::size_of::<[f32; 3]>()) as vk::DeviceSize,
-                    vk::MemoryMapFlags::empty(),
-                )?;
-                std::ptr::copy_nonoverlapping(
-                    instance_positions.as_ptr() as *const u8,
-                    data as *mut u8,
-                    instance_positions.len() * std::mem::size_of::<[f32; 3]>(),
-                );
-                self.core.device.unmap_memory(instance_buffer_memory);
-            }
+        if let Some(culling) = mesh.instance_culling.as_mut() {
+            culling.set_instance_positions(instance_positions.clone());
         }
         
+        // Nothing to upload, the draw is skipped while the count is zero
+        if instance_positions.is_empty() {
+            return Ok(());
+        }
+        
+        // Earlier frames may still be reading the buffer, so the data is copied in at the start
+        // of the next frame like vertex updates
+        let Some(instance_buffer) = mesh.instance_buffer else {
+            return Ok(());
+        };
+        if instance_positions.len() > self.instance_capacity(mesh_index, std::mem::size_of::<[f32; 3]>()) {
+            return self.grow_instance_buffer(mesh_index, bytemuck::cast_slice(&instance_positions), std::mem::size_of::<[f32; 3]>());
+        }
+        self.upload_vertex_data(instance_buffer, bytemuck::cast_slice(&instance_positions));
+        
         Ok(())
     }
     
-    // Update transforms for a specific mesh
-    pub fn update_mesh_transforms(&mut self, mesh_index: usize, transforms: Vec<Mat4>) {
-        if mesh_index < self.meshes.len() {
-            self.meshes[mesh_index].transforms = transforms;
+    // Instances of stride bytes the mesh's instance buffer holds. Buffers created outside the
+    // pool are sized for the instances they were created with, which isn't kept, so they count
+    // as full.
+    fn instance_capacity(&self, mesh_index: usize, stride: usize) -> usize {
+        let mesh = &self.meshes[mesh_index];
+        match mesh.instance_memory_block {
+            Some(ref instance_block) => instance_block.size as usize / stride,
+            None => 0,
         }
     }
     
-    // Remove a mesh from the renderer and free its resources
-    pub fn remove_mesh(&mut self, mesh_index: usize) {
-        if mesh_index >= self.meshes.len() {
-            return;
-        }
+    // Pooled buffers can't grow in place, so updates past the capacity move the instances into
+    // a new buffer sized for the next power of two instances, once the GPU is done with the old
+    // one. GPU culling is set up again for the new buffer.
+    fn grow_instance_buffer(&mut self, mesh_index: usize, instance_bytes: &[u8], stride: usize) -> Result<(), FloError> {
+        let count = instance_bytes.len() / stride;
+        let mut data = instance_bytes.to_vec();
+        data.resize(count.next_power_of_two() * stride, 0);
         
-        let mesh = &self.meshes[mesh_index];
+        let culled = self.meshes[mesh_index].instance_culling.is_some();
+        self.disable_gpu_culling(mesh_index);
         
+        let mesh = &mut self.meshes[mesh_index];
+        let old_buffer = mesh.instance_buffer.take();
+        let old_memory = mesh.instance_buffer_memory.take();
+        let old_block = mesh.instance_memory_block.take();
+        self.pending_vertex_uploads.retain(|upload| Some(upload.buffer) != old_buffer);
         unsafe {
-            // Destroy buffers
-            self.core.device.destroy_buffer(mesh.vertex_buffer, None);
-            self.core.device.destroy_buffer(mesh.index_buffer, None);
-            
-            // Free memory if not using memory pool
-            if let Some(vertex_memory) = mesh.vertex_buffer_memory {
-                self.core.device.free_memory(vertex_memory, None);
-            }
-            if let Some(index_memory) = mesh.index_buffer_memory {
-                self.core.device.free_memory(index_memory, None);
-            }
-            
-            // Free memory pool blocks if using memory pool
-            if let Some(vertex_block) = &mesh.vertex_memory_block {
-                self.memory_pool.free_buffer(vertex_block.clone());
-            }
-            if let Some(index_block) = &mesh.index_memory_block {
-                self.memory_pool.free_buffer(index_block.clone());
-            }
-            
-            // Destroy instance buffer if present
-            if let Some(instance_buffer) = mesh.instance_buffer {
-                self.core.device.destroy_buffer(instance_buffer, None);
-            }
-            if let Some(instance_memory) = mesh.instance_buffer_memory {
-                self.core.device.free_memory(instance_memory, None);
-            }
-            if let Some(instance_block) = &mesh.instance_memory_block {
-                self.memory_pool.free_buffer(instance_block.clone());
-            }
-            
-            // Clean up texture resources if present
-            if let Some(texture_resources) = &mesh.texture_resources {
-                self.core.device.destroy_image_view(texture_resources.image_view, None);
-                self.core.device.destroy_image(texture_resources.image, None);
-                self.core.device.free_memory(texture_resources.image_memory, None);
-                self.core.device.destroy_sampler(texture_resources.sampler, None);
-                self.core.device.destroy_descriptor_pool(texture_resources.descriptor_pool, None);
-                self.core.device.destroy_descriptor_set_layout(texture_resources.descriptor_set_layout, None);
-            }
-            
-            // Clean up skinned mesh resources if present
-            if let Some(joint_buffer) = mesh.joint_buffer {
-                self.core.device.destroy_buffer(joint_buffer, None);
-            }
-            if let Some(joint_memory) = mesh.joint_buffer_memory {
-                self.core.device.free_memory(joint_memory, None);
+            self.core.device.device_wait_idle().map_err(|e| format!("Failed to wait for device idle: {:?}", e))?;
+            if let Some(buffer) = old_buffer {
+                leak_check::destroy(&self.core.device, buffer);
             }
-            
-            // Clean up descriptor sets for skinned meshes
-            if let Some(descriptor_pool) = mesh.skinned_descriptor_pool {
-                self.core.device.destroy_descriptor_pool(descriptor_pool, None);
+            if let Some(memory) = old_memory {
+                free_device_memory(&self.core.device, memory);
             }
-            if let Some(descriptor_set_layout) = mesh.skinned_descriptor_set_layout {
-                self.core.device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+        }
+        if let Some(block) = old_block {
+            self.memory_pool.free_buffer(block);
+        }
+        
+        let instance_memory = self.meshes[mesh_index].instance_memory;
+        let (instance_buffer, instance_memory_block, instance_shadow) = self.create_pooled_instance_buffer(&data, instance_memory)?;
+        let mesh = &mut self.meshes[mesh_index];
+        mesh.instance_buffer = Some(instance_buffer);
+        mesh.instance_memory_block = Some(instance_memory_block);
+        mesh.instance_shadow = instance_shadow;
+        mesh.instance_count = count as u32;
+        self.set_mesh_debug_names(mesh_index);
+        if culled {
+            self.enable_gpu_culling(mesh_index)?;
+        }
+        Ok(())
+    }
+    
+    // Removes one instance of an instanced mesh by moving the last instance into its slot, so
+    // only that instance is uploaded instead of the whole list. Returns the moved instance's new
+    // index (instance_index), or None when the last instance itself was removed. A mesh left
+    // with no instances isn't drawn until it gets new ones. Position only instances keep their
+    // bounds, which still enclose the rest. Instance matrices (add_mesh_instanced_ssbo) aren't
+    // supported.
+    pub fn remove_instance(&mut self, mesh_index: usize, instance_index: usize) -> Result<Option<usize>, FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        if !mesh.use_instancing {
+            return Err(format!("Mesh {} isn't instanced", mesh_index).into());
+        }
+        if mesh.instance_matrices.is_some() {
+            return Err("Mesh has instance matrices, update them with update_instance_matrices_ssbo".into());
+        }
+        let count = mesh.instance_count as usize;
+        if instance_index >= count {
+            return Err(format!("Instance {} is out of range, mesh {} has {} instances", instance_index, mesh_index, count).into());
+        }
+        let last = count - 1;
+        let moved = (instance_index != last).then_some(instance_index);
+        let instance_buffer = mesh.instance_buffer.ok_or("Mesh has no instance buffer")?;
+        
+        if let Some(ref mut instances) = self.meshes[mesh_index].instance_data {
+            swap_remove_instance(instances, count, instance_index);
+            let translations: Vec<[f32; 3]> = instances.iter()
+                .map(|instance| [instance.transform[12], instance.transform[13], instance.transform[14]])
+                .collect();
+            let moved_bytes = moved.map(|index| bytemuck::bytes_of(&instances[index]).to_vec());
+            self.meshes[mesh_index].instance_bounds = instance_bounds(&translations);
+            if let Some(moved_bytes) = moved_bytes {
+                let offset = (instance_index * std::mem::size_of::<InstanceData>()) as vk::DeviceSize;
+                self.upload_vertex_data_at(instance_buffer, offset, &moved_bytes);
             }
-            if let Some(camera_buffer) = mesh.camera_uniform_buffer {
-                self.core.device.destroy_buffer(camera_buffer, None);
+        } else if self.meshes[mesh_index].instance_sort.is_some() {
+            // The buffer is sorted, the next frame rewrites all of it
+            swap_remove_instance(&mut self.meshes[mesh_index].unsorted_instance_positions, count, instance_index);
+        } else {
+            let stride = std::mem::size_of::<[f32; 3]>();
+            if moved.is_some() {
+                let moved_bytes = self.read_instance_bytes(mesh_index, last * stride, stride)?;
+                self.upload_vertex_data_at(instance_buffer, (instance_index * stride) as vk::DeviceSize, &moved_bytes);
             }
-            if let Some(camera_memory) = mesh.camera_uniform_memory {
-                self.core.device.free_memory(camera_memory, None);
+            if let Some(culling) = self.meshes[mesh_index].instance_culling.as_mut() {
+                culling.swap_remove_instance(instance_index);
             }
         }
         
-        // Mark the mesh slot as invalid by clearing it
-        // We don't actually remove from the vector to preserve indices
-        // Instead, we'll mark it as invalid by setting vertex count to 0
-        self.meshes[mesh_index] = MeshEntry {
-            vertex_buffer: vk::Buffer::null(),
-            vertex_buffer_memory: None,
-            vertex_memory_block: None,
-            index_buffer: vk::Buffer::null(),
-            index_buffer_memory: None,
-            index_memory_block: None,
-            index_count: 0,
-            transforms: Vec::new(),
-            pipeline_name: None,
-            texture_resources: None,
-            instance_buffer: None,
-            instance_buffer_memory: None,
-            instance_memory_block: None,
-            instance_count: 0,
-            use_instancing: false,
-            base_color: [1.0, 1.0, 1.0, 1.0],
-            is_skinned: false,
-            joint_matrices: None,
-            joint_buffer: None,
-            joint_buffer_memory: None,
-            skinned_descriptor_pool: None,
-            skinned_descriptor_set_layout: None,
-            skinned_descriptor_sets: None,
-            camera_uniform_buffer: None,
-            camera_uniform_memory: None,
-        };
+        self.meshes[mesh_index].instance_count = last as u32;
+        self.mark_static_scene_dirty();
+        Ok(moved)
     }
     
-    // Update joint matrices for a specific skinned mesh
-    pub fn update_skinned_mesh_joints(&mut self, mesh_index: usize, joint_matrices: Vec<Mat4>) -> Result<(), Box<dyn std::error::Error>> {
-        if mesh_index >= self.meshes.len() {
-            return Err("Invalid mesh index".into());
-        }
-        
-        let mesh = &mut self.meshes[mesh_index];
-        
-        // Store the joint matrices
-        mesh.joint_matrices = Some(joint_matrices.clone());
-        mesh.is_skinned = true;
+    // Add an instanced mesh with a transform and color per instance, drawn with a pipeline
+    // from add_instanced_color_pipeline. GPU culling only supports position only instances.
+    pub fn add_mesh_instanced_with_colors(
+        &mut self,
+        mesh_data: &MeshData,
+        instances: Vec<InstanceData>,
+        pipeline_name: Option<String>,
+    ) -> Result<usize, FloError> {
+        let mesh_index = self.add_mesh_instanced(mesh_data, Vec::new(), None, pipeline_name)?;
+        self.meshes[mesh_index].instance_data = Some(instances);
+        if let Err(e) = self.write_mesh_instance_data(mesh_index) {
+            self.remove_mesh(mesh_index);
+            return Err(e);
+        }
+        Ok(mesh_index)
+    }
+    
+    // Move the instances of a mesh added with add_mesh_instanced_with_colors. Instances past the
+    // old count start out white.
+    pub fn update_mesh_instance_transforms(&mut self, mesh_index: usize, transforms: &[Mat4]) -> Result<(), FloError> {
+        let instances = self.meshes.get_mut(mesh_index)
+            .ok_or(FloError::InvalidMeshIndex(mesh_index))?
+            .instance_data.as_mut()
+            .ok_or("Mesh wasn't added with add_mesh_instanced_with_colors")?;
+        instances.resize(transforms.len(), InstanceData::new(Mat4::IDENTITY, [1.0, 1.0, 1.0, 1.0]));
+        for (instance, transform) in instances.iter_mut().zip(transforms) {
+            instance.transform = transform.to_cols_array();
+        }
+        self.write_mesh_instance_data(mesh_index)
+    }
+    
+    // Recolor the instances of a mesh added with add_mesh_instanced_with_colors, one color per instance
+    pub fn update_mesh_instance_colors(&mut self, mesh_index: usize, colors: &[[f32; 4]]) -> Result<(), FloError> {
+        let instances = self.meshes.get_mut(mesh_index)
+            .ok_or(FloError::InvalidMeshIndex(mesh_index))?
+            .instance_data.as_mut()
+            .ok_or("Mesh wasn't added with add_mesh_instanced_with_colors")?;
+        if colors.len() != instances.len() {
+            return Err(format!("Got {} instance colors for {} instances", colors.len(), instances.len()).into());
+        }
+        for (instance, &color) in instances.iter_mut().zip(colors) {
+            instance.color = color;
+        }
+        self.write_mesh_instance_data(mesh_index)
+    }
+    
+    // Pick the texture array layer of each instance of a mesh added with
+    // add_mesh_instanced_with_colors, see set_mesh_texture_array. Layers past the end of the
+    // array are clamped to its last layer by the shader.
+    pub fn update_mesh_instance_tex_indices(&mut self, mesh_index: usize, tex_indices: &[u32]) -> Result<(), FloError> {
+        let instances = self.meshes.get_mut(mesh_index)
+            .ok_or(FloError::InvalidMeshIndex(mesh_index))?
+            .instance_data.as_mut()
+            .ok_or("Mesh wasn't added with add_mesh_instanced_with_colors")?;
+        if tex_indices.len() != instances.len() {
+            return Err(format!("Got {} instance texture indices for {} instances", tex_indices.len(), instances.len()).into());
+        }
+        for (instance, &texture_index) in instances.iter_mut().zip(tex_indices) {
+            instance.texture_index = texture_index;
+        }
+        self.write_mesh_instance_data(mesh_index)
+    }
+    
+    // Copies a mesh's InstanceData into its instance buffer, growing it like position only
+    // instances when there are more instances than it holds
+    fn write_mesh_instance_data(&mut self, mesh_index: usize) -> Result<(), FloError> {
+        let Some(ref instances) = self.meshes[mesh_index].instance_data else {
+            return Ok(());
+        };
+        let translations: Vec<[f32; 3]> = instances.iter()
+            .map(|instance| [instance.transform[12], instance.transform[13], instance.transform[14]])
+            .collect();
+        let instance_bytes: Vec<u8> = bytemuck::cast_slice(instances).to_vec();
+        let instance_count = instances.len();
+        self.meshes[mesh_index].instance_bounds = instance_bounds(&translations);
+        self.mark_static_scene_dirty();
+        
+        if instance_count == 0 {
+            self.meshes[mesh_index].instance_count = 0;
+            return Ok(());
+        }
+        if self.meshes[mesh_index].instance_buffer.is_none() {
+            let instance_memory = self.meshes[mesh_index].instance_memory;
+            let (instance_buffer, instance_memory_block, instance_shadow) = self.create_pooled_instance_buffer(&instance_bytes, instance_memory)?;
+            let mesh = &mut self.meshes[mesh_index];
+            mesh.instance_buffer = Some(instance_buffer);
+            mesh.instance_memory_block = Some(instance_memory_block);
+            mesh.instance_shadow = instance_shadow;
+            mesh.instance_count = instance_count as u32;
+            self.set_mesh_debug_names(mesh_index);
+            return Ok(());
+        }
         
-        // Create or update the joint buffer if needed
-        if mesh.joint_buffer.is_none() {
-            // Create joint buffer for this mesh
-            let buffer_size = (std::mem::size_of::<Mat4>() * 128) as vk::DeviceSize;
-            
-            let (joint_buffer, joint_buffer_memory) = crate::vulkan_common::create_buffer(
-                &self.core.instance,
-                &self.core.device,
-                self.core.physical_device,
-                buffer_size,
-                vk::BufferUsageFlags::UNIFORM_BUFFER,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            )?;
-            
-            mesh.joint_buffer = Some(joint_buffer);
-            mesh.joint_buffer_memory = Some(joint_buffer_memory);
+        // Copied in at the start of the next frame, see update_mesh_instance_buffer
+        if instance_count > self.instance_capacity(mesh_index, std::mem::size_of::<InstanceData>()) {
+            return self.grow_instance_buffer(mesh_index, &instance_bytes, std::mem::size_of::<InstanceData>());
+        }
+        if let Some(instance_buffer) = self.meshes[mesh_index].instance_buffer {
+            self.meshes[mesh_index].instance_count = instance_count as u32;
+            self.upload_vertex_data(instance_buffer, &instance_bytes);
+        }
+        Ok(())
+    }
+    
+    // Add an instanced mesh whose vertex shader reads a model matrix per instance from a storage
+    // buffer indexed by gl_InstanceIndex, drawn with a pipeline from add_instance_ssbo_pipeline.
+    // Unlike add_mesh_instanced_with_colors this needs no per-instance vertex attributes, so the
+    // matrices can be written by compute shaders too. The buffer holds as many matrices as the
+    // mesh was created with.
+    pub fn add_mesh_instanced_ssbo(
+        &mut self,
+        mesh_data: &MeshData,
+        transforms: &[Mat4],
+        pipeline_name: Option<String>,
+    ) -> Result<usize, FloError> {
+        if transforms.is_empty() {
+            return Err("Meshes with instance matrices need at least one instance".into());
+        }
+        // Reading storage buffers from vertex shaders needs no feature, only writing does, but
+        // the whole buffer has to fit in one binding
+        let size = std::mem::size_of_val(transforms) as u64;
+        let max_range = unsafe {
+            self.core.instance.get_physical_device_properties(self.core.physical_device)
+        }.limits.max_storage_buffer_range as u64;
+        if size > max_range {
+            return Err(FloError::UnsupportedDevice(format!(
+                "{} instance matrices take {} bytes, more than the device's storage buffer range of {}",
+                transforms.len(), size, max_range
+            )));
         }
         
-        // Update the joint buffer with new matrices
-        if let Some(joint_buffer_memory) = mesh.joint_buffer_memory {
-            unsafe {
-                let data = self.core.device.map_memory(
-                    joint_buffer_memory,
-                    0,
-                    (std::mem::size_of::<Mat4>() * 128) as vk::DeviceSize,
-                    vk::MemoryMapFlags::empty(),
-                )? as *mut Mat4;
-                
-                // Copy joint matrices to buffer
-                for (i, mat) in joint_matrices.iter().enumerate() {
-                    if i >= 128 { break; }
-                    data.add(i).write(*mat);
-                }
-                
-                self.core.device.unmap_memory(joint_buffer_memory);
+        let mesh_index = self.add_mesh_instanced(mesh_data, Vec::new(), None, pipeline_name)?;
+        let matrices: Vec<[f32; 16]> = transforms.iter().map(Mat4::to_cols_array).collect();
+        let descriptors = self.create_pooled_instance_buffer(bytemuck::cast_slice(&matrices), InstanceMemory::HostVisible)
+            .and_then(|(instance_buffer, instance_memory_block, _)| {
+                let mesh = &mut self.meshes[mesh_index];
+                mesh.instance_buffer = Some(instance_buffer);
+                mesh.instance_memory_block = Some(instance_memory_block);
+                create_instance_matrix_descriptors(&self.core.device, instance_buffer)
+            });
+        let descriptors = match descriptors {
+            Ok(descriptors) => descriptors,
+            Err(e) => {
+                self.remove_mesh(mesh_index);
+                return Err(e);
             }
-        }
+        };
         
+        let translations: Vec<[f32; 3]> = transforms.iter().map(|transform| transform.w_axis.truncate().into()).collect();
+        let mesh = &mut self.meshes[mesh_index];
+        mesh.instance_count = transforms.len() as u32;
+        mesh.instance_bounds = instance_bounds(&translations);
+        mesh.instance_matrices = Some(descriptors);
+        self.set_mesh_debug_names(mesh_index);
+        Ok(mesh_index)
+    }
+    
+    // Replace the instance matrices of a mesh from add_mesh_instanced_ssbo. Copied in at the
+    // start of the next frame like update_mesh_instance_buffer; matrices past the count the
+    // mesh was created with aren't drawn.
+    pub fn update_instance_matrices_ssbo(&mut self, mesh_index: usize, transforms: &[Mat4]) -> Result<(), FloError> {
+        let mesh = self.meshes.get_mut(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        if mesh.instance_matrices.is_none() {
+            return Err("Mesh wasn't added with add_mesh_instanced_ssbo".into());
+        }
+        let (Some(instance_buffer), Some(ref instance_block)) = (mesh.instance_buffer, &mesh.instance_memory_block) else {
+            return Err("Mesh has no instance buffer".into());
+        };
+        let capacity = instance_block.size as usize / std::mem::size_of::<[f32; 16]>();
+        let count = transforms.len().min(capacity);
+        let translations: Vec<[f32; 3]> = transforms.iter().map(|transform| transform.w_axis.truncate().into()).collect();
+        mesh.instance_count = count as u32;
+        mesh.instance_bounds = instance_bounds(&translations[..count]);
+        self.mark_static_scene_dirty();
+        
+        if count > 0 {
+            let matrices: Vec<[f32; 16]> = transforms[..count].iter().map(Mat4::to_cols_array).collect();
+            self.upload_vertex_data(instance_buffer, bytemuck::cast_slice(&matrices));
+        }
         Ok(())
     }
     
-    pub fn set_mesh_pipeline(&mut self, mesh_index: usize, pipeline_name: &str) {
+    // Update transforms for a specific mesh
+    pub fn update_mesh_transforms(&mut self, mesh_index: usize, transforms: Vec<Mat4>) {
         if mesh_index < self.meshes.len() {
-            self.meshes[mesh_index].pipeline_name = Some(pipeline_name.to_string());
+            self.set_mesh_transforms(mesh_index, transforms);
         }
     }
     
-    // Set water push constants for fluid rendering
-    pub fn set_water_push_constants(&mut self, push_constants: PushConstants) {
-        self.water_push_constants = Some(push_constants);
+    // Parented meshes get their transforms relative to the parent
+    fn set_mesh_transforms(&mut self, mesh_index: usize, transforms: Vec<Mat4>) {
+        let mesh = &mut self.meshes[mesh_index];
+        if mesh.parent.is_some() {
+            mesh.local_transforms = transforms;
+        } else {
+            mesh.transforms = transforms;
+        }
+        self.mesh_hierarchy_dirty = true;
+        self.mark_static_scene_dirty();
     }
     
-    pub fn set_mesh_color(&mut self, mesh_index: usize, color: [f32; 4]) {
-        if mesh_index < self.meshes.len() {
-            self.meshes[mesh_index].base_color = color;
+    // Attach a mesh to another so it follows it, e.g. a windmill's blades or a cart's wheels.
+    // The child's transforms (update_mesh_transforms) become relative to the parent's first
+    // world transform, and are composed with it before every frame that follows a change.
+    // The child's current transforms are kept as its local ones, and detaching (None) makes the
+    // local transforms its world ones again. A parent without transforms hides its children.
+    // Fails on instanced parents, which have no transforms, and on links that would make a cycle.
+    pub fn set_mesh_parent(&mut self, mesh_index: usize, parent: Option<usize>) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        if let Some(parent) = parent {
+            let parent_mesh = self.meshes.get(parent).ok_or(FloError::InvalidMeshIndex(parent))?;
+            if parent_mesh.use_instancing {
+                return Err(format!("Mesh {} is instanced and can't be a parent", parent).into());
+            }
+            if makes_parent_cycle(&self.meshes, mesh_index, parent) {
+                return Err(format!("Parenting mesh {} to mesh {} would make a cycle", mesh_index, parent).into());
+            }
+        }
+        
+        let mesh = &mut self.meshes[mesh_index];
+        match (mesh.parent.is_some(), parent.is_some()) {
+            (false, true) => mesh.local_transforms = std::mem::take(&mut mesh.transforms),
+            (true, false) => mesh.transforms = std::mem::take(&mut mesh.local_transforms),
+            _ => {}
         }
+        mesh.parent = parent;
+        self.mesh_hierarchy_dirty = true;
+        self.mark_static_scene_dirty();
+        Ok(())
     }
     
-    // Add texture to a specific mesh from a file path
-    pub fn set_mesh_texture_from_file(&mut self, mesh_index: usize, texture_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn mesh_parent(&self, mesh_index: usize) -> Option<usize> {
+        self.meshes.get(mesh_index).and_then(|mesh| mesh.parent)
+    }
+    
+    // Composes parented meshes' local transforms with their parent's world transform, parents
+    // before their children
+    fn resolve_mesh_hierarchy(&mut self) {
+        if !self.mesh_hierarchy_dirty {
+            return;
+        }
+        self.mesh_hierarchy_dirty = false;
+        resolve_world_transforms(&mut self.meshes);
+        self.mark_static_scene_dirty();
+    }
+    
+    // Remove a mesh from the renderer and free its resources
+    pub fn remove_mesh(&mut self, mesh_index: usize) {
         if mesh_index >= self.meshes.len() {
-            return Err("Invalid mesh index".into());
+            return;
         }
         
-        // Create texture resources
-        let (texture_image, texture_image_memory) = crate::vulkan_common::create_texture_image(
-            &self.core.instance,
-            &self.core.device,
-            self.core.physical_device,
-            self.core.command_pool,
-            self.core.graphics_queue,
-            texture_path,
-        )?;
-        
-        let texture_image_view = crate::vulkan_common::create_texture_image_view(&self.core.device, texture_image)?;
-        let texture_sampler = crate::vulkan_common::create_texture_sampler(&self.core.instance, &self.core.device, self.core.physical_device)?;
+        // Drop any texture still streaming in for this slot
+        self.texture_streamer.cancel(mesh_index);
+        self.cancel_vertex_uploads(mesh_index);
+        if let Some(instance_buffer) = self.meshes[mesh_index].instance_buffer {
+            self.pending_vertex_uploads.retain(|upload| upload.buffer != instance_buffer);
+        }
+        self.mark_static_scene_dirty();
         
-        // Create descriptor resources
-        let binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(0)
-            .descriptor_count(1)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        // Children keep the world transforms they were last drawn with
+        for child in self.meshes.iter_mut().filter(|child| child.parent == Some(mesh_index)) {
+            child.parent = None;
+            child.local_transforms.clear();
+        }
         
-        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &[binding])?;
+        let mesh = &self.meshes[mesh_index];
         
-        let pool_size = vk::DescriptorPoolSize::default()
-            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(self.core.swapchain_images.len() as u32);
+        unsafe {
+            // Destroy buffers
+            destroy_mesh_vertex_buffers(&self.core.device, &mut self.memory_pool, mesh);
+            leak_check::destroy(&self.core.device, mesh.index_buffer);
+            
+            // Free memory if not using memory pool
+            if let Some(index_memory) = mesh.index_buffer_memory {
+                free_device_memory(&self.core.device, index_memory);
+            }
+            
+            // Free memory pool blocks if using memory pool
+            if let Some(index_block) = &mesh.index_memory_block {
+                self.memory_pool.free_buffer(index_block.clone());
+            }
+            
+            // Destroy instance buffer if present
+            if let Some(instance_buffer) = mesh.instance_buffer {
+                leak_check::destroy(&self.core.device, instance_buffer);
+            }
+            if let Some(instance_memory) = mesh.instance_buffer_memory {
+                free_device_memory(&self.core.device, instance_memory);
+            }
+            if let Some(instance_block) = &mesh.instance_memory_block {
+                self.memory_pool.free_buffer(instance_block.clone());
+            }
+            
+            // Clean up texture resources if present
+            if let Some(texture_resources) = &mesh.texture_resources {
+                destroy_texture_resources(&self.core.device, &mut self.descriptor_allocator, texture_resources);
+            }
+            
+            if let Some(proxy_buffer) = mesh.occlusion_proxy_buffer {
+                leak_check::destroy(&self.core.device, proxy_buffer);
+            }
+            if let Some(proxy_memory) = mesh.occlusion_proxy_memory {
+                free_device_memory(&self.core.device, proxy_memory);
+            }
+            
+            if let Some(ref culling) = mesh.instance_culling {
+                culling.destroy(&self.core.device);
+            }
+            
+            // Clean up skinned mesh resources if present. Meshes in the joint arena share its
+            // joint and camera buffers, and only give their range back.
+            if let Some(joint_arena_size) = mesh.joint_arena_size {
+                if let Some(ref mut arena) = self.joint_arena {
+                    arena.free(mesh.joint_offset, joint_arena_size);
+                }
+            } else {
+                if let Some(joint_buffer) = mesh.joint_buffer {
+                    leak_check::destroy(&self.core.device, joint_buffer);
+                }
+                if let Some(joint_memory) = mesh.joint_buffer_memory {
+                    free_device_memory(&self.core.device, joint_memory);
+                }
+                if let Some(camera_buffer) = mesh.camera_uniform_buffer {
+                    leak_check::destroy(&self.core.device, camera_buffer);
+                }
+                if let Some(camera_memory) = mesh.camera_uniform_memory {
+                    free_device_memory(&self.core.device, camera_memory);
+                }
+            }
+            for (buffer, memory) in [
+                (mesh.morph_delta_buffer, mesh.morph_delta_memory),
+                (mesh.morph_weight_buffer, mesh.morph_weight_memory),
+            ] {
+                if let (Some(buffer), Some(memory)) = (buffer, memory) {
+                    destroy_buffer(&self.core.device, buffer, memory);
+                }
+            }
+            
+            // Clean up descriptor sets for skinned meshes
+            if let Some(descriptor_pool) = mesh.skinned_descriptor_pool {
+                let sets = mesh.skinned_descriptor_sets.as_deref().unwrap_or_default();
+                release_descriptor_sets(&self.core.device, &mut self.descriptor_allocator, descriptor_pool, sets);
+            }
+            if let Some(descriptor_set_layout) = mesh.skinned_descriptor_set_layout {
+                leak_check::destroy(&self.core.device, descriptor_set_layout);
+            }
+            if let Some(ref descriptors) = mesh.instance_matrices {
+                leak_check::destroy(&self.core.device, descriptors.descriptor_pool);
+                leak_check::destroy(&self.core.device, descriptors.descriptor_set_layout);
+            }
+        }
+        if let Some(ref hierarchy) = mesh.bone_hierarchy {
+            hierarchy.destroy(&self.core.device);
+        }
         
-        let descriptor_pool = create_descriptor_pool(&self.core.device, self.core.swapchain_images.len() as u32, &[pool_size])?;
+        // Mark the mesh slot as invalid by clearing it
+        // We don't actually remove from the vector to preserve indices
+        // Instead, we'll mark it as invalid by setting vertex count to 0
+        self.meshes[mesh_index] = MeshEntry {
+            vertex_buffer: vk::Buffer::null(),
+            vertex_buffer_memory: None,
+            vertex_memory_block: None,
+            index_buffer: vk::Buffer::null(),
+            index_buffer_memory: None,
+            index_memory_block: None,
+            index_count: 0,
+            transforms: Vec::new(),
+            pipeline_name: None,
+            texture_resources: None,
+            instance_buffer: None,
+            instance_buffer_memory: None,
+            instance_memory_block: None,
+            instance_count: 0,
+            use_instancing: false,
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            is_skinned: false,
+            joint_matrices: None,
+            joint_buffer: None,
+            joint_buffer_memory: None,
+            skinned_descriptor_pool: None,
+            skinned_descriptor_set_layout: None,
+            skinned_descriptor_sets: None,
+            camera_uniform_buffer: None,
+            camera_uniform_memory: None,
+            ..Default::default()
+        };
+    }
+    
+    // Update joint matrices for a specific skinned mesh
+    pub fn update_skinned_mesh_joints(&mut self, mesh_index: usize, joint_matrices: Vec<Mat4>) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
         
-        let layouts = vec![descriptor_set_layout; self.core.swapchain_images.len()];
-        let descriptor_sets = allocate_descriptor_sets(&self.core.device, descriptor_pool, &layouts)?;
+        let skinning_mode = self.pipeline_skinning_mode(self.meshes[mesh_index].pipeline_name.as_deref());
+        let mesh = &mut self.meshes[mesh_index];
         
-        // Update descriptor sets
-        for &descriptor_set in &descriptor_sets {
-            update_descriptor_sets_texture(&self.core.device, descriptor_set, texture_image_view, texture_sampler, 0);
+        // Create the joint buffer sized to the first joint matrices, or update it
+        match mesh.joint_buffer_memory {
+            Some(joint_buffer_memory) => {
+                let joint_count = mesh.joint_matrices.as_ref().map_or(0, |matrices| matrices.len());
+                if joint_matrices.len() != joint_count {
+                    return Err(format!(
+                        "Mesh {} has {} joints, got {} joint matrices",
+                        mesh_index, joint_count, joint_matrices.len()
+                    ).into());
+                }
+                write_joint_matrices(&self.core.device, joint_buffer_memory, mesh.joint_offset, &skinned_mesh::joint_buffer_data(&joint_matrices, skinning_mode))?;
+            }
+            None => {
+                let (joint_buffer, joint_buffer_memory) = create_joint_buffer(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    &skinned_mesh::joint_buffer_data(&joint_matrices, skinning_mode),
+                )?;
+                
+                mesh.joint_buffer = Some(joint_buffer);
+                mesh.joint_buffer_memory = Some(joint_buffer_memory);
+            }
         }
         
-        let textures = TextureResources {
-            image: texture_image,
-            image_memory: texture_image_memory,
-            image_view: texture_image_view,
-            sampler: texture_sampler,
-            descriptor_pool,
-            descriptor_set_layout,
-            descriptor_sets,
-        };
+        // Store the joint matrices
+        mesh.joint_matrices = Some(joint_matrices);
+        mesh.is_skinned = true;
         
-        self.meshes[mesh_index].texture_resources = Some(textures);
         Ok(())
     }
     
-    // Update instance positions for a specific mesh (convenience method)
-    pub fn update_mesh_instances(&mut self, mesh_index: usize, positions: Vec<[f32; 3]>) {
-        if mesh_index < self.meshes.len() {
-            self.meshes[mesh_index].transforms = positions.iter()
-                .map(|pos| Mat4::from_translation(Vec3::new(pos[0], pos[1], pos[2])))
-                .collect();
-        } else {
-            println!("ERROR: mesh_index {} out of bounds (meshes.len = {})", mesh_index, self.meshes.len());
+    // Blend weights of a skinned mesh's morph targets (see SkinnedMeshData::with_morph_targets),
+    // shared by all its instances. Weights past the end of the slice keep their values.
+    pub fn set_morph_weights(&mut self, mesh_index: usize, weights: &[f32]) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        let Some(morph_weight_memory) = mesh.morph_weight_memory else {
+            return Err(format!("Mesh {} has no morph targets", mesh_index).into());
+        };
+        if weights.len() > mesh.morph_target_count as usize {
+            return Err(format!(
+                "Mesh {} has {} morph targets, got {} weights",
+                mesh_index, mesh.morph_target_count, weights.len()
+            ).into());
         }
+        write_morph_buffer(&self.core.device, morph_weight_memory, weights)?;
+        Ok(())
     }
     
-    // Get the total number of meshes in the renderer
-    pub fn get_mesh_count(&self) -> usize {
-        self.meshes.len()
-    }
-    
-    // Update mesh vertices dynamically (for fluid simulation)
-    pub fn update_mesh_vertices(&mut self, mesh_index: usize, _new_positions: &[[f32; 3]]) {
-        if mesh_index >= self.meshes.len() {
-            eprintln!("ERROR: mesh_index {} out of bounds (meshes.len = {})", mesh_index, self.meshes.len());
-            return;
+    // Computes a skinned mesh's joint matrices from local bone transforms (see
+    // upload_bone_local_transforms) instead of taking final matrices. parents[i] is the parent
+    // of bone i, None for roots. Bone i drives joint i, so both slices need one entry per joint.
+    // Runs as a compute pass before the frame's draws, or on the CPU if the device can't.
+    pub fn set_bone_hierarchy(&mut self, mesh_index: usize, parents: &[Option<usize>], inverse_bind_matrices: &[Mat4]) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        let joint_buffer = mesh.joint_buffer.ok_or("Bone hierarchies need a skinned mesh with a joint buffer")?;
+        let joint_count = mesh.joint_matrices.as_ref().map_or(0, |matrices| matrices.len());
+        if parents.len() != joint_count {
+            return Err(format!("Mesh {} has {} joints, got {} bones", mesh_index, joint_count, parents.len()).into());
+        }
+        
+        let queue_family = self.core.queue_family_indices.graphics_family.unwrap_or(0);
+        if self.bone_hierarchy_pipeline.is_none()
+            && instance_culling::gpu_culling_supported(&self.core.instance, self.core.physical_device, queue_family) {
+            match BoneHierarchyPipeline::new(&self.core.device) {
+                Ok(bone_pipeline) => self.bone_hierarchy_pipeline = Some(bone_pipeline),
+                Err(e) => warn!("Failed to create bone hierarchy pipeline, computing joints on the CPU: {}", e),
+            }
         }
         
-        // TODO: Implement proper vertex buffer update
-        // For now, this is a placeholder - we need to:
-        // 1. Store the original vertex data in MeshEntry
-        // 2. Update the vertex buffer on the GPU
-        // 3. Handle synchronization properly
+        let hierarchy = BoneHierarchy::new(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            self.bone_hierarchy_pipeline.as_ref(),
+            joint_buffer,
+            self.meshes[mesh_index].joint_offset as vk::DeviceSize,
+            parents,
+            inverse_bind_matrices,
+        )?;
         
-        // This would require significant changes to MeshEntry structure
-        // to store vertex data and handle dynamic updates
+        if let Some(old_hierarchy) = self.meshes[mesh_index].bone_hierarchy.replace(hierarchy) {
+            unsafe {
+                // Frames in flight may still be dispatching from the old buffers
+                let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+            }
+            old_hierarchy.destroy(&self.core.device);
+        }
+        Ok(())
     }
     
-    pub fn update_mesh_vertices_full(&mut self, mesh_index: usize, new_vertices: &[Vertex]) {
-        if mesh_index >= self.meshes.len() {
-            eprintln!("ERROR: mesh_index {} out of bounds (meshes.len = {})", mesh_index, self.meshes.len());
-            return;
+    // Local transform of every bone relative to its parent, picked up by the next frame.
+    // With the compute path the mesh's joint_matrices aren't updated, only its joint buffer.
+    pub fn upload_bone_local_transforms(&mut self, mesh_index: usize, local_transforms: &[Transform]) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        let skinning_mode = self.pipeline_skinning_mode(mesh.pipeline_name.as_deref());
+        let mesh = &mut self.meshes[mesh_index];
+        let hierarchy = mesh.bone_hierarchy.as_mut().ok_or("Mesh has no bone hierarchy, see set_bone_hierarchy")?;
+        if local_transforms.len() != hierarchy.bone_count() {
+            return Err(format!(
+                "Mesh {} has {} bones, got {} transforms",
+                mesh_index, hierarchy.bone_count(), local_transforms.len()
+            ).into());
         }
         
-        let mesh = &mut self.meshes[mesh_index];
-        let vertex_data = bytemuck::cast_slice(new_vertices);
-        let vertex_size = vertex_data.len() as u64;
+        let local_matrices: Vec<Mat4> = local_transforms.iter().map(Transform::compute_matrix).collect();
+        // The compute pass writes matrices, dual quaternion meshes are converted on the CPU
+        let joint_matrices = if skinning_mode == SkinningMode::DualQuaternion {
+            Some(hierarchy.joint_matrices(&local_matrices))
+        } else {
+            hierarchy.set_local_matrices(local_matrices)
+        };
+        if let Some(joint_matrices) = joint_matrices {
+            self.update_mesh_joint_matrices(mesh_index, &joint_matrices);
+        }
+        Ok(())
+    }
+    
+    // Dispatch the bone hierarchies with new local transforms, must run outside the render pass
+    fn record_bone_hierarchies(&mut self, command_buffer: vk::CommandBuffer) {
+        let Some(ref bone_pipeline) = self.bone_hierarchy_pipeline else {
+            return;
+        };
+        if !self.meshes.iter().any(|mesh| mesh.bone_hierarchy.as_ref().is_some_and(BoneHierarchy::is_gpu)) {
+            return;
+        }
+        
+        let frame = self.core.current_frame;
+        let device = &self.core.device;
+        bone_hierarchy::record_joint_write_barrier(device, command_buffer);
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, bone_pipeline.pipeline);
+        }
+        for mesh in self.meshes.iter_mut() {
+            if let Some(hierarchy) = mesh.bone_hierarchy.as_mut() {
+                hierarchy.record_dispatch(device, command_buffer, frame, bone_pipeline);
+            }
+        }
+        bone_hierarchy::record_joint_read_barrier(device, command_buffer);
+    }
+    
+    // Emit GPU particles, e.g. a splash where something hit the water. They're simulated by a
+    // compute pass each multi mesh or fluid frame and drawn after the meshes, see particles.rs.
+    // Past MAX_PARTICLES live particles the oldest are overwritten.
+    pub fn spawn_particles(&mut self, particles: &[Particle]) -> Result<(), FloError> {
+        self.particle_system()?.spawn(particles);
+        Ok(())
+    }
+    
+    // Gravity, drag and the plane particles bounce off, e.g. the water level
+    pub fn set_particle_forces(&mut self, forces: ParticleForces) -> Result<(), FloError> {
+        self.particle_system()?.set_forces(forces);
+        Ok(())
+    }
+    
+    fn particle_system(&mut self) -> Result<&mut ParticleSystem, FloError> {
+        if self.particles.is_none() {
+            let queue_family = self.core.queue_family_indices.graphics_family.unwrap_or(0);
+            if !instance_culling::gpu_culling_supported(&self.core.instance, self.core.physical_device, queue_family) {
+                return Err(FloError::UnsupportedDevice("particles need compute on the graphics queue".to_string()));
+            }
+            self.particles = Some(ParticleSystem::new(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                self.core.render_pass,
+                self.core.swapchain_extent,
+                self.has_depth,
+            )?);
+        }
+        Ok(self.particles.as_mut().expect("particle system was just created"))
+    }
+    
+    // Spawn and integrate particles for this frame, must run outside the render pass
+    fn record_particle_simulation(&mut self, command_buffer: vk::CommandBuffer) {
+        let time = self.core.get_elapsed_time();
+        let frame = self.core.current_frame;
+        if let Some(particles) = self.particles.as_mut() {
+            particles.record_simulate(&self.core.device, command_buffer, frame, time);
+        }
+    }
+    
+    // Left out of debug views, which only cover meshes
+    fn record_particle_draw(&self, command_buffer: vk::CommandBuffer, view: Mat4, proj: Mat4) {
+        if self.debug_view != DebugView::None {
+            return;
+        }
+        if let Some(ref particles) = self.particles {
+            particles.record_draw(&self.core.device, command_buffer, view, proj, self.depth_compare_op());
+        }
+    }
+    
+    // Queue a dashed line for the next multi mesh or fluid frame, e.g. a blueprint or placement
+    // outline. Lines are only drawn by one frame, so queue them every frame they should show.
+    // Dash and gap lengths are in pixels (see set_dash_pattern), whatever the line's length.
+    pub fn draw_dashed_line(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) -> Result<(), FloError> {
+        self.dashed_line_renderer()?.queue_line(start, end, color);
+        Ok(())
+    }
+    
+    // Queue the 12 edges of the axis aligned box between min and max, each starting with a dash
+    pub fn draw_dashed_box(&mut self, min: Vec3, max: Vec3, color: [f32; 4]) -> Result<(), FloError> {
+        let lines = self.dashed_line_renderer()?;
+        for (start, end) in dashed_lines::box_edges(min, max) {
+            lines.queue_line(start, end, color);
+        }
+        Ok(())
+    }
+    
+    pub fn set_dash_pattern(&mut self, pattern: DashPattern) -> Result<(), FloError> {
+        self.dashed_line_renderer()?.set_pattern(pattern);
+        Ok(())
+    }
+    
+    fn dashed_line_renderer(&mut self) -> Result<&mut DashedLines, FloError> {
+        if self.dashed_lines.is_none() {
+            self.create_dynamic_ring()?;
+            self.dashed_lines = Some(DashedLines::new(
+                &self.core.device,
+                self.core.render_pass,
+                self.core.swapchain_extent,
+                self.has_depth,
+            )?);
+        }
+        Ok(self.dashed_lines.as_mut().expect("dashed line renderer was just created"))
+    }
+    
+    // Draws a reference grid on the y = 0 plane in the multi mesh and fluid frames, lines every
+    // spacing world units as far as extent from the camera, faded out by fade_distance. The grid
+    // tests and writes depth, so meshes in front of it hide it and meshes below it are hidden.
+    // Calling it again changes the settings.
+    pub fn enable_ground_grid(&mut self, spacing: f32, extent: f32, color: [f32; 4], fade_distance: f32) -> Result<(), FloError> {
+        if !(spacing > 0.0 && extent > 0.0 && fade_distance > 0.0) {
+            return Err(format!(
+                "Ground grid spacing, extent and fade distance must be positive, got {}, {} and {}",
+                spacing, extent, fade_distance
+            ).into());
+        }
+        let settings = GroundGridSettings { spacing, extent, color, fade_distance };
+        match self.ground_grid.as_mut() {
+            Some(grid) => grid.settings = settings,
+            None => {
+                self.ground_grid = Some(GroundGrid::new(
+                    &self.core.device,
+                    self.core.render_pass,
+                    self.core.swapchain_extent,
+                    self.has_depth,
+                    settings,
+                )?);
+            }
+        }
+        Ok(())
+    }
+    
+    pub fn disable_ground_grid(&mut self) {
+        if let Some(grid) = self.ground_grid.take() {
+            unsafe {
+                // Frames in flight may still be drawing with the pipeline
+                let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+            }
+            grid.destroy(&self.core.device);
+        }
+    }
+    
+    pub fn ground_grid(&self) -> Option<GroundGridSettings> {
+        self.ground_grid.as_ref().map(|grid| grid.settings)
+    }
+    
+    // Queue wireframe boxes for the next multi mesh or fluid frame, each (min, max, color), e.g.
+    // the cells of a culling octree. All boxes queued in a frame are drawn by one instanced line
+    // draw and then dropped, so queue them every frame they should show.
+    pub fn draw_boxes_instanced(&mut self, boxes: &[(Vec3, Vec3, [f32; 4])]) -> Result<(), FloError> {
+        if self.debug_boxes.is_none() {
+            self.create_dynamic_ring()?;
+            self.debug_boxes = Some(DebugBoxes::new(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                self.core.render_pass,
+                self.core.swapchain_extent,
+                self.has_depth,
+            )?);
+        }
+        if let Some(debug_boxes) = self.debug_boxes.as_mut() {
+            debug_boxes.queue_boxes(boxes);
+        }
+        Ok(())
+    }
+    
+    fn create_dynamic_ring(&mut self) -> Result<(), FloError> {
+        if self.dynamic_ring.is_none() {
+            self.dynamic_ring = Some(DynamicBufferRing::new(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                MAX_FRAMES_IN_FLIGHT,
+                self.config.dynamic_buffer_budget,
+            )?);
+        }
+        Ok(())
+    }
+    
+    // Draws and clears the queued dashed lines and debug boxes, inside the scene pass after
+    // set_scene_viewport. Recycles what the ring held for this frame, whose fence begin_frame
+    // waited on.
+    fn record_immediate_draws(&mut self, command_buffer: vk::CommandBuffer, view: Mat4, proj: Mat4) {
+        if let Some(ring) = self.dynamic_ring.as_mut() {
+            ring.begin_frame(self.core.current_frame);
+        }
+        self.record_dashed_line_draw(command_buffer, view, proj);
+        self.record_debug_box_draw(command_buffer, view, proj);
+    }
+    
+    fn record_debug_box_draw(&mut self, command_buffer: vk::CommandBuffer, view: Mat4, proj: Mat4) {
+        let depth_compare_op = self.depth_compare_op();
+        if let (Some(debug_boxes), Some(ring)) = (self.debug_boxes.as_mut(), self.dynamic_ring.as_mut()) {
+            if let Err(e) = debug_boxes.record_draw(
+                &self.core.device,
+                command_buffer,
+                ring,
+                view,
+                proj,
+                depth_compare_op,
+            ) {
+                error!("Failed to draw debug boxes: {}", e);
+            }
+        }
+    }
+    
+    // Left out of debug views like particles
+    fn record_ground_grid_draw(&self, command_buffer: vk::CommandBuffer, view: Mat4, proj: Mat4) {
+        if self.debug_view != DebugView::None {
+            return;
+        }
+        if let Some(ref grid) = self.ground_grid {
+            grid.record_draw(&self.core.device, command_buffer, view, proj, self.depth_compare_op());
+        }
+    }
+    
+    fn record_dashed_line_draw(&mut self, command_buffer: vk::CommandBuffer, view: Mat4, proj: Mat4) {
+        let (viewport, _) = self.scene_viewport_and_scissor();
+        let viewport_size = Vec2::new(viewport.width, viewport.height);
+        let depth_compare_op = self.depth_compare_op();
+        if let (Some(dashed_lines), Some(ring)) = (self.dashed_lines.as_mut(), self.dynamic_ring.as_mut()) {
+            if let Err(e) = dashed_lines.record_draw(
+                &self.core.device,
+                command_buffer,
+                ring,
+                view,
+                proj,
+                viewport_size,
+                self.reverse_z,
+                depth_compare_op,
+            ) {
+                error!("Failed to draw dashed lines: {}", e);
+            }
+        }
+    }
+    
+    pub fn set_mesh_pipeline(&mut self, mesh_index: usize, pipeline_name: &str) {
+        if mesh_index < self.meshes.len() {
+            let old_mode = self.pipeline_skinning_mode(self.meshes[mesh_index].pipeline_name.as_deref());
+            self.meshes[mesh_index].pipeline_name = Some(pipeline_name.to_string());
+            self.mark_static_scene_dirty();
+            if self.meshes[mesh_index].double_sided_pipeline.is_some() {
+                self.make_mesh_double_sided(mesh_index);
+            }
+            
+            // The joint buffer holds the old pipeline's format, rewrite it for the new one
+            if self.pipeline_skinning_mode(Some(pipeline_name)) != old_mode {
+                if let Some(joint_matrices) = self.meshes[mesh_index].joint_matrices.clone() {
+                    if let Err(e) = self.write_mesh_joints(mesh_index, &joint_matrices) {
+                        error!("Failed to update joint matrices of mesh {}: {:?}", mesh_index, e);
+                    }
+                }
+            }
+        }
+    }
+    
+    // Replace the sky's built in gradient, e.g. to animate a day/night cycle.
+    // sun_direction points towards the sun and should match the directional light so the sun
+    // disk lines up with the lighting. Only affects the "sky" pipeline drawn by render_frame_fluid.
+    pub fn set_sky_gradient(&mut self, horizon: [f32; 3], zenith: [f32; 3], sun_direction: [f32; 3], sun_intensity: f32) {
+        self.sky_gradient = Some(SkyGradient {
+            horizon,
+            zenith,
+            sun_direction,
+            sun_intensity,
+        });
+    }
+    
+    // Go back to the sky shader's built in gradient
+    pub fn clear_sky_gradient(&mut self) {
+        self.sky_gradient = None;
+    }
+    
+    // None while the sky shader's built in gradient is used
+    pub fn sky_gradient(&self) -> Option<SkyGradient> {
+        self.sky_gradient
+    }
+    
+    // Set water push constants for fluid rendering
+    pub fn set_water_push_constants(&mut self, push_constants: PushConstants) {
+        self.water_push_constants = Some(push_constants);
+    }
+    
+    pub fn water_push_constants(&self) -> Option<PushConstants> {
+        self.water_push_constants
+    }
+    
+    // Fades the water surface out as it gets close to the camera, so a camera at the waterline
+    // doesn't see a screen filling wash. Fully faded within start of the camera and unfaded
+    // from end on, in world units. end <= start turns the fade off.
+    pub fn set_water_near_fade(&mut self, start: f32, end: f32) {
+        let push_constants = self.water_push_constants.get_or_insert_with(bytemuck::Zeroable::zeroed);
+        push_constants.near_fade_start = start;
+        push_constants.near_fade_end = end;
+    }
+    
+    // Adds a deinterleaved surface mesh for a water grid, drawn with pipeline_name (e.g. a
+    // pipeline from add_tessellated_water_pipeline or add_fluid_pipeline_with_layout with
+    // MeshLayout::Deinterleaved). Triangle strips need a pipeline built for them.
+    pub fn add_water_mesh(&mut self, water: &WaterData, pipeline_name: &str, topology: MeshTopology) -> Result<usize, FloError> {
+        let mesh_index = self.add_mesh_with_layout(&water.create_mesh(topology), MeshLayout::Deinterleaved)?;
+        self.update_mesh_transforms(mesh_index, vec![Mat4::IDENTITY]);
+        self.set_mesh_pipeline(mesh_index, pipeline_name);
+        self.update_water_surface(mesh_index, water)?;
+        Ok(mesh_index)
+    }
+    
+    // Pushes a water grid's heights into a mesh from add_water_mesh, e.g. after each simulation
+    // step. Positions and normals are queued like update_mesh_vertices and update_mesh_normals,
+    // and the water push constants get the grid's scale and water level for render_frame_water.
+    pub fn update_water_surface(&mut self, mesh_index: usize, water: &WaterData) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        let Some(normal_buffer) = mesh.vertex_streams.as_ref().map(|streams| streams.normal_buffer) else {
+            return Err(format!("Mesh {} is interleaved, water meshes come from add_water_mesh", mesh_index).into());
+        };
+        let vertex_count = water.vertices_per_side() * water.vertices_per_side();
+        if mesh.vertex_count as usize != vertex_count {
+            return Err(format!(
+                "Mesh {} has {} vertices, a {}x{} water grid needs {}",
+                mesh_index, mesh.vertex_count, water.grid_len, water.grid_len, vertex_count
+            ).into());
+        }
+        
+        let position_buffer = mesh.vertex_buffer;
+        let (positions, normals) = water.surface();
+        self.upload_vertex_data(position_buffer, bytemuck::cast_slice(&positions));
+        self.upload_vertex_data(normal_buffer, bytemuck::cast_slice(&normals));
+        
+        // Surface positions are relative to the rest height, so still water sits at 0
+        let push_constants = self.water_push_constants.get_or_insert_with(bytemuck::Zeroable::zeroed);
+        push_constants.grid_scale = water.grid_scale();
+        push_constants.water_level = 0.0;
+        Ok(())
+    }
+    
+    // Renders with the fluid pipelines using the water push constants from update_water_surface
+    // (or set_water_push_constants), filling in the renderer's elapsed time, the scene's
+    // render resolution and the camera position. The fluid shaders build their view and projection
+    // from these, looking down the -z axis from camera_position.
+    pub fn render_frame_water(&mut self, camera_position: Vec3, egui_output: Option<egui::FullOutput>) {
+        let extent = self.core.render_extent;
+        let push_constants = PushConstants {
+            time: self.get_elapsed_time(),
+            camera_position_x: camera_position.x,
+            camera_position_y: camera_position.y,
+            camera_position_z: camera_position.z,
+            resolution: [extent.width as f32, extent.height as f32],
+            ..self.water_push_constants.unwrap_or_else(bytemuck::Zeroable::zeroed)
+        };
+        self.render_frame_fluid_with_egui(Mat4::IDENTITY, Mat4::IDENTITY, &push_constants, egui_output);
+    }
+    
+    pub fn set_mesh_color(&mut self, mesh_index: usize, color: [f32; 4]) {
+        if mesh_index < self.meshes.len() {
+            self.meshes[mesh_index].base_color = color;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Draw this mesh without depth testing, e.g. for highlights that stay on top. Only turns off
+    // what the mesh's pipeline does: a pipeline built without depth testing can't be switched
+    // to test depth per mesh, use a separate pipeline for that.
+    pub fn set_mesh_depth_test(&mut self, mesh_index: usize, enabled: bool) {
+        if mesh_index < self.meshes.len() {
+            self.meshes[mesh_index].depth_test = enabled;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Same as set_mesh_depth_test for depth writes. Blended pipelines never write depth.
+    pub fn set_mesh_depth_write(&mut self, mesh_index: usize, enabled: bool) {
+        if mesh_index < self.meshes.len() {
+            self.meshes[mesh_index].depth_write = enabled;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Depth compare op for this mesh, written for standard depth (LESS means closer) and mirrored
+    // when reverse-Z is on. None goes back to the renderer's. Like set_mesh_depth_test, only
+    // meshes whose pipeline tests depth are affected.
+    pub fn set_mesh_depth_compare(&mut self, mesh_index: usize, compare_op: Option<vk::CompareOp>) {
+        if mesh_index < self.meshes.len() {
+            self.meshes[mesh_index].depth_compare_op = compare_op;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Offsets the mesh's depth towards the camera by a constant (in units of the smallest
+    // depth difference) and a factor of its depth slope, None for no bias. Opaque meshes with a
+    // bias are drawn after the other opaque meshes. Turning the bias on per draw is dynamic
+    // state from extended dynamic state 2, core in the Vulkan 1.3 devices the renderer requires.
+    pub fn set_mesh_depth_bias(&mut self, mesh_index: usize, bias: Option<(f32, f32)>) {
+        if mesh_index < self.meshes.len() {
+            self.meshes[mesh_index].depth_bias = bias;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Draws the mesh as a decal lying on a surface, e.g. a scorch mark: LESS_OR_EQUAL with a
+    // small depth bias so it wins against the surface, without writing depth. Meant for static
+    // surfaces, a decal doesn't follow one that moves.
+    pub fn mark_as_decal(&mut self, mesh_index: usize) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        self.set_mesh_depth_compare(mesh_index, Some(vk::CompareOp::LESS_OR_EQUAL));
+        self.set_mesh_depth_bias(mesh_index, Some(DECAL_DEPTH_BIAS));
+        self.set_mesh_depth_write(mesh_index, false);
+        Ok(())
+    }
+    
+    // Reference value the mesh's stencil pipeline compares against and writes with REPLACE
+    pub fn set_mesh_stencil_reference(&mut self, mesh_index: usize, reference: u32) {
+        if mesh_index < self.meshes.len() {
+            self.meshes[mesh_index].stencil_reference = reference;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Moves the mesh to GEOMETRY_SUBPASS or LIGHTING_SUBPASS of the scene pass. Its pipeline has
+    // to be built for that subpass, so lighting subpass meshes use a pipeline from
+    // add_lighting_pipeline; switch the pipeline first when moving a mesh between subpasses.
+    // Lighting subpass meshes are drawn by the multi mesh renderer and overlay passes only.
+    pub fn set_mesh_subpass(&mut self, mesh_index: usize, subpass: u32) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        if subpass >= SCENE_SUBPASS_COUNT {
+            return Err(format!("The scene pass has no subpass {}", subpass).into());
+        }
+        let pipeline_name = mesh.pipeline_name.as_deref().unwrap_or("default");
+        let pipeline_subpass = self.mesh_pipeline_descs.get(pipeline_name).map_or(GEOMETRY_SUBPASS, |desc| desc.subpass);
+        if pipeline_subpass != subpass {
+            return Err(format!(
+                "Pipeline '{}' draws in subpass {}, not {}",
+                pipeline_name, pipeline_subpass, subpass
+            ).into());
+        }
+        self.meshes[mesh_index].subpass = subpass;
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    // Draw both sides of the mesh, e.g. for leaves, cloth and flags, or only its front again.
+    // The mesh is drawn with a variant of its pipeline that doesn't cull and sets the fragment
+    // shader's TWO_SIDED_LIGHTING constant, so shaders like mesh.frag flip the normal of back
+    // faces. Variants are built on first use, for pipelines from add_pipeline and its siblings,
+    // add_instanced_color_pipeline and the multi-mesh renderer's default pipeline.
+    pub fn set_mesh_double_sided(&mut self, mesh_index: usize, double_sided: bool) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        let double_sided_pipeline = if double_sided {
+            let pipeline_name = mesh.pipeline_name.clone().unwrap_or_else(|| "default".to_string());
+            let variant = format!("{}{}", pipeline_name, DOUBLE_SIDED_PIPELINE_SUFFIX);
+            if !self.pipelines.contains_key(&variant) {
+                let desc = self.mesh_pipeline_descs.get(&pipeline_name).ok_or_else(|| {
+                    FloError::from(format!("Pipeline '{}' has no double sided variant", pipeline_name))
+                })?;
+                let desc = MeshPipelineDesc { cull_mode: vk::CullModeFlags::NONE, ..desc.clone() };
+                self.create_mesh_pipeline(&variant, &desc, true)?;
+            }
+            Some(variant)
+        } else {
+            None
+        };
+        
+        self.meshes[mesh_index].double_sided_pipeline = double_sided_pipeline;
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    pub fn is_mesh_double_sided(&self, mesh_index: usize) -> bool {
+        self.meshes.get(mesh_index).is_some_and(|mesh| mesh.double_sided_pipeline.is_some())
+    }
+    
+    // For double sided meshes added from MeshData or moved to another pipeline. Without a
+    // double sided variant of its pipeline the mesh is drawn one sided.
+    fn make_mesh_double_sided(&mut self, mesh_index: usize) {
+        if let Err(e) = self.set_mesh_double_sided(mesh_index, true) {
+            warn!("{}, drawing mesh {} one sided", e, mesh_index);
+            self.meshes[mesh_index].double_sided_pipeline = None;
+        }
+    }
+    
+    // Draw the mesh's edges in the color on top of its solid fill, e.g. to highlight the
+    // selection in an editor, or None to stop. Instanced meshes get it on all their instances.
+    // Skinned meshes, deinterleaved meshes and strips or fans aren't supported.
+    pub fn set_mesh_wireframe_overlay(&mut self, mesh_index: usize, color: Option<[f32; 4]>) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        if color.is_some() {
+            if mesh.is_skinned || mesh.vertex_streams.is_some() || mesh.topology != MeshTopology::TriangleList {
+                return Err("Wireframe overlays need an interleaved triangle list mesh that isn't skinned".into());
+            }
+            if mesh.instance_matrices.is_some() {
+                return Err("Wireframe overlays don't support meshes with instance matrices".into());
+            }
+            if !wireframe_supported(&self.core.instance, self.core.physical_device) {
+                return Err(FloError::UnsupportedDevice("Wireframe overlays need the fillModeNonSolid feature".to_string()));
+            }
+            let pipeline_name = wireframe_overlay_pipeline(mesh);
+            if !self.pipelines.contains_key(pipeline_name) {
+                self.create_wireframe_overlay_pipeline(pipeline_name)?;
+            }
+        }
+        
+        self.meshes[mesh_index].wireframe_overlay = color;
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    // Seconds added to the time pushed for this mesh, to phase shift animations like waving
+    // grass or idle poses. Applies to the skinned and fluid paths and to wind (set_mesh_wind).
+    pub fn set_mesh_time_offset(&mut self, mesh_index: usize, time_offset: f32) {
+        if mesh_index < self.meshes.len() {
+            self.meshes[mesh_index].time_offset = time_offset;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Sway the mesh in the wind, drawn with a pipeline from add_wind_pipeline. The time comes
+    // from the renderer's scene time plus the mesh's time offset, so set_mesh_time_offset keeps
+    // neighbouring plants out of step.
+    pub fn set_mesh_wind(&mut self, mesh_index: usize, wind: WindParams) -> Result<(), FloError> {
+        let mesh = self.meshes.get_mut(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        if mesh.is_skinned {
+            return Err("Wind isn't supported on skinned meshes".into());
+        }
+        if mesh.uv_transform.is_some() {
+            return Err("Wind and UV transforms share push constants, clear the mesh's UV transform first".into());
+        }
+        mesh.wind = Some(WindParams { direction: wind.direction.normalize_or_zero(), ..wind });
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    pub fn clear_mesh_wind(&mut self, mesh_index: usize) {
+        if let Some(mesh) = self.meshes.get_mut(mesh_index) {
+            mesh.wind = None;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Scale and offset this mesh's UVs, drawn with a pipeline from add_uv_transform_pipeline,
+    // e.g. to pick a sub rect of an atlas. Keeps the scroll from set_mesh_uv_scroll.
+    pub fn set_mesh_uv_transform(&mut self, mesh_index: usize, scale: Vec2, offset: Vec2) -> Result<(), FloError> {
+        let uv_transform = self.mesh_uv_transform(mesh_index)?;
+        self.meshes[mesh_index].uv_transform = Some(UvTransform { scale, offset, ..uv_transform });
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    // Scroll this mesh's UVs by velocity UV units per second of the renderer's scene time plus the
+    // mesh's time offset, e.g. for conveyor belts or flowing lava. Needs a REPEAT texture.
+    pub fn set_mesh_uv_scroll(&mut self, mesh_index: usize, velocity: Vec2) -> Result<(), FloError> {
+        let uv_transform = self.mesh_uv_transform(mesh_index)?;
+        self.meshes[mesh_index].uv_transform = Some(UvTransform { scroll: velocity, ..uv_transform });
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    pub fn clear_mesh_uv_transform(&mut self, mesh_index: usize) {
+        if let Some(mesh) = self.meshes.get_mut(mesh_index) {
+            mesh.uv_transform = None;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // The mesh's UV transform to change, or an error for meshes that can't have one
+    fn mesh_uv_transform(&self, mesh_index: usize) -> Result<UvTransform, FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        if mesh.is_skinned {
+            return Err("UV transforms aren't supported on skinned meshes".into());
+        }
+        if mesh.wind.is_some() {
+            return Err("Wind and UV transforms share push constants, clear the mesh's wind first".into());
+        }
+        Ok(mesh.uv_transform.unwrap_or_default())
+    }
+    
+    // Discard this mesh's fragments with an alpha below the threshold. Draw it with a pipeline
+    // from add_cutout_pipeline, whose fragment shader reads the threshold after the MVP block.
+    pub fn set_mesh_alpha_cutoff(&mut self, mesh_index: usize, threshold: f32) {
+        self.set_mesh_material_mode(mesh_index, MaterialMode::Cutout { threshold });
+    }
+    
+    pub fn set_mesh_material_mode(&mut self, mesh_index: usize, material_mode: MaterialMode) {
+        if mesh_index < self.meshes.len() {
+            self.meshes[mesh_index].material_mode = material_mode;
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Cull and alpha mode of a loaded material, e.g. from gltf_loader::load_primitives. Mask
+    // materials become cutouts and need a pipeline from add_cutout_pipeline, Blend materials
+    // one from add_pipeline_with_blend_mode; the mesh's pipeline isn't changed here.
+    pub fn set_mesh_material(&mut self, mesh_index: usize, material: &MaterialInfo) -> Result<(), FloError> {
+        self.set_mesh_double_sided(mesh_index, material.double_sided)?;
+        self.set_mesh_material_mode(mesh_index, material.material_mode());
+        let pipeline_name = mesh_pipeline_name(&self.meshes[mesh_index]);
+        let blend_mode = self.pipelines.get(pipeline_name).map_or(BlendMode::Opaque, |pipeline| pipeline.blend_mode);
+        if material.blend_mode() != BlendMode::Opaque && blend_mode == BlendMode::Opaque {
+            warn!("Material {:?} is blended but mesh {} uses the opaque pipeline '{}'",
+                  material.name, mesh_index, pipeline_name);
+        }
+        Ok(())
+    }
+    
+    // Push data for custom shader fields right after the block the renderer pushes for the
+    // mesh (208 byte MVP block, 212 with a cutout mesh's alpha threshold, or the 4 byte time
+    // for skinned meshes). An empty Vec clears it.
+    pub fn set_mesh_push_constants(&mut self, mesh_index: usize, data: Vec<u8>) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        let offset = if mesh.is_skinned {
+            4
+        } else if mesh.material_mode.alpha_cutoff().is_some() {
+            CUTOUT_PUSH_CONSTANTS_SIZE
+        } else {
+            MVP_PUSH_CONSTANTS_SIZE
+        };
+        self.set_mesh_push_constants_at(mesh_index, offset, data)
+    }
+    
+    // Push data at any offset. It's pushed after the built in block, so an offset inside it
+    // overrides those fields, e.g. offset 192 replaces the MVP block's base_color.
+    pub fn set_mesh_push_constants_at(&mut self, mesh_index: usize, offset: u32, data: Vec<u8>) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        if offset % 4 != 0 || data.len() % 4 != 0 {
+            return Err(format!("Push constant offset ({}) and size ({}) have to be multiples of 4", offset, data.len()).into());
+        }
+        if !data.is_empty() {
+            let pipeline_name = mesh_pipeline_name(mesh);
+            if let Some(pipeline) = self.pipelines.get(pipeline_name) {
+                let stages = push_constant_stages(pipeline, offset, data.len());
+                if stages.is_empty() {
+                    return Err(format!("Pipeline '{}' declares no push constants at offset {}", pipeline_name, offset).into());
+                }
+                self.validate_push_constants(pipeline_name, stages, offset, data.len())?;
+            }
+        }
+        
+        let mesh = &mut self.meshes[mesh_index];
+        mesh.custom_push_constants = data;
+        mesh.custom_push_offset = offset;
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    // Called right before every draw of a multi mesh frame's meshes, after the renderer's own
+    // push constants, with the command buffer, mesh index and bound pipeline's layout, e.g. to
+    // push constants or bind extra descriptor sets the built in materials don't cover. None
+    // removes it. The hook runs inside the scene render pass: it may push constants, bind
+    // descriptor sets compatible with the layout and set dynamic state, but must not begin or
+    // end passes, bind pipelines or leave state that later draws rely on changed. Static scene
+    // buffers replay what it recorded until they're re-recorded.
+    pub fn set_pre_draw_hook(&mut self, hook: Option<PreDrawHook>) {
+        self.pre_draw_hook = hook;
+        self.mark_static_scene_dirty();
+    }
+    
+    // Shows overdraw, normals or depth instead of the shaded scene in multi mesh frames, or the
+    // scene again with DebugView::None. Meshes are drawn with the debug pipelines, which are
+    // built the first time a view needs them, and the present pass skips exposure, tonemapping
+    // and post effects. Meshes the debug pipelines can't draw are left out, see debug_view_pipeline.
+    pub fn set_debug_view(&mut self, debug_view: DebugView) -> Result<(), FloError> {
+        let pipelines = match debug_view {
+            DebugView::None => None,
+            DebugView::Overdraw => Some(DEBUG_OVERDRAW_PIPELINES),
+            DebugView::Normals | DebugView::Depth { .. } => Some(DEBUG_VIEW_PIPELINES),
+        };
+        if let Some(pipelines) = pipelines {
+            self.create_debug_view_pipelines(pipelines)?;
+        }
+        
+        self.debug_view = debug_view;
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+    
+    // Add texture to a specific mesh from a file path
+    // Start streaming a texture for a mesh. Returns immediately, the mesh renders with a
+    // magenta placeholder until the decoded image is uploaded by process_texture_uploads.
+    // With 0 upload_threads the texture is decoded and uploaded before returning. The texture
+    // is clamped to its edges, see set_mesh_texture_from_file_with_address_modes to tile it.
+    pub fn set_mesh_texture_from_file(&mut self, mesh_index: usize, texture_path: &str) -> Result<(), FloError> {
+        self.set_mesh_texture_from_file_with_address_modes(mesh_index, texture_path, SamplerAddressModes::default())
+    }
+    
+    // set_mesh_texture_from_file sampling the texture with address_modes, e.g.
+    // SamplerAddressModes::REPEAT for UVs beyond 0..1 that should tile
+    pub fn set_mesh_texture_from_file_with_address_modes(
+        &mut self,
+        mesh_index: usize,
+        texture_path: &str,
+        address_modes: SamplerAddressModes,
+    ) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        
+        self.meshes[mesh_index].texture_address_modes = address_modes;
+        let placeholder = self.create_mesh_texture_resources(&[255, 0, 255, 255], 1, 1, address_modes, self.mesh_texture_filtering(mesh_index))?;
+        self.swap_mesh_texture(mesh_index, placeholder);
+        
+        self.texture_streamer.request(mesh_index, texture_path);
+        if self.config.upload_threads == 0 {
+            self.process_texture_uploads();
+        }
+        Ok(())
+    }
+    
+    // Upload textures that finished decoding on the streaming threads and swap them in.
+    // Runs on the main thread since the upload uses the graphics queue and command pool.
+    pub fn process_texture_uploads(&mut self) {
+        let completed = self.texture_streamer.poll_completed();
+        self.upload_decoded_textures(completed);
+    }
+    
+    fn upload_decoded_textures(&mut self, completed: Vec<DecodedTexture>) {
+        for decoded in completed {
+            let texture_data = match decoded.result {
+                Ok(texture_data) => texture_data,
+                Err(e) => {
+                    warn!("{}, keeping placeholder for mesh {}", e, decoded.mesh_index);
+                    continue;
+                }
+            };
+            
+            let address_modes = self.meshes[decoded.mesh_index].texture_address_modes;
+            let filtering = self.mesh_texture_filtering(decoded.mesh_index);
+            match self.create_mesh_texture_resources(&texture_data.pixels, texture_data.width, texture_data.height, address_modes, filtering) {
+                Ok(textures) => self.swap_mesh_texture(decoded.mesh_index, textures),
+                Err(e) => error!("Failed to upload texture {}: {}", decoded.path, e),
+            }
+        }
+    }
+    
+    // Number of textures still being decoded in the background
+    pub fn pending_texture_count(&self) -> usize {
+        self.texture_streamer.pending_count()
+    }
+    
+    fn create_mesh_texture_resources(
+        &mut self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        address_modes: SamplerAddressModes,
+        filtering: SamplerFiltering,
+    ) -> Result<TextureResources, FloError> {
+        let (texture_image, texture_image_memory) = crate::vulkan_common::create_texture_image_from_rgba(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            self.core.command_pool,
+            self.core.graphics_queue,
+            pixels,
+            width,
+            height,
+        )?;
+        
+        let texture_image_view = crate::vulkan_common::create_texture_image_view(&self.core.device, texture_image)?;
+        let texture_sampler = create_filtered_texture_sampler(&self.core.instance, &self.core.device, self.core.physical_device, 1, address_modes, filtering)?;
+        self.create_mesh_texture_descriptors(texture_image, texture_image_memory, texture_image_view, texture_sampler)
+    }
+    
+    // Descriptor sets sampling a mesh texture's view, which may be a 2D or 2D array view
+    fn create_mesh_texture_descriptors(
+        &mut self,
+        texture_image: vk::Image,
+        texture_image_memory: vk::DeviceMemory,
+        texture_image_view: vk::ImageView,
+        texture_sampler: vk::Sampler,
+    ) -> Result<TextureResources, FloError> {
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        
+        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &[binding])?;
+        
+        let layouts = vec![descriptor_set_layout; self.core.swapchain_images.len()];
+        let (descriptor_pool, descriptor_sets) = self.descriptor_allocator.allocate(&self.core.device, &layouts)?;
+        
+        // Update descriptor sets
+        for &descriptor_set in &descriptor_sets {
+            update_descriptor_sets_texture(&self.core.device, descriptor_set, texture_image_view, texture_sampler, 0);
+        }
+        
+        Ok(TextureResources {
+            image: texture_image,
+            image_memory: texture_image_memory,
+            image_view: texture_image_view,
+            sampler: texture_sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_sets,
+        })
+    }
+    
+    // Give a mesh a texture array, one layer per texture, sampled as a sampler2DArray at
+    // binding 0. Meshes from add_mesh_instanced_with_colors pick a layer per instance with
+    // update_mesh_instance_tex_indices. Like new_texture_array, every layer is as big as the
+    // largest texture, capped by the renderer's max texture size.
+    pub fn set_mesh_texture_array(
+        &mut self,
+        mesh_index: usize,
+        textures: &[TextureData],
+        address_modes: SamplerAddressModes,
+    ) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        let limits = unsafe { self.core.instance.get_physical_device_properties(self.core.physical_device) }.limits;
+        if textures.is_empty() || textures.len() > limits.max_image_array_layers as usize {
+            return Err(format!(
+                "Texture arrays need 1 to {} layers, got {}",
+                limits.max_image_array_layers, textures.len()
+            ).into());
+        }
+        let max_size = self.max_texture_size();
+        let width = textures.iter().map(|t| t.width.min(max_size)).max().unwrap_or(1);
+        let height = textures.iter().map(|t| t.height.min(max_size)).max().unwrap_or(1);
+        
+        let (texture_array, texture_array_memory) = create_texture_array(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            self.core.command_pool,
+            self.core.graphics_queue,
+            textures,
+            width,
+            height,
+        )?;
+        let texture_array_view = create_texture_array_view(&self.core.device, texture_array, vk::Format::R8G8B8A8_SRGB, textures.len() as u32, 1)?;
+        let filtering = self.mesh_texture_filtering(mesh_index);
+        let texture_sampler = create_filtered_texture_sampler(&self.core.instance, &self.core.device, self.core.physical_device, 1, address_modes, filtering)?;
+        let textures = self.create_mesh_texture_descriptors(texture_array, texture_array_memory, texture_array_view, texture_sampler)?;
+        
+        self.meshes[mesh_index].texture_address_modes = address_modes;
+        self.swap_mesh_texture(mesh_index, textures);
+        Ok(())
+    }
+    
+    // Sharpen or soften a mesh's texture, e.g. a negative mip_lod_bias for crisper tiles at
+    // grazing angles. Applies to its current texture and the ones set later. None goes back to
+    // the renderer's anisotropy without a bias, which suits data textures like height maps.
+    pub fn set_mesh_texture_filtering(&mut self, mesh_index: usize, filtering: Option<SamplerFiltering>) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        self.meshes[mesh_index].texture_filtering = filtering;
+        
+        let address_modes = self.meshes[mesh_index].texture_address_modes;
+        let filtering = self.mesh_texture_filtering(mesh_index);
+        if let Some(ref textures) = self.meshes[mesh_index].texture_resources {
+            let sampler = create_filtered_texture_sampler(&self.core.instance, &self.core.device, self.core.physical_device, 1, address_modes, filtering)?;
+            // Frames in flight may still sample with the old sampler
+            unsafe {
+                let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+            }
+            for &descriptor_set in &textures.descriptor_sets {
+                update_descriptor_sets_texture(&self.core.device, descriptor_set, textures.image_view, sampler, 0);
+            }
+            let old_sampler = std::mem::replace(&mut self.meshes[mesh_index].texture_resources.as_mut().unwrap().sampler, sampler);
+            unsafe {
+                leak_check::destroy(&self.core.device, old_sampler);
+            }
+            self.mark_static_scene_dirty();
+        }
+        Ok(())
+    }
+    
+    fn mesh_texture_filtering(&self, mesh_index: usize) -> SamplerFiltering {
+        self.meshes[mesh_index].texture_filtering.unwrap_or(SamplerFiltering {
+            max_anisotropy: self.core.max_anisotropy,
+            mip_lod_bias: 0.0,
+        })
+    }
+    
+    fn swap_mesh_texture(&mut self, mesh_index: usize, textures: TextureResources) {
+        if let Some(old) = self.meshes[mesh_index].texture_resources.replace(textures) {
+            // Frames in flight may still sample the old texture
+            unsafe {
+                let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+            }
+            destroy_texture_resources(&self.core.device, &mut self.descriptor_allocator, &old);
+        }
+        self.set_mesh_debug_names(mesh_index);
+        self.mark_static_scene_dirty();
+    }
+    
+    // Create an offscreen target the scene can be drawn into with render_scene_to_target,
+    // then shown on a mesh with set_mesh_render_target_texture (in-world screens, mirrors).
+    pub fn create_render_target(&mut self, width: u32, height: u32) -> Result<RenderTargetId, FloError> {
+        let render_target = RenderTarget::new(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            HDR_FORMAT,
+            self.core.depth_format,
+            vk::Extent2D { width, height },
+        )?;
+        
+        let id = RenderTargetId(self.render_targets.len());
+        let label = format!("render target[{}]", id.0);
+        self.core.set_debug_name(render_target.color_image, &format!("{} color image", label));
+        if self.has_depth {
+            self.core.set_debug_name(render_target.depth_image, &format!("{} depth image", label));
+        }
+        self.core.set_debug_name(render_target.render_pass, &format!("{} render pass", label));
+        self.core.set_debug_name(render_target.framebuffer, &format!("{} framebuffer", label));
+        self.core.set_debug_name(render_target.descriptor_set, &format!("{} descriptor set", label));
+        
+        self.render_targets.push(Some(render_target));
+        Ok(id)
+    }
+    
+    // Add a fullscreen effect run on the HDR scene before tonemapping, after the effects added
+    // before it. The fragment shader samples the previous output at set 0 binding 0 and gets
+    // params in PostEffectPushConstants (at most MAX_POST_EFFECT_PARAMS, the rest are 0).
+    pub fn add_post_effect(&mut self, frag_shader_path: &str, params: &[f32]) -> Result<PostEffectId, FloError> {
+        self.create_post_process_chain()?;
+        let chain = self.post_process.as_mut().unwrap();
+        chain.add_effect(&self.core.device, frag_shader_path, params)
+    }
+    
+    fn create_post_process_chain(&mut self) -> Result<(), FloError> {
+        if self.post_process.is_none() {
+            self.post_process = Some(PostProcessChain::new(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                &self.core.hdr_image_views,
+                self.core.render_extent,
+            )?);
+        }
+        Ok(())
+    }
+    
+    // FXAA smooths edges with one fullscreen pass after the post effects, before tonemapping and
+    // egui, so UI text stays sharp. MSAA isn't available: the scene pass is single sampled.
+    pub fn set_antialiasing(&mut self, mode: AaMode) -> Result<(), FloError> {
+        match mode {
+            AaMode::None => {
+                if let Some(ref mut chain) = self.post_process {
+                    chain.set_fxaa(&self.core.device, None)?;
+                }
+            }
+            AaMode::Fxaa => {
+                self.create_post_process_chain()?;
+                let chain = self.post_process.as_mut().unwrap();
+                chain.set_fxaa(&self.core.device, Some(self.fxaa_settings))?;
+            }
+            AaMode::Msaa(samples) => {
+                return Err(format!(
+                    "MSAA ({} samples) isn't supported, the scene pass is single sampled. Use AaMode::Fxaa instead.",
+                    samples
+                ).into());
+            }
+        }
+        self.antialiasing = mode;
+        Ok(())
+    }
+    
+    pub fn antialiasing(&self) -> AaMode {
+        self.antialiasing
+    }
+    
+    // Whether the scene pass resolves multisampled depth into a single sampled image for
+    // sampling. Never, since the scene pass is single sampled and its depth can be sampled as is.
+    // DeviceInfo::depth_resolve_modes tells what an MSAA pass could resolve with.
+    pub fn depth_resolve_active(&self) -> bool {
+        false
+    }
+    
+    // Applies right away when FXAA is on, else the next time it's turned on
+    pub fn set_fxaa_settings(&mut self, settings: FxaaSettings) -> Result<(), FloError> {
+        self.fxaa_settings = settings;
+        if self.antialiasing == AaMode::Fxaa {
+            self.set_antialiasing(AaMode::Fxaa)?;
+        }
+        Ok(())
+    }
+    
+    pub fn set_post_effect_params(&mut self, effect: PostEffectId, params: &[f32]) -> Result<(), FloError> {
+        match self.post_process {
+            Some(ref mut chain) => chain.set_params(effect, params),
+            None => Err("Unknown post effect".into()),
+        }
+    }
+    
+    // Disabled effects are skipped, the next enabled one samples the previous output
+    pub fn set_post_effect_enabled(&mut self, effect: PostEffectId, enabled: bool) {
+        if let Some(ref mut chain) = self.post_process {
+            chain.set_enabled(effect, enabled);
+        }
+    }
+    
+    // Meshes showing the target go back to their own texture
+    pub fn destroy_render_target(&mut self, target: RenderTargetId) {
+        let render_target = match self.render_targets.get_mut(target.0).and_then(|slot| slot.take()) {
+            Some(render_target) => render_target,
+            None => return,
+        };
+        
+        for mesh in &mut self.meshes {
+            if mesh.render_target == Some(target) {
+                mesh.render_target = None;
+            }
+        }
+        self.mark_static_scene_dirty();
+        
+        // Frames in flight may still sample it
+        unsafe {
+            let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+        }
+        render_target.destroy(&self.core.device);
+    }
+    
+    // Sample a render target instead of the mesh's own texture, None to switch back.
+    // The mesh needs a textured pipeline, and isn't drawn while rendering into its own target.
+    pub fn set_mesh_render_target_texture(&mut self, mesh_index: usize, target: Option<RenderTargetId>) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        if let Some(target) = target {
+            if self.render_target(target).is_none() {
+                return Err(format!("Invalid render target {:?}", target).into());
+            }
+        }
+        
+        self.meshes[mesh_index].render_target = target;
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    // Keep a copy of each frame's HDR scene color, before post effects and tonemapping, that
+    // meshes can sample in the next frame for temporal effects (trails, feedback, reprojection).
+    // Copying it after the scene pass costs a full screen image copy per frame. Until the first
+    // copy and after the render extent changes it's cleared to all zeros, so shaders can tell
+    // there's no history from alpha 0 as long as the scene itself is drawn with alpha above 0.
+    pub fn enable_previous_frame_access(&mut self) -> Result<(), FloError> {
+        if self.previous_frame.is_some() {
+            return Ok(());
+        }
+        let previous_frame = PreviousFrame::new(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            self.core.command_pool,
+            self.core.graphics_queue,
+            HDR_FORMAT,
+            self.core.render_extent,
+        )?;
+        self.core.set_debug_name(previous_frame.target.color_image, "previous frame color image");
+        self.previous_frame = Some(previous_frame);
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    pub fn disable_previous_frame_access(&mut self) {
+        if let Some(previous_frame) = self.previous_frame.take() {
+            // Frames in flight may still sample or copy into it
+            unsafe {
+                let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+            }
+            previous_frame.destroy(&self.core.device);
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    pub fn previous_frame_access_enabled(&self) -> bool {
+        self.previous_frame.is_some()
+    }
+    
+    // Sample the last frame's scene color at the mesh's texture binding (set 0 binding 0)
+    // instead of its own texture. Needs enable_previous_frame_access and a textured pipeline, a
+    // render target set with set_mesh_render_target_texture takes precedence.
+    pub fn set_mesh_previous_frame_texture(&mut self, mesh_index: usize, enabled: bool) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        if enabled && self.previous_frame.is_none() {
+            return Err("Call enable_previous_frame_access before sampling the previous frame".into());
+        }
+        
+        self.meshes[mesh_index].previous_frame_texture = enabled;
+        self.mark_static_scene_dirty();
+        Ok(())
+    }
+    
+    fn render_target(&self, target: RenderTargetId) -> Option<&RenderTarget> {
+        self.render_targets.get(target.0).and_then(|slot| slot.as_ref())
+    }
+    
+    // Descriptor set with a mesh's texture: its render target, the previous frame, its own
+    // texture or the renderer wide one, in that order
+    fn mesh_texture_descriptor_set(&self, mesh: &MeshEntry, image_index: usize) -> Option<vk::DescriptorSet> {
+        if let Some(render_target) = mesh.render_target.and_then(|target| self.render_target(target)) {
+            Some(render_target.descriptor_set)
+        } else if let Some(previous_frame) = self.previous_frame.as_ref().filter(|_| mesh.previous_frame_texture) {
+            Some(previous_frame.target.descriptor_set)
+        } else if let Some(ref textures) = mesh.texture_resources {
+            Some(textures.descriptor_sets[image_index])
+        } else {
+            self.textures.as_ref().map(|textures| textures.descriptor_sets[image_index])
+        }
+    }
+    
+    // Set 0 for a mesh in the multi mesh pass: its skinning set if skinned, its texture otherwise
+    // What draw planning needs to know about each mesh, see plan_draw_order and plan_mesh_draws
+    fn mesh_draw_infos(&self, image_index: usize) -> Vec<MeshDrawInfo<'_>> {
+        self.meshes.iter()
+            .map(|mesh| {
+                let blend_mode = self.pipelines.get(mesh_pipeline_name(mesh)).map_or(BlendMode::Opaque, |pipeline| pipeline.blend_mode);
+                MeshDrawInfo::new(mesh, blend_mode, self.mesh_descriptor_set(mesh, image_index), self.debug_view)
+            })
+            .collect()
+    }
+    
+    fn mesh_descriptor_set(&self, mesh: &MeshEntry, image_index: usize) -> Option<vk::DescriptorSet> {
+        if mesh.is_skinned {
+            mesh.skinned_descriptor_sets.as_ref().and_then(|sets| sets.get(image_index).copied())
+        } else if let Some(ref descriptors) = mesh.instance_matrices {
+            Some(descriptors.descriptor_set)
+        } else {
+            self.mesh_texture_descriptor_set(mesh, image_index)
+        }
+    }
+    
+    // Draw the meshes from another camera into a render target. Call before rendering the frame
+    // that shows it; the draw is submitted and finished before this returns.
+    // Skinned meshes are left out since their camera lives in per-frame uniform buffers,
+    // and instanced meshes draw all instances (GPU culling only knows the main camera).
+    pub fn render_scene_to_target(&mut self, target: RenderTargetId, view: Mat4, proj: Mat4) -> Result<(), FloError> {
+        self.resolve_mesh_hierarchy();
+        let (render_pass, framebuffer, extent) = match self.render_target(target) {
+            Some(render_target) => (render_target.render_pass, render_target.framebuffer, render_target.extent),
+            None => return Err(format!("Invalid render target {:?}", target).into()),
+        };
+        
+        let command_buffer = crate::texture::begin_single_time_commands(&self.core.device, self.core.command_pool)?;
+        
+        unsafe {
+            let clear_values = [
+                vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: self.clear_color,
+                    },
+                },
+                vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: self.depth_clear_value(),
+                        stencil: 0,
+                    },
+                },
+            ];
+            
+            let render_pass_info = vk::RenderPassBeginInfo::default()
+                .render_pass(render_pass)
+                .framebuffer(framebuffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                })
+                .clear_values(&clear_values);
+            
+            self.core.device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+            set_viewport_and_scissor(&self.core.device, command_buffer, extent);
+            
+            let mut current_pipeline_name: Option<&str> = None;
+            
+            // Lighting subpass meshes aren't drawn into render targets
+            for mesh in &self.meshes {
+                if mesh.is_skinned || mesh.render_target == Some(target) || mesh.subpass != GEOMETRY_SUBPASS {
+                    continue;
+                }
+                if !mesh.use_instancing && mesh.transforms.is_empty() {
+                    continue;
+                }
+                if mesh.use_instancing && mesh.instance_count == 0 {
+                    continue;
+                }
+                
+                let pipeline_name = mesh_pipeline_name(mesh);
+                let (pipeline, pipeline_layout) = match self.pipelines.get(pipeline_name) {
+                    Some(pipeline_entry) => (pipeline_entry.pipeline, pipeline_entry.layout),
+                    None => (self.graphics_pipeline, self.pipeline_layout),
+                };
+                if current_pipeline_name != Some(pipeline_name) {
+                    bind_graphics_pipeline(&self.core.device, command_buffer, pipeline, self.depth_compare_op());
+                    current_pipeline_name = Some(pipeline_name);
+                }
+                set_mesh_depth_state(&self.core.device, command_buffer, mesh, self.depth_compare_op(), self.reverse_z);
+                
+                if mesh.use_instancing {
+                    self.core.device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
+                    if let Some(instance_buffer) = mesh.instance_buffer {
+                        self.core.device.cmd_bind_vertex_buffers(command_buffer, 1, &[instance_buffer], &[0]);
+                    }
+                } else {
+                    bind_mesh_vertex_buffers(&self.core.device, command_buffer, mesh);
+                }
+                bind_mesh_index_buffer(&self.core.device, command_buffer, mesh);
+                
+                // Per swapchain image texture sets all point at the same image, any of them will do
+                if let Some(descriptor_set) = self.mesh_texture_descriptor_set(mesh, 0) {
+                    self.core.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline_layout,
+                        0,
+                        &[descriptor_set],
+                        &[],
+                    );
+                }
+                
+                // Instanced meshes carry their positions in the instance buffer
+                let identity = [Mat4::IDENTITY];
+                let transforms: &[Mat4] = if mesh.use_instancing { &identity } else { &mesh.transforms };
+                let instance_count = if mesh.use_instancing { mesh.instance_count } else { 1 };
+                for transform in transforms {
+                    let mvp = MvpPushConstants {
+                        model: transform.to_cols_array(),
+                        view: view.to_cols_array(),
+                        proj: proj.to_cols_array(),
+                        base_color: mesh.base_color,
+                    };
+                    self.push_constants_checked(
+                        command_buffer,
+                        pipeline_name,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        bytemuck::bytes_of(&mvp),
+                    );
+                    self.push_mesh_custom_constants(command_buffer, pipeline_name, mesh);
+                    draw_mesh(&self.core.device, command_buffer, mesh, instance_count);
+                }
+            }
+            
+            end_scene_render_pass(&self.core.device, command_buffer);
+        }
+        
+        crate::texture::end_single_time_commands(&self.core.device, self.core.command_pool, self.core.graphics_queue, command_buffer)?;
+        Ok(())
+    }
+    
+    // Reads a render target's color back to the host, e.g. to compare the output of
+    // render_scene_to_target against a reference. The target holds the HDR scene before
+    // tonemapping, so Rgba16Float captures compare lighting without 8 bit banding. Waits for the
+    // graphics queue to go idle. The target has to have been drawn into at least once.
+    pub fn capture_render_target(&self, target: RenderTargetId, format: CaptureFormat) -> Result<CapturedImage, FloError> {
+        let Some(render_target) = self.render_target(target) else {
+            return Err(format!("Invalid render target {:?}", target).into());
+        };
+        capture::capture_color_image(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            self.core.command_pool,
+            self.core.graphics_queue,
+            render_target.color_image,
+            render_target.extent,
+            format,
+        )
+    }
+    
+    // Debug names show up in validation messages, e.g. "mesh[12] vertex buffer".
+    // Names are only set when validation layers are enabled.
+    fn set_mesh_debug_names(&self, mesh_index: usize) {
+        let core = &self.core;
+        if core.debug_utils.is_none() {
+            return;
+        }
+        let mesh = &self.meshes[mesh_index];
+        let label = format!("mesh[{}]", mesh_index);
+        
+        let position_stream = if mesh.vertex_streams.is_some() { " position" } else { "" };
+        core.set_debug_name(mesh.vertex_buffer, &format!("{}{} vertex buffer", label, position_stream));
+        if mesh.draw_mode == MeshDrawMode::Indexed {
+            core.set_debug_name(mesh.index_buffer, &format!("{} index buffer", label));
+        }
+        if let Some(ref streams) = mesh.vertex_streams {
+            core.set_debug_name(streams.normal_buffer, &format!("{} normal vertex buffer", label));
+            core.set_debug_name(streams.uv_buffer, &format!("{} uv vertex buffer", label));
+        }
+        if let Some(instance_buffer) = mesh.instance_buffer {
+            core.set_debug_name(instance_buffer, &format!("{} instance buffer", label));
+        }
+        if let Some(morph_delta_buffer) = mesh.morph_delta_buffer {
+            core.set_debug_name(morph_delta_buffer, &format!("{} morph delta buffer", label));
+        }
+        if let Some(morph_weight_buffer) = mesh.morph_weight_buffer {
+            core.set_debug_name(morph_weight_buffer, &format!("{} morph weight buffer", label));
+        }
+        // Meshes in the joint arena share its buffers and set, which are named after the arena
+        if mesh.joint_arena_size.is_none() {
+            if let Some(joint_buffer) = mesh.joint_buffer {
+                core.set_debug_name(joint_buffer, &format!("{} joint buffer", label));
+            }
+            if let Some(camera_buffer) = mesh.camera_uniform_buffer {
+                core.set_debug_name(camera_buffer, &format!("{} camera uniform buffer", label));
+            }
+            if let Some(ref descriptor_sets) = mesh.skinned_descriptor_sets {
+                for (i, &set) in descriptor_sets.iter().enumerate() {
+                    core.set_debug_name(set, &format!("{} skinned descriptor set[{}]", label, i));
+                }
+            }
+        }
+        if let Some(ref textures) = mesh.texture_resources {
+            core.set_debug_name(textures.image, &format!("{} texture image", label));
+            for (i, &set) in textures.descriptor_sets.iter().enumerate() {
+                core.set_debug_name(set, &format!("{} texture descriptor set[{}]", label, i));
+            }
+        }
+    }
+    
+    // Names the resources a constructor set up, meshes and pipelines added later name themselves
+    fn set_debug_names(&self) {
+        if self.core.debug_utils.is_none() {
+            return;
+        }
+        
+        for name in self.pipelines.keys() {
+            self.set_pipeline_debug_names(name);
+        }
+        if let Some(ref buffers) = self.buffers {
+            self.core.set_debug_name(buffers.vertex_buffer, "vertex buffer");
+            if let Some(index_buffer) = buffers.index_buffer {
+                self.core.set_debug_name(index_buffer, "index buffer");
+            }
+            if let Some(instance_buffer) = buffers.instance_buffer {
+                self.core.set_debug_name(instance_buffer, "instance buffer");
+            }
+        }
+        if let Some(ref textures) = self.textures {
+            self.core.set_debug_name(textures.image, "texture image");
+            for (i, &set) in textures.descriptor_sets.iter().enumerate() {
+                self.core.set_debug_name(set, &format!("texture descriptor set[{}]", i));
+            }
+        }
+        if let Some(ref texture_arrays) = self.texture_arrays {
+            self.core.set_debug_name(texture_arrays.texture_array, "texture array image");
+            self.core.set_debug_name(texture_arrays.descriptor_set, "texture array descriptor set");
+        }
+        for mesh_index in 0..self.meshes.len() {
+            self.set_mesh_debug_names(mesh_index);
+        }
+    }
+    
+    fn set_pipeline_debug_names(&self, name: &str) {
+        if let Some(pipeline) = self.pipelines.get(name) {
+            self.core.set_debug_name(pipeline.pipeline, &format!("\"{}\" pipeline", name));
+            self.core.set_debug_name(pipeline.layout, &format!("\"{}\" pipeline layout", name));
+        }
+        if let Some(resources) = self.textured_pipelines.get(name) {
+            for (i, &set) in resources.descriptor_sets.iter().enumerate() {
+                self.core.set_debug_name(set, &format!("\"{}\" descriptor set[{}]", name, i));
+            }
+        }
+    }
+    
+    // Opt a mesh in or out of occlusion culling. While enabled, the mesh's draws are wrapped in
+    // an occlusion query. Once a query reports no samples passed, the mesh is skipped and only its
+    // bounding box is drawn (no color or depth writes) to find out when it becomes visible again.
+    // Results are read back when the frame's fence comes around again rather than stalling, so
+    // visibility lags by MAX_FRAMES_IN_FLIGHT frames and a mesh coming out from behind an occluder
+    // can pop in a frame or two late. Works best for static scenes with large occluders.
+    // Only non-instanced meshes with known bounds are supported.
+    pub fn set_mesh_occlusion_culling(&mut self, mesh_index: usize, enabled: bool) -> Result<(), FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        self.mark_static_scene_dirty();
+        
+        if !enabled {
+            let mesh = &mut self.meshes[mesh_index];
+            mesh.occlusion_culling = false;
+            mesh.occlusion_visible = true;
+            let proxy_buffer = mesh.occlusion_proxy_buffer.take();
+            let proxy_memory = mesh.occlusion_proxy_memory.take();
+            if let (Some(buffer), Some(memory)) = (proxy_buffer, proxy_memory) {
+                unsafe {
+                    // The proxy may still be referenced by frames in flight
+                    let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+                }
+                destroy_buffer(&self.core.device, buffer, memory);
+            }
+            return Ok(());
+        }
+        
+        if !self.has_depth {
+            return Err("Occlusion culling needs a depth buffer".into());
+        }
+        if self.meshes[mesh_index].use_instancing {
+            return Err("Occlusion culling is not supported for instanced meshes".into());
+        }
+        let (bounds_min, bounds_max) = self.meshes[mesh_index].local_bounds
+            .ok_or("Mesh has no bounds to build an occlusion proxy from")?;
+        
+        if !self.pipelines.contains_key(OCCLUSION_PROXY_PIPELINE) {
+            self.create_occlusion_proxy_pipeline()?;
+        }
+        
+        if self.meshes[mesh_index].occlusion_proxy_buffer.is_none() {
+            let (proxy_buffer, proxy_memory) = create_vertex_buffer(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                self.core.command_pool,
+                self.core.graphics_queue,
+                &bounding_box_vertices(bounds_min, bounds_max),
+            )?;
+            self.meshes[mesh_index].occlusion_proxy_buffer = Some(proxy_buffer);
+            self.meshes[mesh_index].occlusion_proxy_memory = Some(proxy_memory);
+        }
+        
+        let mesh = &mut self.meshes[mesh_index];
+        mesh.occlusion_culling = true;
+        mesh.occlusion_visible = true;
+        Ok(())
+    }
+    
+    // Depth-only pipeline for drawing occlusion proxies: tests against depth but writes nothing
+    fn create_occlusion_proxy_pipeline(&mut self) -> Result<(), FloError> {
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(std::mem::size_of::<MvpPushConstants>() as u32);
+        
+        let position_attribute = vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(offset_of!(Vertex, position) as u32);
+        
+        let (pipeline, layout) = PipelineBuilder::new(
+            self.core.device.clone(),
+            "shaders/occlusion_proxy.vert.spv",
+            "shaders/occlusion_proxy.frag.spv",
+            self.core.swapchain_extent,
+            self.core.render_pass,
+        )?
+        .with_vertex_input(vec![Vertex::get_binding_description()], vec![position_attribute])
+        .with_push_constants(vec![push_constant_range])
+        .with_depth_test(true)
+        .with_depth_write(false)
+        .with_color_write_mask(vk::ColorComponentFlags::empty())
+        .with_cull_mode(vk::CullModeFlags::NONE)
+        .build()?;
+        
+        self.pipelines.insert(OCCLUSION_PROXY_PIPELINE.to_string(), Pipeline {
+            pipeline,
+            layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
+        });
+        self.set_pipeline_debug_names(OCCLUSION_PROXY_PIPELINE);
+        
+        Ok(())
+    }
+    
+    // Line mode pipeline for wireframe overlays. It tests depth without writing it, and its
+    // depth bias pulls the edges in front of the fill drawn before them.
+    fn create_wireframe_overlay_pipeline(&mut self, name: &'static str) -> Result<(), FloError> {
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(MVP_PUSH_CONSTANTS_SIZE);
+        
+        let mut bindings = vec![Vertex::get_binding_description()];
+        let mut attributes = vec![vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(offset_of!(Vertex, position) as u32)];
+        let vert_shader_path = match name {
+            WIREFRAME_OVERLAY_INSTANCED_PIPELINE => {
+                // One position per instance, like the meshes from add_mesh_instanced
+                bindings.push(vk::VertexInputBindingDescription::default()
+                    .binding(1)
+                    .stride(std::mem::size_of::<[f32; 3]>() as u32)
+                    .input_rate(vk::VertexInputRate::INSTANCE));
+                attributes.push(vk::VertexInputAttributeDescription::default()
+                    .binding(1)
+                    .location(1)
+                    .format(vk::Format::R32G32B32_SFLOAT)
+                    .offset(0));
+                "shaders/wireframe_overlay_instanced.vert.spv"
+            }
+            WIREFRAME_OVERLAY_INSTANCE_DATA_PIPELINE => {
+                bindings.push(InstanceData::get_binding_description());
+                attributes.extend(InstanceData::get_attribute_descriptions());
+                "shaders/wireframe_overlay_instance_data.vert.spv"
+            }
+            _ => "shaders/wireframe_overlay.vert.spv",
+        };
+        
+        let (pipeline, layout) = PipelineBuilder::new(
+            self.core.device.clone(),
+            vert_shader_path,
+            "shaders/wireframe_overlay.frag.spv",
+            self.core.swapchain_extent,
+            self.core.render_pass,
+        )?
+        .with_name(name)
+        .with_vertex_input(bindings, attributes)
+        .with_push_constants(vec![push_constant_range])
+        .with_polygon_mode(vk::PolygonMode::LINE)
+        .with_cull_mode(vk::CullModeFlags::NONE)
+        .with_depth_test(self.has_depth)
+        .with_depth_write(false)
+        .with_depth_bias(true)
+        .with_blend_mode(BlendMode::AlphaBlend)
+        .build()?;
+        
+        self.pipelines.insert(name.to_string(), Pipeline {
+            pipeline,
+            layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::AlphaBlend,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
+        });
+        self.set_pipeline_debug_names(name);
+        
+        Ok(())
+    }
+    
+    // Builds the pipelines of DEBUG_OVERDRAW_PIPELINES or DEBUG_VIEW_PIPELINES that don't exist
+    // yet. Depth test and write follow the meshes like with their own pipelines, overdraw adds
+    // a constant per fragment.
+    fn create_debug_view_pipelines(&mut self, names: [&'static str; 3]) -> Result<(), FloError> {
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(DEBUG_VIEW_PUSH_CONSTANTS_SIZE);
+        let blend_mode = if names == DEBUG_OVERDRAW_PIPELINES { BlendMode::Additive } else { BlendMode::Opaque };
+        
+        for (instancing, name) in names.into_iter().enumerate() {
+            if self.pipelines.contains_key(name) {
+                continue;
+            }
+            let mut bindings = vec![Vertex::get_binding_description()];
+            let mut attributes = Vertex::get_attribute_descriptions();
+            attributes.truncate(2);  // Position and normal
+            let vert_shader_path = match instancing {
+                1 => {
+                    // One position per instance, like the meshes from add_mesh_instanced
+                    bindings.push(vk::VertexInputBindingDescription::default()
+                        .binding(1)
+                        .stride(std::mem::size_of::<[f32; 3]>() as u32)
+                        .input_rate(vk::VertexInputRate::INSTANCE));
+                    attributes.push(vk::VertexInputAttributeDescription::default()
+                        .binding(1)
+                        .location(4)
+                        .format(vk::Format::R32G32B32_SFLOAT)
+                        .offset(0));
+                    "shaders/debug_view_instanced.vert.spv"
+                }
+                2 => {
+                    bindings.push(InstanceData::get_binding_description());
+                    attributes.extend(InstanceData::get_attribute_descriptions());
+                    "shaders/debug_view_instance_data.vert.spv"
+                }
+                _ => "shaders/debug_view.vert.spv",
+            };
+            
+            let (pipeline, layout) = PipelineBuilder::new(
+                self.core.device.clone(),
+                vert_shader_path,
+                "shaders/debug_view.frag.spv",
+                self.core.swapchain_extent,
+                self.core.render_pass,
+            )?
+            .with_name(name)
+            .with_vertex_input(bindings, attributes)
+            .with_push_constants(vec![push_constant_range])
+            .with_cull_mode(vk::CullModeFlags::NONE)
+            .with_depth_test(self.has_depth)
+            .with_blend_mode(blend_mode)
+            .build()?;
+            
+            self.pipelines.insert(name.to_string(), Pipeline {
+                pipeline,
+                layout,
+                push_constant_ranges: vec![push_constant_range],
+                blend_mode,
+                skinning_mode: SkinningMode::LinearBlend,
+                two_sided_lighting: false,
+            });
+            self.set_pipeline_debug_names(name);
+        }
+        
+        Ok(())
+    }
+    
+    // Apply the occlusion query results of the frame whose fence begin_frame just waited on
+    fn update_occlusion_visibility(&mut self) {
+        let queried_meshes = std::mem::take(&mut self.occlusion_query_meshes[self.core.current_frame]);
+        if queried_meshes.is_empty() {
+            return;
+        }
+        
+        if let Some(results) = self.core.read_occlusion_query_results(queried_meshes.len() as u32) {
+            for (&mesh_index, samples_passed) in queried_meshes.iter().zip(results) {
+                if let Some(mesh) = self.meshes.get_mut(mesh_index) {
+                    if mesh.occlusion_culling {
+                        mesh.occlusion_visible = samples_passed > 0;
+                    }
+                }
+            }
+        }
+    }
+    
+    // Frustum cull an instanced mesh's instances every frame before drawing it. A compute shader
+    // tests each instance's bounding sphere against the frustum and compacts the survivors into
+    // a buffer drawn with cmd_draw_indexed_indirect, so there's no CPU cost and no readback.
+    // Instance positions are treated as translations of the mesh in the view/proj passed to render.
+    // Falls back to culling on the CPU when the graphics queue can't run compute or the cull
+    // shader can't be loaded. Returns true when culling runs on the GPU.
+    pub fn enable_gpu_culling(&mut self, mesh_index: usize) -> Result<bool, FloError> {
+        if mesh_index >= self.meshes.len() {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        
+        let mesh = &self.meshes[mesh_index];
+        if !mesh.use_instancing || mesh.is_skinned || mesh.draw_mode != MeshDrawMode::Indexed {
+            return Err("GPU culling needs a non-skinned, indexed, instanced mesh".into());
+        }
+        if mesh.instance_data.is_some() || mesh.instance_matrices.is_some() {
+            return Err("GPU culling only supports position only instances".into());
+        }
+        if mesh.instance_sort.is_some() {
+            return Err("GPU culling compacts instances out of order, it can't be combined with set_mesh_instance_sort".into());
+        }
+        let source_instance_buffer = mesh.instance_buffer.ok_or("Mesh has no instance buffer")?;
+        let (bounds_min, bounds_max) = mesh.local_bounds.ok_or("Mesh has no bounds to cull with")?;
+        
+        // Farthest bounding box corner from the mesh origin, so any rotation of the mesh still fits
+        let radius = (0..3)
+            .map(|axis| bounds_min[axis].abs().max(bounds_max[axis].abs()).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        
+        let instance_positions = self.read_instance_positions(mesh_index)?;
+        
+        let queue_family = self.core.queue_family_indices.graphics_family.unwrap_or(0);
+        if self.instance_cull_pipeline.is_none()
+            && instance_culling::gpu_culling_supported(&self.core.instance, self.core.physical_device, queue_family) {
+            match InstanceCullPipeline::new(&self.core.device) {
+                Ok(cull_pipeline) => self.instance_cull_pipeline = Some(cull_pipeline),
+                Err(e) => warn!("Failed to create instance cull pipeline, culling on the CPU: {}", e),
+            }
+        }
+        
+        let culling = InstanceCulling::new(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            self.instance_cull_pipeline.as_ref(),
+            source_instance_buffer,
+            instance_positions,
+            radius,
+        )?;
+        let gpu = culling.is_gpu();
+        
+        self.disable_gpu_culling(mesh_index);
+        self.meshes[mesh_index].instance_culling = Some(culling);
+        self.mark_static_scene_dirty();
+        
+        debug!("Enabled {} instance culling for mesh {}", if gpu { "GPU" } else { "CPU" }, mesh_index);
+        Ok(gpu)
+    }
+    
+    pub fn disable_gpu_culling(&mut self, mesh_index: usize) {
+        if let Some(culling) = self.meshes.get_mut(mesh_index).and_then(|mesh| mesh.instance_culling.take()) {
+            unsafe {
+                // Frames in flight may still be drawing from the compacted buffers
+                let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+            }
+            culling.destroy(&self.core.device);
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    // Draw an instanced mesh's instances from back to front, e.g. thousands of alpha blended
+    // particles or foliage cards that per mesh sorting (see plan_draw_order) leaves in the order
+    // they were given. Every frame the instances are radix sorted by the view depth of their
+    // bounds' center and the instance buffer is rewritten in that order, so gl_InstanceIndex
+    // follows the sorted order. Instance indices passed to the update functions and
+    // remove_instance stay the ones the instances were given with. The main camera's order is
+    // also used by overlay passes and render_scene_to_target. None puts the instances back in
+    // their given order. GPU culled meshes and instance matrices (add_mesh_instanced_ssbo)
+    // aren't supported.
+    pub fn set_mesh_instance_sort(&mut self, mesh_index: usize, sort: Option<InstanceSort>) -> Result<(), FloError> {
+        let mesh = self.meshes.get(mesh_index).ok_or(FloError::InvalidMeshIndex(mesh_index))?;
+        if !mesh.use_instancing {
+            return Err(format!("Mesh {} isn't instanced", mesh_index).into());
+        }
+        if mesh.instance_matrices.is_some() {
+            return Err("Instance matrices can't be sorted".into());
+        }
+        if mesh.instance_culling.is_some() {
+            return Err("GPU culling compacts instances out of order, it can't be combined with set_mesh_instance_sort".into());
+        }
+        if let Some(sort) = sort {
+            if !(1..=32).contains(&sort.key_bits) {
+                return Err(format!("Instance sort keys need 1 to 32 bits, got {}", sort.key_bits).into());
+            }
+        }
+        
+        match (mesh.instance_sort.is_some(), sort.is_some()) {
+            (false, true) if mesh.instance_data.is_none() => {
+                self.meshes[mesh_index].unsorted_instance_positions = self.read_instance_positions(mesh_index)?;
+            }
+            (true, false) => {
+                if self.meshes[mesh_index].instance_data.is_some() {
+                    self.write_mesh_instance_data(mesh_index)?;
+                } else if let Some(instance_buffer) = self.meshes[mesh_index].instance_buffer {
+                    let mut positions = std::mem::take(&mut self.meshes[mesh_index].unsorted_instance_positions);
+                    positions.truncate(self.meshes[mesh_index].instance_count as usize);
+                    self.upload_vertex_data(instance_buffer, bytemuck::cast_slice(&positions));
+                }
+            }
+            _ => {}
+        }
+        self.meshes[mesh_index].instance_sort = sort;
+        Ok(())
+    }
+    
+    pub fn mesh_instance_sort(&self, mesh_index: usize) -> Option<InstanceSort> {
+        self.meshes.get(mesh_index)?.instance_sort
+    }
+    
+    // Queues the instances of meshes with set_mesh_instance_sort in back to front order for view,
+    // before this frame's uploads are recorded
+    fn sort_mesh_instances(&mut self, view: Mat4) {
+        for mesh_index in 0..self.meshes.len() {
+            let mesh = &self.meshes[mesh_index];
+            let (Some(sort), Some(instance_buffer)) = (mesh.instance_sort, mesh.instance_buffer) else {
+                continue;
+            };
+            let count = mesh.instance_count as usize;
+            if count < 2 {
+                continue;
+            }
+            let local_center = mesh.local_bounds
+                .map_or(Vec3::ZERO, |(min, max)| (Vec3::from(min) + Vec3::from(max)) * 0.5);
+            let depth = |position: Vec3| -view.transform_point3(position).z;
+            
+            let sorted_bytes: Vec<u8> = if let Some(ref instances) = mesh.instance_data {
+                let instances = &instances[..count.min(instances.len())];
+                let depths: Vec<f32> = instances.iter()
+                    .map(|instance| depth(Mat4::from_cols_array(&instance.transform).transform_point3(local_center)))
+                    .collect();
+                let sorted: Vec<InstanceData> = instance_sort::sort_back_to_front(&depths, sort.key_bits).into_iter()
+                    .map(|index| instances[index as usize])
+                    .collect();
+                bytemuck::cast_slice(&sorted).to_vec()
+            } else {
+                let positions = &mesh.unsorted_instance_positions[..count.min(mesh.unsorted_instance_positions.len())];
+                let depths: Vec<f32> = positions.iter()
+                    .map(|&position| depth(local_center + Vec3::from(position)))
+                    .collect();
+                let sorted: Vec<[f32; 3]> = instance_sort::sort_back_to_front(&depths, sort.key_bits).into_iter()
+                    .map(|index| positions[index as usize])
+                    .collect();
+                bytemuck::cast_slice(&sorted).to_vec()
+            };
+            self.upload_vertex_data(instance_buffer, &sorted_bytes);
+        }
+    }
+    
+    // Copy of an instanced mesh's current positions, read back from its host visible instance buffer
+    fn read_instance_positions(&self, mesh_index: usize) -> Result<Vec<[f32; 3]>, FloError> {
+        let count = self.meshes[mesh_index].instance_count as usize;
+        let mut positions = vec![[0.0f32; 3]; count];
+        if count == 0 {
+            return Ok(positions);
+        }
+        let bytes = self.read_instance_bytes(mesh_index, 0, std::mem::size_of_val(positions.as_slice()))?;
+        bytemuck::cast_slice_mut(&mut positions).copy_from_slice(&bytes);
+        Ok(positions)
+    }
+    
+    // size bytes at offset in a mesh's instance buffer, with the uploads still waiting to be
+    // copied in applied on top since they're the current data
+    fn read_instance_bytes(&self, mesh_index: usize, offset: usize, size: usize) -> Result<Vec<u8>, FloError> {
+        let mesh = &self.meshes[mesh_index];
+        // Queued uploads are already applied to the shadow
+        if let Some(ref shadow) = mesh.instance_shadow {
+            return shadow.get(offset..offset + size)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| "Read past the end of the instance buffer".into());
+        }
+        let (memory, memory_offset) = if let Some(block) = &mesh.instance_memory_block {
+            (block.memory, block.offset)
+        } else if let Some(memory) = mesh.instance_buffer_memory {
+            (memory, 0)
+        } else {
+            return Err("Mesh has no instance memory".into());
+        };
+        
+        let mut bytes = vec![0u8; size];
+        unsafe {
+            let data = self.core.device.map_memory(memory, memory_offset + offset as vk::DeviceSize, size as vk::DeviceSize, vk::MemoryMapFlags::empty())?;
+            std::ptr::copy_nonoverlapping(data as *const u8, bytes.as_mut_ptr(), size);
+            self.core.device.unmap_memory(memory);
+        }
+        
+        for upload in self.pending_vertex_uploads.iter().filter(|upload| Some(upload.buffer) == mesh.instance_buffer) {
+            let upload_start = upload.offset as usize;
+            let start = upload_start.max(offset);
+            let end = (upload_start + upload.data.len()).min(offset + size);
+            if start < end {
+                bytes[start - offset..end - offset].copy_from_slice(&upload.data[start - upload_start..end - upload_start]);
+            }
+        }
+        Ok(bytes)
+    }
+    
+    // Reset and dispatch instance culling for this frame, must run outside the render pass
+    fn record_instance_culling(&mut self, command_buffer: vk::CommandBuffer, view: Mat4, proj: Mat4) {
+        if !self.meshes.iter().any(|mesh| mesh.instance_culling.is_some()) {
+            return;
+        }
+        
+        let frame = self.core.current_frame;
+        let frustum = Frustum::from_view_proj(proj * view);
+        let device = &self.core.device;
+        
+        for mesh in self.meshes.iter_mut() {
+            let index_count = mesh.index_count;
+            if let Some(culling) = mesh.instance_culling.as_mut() {
+                culling.record_reset(device, command_buffer, frame, &frustum, index_count);
+            }
+        }
+        
+        if let Some(ref cull_pipeline) = self.instance_cull_pipeline {
+            instance_culling::record_reset_barrier(device, command_buffer);
+            unsafe {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, cull_pipeline.pipeline);
+            }
+            for mesh in &self.meshes {
+                if let Some(ref culling) = mesh.instance_culling {
+                    culling.record_dispatch(device, command_buffer, frame, cull_pipeline);
+                }
+            }
+            instance_culling::record_draw_barrier(device, command_buffer);
+        }
+    }
+    
+    // Update instance positions for a specific mesh (convenience method)
+    pub fn update_mesh_instances(&mut self, mesh_index: usize, positions: Vec<[f32; 3]>) {
+        if mesh_index < self.meshes.len() {
+            let transforms = positions.iter()
+                .map(|pos| Mat4::from_translation(Vec3::new(pos[0], pos[1], pos[2])))
+                .collect();
+            self.set_mesh_transforms(mesh_index, transforms);
+        } else {
+            error!("mesh_index {} out of bounds (meshes.len = {})", mesh_index, self.meshes.len());
+        }
+    }
+    
+    // Get the total number of meshes in the renderer
+    pub fn get_mesh_count(&self) -> usize {
+        self.meshes.len()
+    }
+    
+    // Local space (min, max) the mesh was added with, None for unknown bounds (e.g. skinned meshes)
+    pub fn get_mesh_bounds(&self, mesh_index: usize) -> Option<(Vec3, Vec3)> {
+        let (min, max) = self.meshes.get(mesh_index)?.local_bounds?;
+        Some((Vec3::from(min), Vec3::from(max)))
+    }
+    
+    // World space (min, max) of everything that gets drawn, e.g. to frame the camera on load.
+    // Meshes without transforms or instances and meshes with unknown bounds are left out.
+    pub fn scene_bounds(&self) -> Option<(Vec3, Vec3)> {
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        let mut include = |(min, max): (Vec3, Vec3)| {
+            bounds = Some(match bounds {
+                Some((bounds_min, bounds_max)) => (bounds_min.min(min), bounds_max.max(max)),
+                None => (min, max),
+            });
+        };
+        
+        for mesh in &self.meshes {
+            let (local_min, local_max) = match mesh.local_bounds {
+                Some((min, max)) => (Vec3::from(min), Vec3::from(max)),
+                None => continue,
+            };
+            
+            if mesh.use_instancing {
+                if mesh.instance_count == 0 {
+                    continue;
+                }
+                if let Some((instance_min, instance_max)) = mesh.instance_bounds {
+                    include((local_min + Vec3::from(instance_min), local_max + Vec3::from(instance_max)));
+                }
+            } else {
+                for transform in &mesh.transforms {
+                    include(transform_bounds(transform, local_min, local_max));
+                }
+            }
+        }
+        
+        bounds
+    }
+    
+    // World space center of everything that gets drawn, e.g. as the camera's orbit focus. Each
+    // mesh's centroid is weighted by its surface area, so a detailed part of a model doesn't
+    // pull the center toward it the way a vertex average would. Instanced meshes count once
+    // per instance, at the center of their instance bounds. Meshes left out of scene_bounds
+    // are left out here too.
+    pub fn scene_centroid(&self) -> Option<Vec3> {
+        let mut weighted_sum = Vec3::ZERO;
+        let mut total_area = 0.0;
+        
+        for mesh in &self.meshes {
+            let Some((local_centroid, area)) = mesh.local_centroid else {
+                continue;
+            };
+            let local_centroid = Vec3::from(local_centroid);
+            
+            if mesh.use_instancing {
+                let Some((instance_min, instance_max)) = mesh.instance_bounds else {
+                    continue;
+                };
+                let instance_center = (Vec3::from(instance_min) + Vec3::from(instance_max)) * 0.5;
+                let weight = area * mesh.instance_count as f32;
+                weighted_sum += (local_centroid + instance_center) * weight;
+                total_area += weight;
+            } else {
+                for transform in &mesh.transforms {
+                    // Scaling by s scales areas by s squared, the determinant by s cubed
+                    let weight = area * transform.determinant().abs().powf(2.0 / 3.0);
+                    weighted_sum += transform.transform_point3(local_centroid) * weight;
+                    total_area += weight;
+                }
+            }
+        }
+        
+        (total_area > 0.0).then(|| weighted_sum / total_area)
+    }
+    
+    // Update mesh vertex positions dynamically (for fluid simulation).
+    // Only deinterleaved meshes keep positions in their own buffer, so interleaved meshes
+    // need update_mesh_vertices_full instead.
+    pub fn update_mesh_vertices(&mut self, mesh_index: usize, new_positions: &[[f32; 3]]) {
+        if mesh_index >= self.meshes.len() {
+            error!("mesh_index {} out of bounds (meshes.len = {})", mesh_index, self.meshes.len());
+            return;
+        }
+        
+        if self.meshes[mesh_index].vertex_streams.is_none() {
+            error!("Mesh {} is interleaved, use update_mesh_vertices_full to update it", mesh_index);
+            return;
+        }
+        
+        let position_buffer = self.meshes[mesh_index].vertex_buffer;
+        self.upload_vertex_data(position_buffer, bytemuck::cast_slice(new_positions));
+    }
+    
+    // Update just the normals of a deinterleaved mesh
+    pub fn update_mesh_normals(&mut self, mesh_index: usize, new_normals: &[[f32; 3]]) {
+        if mesh_index >= self.meshes.len() {
+            error!("mesh_index {} out of bounds (meshes.len = {})", mesh_index, self.meshes.len());
+            return;
+        }
+        
+        let normal_buffer = match self.meshes[mesh_index].vertex_streams {
+            Some(ref streams) => streams.normal_buffer,
+            None => {
+                error!("Mesh {} is interleaved, use update_mesh_vertices_full to update it", mesh_index);
+                return;
+            }
+        };
+        
+        self.upload_vertex_data(normal_buffer, bytemuck::cast_slice(new_normals));
+    }
+    
+    // The copy is recorded at the start of the next multi mesh or fluid frame
+    pub fn update_mesh_vertices_full(&mut self, mesh_index: usize, new_vertices: &[Vertex]) {
+        if mesh_index >= self.meshes.len() {
+            error!("mesh_index {} out of bounds (meshes.len = {})", mesh_index, self.meshes.len());
+            return;
+        }
+        
+        let mesh = &self.meshes[mesh_index];
+        if let Some(ref streams) = mesh.vertex_streams {
+            let (position_buffer, normal_buffer, uv_buffer) = (mesh.vertex_buffer, streams.normal_buffer, streams.uv_buffer);
+            let positions: Vec<[f32; 3]> = new_vertices.iter().map(|v| v.position).collect();
+            let normals: Vec<[f32; 3]> = new_vertices.iter().map(|v| v.normal).collect();
+            let uvs: Vec<[f32; 2]> = new_vertices.iter().map(|v| v.uv).collect();
+            self.upload_vertex_data(position_buffer, bytemuck::cast_slice(&positions));
+            self.upload_vertex_data(normal_buffer, bytemuck::cast_slice(&normals));
+            self.upload_vertex_data(uv_buffer, bytemuck::cast_slice(&uvs));
+        } else {
+            let vertex_buffer = mesh.vertex_buffer;
+            self.upload_vertex_data(vertex_buffer, bytemuck::cast_slice(new_vertices));
+        }
+    }
+    
+    // Same as update_mesh_vertices_full for several meshes, e.g. each water body of a scene.
+    // All their copies go through one staging buffer in the next frame's command buffer, with
+    // no submits or waits of their own. Nothing is queued if any index is out of bounds.
+    pub fn update_meshes_vertices_full(&mut self, updates: &[(usize, &[Vertex])]) -> Result<(), FloError> {
+        if let Some(&(mesh_index, _)) = updates.iter().find(|(mesh_index, _)| *mesh_index >= self.meshes.len()) {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        for &(mesh_index, vertices) in updates {
+            self.update_mesh_vertices_full(mesh_index, vertices);
+        }
+        Ok(())
+    }
+    
+    // Queues vertex data to be copied into a device local vertex buffer (or an instance buffer)
+    // at the start of the next frame, so updating doesn't wait for the GPU to go idle or write
+    // a buffer an earlier frame is still reading
+    fn upload_vertex_data(&mut self, vertex_buffer: vk::Buffer, vertex_data: &[u8]) {
+        self.write_instance_shadow(vertex_buffer, 0, vertex_data);
+        // Mapped buffers are written directly when each frame's copy comes up
+        let mapped_buffer = self.meshes.iter_mut()
+            .flat_map(|mesh| mesh.mapped_vertex_buffers.iter_mut())
+            .find(|mapped_buffer| mapped_buffer.contains(vertex_buffer));
+        if let Some(mapped_buffer) = mapped_buffer {
+            mapped_buffer.write(vertex_data);
+            return;
+        }
+        
+        // Only the latest data for a buffer needs copying
+        self.pending_vertex_uploads.retain(|upload| upload.buffer != vertex_buffer);
+        if !vertex_data.is_empty() {
+            self.pending_vertex_uploads.push(PendingVertexUpload {
+                buffer: vertex_buffer,
+                offset: 0,
+                data: vertex_data.to_vec(),
+            });
+        }
+    }
+    
+    // Queues data for part of a buffer, copied after the uploads queued before it. Only for
+    // instance buffers, which are never mapped per frame like vertex buffers can be.
+    fn upload_vertex_data_at(&mut self, buffer: vk::Buffer, offset: vk::DeviceSize, data: &[u8]) {
+        self.write_instance_shadow(buffer, offset as usize, data);
+        self.pending_vertex_uploads.retain(|upload| {
+            upload.buffer != buffer || upload.offset != offset || upload.data.len() > data.len()
+        });
+        if !data.is_empty() {
+            self.pending_vertex_uploads.push(PendingVertexUpload { buffer, offset, data: data.to_vec() });
+        }
+    }
+    
+    // Drops queued vertex data for a mesh whose buffers are about to be destroyed
+    fn cancel_vertex_uploads(&mut self, mesh_index: usize) {
+        let mesh = &self.meshes[mesh_index];
+        // Index buffers get uploads from upload_mesh_data
+        let mut buffers = vec![mesh.vertex_buffer, mesh.index_buffer];
+        if let Some(ref streams) = mesh.vertex_streams {
+            buffers.extend([streams.normal_buffer, streams.uv_buffer]);
+        }
+        self.pending_vertex_uploads.retain(|upload| !buffers.contains(&upload.buffer));
+    }
+    
+    // Keeps a DeviceLocal instance buffer's shadow in step with the data queued for it
+    fn write_instance_shadow(&mut self, buffer: vk::Buffer, offset: usize, data: &[u8]) {
+        let shadow = self.meshes.iter_mut()
+            .filter(|mesh| mesh.instance_buffer == Some(buffer))
+            .find_map(|mesh| mesh.instance_shadow.as_mut());
+        if let Some(shadow) = shadow {
+            let end = (offset + data.len()).min(shadow.len());
+            if offset < end {
+                shadow[offset..end].copy_from_slice(&data[..end - offset]);
+            }
+        }
+    }
+    
+    // Copy queued vertex data through this frame's staging buffer, must run outside the render
+    // pass. The barriers order the copies after earlier frames' vertex reads and before this one's,
+    // including the culling compute shader's reads of instance buffers.
+    fn record_vertex_uploads(&mut self, command_buffer: vk::CommandBuffer) {
+        // Point mapped meshes at this frame's copies, whose fence was waited on
+        let frame = self.core.current_frame;
+        for mesh in self.meshes.iter_mut().filter(|mesh| !mesh.mapped_vertex_buffers.is_empty()) {
+            mesh.vertex_buffer = mesh.mapped_vertex_buffers[0].frame_buffer(frame);
+            if let Some(ref mut streams) = mesh.vertex_streams {
+                streams.normal_buffer = mesh.mapped_vertex_buffers[1].frame_buffer(frame);
+                streams.uv_buffer = mesh.mapped_vertex_buffers[2].frame_buffer(frame);
+            }
+        }
+        
+        if self.pending_vertex_uploads.is_empty() {
+            return;
+        }
+        // Copies in one command buffer aren't ordered against each other, so no two may write
+        // the same bytes
+        self.pending_vertex_uploads = merge_overlapping_uploads(std::mem::take(&mut self.pending_vertex_uploads));
+        
+        let total_size: vk::DeviceSize = self.pending_vertex_uploads.iter()
+            .map(|upload| upload.data.len() as vk::DeviceSize)
+            .sum();
+        let current_staging = self.vertex_staging_buffers[frame];
+        let (staging_buffer, staging_memory) = match current_staging {
+            Some((buffer, memory, size)) if size >= total_size => (buffer, memory),
+            old_staging => {
+                // This frame's fence was waited on, so the copies from the old buffer are done
+                if let Some((buffer, memory, _)) = old_staging {
+                    destroy_buffer(&self.core.device, buffer, memory);
+                }
+                self.vertex_staging_buffers[frame] = None;
+                match create_buffer(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    total_size,
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ) {
+                    Ok((buffer, memory)) => {
+                        self.vertex_staging_buffers[frame] = Some((buffer, memory, total_size));
+                        (buffer, memory)
+                    }
+                    Err(e) => {
+                        error!("Failed to create vertex staging buffer: {}", e);
+                        return;
+                    }
+                }
+            }
+        };
+        
+        let uploads = std::mem::take(&mut self.pending_vertex_uploads);
+        let device = &self.core.device;
+        unsafe {
+            let data = match device.map_memory(staging_memory, 0, total_size, vk::MemoryMapFlags::empty()) {
+                Ok(data) => data as *mut u8,
+                Err(e) => {
+                    error!("Failed to map vertex staging buffer: {}", e);
+                    return;
+                }
+            };
+            let mut copies = Vec::with_capacity(uploads.len());
+            let mut offset = 0;
+            for upload in &uploads {
+                std::ptr::copy_nonoverlapping(upload.data.as_ptr(), data.add(offset as usize), upload.data.len());
+                let size = upload.data.len() as vk::DeviceSize;
+                copies.push((upload.buffer, vk::BufferCopy { src_offset: offset, dst_offset: upload.offset, size }));
+                offset += size;
+            }
+            device.unmap_memory(staging_memory);
+            
+            let barriers = |src_access: vk::AccessFlags, dst_access: vk::AccessFlags| -> Vec<vk::BufferMemoryBarrier> {
+                copies.iter().map(|&(buffer, copy)| {
+                    vk::BufferMemoryBarrier::default()
+                        .src_access_mask(src_access)
+                        .dst_access_mask(dst_access)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .buffer(buffer)
+                        .offset(copy.dst_offset)
+                        .size(copy.size)
+                }).collect()
+            };
+            
+            // Vertex shaders read the instance matrices of add_mesh_instanced_ssbo meshes
+            let read_stages = vk::PipelineStageFlags::VERTEX_INPUT
+                | vk::PipelineStageFlags::VERTEX_SHADER
+                | vk::PipelineStageFlags::COMPUTE_SHADER;
+            // Index reads cover the index buffers of upload_mesh_data
+            let read_access = vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ | vk::AccessFlags::SHADER_READ;
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                read_stages,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &barriers(read_access, vk::AccessFlags::TRANSFER_WRITE),
+                &[],
+            );
+            for &(buffer, copy) in &copies {
+                device.cmd_copy_buffer(command_buffer, staging_buffer, buffer, &[copy]);
+            }
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                read_stages,
+                vk::DependencyFlags::empty(),
+                &[],
+                &barriers(vk::AccessFlags::TRANSFER_WRITE, read_access),
+                &[],
+            );
+        }
+    }
+    
+    // Add a new pipeline with a given name
+    pub fn get_memory_stats(&self) -> String {
+        self.memory_pool.get_stats()
+    }
+    
+    // Device memory currently allocated, by usage. Covers pooled and unpooled allocations;
+    // pool chunks are reported whole under `pool`, see get_memory_stats for their usage.
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport { descriptors: self.descriptor_allocator.usage(), ..memory_report() }
+    }
+    
+    // How long each pipeline built on this renderer's device took, in build order. Only
+    // recorded while debug logging is enabled for the crate, see vulkan_common::pipeline_build_report.
+    pub fn pipeline_build_report(&self) -> Vec<PipelineBuildTime> {
+        pipeline_build_report(&self.core.device)
+    }
+    
+    // Layout of a set shared between pipelines, for PipelineBuilder::with_shared_descriptor_layout.
+    // Every pipeline gets the same layout handle, so sets allocated with it work with all of them.
+    pub fn shared_descriptor_layout(&mut self, set: SharedSet) -> Result<SharedDescriptorLayout, FloError> {
+        if self.shared_layouts.is_none() {
+            self.shared_layouts = Some(SharedDescriptorLayouts::new(&self.core.device)?);
+        }
+        Ok(self.shared_layouts.as_ref().unwrap().get(set))
+    }
+
+    pub fn add_pipeline(&mut self, name: &str, vert_shader_path: &str, frag_shader_path: &str) -> Result<(), FloError> {
+        self.add_pipeline_with_texture(name, vert_shader_path, frag_shader_path, false)
+    }
+    
+    // Add a new pipeline with optional texture support
+    pub fn add_pipeline_with_texture(&mut self, name: &str, vert_shader_path: &str, frag_shader_path: &str, has_texture: bool) -> Result<(), FloError> {
+        // Use default COUNTER_CLOCKWISE for compatibility
+        self.add_pipeline_with_texture_and_winding(name, vert_shader_path, frag_shader_path, has_texture, vk::FrontFace::COUNTER_CLOCKWISE)
+    }
+    
+    // Add a new pipeline with optional texture support and custom winding order
+    pub fn add_pipeline_with_texture_and_winding(&mut self, name: &str, vert_shader_path: &str, frag_shader_path: &str, has_texture: bool, front_face: vk::FrontFace) -> Result<(), FloError> {
+        self.add_pipeline_with_blend_mode(name, vert_shader_path, frag_shader_path, has_texture, front_face, BlendMode::Opaque)
+    }
+    
+    // Add a new pipeline with optional texture support, custom winding order and blending,
+    // e.g. Additive for particles and glows. Blended pipelines test depth but don't write it,
+    // so meshes behind a transparent one still show through.
+    pub fn add_pipeline_with_blend_mode(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        front_face: vk::FrontFace,
+        blend_mode: BlendMode,
+    ) -> Result<(), FloError> {
+        self.add_pipeline_with_push_constant_size(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            has_texture,
+            front_face,
+            blend_mode,
+            MVP_PUSH_CONSTANTS_SIZE,
+        )
+    }
+    
+    // Same as add_pipeline_with_blend_mode with a push constant range of push_constant_size
+    // bytes for shaders that declare their own fields after the MVP block, which meshes fill
+    // with set_mesh_push_constants. Sizes below the MVP block's 208 bytes are raised to it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_pipeline_with_push_constant_size(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        front_face: vk::FrontFace,
+        blend_mode: BlendMode,
+        push_constant_size: u32,
+    ) -> Result<(), FloError> {
+        self.add_mesh_pipeline::<Vertex>(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            has_texture,
+            vk::CullModeFlags::BACK,
+            front_face,
+            blend_mode,
+            push_constant_size,
+            None,
+            CutoutAntialiasing::default(),
+        )
+    }
+    
+    // Add an opaque pipeline for meshes with a MaterialMode::Cutout, e.g. foliage and fences.
+    // It writes depth like other opaque pipelines, and its push constant range has room for
+    // the alpha threshold after the MVP block (see shaders/mesh_cutout.frag). Leaves and other
+    // single sheet cutouts usually want vk::CullModeFlags::NONE.
+    pub fn add_cutout_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
+        self.add_cutout_pipeline_with_antialiasing(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            has_texture,
+            cull_mode,
+            front_face,
+            CutoutAntialiasing::default(),
+        )
+    }
+    
+    // add_cutout_pipeline with sample shading and/or alpha to coverage, which smooth cutout
+    // edges that otherwise alias inside a pixel under MSAA. Sample shading runs the fragment
+    // shader per sample, up to the sample count times the shading cost of the pipeline's
+    // meshes, so it's only worth it for foliage close to the camera. Errors if the device
+    // lacks the sampleRateShading feature.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_cutout_pipeline_with_antialiasing(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+        antialiasing: CutoutAntialiasing,
+    ) -> Result<(), FloError> {
+        if antialiasing.min_sample_shading.is_some()
+            && !sample_rate_shading_supported(&self.core.instance, self.core.physical_device) {
+            return Err(FloError::UnsupportedDevice("Sample shading needs the sampleRateShading feature".to_string()));
+        }
+        self.add_mesh_pipeline::<Vertex>(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            has_texture,
+            cull_mode,
+            front_face,
+            BlendMode::Opaque,
+            CUTOUT_PUSH_CONSTANTS_SIZE,
+            None,
+            antialiasing,
+        )
+    }
+    
+    // Pipeline for meshes swaying in the wind (set_mesh_wind), with shaders/wind.vert or a vertex
+    // shader reading the same push constants. The range has room for the cutout threshold too, so
+    // foliage can use mesh_cutout.frag.
+    pub fn add_wind_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
+        self.add_mesh_pipeline::<Vertex>(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            has_texture,
+            cull_mode,
+            front_face,
+            BlendMode::Opaque,
+            WIND_PUSH_CONSTANTS_SIZE,
+            None,
+            CutoutAntialiasing::default(),
+        )
+    }
+    
+    // Pipeline for meshes with a UV transform (set_mesh_uv_transform), with
+    // shaders/mesh_uv_transform.vert or a vertex shader reading the same push constants. Like
+    // wind pipelines the range has room for the cutout threshold.
+    pub fn add_uv_transform_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
+        self.add_mesh_pipeline::<Vertex>(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            has_texture,
+            cull_mode,
+            front_face,
+            BlendMode::Opaque,
+            UV_TRANSFORM_PUSH_CONSTANTS_SIZE,
+            None,
+            CutoutAntialiasing::default(),
+        )
+    }
+    
+    // Opaque pipeline for meshes from add_mesh_with_format, reading T at binding 0. Its push
+    // constants are the usual MVP block, so the vertex shader declares the same block as
+    // shaders/mesh.vert and its own inputs.
+    pub fn add_pipeline_with_vertex_format<T: VertexFormat>(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
+        self.add_mesh_pipeline::<T>(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            has_texture,
+            cull_mode,
+            front_face,
+            BlendMode::Opaque,
+            MVP_PUSH_CONSTANTS_SIZE,
+            None,
+            CutoutAntialiasing::default(),
+        )
+    }
+    
+    // Add a pipeline that tests and writes the stencil buffer with the given ops, e.g. one
+    // that writes 1 for an object and one that draws a scaled copy where the stencil isn't 1
+    // for an outline (examples/stencil_outline.rs). The reference is per mesh, see
+    // set_mesh_stencil_reference. Needs a renderer created with CoreOptions::stencil.
+    pub fn add_stencil_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        stencil: vk::StencilOpState,
+        blend_mode: BlendMode,
+    ) -> Result<(), FloError> {
+        if !self.has_stencil() {
+            return Err("Stencil pipelines need a renderer created with CoreOptions::stencil".into());
+        }
+        self.add_mesh_pipeline::<Vertex>(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            false,
+            vk::CullModeFlags::BACK,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            blend_mode,
+            MVP_PUSH_CONSTANTS_SIZE,
+            Some(stencil),
+            CutoutAntialiasing::default(),
+        )
+    }
+    
+    // Add a pipeline for meshes drawn in the lighting subpass (see set_mesh_subpass), after all
+    // geometry subpass meshes, over their color. It tests against their depth without writing
+    // it, like a light volume or fog pass would.
+    pub fn add_lighting_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        blend_mode: BlendMode,
+    ) -> Result<(), FloError> {
+        let desc = MeshPipelineDesc {
+            vert_shader_path: vert_shader_path.to_string(),
+            frag_shader_path: frag_shader_path.to_string(),
+            vertex_bindings: vec![Vertex::binding_description()],
+            vertex_attributes: Vertex::attribute_descriptions(),
+            has_texture,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            blend_mode,
+            push_constant_size: MVP_PUSH_CONSTANTS_SIZE,
+            stencil: None,
+            antialiasing: CutoutAntialiasing::default(),
+            subpass: LIGHTING_SUBPASS,
+        };
+        self.create_mesh_pipeline(name, &desc, false)?;
+        self.mesh_pipeline_descs.insert(name.to_string(), desc);
+        Ok(())
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    fn add_mesh_pipeline<T: VertexFormat>(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        has_texture: bool,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+        blend_mode: BlendMode,
+        push_constant_size: u32,
+        stencil: Option<vk::StencilOpState>,
+        antialiasing: CutoutAntialiasing,
+    ) -> Result<(), FloError> {
+        let desc = MeshPipelineDesc {
+            vert_shader_path: vert_shader_path.to_string(),
+            frag_shader_path: frag_shader_path.to_string(),
+            vertex_bindings: vec![T::binding_description()],
+            vertex_attributes: T::attribute_descriptions(),
+            has_texture,
+            cull_mode,
+            front_face,
+            blend_mode,
+            push_constant_size,
+            stencil,
+            antialiasing,
+            subpass: GEOMETRY_SUBPASS,
+        };
+        self.create_mesh_pipeline(name, &desc, false)?;
+        self.mesh_pipeline_descs.insert(name.to_string(), desc);
+        Ok(())
+    }
+    
+    fn create_mesh_pipeline(&mut self, name: &str, desc: &MeshPipelineDesc, two_sided_lighting: bool) -> Result<(), FloError> {
+        let (graphics_pipeline, pipeline_layout, push_constant_range) = build_mesh_pipeline(&self.core, name, self.has_depth, desc, two_sided_lighting)?;
         
-        // Get a reusable staging buffer from the memory pool
-        let staging_buffer = self.memory_pool.get_staging_buffer(
-            &self.core.instance,
-            self.core.physical_device,
-            vertex_size
-        ).expect("Failed to get staging buffer");
+        self.mark_static_scene_dirty();
+        self.pipelines.insert(name.to_string(), Pipeline {
+            pipeline: graphics_pipeline,
+            layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: desc.blend_mode,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting,
+        });
+        self.set_pipeline_debug_names(name);
         
-        unsafe {
-            // Map the staging buffer and copy vertex data
-            let ptr = self.core.device
-                .map_memory(staging_buffer.1, 0, vertex_size, vk::MemoryMapFlags::empty())
-                .expect("Failed to map staging buffer memory");
-            
-            std::ptr::copy_nonoverlapping(vertex_data.as_ptr(), ptr as *mut u8, vertex_data.len());
-            
-            self.core.device.unmap_memory(staging_buffer.1);
-            
-            // Copy from staging buffer to vertex buffer using command pool
-            let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::default()
-                .command_pool(self.core.command_pool)
-                .level(vk::CommandBufferLevel::PRIMARY)
-                .command_buffer_count(1);
-            
-            let command_buffers = self.core.device
-                .allocate_command_buffers(&command_buffer_alloc_info)
-                .expect("Failed to allocate command buffer");
-            let command_buffer = command_buffers[0];
-            
-            let begin_info = vk::CommandBufferBeginInfo::default()
-                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-            
-            self.core.device
-                .begin_command_buffer(command_buffer, &begin_info)
-                .expect("Failed to begin command buffer");
-            
-            let copy_region = vk::BufferCopy::default()
-                .src_offset(0)
-                .dst_offset(0)
-                .size(vertex_size);
-            
-            self.core.device.cmd_copy_buffer(
-                command_buffer,
-                staging_buffer.0,
-                mesh.vertex_buffer,
-                &[copy_region],
-            );
-            
-            self.core.device
-                .end_command_buffer(command_buffer)
-                .expect("Failed to end command buffer");
-            
-            let command_buffers_to_submit = [command_buffer];
-            let submit_info = vk::SubmitInfo::default()
-                .command_buffers(&command_buffers_to_submit);
-            
-            self.core.device
-                .queue_submit(self.core.graphics_queue, &[submit_info], vk::Fence::null())
-                .expect("Failed to submit command buffer");
-            
-            self.core.device.queue_wait_idle(self.core.graphics_queue)
-                .expect("Failed to wait for queue");
-            
-            self.core.device.free_command_buffers(self.core.command_pool, &[command_buffer]);
-        }
+        Ok(())
     }
     
-    // Add a new pipeline with a given name
-    pub fn get_memory_stats(&self) -> String {
-        self.memory_pool.get_stats()
+    // Add a pipeline for meshes from add_mesh_instanced_with_colors, which reads Vertex at
+    // binding 0 and InstanceData at binding 1 (see shaders/mesh_instanced_color.vert)
+    pub fn add_instanced_color_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
+        self.add_instance_data_pipeline(name, vert_shader_path, frag_shader_path, front_face, false)
     }
     
-    pub fn add_pipeline(&mut self, name: &str, vert_shader_path: &str, frag_shader_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        self.add_pipeline_with_texture(name, vert_shader_path, frag_shader_path, false)
+    // add_instanced_color_pipeline for meshes with a texture array from set_mesh_texture_array,
+    // sampling each instance's layer (see shaders/mesh_instanced_texture_array.vert and .frag)
+    pub fn add_instanced_texture_array_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
+        self.add_instance_data_pipeline(name, vert_shader_path, frag_shader_path, front_face, true)
     }
     
-    // Add a new pipeline with optional texture support
-    pub fn add_pipeline_with_texture(&mut self, name: &str, vert_shader_path: &str, frag_shader_path: &str, has_texture: bool) -> Result<(), Box<dyn std::error::Error>> {
-        // Use default COUNTER_CLOCKWISE for compatibility
-        self.add_pipeline_with_texture_and_winding(name, vert_shader_path, frag_shader_path, has_texture, vk::FrontFace::COUNTER_CLOCKWISE)
+    fn add_instance_data_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        front_face: vk::FrontFace,
+        has_texture: bool,
+    ) -> Result<(), FloError> {
+        // Same MVP push constants as regular meshes, the model matrix comes from the instance
+        let mut vertex_attributes = Vertex::get_attribute_descriptions();
+        vertex_attributes.extend(InstanceData::get_attribute_descriptions());
+        let desc = MeshPipelineDesc {
+            vert_shader_path: vert_shader_path.to_string(),
+            frag_shader_path: frag_shader_path.to_string(),
+            vertex_bindings: vec![Vertex::get_binding_description(), InstanceData::get_binding_description()],
+            vertex_attributes,
+            has_texture,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face,
+            blend_mode: BlendMode::Opaque,
+            push_constant_size: MVP_PUSH_CONSTANTS_SIZE,
+            stencil: None,
+            antialiasing: CutoutAntialiasing::default(),
+            subpass: GEOMETRY_SUBPASS,
+        };
+        self.create_mesh_pipeline(name, &desc, false)?;
+        self.mesh_pipeline_descs.insert(name.to_string(), desc);
+        Ok(())
     }
     
-    // Add a new pipeline with optional texture support and custom winding order
-    pub fn add_pipeline_with_texture_and_winding(&mut self, name: &str, vert_shader_path: &str, frag_shader_path: &str, has_texture: bool, front_face: vk::FrontFace) -> Result<(), Box<dyn std::error::Error>> {
-        // Configure push constants for MVP matrices
+    // Add a pipeline for meshes from add_mesh_instanced_ssbo, which reads Vertex at binding 0
+    // and the instance matrices from a storage buffer in set 0 (see shaders/mesh_instanced_ssbo.vert)
+    pub fn add_instance_ssbo_pipeline(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
             .offset(0)
-            .size(208); // view (64) + proj (64) + model (64) + base_color (16)
-        
-        // Create descriptor set layout for texture if needed
-        let descriptor_set_layout = if has_texture {
-            let binding = vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-            
-            Some(create_descriptor_set_layout(&self.core.device, &[binding])?)
-        } else {
-            None
-        };
+            .size(MVP_PUSH_CONSTANTS_SIZE);
         
-        let mut builder = PipelineBuilder::new(
+        // Identically defined to the meshes' own layouts, so their sets are compatible with it.
+        // The pipeline layout doesn't need it once it's created.
+        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &[instance_matrix_layout_binding()])?;
+        let built = PipelineBuilder::new(
             self.core.device.clone(),
             vert_shader_path,
             frag_shader_path,
             self.core.swapchain_extent,
             self.core.render_pass,
-        )?
-        .with_vertex_input(vec![Vertex::get_binding_description()], Vertex::get_attribute_descriptions())
-        .with_push_constants(vec![push_constant_range])
-        .with_depth_test(self.has_depth)
-        .with_cull_mode(vk::CullModeFlags::BACK)
-        .with_front_face(front_face);
-        
-        // Add descriptor set layout if we have texture
-        if let Some(layout) = descriptor_set_layout {
-            builder = builder.with_descriptor_sets(vec![layout]);
+        )
+        .and_then(|builder| builder
+            .with_name(name)
+            .with_vertex_format::<Vertex>()
+            .with_push_constants(vec![push_constant_range])
+            .with_descriptor_sets(vec![descriptor_set_layout])
+            .with_depth_test(self.has_depth)
+            .with_cull_mode(vk::CullModeFlags::BACK)
+            .with_front_face(front_face)
+            .build());
+        unsafe {
+            leak_check::destroy(&self.core.device, descriptor_set_layout);
         }
+        let (graphics_pipeline, pipeline_layout) = built?;
         
-        let (graphics_pipeline, pipeline_layout) = builder.build()?;
-        
+        self.mark_static_scene_dirty();
         self.pipelines.insert(name.to_string(), Pipeline {
             pipeline: graphics_pipeline,
             layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
         });
+        self.set_pipeline_debug_names(name);
         
         Ok(())
     }
@@ -2268,7 +6913,7 @@ impl VulkanRenderer {
         vert_shader_path: &str, 
         frag_shader_path: &str,
         mesh_data: &SkinnedMeshData,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), FloError> {
         // Store the skinned mesh data
         self.setup_skinned_mesh_resources(mesh_data, None)?;
         
@@ -2286,7 +6931,7 @@ impl VulkanRenderer {
         frag_shader_path: &str,
         mesh_data: &SkinnedMeshData,
         instance_positions: &[[f32; 3]],
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), FloError> {
         // Store the skinned mesh data with instancing
         self.setup_skinned_mesh_resources(mesh_data, Some(instance_positions))?;
         
@@ -2302,13 +6947,98 @@ impl VulkanRenderer {
         name: &str,
         vert_shader_path: &str,
         frag_shader_path: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), FloError> {
+        // No culling so the water surface is visible from below too
+        self.add_fluid_pipeline_with_culling(name, vert_shader_path, frag_shader_path, vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+    }
+    
+    // Add a fluid rendering pipeline with custom push constants, cull mode and winding order
+    pub fn add_fluid_pipeline_with_culling(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
+        self.add_fluid_pipeline_with_layout(name, vert_shader_path, frag_shader_path, cull_mode, front_face, MeshLayout::Interleaved)
+    }
+    
+    // Add a fluid rendering pipeline for meshes added with the given layout
+    pub fn add_fluid_pipeline_with_layout(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+        layout: MeshLayout,
+    ) -> Result<(), FloError> {
+        self.add_fluid_pipeline_with_topology(name, vert_shader_path, frag_shader_path, cull_mode, front_face, layout, MeshTopology::TriangleList)
+    }
+    
+    // Add a fluid rendering pipeline for meshes added with the given layout and topology,
+    // e.g. a water grid built from triangle strips
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_fluid_pipeline_with_topology(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+        layout: MeshLayout,
+        topology: MeshTopology,
+    ) -> Result<(), FloError> {
+        let pipeline = self.build_fluid_pipeline(name, vert_shader_path, frag_shader_path, cull_mode, front_face, layout, topology, Vec::new())?;
         
-        // Configure push constants for fluid rendering
+        // Store the pipeline
+        self.mark_static_scene_dirty();
+        self.pipelines.insert(name.to_string(), pipeline);
+        self.set_pipeline_debug_names(name);
+        
+        // Water reflections draw with a twin of this pipeline, rebuild it to match
+        if name == "water" {
+            self.water_pipeline_desc = Some(WaterPipelineDesc {
+                vert_shader_path: vert_shader_path.to_string(),
+                cull_mode,
+                front_face,
+                layout,
+                topology,
+            });
+            if let Some(quality) = self.water_reflections.as_ref().map(|reflections| reflections.quality) {
+                self.enable_water_reflections(quality)?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    fn build_fluid_pipeline(
+        &self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+        layout: MeshLayout,
+        topology: MeshTopology,
+        descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    ) -> Result<Pipeline, FloError> {
+        // Enable blending for water pipeline
+        let blend_mode = if name == "water" { BlendMode::AlphaBlend } else { BlendMode::Opaque };
+        
+        // Configure push constants for fluid rendering, the sky also gets its gradient
+        let push_constant_size = if name == "sky" {
+            std::mem::size_of::<SkyPushConstants>()
+        } else {
+            std::mem::size_of::<PushConstants>()
+        };
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
             .offset(0)
-            .size(std::mem::size_of::<PushConstants>() as u32);
+            .size(push_constant_size as u32);
         
         // Build the pipeline with fluid-specific configuration
         let mut builder = PipelineBuilder::new(
@@ -2320,55 +7050,237 @@ impl VulkanRenderer {
         )?;
         
         // Configure vertex input for basic water/wall meshes
-        let binding_description = vk::VertexInputBindingDescription::default()
-            .binding(0)
-            .stride(std::mem::size_of::<Vertex>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX);
-        
-        let attribute_descriptions = vec![
-            vk::VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(0)
-                .format(vk::Format::R32G32B32_SFLOAT)
-                .offset(offset_of!(Vertex, position) as u32),
-            vk::VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(1)
-                .format(vk::Format::R32G32B32_SFLOAT)
-                .offset(offset_of!(Vertex, normal) as u32),
-            vk::VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(2)
-                .format(vk::Format::R32G32_SFLOAT)
-                .offset(offset_of!(Vertex, uv) as u32),
-        ];
+        let (binding_descriptions, attribute_descriptions) = fluid_vertex_input(layout);
+        let two_sided_lighting = cull_mode == vk::CullModeFlags::NONE;
+        let specialization = two_sided_lighting_specialization(two_sided_lighting);
         
         builder = builder
-            .with_vertex_input(vec![binding_description], attribute_descriptions)
+            .with_name(name)
+            .with_vertex_input(binding_descriptions, attribute_descriptions)
             .with_push_constants(vec![push_constant_range])
+            .with_descriptor_sets(descriptor_set_layouts)
+            .with_specialization(vk::ShaderStageFlags::FRAGMENT, &specialization)
             .with_depth_test(true)
-            .with_cull_mode(vk::CullModeFlags::NONE) // No culling for water
-            .with_alpha_blending(name == "water"); // Enable blending for water pipeline
+            .with_cull_mode(cull_mode)
+            .with_front_face(front_face)
+            .with_topology(topology.to_vk())
+            .with_blend_mode(blend_mode);
         
         let (pipeline, layout) = builder.build()?;
+        Ok(Pipeline {
+            pipeline,
+            layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting,
+        })
+    }
+    
+    // Water pipeline that subdivides the surface on the GPU so close up waves don't look faceted,
+    // displaced by the red channel of an optional height texture. Triangles are split until their
+    // edges are about WaterTessellation::target_edge_pixels long on screen (see set_water_tessellation).
+    // Without the tessellationShader feature the regular flat water pipeline is added under the
+    // same name instead. Returns whether the tessellated pipeline is in use.
+    pub fn add_tessellated_water_pipeline(
+        &mut self,
+        name: &str,
+        height_texture_path: Option<&str>,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+        layout: MeshLayout,
+    ) -> Result<bool, FloError> {
+        if !tessellation_supported(&self.core.instance, self.core.physical_device) {
+            warn!("Tessellation shaders not supported, using flat water for '{}'", name);
+            self.add_fluid_pipeline_with_layout(name, "shaders/water.vert.spv", "shaders/water.frag.spv", cull_mode, front_face, layout)?;
+            return Ok(false);
+        }
         
-        // Store the pipeline
+        // A black texture displaces nothing, so the shaders don't need a separate path without one
+        let height_texture = match height_texture_path {
+            Some(path) => Texture::from_file(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                self.core.command_pool,
+                self.core.graphics_queue,
+                path,
+                ColorSpace::Linear,
+            )?,
+            None => Texture::create(
+                &self.core.instance,
+                &self.core.device,
+                self.core.physical_device,
+                self.core.command_pool,
+                self.core.graphics_queue,
+                &TextureData::new(vec![0, 0, 0, 255], 1, 1),
+                ColorSpace::Linear,
+            )?,
+        };
+        let sampler = crate::vulkan_common::create_texture_sampler(&self.core.instance, &self.core.device, self.core.physical_device, self.core.max_anisotropy, SamplerAddressModes::default())?;
+        
+        // The height texture is sampled in the evaluation shader
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::TESSELLATION_EVALUATION);
+        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &[binding])?;
+        
+        let pool_size = vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(self.core.swapchain_images.len() as u32);
+        let descriptor_pool = create_descriptor_pool(&self.core.device, self.core.swapchain_images.len() as u32, &[pool_size])?;
+        
+        let layouts = vec![descriptor_set_layout; self.core.swapchain_images.len()];
+        let descriptor_sets = allocate_descriptor_sets(&self.core.device, descriptor_pool, &layouts)?;
+        for &descriptor_set in &descriptor_sets {
+            update_descriptor_sets_texture(&self.core.device, descriptor_set, height_texture.view, sampler, 0);
+        }
+        
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(WATER_TESSELLATION_STAGES)
+            .offset(0)
+            .size(std::mem::size_of::<WaterTessellationPushConstants>() as u32);
+        
+        let (binding_descriptions, attribute_descriptions) = fluid_vertex_input(layout);
+        let two_sided_lighting = cull_mode == vk::CullModeFlags::NONE;
+        let specialization = two_sided_lighting_specialization(two_sided_lighting);
+        
+        let (pipeline, pipeline_layout) = PipelineBuilder::new(
+            self.core.device.clone(),
+            "shaders/water_tess.vert.spv",
+            "shaders/water.frag.spv",
+            self.core.swapchain_extent,
+            self.core.render_pass,
+        )?
+            .with_name(name)
+            .with_tessellation("shaders/water.tesc.spv", "shaders/water.tese.spv", 3)?
+            .with_vertex_input(binding_descriptions, attribute_descriptions)
+            .with_push_constants(vec![push_constant_range])
+            .with_specialization(vk::ShaderStageFlags::FRAGMENT, &specialization)
+            .with_descriptor_sets(vec![descriptor_set_layout])
+            .with_depth_test(true)
+            .with_cull_mode(cull_mode)
+            .with_front_face(front_face)
+            .with_blend_mode(BlendMode::AlphaBlend)
+            .build()?;
+        
+        self.mark_static_scene_dirty();
         self.pipelines.insert(
             name.to_string(),
             Pipeline {
                 pipeline,
-                layout,
+                layout: pipeline_layout,
+                push_constant_ranges: vec![push_constant_range],
+                blend_mode: BlendMode::AlphaBlend,
+                skinning_mode: SkinningMode::LinearBlend,
+                two_sided_lighting,
+            },
+        );
+        self.textured_pipelines.insert(
+            name.to_string(),
+            TexturedPipelineResources {
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_sets,
+                texture: Some((height_texture, sampler)),
             },
         );
+        self.water_tessellation.insert(name.to_string(), WaterTessellation::default());
+        self.set_pipeline_debug_names(name);
+        
+        Ok(true)
+    }
+    
+    // Whether the pipeline draws both sides of its triangles and lights back faces from their
+    // own side. Fluid and water pipelines do when added with CullModeFlags::NONE, the water
+    // shader then flips the normal of back faces so the surface seen from below isn't lit as if
+    // from above. The front face passed along with the cull mode should be the top's winding.
+    // So do the double sided variants of mesh pipelines, see set_mesh_double_sided.
+    pub fn is_two_sided_lighting(&self, pipeline_name: &str) -> bool {
+        self.pipelines.get(pipeline_name).is_some_and(|pipeline| pipeline.two_sided_lighting)
+    }
+    
+    // Tune a pipeline added with add_tessellated_water_pipeline, ignored for other pipelines
+    pub fn set_water_tessellation(&mut self, name: &str, tessellation: WaterTessellation) {
+        if let Some(current) = self.water_tessellation.get_mut(name) {
+            *current = tessellation;
+        }
+    }
+    
+    // Reflect the scene in the water instead of only the procedural sky. Each render_frame_water
+    // first draws the sky and the non water fluid meshes from the camera mirrored across the
+    // water level into a target at the quality's fraction of the render resolution, then
+    // "water" meshes sample it at their mirrored screen position, offset by the surface normal.
+    // Needs the "water" fluid pipeline, tessellated water keeps reflecting the procedural sky.
+    pub fn enable_water_reflections(&mut self, quality: WaterReflectionQuality) -> Result<(), FloError> {
+        let Some(desc) = self.water_pipeline_desc.clone() else {
+            return Err("Add the \"water\" fluid pipeline before enabling water reflections".into());
+        };
+        self.disable_water_reflections();
         
+        let target = WaterReflections::create_target(
+            &self.core.instance,
+            &self.core.device,
+            self.core.physical_device,
+            HDR_FORMAT,
+            self.core.depth_format,
+            self.core.render_extent,
+            quality,
+        )?;
+        let pipeline = self.build_fluid_pipeline(
+            "water",
+            &desc.vert_shader_path,
+            WATER_REFLECTION_FRAG_SHADER_PATH,
+            desc.cull_mode,
+            desc.front_face,
+            desc.layout,
+            desc.topology,
+            vec![target.descriptor_set_layout],
+        );
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                target.destroy(&self.core.device);
+                return Err(e);
+            }
+        };
+        self.core.set_debug_name(target.color_image, "water reflection color image");
+        self.core.set_debug_name(pipeline.pipeline, "water reflection pipeline");
+        
+        self.water_reflections = Some(WaterReflections {
+            quality,
+            target,
+            pipeline: pipeline.pipeline,
+            layout: pipeline.layout,
+        });
+        self.mark_static_scene_dirty();
         Ok(())
     }
     
-    // Add a wall pipeline with stone wall textures
-    pub fn add_wall_pipeline_with_textures(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn disable_water_reflections(&mut self) {
+        if let Some(reflections) = self.water_reflections.take() {
+            // Frames in flight may still draw with it
+            unsafe {
+                let _ = self.core.device.queue_wait_idle(self.core.graphics_queue);
+            }
+            reflections.destroy(&self.core.device);
+            self.mark_static_scene_dirty();
+        }
+    }
+    
+    pub fn water_reflection_quality(&self) -> Option<WaterReflectionQuality> {
+        self.water_reflections.as_ref().map(|reflections| reflections.quality)
+    }
+    
+    // Add a wall pipeline with stone wall textures.
+    // Walls are closed or one-sided geometry, so BACK culling skips shading faces nobody sees.
+    // Only the base color is sRGB, the normal, roughness and AO maps are sampled as linear data.
+    pub fn add_wall_pipeline_with_textures(&mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Result<(), FloError> {
         
         // Load the stone wall textures
-        println!("Loading wall textures...");
+        info!("Loading wall textures");
         let wall_base_color = Texture::from_file(
             &self.core.instance,
             &self.core.device,
@@ -2376,6 +7288,7 @@ impl VulkanRenderer {
             self.core.command_pool,
             self.core.graphics_queue,
             "assets/Stone Wall/Stone_Wall_basecolor.jpg",
+            ColorSpace::Srgb,
         )?;
         
         let wall_normal = Texture::from_file(
@@ -2385,6 +7298,7 @@ impl VulkanRenderer {
             self.core.command_pool,
             self.core.graphics_queue,
             "assets/Stone Wall/Stone_Wall_normal.jpg",
+            ColorSpace::Linear,
         )?;
         
         let wall_roughness = Texture::from_file(
@@ -2394,6 +7308,7 @@ impl VulkanRenderer {
             self.core.command_pool,
             self.core.graphics_queue,
             "assets/Stone Wall/Stone_Wall_roughness.jpg",
+            ColorSpace::Linear,
         )?;
         
         let wall_ao = Texture::from_file(
@@ -2403,9 +7318,11 @@ impl VulkanRenderer {
             self.core.command_pool,
             self.core.graphics_queue,
             "assets/Stone Wall/Stone_Wall_ambientOcclusion.jpg",
+            ColorSpace::Linear,
         )?;
         
-        let sampler = crate::texture::create_texture_sampler(&self.core.device)?;
+        // Wall UVs run past 1 to tile the stone, like create_scaled_uv_cuboid's in fluid_sim_bevy
+        let sampler = crate::vulkan_common::create_texture_sampler(&self.core.instance, &self.core.device, self.core.physical_device, self.core.max_anisotropy, SamplerAddressModes::REPEAT)?;
         
         let textures = vec![&wall_base_color, &wall_normal, &wall_roughness, &wall_ao];
         
@@ -2415,6 +7332,8 @@ impl VulkanRenderer {
             "shaders/wall.frag.spv",
             textures,
             sampler,
+            cull_mode,
+            front_face,
         )
     }
     
@@ -2426,7 +7345,9 @@ impl VulkanRenderer {
         frag_shader_path: &str,
         textures: Vec<&Texture>,
         sampler: vk::Sampler,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+    ) -> Result<(), FloError> {
         
         // Configure push constants for fluid rendering
         let push_constant_range = vk::PushConstantRange::default()
@@ -2449,9 +7370,9 @@ impl VulkanRenderer {
         let descriptor_set_layout_info = vk::DescriptorSetLayoutCreateInfo::default()
             .bindings(&bindings);
         
-        let descriptor_set_layout = unsafe {
+        let descriptor_set_layout = leak_check::created(&self.core.device, unsafe {
             self.core.device.create_descriptor_set_layout(&descriptor_set_layout_info, None)?
-        };
+        });
         
         // Create descriptor pool
         let pool_size = vk::DescriptorPoolSize::default()
@@ -2463,9 +7384,9 @@ impl VulkanRenderer {
             .pool_sizes(&pool_sizes)
             .max_sets(self.core.swapchain_images.len() as u32);
         
-        let descriptor_pool = unsafe {
+        let descriptor_pool = leak_check::created(&self.core.device, unsafe {
             self.core.device.create_descriptor_pool(&pool_info, None)?
-        };
+        });
         
         // Create descriptor sets
         let layouts = vec![descriptor_set_layout; self.core.swapchain_images.len()];
@@ -2513,7 +7434,8 @@ impl VulkanRenderer {
             frag_shader_path,
             self.core.swapchain_extent,
             self.core.render_pass,
-        )?;
+        )?
+        .with_name(name);
         
         // Configure vertex input for basic water/wall meshes
         let binding_description = vk::VertexInputBindingDescription::default()
@@ -2544,16 +7466,22 @@ impl VulkanRenderer {
             .with_push_constants(vec![push_constant_range])
             .with_descriptor_sets(vec![descriptor_set_layout])
             .with_depth_test(true)
-            .with_cull_mode(vk::CullModeFlags::NONE); // No culling for walls to see all sides
+            .with_cull_mode(cull_mode)
+            .with_front_face(front_face);
         
         let (pipeline, layout) = builder.build()?;
         
         // Store the pipeline with descriptor resources
+        self.mark_static_scene_dirty();
         self.pipelines.insert(
             name.to_string(),
             Pipeline {
                 pipeline,
                 layout,
+                push_constant_ranges: vec![push_constant_range],
+                blend_mode: BlendMode::Opaque,
+                skinning_mode: SkinningMode::LinearBlend,
+                two_sided_lighting: false,
             },
         );
         
@@ -2564,37 +7492,51 @@ impl VulkanRenderer {
                 descriptor_set_layout,
                 descriptor_pool,
                 descriptor_sets,
+                texture: None,
             },
         );
+        self.set_pipeline_debug_names(name);
         
         Ok(())
     }
     
-    // Add a skinned pipeline with the correct descriptor set layout for joint and camera uniforms
+    // Add a skinned pipeline with the correct descriptor set layout for joint and camera uniforms.
+    // joint_count is the joint count of the meshes drawn with it (SkinnedMeshData::joint_matrices),
+    // rigs with a different count need their own pipeline.
     pub fn add_skinned_pipeline(
         &mut self,
         name: &str,
         vert_shader_path: &str,
         frag_shader_path: &str,
         use_instancing: bool,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        joint_count: usize,
+    ) -> Result<(), FloError> {
+        self.add_skinned_pipeline_with_skinning_mode(
+            name,
+            vert_shader_path,
+            frag_shader_path,
+            use_instancing,
+            joint_count,
+            SkinningMode::LinearBlend,
+        )
+    }
+    
+    // Same as add_skinned_pipeline with a choice of joint blending. Meshes drawn with it get their
+    // joint matrices converted to the mode's format whenever they're written.
+    pub fn add_skinned_pipeline_with_skinning_mode(
+        &mut self,
+        name: &str,
+        vert_shader_path: &str,
+        frag_shader_path: &str,
+        use_instancing: bool,
+        joint_count: usize,
+        skinning_mode: SkinningMode,
+    ) -> Result<(), FloError> {
         // Create descriptor set layout for skinned meshes
-        // Binding 0: Joint matrices uniform buffer
-        // Binding 1: Camera matrices uniform buffer
-        let bindings = vec![
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .descriptor_count(1)
-                .stage_flags(vk::ShaderStageFlags::VERTEX),
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(1)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .descriptor_count(1)
-                .stage_flags(vk::ShaderStageFlags::VERTEX),
-        ];
+        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &skinned_descriptor_set_layout_bindings())?;
         
-        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &bindings)?;
+        let joints_in_storage = joints_in_storage_buffer(&self.core.instance, self.core.physical_device, joint_count);
+        let specialization = skinned_vertex_specialization(joint_count, joints_in_storage, skinning_mode);
         
         // Create pipeline with skinned vertex format
         let mut builder = PipelineBuilder::new(
@@ -2603,7 +7545,8 @@ impl VulkanRenderer {
             frag_shader_path,
             self.core.swapchain_extent,
             self.core.render_pass,
-        )?;
+        )?
+        .with_name(name);
         
         // Configure for skinned vertex format with instancing
         if use_instancing {
@@ -2706,15 +7649,15 @@ impl VulkanRenderer {
         }
         
         // Configure push constants for time
-        builder = builder.with_push_constants(vec![
-            vk::PushConstantRange::default()
-                .stage_flags(vk::ShaderStageFlags::VERTEX)
-                .offset(0)
-                .size(4), // Just a float for time
-        ]);
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(4); // Just a float for time
+        builder = builder.with_push_constants(vec![push_constant_range]);
         
         // Set descriptor set layout
         builder = builder.with_descriptor_sets(vec![descriptor_set_layout])
+            .with_specialization(vk::ShaderStageFlags::VERTEX, &specialization)
             .with_depth_test(true)
             .with_cull_mode(vk::CullModeFlags::BACK)
             .with_front_face(vk::FrontFace::COUNTER_CLOCKWISE);
@@ -2723,13 +7666,19 @@ impl VulkanRenderer {
         let (pipeline, layout) = builder.build()?;
         
         // Store the pipeline
+        self.mark_static_scene_dirty();
         self.pipelines.insert(
             name.to_string(),
             Pipeline {
                 pipeline,
                 layout,
+                push_constant_ranges: vec![push_constant_range],
+                blend_mode: BlendMode::Opaque,
+                skinning_mode,
+                two_sided_lighting: false,
             },
         );
+        self.set_pipeline_debug_names(name);
         
         Ok(())
     }
@@ -2739,7 +7688,7 @@ impl VulkanRenderer {
         &mut self,
         mesh_data: &SkinnedMeshData,
         instance_positions: Option<&[[f32; 3]]>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), FloError> {
         // Create vertex buffer for skinned mesh
         let (vertex_buffer, vertex_buffer_memory) = create_vertex_buffer(
             &self.core.instance,
@@ -2760,15 +7709,14 @@ impl VulkanRenderer {
             &mesh_data.indices,
         )?;
         
-        // Create uniform buffers for joints and camera
-        let joint_buffer_size = (std::mem::size_of::<Mat4>() * 128) as vk::DeviceSize; // Support up to 128 joints
-        let (joint_uniform_buffer, joint_uniform_memory) = create_buffer(
+        // Create buffers for joints and camera, the joint buffer holds the initial joint matrices
+        let joint_count = mesh_data.joint_matrices.len();
+        let joints_in_storage = joints_in_storage_buffer(&self.core.instance, self.core.physical_device, joint_count);
+        let (joint_uniform_buffer, joint_uniform_memory) = create_joint_buffer(
             &self.core.instance,
             &self.core.device,
-            self.core.physical_device,
-            joint_buffer_size,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.core.physical_device,
+            &mesh_data.joint_matrices,
         )?;
         
         let camera_buffer_size = (std::mem::size_of::<Mat4>() * 2) as vk::DeviceSize; // view + proj matrices
@@ -2814,36 +7762,18 @@ impl VulkanRenderer {
         };
         
         // Create descriptor pool and sets for uniforms
-        let pool_sizes = vec![
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: self.core.swapchain_images.len() as u32 * 2, // joints + camera
-            },
-        ];
+        let pool_sizes = skinned_descriptor_pool_sizes(self.core.swapchain_images.len() as u32);
         
         let pool_info = vk::DescriptorPoolCreateInfo::default()
             .pool_sizes(&pool_sizes)
             .max_sets(self.core.swapchain_images.len() as u32);
         
-        let descriptor_pool = unsafe {
+        let descriptor_pool = leak_check::created(&self.core.device, unsafe {
             self.core.device.create_descriptor_pool(&pool_info, None)?
-        };
+        });
         
         // Create descriptor set layout
-        let bindings = vec![
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .stage_flags(vk::ShaderStageFlags::VERTEX),
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(1)
-                .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .stage_flags(vk::ShaderStageFlags::VERTEX),
-        ];
-        
-        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &bindings)?;
+        let descriptor_set_layout = create_descriptor_set_layout(&self.core.device, &skinned_descriptor_set_layout_bindings())?;
         
         // Create descriptor sets
         let layouts = vec![descriptor_set_layout; self.core.swapchain_images.len()];
@@ -2856,35 +7786,26 @@ impl VulkanRenderer {
         };
         
         // Update descriptor sets
-        for set in &descriptor_sets {
-            let joint_buffer_info = vk::DescriptorBufferInfo::default()
-                .buffer(joint_uniform_buffer)
-                .offset(0)
-                .range(joint_buffer_size);
-            
-            let camera_buffer_info = vk::DescriptorBufferInfo::default()
-                .buffer(camera_uniform_buffer)
-                .offset(0)
-                .range(camera_buffer_size);
-            
-            let descriptor_writes = vec![
-                vk::WriteDescriptorSet::default()
-                    .dst_set(*set)
-                    .dst_binding(0)
-                    .dst_array_element(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(std::slice::from_ref(&joint_buffer_info)),
-                vk::WriteDescriptorSet::default()
-                    .dst_set(*set)
-                    .dst_binding(1)
-                    .dst_array_element(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(std::slice::from_ref(&camera_buffer_info)),
-            ];
-            
-            unsafe {
-                self.core.device.update_descriptor_sets(&descriptor_writes, &[]);
-            }
+        write_skinned_descriptor_sets(
+            &self.core.device,
+            &descriptor_sets,
+            joint_uniform_buffer,
+            joint_count,
+            joints_in_storage,
+            camera_uniform_buffer,
+            camera_buffer_size,
+            None,
+        );
+        
+        self.core.set_debug_name(vertex_buffer, "skinned mesh vertex buffer");
+        self.core.set_debug_name(index_buffer, "skinned mesh index buffer");
+        self.core.set_debug_name(joint_uniform_buffer, "skinned mesh joint buffer");
+        self.core.set_debug_name(camera_uniform_buffer, "skinned mesh camera uniform buffer");
+        if let Some(instance_buffer) = instance_buffer {
+            self.core.set_debug_name(instance_buffer, "skinned mesh instance buffer");
+        }
+        for (i, &set) in descriptor_sets.iter().enumerate() {
+            self.core.set_debug_name(set, &format!("skinned mesh descriptor set[{}]", i));
         }
         
         // Store the resources
@@ -2896,6 +7817,7 @@ impl VulkanRenderer {
             index_count: mesh_data.indices.len() as u32,
             joint_uniform_buffer,
             joint_uniform_memory,
+            joint_count,
             camera_uniform_buffer,
             camera_uniform_memory,
             descriptor_pool,
@@ -2907,9 +7829,6 @@ impl VulkanRenderer {
             use_instancing: instance_positions.is_some(),
         });
         
-        // Update the initial joint matrices
-        self.update_joint_matrices(&mesh_data.joint_matrices);
-        
         Ok(())
     }
     
@@ -2920,12 +7839,15 @@ impl VulkanRenderer {
         vert_shader_path: &str,
         frag_shader_path: &str,
         use_instancing: bool,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Get the descriptor set layout from skinned mesh resources
-        let descriptor_set_layout = self.skinned_mesh
+    ) -> Result<(), FloError> {
+        // Get the descriptor set layout and joint count from skinned mesh resources
+        let skinned = self.skinned_mesh
             .as_ref()
-            .ok_or("Skinned mesh resources not initialized")?
-            .descriptor_set_layout;
+            .ok_or("Skinned mesh resources not initialized")?;
+        let descriptor_set_layout = skinned.descriptor_set_layout;
+        let joint_count = skinned.joint_count;
+        let joints_in_storage = joints_in_storage_buffer(&self.core.instance, self.core.physical_device, joint_count);
+        let specialization = skinned_vertex_specialization(joint_count, joints_in_storage, SkinningMode::LinearBlend);
         
         // Configure push constants for model matrix only (view/proj in uniforms)
         let push_constant_range = vk::PushConstantRange::default()
@@ -2940,7 +7862,9 @@ impl VulkanRenderer {
             self.core.swapchain_extent,
             self.core.render_pass,
         )?
-        .with_vertex_input(vec![SkinnedVertex::get_binding_description()], SkinnedVertex::get_attribute_descriptions())
+        .with_name(name)
+        .with_vertex_format::<SkinnedVertex>()
+        .with_specialization(vk::ShaderStageFlags::VERTEX, &specialization)
         .with_push_constants(vec![push_constant_range])
         .with_descriptor_sets(vec![descriptor_set_layout])
         .with_depth_test(self.has_depth)
@@ -2963,8 +7887,8 @@ impl VulkanRenderer {
                 .offset(0);
             
             // Get existing vertex bindings and attributes
-            let existing_bindings = vec![SkinnedVertex::get_binding_description()];
-            let existing_attributes = SkinnedVertex::get_attribute_descriptions();
+            let existing_bindings = vec![SkinnedVertex::binding_description()];
+            let existing_attributes = SkinnedVertex::attribute_descriptions();
             
             // Combine with instance data
             let mut all_bindings = existing_bindings;
@@ -2981,51 +7905,771 @@ impl VulkanRenderer {
                 self.core.swapchain_extent,
                 self.core.render_pass,
             )?
+            .with_name(name)
             .with_vertex_input(all_bindings, all_attributes)
+            .with_specialization(vk::ShaderStageFlags::VERTEX, &specialization)
             .with_push_constants(vec![push_constant_range])
             .with_descriptor_sets(vec![descriptor_set_layout])
             .with_depth_test(self.has_depth)
             .with_cull_mode(vk::CullModeFlags::BACK)
             .with_front_face(vk::FrontFace::COUNTER_CLOCKWISE);
         }
-        
-        let (graphics_pipeline, pipeline_layout) = builder.build()?;
-        
-        self.pipelines.insert(name.to_string(), Pipeline {
-            pipeline: graphics_pipeline,
-            layout: pipeline_layout,
-        });
-        
+        
+        let (graphics_pipeline, pipeline_layout) = builder.build()?;
+        
+        self.mark_static_scene_dirty();
+        self.pipelines.insert(name.to_string(), Pipeline {
+            pipeline: graphics_pipeline,
+            layout: pipeline_layout,
+            push_constant_ranges: vec![push_constant_range],
+            blend_mode: BlendMode::Opaque,
+            skinning_mode: SkinningMode::LinearBlend,
+            two_sided_lighting: false,
+        });
+        self.set_pipeline_debug_names(name);
+        
+        Ok(())
+    }
+    
+    // Switch to a different pipeline (alias for set_current_pipeline for compatibility)
+    pub fn set_active_pipeline(&mut self, name: &str) -> Result<(), String> {
+        self.set_current_pipeline(name)
+    }
+    
+    // Switch to a different pipeline
+    pub fn set_current_pipeline(&mut self, name: &str) -> Result<(), String> {
+        if self.pipelines.contains_key(name) {
+            self.current_pipeline = name.to_string();
+            // Update the compatibility fields
+            if let Some(pipeline) = self.pipelines.get(name) {
+                self.graphics_pipeline = pipeline.pipeline;
+                self.pipeline_layout = pipeline.layout;
+            }
+            self.mark_static_scene_dirty();
+            Ok(())
+        } else {
+            Err(format!("Pipeline '{}' not found", name))
+        }
+    }
+
+    // Reverse-Z: depth clears to 0.0 and tests with GREATER_OR_EQUAL, which spreads float
+    // precision evenly over distance. Applies to all pipelines since the compare op is dynamic
+    // state. Projections must be built with projection_matrix (near/far swapped) to match.
+    pub fn set_reverse_z(&mut self, enabled: bool) {
+        self.reverse_z = enabled;
+        self.mark_static_scene_dirty();
+    }
+    
+    pub fn is_reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+    
+    // Static scene mode records the scene's draws once per swapchain image and replays them on
+    // later frames, re-recording only after the scene changes (meshes added, removed or moved,
+    // materials or pipelines changed) or the camera moves, since view and projection are push
+    // constants. Skinned, occlusion culled and GPU culled meshes are still recorded every frame
+    // and drawn after the static meshes. Applies to the multi-mesh render paths.
+    pub fn set_static_scene(&mut self, enabled: bool) -> Result<(), FloError> {
+        let image_count = self.core.command_buffers.len();
+        if enabled {
+            self.allocate_scene_command_buffers()?;
+        }
+        self.static_scene = enabled;
+        self.static_scene_recorded_with = vec![None; image_count];
+        self.static_scene_stats = vec![FrameStats::default(); image_count];
+        Ok(())
+    }
+    
+    // Secondary buffers for static scene mode and record_parallel, allocated once
+    fn allocate_scene_command_buffers(&mut self) -> Result<(), FloError> {
+        if !self.static_scene_command_buffers.is_empty() {
+            return Ok(());
+        }
+        let image_count = self.core.command_buffers.len();
+        let mut command_buffers = create_command_buffers_with_level(
+            &self.core.device,
+            self.core.command_pool,
+            image_count * 2,
+            vk::CommandBufferLevel::SECONDARY,
+        )?;
+        self.dynamic_scene_command_buffers = command_buffers.split_off(image_count);
+        self.static_scene_command_buffers = command_buffers;
+        Ok(())
+    }
+    
+    // Records draws on several threads, each into its own secondary command buffer from its own
+    // command pool, for scenes whose draw recording is CPU bound. 0..item_count is split into
+    // a contiguous chunk per thread (RendererConfig::record_threads) and record is called once
+    // per chunk with a RecordContext. The buffers are executed inside the scene pass of the next
+    // multi mesh frame, after the renderer's own draws; call this once per frame before rendering,
+    // a second call replaces the first's draws. Bind pipelines with RecordContext::bind_pipeline,
+    // secondaries don't inherit the depth compare op.
+    pub fn record_parallel<F>(&mut self, item_count: usize, record: F) -> Result<(), FloError>
+    where
+        F: Fn(&RecordContext) + Sync,
+    {
+        self.check_lost()?;
+        self.allocate_scene_command_buffers()?;
+        if self.parallel_recorder.is_none() {
+            let queue_family = self.core.queue_family_indices.graphics_family.unwrap();
+            self.parallel_recorder = Some(ParallelRecorder::new(&self.core.device, queue_family, self.config.record_threads)?);
+        }
+        
+        // The frame's pools are reset, so its last submission has to be done with them
+        let frame = self.core.current_frame;
+        unsafe {
+            self.core.device.wait_for_fences(&[self.core.in_flight_fences[frame]], true, u64::MAX)?;
+        }
+        let (viewport, scissor) = self.scene_viewport_and_scissor();
+        let target = SecondaryTarget {
+            render_pass: self.core.render_pass,
+            framebuffer: vk::Framebuffer::null(),
+            extent: self.core.render_extent,
+            viewport,
+            scissor,
+            depth_compare_op: self.depth_compare_op(),
+        };
+        self.parallel_draws = None;
+        let recorder = self.parallel_recorder.as_ref().unwrap();
+        let command_buffers = recorder.record(&self.core.device, frame, &target, item_count, record)?;
+        self.parallel_draws = Some((frame, command_buffers));
+        Ok(())
+    }
+    
+    pub fn is_static_scene(&self) -> bool {
+        self.static_scene
+    }
+    
+    // Draws the scene into a sub-rectangle of the window (in pixels) instead of the full extent,
+    // e.g. for split screen or a 3D view next to editor panels. Used by the multi mesh and fluid
+    // render paths, and their projections are corrected to the viewport's aspect ratio, so keep
+    // building them for the whole window. egui is still drawn over the full window.
+    pub fn set_viewport(&mut self, x: u32, y: u32, width: u32, height: u32) -> Result<(), FloError> {
+        let rect = Some(pixel_rect(x, y, width, height)?);
+        if self.viewport != rect {
+            self.viewport = rect;
+            self.mark_static_scene_dirty();
+        }
+        Ok(())
+    }
+    
+    // Clips the scene to a sub-rectangle in pixels, defaults to the viewport
+    pub fn set_scissor(&mut self, x: u32, y: u32, width: u32, height: u32) -> Result<(), FloError> {
+        let rect = Some(pixel_rect(x, y, width, height)?);
+        if self.scissor != rect {
+            self.scissor = rect;
+            self.mark_static_scene_dirty();
+        }
+        Ok(())
+    }
+    
+    // Goes back to drawing the scene over the full extent
+    pub fn reset_viewport(&mut self) {
+        self.viewport = None;
+        self.scissor = None;
+        self.mark_static_scene_dirty();
+    }
+    
+    // Draw some meshes again from another camera over the next multi mesh frame, e.g. a held
+    // tool or a 3D HUD. The pass runs after the scene pass in a render pass that loads the
+    // scene's color and depth instead of clearing them, so clear_depth draws the meshes over
+    // everything while false lets the scene hide them. Passes are drawn in the order they were
+    // queued and only by one frame, so queue them every frame they should show.
+    pub fn queue_overlay_pass(&mut self, view: Mat4, proj: Mat4, meshes: &[usize], clear_depth: bool) -> Result<(), FloError> {
+        let clear = if clear_depth { ClearFlags::DEPTH_STENCIL } else { ClearFlags::NONE };
+        self.queue_overlay_pass_with_clear(view, proj, meshes, clear)
+    }
+    
+    // Like queue_overlay_pass, choosing which of the scene's attachments the pass clears under
+    // the viewport before drawing, e.g. ClearFlags::COLOR | ClearFlags::DEPTH_STENCIL for an
+    // inset camera that replaces the scene there. Color is cleared to the clear color.
+    pub fn queue_overlay_pass_with_clear(&mut self, view: Mat4, proj: Mat4, meshes: &[usize], clear: ClearFlags) -> Result<(), FloError> {
+        if let Some(&mesh_index) = meshes.iter().find(|&&mesh_index| mesh_index >= self.meshes.len()) {
+            return Err(FloError::InvalidMeshIndex(mesh_index));
+        }
+        self.overlay_passes.push(OverlayPass { view, proj, meshes: meshes.to_vec(), clear });
+        Ok(())
+    }
+    
+    // Draws and clears the queued overlay passes, after the frame's scene pass has ended.
+    // Skinned meshes are left out since their camera lives in per-frame uniform buffers the
+    // scene pass already wrote.
+    fn record_overlay_passes(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+        occlusion_queries: &mut Vec<usize>,
+        stats: &mut FrameStats,
+    ) {
+        let passes = std::mem::take(&mut self.overlay_passes);
+        let full_extent = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.core.render_extent,
+        };
+        for pass in &passes {
+            let render_pass_info = vk::RenderPassBeginInfo::default()
+                .render_pass(self.core.load_render_pass)
+                .framebuffer(self.core.framebuffers[image_index as usize])
+                .render_area(full_extent);
+            unsafe {
+                self.core.device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+            }
+            self.set_scene_viewport(command_buffer);
+            
+            // Only under the viewport, so an inset camera leaves the rest of the scene
+            clear_attachments(
+                &self.core.device,
+                command_buffer,
+                pass.clear,
+                self.viewport.map_or(full_extent, |viewport| self.scale_to_render_extent(viewport)),
+                self.scene_clear_color(),
+                self.depth_clear_value(),
+                self.core.depth_format,
+            );
+            
+            let proj = self.viewport_projection(pass.proj);
+            let (draw_order, lighting_draws): (Vec<usize>, Vec<usize>) = plan_draw_order(&self.mesh_draw_infos(image_index as usize), pass.view)
+            .into_iter()
+            .filter(|mesh_idx| pass.meshes.contains(mesh_idx) && !self.meshes[*mesh_idx].is_skinned)
+            .partition(|&mesh_idx| self.meshes[mesh_idx].subpass == GEOMETRY_SUBPASS);
+            self.record_mesh_draws(command_buffer, image_index, pass.view, proj, &draw_order, occlusion_queries, stats);
+            
+            unsafe {
+                self.core.device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+            }
+            self.set_scene_viewport(command_buffer);
+            self.record_mesh_draws(command_buffer, image_index, pass.view, proj, &lighting_draws, occlusion_queries, stats);
+            
+            unsafe {
+                self.core.device.cmd_end_render_pass(command_buffer);
+            }
+        }
+    }
+    
+    // Sets the scene's viewport and scissor, the full extent unless set_viewport or set_scissor
+    // moved them
+    fn set_scene_viewport(&self, command_buffer: vk::CommandBuffer) {
+        let (viewport, scissor) = self.scene_viewport_and_scissor();
+        unsafe {
+            self.core.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.core.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+        }
+    }
+    
+    fn scene_viewport_and_scissor(&self) -> (vk::Viewport, vk::Rect2D) {
+        let full_extent = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.core.render_extent,
+        };
+        let viewport_rect = self.viewport.map_or(full_extent, |viewport| self.scale_to_render_extent(viewport));
+        let scissor = self.scissor.or(self.viewport).map_or(full_extent, |scissor| self.scale_to_render_extent(scissor));
+        let viewport = vk::Viewport {
+            x: viewport_rect.offset.x as f32,
+            y: viewport_rect.offset.y as f32,
+            width: viewport_rect.extent.width as f32,
+            height: viewport_rect.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        (viewport, scissor)
+    }
+    
+    // Viewports and scissors are given in swapchain pixels, the scene targets may be smaller
+    // (see set_resolution_scale)
+    fn scale_to_render_extent(&self, rect: vk::Rect2D) -> vk::Rect2D {
+        let swapchain = self.core.swapchain_extent;
+        let render = self.core.render_extent;
+        if swapchain == render {
+            return rect;
+        }
+        let scale_x = render.width as f32 / swapchain.width as f32;
+        let scale_y = render.height as f32 / swapchain.height as f32;
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: (rect.offset.x as f32 * scale_x).round() as i32,
+                y: (rect.offset.y as f32 * scale_y).round() as i32,
+            },
+            extent: vk::Extent2D {
+                width: ((rect.extent.width as f32 * scale_x).round() as u32).max(1),
+                height: ((rect.extent.height as f32 * scale_y).round() as u32).max(1),
+            },
+        }
+    }
+    
+    // Scales a projection built for the full extent's aspect ratio to the viewport's
+    fn viewport_projection(&self, proj: Mat4) -> Mat4 {
+        match self.viewport {
+            Some(viewport) => {
+                let extent = self.core.swapchain_extent;
+                let full_aspect = extent.width as f32 / extent.height as f32;
+                let viewport_aspect = viewport.extent.width as f32 / viewport.extent.height as f32;
+                Mat4::from_scale(Vec3::new(full_aspect / viewport_aspect, 1.0, 1.0)) * proj
+            }
+            None => proj,
+        }
+    }
+    
+    // Draw calls, triangles and culling counts of the last frame recorded by the multi mesh
+    // render path (render_frame_with_camera_multi, render_frame_with_egui, ...)
+    pub fn last_frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+    
+    // GPU time in milliseconds from the start to the end of a recent multi mesh frame, measured
+    // with timestamp queries. It's read back once that frame's queries come around again, so it
+    // trails the last rendered frame by MAX_FRAMES_IN_FLIGHT. None until then or when the
+    // graphics queue doesn't support timestamps.
+    pub fn last_gpu_frame_time_ms(&self) -> Option<f32> {
+        self.core.gpu_frame_time_ms
+    }
+    
+    // Caps the frame rate by sleeping after each present, whatever the present mode. None runs
+    // as fast as the present mode allows.
+    pub fn set_target_fps(&mut self, target_fps: Option<u32>) {
+        self.core.frame_pacer.set_target_fps(target_fps);
+    }
+    
+    pub fn target_fps(&self) -> Option<u32> {
+        self.core.frame_pacer.target_fps()
+    }
+    
+    // Target frame time next to the achieved one, to see whether the cap or the frame's own
+    // cost limits the frame rate
+    pub fn frame_pacing(&self) -> FramePacing {
+        self.core.frame_pacer.pacing()
+    }
+    
+    // Render the scene at a fraction of the swapchain resolution, clamped to
+    // MIN_RESOLUTION_SCALE..=MAX_RESOLUTION_SCALE, and scale it up to the swapchain in the
+    // present pass with the upscale filter. egui is drawn after upscaling, at full resolution.
+    // Recreates the scene targets, which waits for the device to go idle.
+    pub fn set_resolution_scale(&mut self, scale: f32) -> Result<(), FloError> {
+        let scale = scale.clamp(MIN_RESOLUTION_SCALE, MAX_RESOLUTION_SCALE);
+        self.set_scene_extent(dynamic_resolution::scaled_extent(self.core.swapchain_extent, scale))?;
+        self.resolution_scale = scale;
+        Ok(())
+    }
+    
+    // Recreates the swapchain at the window's new size, then the scene targets at the
+    // resolution scale of it. Call after the window was resized (FloRenderPlugin does), but not
+    // while it's minimized. Waits for the device to go idle.
+    pub fn resize(&mut self) -> Result<(), FloError> {
+        self.core.resize_swapchain()?;
+        self.set_scene_extent(dynamic_resolution::scaled_extent(self.core.swapchain_extent, self.resolution_scale))
+    }
+    
+    fn set_scene_extent(&mut self, extent: vk::Extent2D) -> Result<(), FloError> {
+        if extent != self.core.render_extent {
+            self.core.set_render_extent(extent)?;
+            if let Some(ref mut chain) = self.post_process {
+                chain.resize(&self.core.instance, &self.core.device, self.core.physical_device, &self.core.hdr_image_views, extent)?;
+            }
+            if let Some(ref mut reflections) = self.water_reflections {
+                reflections.resize(&self.core.instance, &self.core.device, self.core.physical_device, HDR_FORMAT, self.core.depth_format, extent)?;
+            }
+            if let Some(ref mut previous_frame) = self.previous_frame {
+                previous_frame.resize(
+                    &self.core.instance,
+                    &self.core.device,
+                    self.core.physical_device,
+                    self.core.command_pool,
+                    self.core.graphics_queue,
+                    HDR_FORMAT,
+                    extent,
+                )?;
+            }
+            // Static scene buffers were recorded against the old framebuffers
+            self.mark_static_scene_dirty();
+        }
+        Ok(())
+    }
+    
+    pub fn resolution_scale(&self) -> f32 {
+        self.resolution_scale
+    }
+    
+    // Size of the scene's framebuffers, the swapchain extent times the resolution scale
+    pub fn render_extent(&self) -> vk::Extent2D {
+        self.core.render_extent
+    }
+    
+    // Filter the present pass scales a reduced resolution scene up with. Takes effect next frame.
+    pub fn set_upscale_filter(&mut self, filter: UpscaleFilter) {
+        self.upscale_filter = filter;
+    }
+    
+    pub fn upscale_filter(&self) -> UpscaleFilter {
+        self.upscale_filter
+    }
+    
+    // Adjust the resolution scale every ADJUST_INTERVAL_FRAMES frames to keep the GPU frame time
+    // (last_gpu_frame_time_ms) under the target, or None to keep the current scale from now on.
+    // Only the multi mesh render path measures GPU time, without timestamp support the scale
+    // never changes.
+    pub fn set_dynamic_resolution(&mut self, settings: Option<DynamicResolution>) {
+        self.dynamic_resolution = settings;
+        self.frames_until_resolution_update = dynamic_resolution::ADJUST_INTERVAL_FRAMES;
+    }
+    
+    pub fn dynamic_resolution(&self) -> Option<DynamicResolution> {
+        self.dynamic_resolution
+    }
+    
+    // Called before each multi mesh frame begins, since changing the scale waits for the device
+    fn update_dynamic_resolution(&mut self) {
+        let Some(settings) = self.dynamic_resolution else {
+            return;
+        };
+        if self.frames_until_resolution_update > 0 {
+            self.frames_until_resolution_update -= 1;
+            return;
+        }
+        let Some(gpu_frame_ms) = self.core.gpu_frame_time_ms else {
+            return;
+        };
+        let scale = settings.next_scale(self.resolution_scale, gpu_frame_ms);
+        if scale != self.resolution_scale {
+            debug!("GPU frame took {:.2} ms, changing the resolution scale from {:.2} to {:.2}",
+                   gpu_frame_ms, self.resolution_scale, scale);
+            if let Err(e) = self.set_resolution_scale(scale) {
+                warn!("Failed to change the resolution scale: {}", e);
+            }
+            self.frames_until_resolution_update = dynamic_resolution::ADJUST_INTERVAL_FRAMES;
+        }
+    }
+    
+    // Makes every swapchain image re-record its static scene buffer on its next frame
+    fn mark_static_scene_dirty(&mut self) {
+        self.static_scene_recorded_with.fill(None);
+    }
+    
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.reverse_z { 0.0 } else { DEPTH_CLEAR_VALUE }
+    }
+    
+    pub fn depth_compare_op(&self) -> vk::CompareOp {
+        if self.reverse_z { vk::CompareOp::GREATER_OR_EQUAL } else { vk::CompareOp::LESS }
+    }
+    
+    // Vulkan perspective projection (Y flipped) matching the current depth convention.
+    // With reverse-Z, near and far are swapped so near maps to 1.0 and far to 0.0.
+    // An infinite far plane (Mat4::perspective_infinite_reverse_rh) works without extensions
+    // since depth stays in [0, 1]; VK_EXT_depth_range_unrestricted is only needed if depth
+    // values or clears have to go outside that range.
+    pub fn projection_matrix(&self, fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Mat4 {
+        Camera { fov_y, aspect_ratio, near, far, reverse_z: self.reverse_z, ..Default::default() }.projection_matrix()
+    }
+    
+    // Width over height of the swapchain. Read it when building each frame's projection instead
+    // of assuming a window shape, a scene viewport corrects it further (see set_scene_viewport).
+    pub fn aspect_ratio(&self) -> f32 {
+        let extent = self.core.swapchain_extent;
+        extent.width as f32 / extent.height.max(1) as f32
+    }
+    
+    // projection_matrix with the swapchain's aspect_ratio
+    pub fn perspective(&self, fov_y: f32, near: f32, far: f32) -> Mat4 {
+        self.projection_matrix(fov_y, self.aspect_ratio(), near, far)
+    }
+    
+    // Shaders write linear color into the HDR scene image, the present pass applies exposure
+    // and this curve, and the sRGB swapchain encodes the result. Takes effect next frame.
+    pub fn set_tonemap(&mut self, tonemap: Tonemap) {
+        self.tonemap = tonemap;
+    }
+    
+    pub fn get_tonemap(&self) -> Tonemap {
+        self.tonemap
+    }
+    
+    // Background the scene pass clears to, as an sRGB color like a color picker gives. It's
+    // converted to linear for the HDR scene image, so it shows as picked with the default
+    // tonemap and exposure. Applies to render targets too.
+    pub fn set_clear_color(&mut self, srgb: [f32; 4]) {
+        self.clear_color = crate::texture::srgb_to_linear(srgb);
+        self.mark_static_scene_dirty();
+    }
+    
+    // Clear color of multi mesh frames, black in debug views so overdraw counts start at zero
+    fn scene_clear_color(&self) -> [f32; 4] {
+        match self.debug_view {
+            DebugView::None => self.clear_color,
+            _ => [0.0; 4],
+        }
+    }
+    
+    // Linear multiplier on scene color before tonemapping, 1.0 leaves it unchanged
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure.max(0.0);
+    }
+    
+    pub fn get_exposure(&self) -> f32 {
+        self.exposure
+    }
+    
+    // Time pushed to animated shaders (water, skinned meshes), scaled and stopped while paused
+    pub fn get_elapsed_time(&self) -> f32 {
+        self.core.get_elapsed_time()
+    }
+    
+    // Freezes shader animations, e.g. for screenshots. Resuming continues from the same time.
+    pub fn pause_time(&mut self) {
+        self.core.pause();
+    }
+    
+    pub fn resume_time(&mut self) {
+        self.core.resume();
+    }
+    
+    pub fn is_time_paused(&self) -> bool {
+        self.core.clock.is_paused()
+    }
+    
+    // 0.5 plays shader animations at half speed, negative scales are clamped to 0
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.core.set_time_scale(scale);
+    }
+    
+    pub fn get_time_scale(&self) -> f32 {
+        self.core.clock.scale()
+    }
+    
+    pub fn reset_time(&mut self) {
+        self.core.reset_time();
+    }
+    
+    // Check a push against the ranges the pipeline layout declared and the device limit
+    pub fn validate_push_constants(&self, pipeline_name: &str, stages: vk::ShaderStageFlags, offset: u32, size: usize) -> Result<(), String> {
+        let pipeline = self.pipelines.get(pipeline_name)
+            .ok_or_else(|| format!("Push constants for unknown pipeline '{}'", pipeline_name))?;
+
+        let max_size = unsafe {
+            self.core.instance.get_physical_device_properties(self.core.physical_device)
+        }.limits.max_push_constants_size;
+        let end = offset as usize + size;
+        if end > max_size as usize {
+            return Err(format!(
+                "Pipeline '{}': pushing {} bytes at offset {} exceeds maxPushConstantsSize ({})",
+                pipeline_name, size, offset, max_size
+            ));
+        }
+
+        // Every pushed byte needs a declared range for each pushed stage, and every
+        // range overlapping the push must have all of its stages included in the push
+        for byte in offset..end as u32 {
+            let mut covered_stages = vk::ShaderStageFlags::empty();
+            for range in &pipeline.push_constant_ranges {
+                if byte < range.offset || byte >= range.offset + range.size {
+                    continue;
+                }
+                if !stages.contains(range.stage_flags) {
+                    return Err(format!(
+                        "Pipeline '{}': push with stages {:?} overlaps declared range {:?} (offset {}, size {}) but doesn't include all of its stages",
+                        pipeline_name, stages, range.stage_flags, range.offset, range.size
+                    ));
+                }
+                covered_stages |= range.stage_flags;
+            }
+            if !covered_stages.contains(stages) {
+                return Err(format!(
+                    "Pipeline '{}': push of {} bytes at offset {} with stages {:?} doesn't match declared ranges {:?}",
+                    pipeline_name, size, offset, stages,
+                    pipeline.push_constant_ranges.iter()
+                        .map(|r| (r.stage_flags, r.offset, r.size))
+                        .collect::<Vec<_>>()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // cmd_push_constants for the named pipeline, validated in debug builds.
+    // Falls back to the current pipeline's layout like the bind code does when the name is unknown.
+    fn push_constants_checked(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipeline_name: &str,
+        stages: vk::ShaderStageFlags,
+        data: &[u8],
+    ) {
+        self.push_constants_checked_at(command_buffer, pipeline_name, stages, 0, data);
+    }
+    
+    fn push_constants_checked_at(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipeline_name: &str,
+        stages: vk::ShaderStageFlags,
+        offset: u32,
+        data: &[u8],
+    ) {
+        let pipeline_name = if self.pipelines.contains_key(pipeline_name) {
+            pipeline_name
+        } else {
+            self.current_pipeline.as_str()
+        };
+
+        if cfg!(debug_assertions) {
+            if let Err(e) = self.validate_push_constants(pipeline_name, stages, offset, data.len()) {
+                warn!("Skipping push constants: {}", e);
+                return;
+            }
+        }
+
+        if let Some(pipeline) = self.pipelines.get(pipeline_name) {
+            unsafe {
+                self.core.device.cmd_push_constants(command_buffer, pipeline.layout, stages, offset, data);
+            }
+        }
+    }
+    
+    // Pushes what follows the MVP block for a draw of the mesh and runs the pre draw hook. In a
+    // debug view the debug pipelines' constants take their place, and the hook is skipped since
+    // it binds for the mesh's own pipeline.
+    fn push_draw_constants(&self, command_buffer: vk::CommandBuffer, pipeline_name: &str, mesh_idx: usize, layout: vk::PipelineLayout) {
+        let (mode, near, far) = match self.debug_view {
+            DebugView::None => {
+                self.push_mesh_custom_constants(command_buffer, pipeline_name, &self.meshes[mesh_idx]);
+                if let Some(ref hook) = self.pre_draw_hook {
+                    hook(command_buffer, mesh_idx, layout);
+                }
+                return;
+            }
+            DebugView::Overdraw => (0, 0.0, 1.0),
+            DebugView::Normals => (1, 0.0, 1.0),
+            DebugView::Depth { near, far } => (2, near, far),
+        };
+        let constants = DebugViewPushConstants { mode, depth_near: near, depth_far: far, _padding: 0 };
+        self.push_constants_checked_at(
+            command_buffer,
+            pipeline_name,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            MVP_PUSH_CONSTANTS_SIZE,
+            bytemuck::bytes_of(&constants),
+        );
+    }
+    
+    // Pushes a mesh's alpha cutoff and custom push constants, after its built in ones since
+    // they may overlap. The cutoff is skipped by pipelines without room for it after the MVP block.
+    fn push_mesh_custom_constants(&self, command_buffer: vk::CommandBuffer, pipeline_name: &str, mesh: &MeshEntry) {
+        let Some(pipeline) = self.pipelines.get(pipeline_name) else {
+            return;
+        };
+        if let Some(threshold) = mesh.material_mode.alpha_cutoff() {
+            let stages = push_constant_stages(pipeline, MVP_PUSH_CONSTANTS_SIZE, 4);
+            if !mesh.is_skinned && !stages.is_empty() {
+                self.push_constants_checked_at(command_buffer, pipeline_name, stages, MVP_PUSH_CONSTANTS_SIZE, bytemuck::bytes_of(&threshold));
+            }
+        }
+        if let Some(wind) = mesh.wind {
+            let wind_constants = WindPushConstants {
+                direction_amplitude: wind.direction.extend(wind.amplitude).to_array(),
+                frequency_time: [wind.frequency, self.core.get_elapsed_time() + mesh.time_offset, 0.0, 0.0],
+            };
+            let stages = push_constant_stages(pipeline, WIND_PUSH_CONSTANTS_OFFSET, mem::size_of::<WindPushConstants>());
+            if !mesh.is_skinned && !stages.is_empty() {
+                self.push_constants_checked_at(command_buffer, pipeline_name, stages, WIND_PUSH_CONSTANTS_OFFSET, bytemuck::bytes_of(&wind_constants));
+            }
+        }
+        if let Some(uv_transform) = mesh.uv_transform {
+            // Wrapping the scroll keeps UVs precise however long the scene runs, REPEAT
+            // textures look the same
+            let time = self.core.get_elapsed_time() + mesh.time_offset;
+            let offset = uv_transform.offset + (uv_transform.scroll * time).rem_euclid(Vec2::ONE);
+            let uv_constants = [uv_transform.scale.x, uv_transform.scale.y, offset.x, offset.y];
+            let stages = push_constant_stages(pipeline, UV_TRANSFORM_PUSH_CONSTANTS_OFFSET, mem::size_of_val(&uv_constants));
+            if !mesh.is_skinned && !stages.is_empty() {
+                self.push_constants_checked_at(command_buffer, pipeline_name, stages, UV_TRANSFORM_PUSH_CONSTANTS_OFFSET, bytemuck::bytes_of(&uv_constants));
+            }
+        }
+        if mesh.custom_push_constants.is_empty() {
+            return;
+        }
+        let stages = push_constant_stages(pipeline, mesh.custom_push_offset, mesh.custom_push_constants.len());
+        if stages.is_empty() {
+            return;
+        }
+        self.push_constants_checked_at(command_buffer, pipeline_name, stages, mesh.custom_push_offset, &mesh.custom_push_constants);
+    }
+    
+    // Blocks until the GPU has finished all submitted work, e.g. before reading back a
+    // screenshot or destroying resources shared with other code. Rendering carries on as usual
+    // afterwards.
+    pub fn wait_idle(&self) -> Result<(), FloError> {
+        self.check_lost()?;
+        unsafe {
+            self.core.device.device_wait_idle()?;
+        }
         Ok(())
     }
     
-    // Switch to a different pipeline (alias for set_current_pipeline for compatibility)
-    pub fn set_active_pipeline(&mut self, name: &str) -> Result<(), String> {
-        self.set_current_pipeline(name)
+    // Destroys everything the renderer created, in dependency order: waits for the GPU, then
+    // destroys meshes, per-frame buffers, pipelines and descriptors, then the core's sync
+    // objects, the swapchain with its framebuffers and depth images, the surface and finally the
+    // device and instance, unless another renderer shares them (see shared_device). Dropping the
+    // renderer does the same, but the surface has to go before the window it was created for,
+    // so call this while the window still exists instead of leaving it to whenever the renderer
+    // happens to drop. A failed wait (a lost device) is returned after tearing down anyway.
+    pub fn shutdown(mut self) -> Result<(), FloError> {
+        let idle = unsafe { self.core.device.device_wait_idle() };
+        self.destroy_resources();
+        drop(self);
+        idle.map_err(FloError::from)
     }
     
-    // Switch to a different pipeline
-    pub fn set_current_pipeline(&mut self, name: &str) -> Result<(), String> {
-        if self.pipelines.contains_key(name) {
-            self.current_pipeline = name.to_string();
-            // Update the compatibility fields
-            if let Some(pipeline) = self.pipelines.get(name) {
-                self.graphics_pipeline = pipeline.pipeline;
-                self.pipeline_layout = pipeline.layout;
-            }
-            Ok(())
-        } else {
-            Err(format!("Pipeline '{}' not found", name))
+    // Completes every upload that would otherwise finish later: waits for textures still
+    // decoding and swaps them in, and copies queued vertex and instance updates now instead of
+    // at the start of the next frame. Returns with the GPU idle.
+    pub fn flush_pending_uploads(&mut self) -> Result<(), FloError> {
+        self.check_lost()?;
+        let completed = self.texture_streamer.wait_completed();
+        self.upload_decoded_textures(completed);
+        
+        // The copies go through the current frame's staging buffer, which a frame in flight may
+        // still be reading
+        self.wait_idle()?;
+        if !self.pending_vertex_uploads.is_empty() {
+            let command_buffer = crate::texture::begin_single_time_commands(&self.core.device, self.core.command_pool)?;
+            self.record_vertex_uploads(command_buffer);
+            crate::texture::end_single_time_commands(&self.core.device, self.core.command_pool, self.core.graphics_queue, command_buffer)?;
+        }
+        Ok(())
+    }
+    
+    // FloError::SurfaceLost or FloError::DeviceLost once a frame hit either. Frames are skipped
+    // from then on, since rendering into a lost surface or device is undefined.
+    pub fn check_lost(&self) -> Result<(), FloError> {
+        match self.core.lost {
+            Some(result) => Err(result.into()),
+            None => Ok(()),
         }
     }
     
+    // Recovers from a lost surface by recreating it for the window, along with the swapchain.
+    // Meshes, textures and pipelines are kept. A lost device returns FloError::DeviceLost: its
+    // resources are gone, so drop the renderer and create a new one. Does nothing if nothing
+    // was lost.
+    pub fn recover(&mut self, handle_wrapper: &impl WindowHandles) -> Result<(), FloError> {
+        if self.core.lost.is_none() {
+            return Ok(());
+        }
+        let (display_handle, window_handle) = handle_wrapper.raw_handles()?;
+        self.core.recreate_surface(display_handle, window_handle)?;
+        info!("Recovered from surface loss");
+        Ok(())
+    }
     
     // Render frame with multi-mesh support
     pub fn render_frame_with_camera_multi(&mut self, view: Mat4, proj: Mat4) {
+        self.process_texture_uploads();
+        self.update_dynamic_resolution();
+        self.resolve_mesh_hierarchy();
+        
         let image_index = match self.core.begin_frame() {
             Ok(index) => index,
             Err(e) => {
-                eprintln!("Failed to begin frame: {}", e);
+                report_frame_error("begin", &e);
                 return;
             }
         };
@@ -3033,7 +8677,7 @@ impl VulkanRenderer {
         self.record_command_buffer_multi_mesh_with_egui(image_index, view, proj, None);
         
         if let Err(e) = self.core.end_frame(image_index) {
-            eprintln!("Failed to end frame: {}", e);
+            report_frame_error("end", &e);
         }
     }
     
@@ -3041,7 +8685,7 @@ impl VulkanRenderer {
         let image_index = match self.core.begin_frame() {
             Ok(index) => index,
             Err(e) => {
-                eprintln!("Failed to begin frame: {}", e);
+                report_frame_error("begin", &e);
                 return;
             }
         };
@@ -3049,7 +8693,7 @@ impl VulkanRenderer {
         self.record_command_buffer_instanced(image_index);
         
         if let Err(e) = self.core.end_frame(image_index) {
-            eprintln!("Failed to end frame: {}", e);
+            report_frame_error("end", &e);
         }
     }
     
@@ -3057,7 +8701,7 @@ impl VulkanRenderer {
         let image_index = match self.core.begin_frame() {
             Ok(index) => index,
             Err(e) => {
-                eprintln!("Failed to begin frame: {}", e);
+                report_frame_error("begin", &e);
                 return;
             }
         };
@@ -3065,7 +8709,7 @@ impl VulkanRenderer {
         self.record_command_buffer_multi_instance(image_index, instance_positions);
         
         if let Err(e) = self.core.end_frame(image_index) {
-            eprintln!("Failed to end frame: {}", e);
+            report_frame_error("end", &e);
         }
     }
     
@@ -3073,7 +8717,7 @@ impl VulkanRenderer {
         let image_index = match self.core.begin_frame() {
             Ok(index) => index,
             Err(e) => {
-                eprintln!("Failed to begin frame: {}", e);
+                report_frame_error("begin", &e);
                 return;
             }
         };
@@ -3081,7 +8725,7 @@ impl VulkanRenderer {
         self.record_command_buffer_with_view_proj(image_index, view_proj);
         
         if let Err(e) = self.core.end_frame(image_index) {
-            eprintln!("Failed to end frame: {}", e);
+            report_frame_error("end", &e);
         }
     }
     
@@ -3089,7 +8733,7 @@ impl VulkanRenderer {
         let image_index = match self.core.begin_frame() {
             Ok(index) => index,
             Err(e) => {
-                eprintln!("Failed to begin frame: {}", e);
+                report_frame_error("begin", &e);
                 return;
             }
         };
@@ -3097,7 +8741,7 @@ impl VulkanRenderer {
         self.record_command_buffer(image_index);
         
         if let Err(e) = self.core.end_frame(image_index) {
-            eprintln!("Failed to end frame: {}", e);
+            report_frame_error("end", &e);
         }
     }
     
@@ -3107,26 +8751,39 @@ impl VulkanRenderer {
         view: Mat4, 
         proj: Mat4,
         push_constants: &PushConstants,
+    ) {
+        self.render_frame_fluid_with_egui(view, proj, push_constants, None);
+    }
+    
+    // Render frame with fluid simulation push constants and an egui overlay
+    pub fn render_frame_fluid_with_egui(
+        &mut self, 
+        view: Mat4, 
+        proj: Mat4,
+        push_constants: &PushConstants,
+        egui_output: Option<egui::FullOutput>,
     ) {
         let image_index = match self.core.begin_frame() {
             Ok(index) => index,
             Err(e) => {
-                eprintln!("Failed to begin frame: {:?}", e);
+                report_frame_error("begin", &e);
                 return;
             }
         };
         
         // Record command buffer with fluid push constants
-        self.record_command_buffer_fluid(image_index, view, proj, push_constants);
+        self.record_command_buffer_fluid(image_index, view, proj, push_constants, egui_output);
         
-        let _ = self.core.end_frame(image_index);
+        if let Err(e) = self.core.end_frame(image_index) {
+            report_frame_error("end", &e);
+        }
     }
     
     pub fn render_frame_with_camera(&mut self, view: Mat4, proj: Mat4) {
         let image_index = match self.core.begin_frame() {
             Ok(index) => index,
             Err(e) => {
-                eprintln!("Failed to begin frame: {}", e);
+                report_frame_error("begin", &e);
                 return;
             }
         };
@@ -3140,29 +8797,17 @@ impl VulkanRenderer {
         }
         
         if let Err(e) = self.core.end_frame(image_index) {
-            eprintln!("Failed to end frame: {}", e);
+            report_frame_error("end", &e);
         }
     }
     
     // Update joint matrices for skinned mesh
     pub fn update_joint_matrices(&mut self, joint_matrices: &[Mat4]) {
         if let Some(ref skinned) = self.skinned_mesh {
-            unsafe {
-                let buffer_size = (std::mem::size_of::<Mat4>() * 128) as u64;
-                let data_ptr = self.core.device.map_memory(
-                    skinned.joint_uniform_memory,
-                    0,
-                    buffer_size,
-                    vk::MemoryMapFlags::empty(),
-                ).unwrap() as *mut Mat4;
-                
-                // Copy joint matrices to buffer
-                for (i, mat) in joint_matrices.iter().enumerate() {
-                    if i >= 128 { break; }
-                    data_ptr.add(i).write(*mat);
-                }
-                
-                self.core.device.unmap_memory(skinned.joint_uniform_memory);
+            // Extra matrices don't fit, the buffer was sized to the mesh's joints
+            let joint_count = joint_matrices.len().min(skinned.joint_count);
+            if let Err(e) = write_joint_matrices(&self.core.device, skinned.joint_uniform_memory, 0, &joint_matrices[..joint_count]) {
+                error!("Failed to update joint matrices: {:?}", e);
             }
         }
     }
@@ -3206,12 +8851,12 @@ impl VulkanRenderer {
                 let clear_values = [
                     vk::ClearValue {
                         color: vk::ClearColorValue {
-                            float32: [0.529, 0.0, 0.69, 1.0], // Magenta clear color
+                            float32: self.clear_color,
                         },
                     },
                     vk::ClearValue {
                         depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 1.0,
+                            depth: self.depth_clear_value(),
                             stencil: 0,
                         },
                     },
@@ -3222,7 +8867,7 @@ impl VulkanRenderer {
                     .framebuffer(framebuffer)
                     .render_area(vk::Rect2D {
                         offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: self.core.swapchain_extent,
+                        extent: self.core.render_extent,
                     })
                     .clear_values(&clear_values);
                 
@@ -3231,6 +8876,7 @@ impl VulkanRenderer {
                     &render_pass_begin_info,
                     vk::SubpassContents::INLINE,
                 );
+                set_viewport_and_scissor(&self.core.device, command_buffer, self.core.render_extent);
                 
                 // Choose appropriate pipeline based on instancing
                 let pipeline_name = if skinned.use_instancing {
@@ -3241,13 +8887,10 @@ impl VulkanRenderer {
                 
                 // Bind the appropriate pipeline
                 if let Some(pipeline) = self.pipelines.get(pipeline_name) {
-                    self.core.device.cmd_bind_pipeline(
-                        command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        pipeline.pipeline,
-                    );
+                    bind_graphics_pipeline(&self.core.device, command_buffer, pipeline.pipeline, self.depth_compare_op());
                     
                     // Bind descriptor sets
+                    // Offset 0 for both dynamic joint bindings, the joints have a buffer of their own
                     let descriptor_set = skinned.descriptor_sets[self.core.current_frame];
                     self.core.device.cmd_bind_descriptor_sets(
                         command_buffer,
@@ -3255,7 +8898,7 @@ impl VulkanRenderer {
                         pipeline.layout,
                         0,
                         &[descriptor_set],
-                        &[],
+                        &[0, 0],
                     );
                     
                     // Bind vertex buffers - include instance buffer if using instancing
@@ -3297,14 +8940,13 @@ impl VulkanRenderer {
                         vk::IndexType::UINT32,
                     );
                     
-                    // Push time constant
-                    let push_data = self.core.start_time.elapsed().as_secs_f32();
-                    self.core.device.cmd_push_constants(
+                    // Push time, the single skinned mesh has no morph targets
+                    let push_data = [self.core.get_elapsed_time().to_bits(), 0];
+                    self.push_constants_checked(
                         command_buffer,
-                        pipeline.layout,
+                        pipeline_name,
                         vk::ShaderStageFlags::VERTEX,
-                        0,
-                        std::slice::from_raw_parts(&push_data as *const f32 as *const u8, 4),
+                        bytemuck::cast_slice(&push_data),
                     );
                     
                     // Draw indexed with appropriate instance count
@@ -3317,10 +8959,14 @@ impl VulkanRenderer {
                         0,
                     );
                 } else {
-                    println!("ERROR: Could not find {} pipeline!", pipeline_name);
+                    error!("Could not find {} pipeline", pipeline_name);
                 }
                 
+                end_scene_render_pass(&self.core.device, command_buffer);
+                
+                self.begin_present_pass(command_buffer, image_index);
                 self.core.device.cmd_end_render_pass(command_buffer);
+                
                 self.core.device
                     .end_command_buffer(command_buffer)
                     .expect("Failed to record command buffer");
@@ -3328,12 +8974,26 @@ impl VulkanRenderer {
         }
     }
     
+    // Push constants of the shaders with a built-in camera, like mesh.vert and cube.vert
+    fn simple_push_constants(&self) -> PushConstants {
+        let extent = self.core.render_extent;
+        PushConstants {
+            time: self.core.get_elapsed_time(),
+            resolution: [extent.width as f32, extent.height as f32],
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+    
     fn record_command_buffer(&self, image_index: u32) {
         let command_buffer = self.core.command_buffers[image_index as usize];
         let framebuffer = self.core.framebuffers[image_index as usize];
         
         // Build render configuration
-        let mut config = RenderConfig::default();
+        let mut config = RenderConfig {
+            depth_clear_value: self.depth_clear_value(),
+            depth_compare_op: self.depth_compare_op(),
+            ..Default::default()
+        };
         
         // Set draw mode and resources based on what we have
         if let Some(ref buffers) = self.buffers {
@@ -3365,11 +9025,10 @@ impl VulkanRenderer {
             config.descriptor_sets = &textures.descriptor_sets[image_index as usize..=image_index as usize];
         }
         
-        // Add time push constant for simple animations
-        let elapsed = self.core.get_elapsed_time();
-        let time_data = [elapsed];
-        let time_bytes = bytemuck::cast_slice(&time_data);
-        config.push_constant_data = Some(time_bytes);
+        // Time for simple animations and the scene's size, which the shaders' projection
+        // takes its aspect ratio from, so it follows resizes
+        let push_constants = self.simple_push_constants();
+        config.push_constant_data = Some(bytemuck::bytes_of(&push_constants));
         
         // Use the unified command buffer recording
         record_command_buffer_unified(
@@ -3377,12 +9036,20 @@ impl VulkanRenderer {
             command_buffer,
             self.core.render_pass,
             framebuffer,
-            self.core.swapchain_extent,
+            self.core.render_extent,
             self.graphics_pipeline,
             self.pipeline_layout,
             &config,
             self.has_depth,
         );
+        
+        self.begin_present_pass(command_buffer, image_index);
+        unsafe {
+            self.core.device.cmd_end_render_pass(command_buffer);
+            self.core.device
+                .end_command_buffer(command_buffer)
+                .expect("Failed to record command buffer");
+        }
     }
     
     fn record_command_buffer_instanced(&self, image_index: u32) {
@@ -3390,8 +9057,12 @@ impl VulkanRenderer {
         let framebuffer = self.core.framebuffers[image_index as usize];
         
         // Build render configuration
-        let mut config = RenderConfig::default();
-        config.clear_color = CLEAR_COLOR_MAGENTA;
+        let mut config = RenderConfig {
+            depth_clear_value: self.depth_clear_value(),
+            depth_compare_op: self.depth_compare_op(),
+            ..Default::default()
+        };
+        config.clear_color = self.clear_color;
         
         // Set resources
         if let Some(ref buffers) = self.buffers {
@@ -3413,11 +9084,10 @@ impl VulkanRenderer {
             config.descriptor_sets = &textures.descriptor_sets[image_index as usize..=image_index as usize];
         }
         
-        // Add time push constant
-        let elapsed = self.core.get_elapsed_time();
-        let time_data = [elapsed];
-        let time_bytes = bytemuck::cast_slice(&time_data);
-        config.push_constant_data = Some(time_bytes);
+        // Time for simple animations and the scene's size, which the shaders' projection
+        // takes its aspect ratio from, so it follows resizes
+        let push_constants = self.simple_push_constants();
+        config.push_constant_data = Some(bytemuck::bytes_of(&push_constants));
         
         // Record command buffer with special handling for instance buffer
         unsafe {
@@ -3436,7 +9106,7 @@ impl VulkanRenderer {
                 },
                 vk::ClearValue {
                     depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
+                        depth: self.depth_clear_value(),
                         stencil: 0,
                     },
                 },
@@ -3447,7 +9117,7 @@ impl VulkanRenderer {
                 .framebuffer(framebuffer)
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: self.core.swapchain_extent,
+                    extent: self.core.render_extent,
                 })
                 .clear_values(&clear_values);
             
@@ -3456,12 +9126,9 @@ impl VulkanRenderer {
                 &render_pass_info,
                 vk::SubpassContents::INLINE,
             );
+            set_viewport_and_scissor(&self.core.device, command_buffer, self.core.render_extent);
             
-            self.core.device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.graphics_pipeline,
-            );
+            bind_graphics_pipeline(&self.core.device, command_buffer, self.graphics_pipeline, self.depth_compare_op());
             
             // Bind both vertex and instance buffers
             if let Some(ref buffers) = self.buffers {
@@ -3495,28 +9162,33 @@ impl VulkanRenderer {
             
             // Push constants
             if let Some(push_data) = config.push_constant_data {
-                self.core.device.cmd_push_constants(
+                self.push_constants_checked(
                     command_buffer,
-                    self.pipeline_layout,
+                    &self.current_pipeline,
                     vk::ShaderStageFlags::VERTEX,
-                    0,
                     push_data,
                 );
             }
             
-            // Draw instanced
+            // Draw instanced, skipped when there are no instances since no instance buffer is bound
             if let DrawMode::IndexedInstanced { index_count, instance_count } = config.draw_mode {
-                self.core.device.cmd_draw_indexed(
-                    command_buffer,
-                    index_count,
-                    instance_count,
-                    0,
-                    0,
-                    0,
-                );
+                if instance_count > 0 {
+                    self.core.device.cmd_draw_indexed(
+                        command_buffer,
+                        index_count,
+                        instance_count,
+                        0,
+                        0,
+                        0,
+                    );
+                }
             }
             
+            end_scene_render_pass(&self.core.device, command_buffer);
+            
+            self.begin_present_pass(command_buffer, image_index);
             self.core.device.cmd_end_render_pass(command_buffer);
+            
             self.core.device.end_command_buffer(command_buffer)
                 .expect("Failed to record command buffer");
         }
@@ -3537,12 +9209,12 @@ impl VulkanRenderer {
             let clear_values = [
                 vk::ClearValue {
                     color: vk::ClearColorValue {
-                        float32: CLEAR_COLOR_MAGENTA,
+                        float32: self.clear_color,
                     },
                 },
                 vk::ClearValue {
                     depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
+                        depth: self.depth_clear_value(),
                         stencil: 0,
                     },
                 },
@@ -3553,7 +9225,7 @@ impl VulkanRenderer {
                 .framebuffer(framebuffer)
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: self.core.swapchain_extent,
+                    extent: self.core.render_extent,
                 })
                 .clear_values(&clear_values);
             
@@ -3562,12 +9234,9 @@ impl VulkanRenderer {
                 &render_pass_info,
                 vk::SubpassContents::INLINE,
             );
+            set_viewport_and_scissor(&self.core.device, command_buffer, self.core.render_extent);
             
-            self.core.device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.graphics_pipeline,
-            );
+            bind_graphics_pipeline(&self.core.device, command_buffer, self.graphics_pipeline, self.depth_compare_op());
             
             // Bind vertex and index buffers if available
             if let Some(ref buffers) = self.buffers {
@@ -3601,11 +9270,10 @@ impl VulkanRenderer {
                 let push_data = [elapsed, position[0], position[1], position[2]];
                 let push_bytes = bytemuck::cast_slice(&push_data);
                 
-                self.core.device.cmd_push_constants(
+                self.push_constants_checked(
                     command_buffer,
-                    self.pipeline_layout,
+                    &self.current_pipeline,
                     vk::ShaderStageFlags::VERTEX,
-                    0,
                     push_bytes,
                 );
                 
@@ -3616,7 +9284,11 @@ impl VulkanRenderer {
                 }
             }
             
+            end_scene_render_pass(&self.core.device, command_buffer);
+            
+            self.begin_present_pass(command_buffer, image_index);
             self.core.device.cmd_end_render_pass(command_buffer);
+            
             self.core.device.end_command_buffer(command_buffer)
                 .expect("Failed to record command buffer");
         }
@@ -3629,8 +9301,12 @@ impl VulkanRenderer {
         // Prepare view-proj matrix for push constant
         let view_proj_array = view_proj.to_cols_array_2d();
         
-        let mut config = RenderConfig::default();
-        config.clear_color = CLEAR_COLOR_MAGENTA;
+        let mut config = RenderConfig {
+            depth_clear_value: self.depth_clear_value(),
+            depth_compare_op: self.depth_compare_op(),
+            ..Default::default()
+        };
+        config.clear_color = self.clear_color;
         
         // Set resources
         if let Some(ref buffers) = self.buffers {
@@ -3660,12 +9336,20 @@ impl VulkanRenderer {
             command_buffer,
             self.core.render_pass,
             framebuffer,
-            self.core.swapchain_extent,
+            self.core.render_extent,
             self.graphics_pipeline,
             self.pipeline_layout,
             &config,
             self.has_depth,
         );
+        
+        self.begin_present_pass(command_buffer, image_index);
+        unsafe {
+            self.core.device.cmd_end_render_pass(command_buffer);
+            self.core.device
+                .end_command_buffer(command_buffer)
+                .expect("Failed to record command buffer");
+        }
     }
     
     fn record_command_buffer_with_push_data(&mut self, image_index: u32, view: Mat4, proj: Mat4) {
@@ -3676,6 +9360,10 @@ impl VulkanRenderer {
         let command_buffer = self.core.command_buffers[image_index as usize];
         let framebuffer = self.core.framebuffers[image_index as usize];
         
+        self.update_occlusion_visibility();
+        let query_pool = self.core.occlusion_query_pools[self.core.current_frame];
+        let proj = self.viewport_projection(proj);
+        
         unsafe {
             let begin_info = vk::CommandBufferBeginInfo::default();
             
@@ -3683,16 +9371,26 @@ impl VulkanRenderer {
                 .begin_command_buffer(command_buffer, &begin_info)
                 .expect("Failed to begin command buffer");
             
+            // Queries have to be reset outside the render pass
+            self.core.device.cmd_reset_query_pool(command_buffer, query_pool, 0, MAX_OCCLUSION_QUERIES);
+            self.core.cmd_begin_gpu_timer(command_buffer);
+            
+            self.sort_mesh_instances(view);
+            self.record_vertex_uploads(command_buffer);
+            self.record_instance_culling(command_buffer, view, proj);
+            self.record_bone_hierarchies(command_buffer);
+            self.record_particle_simulation(command_buffer);
+            
             // Begin render pass
             let clear_values = [
                 vk::ClearValue {
                     color: vk::ClearColorValue {
-                        float32: CLEAR_COLOR_MAGENTA,
+                        float32: self.scene_clear_color(),
                     },
                 },
                 vk::ClearValue {
                     depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
+                        depth: self.depth_clear_value(),
                         stencil: 0,
                     },
                 },
@@ -3703,326 +9401,432 @@ impl VulkanRenderer {
                 .framebuffer(framebuffer)
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: self.core.swapchain_extent,
+                    extent: self.core.render_extent,
                 })
                 .clear_values(&clear_values);
             
+            // Draws from record_parallel, only valid for the frame in flight they were recorded for
+            let parallel_draws = match self.parallel_draws.take() {
+                Some((frame, command_buffers)) if frame == self.core.current_frame => command_buffers,
+                Some(_) => {
+                    warn!("Dropping draws from record_parallel, they were recorded for a different frame");
+                    Vec::new()
+                }
+                None => Vec::new(),
+            };
+            
+            // Static scene mode and record_parallel draw from secondary command buffers, which
+            // can't be mixed with inline draws
+            let static_scene = self.static_scene;
+            let contents = if static_scene || !parallel_draws.is_empty() {
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+            } else {
+                vk::SubpassContents::INLINE
+            };
             self.core.device.cmd_begin_render_pass(
                 command_buffer,
                 &render_pass_info,
-                vk::SubpassContents::INLINE,
+                contents,
             );
             
-            // Set viewport and scissor (do this once before the loop)
-            let viewport = vk::Viewport {
-                x: 0.0,
-                y: 0.0,
-                width: self.core.swapchain_extent.width as f32,
-                height: self.core.swapchain_extent.height as f32,
-                min_depth: 0.0,
-                max_depth: 1.0,
-            };
-            self.core.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
-            
-            let scissor = vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: self.core.swapchain_extent,
-            };
-            self.core.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            // Mesh index for each occlusion query issued this frame
+            let mut occlusion_queries: Vec<usize> = Vec::new();
+            let mut stats = FrameStats::default();
             
-            // Track the currently bound pipeline to avoid redundant binds
-            let mut current_pipeline_name: Option<String> = None;
+            // Lighting subpass meshes are drawn inline after everything in the geometry subpass
+            let (draw_order, lighting_draws): (Vec<usize>, Vec<usize>) = plan_draw_order(&self.mesh_draw_infos(image_index as usize), view)
+            .into_iter()
+            .partition(|&mesh_idx| self.meshes[mesh_idx].subpass == GEOMETRY_SUBPASS);
             
-            // Render each mesh with its transforms
-            for (mesh_idx, mesh) in self.meshes.iter().enumerate() {
-                // Skip meshes with no transforms and non-instanced meshes with no instances
-                if !mesh.use_instancing && mesh.transforms.is_empty() {
-                    continue;
-                }
-                if mesh.use_instancing && mesh.instance_count == 0 {
-                    continue;
-                }
+            if static_scene {
+                // Static meshes are recorded once per swapchain image and replayed until the scene
+                // or camera changes. Meshes that change every frame are recorded into a second
+                // buffer each frame and drawn after them.
+                let (static_draws, dynamic_draws): (Vec<usize>, Vec<usize>) = draw_order.iter()
+                    .partition(|&&mesh_idx| is_static_mesh(&self.meshes[mesh_idx]));
+                let image = image_index as usize;
+                let static_command_buffer = self.static_scene_command_buffers[image];
+                let dynamic_command_buffer = self.dynamic_scene_command_buffers[image];
                 
-                // Determine which pipeline to use for this mesh
-                let actual_pipeline_name = mesh.pipeline_name.as_ref()
-                    .map(|s| s.as_str())
-                    .unwrap_or("default");
-                
-                // Debug log for colonist meshes
-                if actual_pipeline_name.contains("colonist") || mesh_idx == 50 {
-                    static mut COLONIST_LOG_COUNT: u32 = 0;
-                    COLONIST_LOG_COUNT += 1;
-                    if COLONIST_LOG_COUNT % 60 == 0 {
-                        println!("Rendering mesh {}: pipeline={}, is_skinned={}, instance_count={}, use_instancing={}", 
-                                 mesh_idx, actual_pipeline_name, mesh.is_skinned, mesh.instance_count, mesh.use_instancing);
-                    }
+                if self.static_scene_recorded_with[image] != Some((view, proj)) {
+                    begin_secondary_command_buffer(&self.core.device, static_command_buffer, self.core.render_pass, framebuffer, self.core.render_extent)
+                        .expect("Failed to begin static scene command buffer");
+                    self.set_scene_viewport(static_command_buffer);
+                    let mut static_stats = FrameStats::default();
+                    self.record_mesh_draws(static_command_buffer, image_index, view, proj, &static_draws, &mut occlusion_queries, &mut static_stats);
+                    self.core.device
+                        .end_command_buffer(static_command_buffer)
+                        .expect("Failed to end static scene command buffer");
+                    self.static_scene_recorded_with[image] = Some((view, proj));
+                    self.static_scene_stats[image] = static_stats;
                 }
+                stats += self.static_scene_stats[image];
                 
-                // Switch pipeline if needed
-                if current_pipeline_name.as_deref() != Some(actual_pipeline_name) {
-                    let (pipeline, _pipeline_layout) = if let Some(pipeline_entry) = self.pipelines.get(actual_pipeline_name) {
-                        (pipeline_entry.pipeline, pipeline_entry.layout)
-                    } else {
-                        // Fallback to default pipeline
-                        println!("WARNING: Pipeline '{}' not found, using default", actual_pipeline_name);
-                        (self.graphics_pipeline, self.pipeline_layout)
-                    };
-                    
-                    self.core.device.cmd_bind_pipeline(
-                        command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        pipeline,
-                    );
-                    
-                    current_pipeline_name = Some(actual_pipeline_name.to_string());
-                }
+                begin_secondary_command_buffer(&self.core.device, dynamic_command_buffer, self.core.render_pass, framebuffer, self.core.render_extent)
+                    .expect("Failed to begin dynamic scene command buffer");
+                self.set_scene_viewport(dynamic_command_buffer);
+                self.record_mesh_draws(dynamic_command_buffer, image_index, view, proj, &dynamic_draws, &mut occlusion_queries, &mut stats);
+                self.record_fallback_draw(dynamic_command_buffer, view, proj);
+                self.record_ground_grid_draw(dynamic_command_buffer, view, proj);
+                self.record_particle_draw(dynamic_command_buffer, view, proj);
+                self.record_immediate_draws(dynamic_command_buffer, view, proj);
+                self.core.device
+                    .end_command_buffer(dynamic_command_buffer)
+                    .expect("Failed to end dynamic scene command buffer");
                 
-                // Get the current pipeline layout for push constants
-                let pipeline_layout = if let Some(pipeline_entry) = self.pipelines.get(actual_pipeline_name) {
-                    pipeline_entry.layout
-                } else {
-                    self.pipeline_layout
-                };
+                let mut secondaries = vec![static_command_buffer, dynamic_command_buffer];
+                secondaries.extend_from_slice(&parallel_draws);
+                self.core.device.cmd_execute_commands(command_buffer, &secondaries);
+            } else if !parallel_draws.is_empty() {
+                // The renderer's own draws go into a secondary buffer too, executed before the threads'
+                let dynamic_command_buffer = self.dynamic_scene_command_buffers[image_index as usize];
+                begin_secondary_command_buffer(&self.core.device, dynamic_command_buffer, self.core.render_pass, framebuffer, self.core.render_extent)
+                    .expect("Failed to begin scene command buffer");
+                self.set_scene_viewport(dynamic_command_buffer);
+                self.record_mesh_draws(dynamic_command_buffer, image_index, view, proj, &draw_order, &mut occlusion_queries, &mut stats);
+                self.record_fallback_draw(dynamic_command_buffer, view, proj);
+                self.record_ground_grid_draw(dynamic_command_buffer, view, proj);
+                self.record_particle_draw(dynamic_command_buffer, view, proj);
+                self.record_immediate_draws(dynamic_command_buffer, view, proj);
+                self.core.device
+                    .end_command_buffer(dynamic_command_buffer)
+                    .expect("Failed to end scene command buffer");
                 
-                // Check if using GPU instancing
-                if mesh.use_instancing {
-                    // TRUE GPU INSTANCING PATH
+                let mut secondaries = vec![dynamic_command_buffer];
+                secondaries.extend_from_slice(&parallel_draws);
+                self.core.device.cmd_execute_commands(command_buffer, &secondaries);
+            } else {
+                self.set_scene_viewport(command_buffer);
+                self.record_mesh_draws(command_buffer, image_index, view, proj, &draw_order, &mut occlusion_queries, &mut stats);
+                self.record_fallback_draw(command_buffer, view, proj);
+                self.record_ground_grid_draw(command_buffer, view, proj);
+                self.record_particle_draw(command_buffer, view, proj);
+                self.record_immediate_draws(command_buffer, view, proj);
+            }
+            
+            self.core.device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+            self.set_scene_viewport(command_buffer);
+            self.record_mesh_draws(command_buffer, image_index, view, proj, &lighting_draws, &mut occlusion_queries, &mut stats);
+            self.core.device.cmd_end_render_pass(command_buffer);
+            self.record_overlay_passes(command_buffer, image_index, &mut occlusion_queries, &mut stats);
+            
+            self.occlusion_query_meshes[self.core.current_frame] = occlusion_queries;
+            self.frame_stats = stats;
+            
+            self.begin_present_pass(command_buffer, image_index);
+            
+            // Render egui on top of the tonemapped scene if provided
+            if let Some(egui_output) = egui_output {
+                if let Some(ref mut egui_integration) = self.egui_integration {
+                    // Update textures before rendering
+                    if !egui_output.textures_delta.set.is_empty() {
+                        if let Err(e) = egui_integration.renderer.set_textures(
+                            self.core.graphics_queue,
+                            self.core.command_pool,
+                            egui_output.textures_delta.set.as_slice(),
+                        ) {
+                            error!("Failed to set egui textures: {}", e);
+                        }
+                    }
                     
-                    // Bind vertex buffer at binding 0
-                    self.core.device.cmd_bind_vertex_buffers(
-                        command_buffer,
-                        0,
-                        &[mesh.vertex_buffer],
-                        &[0],
+                    let clipped_primitives = egui_integration.context.tessellate(
+                        egui_output.shapes,
+                        egui_output.pixels_per_point,
                     );
                     
-                    // Bind instance buffer at binding 1 if available
-                    if let Some(instance_buffer) = mesh.instance_buffer {
-                        self.core.device.cmd_bind_vertex_buffers(
-                            command_buffer,
-                            1,
-                            &[instance_buffer],
-                            &[0],
-                        );
-                    }
-                    
-                    // Bind index buffer
-                    self.core.device.cmd_bind_index_buffer(
+                    if let Err(e) = egui_integration.renderer.cmd_draw(
                         command_buffer,
-                        mesh.index_buffer,
-                        0,
-                        vk::IndexType::UINT32,
-                    );
+                        self.core.swapchain_extent,
+                        egui_output.pixels_per_point,
+                        &clipped_primitives,
+                    ) {
+                        error!("Failed to render egui: {}", e);
+                    }
                     
-                    // Bind descriptor sets if available
-                    // For skinned meshes, bind the skinned descriptor sets
-                    if mesh.is_skinned {
-                        // Debug log for skinned mesh descriptor binding
-                        if actual_pipeline_name.contains("colonist") {
-                            static mut SKINNED_LOG_COUNT: u32 = 0;
-                            SKINNED_LOG_COUNT += 1;
-                            if SKINNED_LOG_COUNT % 60 == 0 {
-                                println!("Binding skinned descriptors for mesh {}: has_sets={}, has_camera_buffer={}", 
-                                         mesh_idx, 
-                                         mesh.skinned_descriptor_sets.is_some(),
-                                         mesh.camera_uniform_memory.is_some());
-                            }
-                        }
-                        
-                        // Update camera uniform buffer with current view/proj matrices
-                        if let Some(camera_buffer_memory) = mesh.camera_uniform_memory {
-                            let camera_uniforms = CameraUniforms {
-                                view: view.to_cols_array(),
-                                proj: proj.to_cols_array(),
-                            };
-                            
-                            if let Ok(data) = self.core.device.map_memory(
-                                    camera_buffer_memory,
-                                    0,
-                                    std::mem::size_of::<CameraUniforms>() as u64,
-                                    vk::MemoryMapFlags::empty(),
-                                ) {
-                                    std::ptr::copy_nonoverlapping(
-                                        &camera_uniforms as *const _ as *const u8,
-                                        data as *mut u8,
-                                        std::mem::size_of::<CameraUniforms>(),
-                                    );
-                                    self.core.device.unmap_memory(camera_buffer_memory);
-                                } else {
-                                    eprintln!("Failed to map camera buffer memory");
-                                }
-                        }
-                        
-                        if let Some(ref descriptor_sets) = mesh.skinned_descriptor_sets {
-                            if descriptor_sets.len() > image_index as usize {
-                                self.core.device.cmd_bind_descriptor_sets(
-                                    command_buffer,
-                                    vk::PipelineBindPoint::GRAPHICS,
-                                    pipeline_layout,
-                                    0,
-                                    &descriptor_sets[image_index as usize..=image_index as usize],
-                                    &[],
-                                );
-                            }
+                    // Free removed textures
+                    if !egui_output.textures_delta.free.is_empty() {
+                        if let Err(e) = egui_integration.renderer.free_textures(&egui_output.textures_delta.free) {
+                            error!("Failed to free egui textures: {}", e);
                         }
-                    } else if let Some(ref textures) = mesh.texture_resources {
-                        self.core.device.cmd_bind_descriptor_sets(
-                            command_buffer,
-                            vk::PipelineBindPoint::GRAPHICS,
-                            pipeline_layout,
-                            0,
-                            &textures.descriptor_sets[image_index as usize..=image_index as usize],
-                            &[],
-                        );
-                    } else if let Some(ref textures) = self.textures {
-                        self.core.device.cmd_bind_descriptor_sets(
-                            command_buffer,
-                            vk::PipelineBindPoint::GRAPHICS,
-                            pipeline_layout,
-                            0,
-                            &textures.descriptor_sets[image_index as usize..=image_index as usize],
-                            &[],
-                        );
                     }
-                    
-                    // Set push constants based on whether this is a skinned mesh
-                    if mesh.is_skinned {
-                        // Skinned shaders only expect time as push constant
-                        let push_data = self.core.start_time.elapsed().as_secs_f32();
-                        self.core.device.cmd_push_constants(
+                }
+            }
+            
+            self.core.device.cmd_end_render_pass(command_buffer);
+            self.core.cmd_end_gpu_timer(command_buffer);
+            
+            self.core.device
+                .end_command_buffer(command_buffer)
+                .expect("Failed to end command buffer");
+        }
+    }
+    
+    // Records the draws for the meshes in draw_order into a command buffer inside the scene pass
+    // by executing their plan (see draw_plan). Queries issued for occlusion culled meshes are
+    // appended to occlusion_queries and the recorded draws are added to stats.
+    #[allow(clippy::too_many_arguments)]
+    fn record_mesh_draws(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+        view: Mat4,
+        proj: Mat4,
+        draw_order: &[usize],
+        occlusion_queries: &mut Vec<usize>,
+        stats: &mut FrameStats,
+    ) {
+        let query_pool = self.core.occlusion_query_pools[self.core.current_frame];
+        // The proxy pipeline is built for the geometry subpass
+        let proxies_available = self.pipelines.contains_key(OCCLUSION_PROXY_PIPELINE)
+            && draw_order.iter().all(|&mesh_idx| self.meshes[mesh_idx].subpass == GEOMETRY_SUBPASS);
+        let plan = plan_mesh_draws(
+            &self.mesh_draw_infos(image_index as usize),
+            draw_order,
+            occlusion_queries.len() as u32,
+            proxies_available,
+            self.debug_view,
+        );
+        *stats += FrameStats::from_draw_plan(&self.meshes, &plan);
+        
+        unsafe {
+            // Name and layout of the pipeline bound by the last BindPipeline
+            let mut current_pipeline_name = "default";
+            let mut pipeline_layout = self.pipeline_layout;
+            
+            for command in &plan {
+                match *command {
+                    DrawCommand::BindPipeline(ref pipeline_name) => {
+                        let pipeline = if let Some(pipeline_entry) = self.pipelines.get(pipeline_name) {
+                            pipeline_layout = pipeline_entry.layout;
+                            pipeline_entry.pipeline
+                        } else {
+                            // Fallback to default pipeline
+                            warn!("Pipeline '{}' not found, using default", pipeline_name);
+                            pipeline_layout = self.pipeline_layout;
+                            self.graphics_pipeline
+                        };
+                        bind_graphics_pipeline(&self.core.device, command_buffer, pipeline, self.depth_compare_op());
+                        current_pipeline_name = pipeline_name.as_str();
+                    }
+                    DrawCommand::BindDescriptorSet(descriptor_set, joint_offset) => {
+                        let offsets = [joint_offset.unwrap_or(0); 2];
+                        let dynamic_offsets: &[u32] = if joint_offset.is_some() { &offsets } else { &[] };
+                        self.core.device.cmd_bind_descriptor_sets(
                             command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
                             pipeline_layout,
-                            vk::ShaderStageFlags::VERTEX,
                             0,
-                            std::slice::from_raw_parts(&push_data as *const f32 as *const u8, 4),
+                            &[descriptor_set],
+                            dynamic_offsets,
                         );
-                    } else {
-                        // Regular meshes use MVP push constants
-                        let mvp = MvpPushConstants {
-                            model: Mat4::IDENTITY.to_cols_array(),  // Model matrix handled by instance data
-                            view: view.to_cols_array(),
-                            proj: proj.to_cols_array(),
-                            base_color: mesh.base_color,
+                    }
+                    DrawCommand::DrawOcclusionProxy { mesh: mesh_idx, query } => {
+                        let mesh = &self.meshes[mesh_idx];
+                        let Some(proxy_buffer) = mesh.occlusion_proxy_buffer else {
+                            continue;
                         };
+                        self.core.device.cmd_bind_vertex_buffers(command_buffer, 0, &[proxy_buffer], &[0]);
                         
-                        let push_bytes = bytemuck::bytes_of(&mvp);
+                        occlusion_queries.push(mesh_idx);
+                        self.core.device.cmd_begin_query(command_buffer, query_pool, query, vk::QueryControlFlags::empty());
+                        for transform in &mesh.transforms {
+                            let mvp = MvpPushConstants {
+                                model: transform.to_cols_array(),
+                                view: view.to_cols_array(),
+                                proj: proj.to_cols_array(),
+                                base_color: mesh.base_color,
+                            };
+                            self.push_constants_checked(
+                                command_buffer,
+                                OCCLUSION_PROXY_PIPELINE,
+                                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                                bytemuck::bytes_of(&mvp),
+                            );
+                            self.core.device.cmd_draw(command_buffer, BOUNDING_BOX_VERTEX_COUNT, 1, 0, 0);
+                        }
+                        self.core.device.cmd_end_query(command_buffer, query_pool, query);
+                    }
+                    DrawCommand::DrawInstanced { mesh: mesh_idx } => {
+                        let mesh = &self.meshes[mesh_idx];
+                        set_mesh_depth_state(&self.core.device, command_buffer, mesh, self.depth_compare_op(), self.reverse_z);
                         
-                        self.core.device.cmd_push_constants(
+                        trace!("Rendering mesh {}: pipeline={}, is_skinned={}, instance_count={}, use_instancing={}",
+                               mesh_idx, current_pipeline_name, mesh.is_skinned, mesh.instance_count, mesh.use_instancing);
+                        
+                        // Bind vertex buffer at binding 0
+                        self.core.device.cmd_bind_vertex_buffers(
                             command_buffer,
-                            pipeline_layout,
-                            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
                             0,
-                            push_bytes,
+                            &[mesh.vertex_buffer],
+                            &[0],
                         );
-                    }
-                    
-                    // Debug log draw call for colonist meshes
-                    if actual_pipeline_name.contains("colonist") && mesh.use_instancing {
-                        static mut DRAW_LOG_COUNT: u32 = 0;
-                        DRAW_LOG_COUNT += 1;
-                        if DRAW_LOG_COUNT % 60 == 0 {
-                            println!("Drawing colonist mesh {}: index_count={}, instance_count={}, vertex_count={}", 
-                                     mesh_idx, mesh.index_count, mesh.instance_count,
-                                     mesh.index_count / 3); // Approximate vertex count
-                            println!("  Using pipeline: {}", actual_pipeline_name);
-                            println!("  Is skinned: {}", mesh.is_skinned);
-                            println!("  Has descriptor sets: {}", mesh.skinned_descriptor_sets.is_some());
+                        
+                        // Bind instance buffer at binding 1 if available
+                        if let Some(instance_buffer) = mesh.instance_buffer {
+                            self.core.device.cmd_bind_vertex_buffers(
+                                command_buffer,
+                                1,
+                                &[instance_buffer],
+                                &[0],
+                            );
+                        }
+                        
+                        bind_mesh_index_buffer(&self.core.device, command_buffer, mesh);
+                        
+                        // Skinned meshes read the camera from a uniform buffer
+                        if mesh.is_skinned {
+                            trace!("Binding skinned descriptors for mesh {}: has_sets={}, has_camera_buffer={}",
+                                   mesh_idx,
+                                   mesh.skinned_descriptor_sets.is_some(),
+                                   mesh.camera_uniform_memory.is_some());
+                            
+                            // Update camera uniform buffer with current view/proj matrices
+                            if let Some(camera_buffer_memory) = mesh.camera_uniform_memory {
+                                let camera_uniforms = CameraUniforms {
+                                    view: view.to_cols_array(),
+                                    proj: proj.to_cols_array(),
+                                };
+                                
+                                if let Ok(data) = self.core.device.map_memory(
+                                        camera_buffer_memory,
+                                        0,
+                                        std::mem::size_of::<CameraUniforms>() as u64,
+                                        vk::MemoryMapFlags::empty(),
+                                    ) {
+                                        std::ptr::copy_nonoverlapping(
+                                            &camera_uniforms as *const _ as *const u8,
+                                            data as *mut u8,
+                                            std::mem::size_of::<CameraUniforms>(),
+                                        );
+                                        self.core.device.unmap_memory(camera_buffer_memory);
+                                    } else {
+                                        error!("Failed to map camera buffer memory");
+                                    }
+                            }
+                            
+                            // Skinned shaders expect time and the morph target count
+                            let time = self.core.get_elapsed_time() + mesh.time_offset;
+                            let push_data = [time.to_bits(), mesh.morph_target_count];
+                            self.push_constants_checked(
+                                command_buffer,
+                                current_pipeline_name,
+                                vk::ShaderStageFlags::VERTEX,
+                                bytemuck::cast_slice(&push_data),
+                            );
+                        } else {
+                            // Regular meshes use MVP push constants
+                            let mvp = MvpPushConstants {
+                                model: Mat4::IDENTITY.to_cols_array(),  // Model matrix handled by instance data
+                                view: view.to_cols_array(),
+                                proj: proj.to_cols_array(),
+                                base_color: mesh.base_color,
+                            };
+                            
+                            self.push_constants_checked(
+                                command_buffer,
+                                current_pipeline_name,
+                                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                                bytemuck::bytes_of(&mvp),
+                            );
+                        }
+                        self.push_draw_constants(command_buffer, current_pipeline_name, mesh_idx, pipeline_layout);
+                        
+                        trace!("Drawing mesh {}: index_count={}, instance_count={}",
+                               mesh_idx, mesh.index_count, mesh.instance_count);
+                        
+                        if let Some(ref culling) = mesh.instance_culling {
+                            // Draws only the instances that survived culling this frame
+                            culling.record_draw(&self.core.device, command_buffer, self.core.current_frame, mesh.index_count);
+                        } else {
+                            // SINGLE DRAW CALL FOR ALL INSTANCES!
+                            draw_mesh(&self.core.device, command_buffer, mesh, mesh.instance_count);
                         }
                     }
-                    
-                    // SINGLE DRAW CALL FOR ALL INSTANCES!
-                    self.core.device.cmd_draw_indexed(
-                        command_buffer,
-                        mesh.index_count,
-                        mesh.instance_count,  // Draw all instances in one call!
-                        0,
-                        0,
-                        0,
-                    );
-                } else {
-                    // INDIVIDUAL DRAW CALLS PATH (old behavior)
-                    
-                    // Bind vertex buffer
-                    self.core.device.cmd_bind_vertex_buffers(
-                        command_buffer,
-                        0,
-                        &[mesh.vertex_buffer],
-                        &[0],
-                    );
-                    
-                    // Bind index buffer
-                    self.core.device.cmd_bind_index_buffer(
-                        command_buffer,
-                        mesh.index_buffer,
-                        0,
-                        vk::IndexType::UINT32,
-                    );
-                    
-                    // Bind descriptor sets if available
-                    // For skinned meshes, bind the skinned descriptor sets
-                    if mesh.is_skinned {
-                        if let Some(ref descriptor_sets) = mesh.skinned_descriptor_sets {
-                            self.core.device.cmd_bind_descriptor_sets(
+                    DrawCommand::DrawTransforms { mesh: mesh_idx, query } => {
+                        let mesh = &self.meshes[mesh_idx];
+                        set_mesh_depth_state(&self.core.device, command_buffer, mesh, self.depth_compare_op(), self.reverse_z);
+                        bind_mesh_vertex_buffers(&self.core.device, command_buffer, mesh);
+                        bind_mesh_index_buffer(&self.core.device, command_buffer, mesh);
+                        
+                        if let Some(query) = query {
+                            occlusion_queries.push(mesh_idx);
+                            self.core.device.cmd_begin_query(command_buffer, query_pool, query, vk::QueryControlFlags::empty());
+                        }
+                        
+                        // Draw each instance with its transform
+                        for transform in &mesh.transforms {
+                            let mvp = MvpPushConstants {
+                                model: transform.to_cols_array(),
+                                view: view.to_cols_array(),
+                                proj: proj.to_cols_array(),
+                                base_color: mesh.base_color,
+                            };
+                            
+                            self.push_constants_checked(
                                 command_buffer,
-                                vk::PipelineBindPoint::GRAPHICS,
-                                pipeline_layout,
-                                0,
-                                &descriptor_sets[image_index as usize..=image_index as usize],
-                                &[],
+                                current_pipeline_name,
+                                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                                bytemuck::bytes_of(&mvp),
                             );
+                            self.push_draw_constants(command_buffer, current_pipeline_name, mesh_idx, pipeline_layout);
+                            
+                            draw_mesh(&self.core.device, command_buffer, mesh, 1);
+                        }
+                        
+                        if let Some(query) = query {
+                            self.core.device.cmd_end_query(command_buffer, query_pool, query);
                         }
-                    } else if let Some(ref textures) = mesh.texture_resources {
-                        self.core.device.cmd_bind_descriptor_sets(
-                            command_buffer,
-                            vk::PipelineBindPoint::GRAPHICS,
-                            pipeline_layout,
-                            0,
-                            &textures.descriptor_sets[image_index as usize..=image_index as usize],
-                            &[],
-                        );
-                    } else if let Some(ref textures) = self.textures {
-                        self.core.device.cmd_bind_descriptor_sets(
-                            command_buffer,
-                            vk::PipelineBindPoint::GRAPHICS,
-                            pipeline_layout,
-                            0,
-                            &textures.descriptor_sets[image_index as usize..=image_index as usize],
-                            &[],
-                        );
                     }
-                    
-                    // Draw each instance with its transform
-                    for transform in &mesh.transforms {
-                        let mvp = MvpPushConstants {
-                            model: transform.to_cols_array(),
-                            view: view.to_cols_array(),
-                            proj: proj.to_cols_array(),
-                            base_color: mesh.base_color,
+                    DrawCommand::DrawWireframeOverlay { mesh: mesh_idx } => {
+                        let mesh = &self.meshes[mesh_idx];
+                        let Some(color) = mesh.wireframe_overlay else {
+                            continue;
                         };
+                        if self.has_depth {
+                            self.core.device.cmd_set_depth_test_enable(command_buffer, mesh.depth_test);
+                        }
+                        // Towards the camera, which is up the depth range with reverse-Z
+                        let (constant_bias, slope_bias) = WIREFRAME_OVERLAY_DEPTH_BIAS;
+                        let bias_sign = if self.reverse_z { 1.0 } else { -1.0 };
+                        self.core.device.cmd_set_depth_bias(command_buffer, bias_sign * constant_bias, 0.0, bias_sign * slope_bias);
                         
-                        let push_bytes = bytemuck::bytes_of(&mvp);
-                        
-                        self.core.device.cmd_push_constants(
-                            command_buffer,
-                            pipeline_layout,
-                            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                            0,
-                            push_bytes,
-                        );
+                        self.core.device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
+                        bind_mesh_index_buffer(&self.core.device, command_buffer, mesh);
                         
-                        // Draw indexed
-                        self.core.device.cmd_draw_indexed(
-                            command_buffer,
-                            mesh.index_count,
-                            1,
-                            0,
-                            0,
-                            0,
-                        );
+                        // Instances are placed by the instance buffer, the pushed model is ignored
+                        let identity = [Mat4::IDENTITY];
+                        let transforms: &[Mat4] = if mesh.use_instancing { &identity } else { &mesh.transforms };
+                        for transform in transforms {
+                            let mvp = MvpPushConstants {
+                                model: transform.to_cols_array(),
+                                view: view.to_cols_array(),
+                                proj: proj.to_cols_array(),
+                                base_color: color,
+                            };
+                            self.push_constants_checked(
+                                command_buffer,
+                                current_pipeline_name,
+                                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                                bytemuck::bytes_of(&mvp),
+                            );
+                            
+                            if !mesh.use_instancing {
+                                draw_mesh(&self.core.device, command_buffer, mesh, 1);
+                            } else if let Some(ref culling) = mesh.instance_culling {
+                                culling.record_draw(&self.core.device, command_buffer, self.core.current_frame, mesh.index_count);
+                            } else if let Some(instance_buffer) = mesh.instance_buffer {
+                                self.core.device.cmd_bind_vertex_buffers(command_buffer, 1, &[instance_buffer], &[0]);
+                                draw_mesh(&self.core.device, command_buffer, mesh, mesh.instance_count);
+                            }
+                        }
                     }
                 }
             }
-            
+        }
+    }
+    
+    fn record_fallback_draw(&self, command_buffer: vk::CommandBuffer, view: Mat4, proj: Mat4) {
+        unsafe {
             // Fallback: render using the old buffers if meshes are empty but buffers exist
             if self.meshes.is_empty() && self.buffers.is_some() {
                 // Use the old hardcoded model for backwards compatibility
@@ -4052,11 +9856,10 @@ impl VulkanRenderer {
                         );
                         
                         let push_bytes = bytemuck::bytes_of(&mvp);
-                        self.core.device.cmd_push_constants(
+                        self.push_constants_checked(
                             command_buffer,
-                            self.pipeline_layout,
+                            &self.current_pipeline,
                             vk::ShaderStageFlags::VERTEX,
-                            0,
                             push_bytes,
                         );
                         
@@ -4071,49 +9874,6 @@ impl VulkanRenderer {
                     }
                 }
             }
-            
-            // Render egui if provided
-            if let Some(egui_output) = egui_output {
-                if let Some(ref mut egui_integration) = self.egui_integration {
-                    // Update textures before rendering
-                    if !egui_output.textures_delta.set.is_empty() {
-                        if let Err(e) = egui_integration.renderer.set_textures(
-                            self.core.graphics_queue,
-                            self.core.command_pool,
-                            egui_output.textures_delta.set.as_slice(),
-                        ) {
-                            eprintln!("Failed to set egui textures: {}", e);
-                        }
-                    }
-                    
-                    let clipped_primitives = egui_integration.context.tessellate(
-                        egui_output.shapes,
-                        egui_output.pixels_per_point,
-                    );
-                    
-                    if let Err(e) = egui_integration.renderer.cmd_draw(
-                        command_buffer,
-                        self.core.swapchain_extent,
-                        egui_output.pixels_per_point,
-                        &clipped_primitives,
-                    ) {
-                        eprintln!("Failed to render egui: {}", e);
-                    }
-                    
-                    // Free removed textures
-                    if !egui_output.textures_delta.free.is_empty() {
-                        if let Err(e) = egui_integration.renderer.free_textures(&egui_output.textures_delta.free) {
-                            eprintln!("Failed to free egui textures: {}", e);
-                        }
-                    }
-                }
-            }
-            
-            self.core.device.cmd_end_render_pass(command_buffer);
-            
-            self.core.device
-                .end_command_buffer(command_buffer)
-                .expect("Failed to end command buffer");
         }
     }
     
@@ -4130,8 +9890,12 @@ impl VulkanRenderer {
             base_color: [1.0, 1.0, 1.0, 1.0], // Default white
         };
         
-        let mut config = RenderConfig::default();
-        config.clear_color = CLEAR_COLOR_MAGENTA;
+        let mut config = RenderConfig {
+            depth_clear_value: self.depth_clear_value(),
+            depth_compare_op: self.depth_compare_op(),
+            ..Default::default()
+        };
+        config.clear_color = self.clear_color;
         
         // Set resources
         if let Some(ref buffers) = self.buffers {
@@ -4181,7 +9945,7 @@ impl VulkanRenderer {
             if self.has_depth {
                 clear_values.push(vk::ClearValue {
                     depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
+                        depth: self.depth_clear_value(),
                         stencil: 0,
                     },
                 });
@@ -4192,15 +9956,16 @@ impl VulkanRenderer {
                 .framebuffer(framebuffer)
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: self.core.swapchain_extent,
+                    extent: self.core.render_extent,
                 })
                 .clear_values(&clear_values);
             
             self.core.device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+            set_viewport_and_scissor(&self.core.device, command_buffer, self.core.render_extent);
             
             // Draw main geometry
             if self.graphics_pipeline != vk::Pipeline::null() {
-                self.core.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.graphics_pipeline);
+                bind_graphics_pipeline(&self.core.device, command_buffer, self.graphics_pipeline, self.depth_compare_op());
                 
                 // Bind descriptor sets if available
                 if !config.descriptor_sets.is_empty() {
@@ -4216,11 +9981,10 @@ impl VulkanRenderer {
                 
                 // Push constants
                 if let Some(push_data) = config.push_constant_data {
-                    self.core.device.cmd_push_constants(
+                    self.push_constants_checked(
                         command_buffer,
-                        self.pipeline_layout,
+                        &self.current_pipeline,
                         vk::ShaderStageFlags::VERTEX,
-                        0,
                         push_data,
                     );
                 }
@@ -4258,15 +10022,19 @@ impl VulkanRenderer {
                 }
             }
             
-            // Draw egui if output is provided
+            end_scene_render_pass(&self.core.device, command_buffer);
+            
+            self.begin_present_pass(command_buffer, image_index);
+            
+            // Draw egui on top of the tonemapped scene if output is provided
             if let (Some(egui_integration), Some(output)) = (&mut self.egui_integration, egui_output) {
-                // Paint egui inside the render pass
+                // Paint egui inside the present pass
                 if let Err(e) = egui_integration.paint(
                     command_buffer,
                     self.core.swapchain_extent,
                     output,
                 ) {
-                    eprintln!("Failed to paint egui: {}", e);
+                    error!("Failed to paint egui: {}", e);
                 }
             }
             
@@ -4278,16 +10046,224 @@ impl VulkanRenderer {
         }
     }
     
+    // Sky and geometry subpass meshes of the fluid pass. Water is left out of the reflection pass
+    // (push_constants.reflection_pass set), and samples the reflection in the main pass while the
+    // camera is above it.
+    fn record_fluid_scene(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        fluid_push_constants: &PushConstants,
+        extent: vk::Extent2D,
+    ) {
+        let reflection_pass = fluid_push_constants.reflection_pass > 0.0;
+        let reflections = self.water_reflections.as_ref().filter(|_| {
+            !reflection_pass && fluid_push_constants.camera_position_y > fluid_push_constants.water_level
+        });
+        
+        unsafe {
+            // First, render the sky background (if sky pipeline exists)
+            if let Some(sky_pipeline_entry) = self.pipelines.get("sky") {
+                // The sky shader writes the far plane's depth of 1, which only passes the depth
+                // clear with LESS_OR_EQUAL
+                let sky_depth_compare_op = if self.reverse_z { self.depth_compare_op() } else { vk::CompareOp::LESS_OR_EQUAL };
+                bind_graphics_pipeline(&self.core.device, command_buffer, sky_pipeline_entry.pipeline, sky_depth_compare_op);
+                
+                // Push constants for sky
+                let sky_push_constants = SkyPushConstants::new(*fluid_push_constants, self.sky_gradient.as_ref());
+                let push_bytes = bytemuck::bytes_of(&sky_push_constants);
+                self.push_constants_checked(
+                    command_buffer,
+                    "sky",
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    push_bytes,
+                );
+                
+                // Draw fullscreen triangle for sky (3 vertices, no vertex buffer needed)
+                self.core.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                
+                // Depth 1 is the near plane under reverse-Z. Reset depth so the sky stays behind
+                // everything drawn after it.
+                if self.reverse_z {
+                    clear_attachments(
+                        &self.core.device,
+                        command_buffer,
+                        ClearFlags::DEPTH,
+                        vk::Rect2D {
+                            offset: vk::Offset2D { x: 0, y: 0 },
+                            extent,
+                        },
+                        self.clear_color,
+                        self.depth_clear_value(),
+                        self.core.depth_format,
+                    );
+                }
+            }
+            
+            // Then render each mesh with the appropriate pipeline
+            for (_mesh_idx, mesh) in self.meshes.iter().enumerate() {
+                // Skip meshes with no transforms, and lighting subpass meshes the fluid pass doesn't draw
+                if (!mesh.use_instancing && mesh.transforms.is_empty()) || mesh.subpass != GEOMETRY_SUBPASS {
+                    continue;
+                }
+                // Skip instanced meshes that have no instances yet
+                if mesh.use_instancing && mesh.instance_count == 0 {
+                    continue;
+                }
+                
+                // Determine which pipeline to use
+                let pipeline_name = mesh.pipeline_name.as_ref()
+                    .map(|s| s.as_str())
+                    .unwrap_or("water"); // Default to water pipeline for fluid sim
+                
+                // The reflection only shows what's above the water
+                if reflection_pass && (pipeline_name == "water" || self.water_tessellation.contains_key(pipeline_name)) {
+                    continue;
+                }
+                
+                // Get the pipeline
+                let (pipeline, pipeline_layout) = if let Some(pipeline_entry) = self.pipelines.get(pipeline_name) {
+                    (pipeline_entry.pipeline, pipeline_entry.layout)
+                } else {
+                    warn!("Pipeline '{}' not found, skipping its meshes", pipeline_name);
+                    continue;
+                };
+                
+                // Bind the pipeline, water sampling the reflection if there is one
+                match reflections.filter(|_| pipeline_name == "water") {
+                    Some(reflections) => {
+                        bind_graphics_pipeline(&self.core.device, command_buffer, reflections.pipeline, self.depth_compare_op());
+                        self.core.device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            reflections.layout,
+                            0,
+                            &[reflections.target.descriptor_set],
+                            &[],
+                        );
+                    }
+                    None => bind_graphics_pipeline(&self.core.device, command_buffer, pipeline, self.depth_compare_op()),
+                }
+                
+                // Bind descriptor sets if this is a textured pipeline
+                if let Some(textured_resources) = self.textured_pipelines.get(pipeline_name) {
+                    self.core.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline_layout,
+                        0,
+                        &[textured_resources.descriptor_sets[image_index]],
+                        &[],
+                    );
+                }
+                
+                // Push the fluid constants, tessellated water also gets its tessellation settings
+                let mesh_push_constants = PushConstants {
+                    time: fluid_push_constants.time + mesh.time_offset,
+                    ..*fluid_push_constants
+                };
+                if let Some(tessellation) = self.water_tessellation.get(pipeline_name) {
+                    let tessellation_push_constants = WaterTessellationPushConstants::new(mesh_push_constants, tessellation);
+                    self.push_constants_checked(
+                        command_buffer,
+                        pipeline_name,
+                        WATER_TESSELLATION_STAGES,
+                        bytemuck::bytes_of(&tessellation_push_constants),
+                    );
+                } else {
+                    let push_bytes = bytemuck::bytes_of(&mesh_push_constants);
+                    self.push_constants_checked(
+                        command_buffer,
+                        pipeline_name,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        push_bytes,
+                    );
+                }
+                
+                // Bind vertex and index buffers
+                set_mesh_depth_state(&self.core.device, command_buffer, mesh, self.depth_compare_op(), self.reverse_z);
+                bind_mesh_vertex_buffers(&self.core.device, command_buffer, mesh);
+                bind_mesh_index_buffer(&self.core.device, command_buffer, mesh);
+                
+                // Draw the mesh
+                if mesh.use_instancing && mesh.instance_buffer.is_some() {
+                    draw_mesh(&self.core.device, command_buffer, mesh, mesh.instance_count);
+                } else {
+                    // Draw once for each transform
+                    let num_instances = mesh.transforms.len().max(1) as u32;
+                    draw_mesh(&self.core.device, command_buffer, mesh, num_instances);
+                }
+            }
+        }
+    }
+    
+    // Draws the scene above the water from the camera mirrored across the water level into the
+    // reflection target. The mirrored image is upside down, water_reflection.frag flips it back.
+    // Nothing to reflect while the camera is under water.
+    fn record_water_reflection(&self, command_buffer: vk::CommandBuffer, image_index: usize, fluid_push_constants: &PushConstants) {
+        let Some(ref reflections) = self.water_reflections else {
+            return;
+        };
+        let water_level = fluid_push_constants.water_level;
+        if fluid_push_constants.camera_position_y <= water_level {
+            return;
+        }
+        // Same resolution as the main pass, so the projection's aspect ratio matches
+        let mirrored_push_constants = PushConstants {
+            camera_position_y: 2.0 * water_level - fluid_push_constants.camera_position_y,
+            reflection_pass: 1.0,
+            ..*fluid_push_constants
+        };
+        
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: self.clear_color,
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.depth_clear_value(),
+                    stencil: 0,
+                },
+            },
+        ];
+        let extent = reflections.target.extent;
+        let render_pass_info = vk::RenderPassBeginInfo::default()
+            .render_pass(reflections.target.render_pass)
+            .framebuffer(reflections.target.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            })
+            .clear_values(&clear_values);
+        
+        unsafe {
+            self.core.device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+            set_viewport_and_scissor(&self.core.device, command_buffer, extent);
+            self.record_fluid_scene(command_buffer, image_index, &mirrored_push_constants, extent);
+            end_scene_render_pass(&self.core.device, command_buffer);
+        }
+    }
+    
     fn record_command_buffer_fluid(
-        &self, 
+        &mut self, 
         image_index: u32, 
-        _view: Mat4, 
-        _proj: Mat4,
+        view: Mat4, 
+        proj: Mat4,
         fluid_push_constants: &PushConstants,
+        egui_output: Option<egui::FullOutput>,
     ) {
         let command_buffer = self.core.command_buffers[image_index as usize];
         let framebuffer = self.core.framebuffers[image_index as usize];
         
+        // The fluid shaders build their projection from the resolution, so use the viewport's
+        let mut fluid_push_constants = *fluid_push_constants;
+        if let Some(viewport) = self.viewport {
+            fluid_push_constants.resolution = [viewport.extent.width as f32, viewport.extent.height as f32];
+        }
+        let fluid_push_constants = &fluid_push_constants;
+        
         let begin_info = vk::CommandBufferBeginInfo::default();
         
         unsafe {
@@ -4295,6 +10271,10 @@ impl VulkanRenderer {
                 .begin_command_buffer(command_buffer, &begin_info)
                 .expect("Failed to begin recording command buffer");
             
+            self.record_vertex_uploads(command_buffer);
+            self.record_particle_simulation(command_buffer);
+            self.record_water_reflection(command_buffer, image_index as usize, fluid_push_constants);
+            
             let clear_values = [
                 vk::ClearValue {
                     color: vk::ClearColorValue {
@@ -4303,7 +10283,7 @@ impl VulkanRenderer {
                 },
                 vk::ClearValue {
                     depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
+                        depth: self.depth_clear_value(),
                         stencil: 0,
                     },
                 },
@@ -4314,7 +10294,7 @@ impl VulkanRenderer {
                 .framebuffer(framebuffer)
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: self.core.swapchain_extent,
+                    extent: self.core.render_extent,
                 })
                 .clear_values(&clear_values);
             
@@ -4323,116 +10303,26 @@ impl VulkanRenderer {
                 &render_pass_info,
                 vk::SubpassContents::INLINE,
             );
+            self.set_scene_viewport(command_buffer);
             
-            // First, render the sky background (if sky pipeline exists)
-            if let Some(sky_pipeline_entry) = self.pipelines.get("sky") {
-                // Bind sky pipeline
-                self.core.device.cmd_bind_pipeline(
-                    command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    sky_pipeline_entry.pipeline,
-                );
-                
-                // Push constants for sky
-                let push_bytes = bytemuck::bytes_of(fluid_push_constants);
-                self.core.device.cmd_push_constants(
-                    command_buffer,
-                    sky_pipeline_entry.layout,
-                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                    0,
-                    push_bytes,
-                );
-                
-                // Draw fullscreen triangle for sky (3 vertices, no vertex buffer needed)
-                self.core.device.cmd_draw(command_buffer, 3, 1, 0, 0);
-            }
+            self.record_fluid_scene(command_buffer, image_index as usize, fluid_push_constants, self.core.render_extent);
             
-            // Then render each mesh with the appropriate pipeline
-            for (_mesh_idx, mesh) in self.meshes.iter().enumerate() {
-                // Skip meshes with no transforms
-                if !mesh.use_instancing && mesh.transforms.is_empty() {
-                    continue;
-                }
-                
-                // Determine which pipeline to use
-                let pipeline_name = mesh.pipeline_name.as_ref()
-                    .map(|s| s.as_str())
-                    .unwrap_or("water"); // Default to water pipeline for fluid sim
-                
-                // Get the pipeline
-                let (pipeline, pipeline_layout) = if let Some(pipeline_entry) = self.pipelines.get(pipeline_name) {
-                    (pipeline_entry.pipeline, pipeline_entry.layout)
-                } else {
-                    // Fall back to default pipeline if not found
-                    println!("WARNING: Pipeline '{}' not found, using default", pipeline_name);
-                    continue;
-                };
-                
-                // Bind the pipeline
-                self.core.device.cmd_bind_pipeline(
-                    command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    pipeline,
-                );
-                
-                // Bind descriptor sets if this is a textured pipeline
-                if let Some(textured_resources) = self.textured_pipelines.get(pipeline_name) {
-                    self.core.device.cmd_bind_descriptor_sets(
-                        command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        pipeline_layout,
-                        0,
-                        &[textured_resources.descriptor_sets[image_index as usize]],
-                        &[],
-                    );
-                }
-                
-                // Push the fluid constants
-                let push_bytes = bytemuck::bytes_of(fluid_push_constants);
-                self.core.device.cmd_push_constants(
-                    command_buffer,
-                    pipeline_layout,
-                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                    0,
-                    push_bytes,
-                );
-                
-                // Bind vertex and index buffers
-                self.core.device.cmd_bind_vertex_buffers(
-                    command_buffer,
-                    0,
-                    &[mesh.vertex_buffer],
-                    &[0],
-                );
-                
-                self.core.device.cmd_bind_index_buffer(
+            self.record_ground_grid_draw(command_buffer, view, proj);
+            self.record_particle_draw(command_buffer, view, proj);
+            self.record_immediate_draws(command_buffer, view, proj);
+            
+            end_scene_render_pass(&self.core.device, command_buffer);
+            
+            self.begin_present_pass(command_buffer, image_index);
+            
+            // Draw egui on top of the tonemapped water if output is provided
+            if let (Some(egui_integration), Some(output)) = (&mut self.egui_integration, egui_output) {
+                if let Err(e) = egui_integration.paint(
                     command_buffer,
-                    mesh.index_buffer,
-                    0,
-                    vk::IndexType::UINT32,
-                );
-                
-                // Draw the mesh
-                if mesh.use_instancing && mesh.instance_buffer.is_some() {
-                    self.core.device.cmd_draw_indexed(
-                        command_buffer,
-                        mesh.index_count,
-                        mesh.instance_count,
-                        0,
-                        0,
-                        0,
-                    );
-                } else {
-                    // Draw once for each transform
-                    let num_instances = mesh.transforms.len().max(1) as u32;
-                    self.core.device.cmd_draw_indexed(
-                        command_buffer,
-                        mesh.index_count,
-                        num_instances,
-                        0,
-                        0,
-                        0,
-                    );
+                    self.core.swapchain_extent,
+                    output,
+                ) {
+                    error!("Failed to paint egui: {}", e);
                 }
             }
             
@@ -4443,177 +10333,1232 @@ impl VulkanRenderer {
         }
     }
     
-    // Get render pass for external use
+    // Scene render pass (HDR color target), for creating pipelines outside the renderer
     pub fn get_render_pass(&self) -> vk::RenderPass {
         self.core.render_pass
     }
     
+    // Swapchain render pass the tonemapped scene and egui are drawn in, for initialize_egui
+    pub fn get_present_render_pass(&self) -> vk::RenderPass {
+        self.core.present.render_pass
+    }
+    
+    // Number of swapchain images. Per image resources (command buffers, texture descriptor
+    // sets) come in this many copies, indexed by current_image_index.
+    pub fn swapchain_image_count(&self) -> usize {
+        self.core.swapchain_images.len()
+    }
+    
+    // Frame in flight slot, 0..MAX_FRAMES_IN_FLIGHT. Per frame resources (fences, semaphores,
+    // occlusion queries) are indexed by this. Advances when a frame is presented.
+    pub fn current_frame_index(&self) -> usize {
+        self.core.current_frame
+    }
+    
+    // Waits until the GPU is done with the last frame that used the current frame in flight
+    // slot and returns the slot, so per frame user data (see FramePool) can be rewritten before
+    // the next render_frame_* call. Not for use inside hooks: the renderer has already waited
+    // by then and the fence won't signal until the frame is submitted.
+    pub fn wait_for_current_frame(&self) -> Result<usize, FloError> {
+        self.check_lost()?;
+        let frame = self.core.current_frame;
+        unsafe {
+            self.core.device.wait_for_fences(&[self.core.in_flight_fences[frame]], true, u64::MAX)?;
+        }
+        Ok(frame)
+    }
+    
+    // Swapchain image the current (or, between frames, the last) frame renders to
+    pub fn current_image_index(&self) -> u32 {
+        self.core.current_image_index
+    }
+    
+    // Scene framebuffer of current_image_index, compatible with get_render_pass. Its color
+    // attachment is the HDR image the present pass tonemaps, so passes drawn into it between
+    // the scene and the present pass show up in the frame.
+    pub fn current_framebuffer(&self) -> vk::Framebuffer {
+        self.core.framebuffers[self.core.current_image_index as usize]
+    }
+    
+    // Size of the swapchain, and of the scene framebuffers unless a resolution scale shrank
+    // them (see render_extent). Changes when resize recreates the swapchain for a resized window.
+    pub fn swapchain_extent(&self) -> vk::Extent2D {
+        self.core.swapchain_extent
+    }
+    
+    // Format of the scene framebuffers' color attachment (HDR_FORMAT). The swapchain images
+    // themselves only receive the tonemapped result and are in swapchain_format.
+    pub fn color_format(&self) -> vk::Format {
+        HDR_FORMAT
+    }
+    
+    pub fn swapchain_format(&self) -> vk::Format {
+        self.core.swapchain_format
+    }
+    
+    // Logical device, for creating pipelines, buffers and passes outside the renderer. Objects
+    // made with it must be destroyed before the renderer is dropped.
+    pub fn device(&self) -> &ash::Device {
+        &self.core.device
+    }
+    
+    // Device, instance and queues to create another renderer on, e.g. for a second window, by
+    // passing it as CoreOptions::shared_device to new_multi_mesh_with_options. Each renderer
+    // keeps its own swapchain, frames and resources, the device lives until the last of them is
+    // dropped.
+    pub fn shared_device(&self) -> SharedDevice {
+        self.core.shared_device()
+    }
+    
+    // Starts the present pass after the scene pass has ended. Callers draw any overlays,
+    // then end the render pass.
+    // Runs the post effects, if any, then begins the present pass on their output
+    fn begin_present_pass(&self, command_buffer: vk::CommandBuffer, image_index: u32) {
+        // Before the post effects, which read the scene image in place
+        if let Some(ref previous_frame) = self.previous_frame {
+            previous_frame.record_copy(&self.core.device, command_buffer, self.core.hdr_images[image_index as usize]);
+        }
+        // Debug views show the scene image as drawn
+        let source = match self.post_process {
+            Some(ref chain) if self.debug_view == DebugView::None => {
+                chain.record(&self.core.device, command_buffer, image_index as usize)
+            }
+            _ => self.core.present.descriptor_sets[image_index as usize],
+        };
+        let (tonemap, exposure) = match self.debug_view {
+            DebugView::None => (self.tonemap, self.exposure),
+            _ => (Tonemap::None, 1.0),
+        };
+        self.core.present.begin(
+            &self.core.device,
+            command_buffer,
+            image_index as usize,
+            source,
+            self.core.swapchain_extent,
+            tonemap,
+            exposure,
+            // Nothing to sharpen at full resolution
+            if self.core.render_extent == self.core.swapchain_extent { UpscaleFilter::Bilinear } else { self.upscale_filter },
+            self.debug_view == DebugView::Overdraw,
+        );
+    }
+    
     // Get egui context for UI code
     pub fn get_egui_context(&mut self) -> Option<&egui::Context> {
         self.egui_integration.as_ref().map(|i| &i.context)
     }
     
-    // Initialize egui integration
-    pub fn initialize_egui(&mut self, render_pass: vk::RenderPass) -> Result<(), Box<dyn std::error::Error>> {
-        let egui_integration = EguiIntegration::new(
-            &self.core.instance,
-            self.core.physical_device,
-            self.core.device.clone(),
-            render_pass,
-            self.core.graphics_queue,
-            self.core.command_pool,
-        )?;
+    // Initialize egui integration
+    pub fn initialize_egui(&mut self, render_pass: vk::RenderPass) -> Result<(), FloError> {
+        let mut egui_integration = EguiIntegration::new(
+            &self.core.instance,
+            self.core.physical_device,
+            self.core.device.clone(),
+            render_pass,
+            self.core.graphics_queue,
+            self.core.command_pool,
+        )?;
+        
+        let extent = self.core.swapchain_extent;
+        egui_integration.update_swapchain(extent.width, extent.height, 1.0);
+        self.egui_integration = Some(egui_integration);
+        Ok(())
+    }
+    
+    
+    // Render frame with egui support
+    pub fn render_frame_with_egui(
+        &mut self,
+        view: Mat4,
+        proj: Mat4,
+        egui_output: Option<egui::FullOutput>,
+    ) {
+        self.process_texture_uploads();
+        self.update_dynamic_resolution();
+        self.resolve_mesh_hierarchy();
+        
+        let image_index = match self.core.begin_frame() {
+            Ok(index) => index,
+            Err(e) => {
+                report_frame_error("begin", &e);
+                return;
+            }
+        };
+        
+        // Check if we have meshes with transforms - if so, use multi-mesh rendering
+        if !self.meshes.is_empty() && self.meshes.iter().any(|m| !m.transforms.is_empty()) {
+            self.record_command_buffer_multi_mesh_with_egui(image_index, view, proj, egui_output);
+        } else {
+            // Use the old function for backwards compatibility
+            self.record_command_buffer_with_push_data_and_egui(image_index, view, proj, egui_output);
+        }
+        
+        if let Err(e) = self.core.end_frame(image_index) {
+            report_frame_error("end", &e);
+        }
+    }
+    
+    // Update egui's size and scale when the window resizes or its scale factor changes
+    pub fn update_egui_swapchain(&mut self, width: u32, height: u32, scale_factor: f32) {
+        if let Some(egui_integration) = &mut self.egui_integration {
+            egui_integration.update_swapchain(width, height, scale_factor);
+        }
+    }
+    
+    // Scale egui on top of the window's scale factor, see EguiIntegration::set_ui_scale
+    pub fn set_egui_ui_scale(&mut self, factor: f32) -> Result<(), FloError> {
+        let egui_integration = self.egui_integration.as_mut()
+            .ok_or("egui isn't initialized, call initialize_egui first")?;
+        egui_integration.set_ui_scale(factor);
+        Ok(())
+    }
+}
+
+// World transforms of parented meshes from their local ones, without a device. Meshes are
+// visited by depth so a parent is resolved before its children. Links have to be acyclic, as
+// set_mesh_parent keeps them.
+pub fn resolve_world_transforms(meshes: &mut [MeshEntry]) {
+    let depth = |mut index: usize| {
+        let mut depth = 0;
+        while let Some(parent) = meshes[index].parent {
+            index = parent;
+            depth += 1;
+        }
+        depth
+    };
+    let mut order: Vec<(usize, usize)> = (0..meshes.len())
+        .filter(|&index| meshes[index].parent.is_some())
+        .map(|index| (depth(index), index))
+        .collect();
+    order.sort_unstable();
+
+    for (_, index) in order {
+        let parent_transform = meshes[index].parent.and_then(|parent| meshes[parent].transforms.first().copied());
+        let mesh = &mut meshes[index];
+        mesh.transforms = match parent_transform {
+            Some(parent_transform) => mesh.local_transforms.iter().map(|local| parent_transform * *local).collect(),
+            None => Vec::new(),
+        };
+    }
+}
+
+// Whether parenting mesh_index to parent closes a loop, when the mesh is the parent itself or
+// one of its ancestors
+fn makes_parent_cycle(meshes: &[MeshEntry], mesh_index: usize, parent: usize) -> bool {
+    // Links are checked as they're made, so walking up from the parent always ends
+    let mut ancestor = Some(parent);
+    while let Some(index) = ancestor {
+        if index == mesh_index {
+            return true;
+        }
+        ancestor = meshes[index].parent;
+    }
+    false
+}
+
+// The user's cap clamped to the device limit, and at least 1 so layers are never empty
+fn capped_texture_size(device_limit: u32, max_texture_size: Option<u32>) -> u32 {
+    max_texture_size.map_or(device_limit, |max| max.min(device_limit)).max(1)
+}
+
+// Empty buffers can't be created in Vulkan, so catch empty meshes up front with a clear error
+// instead of a VK_ERROR from deep inside buffer creation
+fn validate_mesh_geometry(vertex_count: usize, index_count: usize) -> Result<(), FloError> {
+    if vertex_count == 0 {
+        return Err("Mesh has no vertices".into());
+    }
+    if index_count == 0 {
+        return Err("Mesh has no indices".into());
+    }
+    Ok(())
+}
+
+// add_mesh and replace_mesh also take meshes without indices, drawn from their vertices in order
+fn mesh_draw_mode(mesh_data: &MeshData) -> Result<MeshDrawMode, FloError> {
+    if mesh_data.vertices.is_empty() {
+        return Err("Mesh has no vertices".into());
+    }
+    if mesh_data.indices.is_empty() {
+        Ok(MeshDrawMode::NonIndexed)
+    } else {
+        Ok(MeshDrawMode::Indexed)
+    }
+}
+
+// Host visible per-instance position buffer. Returns None for an empty instance list,
+// the mesh just isn't drawn until it gets instances.
+fn create_instance_buffer(
+    instance: &ash::Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    instance_positions: &[[f32; 3]],
+) -> Result<Option<(vk::Buffer, vk::DeviceMemory)>, FloError> {
+    if instance_positions.is_empty() {
+        return Ok(None);
+    }
+    
+    let size = std::mem::size_of_val(instance_positions);
+    let (instance_buffer, instance_buffer_memory) = create_buffer(
+        instance,
+        device,
+        physical_device,
+        size as vk::DeviceSize,
+        vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    
+    unsafe {
+        let data = device.map_memory(
+            instance_buffer_memory,
+            0,
+            size as vk::DeviceSize,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        std::ptr::copy_nonoverlapping(
+            instance_positions.as_ptr() as *const u8,
+            data as *mut u8,
+            size,
+        );
+        device.unmap_memory(instance_buffer_memory);
+    }
+    
+    Ok(Some((instance_buffer, instance_buffer_memory)))
+}
+
+// The CPU side of remove_instance: entries past the count of drawn instances are stale and
+// dropped, then the last drawn one takes the removed one's place
+fn swap_remove_instance<T>(instances: &mut Vec<T>, count: usize, index: usize) {
+    instances.truncate(count);
+    instances.swap_remove(index);
+}
+
+// Combines queued uploads that overlap in the same buffer into one, later data on top, so each
+// byte is written by a single copy. Uploads that don't overlap are left as they are.
+fn merge_overlapping_uploads(uploads: Vec<PendingVertexUpload>) -> Vec<PendingVertexUpload> {
+    let mut merged: Vec<PendingVertexUpload> = Vec::with_capacity(uploads.len());
+    for upload in uploads {
+        let end = |upload: &PendingVertexUpload| upload.offset + upload.data.len() as vk::DeviceSize;
+        let (overlapping, rest): (Vec<_>, Vec<_>) = merged.into_iter()
+            .partition(|other| other.buffer == upload.buffer && other.offset < end(&upload) && upload.offset < end(other));
+        merged = rest;
+        if overlapping.is_empty() {
+            merged.push(upload);
+            continue;
+        }
+        // Each overlaps the new upload, so together they cover one range without gaps
+        let start = overlapping.iter().map(|other| other.offset).fold(upload.offset, vk::DeviceSize::min);
+        let range_end = overlapping.iter().map(end).fold(end(&upload), vk::DeviceSize::max);
+        let mut data = vec![0u8; (range_end - start) as usize];
+        for part in overlapping.iter().chain(std::iter::once(&upload)) {
+            let at = (part.offset - start) as usize;
+            data[at..at + part.data.len()].copy_from_slice(&part.data);
+        }
+        merged.push(PendingVertexUpload { buffer: upload.buffer, offset: start, data });
+    }
+    merged
+}
+
+// Joint buffers hold exactly the rig's joints, at least one so there's always something to bind
+fn joint_buffer_size(joint_count: usize) -> vk::DeviceSize {
+    (std::mem::size_of::<Mat4>() * joint_count.max(1)) as vk::DeviceSize
+}
+
+// Rigs too big for maxUniformBufferRange have their joints read from a storage buffer instead
+fn joints_in_storage_buffer(instance: &ash::Instance, physical_device: vk::PhysicalDevice, joint_count: usize) -> bool {
+    let max_uniform_buffer_range = unsafe {
+        instance.get_physical_device_properties(physical_device)
+    }.limits.max_uniform_buffer_range;
+    joints_exceed_uniform_range(joint_count, max_uniform_buffer_range)
+}
+
+fn joints_exceed_uniform_range(joint_count: usize, max_uniform_buffer_range: u32) -> bool {
+    joint_buffer_size(joint_count) > max_uniform_buffer_range as vk::DeviceSize
+}
+
+// Host visible joint buffer filled with joint_matrices, usable as a uniform and a storage buffer
+fn create_joint_buffer(
+    instance: &ash::Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    joint_matrices: &[Mat4],
+) -> Result<(vk::Buffer, vk::DeviceMemory), FloError> {
+    let (joint_buffer, joint_buffer_memory) = create_buffer(
+        instance,
+        device,
+        physical_device,
+        joint_buffer_size(joint_matrices.len()),
+        vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    write_joint_matrices(device, joint_buffer_memory, 0, joint_matrices)?;
+    
+    Ok((joint_buffer, joint_buffer_memory))
+}
+
+// Copies joint_matrices to a joint buffer at offset (the mesh's joint_offset), callers keep them
+// within its joint count
+fn write_joint_matrices(device: &ash::Device, memory: vk::DeviceMemory, offset: u32, joint_matrices: &[Mat4]) -> Result<(), vk::Result> {
+    if joint_matrices.is_empty() {
+        return Ok(());
+    }
+    
+    unsafe {
+        let data = device.map_memory(
+            memory,
+            offset as vk::DeviceSize,
+            std::mem::size_of_val(joint_matrices) as vk::DeviceSize,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        std::ptr::copy_nonoverlapping(joint_matrices.as_ptr(), data as *mut Mat4, joint_matrices.len());
+        device.unmap_memory(memory);
+    }
+    
+    Ok(())
+}
+
+// Host visible storage buffer filled with data, for a mesh's morph deltas and weights
+fn create_morph_buffer<T: Copy>(
+    instance: &ash::Instance,
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    data: &[T],
+) -> Result<(vk::Buffer, vk::DeviceMemory), FloError> {
+    let (buffer, memory) = create_buffer(
+        instance,
+        device,
+        physical_device,
+        std::mem::size_of_val(data) as vk::DeviceSize,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    if let Err(e) = write_morph_buffer(device, memory, data) {
+        destroy_buffer(device, buffer, memory);
+        return Err(e.into());
+    }
+    Ok((buffer, memory))
+}
+
+// Copies data to the start of a morph buffer
+fn write_morph_buffer<T: Copy>(device: &ash::Device, memory: vk::DeviceMemory, data: &[T]) -> Result<(), vk::Result> {
+    unsafe {
+        let mapped = device.map_memory(memory, 0, std::mem::size_of_val(data) as vk::DeviceSize, vk::MemoryMapFlags::empty())?;
+        std::ptr::copy_nonoverlapping(data.as_ptr(), mapped as *mut T, data.len());
+        device.unmap_memory(memory);
+    }
+    Ok(())
+}
+
+// Binding 0: joint matrices as a uniform buffer
+// Binding 1: camera matrices
+// Binding 2: the same joint matrices as a storage buffer
+// The shaders read only one of the joint bindings (see skinned_vertex_specialization)
+// Binding 0 of set 0 for meshes from add_mesh_instanced_ssbo
+fn instance_matrix_layout_binding() -> vk::DescriptorSetLayoutBinding<'static> {
+    vk::DescriptorSetLayoutBinding::default()
+        .binding(0)
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+}
+
+// A single set over the whole instance buffer. It's only read during draws and the buffer is
+// updated with copies, so one set serves every frame in flight.
+fn create_instance_matrix_descriptors(device: &ash::Device, instance_buffer: vk::Buffer) -> Result<InstanceMatrixDescriptors, FloError> {
+    let descriptor_set_layout = create_descriptor_set_layout(device, &[instance_matrix_layout_binding()])?;
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::STORAGE_BUFFER,
+        descriptor_count: 1,
+    }];
+    let descriptor_pool = match create_descriptor_pool(device, 1, &pool_sizes) {
+        Ok(pool) => pool,
+        Err(e) => {
+            unsafe { leak_check::destroy(device, descriptor_set_layout) };
+            return Err(e);
+        }
+    };
+    let descriptor_set = match allocate_descriptor_sets(device, descriptor_pool, &[descriptor_set_layout]) {
+        Ok(sets) => sets[0],
+        Err(e) => {
+            unsafe {
+                leak_check::destroy(device, descriptor_pool);
+                leak_check::destroy(device, descriptor_set_layout);
+            }
+            return Err(e);
+        }
+    };
+    
+    let buffer_info = [vk::DescriptorBufferInfo::default()
+        .buffer(instance_buffer)
+        .offset(0)
+        .range(vk::WHOLE_SIZE)];
+    let write = vk::WriteDescriptorSet::default()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&buffer_info);
+    unsafe {
+        device.update_descriptor_sets(&[write], &[]);
+    }
+    
+    Ok(InstanceMatrixDescriptors { descriptor_pool, descriptor_set_layout, descriptor_set })
+}
+
+// Binding 3: morph target deltas, binding 4: morph target weights (see set_morph_weights)
+// Both joint bindings are dynamic so meshes in the joint arena share a set, bound with their
+// joint_offset for each. Meshes with their own joint buffer are bound with offset 0.
+fn skinned_descriptor_set_layout_bindings() -> [vk::DescriptorSetLayoutBinding<'static>; 5] {
+    [
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .stage_flags(vk::ShaderStageFlags::VERTEX),
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(1)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX),
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(2)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
+            .stage_flags(vk::ShaderStageFlags::VERTEX),
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(3)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX),
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(4)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX),
+    ]
+}
+
+fn skinned_descriptor_pool_sizes(set_count: u32) -> [vk::DescriptorPoolSize; 4] {
+    [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: set_count, // joints
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: set_count, // camera
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+            descriptor_count: set_count, // joints
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: set_count * 2, // morph deltas + weights
+        },
+    ]
+}
+
+// Points every set at the joint and camera buffers. Both joint bindings are written so the set
+// is complete, the uniform one only covers the first joint when the rig is read from storage.
+// Meshes without morph targets (morph_buffers None) get the joint buffer in the morph bindings,
+// which the shaders skip with a morph target count of 0.
+#[allow(clippy::too_many_arguments)]
+fn write_skinned_descriptor_sets(
+    device: &ash::Device,
+    descriptor_sets: &[vk::DescriptorSet],
+    joint_buffer: vk::Buffer,
+    joint_count: usize,
+    joints_in_storage: bool,
+    camera_buffer: vk::Buffer,
+    camera_buffer_size: vk::DeviceSize,
+    morph_buffers: Option<(vk::Buffer, vk::Buffer)>,
+) {
+    let (morph_delta_buffer, morph_weight_buffer) = morph_buffers.unwrap_or((joint_buffer, joint_buffer));
+
+    let joint_uniform_range = if joints_in_storage {
+        joint_buffer_size(1)
+    } else {
+        joint_buffer_size(joint_count)
+    };
+    
+    for &set in descriptor_sets {
+        let joint_uniform_info = vk::DescriptorBufferInfo::default()
+            .buffer(joint_buffer)
+            .offset(0)
+            .range(joint_uniform_range);
         
-        self.egui_integration = Some(egui_integration);
-        Ok(())
+        let camera_buffer_info = vk::DescriptorBufferInfo::default()
+            .buffer(camera_buffer)
+            .offset(0)
+            .range(camera_buffer_size);
+        
+        let joint_storage_info = vk::DescriptorBufferInfo::default()
+            .buffer(joint_buffer)
+            .offset(0)
+            .range(joint_buffer_size(joint_count));
+        
+        let morph_delta_info = vk::DescriptorBufferInfo::default()
+            .buffer(morph_delta_buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE);
+        
+        let morph_weight_info = vk::DescriptorBufferInfo::default()
+            .buffer(morph_weight_buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE);
+        
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(std::slice::from_ref(&joint_uniform_info)),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(std::slice::from_ref(&camera_buffer_info)),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(2)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
+                .buffer_info(std::slice::from_ref(&joint_storage_info)),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(3)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(std::slice::from_ref(&morph_delta_info)),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(4)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(std::slice::from_ref(&morph_weight_info)),
+        ];
+        
+        unsafe {
+            device.update_descriptor_sets(&descriptor_writes, &[]);
+        }
     }
+}
+
+// Fragment shaders that light both sides of a surface read TWO_SIDED_LIGHTING (constant_id 0),
+// shaders without it ignore the entry
+fn two_sided_lighting_specialization(two_sided_lighting: bool) -> [(u32, u32); 1] {
+    [(0, two_sided_lighting as vk::Bool32)]
+}
+
+// Pipeline, layout and push constant range of a mesh pipeline. Push constants are the MVP block
+// (view, proj, model and base_color, 208 bytes) followed by any custom fields.
+fn build_mesh_pipeline(
+    core: &VulkanCore,
+    name: &str,
+    has_depth: bool,
+    desc: &MeshPipelineDesc,
+    two_sided_lighting: bool,
+) -> Result<(vk::Pipeline, vk::PipelineLayout, vk::PushConstantRange), FloError> {
+    let push_constant_range = vk::PushConstantRange::default()
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(desc.push_constant_size.max(MVP_PUSH_CONSTANTS_SIZE));
     
+    // Identically defined to the layouts of the meshes' texture sets, so their sets are
+    // compatible with it. The pipeline layout doesn't need it once it's created.
+    let descriptor_set_layout = if desc.has_texture {
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        Some(create_descriptor_set_layout(&core.device, &[binding])?)
+    } else {
+        None
+    };
     
-    // Render frame with egui support
-    pub fn render_frame_with_egui(
-        &mut self,
-        view: Mat4,
-        proj: Mat4,
-        egui_output: Option<egui::FullOutput>,
-    ) {
-        let image_index = match self.core.begin_frame() {
-            Ok(index) => index,
-            Err(e) => {
-                eprintln!("Failed to begin frame: {}", e);
-                return;
-            }
-        };
-        
-        // Check if we have meshes with transforms - if so, use multi-mesh rendering
-        if !self.meshes.is_empty() && self.meshes.iter().any(|m| !m.transforms.is_empty()) {
-            self.record_command_buffer_multi_mesh_with_egui(image_index, view, proj, egui_output);
-        } else {
-            // Use the old function for backwards compatibility
-            self.record_command_buffer_with_push_data_and_egui(image_index, view, proj, egui_output);
+    let built = PipelineBuilder::new(
+        core.device.clone(),
+        &desc.vert_shader_path,
+        &desc.frag_shader_path,
+        core.swapchain_extent,
+        core.render_pass,
+    )
+    .and_then(|builder| {
+        let mut builder = builder
+            .with_name(name)
+            .with_vertex_input(desc.vertex_bindings.clone(), desc.vertex_attributes.clone())
+            .with_push_constants(vec![push_constant_range])
+            .with_depth_test(has_depth)
+            .with_cull_mode(desc.cull_mode)
+            .with_front_face(desc.front_face)
+            .with_blend_mode(desc.blend_mode)
+            .with_depth_write(desc.blend_mode == BlendMode::Opaque)
+            .with_alpha_to_coverage(desc.antialiasing.alpha_to_coverage)
+            .with_subpass(desc.subpass);
+        if let Some(layout) = descriptor_set_layout {
+            builder = builder.with_descriptor_sets(vec![layout]);
         }
-        
-        if let Err(e) = self.core.end_frame(image_index) {
-            eprintln!("Failed to end frame: {}", e);
+        if let Some(stencil) = desc.stencil {
+            builder = builder.with_stencil(stencil);
+        }
+        if let Some(min_sample_shading) = desc.antialiasing.min_sample_shading {
+            builder = builder.with_sample_shading(min_sample_shading);
+        }
+        if two_sided_lighting {
+            builder = builder.with_specialization(vk::ShaderStageFlags::FRAGMENT, &two_sided_lighting_specialization(true));
+        }
+        builder.build()
+    });
+    if let Some(layout) = descriptor_set_layout {
+        unsafe {
+            leak_check::destroy(&core.device, layout);
+        }
+    }
+    let (pipeline, layout) = built?;
+    Ok((pipeline, layout, push_constant_range))
+}
+
+// Skinned vertex shaders size their joint uniform array with JOINT_COUNT (constant_id 0),
+// read binding 2 instead when JOINTS_IN_STORAGE_BUFFER (constant_id 1) is set and blend dual
+// quaternions when DUAL_QUATERNION_SKINNING (constant_id 2) is set
+fn skinned_vertex_specialization(
+    joint_count: usize,
+    joints_in_storage: bool,
+    skinning_mode: SkinningMode,
+) -> [(u32, u32); 3] {
+    let uniform_joint_count = if joints_in_storage { 1 } else { joint_count.max(1) as u32 };
+    let dual_quaternion = skinning_mode == SkinningMode::DualQuaternion;
+    [
+        (0, uniform_joint_count),
+        (1, joints_in_storage as vk::Bool32),
+        (2, dual_quaternion as vk::Bool32),
+    ]
+}
+
+// Position, normal and uv inputs of the fluid pipelines (no vertex color)
+fn fluid_vertex_input(layout: MeshLayout) -> (Vec<vk::VertexInputBindingDescription>, Vec<vk::VertexInputAttributeDescription>) {
+    match layout {
+        MeshLayout::Interleaved => {
+            let binding_description = vk::VertexInputBindingDescription::default()
+                .binding(0)
+                .stride(std::mem::size_of::<Vertex>() as u32)
+                .input_rate(vk::VertexInputRate::VERTEX);
+            
+            let attribute_descriptions = vec![
+                vk::VertexInputAttributeDescription::default()
+                    .binding(0)
+                    .location(0)
+                    .format(vk::Format::R32G32B32_SFLOAT)
+                    .offset(offset_of!(Vertex, position) as u32),
+                vk::VertexInputAttributeDescription::default()
+                    .binding(0)
+                    .location(1)
+                    .format(vk::Format::R32G32B32_SFLOAT)
+                    .offset(offset_of!(Vertex, normal) as u32),
+                vk::VertexInputAttributeDescription::default()
+                    .binding(0)
+                    .location(2)
+                    .format(vk::Format::R32G32_SFLOAT)
+                    .offset(offset_of!(Vertex, uv) as u32),
+            ];
+            (vec![binding_description], attribute_descriptions)
+        }
+        MeshLayout::Deinterleaved => (layout.get_binding_descriptions(), layout.get_attribute_descriptions()),
+    }
+}
+
+// Destroys a mesh's vertex buffer and attribute streams and returns their memory to the pool.
+// Mapped meshes' buffers are aliases of their mapped copies, which are destroyed instead.
+unsafe fn destroy_mesh_vertex_buffers(device: &ash::Device, memory_pool: &mut MemoryPoolManager, mesh: &MeshEntry) {
+    if !mesh.mapped_vertex_buffers.is_empty() {
+        for mapped_buffer in &mesh.mapped_vertex_buffers {
+            mapped_buffer.destroy(device);
         }
+        return;
     }
     
-    // Update egui swapchain when window resizes
-    pub fn update_egui_swapchain(&mut self, width: u32, height: u32) {
-        if let Some(egui_integration) = &mut self.egui_integration {
-            egui_integration.update_swapchain(width, height);
+    leak_check::destroy(device, mesh.vertex_buffer);
+    if let Some(memory) = mesh.vertex_buffer_memory {
+        free_device_memory(device, memory);
+    } else if let Some(ref block) = mesh.vertex_memory_block {
+        memory_pool.free_buffer(block.clone());
+    }
+    if let Some(ref streams) = mesh.vertex_streams {
+        leak_check::destroy(device, streams.normal_buffer);
+        leak_check::destroy(device, streams.uv_buffer);
+        for block in [&streams.normal_memory_block, &streams.uv_memory_block].into_iter().flatten() {
+            memory_pool.free_buffer(block.clone());
         }
     }
 }
 
-impl Drop for VulkanRenderer {
-    fn drop(&mut self) {
+// Binds a mesh's vertex data, one buffer per attribute stream for deinterleaved meshes
+unsafe fn bind_mesh_vertex_buffers(device: &ash::Device, command_buffer: vk::CommandBuffer, mesh: &MeshEntry) {
+    if let Some(ref streams) = mesh.vertex_streams {
+        device.cmd_bind_vertex_buffers(
+            command_buffer,
+            0,
+            &[mesh.vertex_buffer, streams.normal_buffer, streams.uv_buffer],
+            &[0, 0, 0],
+        );
+    } else {
+        device.cmd_bind_vertex_buffers(
+            command_buffer,
+            0,
+            &[mesh.vertex_buffer],
+            &[0],
+        );
+    }
+}
+
+// No-op for non-indexed meshes, which have no index buffer
+// UINT32 indices make 0xFFFFFFFF (PRIMITIVE_RESTART_INDEX) the restart index for strip and fan meshes
+unsafe fn bind_mesh_index_buffer(device: &ash::Device, command_buffer: vk::CommandBuffer, mesh: &MeshEntry) {
+    if mesh.draw_mode == MeshDrawMode::Indexed {
+        device.cmd_bind_index_buffer(command_buffer, mesh.index_buffer, 0, vk::IndexType::UINT32);
+    }
+}
+
+// Set after binding the mesh's pipeline. Pipelines without depth testing or writing take these
+// as static state, so they can only turn depth off, never on. depth_compare_op is the renderer's.
+unsafe fn set_mesh_depth_state(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    mesh: &MeshEntry,
+    depth_compare_op: vk::CompareOp,
+    reverse_z: bool,
+) {
+    device.cmd_set_depth_test_enable(command_buffer, mesh.depth_test);
+    // Depth is read only in the lighting subpass
+    device.cmd_set_depth_write_enable(command_buffer, mesh.depth_write && mesh.subpass == GEOMETRY_SUBPASS);
+    let compare_op = match mesh.depth_compare_op {
+        Some(op) if reverse_z => match op {
+            vk::CompareOp::LESS => vk::CompareOp::GREATER,
+            vk::CompareOp::LESS_OR_EQUAL => vk::CompareOp::GREATER_OR_EQUAL,
+            vk::CompareOp::GREATER => vk::CompareOp::LESS,
+            vk::CompareOp::GREATER_OR_EQUAL => vk::CompareOp::LESS_OR_EQUAL,
+            op => op,
+        },
+        Some(op) => op,
+        None => depth_compare_op,
+    };
+    device.cmd_set_depth_compare_op(command_buffer, compare_op);
+    // Towards the camera, which is up the depth range with reverse-Z
+    let (constant_bias, slope_bias) = mesh.depth_bias.unwrap_or((0.0, 0.0));
+    let bias_sign = if reverse_z { 1.0 } else { -1.0 };
+    device.cmd_set_depth_bias_enable(command_buffer, mesh.depth_bias.is_some());
+    device.cmd_set_depth_bias(command_buffer, bias_sign * constant_bias, 0.0, bias_sign * slope_bias);
+    device.cmd_set_stencil_reference(command_buffer, vk::StencilFaceFlags::FRONT_AND_BACK, mesh.stencil_reference);
+}
+
+// Static meshes can be replayed from static scene mode's cached command buffers. Skinned
+// meshes push the time and write camera uniforms, culled meshes depend on per frame query
+// and compute results, and mapped meshes bind a different buffer each frame, so they're
+// recorded every frame.
+// Losses are reported once by VulkanCore when they happen, then every frame fails the same way
+// until VulkanRenderer::recover
+fn report_frame_error(stage: &str, e: &FloError) {
+    if !e.is_lost() {
+        error!("Failed to {} frame: {}", stage, e);
+    }
+}
+
+fn is_static_mesh(mesh: &MeshEntry) -> bool {
+    !mesh.is_skinned && !mesh.occlusion_culling && mesh.instance_culling.is_none()
+        && mesh.mapped_vertex_buffers.is_empty() && mesh.wind.is_none()
+        && mesh.uv_transform.is_none_or(|uv_transform| uv_transform.scroll == Vec2::ZERO)
+}
+
+// Viewport or scissor rectangle in pixels, which can't be empty
+fn pixel_rect(x: u32, y: u32, width: u32, height: u32) -> Result<vk::Rect2D, FloError> {
+    if width == 0 || height == 0 {
+        return Err(format!("Viewport and scissor need a nonzero size, got {}x{}", width, height).into());
+    }
+    Ok(vk::Rect2D {
+        offset: vk::Offset2D { x: x as i32, y: y as i32 },
+        extent: vk::Extent2D { width, height },
+    })
+}
+
+unsafe fn draw_mesh(device: &ash::Device, command_buffer: vk::CommandBuffer, mesh: &MeshEntry, instance_count: u32) {
+    match mesh.draw_mode {
+        MeshDrawMode::Indexed => device.cmd_draw_indexed(command_buffer, mesh.index_count, instance_count, 0, 0, 0),
+        MeshDrawMode::NonIndexed => device.cmd_draw(command_buffer, mesh.vertex_count, instance_count, 0, 0),
+    }
+}
+
+const BOUNDING_BOX_VERTEX_COUNT: u32 = 36;
+
+// Constant and slope scaled depth bias of wireframe overlays, negated without reverse-Z
+const WIREFRAME_OVERLAY_DEPTH_BIAS: (f32, f32) = (1.0, 1.0);
+
+// Depth bias of mark_as_decal, enough to keep a coplanar decal in front of its surface at
+// grazing angles
+const DECAL_DEPTH_BIAS: (f32, f32) = (1.0, 2.0);
+
+// Local space (min, max) of a mesh's vertices
+fn mesh_bounds(mesh_data: &MeshData) -> Option<([f32; 3], [f32; 3])> {
+    if mesh_data.vertices.is_empty() {
+        return None;
+    }
+    let (min, max) = mesh_data.bounds();
+    Some((min.into(), max.into()))
+}
+
+// Local space centroid and surface area of a mesh, see MeshData::centroid
+fn mesh_centroid(mesh_data: &MeshData) -> Option<([f32; 3], f32)> {
+    if mesh_data.vertices.is_empty() {
+        return None;
+    }
+    Some((mesh_data.centroid().into(), mesh_data.surface_area()))
+}
+
+// (min, max) of an instanced mesh's instance positions
+fn instance_bounds(instance_positions: &[[f32; 3]]) -> Option<([f32; 3], [f32; 3])> {
+    let first = Vec3::from(*instance_positions.first()?);
+    let (min, max) = instance_positions.iter().fold((first, first), |(min, max), &position| {
+        (min.min(Vec3::from(position)), max.max(Vec3::from(position)))
+    });
+    Some((min.into(), max.into()))
+}
+
+// World space (min, max) of a local box under a transform, from its 8 transformed corners
+fn transform_bounds(transform: &Mat4, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    let mut world_min = Vec3::splat(f32::INFINITY);
+    let mut world_max = Vec3::splat(f32::NEG_INFINITY);
+    for i in 0..8 {
+        let corner = Vec3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        let corner = transform.transform_point3(corner);
+        world_min = world_min.min(corner);
+        world_max = world_max.max(corner);
+    }
+    (world_min, world_max)
+}
+
+// Non-indexed triangle list covering the 6 faces of an axis aligned box
+fn bounding_box_vertices(min: [f32; 3], max: [f32; 3]) -> Vec<Vertex> {
+    let corner = |x: bool, y: bool, z: bool| {
+        [
+            if x { max[0] } else { min[0] },
+            if y { max[1] } else { min[1] },
+            if z { max[2] } else { min[2] },
+        ]
+    };
+    let faces = [
+        [corner(false, false, false), corner(true, false, false), corner(true, true, false), corner(false, true, false)],
+        [corner(false, false, true), corner(false, true, true), corner(true, true, true), corner(true, false, true)],
+        [corner(false, false, false), corner(false, true, false), corner(false, true, true), corner(false, false, true)],
+        [corner(true, false, false), corner(true, false, true), corner(true, true, true), corner(true, true, false)],
+        [corner(false, false, false), corner(false, false, true), corner(true, false, true), corner(true, false, false)],
+        [corner(false, true, false), corner(true, true, false), corner(true, true, true), corner(false, true, true)],
+    ];
+    
+    let mut vertices = Vec::with_capacity(BOUNDING_BOX_VERTEX_COUNT as usize);
+    for face in &faces {
+        for &i in &[0, 1, 2, 0, 2, 3] {
+            vertices.push(Vertex::new(face[i], [0.0, 0.0, 0.0], [0.0, 0.0]));
+        }
+    }
+    vertices
+}
+
+// Sets from the descriptor allocator go back to it, pools created with the textures are destroyed
+fn destroy_texture_resources(device: &ash::Device, descriptor_allocator: &mut DescriptorAllocator, textures: &TextureResources) {
+    release_descriptor_sets(device, descriptor_allocator, textures.descriptor_pool, &textures.descriptor_sets);
+    unsafe {
+        leak_check::destroy(device, textures.sampler);
+        leak_check::destroy(device, textures.image_view);
+        leak_check::destroy(device, textures.image);
+        free_device_memory(device, textures.image_memory);
+        leak_check::destroy(device, textures.descriptor_set_layout);
+    }
+}
+
+fn release_descriptor_sets(
+    device: &ash::Device,
+    descriptor_allocator: &mut DescriptorAllocator,
+    pool: vk::DescriptorPool,
+    sets: &[vk::DescriptorSet],
+) {
+    if !descriptor_allocator.free(device, pool, sets) {
+        unsafe {
+            leak_check::destroy(device, pool);
+        }
+    }
+}
+
+impl VulkanRenderer {
+    // Destroys everything the renderer created itself, meshes, per-frame buffers, pipelines and
+    // descriptors, taking each out as it goes so a second call finds nothing left (shutdown,
+    // then drop). The GPU has to be idle. The core's sync objects, swapchain, surface and device
+    // go when it drops afterwards.
+    fn destroy_resources(&mut self) {
         unsafe {
-            let _ = self.core.device.device_wait_idle();
-            
             // Clean up egui integration
             if let Some(mut egui_integration) = self.egui_integration.take() {
                 egui_integration.cleanup();
             }
             
             // Clean up texture resources
-            if let Some(ref textures) = self.textures {
-                self.core.device.destroy_sampler(textures.sampler, None);
-                self.core.device.destroy_image_view(textures.image_view, None);
-                self.core.device.destroy_image(textures.image, None);
-                self.core.device.free_memory(textures.image_memory, None);
-                self.core.device.destroy_descriptor_pool(textures.descriptor_pool, None);
-                self.core.device.destroy_descriptor_set_layout(textures.descriptor_set_layout, None);
+            if let Some(ref textures) = self.textures.take() {
+                destroy_texture_resources(&self.core.device, &mut self.descriptor_allocator, textures);
             }
             
             // Clean up texture array resources
-            if let Some(ref texture_arrays) = self.texture_arrays {
-                self.core.device.destroy_sampler(texture_arrays.texture_sampler, None);
-                self.core.device.destroy_image_view(texture_arrays.texture_array_view, None);
-                self.core.device.destroy_image(texture_arrays.texture_array, None);
-                self.core.device.free_memory(texture_arrays.texture_array_memory, None);
-                self.core.device.destroy_descriptor_pool(texture_arrays.descriptor_pool, None);
-                self.core.device.destroy_descriptor_set_layout(texture_arrays.descriptor_set_layout, None);
+            if let Some(ref texture_arrays) = self.texture_arrays.take() {
+                leak_check::destroy(&self.core.device, texture_arrays.texture_sampler);
+                leak_check::destroy(&self.core.device, texture_arrays.texture_array_view);
+                leak_check::destroy(&self.core.device, texture_arrays.texture_array);
+                free_device_memory(&self.core.device, texture_arrays.texture_array_memory);
+                leak_check::destroy(&self.core.device, texture_arrays.descriptor_pool);
+                leak_check::destroy(&self.core.device, texture_arrays.descriptor_set_layout);
             }
             
             // Clean up buffer resources
-            if let Some(ref buffers) = self.buffers {
-                self.core.device.destroy_buffer(buffers.vertex_buffer, None);
-                self.core.device.free_memory(buffers.vertex_buffer_memory, None);
+            if let Some(ref buffers) = self.buffers.take() {
+                leak_check::destroy(&self.core.device, buffers.vertex_buffer);
+                free_device_memory(&self.core.device, buffers.vertex_buffer_memory);
                 
                 if let Some(index_buffer) = buffers.index_buffer {
-                    self.core.device.destroy_buffer(index_buffer, None);
+                    leak_check::destroy(&self.core.device, index_buffer);
                 }
                 if let Some(index_memory) = buffers.index_buffer_memory {
-                    self.core.device.free_memory(index_memory, None);
+                    free_device_memory(&self.core.device, index_memory);
                 }
                 
                 if let Some(instance_buffer) = buffers.instance_buffer {
-                    self.core.device.destroy_buffer(instance_buffer, None);
+                    leak_check::destroy(&self.core.device, instance_buffer);
                 }
                 if let Some(instance_memory) = buffers.instance_buffer_memory {
-                    self.core.device.free_memory(instance_memory, None);
+                    free_device_memory(&self.core.device, instance_memory);
                 }
             }
             
             // Clean up mesh resources
-            for mesh in &self.meshes {
-                self.core.device.destroy_buffer(mesh.vertex_buffer, None);
-                // Only free memory if not using pooled memory
-                if let Some(memory) = mesh.vertex_buffer_memory {
-                    self.core.device.free_memory(memory, None);
-                }
+            for mesh in &std::mem::take(&mut self.meshes) {
+                destroy_mesh_vertex_buffers(&self.core.device, &mut self.memory_pool, mesh);
                 
-                self.core.device.destroy_buffer(mesh.index_buffer, None);
+                leak_check::destroy(&self.core.device, mesh.index_buffer);
                 // Only free memory if not using pooled memory
                 if let Some(memory) = mesh.index_buffer_memory {
-                    self.core.device.free_memory(memory, None);
+                    free_device_memory(&self.core.device, memory);
                 }
                 
                 if let Some(instance_buffer) = mesh.instance_buffer {
-                    self.core.device.destroy_buffer(instance_buffer, None);
+                    leak_check::destroy(&self.core.device, instance_buffer);
                 }
                 if let Some(memory) = mesh.instance_buffer_memory {
-                    self.core.device.free_memory(memory, None);
+                    free_device_memory(&self.core.device, memory);
                 }
                 
-                // Clean up joint buffer for skinned meshes
-                if let Some(joint_buffer) = mesh.joint_buffer {
-                    self.core.device.destroy_buffer(joint_buffer, None);
+                // Clean up joint buffer for skinned meshes, the joint arena's are destroyed below
+                if mesh.joint_arena_size.is_none() {
+                    if let Some(joint_buffer) = mesh.joint_buffer {
+                        leak_check::destroy(&self.core.device, joint_buffer);
+                    }
+                    if let Some(memory) = mesh.joint_buffer_memory {
+                        free_device_memory(&self.core.device, memory);
+                    }
+                    if let Some(camera_buffer) = mesh.camera_uniform_buffer {
+                        leak_check::destroy(&self.core.device, camera_buffer);
+                    }
+                    if let Some(memory) = mesh.camera_uniform_memory {
+                        free_device_memory(&self.core.device, memory);
+                    }
                 }
-                if let Some(memory) = mesh.joint_buffer_memory {
-                    self.core.device.free_memory(memory, None);
+                for (buffer, memory) in [
+                    (mesh.morph_delta_buffer, mesh.morph_delta_memory),
+                    (mesh.morph_weight_buffer, mesh.morph_weight_memory),
+                ] {
+                    if let (Some(buffer), Some(memory)) = (buffer, memory) {
+                        destroy_buffer(&self.core.device, buffer, memory);
+                    }
                 }
                 
                 // Clean up skinned mesh descriptor resources
-                if let Some(camera_buffer) = mesh.camera_uniform_buffer {
-                    self.core.device.destroy_buffer(camera_buffer, None);
-                }
-                if let Some(memory) = mesh.camera_uniform_memory {
-                    self.core.device.free_memory(memory, None);
-                }
                 if let Some(pool) = mesh.skinned_descriptor_pool {
-                    self.core.device.destroy_descriptor_pool(pool, None);
+                    let sets = mesh.skinned_descriptor_sets.as_deref().unwrap_or_default();
+                    release_descriptor_sets(&self.core.device, &mut self.descriptor_allocator, pool, sets);
                 }
                 if let Some(layout) = mesh.skinned_descriptor_set_layout {
-                    self.core.device.destroy_descriptor_set_layout(layout, None);
+                    leak_check::destroy(&self.core.device, layout);
+                }
+                if let Some(ref descriptors) = mesh.instance_matrices {
+                    leak_check::destroy(&self.core.device, descriptors.descriptor_pool);
+                    leak_check::destroy(&self.core.device, descriptors.descriptor_set_layout);
+                }
+                
+                if let Some(ref textures) = mesh.texture_resources {
+                    destroy_texture_resources(&self.core.device, &mut self.descriptor_allocator, textures);
+                }
+                
+                if let Some(proxy_buffer) = mesh.occlusion_proxy_buffer {
+                    leak_check::destroy(&self.core.device, proxy_buffer);
                 }
+                if let Some(memory) = mesh.occlusion_proxy_memory {
+                    free_device_memory(&self.core.device, memory);
+                }
+                
+                if let Some(ref culling) = mesh.instance_culling {
+                    culling.destroy(&self.core.device);
+                }
+                if let Some(ref hierarchy) = mesh.bone_hierarchy {
+                    hierarchy.destroy(&self.core.device);
+                }
+            }
+            
+            if let Some(ref arena) = self.joint_arena.take() {
+                arena.destroy(&self.core.device);
+            }
+            if let Some(ref cull_pipeline) = self.instance_cull_pipeline.take() {
+                cull_pipeline.destroy(&self.core.device);
+            }
+            if let Some(ref bone_pipeline) = self.bone_hierarchy_pipeline.take() {
+                bone_pipeline.destroy(&self.core.device);
+            }
+            if let Some(ref particles) = self.particles.take() {
+                particles.destroy(&self.core.device);
+            }
+            if let Some(ref dashed_lines) = self.dashed_lines.take() {
+                dashed_lines.destroy(&self.core.device);
+            }
+            if let Some(ref grid) = self.ground_grid.take() {
+                grid.destroy(&self.core.device);
+            }
+            if let Some(ref debug_boxes) = self.debug_boxes.take() {
+                debug_boxes.destroy(&self.core.device);
+            }
+            if let Some(ref ring) = self.dynamic_ring.take() {
+                ring.destroy(&self.core.device);
+            }
+            if let Some(ref shared_layouts) = self.shared_layouts.take() {
+                shared_layouts.destroy(&self.core.device);
+            }
+            for (buffer, memory, _) in self.vertex_staging_buffers.iter_mut().filter_map(Option::take) {
+                destroy_buffer(&self.core.device, buffer, memory);
+            }
+            
+            for render_target in std::mem::take(&mut self.render_targets).iter().flatten() {
+                render_target.destroy(&self.core.device);
+            }
+            if let Some(ref reflections) = self.water_reflections.take() {
+                reflections.destroy(&self.core.device);
+            }
+            if let Some(ref previous_frame) = self.previous_frame.take() {
+                previous_frame.destroy(&self.core.device);
+            }
+            
+            if let Some(ref chain) = self.post_process.take() {
+                chain.destroy(&self.core.device);
             }
             
             // Clean up textured pipeline resources
             for (_, resources) in self.textured_pipelines.drain() {
-                self.core.device.destroy_descriptor_pool(resources.descriptor_pool, None);
-                self.core.device.destroy_descriptor_set_layout(resources.descriptor_set_layout, None);
+                leak_check::destroy(&self.core.device, resources.descriptor_pool);
+                leak_check::destroy(&self.core.device, resources.descriptor_set_layout);
+                if let Some((ref texture, sampler)) = resources.texture {
+                    texture.destroy(&self.core.device);
+                    leak_check::destroy(&self.core.device, sampler);
+                }
+            }
+            
+            // Clean up the single skinned mesh
+            if let Some(ref skinned) = self.skinned_mesh.take() {
+                destroy_buffer(&self.core.device, skinned.vertex_buffer, skinned.vertex_buffer_memory);
+                destroy_buffer(&self.core.device, skinned.index_buffer, skinned.index_buffer_memory);
+                destroy_buffer(&self.core.device, skinned.joint_uniform_buffer, skinned.joint_uniform_memory);
+                destroy_buffer(&self.core.device, skinned.camera_uniform_buffer, skinned.camera_uniform_memory);
+                if let (Some(buffer), Some(memory)) = (skinned.instance_buffer, skinned.instance_buffer_memory) {
+                    destroy_buffer(&self.core.device, buffer, memory);
+                }
+                leak_check::destroy(&self.core.device, skinned.descriptor_pool);
+                leak_check::destroy(&self.core.device, skinned.descriptor_set_layout);
             }
             
             // Clean up memory pool
             self.memory_pool.destroy();
             
-            // Clean up pipeline
-            destroy_pipeline(&self.core.device, self.graphics_pipeline, self.pipeline_layout);
+            // Every set handed out was freed above, this only destroys the blocks
+            self.descriptor_allocator.destroy(&self.core.device);
+            
+            if let Some(mut recorder) = self.parallel_recorder.take() {
+                recorder.destroy(&self.core.device);
+            }
+            
+            // Clean up pipelines, the default one is usually also in the map
+            let pipelines: Vec<Pipeline> = self.pipelines.drain().map(|(_, pipeline)| pipeline).collect();
+            for pipeline in &pipelines {
+                destroy_pipeline(&self.core.device, pipeline.pipeline, pipeline.layout);
+            }
+            if !pipelines.iter().any(|pipeline| pipeline.pipeline == self.graphics_pipeline) {
+                destroy_pipeline(&self.core.device, self.graphics_pipeline, self.pipeline_layout);
+            }
+            self.graphics_pipeline = vk::Pipeline::null();
+            self.pipeline_layout = vk::PipelineLayout::null();
+        }
+    }
+}
+
+// Runs when the renderer goes out of scope wherever that happens, see shutdown to control when
+impl Drop for VulkanRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.core.device.device_wait_idle();
         }
+        self.destroy_resources();
     }
 }
 
+// Size of MvpPushConstants, which every mesh pipeline's push constant range starts with
+const MVP_PUSH_CONSTANTS_SIZE: u32 = mem::size_of::<MvpPushConstants>() as u32;
+
+// Appended to a pipeline's name for its double sided variant, see set_mesh_double_sided
+const DOUBLE_SIDED_PIPELINE_SUFFIX: &str = " (double sided)";
+// MVP block followed by a cutout mesh's alpha threshold
+const CUTOUT_PUSH_CONSTANTS_SIZE: u32 = MVP_PUSH_CONSTANTS_SIZE + 4;
+
+// Stages of the pipeline's push constant ranges that overlap offset..offset + size
+fn push_constant_stages(pipeline: &Pipeline, offset: u32, size: usize) -> vk::ShaderStageFlags {
+    let end = offset + size as u32;
+    pipeline.push_constant_ranges.iter()
+        .filter(|range| range.offset < end && offset < range.offset + range.size)
+        .fold(vk::ShaderStageFlags::empty(), |stages, range| stages | range.stage_flags)
+}
+
+// Wind block of pipelines from add_wind_pipeline, after the MVP block and the cutout
+// threshold's 16 byte slot so foliage can be both
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct WindPushConstants {
+    direction_amplitude: [f32; 4],
+    frequency_time: [f32; 4],
+}
+
+const WIND_PUSH_CONSTANTS_OFFSET: u32 = MVP_PUSH_CONSTANTS_SIZE + 16;
+const WIND_PUSH_CONSTANTS_SIZE: u32 = WIND_PUSH_CONSTANTS_OFFSET + mem::size_of::<WindPushConstants>() as u32;
+
+// UV scale and offset of pipelines from add_uv_transform_pipeline, in the wind block's place
+const UV_TRANSFORM_PUSH_CONSTANTS_OFFSET: u32 = WIND_PUSH_CONSTANTS_OFFSET;
+const UV_TRANSFORM_PUSH_CONSTANTS_SIZE: u32 = UV_TRANSFORM_PUSH_CONSTANTS_OFFSET + 16;
+
+// Debug view parameters of shaders/debug_view.frag, in the cutout threshold's slot
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugViewPushConstants {
+    mode: u32,
+    depth_near: f32,
+    depth_far: f32,
+    _padding: u32,
+}
+
+const DEBUG_VIEW_PUSH_CONSTANTS_SIZE: u32 = MVP_PUSH_CONSTANTS_SIZE + mem::size_of::<DebugViewPushConstants>() as u32;
+
 // Helper struct for push constants
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -4622,4 +11567,168 @@ struct MvpPushConstants {
     view: [f32; 16],
     proj: [f32; 16],
     base_color: [f32; 4], // Added base color for material-specific coloring
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+    
+    #[test]
+    fn instance_bounds_enclose_every_instance() {
+        assert_eq!(instance_bounds(&[]), None);
+        assert_eq!(
+            instance_bounds(&[[1.0, 2.0, 3.0], [-1.0, 5.0, 0.0], [0.0, -3.0, 4.0]]),
+            Some(([-1.0, -3.0, 0.0], [1.0, 5.0, 4.0])),
+        );
+    }
+    
+    #[test]
+    fn overlapping_uploads_merge_with_later_data_on_top() {
+        let buffer = vk::Buffer::from_raw(1);
+        let other_buffer = vk::Buffer::from_raw(2);
+        let upload = |buffer, offset, data: &[u8]| PendingVertexUpload { buffer, offset, data: data.to_vec() };
+        let merged = merge_overlapping_uploads(vec![
+            upload(buffer, 0, &[1, 1, 1, 1]),
+            upload(other_buffer, 2, &[9, 9]),
+            upload(buffer, 8, &[2, 2]),
+            // Overlaps the first, touches nothing else
+            upload(buffer, 2, &[3, 3, 3]),
+            // Bridges the first two ranges
+            upload(buffer, 4, &[4, 4, 4, 4, 4]),
+        ]);
+        
+        assert_eq!(merged.len(), 2);
+        assert_eq!((merged[0].buffer, merged[0].offset, merged[0].data.clone()), (other_buffer, 2, vec![9, 9]));
+        assert_eq!((merged[1].buffer, merged[1].offset, merged[1].data.clone()), (buffer, 0, vec![1, 1, 3, 3, 4, 4, 4, 4, 4, 2]));
+    }
+    
+    #[test]
+    fn adjacent_uploads_stay_separate() {
+        let buffer = vk::Buffer::from_raw(1);
+        let merged = merge_overlapping_uploads(vec![
+            PendingVertexUpload { buffer, offset: 0, data: vec![1; 4] },
+            PendingVertexUpload { buffer, offset: 4, data: vec![2; 4] },
+        ]);
+        assert_eq!(merged.len(), 2);
+    }
+    
+    #[test]
+    fn merged_uploads_match_applying_every_upload_in_order() {
+        // Frames of random updates to two buffers, many overlapping or touching each other,
+        // against buffers written one queued upload at a time
+        const SIZE: usize = 256;
+        let buffers = [vk::Buffer::from_raw(1), vk::Buffer::from_raw(2)];
+        let mut rng = crate::random::SeededRng::new(3);
+        let mut expected = [[0u8; SIZE]; 2];
+        let mut copied = [[0u8; SIZE]; 2];
+        for frame in 0..200u32 {
+            let mut uploads = Vec::new();
+            let mut last_end = 0;
+            for upload_index in 0..rng.range_usize(1..12) {
+                let buffer_index = rng.range_usize(0..2);
+                let len = rng.range_usize(1..24);
+                // Every few uploads start right where the previous one ended
+                let offset = if upload_index % 3 == 2 && last_end + len <= SIZE { last_end } else { rng.range_usize(0..SIZE - len) };
+                last_end = offset + len;
+                let data: Vec<u8> = (0..len).map(|byte| (frame as usize * 31 + upload_index * 7 + byte) as u8).collect();
+                expected[buffer_index][offset..offset + len].copy_from_slice(&data);
+                uploads.push(PendingVertexUpload { buffer: buffers[buffer_index], offset: offset as vk::DeviceSize, data });
+            }
+            
+            let merged = merge_overlapping_uploads(uploads);
+            for (index, upload) in merged.iter().enumerate() {
+                let end = upload.offset + upload.data.len() as vk::DeviceSize;
+                for other in &merged[index + 1..] {
+                    let other_end = other.offset + other.data.len() as vk::DeviceSize;
+                    assert!(other.buffer != upload.buffer || other.offset >= end || upload.offset >= other_end);
+                }
+                let buffer_index = buffers.iter().position(|&buffer| buffer == upload.buffer).unwrap();
+                let offset = upload.offset as usize;
+                copied[buffer_index][offset..offset + upload.data.len()].copy_from_slice(&upload.data);
+            }
+            assert_eq!(copied, expected, "frame {}", frame);
+        }
+    }
+    
+    #[test]
+    fn remove_instance_moves_the_last_instance_into_the_gap() {
+        let mut instances = vec!['a', 'b', 'c', 'd'];
+        swap_remove_instance(&mut instances, 4, 1);
+        assert_eq!(instances, vec!['a', 'd', 'c']);
+        
+        // Removing the last one moves nothing
+        swap_remove_instance(&mut instances, 3, 2);
+        assert_eq!(instances, vec!['a', 'd']);
+        
+        // Stale entries past the drawn instances don't get moved in
+        let mut instances = vec!['a', 'b', 'c', 'x'];
+        swap_remove_instance(&mut instances, 3, 0);
+        assert_eq!(instances, vec!['c', 'b']);
+    }
+    
+    // Mesh 0 is the child of 1, which is the child of 2, so children come before their parents
+    fn mesh_chain() -> Vec<MeshEntry> {
+        let mut meshes: Vec<MeshEntry> = (0..3).map(|_| MeshEntry::default()).collect();
+        meshes[0].parent = Some(1);
+        meshes[0].local_transforms = vec![Mat4::from_translation(Vec3::Z)];
+        meshes[1].parent = Some(2);
+        meshes[1].local_transforms = vec![Mat4::from_translation(Vec3::Y)];
+        meshes[2].transforms = vec![Mat4::from_translation(Vec3::X)];
+        meshes
+    }
+    
+    #[test]
+    fn children_compose_with_their_parents_world_transform() {
+        let mut meshes = mesh_chain();
+        resolve_world_transforms(&mut meshes);
+        assert_eq!(meshes[1].transforms, vec![Mat4::from_translation(Vec3::new(1.0, 1.0, 0.0))]);
+        assert_eq!(meshes[0].transforms, vec![Mat4::from_translation(Vec3::ONE)]);
+        
+        // A parent without transforms hides its children
+        meshes[2].transforms.clear();
+        resolve_world_transforms(&mut meshes);
+        assert!(meshes[1].transforms.is_empty());
+        assert!(meshes[0].transforms.is_empty());
+    }
+    
+    #[test]
+    fn parenting_to_a_descendant_is_a_cycle() {
+        let meshes = mesh_chain();
+        assert!(makes_parent_cycle(&meshes, 2, 0));
+        assert!(makes_parent_cycle(&meshes, 1, 1));
+        assert!(!makes_parent_cycle(&meshes, 0, 2));
+    }
+    
+    #[test]
+    fn large_rigs_fall_back_to_storage_buffer() {
+        // 16 KiB is the smallest maxUniformBufferRange Vulkan allows, 256 matrices
+        assert!(!joints_exceed_uniform_range(128, 16384));
+        assert!(!joints_exceed_uniform_range(256, 16384));
+        assert!(joints_exceed_uniform_range(257, 16384));
+        assert!(!joints_exceed_uniform_range(1024, 65536));
+        assert!(joints_exceed_uniform_range(1025, 65536));
+    }
+    
+    #[test]
+    fn storage_buffer_rigs_shrink_the_uniform_array() {
+        assert_eq!(
+            skinned_vertex_specialization(300, true, SkinningMode::LinearBlend),
+            [(0, 1), (1, vk::TRUE), (2, vk::FALSE)],
+        );
+        assert_eq!(
+            skinned_vertex_specialization(64, false, SkinningMode::DualQuaternion),
+            [(0, 64), (1, vk::FALSE), (2, vk::TRUE)],
+        );
+        // A rig without joints still binds one matrix
+        assert_eq!(skinned_vertex_specialization(0, false, SkinningMode::LinearBlend)[0], (0, 1));
+    }
+    
+    #[test]
+    fn transform_bounds_of_rotated_box() {
+        // A quarter turn about y maps x to -z and z to x
+        let transform = Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)) * Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let (min, max) = transform_bounds(&transform, Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 1.0, 1.0));
+        assert!(min.abs_diff_eq(Vec3::new(10.0, 0.0, -2.0), 1e-5), "{min}");
+        assert!(max.abs_diff_eq(Vec3::new(11.0, 1.0, 0.0), 1e-5), "{max}");
+    }
+}
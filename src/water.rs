@@ -0,0 +1,269 @@
+use bevy_math::Vec3;
+use crate::mesh::{MeshData, MeshTopology, Vertex, PRIMITIVE_RESTART_INDEX};
+
+// Heights of a square water grid, e.g. a shallow water simulation's state. The surface mesh has
+// a vertex per cell corner, see VulkanRenderer::add_water_mesh and update_water_surface.
+#[derive(Clone, Debug)]
+pub struct WaterData {
+    // Cells per side
+    pub grid_len: usize,
+    // World size of the whole grid, centered on the origin
+    pub size: f32,
+    // Height of still water, drawn at y = 0
+    pub rest_height: f32,
+    // Indexed [x][z]
+    pub heights: Vec<Vec<f32>>,
+    // Cells without water, e.g. the simulation's boundary. Indexed [x][z], none by default.
+    pub walls: Vec<Vec<bool>>,
+}
+
+impl WaterData {
+    pub fn new(grid_len: usize, size: f32, rest_height: f32) -> Self {
+        Self {
+            grid_len,
+            size,
+            rest_height,
+            heights: vec![vec![rest_height; grid_len]; grid_len],
+            walls: vec![vec![false; grid_len]; grid_len],
+        }
+    }
+
+    // World size of one cell, the fluid shaders' gridScale
+    pub fn grid_scale(&self) -> f32 {
+        self.size / self.grid_len as f32
+    }
+
+    pub fn vertices_per_side(&self) -> usize {
+        self.grid_len + 1
+    }
+
+    // Flat grid at the rest height. Triangle strips use one strip per row and produce the same
+    // triangles with the same winding as the list.
+    pub fn create_mesh(&self, topology: MeshTopology) -> MeshData {
+        let vertices_per_side = self.vertices_per_side();
+        let mut vertices = Vec::with_capacity(vertices_per_side * vertices_per_side);
+        for z_idx in 0..vertices_per_side {
+            for x_idx in 0..vertices_per_side {
+                let (x, z) = self.vertex_xz(x_idx, z_idx);
+                vertices.push(Vertex {
+                    position: [x, 0.0, z],
+                    normal: [0.0, 1.0, 0.0],
+                    uv: [x_idx as f32 / self.grid_len as f32, z_idx as f32 / self.grid_len as f32],
+                    color: [1.0, 1.0, 1.0, 1.0],
+                });
+            }
+        }
+
+        let mut indices = Vec::new();
+        if topology == MeshTopology::TriangleStrip {
+            for z in 0..self.grid_len {
+                for x in 0..vertices_per_side {
+                    indices.push((z * vertices_per_side + x) as u32);
+                    indices.push(((z + 1) * vertices_per_side + x) as u32);
+                }
+                indices.push(PRIMITIVE_RESTART_INDEX);
+            }
+            return MeshData::new(vertices, indices).with_topology(MeshTopology::TriangleStrip);
+        }
+        for z in 0..self.grid_len {
+            for x in 0..self.grid_len {
+                let top_left = (z * vertices_per_side + x) as u32;
+                let top_right = top_left + 1;
+                let bottom_left = ((z + 1) * vertices_per_side + x) as u32;
+                let bottom_right = bottom_left + 1;
+                indices.extend_from_slice(&[top_left, bottom_left, top_right, top_right, bottom_left, bottom_right]);
+            }
+        }
+        MeshData::new(vertices, indices)
+    }
+
+    // Vertex positions and normals of the surface, in the order create_mesh lays out vertices.
+    // Corner vertices take the height of the cell they start, normals come from the central
+    // difference of the neighbouring cells.
+    pub fn surface(&self) -> (Vec<[f32; 3]>, Vec<[f32; 3]>) {
+        let vertices_per_side = self.vertices_per_side();
+        let grid_scale = self.grid_scale();
+        let last = self.grid_len - 1;
+        let mut positions = Vec::with_capacity(vertices_per_side * vertices_per_side);
+        let mut normals = Vec::with_capacity(vertices_per_side * vertices_per_side);
+        for z_idx in 0..vertices_per_side {
+            for x_idx in 0..vertices_per_side {
+                let (x, z) = self.vertex_xz(x_idx, z_idx);
+                let grid_x = x_idx.min(last);
+                let grid_z = z_idx.min(last);
+                let height = self.heights[grid_x][grid_z];
+
+                // Edge vertices stay flat so the surface meets the walls cleanly
+                let mut dx = 0.0;
+                let mut dz = 0.0;
+                if x_idx > 0 && x_idx < vertices_per_side - 1 {
+                    let left = self.heights[grid_x - 1][grid_z];
+                    let right = if grid_x < last { self.heights[grid_x + 1][grid_z] } else { height };
+                    dx = (right - left) / (2.0 * grid_scale);
+                }
+                if z_idx > 0 && z_idx < vertices_per_side - 1 {
+                    let up = self.heights[grid_x][grid_z - 1];
+                    let down = if grid_z < last { self.heights[grid_x][grid_z + 1] } else { height };
+                    dz = (down - up) / (2.0 * grid_scale);
+                }
+                let normal_len = (dx * dx + 1.0 + dz * dz).sqrt();
+
+                positions.push([x, height - self.rest_height, z]);
+                normals.push([-dx / normal_len, 1.0 / normal_len, -dz / normal_len]);
+            }
+        }
+        (positions, normals)
+    }
+
+    // Cell under a point in the water mesh's space, None outside the grid
+    pub fn world_to_grid(&self, x: f32, z: f32) -> Option<(usize, usize)> {
+        let u = x / self.size + 0.5;
+        let v = z / self.size + 0.5;
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+        let last = self.grid_len - 1;
+        Some((((u * self.grid_len as f32) as usize).min(last), ((v * self.grid_len as f32) as usize).min(last)))
+    }
+
+    // Surface height of a cell in the water mesh's space, where still water is at y = 0.
+    // None for walls and cells outside the grid.
+    pub fn read_height(&self, grid_x: usize, grid_z: usize) -> Option<f32> {
+        if grid_x >= self.grid_len || grid_z >= self.grid_len || self.walls[grid_x][grid_z] {
+            return None;
+        }
+        Some(self.heights[grid_x][grid_z] - self.rest_height)
+    }
+
+    // Fraction of a sphere's volume below the water, from 0 to 1, e.g. to scale buoyancy.
+    // center is in the water mesh's space like read_height. The water level is the average
+    // height of the cells whose centers lie within the sphere's footprint, or of the cell under
+    // its center when it's smaller than a cell. Walls and the outside of the grid hold no water.
+    pub fn submersion(&self, center: Vec3, radius: f32) -> f32 {
+        if radius <= 0.0 {
+            return 0.0;
+        }
+        let grid_scale = self.grid_scale();
+        let half_size = self.size * 0.5;
+        // Cells whose centers can be within the footprint, clamped to the grid
+        let cell_range = |coordinate: f32| {
+            let first = ((coordinate - radius + half_size) / grid_scale - 0.5).ceil().max(0.0) as usize;
+            let last = ((coordinate + radius + half_size) / grid_scale - 0.5).floor().min(self.grid_len as f32 - 1.0);
+            (first, last)
+        };
+        let (first_x, last_x) = cell_range(center.x);
+        let (first_z, last_z) = cell_range(center.z);
+
+        let mut height_sum = 0.0;
+        let mut cell_count = 0;
+        if last_x >= 0.0 && last_z >= 0.0 {
+            for grid_x in first_x..=last_x as usize {
+                for grid_z in first_z..=last_z as usize {
+                    let cell_x = (grid_x as f32 + 0.5) * grid_scale - half_size;
+                    let cell_z = (grid_z as f32 + 0.5) * grid_scale - half_size;
+                    let inside = (cell_x - center.x).powi(2) + (cell_z - center.z).powi(2) <= radius * radius;
+                    if let Some(height) = self.read_height(grid_x, grid_z).filter(|_| inside) {
+                        height_sum += height;
+                        cell_count += 1;
+                    }
+                }
+            }
+        }
+        let water_level = if cell_count > 0 {
+            height_sum / cell_count as f32
+        } else {
+            let Some(height) = self.world_to_grid(center.x, center.z)
+                .and_then(|(grid_x, grid_z)| self.read_height(grid_x, grid_z)) else {
+                return 0.0;
+            };
+            height
+        };
+
+        // Volume of the spherical cap below the water level over the sphere's volume
+        let depth = (water_level - (center.y - radius)).clamp(0.0, 2.0 * radius);
+        depth * depth * (3.0 * radius - depth) / (4.0 * radius * radius * radius)
+    }
+
+    fn vertex_xz(&self, x_idx: usize, z_idx: usize) -> (f32, f32) {
+        let half_size = self.size * 0.5;
+        (
+            -half_size + x_idx as f32 / self.grid_len as f32 * self.size,
+            -half_size + z_idx as f32 / self.grid_len as f32 * self.size,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 by 4 cells of 1 meter, centers at -1.5, -0.5, 0.5 and 1.5, still water 2 meters deep
+    fn water() -> WaterData {
+        WaterData::new(4, 4.0, 2.0)
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn read_height_is_relative_to_still_water() {
+        let mut water = water();
+        // A wave along x, 0.25 meters high with a wavelength of the whole grid
+        let wave = |grid_x: usize| 0.25 * (std::f32::consts::TAU * (grid_x as f32 + 0.5) / 4.0).sin();
+        for (grid_x, column) in water.heights.iter_mut().enumerate() {
+            column.fill(2.0 + wave(grid_x));
+        }
+        water.walls[3][1] = true;
+
+        for grid_x in 0..4 {
+            assert_near(water.read_height(grid_x, 0).unwrap(), wave(grid_x));
+        }
+        assert!(water.read_height(0, 0).unwrap() > 0.0);
+        assert!(water.read_height(2, 0).unwrap() < 0.0);
+        assert_eq!(water.read_height(3, 1), None);
+        assert_eq!(water.read_height(4, 0), None);
+        assert_eq!(water.read_height(0, 4), None);
+    }
+
+    #[test]
+    fn world_to_grid_finds_the_cell_under_a_point() {
+        let water = water();
+        assert_eq!(water.world_to_grid(-2.0, -2.0), Some((0, 0)));
+        assert_eq!(water.world_to_grid(1.5, -0.5), Some((3, 1)));
+        assert_eq!(water.world_to_grid(0.0, 0.0), Some((2, 2)));
+        assert_eq!(water.world_to_grid(2.0, 0.0), None);
+        assert_eq!(water.world_to_grid(0.0, -2.1), None);
+    }
+
+    #[test]
+    fn submersion_of_spheres_in_still_water() {
+        let water = water();
+        assert_near(water.submersion(Vec3::ZERO, 0.5), 0.5);
+        assert_near(water.submersion(Vec3::new(0.0, -1.0, 0.0), 0.5), 1.0);
+        assert_near(water.submersion(Vec3::new(0.0, 1.0, 0.0), 0.5), 0.0);
+        // A quarter of the diameter under water holds 5/32 of the volume
+        assert_near(water.submersion(Vec3::new(0.0, 0.25, 0.0), 0.5), 5.0 / 32.0);
+        assert_near(water.submersion(Vec3::ZERO, 0.0), 0.0);
+        // Off the grid there's no water
+        assert_near(water.submersion(Vec3::new(5.0, 0.0, 0.0), 0.5), 0.0);
+    }
+
+    #[test]
+    fn submersion_averages_the_cells_under_the_sphere() {
+        let mut water = water();
+        // A step: the half of the grid at positive x is a meter higher
+        for column in &mut water.heights[2..] {
+            column.fill(3.0);
+        }
+
+        // Small spheres only see the cell under their center
+        assert_near(water.submersion(Vec3::new(0.5, 1.0, 0.5), 0.4), 0.5);
+        assert_near(water.submersion(Vec3::new(-0.5, 1.0, 0.5), 0.4), 0.0);
+        // This one covers the cell centers at x = -0.5 and 0.5, so the level is halfway up the step
+        assert_near(water.submersion(Vec3::new(0.0, 0.5, 0.5), 1.0), 0.5);
+
+        water.walls[2][2] = true;
+        assert_near(water.submersion(Vec3::new(0.5, 1.0, 0.5), 0.4), 0.0);
+    }
+}
@@ -0,0 +1,95 @@
+use ash::{vk, Instance};
+use crate::error::FloError;
+use crate::leak_check;
+use crate::mesh::{MeshLayout, MeshTopology};
+use crate::render_target::RenderTarget;
+
+pub const WATER_REFLECTION_FRAG_SHADER_PATH: &str = "shaders/water_reflection.frag.spv";
+
+// Resolution of the reflection target, relative to the scene's render extent. The reflection is
+// blurred by the waves anyway, so it rarely needs the full resolution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WaterReflectionQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl WaterReflectionQuality {
+    pub fn resolution_scale(self) -> f32 {
+        match self {
+            WaterReflectionQuality::Low => 0.25,
+            WaterReflectionQuality::Medium => 0.5,
+            WaterReflectionQuality::High => 1.0,
+        }
+    }
+
+    fn extent(self, render_extent: vk::Extent2D) -> vk::Extent2D {
+        let scale = self.resolution_scale();
+        vk::Extent2D {
+            width: ((render_extent.width as f32 * scale) as u32).max(1),
+            height: ((render_extent.height as f32 * scale) as u32).max(1),
+        }
+    }
+}
+
+// How the "water" fluid pipeline was built, so its reflective twin can be built the same way
+#[derive(Clone, Debug)]
+pub(crate) struct WaterPipelineDesc {
+    pub vert_shader_path: String,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub layout: MeshLayout,
+    pub topology: MeshTopology,
+}
+
+// The scene above the water drawn from a camera mirrored below it, and the water pipeline
+// sampling it. See VulkanRenderer::enable_water_reflections.
+pub(crate) struct WaterReflections {
+    pub quality: WaterReflectionQuality,
+    pub target: RenderTarget,
+    // "water" with shaders/water_reflection.frag, the target's set at set 0
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+}
+
+impl WaterReflections {
+    pub fn create_target(
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        color_format: vk::Format,
+        depth_format: Option<vk::Format>,
+        render_extent: vk::Extent2D,
+        quality: WaterReflectionQuality,
+    ) -> Result<RenderTarget, FloError> {
+        RenderTarget::new(instance, device, physical_device, color_format, depth_format, quality.extent(render_extent))
+    }
+
+    // Follows the scene's render extent. The device has to be idle.
+    pub fn resize(
+        &mut self,
+        instance: &Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        color_format: vk::Format,
+        depth_format: Option<vk::Format>,
+        render_extent: vk::Extent2D,
+    ) -> Result<(), FloError> {
+        if self.target.extent == self.quality.extent(render_extent) {
+            return Ok(());
+        }
+        let target = Self::create_target(instance, device, physical_device, color_format, depth_format, render_extent, self.quality)?;
+        std::mem::replace(&mut self.target, target).destroy(device);
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        self.target.destroy(device);
+        unsafe {
+            leak_check::destroy(device, self.pipeline);
+            leak_check::destroy(device, self.layout);
+        }
+    }
+}